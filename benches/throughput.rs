@@ -0,0 +1,29 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use csv_transactions::bench::{fixture, run, BenchMode, FIXTURES};
+
+/// Measures each deployment mode on the small fixture, swap in fixture(&your_config)
+/// or your own input to measure your data shapes
+fn throughput(c: &mut Criterion)
+{
+    let (name, config) = &FIXTURES[0];
+    let input = fixture(config);
+    let dir = std::env::temp_dir().join("csv_transactions_bench");
+    let modes = [
+        ("sync", BenchMode::Sync),
+        ("sharded-4", BenchMode::Sharded(4)),
+        ("queued", BenchMode::Queued(1024)),
+        ("columnar", BenchMode::Columnar(8192)),
+        ("checkpointed", BenchMode::Checkpointed { dir: dir.clone(), every: 5000 }),
+    ];
+    let mut group = c.benchmark_group(*name);
+    group.throughput(Throughput::Elements(config.rows));
+    for (label, mode) in &modes
+    {
+        group.bench_function(*label, |b| b.iter(|| run(black_box(&input), mode).unwrap()));
+    }
+    group.finish();
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+criterion_group!(benches, throughput);
+criterion_main!(benches);
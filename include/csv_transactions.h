@@ -0,0 +1,84 @@
+#ifndef CSV_TRANSACTIONS_H
+#define CSV_TRANSACTIONS_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit by hand */
+
+#include <stdbool.h>
+#include <stdint.h>
+
+// The call succeeded
+#define ENGINE_OK 0
+
+// The client asked for hasn't been seen
+#define ENGINE_NOT_FOUND 1
+
+// A null pointer, or a row that isn't a transaction
+#define ENGINE_INVALID -1
+
+// The report couldn't be written
+#define ENGINE_IO_ERROR -2
+
+// Amounts are handed out as whole numbers of this fraction of a unit
+#define ENGINE_AMOUNT_SCALE 10000
+
+//
+// Holds every client seen so far and applies transactions to them in order
+//
+typedef struct Engine Engine;
+
+//
+// The balances of a single client, with amounts in 1/ENGINE_AMOUNT_SCALE units
+//
+typedef struct {
+  uint16_t client;
+  int64_t available;
+  int64_t held;
+  int64_t total;
+  bool locked;
+  int64_t pending;
+} EngineAccount;
+
+
+
+
+
+
+
+// Returns a new engine following the default policy, to be freed with engine_free
+Engine *engine_new(void);
+
+// Frees an engine returned by engine_new
+//
+// # Safety
+//
+// 'engine' has to come from engine_new and not be used again afterwards
+void engine_free(Engine *engine);
+
+// Applies a single csv row, E.G. "deposit,1,1,1.5", with the columns in the
+// order of the input file and no header
+//
+// Returns ENGINE_OK once the row is applied, even if the engine refused the
+// transaction, and ENGINE_INVALID if the row can't be read at all
+//
+// # Safety
+//
+// 'engine' has to come from engine_new, and 'row' has to be a nul terminated string
+int32_t engine_apply_csv_row(Engine *engine, const char *row);
+
+// Copies the balances of a client into 'out'
+//
+// Returns ENGINE_NOT_FOUND, and leaves 'out' alone, if the client hasn't been seen
+//
+// # Safety
+//
+// 'engine' has to come from engine_new, and 'out' has to point to an EngineAccount
+int32_t engine_account(const Engine *engine, uint16_t client, EngineAccount *out);
+
+// Writes the account report as csv to the file at 'path', replacing it
+//
+// # Safety
+//
+// 'engine' has to come from engine_new, and 'path' has to be a nul terminated string
+int32_t engine_write_report(const Engine *engine, const char *path);
+
+#endif  /* CSV_TRANSACTIONS_H */
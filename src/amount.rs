@@ -0,0 +1,288 @@
+use std::{convert::TryFrom, fmt, str::FromStr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use crate::RoundingMode;
+
+/// The number of decimal places an amount is allowed to carry
+pub const AMOUNT_PRECISION: usize = 4;
+/// How many minor units go into a whole unit
+const SCALE: i64 = 10_000;
+
+///
+/// Why a piece of text couldn't be turned into an amount
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmountError
+{
+    /// Not a number at all
+    Invalid,
+    /// NaN or infinity
+    NonFinite,
+    /// More than four decimals, and the rounding mode is Reject
+    Precision,
+    /// Too large to be represented
+    Overflow,
+}
+impl fmt::Display for AmountError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+///
+/// A money amount, stored as a whole number of ten-thousandths
+///
+/// All arithmetic on amounts is checked, so an operation that would wrap
+/// gives back None instead
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+impl Amount
+{
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(i64::MAX);
+    pub const MIN: Amount = Amount(i64::MIN);
+
+    /// Returns an amount from a number of minor units (ten-thousandths)
+    pub const fn from_minor(minor: i64) -> Amount
+    {
+        Amount(minor)
+    }
+    /// Returns the amount as a number of minor units (ten-thousandths)
+    pub const fn minor(&self) -> i64
+    {
+        self.0
+    }
+    pub fn checked_add(self, other: Amount) -> Option<Amount>
+    {
+        self.0.checked_add(other.0).map(Amount)
+    }
+    pub fn checked_sub(self, other: Amount) -> Option<Amount>
+    {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+    /// Multiplies by a rate, E.G. 0.01 for one percent, dropping anything past four decimals
+    pub fn checked_mul_rate(self, rate: Amount) -> Option<Amount>
+    {
+        i64::try_from(i128::from(self.0) * i128::from(rate.0) / i128::from(SCALE)).ok().map(Amount)
+    }
+    pub fn is_negative(&self) -> bool
+    {
+        self.0 < 0
+    }
+    /// Parses an amount from its decimal text, rounding it to four decimals if needed
+    ///
+    /// Plain decimals ("1.5", "-0.25") and scientific notation ("1e3") are accepted
+    ///
+    /// # Arguments
+    ///
+    /// 'text' - The amount as it appears in the input
+    /// 'rounding' - What to do if there are more than four decimals
+    ///
+    /// The digits are worked on as text rather than going through a float, so
+    /// that nothing is lost before we even get to round it
+    pub fn parse(text: &str, rounding: RoundingMode) -> Result<Amount, AmountError>
+    {
+        let lower = text.to_ascii_lowercase();
+        let unsigned = lower.trim_start_matches(['+', '-']);
+        if matches!(unsigned, "nan" | "inf" | "infinity") {return Err(AmountError::NonFinite)}
+
+        let (negative, rest) = match text.as_bytes().first()
+        {
+            Some(b'-') => (true, &text[1..]),
+            Some(b'+') => (false, &text[1..]),
+            _ => (false, text)
+        };
+        let (mantissa, exponent) = match rest.find(['e', 'E'])
+        {
+            Some(i) => (&rest[..i], rest[i+1..].parse::<i32>().map_err(|_| AmountError::Invalid)?),
+            None => (rest, 0)
+        };
+        let (int_digits, frac_digits) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+        let all_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        if (int_digits.is_empty() && frac_digits.is_empty()) || !all_digits(int_digits) || !all_digits(frac_digits)
+        {
+            return Err(AmountError::Invalid);
+        }
+
+        // the value is digits * 10^-scale, and we want it as digits * 10^-AMOUNT_PRECISION
+        let mut digits = format!("{}{}", int_digits, frac_digits);
+        let scale = frac_digits.len() as i64 - exponent as i64;
+        let shift = AMOUNT_PRECISION as i64 - scale;
+        let mut round_up = false;
+        if shift >= 0
+        {
+            if digits.trim_start_matches('0').len() as i64 + shift > 40 {return Err(AmountError::Overflow)}
+            digits.extend(std::iter::repeat_n('0', shift as usize));
+        }
+        else
+        {
+            let drop = (-shift) as usize;
+            // past the length of the digits only the first dropped one matters, and that's a zero
+            let dropped = if drop > digits.len()
+            {
+                format!("0{}", std::mem::take(&mut digits))
+            }
+            else
+            {
+                digits.split_off(digits.len() - drop)
+            };
+            if dropped.bytes().any(|d| d != b'0')
+            {
+                round_up = match rounding
+                {
+                    RoundingMode::Reject => return Err(AmountError::Precision),
+                    RoundingMode::Truncate => false,
+                    RoundingMode::HalfEven => {
+                        let last_kept_odd = digits.bytes().last().is_some_and(|d| (d - b'0') % 2 == 1);
+                        match dropped.as_bytes()[0]
+                        {
+                            b'6'..=b'9' => true,
+                            b'5' => dropped[1..].bytes().any(|d| d != b'0') || last_kept_odd,
+                            _ => false
+                        }
+                    }
+                };
+            }
+        }
+        let mut minor: i128 = 0;
+        for d in digits.trim_start_matches('0').bytes()
+        {
+            minor = minor.checked_mul(10).and_then(|m| m.checked_add((d - b'0') as i128))
+                .ok_or(AmountError::Overflow)?;
+        }
+        if round_up {minor += 1}
+        if negative {minor = -minor}
+        i64::try_from(minor).map(Amount).map_err(|_| AmountError::Overflow)
+    }
+}
+impl FromStr for Amount
+{
+    type Err = AmountError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Amount::parse(s, RoundingMode::Reject)
+    }
+}
+impl fmt::Display for Amount
+{
+    /// Writes the amount as a decimal, with trailing zeros dropped but always
+    /// at least one decimal, E.G. "1.5", "2.0", "0.0001"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let abs = self.0.unsigned_abs();
+        let int_part = abs / SCALE as u64;
+        let frac = format!("{:0width$}", abs % SCALE as u64, width = AMOUNT_PRECISION);
+        let frac = frac.trim_end_matches('0');
+        let sign = if self.0 < 0 {"-"} else {""};
+        write!(f, "{}{}.{}", sign, int_part, if frac.is_empty() {"0"} else {frac})
+    }
+}
+impl Serialize for Amount
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for Amount
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct AmountVisitor;
+        impl de::Visitor<'_> for AmountVisitor
+        {
+            type Value = Amount;
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal amount with at most four decimal places")
+            }
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+                v.parse().map_err(|e| E::custom(format!("invalid amount '{}': {}", v, e)))
+            }
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Amount, E> {
+                self.visit_str(&v.to_string())
+            }
+        }
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str, rounding: RoundingMode) -> Result<i64, AmountError>
+    {
+        Amount::parse(text, rounding).map(|a| a.minor())
+    }
+
+    #[test]
+    fn within_precision()
+    {
+        assert_eq!(parse("1.5",RoundingMode::Reject),Ok(15000));
+        assert_eq!(parse("0.0001",RoundingMode::Reject),Ok(1));
+        assert_eq!(parse("3",RoundingMode::Truncate),Ok(30000));
+        assert_eq!(parse("-2.25",RoundingMode::Reject),Ok(-22500));
+        assert_eq!(parse(".5",RoundingMode::Reject),Ok(5000));
+        assert_eq!(parse("1.50000",RoundingMode::Reject),Ok(15000));
+        assert_eq!(parse("1e3",RoundingMode::Reject),Ok(10_000_000));
+        assert_eq!(parse("25E-4",RoundingMode::Reject),Ok(25));
+    }
+    #[test]
+    fn reject_precision()
+    {
+        assert_eq!(parse("0.00001",RoundingMode::Reject),Err(AmountError::Precision));
+        assert_eq!(parse("1.23456",RoundingMode::Reject),Err(AmountError::Precision));
+    }
+    #[test]
+    fn truncate_precision()
+    {
+        assert_eq!(parse("1.23459",RoundingMode::Truncate),Ok(12345));
+        assert_eq!(parse("0.00009",RoundingMode::Truncate),Ok(0));
+        assert_eq!(parse("-1.23459",RoundingMode::Truncate),Ok(-12345));
+    }
+    #[test]
+    fn half_even_precision()
+    {
+        assert_eq!(parse("1.23456",RoundingMode::HalfEven),Ok(12346));
+        assert_eq!(parse("1.23454",RoundingMode::HalfEven),Ok(12345));
+        assert_eq!(parse("1.23445",RoundingMode::HalfEven),Ok(12344));
+        assert_eq!(parse("1.23435",RoundingMode::HalfEven),Ok(12344));
+        assert_eq!(parse("1.234451",RoundingMode::HalfEven),Ok(12345));
+        assert_eq!(parse("0.99995",RoundingMode::HalfEven),Ok(10000));
+        assert_eq!(parse("-0.00005",RoundingMode::HalfEven),Ok(0));
+        assert_eq!(parse("1e-9",RoundingMode::HalfEven),Ok(0));
+    }
+    #[test]
+    fn invalid_amounts()
+    {
+        assert_eq!(parse("",RoundingMode::Reject),Err(AmountError::Invalid));
+        assert_eq!(parse("abc",RoundingMode::Reject),Err(AmountError::Invalid));
+        assert_eq!(parse(" 1.0",RoundingMode::Reject),Err(AmountError::Invalid));
+        assert_eq!(parse("1.0.0",RoundingMode::Reject),Err(AmountError::Invalid));
+        assert_eq!(parse("NaN",RoundingMode::Reject),Err(AmountError::NonFinite));
+        assert_eq!(parse("-inf",RoundingMode::Reject),Err(AmountError::NonFinite));
+    }
+    #[test]
+    fn amount_extremes()
+    {
+        assert_eq!(parse("922337203685477.5807",RoundingMode::Reject),Ok(i64::MAX));
+        assert_eq!(parse("-922337203685477.5808",RoundingMode::Reject),Ok(i64::MIN));
+        assert_eq!(parse("922337203685477.5808",RoundingMode::Reject),Err(AmountError::Overflow));
+        assert_eq!(parse("1e308",RoundingMode::Reject),Err(AmountError::Overflow));
+        assert_eq!(Amount::MAX.checked_add(Amount::from_minor(1)),None);
+        assert_eq!(Amount::MIN.checked_sub(Amount::from_minor(1)),None);
+        assert_eq!(Amount::MAX.checked_sub(Amount::MAX),Some(Amount::ZERO));
+    }
+    #[test]
+    fn amount_display()
+    {
+        assert_eq!(Amount::from_minor(20000).to_string(),"2.0");
+        assert_eq!(Amount::from_minor(15000).to_string(),"1.5");
+        assert_eq!(Amount::from_minor(9999).to_string(),"0.9999");
+        assert_eq!(Amount::from_minor(-1).to_string(),"-0.0001");
+        assert_eq!(Amount::MIN.to_string(),"-922337203685477.5808");
+    }
+}
@@ -0,0 +1,164 @@
+use std::{fmt, sync::Arc};
+
+///
+/// Reads the amounts of an input source written in its own notation, E.G. in cents or
+/// with a currency symbol, so they can be parsed the same way as any other
+///
+/// Set on the Dialect of the input, a feed of its own can have a parser of its own
+///
+pub trait AmountParser: fmt::Debug + Send + Sync
+{
+    /// Rewrites the amount as a plain decimal with a dot for the decimal point,
+    /// E.G. "$1,234.56" to "1234.56", or None if it can't be read; amounts that can't be
+    /// read are kept as given, so they are rejected as invalid
+    ///
+    /// # Arguments
+    ///
+    /// * 'text' - The amount as given in the input
+    /// * 'decimal_comma' - Whether the input uses a comma for the decimal point
+    fn normalize(&self, text: &str, decimal_comma: bool) -> Option<String>;
+}
+
+///
+/// Amounts written as plain decimals, E.G. "1234.56", the default
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlainDecimal;
+impl AmountParser for PlainDecimal
+{
+    fn normalize(&self, text: &str, decimal_comma: bool) -> Option<String> {
+        match decimal_comma
+        {
+            true => Some(text.replace(',', ".")),
+            false => Some(text.to_string())
+        }
+    }
+}
+
+///
+/// Amounts written as whole minor units, E.G. "123456" cents for 1234.56
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinorUnits
+{
+    /// How many decimals a unit is, 2 for cents
+    pub decimals: usize,
+}
+impl AmountParser for MinorUnits
+{
+    fn normalize(&self, text: &str, _decimal_comma: bool) -> Option<String> {
+        let (sign, digits) = split_sign(text.trim());
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {return None}
+        let digits = format!("{:0>width$}", digits, width = self.decimals + 1);
+        let (int_part, frac) = digits.split_at(digits.len() - self.decimals);
+        match frac.is_empty()
+        {
+            true => Some(format!("{}{}", sign, int_part)),
+            false => Some(format!("{}{}.{}", sign, int_part, frac))
+        }
+    }
+}
+
+///
+/// Amounts that may have a currency symbol or code in front and separators between
+/// the thousands, E.G. "$1,234.56", "-€5" or "EUR 1.234,56" with a decimal comma
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SymbolPrefixed;
+impl AmountParser for SymbolPrefixed
+{
+    fn normalize(&self, text: &str, decimal_comma: bool) -> Option<String> {
+        //the sign can come before the symbol or after it
+        let (outer, rest) = split_sign(text.trim());
+        let rest = rest.trim_start_matches(|c: char| !c.is_ascii_digit() && !matches!(c, '-' | '+' | '.' | ',')).trim_start();
+        let (inner, number) = split_sign(rest);
+        if !outer.is_empty() && !inner.is_empty() {return None}
+        let (thousands, point) = if decimal_comma {('.', ',')} else {(',', '.')};
+        let number: String = number.chars().filter(|c| *c != thousands).map(|c| if c == point {'.'} else {c}).collect();
+        let valid = number.bytes().any(|b| b.is_ascii_digit()) && number.bytes().all(|b| b.is_ascii_digit() || b == b'.') && number.matches('.').count() <= 1;
+        valid.then(|| format!("{}{}{}", outer, inner, number))
+    }
+}
+
+/// Splits a leading sign off, returning "-" for a minus and nothing otherwise
+///
+/// # Arguments
+///
+/// * 'text' - The amount, trimmed
+fn split_sign(text: &str) -> (&'static str, &str)
+{
+    match text.strip_prefix('-')
+    {
+        Some(rest) => ("-", rest),
+        None => ("", text.strip_prefix('+').unwrap_or(text))
+    }
+}
+
+/// Parses the name of a built-in amount parser given on the command line, "decimal",
+/// "minor" for cents, "minor:<decimals>" for other minor units, or "symbol"
+///
+/// # Arguments
+///
+/// * 'text' - The name as given
+pub fn parse_amount_parser(text: &str) -> Result<Arc<dyn AmountParser>, String>
+{
+    match text.split_once(':')
+    {
+        None if text == "decimal" => Ok(Arc::new(PlainDecimal)),
+        None if text == "minor" => Ok(Arc::new(MinorUnits { decimals: 2 })),
+        None if text == "symbol" => Ok(Arc::new(SymbolPrefixed)),
+        Some(("minor", decimals)) => match decimals.parse()
+        {
+            Ok(decimals) if decimals <= 18 => Ok(Arc::new(MinorUnits { decimals })),
+            _ => Err(format!("invalid number of decimals '{}'", decimals))
+        },
+        _ => Err(format!("unknown amount format '{}'", text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dialect, Engine, EnginePolicy, RejectReason, SchemaMode, TxRecord};
+
+    #[test]
+    fn minor_units()
+    {
+        let cents = MinorUnits { decimals: 2 };
+        assert_eq!(cents.normalize("123456", false).as_deref(),Some("1234.56"));
+        assert_eq!(cents.normalize("-5", false).as_deref(),Some("-0.05"));
+        assert_eq!(MinorUnits { decimals: 0 }.normalize("7", false).as_deref(),Some("7"));
+        assert_eq!(cents.normalize("1.5", false),None);
+    }
+    #[test]
+    fn symbol_prefixed()
+    {
+        assert_eq!(SymbolPrefixed.normalize("$1,234.56", false).as_deref(),Some("1234.56"));
+        assert_eq!(SymbolPrefixed.normalize("-€5", false).as_deref(),Some("-5"));
+        assert_eq!(SymbolPrefixed.normalize("EUR -1.234,5", true).as_deref(),Some("-1234.5"));
+        assert_eq!(SymbolPrefixed.normalize("12", false).as_deref(),Some("12"));
+        assert_eq!(SymbolPrefixed.normalize("$1.2.3", false),None);
+        assert_eq!(SymbolPrefixed.normalize("$", false),None);
+    }
+    #[test]
+    fn parser_names()
+    {
+        assert!(parse_amount_parser("decimal").is_ok() && parse_amount_parser("symbol").is_ok());
+        assert!(parse_amount_parser("minor:3").is_ok());
+        assert!(parse_amount_parser("minor:x").is_err() && parse_amount_parser("roman").is_err());
+    }
+    #[test]
+    fn unreadable_amount_rejected()
+    {
+        let dialect = Dialect { amount_parser: parse_amount_parser("minor").unwrap(), ..Dialect::default() };
+        let records: Vec<TxRecord> = dialect.read_records("type,client,tx,amount\ndeposit,1,1,150\ndeposit,1,2,1.5\n".as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!((records[0].amount.as_deref(), records[0].invalid_amount),(Some("1.50"), false));
+        assert_eq!((records[1].amount.as_deref(), records[1].invalid_amount),(Some("1.5"), true));
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        records.into_iter().for_each(|r| engine.apply_record(r));
+        assert_eq!(engine.rejections.len(),1);
+        assert_eq!((engine.rejections[0].tx, engine.rejections[0].reason, engine.rejections[0].amount.as_deref()),(2, RejectReason::InvalidAmount, Some("1.5")));
+        assert_eq!(engine.clients[&1].acc.total.to_string(),"1.5");
+    }
+}
@@ -0,0 +1,366 @@
+use std::{convert::TryFrom, fmt, io, sync::OnceLock};
+use apache_avro::{Reader, Schema, Writer, types::Value};
+use crate::{Account, AccountSink, Dialect, HeaderError, SchemaMode, Tx, TxRecord};
+
+/// The schema transactions are written with, the same columns as the csv input
+pub const TX_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Tx",
+    "fields": [
+        {"name": "type", "type": "string"},
+        {"name": "client", "type": "int"},
+        {"name": "tx", "type": "long"},
+        {"name": "amount", "type": ["null", "string"], "default": null},
+        {"name": "timestamp", "type": ["null", "long"], "default": null},
+        {"name": "currency", "type": ["null", "string"], "default": null},
+        {"name": "memo", "type": ["null", "string"], "default": null},
+        {"name": "counterparty", "type": ["null", "string"], "default": null},
+        {"name": "tenant", "type": ["null", "string"], "default": null},
+        {"name": "signature", "type": ["null", "string"], "default": null}
+    ]
+}"#;
+/// The schema of the account report, amounts are kept as text so no precision is lost
+pub const ACCOUNT_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Account",
+    "fields": [
+        {"name": "client", "type": "int"},
+        {"name": "available", "type": "string"},
+        {"name": "held", "type": "string"},
+        {"name": "total", "type": "string"},
+        {"name": "locked", "type": "boolean"},
+        {"name": "pending", "type": "string", "default": "0"}
+    ]
+}"#;
+/// The first byte of every message framed for a schema registry
+const REGISTRY_MAGIC: u8 = 0;
+
+///
+/// Something went wrong reading or writing avro
+///
+#[derive(Debug)]
+pub enum AvroError
+{
+    Avro(Box<apache_avro::Error>),
+    Header(HeaderError),
+    /// A registry message that doesn't start with the magic byte and schema ID
+    Framing,
+}
+impl fmt::Display for AvroError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            AvroError::Avro(e) => write!(f, "{}", e),
+            AvroError::Header(e) => write!(f, "{}", e),
+            AvroError::Framing => write!(f, "not a schema registry message"),
+        }
+    }
+}
+impl From<apache_avro::Error> for AvroError
+{
+    fn from(e: apache_avro::Error) -> Self {
+        AvroError::Avro(Box::new(e))
+    }
+}
+impl From<HeaderError> for AvroError
+{
+    fn from(e: HeaderError) -> Self {
+        AvroError::Header(e)
+    }
+}
+impl From<AvroError> for io::Error
+{
+    fn from(e: AvroError) -> Self {
+        io::Error::other(e.to_string())
+    }
+}
+
+/// The parsed transaction schema
+pub fn tx_schema() -> &'static Schema
+{
+    static SCHEMA: OnceLock<Schema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::parse_str(TX_SCHEMA).expect("the transaction schema is valid"))
+}
+/// The parsed account schema
+pub fn account_schema() -> &'static Schema
+{
+    static SCHEMA: OnceLock<Schema> = OnceLock::new();
+    SCHEMA.get_or_init(|| Schema::parse_str(ACCOUNT_SCHEMA).expect("the account schema is valid"))
+}
+
+/// Wraps an optional value in the nullable union it is written as
+fn nullable(value: Option<Value>) -> Value
+{
+    match value
+    {
+        Some(v) => Value::Union(1, Box::new(v)),
+        None => Value::Union(0, Box::new(Value::Null))
+    }
+}
+/// Returns a transaction as a value following the transaction schema
+pub fn tx_value(tx: &Tx) -> Value
+{
+    Value::Record(vec![
+        ("type".to_string(), Value::String(tx.r#type.as_str().to_string())),
+        ("client".to_string(), Value::Int(tx.client.into())),
+        ("tx".to_string(), Value::Long(tx.tx.into())),
+        ("amount".to_string(), nullable(tx.amount.map(|a| Value::String(a.to_string())))),
+        ("timestamp".to_string(), nullable(tx.timestamp.map(Value::Long))),
+        ("currency".to_string(), nullable(tx.currency.clone().map(Value::String))),
+        ("memo".to_string(), nullable(tx.memo.clone().map(Value::String))),
+        ("counterparty".to_string(), nullable(tx.counterparty.clone().map(Value::String))),
+        ("tenant".to_string(), nullable(None)),
+        ("signature".to_string(), nullable(tx.signature.clone().map(Value::String))),
+    ])
+}
+/// Returns an account as a value following the account schema
+pub fn account_value(acc: &Account) -> Value
+{
+    Value::Record(vec![
+        ("client".to_string(), Value::Int(acc.client.into())),
+        ("available".to_string(), Value::String(acc.available.to_string())),
+        ("held".to_string(), Value::String(acc.held.to_string())),
+        ("total".to_string(), Value::String(acc.total.to_string())),
+        ("locked".to_string(), Value::Boolean(acc.locked)),
+        ("pending".to_string(), Value::String(acc.pending.to_string())),
+    ])
+}
+
+/// Reads a field as text, whatever type it was written with
+fn text(value: &Value) -> Option<String>
+{
+    match value
+    {
+        Value::Union(_, inner) => text(inner),
+        Value::String(s) | Value::Enum(_, s) => Some(s.clone()),
+        Value::Int(i) => Some(i.to_string()),
+        Value::Long(l) => Some(l.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Double(d) => Some(d.to_string()),
+        _ => None
+    }
+}
+/// Reads a field as a whole number
+fn integer(value: &Value) -> Option<i64>
+{
+    match value
+    {
+        Value::Union(_, inner) => integer(inner),
+        Value::Int(i) => Some((*i).into()),
+        Value::Long(l) => Some(*l),
+        _ => None
+    }
+}
+/// Turns an avro record into a transaction record
+///
+/// Returns None if the type, client or tx can't be read, the same as a malformed csv row
+///
+/// # Arguments
+///
+/// * 'value' - The record as read from avro
+/// * 'names' - The names of the record fields, after renaming
+pub fn value_to_record(value: &Value, names: &csv::StringRecord) -> Option<TxRecord>
+{
+    let fields = match value
+    {
+        Value::Record(fields) => fields,
+        _ => return None
+    };
+    let field = |name: &str| names.iter().position(|n| n == name).and_then(|i| fields.get(i)).map(|(_, v)| v);
+    let r#type = text(field("type")?)?.parse().ok()?;
+    let client = u16::try_from(integer(field("client")?)?).ok()?;
+    let tx = u32::try_from(integer(field("tx")?)?).ok()?;
+    let mut record = TxRecord::new(r#type, client, tx, field("amount").and_then(text));
+    record.timestamp = field("timestamp").and_then(text);
+    record.currency = field("currency").and_then(text);
+    record.memo = field("memo").and_then(text);
+    record.counterparty = field("counterparty").and_then(text);
+    record.tenant = field("tenant").and_then(text);
+    record.signature = field("signature").and_then(text);
+    Some(record)
+}
+
+/// Reads every transaction record from an avro container file, using the
+/// schema embedded in the file
+///
+/// Fields are renamed and checked the same way as csv headers
+///
+/// # Arguments
+///
+/// * 'input' - The avro file
+/// * 'dialect' - Holds the column renames
+/// * 'schema' - Whether fields we don't know about are an error
+pub fn read_avro_records<'a, R: io::Read + 'a>(input: R, dialect: &Dialect, schema: SchemaMode) -> Result<impl Iterator<Item = TxRecord> + 'a, AvroError>
+{
+    let reader = Reader::new(input)?;
+    let headers: csv::StringRecord = match reader.writer_schema()
+    {
+        Schema::Record(record) => record.fields.iter().map(|f| f.name.as_str()).collect(),
+        _ => csv::StringRecord::new()
+    };
+    let names = dialect.map_headers(&headers, schema)?;
+    Ok(reader
+        .filter_map(Result::ok)
+        .filter_map(move |value| value_to_record(&value, &names)))
+}
+
+/// Writes transactions to an avro container file, with the transaction schema embedded
+///
+/// # Arguments
+///
+/// * 'txs' - The transactions to write
+/// * 'out' - Where to write the file to
+pub fn write_tx_avro<'t, W: io::Write>(txs: impl IntoIterator<Item = &'t Tx>, out: W) -> Result<W, AvroError>
+{
+    let mut writer = Writer::new(tx_schema(), out);
+    for tx in txs
+    {
+        writer.append(tx_value(tx))?;
+    }
+    Ok(writer.into_inner()?)
+}
+
+/// Encodes a transaction as a schema registry message, that is the magic byte,
+/// the schema ID as four big endian bytes, and then the bare avro datum
+///
+/// # Arguments
+///
+/// * 'schema_id' - The ID the transaction schema is registered under
+/// * 'tx' - The transaction to encode
+pub fn encode_registry_message(schema_id: u32, tx: &Tx) -> Result<Vec<u8>, AvroError>
+{
+    let mut message = vec![REGISTRY_MAGIC];
+    message.extend_from_slice(&schema_id.to_be_bytes());
+    message.extend(apache_avro::to_avro_datum(tx_schema(), tx_value(tx))?);
+    Ok(message)
+}
+/// Decodes a schema registry message written with the transaction schema,
+/// returning the schema ID it was framed with and the transaction
+///
+/// The datum is always read with the transaction schema, so messages written
+/// with any other schema can't be read this way
+///
+/// # Arguments
+///
+/// * 'message' - The message as taken off the topic
+pub fn decode_registry_message(message: &[u8]) -> Result<(u32, TxRecord), AvroError>
+{
+    if message.len() < 5 || message[0] != REGISTRY_MAGIC {return Err(AvroError::Framing)}
+    let schema_id = u32::from_be_bytes([message[1], message[2], message[3], message[4]]);
+    let value = apache_avro::from_avro_datum(tx_schema(), &mut &message[5..], None)?;
+    let names: csv::StringRecord = ["type", "client", "tx", "amount", "timestamp", "currency", "memo", "counterparty", "tenant", "signature"].iter().collect();
+    match value_to_record(&value, &names)
+    {
+        Some(record) => Ok((schema_id, record)),
+        None => Err(AvroError::Framing)
+    }
+}
+
+///
+/// Writes accounts to an avro container file, with the account schema embedded
+///
+pub struct AvroSink<W: io::Write>
+{
+    writer: Option<Writer<'static, W>>,
+}
+impl<W: io::Write> AvroSink<W>
+{
+    pub fn new(out: W) -> AvroSink<W>
+    {
+        AvroSink { writer: Some(Writer::new(account_schema(), out)) }
+    }
+}
+impl<W: io::Write> AccountSink for AvroSink<W>
+{
+    fn write_account(&mut self, acc: &Account) -> io::Result<()>
+    {
+        if let Some(writer) = self.writer.as_mut()
+        {
+            writer.append(account_value(acc)).map_err(AvroError::from)?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> io::Result<()>
+    {
+        if let Some(writer) = self.writer.take()
+        {
+            writer.into_inner().map_err(AvroError::from)?.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, TypeTx};
+
+    #[test]
+    fn tx_round_trip()
+    {
+        let mut deposit = Tx::new(TypeTx::Deposit, 3, 7, Some(Amount::from_minor(15000)));
+        deposit.memo = Some("invoice 12".to_string());
+        let dispute = Tx::new(TypeTx::Dispute, 3, 7, None);
+        let file = write_tx_avro(&[deposit, dispute], Vec::new()).unwrap();
+        let records: Vec<TxRecord> = read_avro_records(file.as_slice(), &Dialect::default(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records.len(),2);
+        assert_eq!(records[0].amount.as_deref(),Some("1.5"));
+        assert_eq!(records[0].memo.as_deref(),Some("invoice 12"));
+        assert_eq!(records[1].r#type,TypeTx::Dispute);
+        assert!(records[1].amount.is_none());
+    }
+    #[test]
+    fn renamed_fields()
+    {
+        let schema = Schema::parse_str(r#"{"type": "record", "name": "Upstream", "fields": [
+            {"name": "kind", "type": "string"}, {"name": "client", "type": "long"},
+            {"name": "tx", "type": "long"}, {"name": "amount", "type": "double"}]}"#).unwrap();
+        let mut writer = Writer::new(&schema, Vec::new());
+        writer.append(Value::Record(vec![
+            ("kind".to_string(), Value::String("deposit".to_string())),
+            ("client".to_string(), Value::Long(2)),
+            ("tx".to_string(), Value::Long(9)),
+            ("amount".to_string(), Value::Double(2.5)),
+        ])).unwrap();
+        let file = writer.into_inner().unwrap();
+        let mut dialect = Dialect::default();
+        assert!(read_avro_records(file.as_slice(), &dialect, SchemaMode::Strict).is_err());
+        dialect.renames.insert("kind".to_string(), "type".to_string());
+        let records: Vec<TxRecord> = read_avro_records(file.as_slice(), &dialect, SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records[0].client,2);
+        assert_eq!(records[0].amount.as_deref(),Some("2.5"));
+    }
+    #[test]
+    fn registry_message()
+    {
+        let tx = Tx::new(TypeTx::Withdrawal, 1, 4, Some(Amount::from_minor(2500)));
+        let message = encode_registry_message(42, &tx).unwrap();
+        assert_eq!(&message[..5],&[0, 0, 0, 0, 42]);
+        let (schema_id, record) = decode_registry_message(&message).unwrap();
+        assert_eq!(schema_id,42);
+        assert_eq!(record.r#type,TypeTx::Withdrawal);
+        assert_eq!(record.amount.as_deref(),Some("0.25"));
+        assert!(matches!(decode_registry_message(&[1, 0]), Err(AvroError::Framing)));
+    }
+    #[test]
+    fn account_sink()
+    {
+        let mut acc = Account::new(5);
+        acc.held = Amount::from_minor(10000);
+        let mut out = Vec::new();
+        {
+            let mut sink = AvroSink::new(&mut out);
+            sink.write_account(&acc).unwrap();
+            sink.finish().unwrap();
+        }
+        let values: Vec<Value> = Reader::new(out.as_slice()).unwrap().map(Result::unwrap).collect();
+        assert_eq!(values.len(),1);
+        let mut empty = Vec::new();
+        {
+            let mut sink = AvroSink::new(&mut empty);
+            sink.finish().unwrap();
+        }
+        assert_eq!(Reader::new(empty.as_slice()).unwrap().count(),0);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{io, path::PathBuf, thread, time::{Duration, Instant}};
+use crate::{generate, Amount, Checkpoint, Dialect, Engine, EnginePolicy, GeneratorConfig, IngestQueue, Overflow, TxRecord, TypeTx};
+
+/// Ready-made input shapes to measure with, by name
+pub const FIXTURES: [(&str, GeneratorConfig); 3] = [
+    ("small", GeneratorConfig { clients: 100, rows: 10_000, dispute_rate: 0.01, duplicate_rate: 0.0, malformed_rate: 0.0, seed: 1 }),
+    ("wide", GeneratorConfig { clients: 60_000, rows: 1_000_000, dispute_rate: 0.01, duplicate_rate: 0.001, malformed_rate: 0.001, seed: 1 }),
+    ("dispute-heavy", GeneratorConfig { clients: 1_000, rows: 1_000_000, dispute_rate: 0.2, duplicate_rate: 0.01, malformed_rate: 0.001, seed: 1 }),
+];
+
+/// Generates the csv input of a fixture, or any other shape, in memory
+///
+/// The same config always gives the same input, so runs can be compared
+///
+/// # Arguments
+///
+/// * 'config' - The shape of the input, E.G. one of FIXTURES
+pub fn fixture(config: &GeneratorConfig) -> Vec<u8>
+{
+    let mut input = Vec::new();
+    //writing to memory can't fail
+    let _ = generate(config, &mut input);
+    input
+}
+
+///
+/// How the engine is run, matching the ways it can be deployed
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BenchMode
+{
+    /// A single engine applying every row in order, as the batch run does
+    Sync,
+    /// The clients split over this many engines by client ID, each on a thread of its own
+    Sharded(usize),
+    /// Rows pushed from another thread onto a bounded ingestion queue of this capacity
+    Queued(usize),
+    /// A single engine persisting a checkpoint to the directory every so many rows, as --checkpoint does
+    Checkpointed { dir: PathBuf, every: u64 },
+    /// A single engine applying the rows a batch of columns at a time, as Engine::apply_columns does
+    Columnar(usize),
+    /// The clients kept in redis, as --redis does
+    #[cfg(feature = "redis")]
+    Redis { url: String, prefix: String },
+}
+
+///
+/// What a benchmark run measured
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchResult
+{
+    /// The rows read from the input
+    pub rows: u64,
+    /// The clients at the end
+    pub clients: usize,
+    /// The transactions rejected
+    pub rejections: usize,
+    /// How long reading and applying the input took
+    pub elapsed: Duration,
+}
+impl BenchResult
+{
+    /// How many rows were applied a second
+    pub fn rows_per_sec(&self) -> f64
+    {
+        self.rows as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Runs the input through the engine as the mode says under the default policy,
+/// timing it from reading the first row to applying the last
+///
+/// Fast enough to call from a criterion iteration, E.G.
+/// `b.iter(|| bench::run(&input, &BenchMode::Sync))`
+///
+/// # Arguments
+///
+/// * 'input' - The transactions as csv, E.G. from fixture
+/// * 'mode' - How the engine is run
+///
+/// # Errors
+///
+/// Fails if the header can't be read, or the storage the mode uses fails
+pub fn run(input: &[u8], mode: &BenchMode) -> io::Result<BenchResult>
+{
+    run_with_policy(input, mode, &EnginePolicy::default())
+}
+
+/// Runs the input through the engine as the mode says, under the given policy
+///
+/// # Arguments
+///
+/// * 'input' - The transactions as csv, E.G. from fixture
+/// * 'mode' - How the engine is run
+/// * 'policy' - The policy to deploy with
+///
+/// # Errors
+///
+/// Fails if the header can't be read, or the storage the mode uses fails
+pub fn run_with_policy(input: &[u8], mode: &BenchMode, policy: &EnginePolicy) -> io::Result<BenchResult>
+{
+    let dialect = Dialect::default();
+    let start = Instant::now();
+    let (rows, clients, rejections) = match mode
+    {
+        BenchMode::Sync => {
+            let mut engine = Engine::new(policy.clone());
+            let mut rows = 0;
+            for record in dialect.read_records(input, policy.schema)?
+            {
+                engine.apply_record(record);
+                rows += 1;
+            }
+            (rows, engine.clients.len(), engine.rejections.len())
+        },
+        BenchMode::Sharded(shards) => {
+            let shards = (*shards).max(1);
+            let mut partitions: Vec<Vec<TxRecord>> = vec![Vec::new(); shards];
+            let mut rows = 0;
+            for record in dialect.read_records(input, policy.schema)?
+            {
+                partitions[record.client as usize % shards].push(record);
+                rows += 1;
+            }
+            let totals = thread::scope(|scope| {
+                let workers: Vec<_> = partitions.into_iter().map(|partition| scope.spawn(move || {
+                    let mut engine = Engine::new(policy.clone());
+                    partition.into_iter().for_each(|record| engine.apply_record(record));
+                    (engine.clients.len(), engine.rejections.len())
+                })).collect();
+                workers.into_iter().map(|w| w.join().unwrap_or_default()).fold((0, 0), |(c, r), (wc, wr)| (c + wc, r + wr))
+            });
+            (rows, totals.0, totals.1)
+        },
+        BenchMode::Queued(capacity) => {
+            let records = dialect.read_records(input, policy.schema)?;
+            let queue = IngestQueue::new(*capacity, Overflow::Block);
+            let mut engine = Engine::new(policy.clone());
+            let (rows, applied) = thread::scope(|scope| {
+                let producer = scope.spawn(|| {
+                    let mut rows = 0;
+                    for record in records
+                    {
+                        //a blocking queue only fails when spilling, which it never does
+                        let _ = queue.push(record);
+                        rows += 1;
+                    }
+                    queue.close();
+                    rows
+                });
+                let applied = engine.apply_queue(&queue);
+                (producer.join().unwrap_or_default(), applied)
+            });
+            applied?;
+            (rows, engine.clients.len(), engine.rejections.len())
+        },
+        BenchMode::Checkpointed { dir, every } => {
+            std::fs::create_dir_all(dir)?;
+            let mut engine = Engine::new(policy.clone());
+            let mut rows = 0;
+            for (offset, record) in dialect.read_records_from(io::Cursor::new(input), 0, policy.schema)?
+            {
+                engine.apply_record(record);
+                rows += 1;
+                if rows % (*every).max(1) == 0
+                {
+                    engine.checkpoint(dir, &Checkpoint { input: "bench".to_string(), offset, rows }, None)?;
+                }
+            }
+            (rows, engine.clients.len(), engine.rejections.len())
+        },
+        BenchMode::Columnar(batch) => {
+            let mut engine = Engine::new(policy.clone());
+            let mut columns = Columns::default();
+            let mut rows = 0;
+            for record in dialect.read_records(input, policy.schema)?
+            {
+                rows += 1;
+                match columns.push(&record, policy)
+                {
+                    true if columns.types.len() >= *batch => columns.flush(&mut engine),
+                    true => (),
+                    //rows the columns can't hold go through the record path, in order
+                    false => {
+                        columns.flush(&mut engine);
+                        engine.apply_record(record);
+                    }
+                }
+            }
+            columns.flush(&mut engine);
+            (rows, engine.clients.len(), engine.rejections.len())
+        },
+        #[cfg(feature = "redis")]
+        BenchMode::Redis { url, prefix } => {
+            let mut state = crate::redis_state::RedisState::connect(url, prefix, policy.clone()).map_err(|e| io::Error::other(e.to_string()))?;
+            let mut rows = 0;
+            for record in dialect.read_records(input, policy.schema)?
+            {
+                state.apply_record(record).map_err(|e| io::Error::other(e.to_string()))?;
+                rows += 1;
+            }
+            let clients = state.clients().map_err(|e| io::Error::other(e.to_string()))?.len();
+            (rows, clients, state.rejections.len())
+        }
+    };
+    Ok(BenchResult { rows, clients, rejections, elapsed: start.elapsed() })
+}
+
+///
+/// Rows waiting to be applied as columns
+///
+#[derive(Default)]
+struct Columns
+{
+    types: Vec<TypeTx>,
+    clients: Vec<u16>,
+    txs: Vec<u32>,
+    amounts: Vec<Option<Amount>>,
+}
+impl Columns
+{
+    /// Adds the record as a row, returning false if it has more than the columns
+    /// hold or an amount that doesn't parse
+    fn push(&mut self, record: &TxRecord, policy: &EnginePolicy) -> bool
+    {
+        if record.timestamp.is_some() || record.currency.is_some() || record.memo.is_some() || record.counterparty.is_some()
+            || record.tenant.is_some() || record.signature.is_some() || record.custom.is_some()
+        {
+            return false;
+        }
+        let amount = match &record.amount
+        {
+            Some(text) => match Amount::parse(text, policy.rounding)
+            {
+                Ok(amount) => Some(amount),
+                Err(_) => return false
+            },
+            None => None
+        };
+        self.types.push(record.r#type);
+        self.clients.push(record.client);
+        self.txs.push(record.tx);
+        self.amounts.push(amount);
+        true
+    }
+    /// Applies the waiting rows and clears them
+    fn flush(&mut self, engine: &mut Engine)
+    {
+        //the columns always grow together
+        let _ = engine.apply_columns(&self.types, &self.clients, &self.txs, &self.amounts);
+        self.types.clear();
+        self.clients.clear();
+        self.txs.clear();
+        self.amounts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bench_modes()
+    {
+        let input = fixture(&FIXTURES[0].1);
+        assert_eq!(input,fixture(&FIXTURES[0].1));
+        let dir = std::env::temp_dir().join(format!("bench_{}", std::process::id()));
+        let sync = run(&input, &BenchMode::Sync).unwrap();
+        assert_eq!(sync.rows,10_000);
+        assert!(sync.rows_per_sec() > 0.0);
+        for mode in [BenchMode::Sharded(4), BenchMode::Queued(64), BenchMode::Columnar(512), BenchMode::Checkpointed { dir: dir.clone(), every: 2500 }]
+        {
+            let result = run(&input, &mode).unwrap();
+            assert_eq!((result.rows, result.clients, result.rejections),(sync.rows, sync.clients, sync.rejections));
+        }
+        assert_eq!(Checkpoint::read(&dir).unwrap().map(|c| c.rows),Some(10_000));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
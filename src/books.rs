@@ -0,0 +1,283 @@
+use std::{collections::BTreeMap, fmt, io};
+use serde::{Deserialize, Serialize};
+use crate::{Amount, BalanceChange, Engine, FEE_MEMO};
+
+///
+/// A system account the operations of the engine are posted to, from the point of
+/// view of the business running it
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BookAccount
+{
+    /// The funds the business holds, which deposits come into and withdrawals and chargebacks go out of
+    Cash,
+    /// What the business owes clients and they can withdraw
+    CustomerFunds,
+    /// What the business owes clients for deposits that haven't settled yet
+    PendingFunds,
+    /// What the business owes clients for deposits held while disputed
+    HeldFunds,
+    /// The interest credited to clients
+    InterestExpense,
+    /// The fees the policy hook charged clients
+    FeeIncome,
+    /// The chargeback reserve
+    ChargebackReserve,
+    /// The chargebacks debited from the reserve
+    ChargebackExpense,
+    /// The balances that were there when the books were started, and the reserve opened with
+    OpeningBalances,
+}
+impl BookAccount
+{
+    /// Returns the name of the account, as in the trial balance
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            BookAccount::Cash => "cash",
+            BookAccount::CustomerFunds => "customer_funds",
+            BookAccount::PendingFunds => "pending_funds",
+            BookAccount::HeldFunds => "held_funds",
+            BookAccount::InterestExpense => "interest_expense",
+            BookAccount::FeeIncome => "fee_income",
+            BookAccount::ChargebackReserve => "chargeback_reserve",
+            BookAccount::ChargebackExpense => "chargeback_expense",
+            BookAccount::OpeningBalances => "opening_balances",
+        }
+    }
+    /// The account on the other side of a change to the balances of a client
+    ///
+    /// # Arguments
+    ///
+    /// * 'cause' - What made the change, as in BalanceChange
+    pub(crate) fn counter(cause: &str) -> BookAccount
+    {
+        match cause
+        {
+            "interest" => BookAccount::InterestExpense,
+            FEE_MEMO => BookAccount::FeeIncome,
+            "opening" => BookAccount::OpeningBalances,
+            _ => BookAccount::Cash
+        }
+    }
+}
+impl fmt::Display for BookAccount
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The postings a change to the balances of a client makes, as amounts to debit each
+/// account by, negative for a credit
+///
+/// # Arguments
+///
+/// * 'change' - How the balances of the client changed
+pub(crate) fn change_postings(change: &BalanceChange) -> [(BookAccount, Amount); 4]
+{
+    let credit = |amount: Amount| Amount::ZERO.checked_sub(amount).unwrap_or(Amount::MAX);
+    [
+        (BookAccount::CustomerFunds, credit(change.available)),
+        (BookAccount::HeldFunds, credit(change.held)),
+        (BookAccount::PendingFunds, credit(change.pending)),
+        (BookAccount::counter(&change.cause), change.total),
+    ]
+}
+
+/// The postings a chargeback debited from the reserve makes, as change_postings
+///
+/// # Arguments
+///
+/// * 'amount' - How much the reserve went down by, negative if it was credited back
+pub(crate) fn reserve_postings(amount: Amount) -> [(BookAccount, Amount); 2]
+{
+    [
+        (BookAccount::ChargebackExpense, amount),
+        (BookAccount::ChargebackReserve, Amount::ZERO.checked_sub(amount).unwrap_or(Amount::MAX)),
+    ]
+}
+
+///
+/// The debits and credits posted to a single book account
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Posted
+{
+    pub debits: Amount,
+    pub credits: Amount,
+}
+
+///
+/// Every operation of the engine mirrored as debit and credit postings against the
+/// system accounts, kept as running totals
+///
+/// A client balance going up is a credit to what the business owes, matched by a debit
+/// to the account the funds came from, so the debits and credits of every operation
+/// come to the same. If they don't, the balances of some client moved without their
+/// total doing the same, which is what the trial balance is there to catch
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TrialBalance
+{
+    pub accounts: BTreeMap<BookAccount, Posted>,
+}
+impl TrialBalance
+{
+    /// Posts an amount to the account, a debit if it is positive and a credit if negative
+    ///
+    /// # Arguments
+    ///
+    /// * 'account' - The account to post to
+    /// * 'amount' - How much to debit it by
+    pub(crate) fn post(&mut self, account: BookAccount, amount: Amount)
+    {
+        if amount == Amount::ZERO {return}
+        let posted = self.accounts.entry(account).or_default();
+        match amount.is_negative()
+        {
+            true => posted.credits = posted.credits.checked_sub(amount).unwrap_or(Amount::MAX),
+            false => posted.debits = posted.debits.checked_add(amount).unwrap_or(Amount::MAX)
+        }
+    }
+    /// Posts a change to the balances of a client, and its counter entry
+    ///
+    /// # Arguments
+    ///
+    /// * 'change' - How the balances of the client changed
+    pub(crate) fn post_change(&mut self, change: &BalanceChange)
+    {
+        for (account, amount) in change_postings(change)
+        {
+            self.post(account, amount);
+        }
+    }
+    /// Posts a chargeback debited from the reserve, or credited back to it if negative
+    ///
+    /// # Arguments
+    ///
+    /// * 'amount' - How much the reserve went down by
+    pub(crate) fn post_reserve(&mut self, amount: Amount)
+    {
+        for (account, amount) in reserve_postings(amount)
+        {
+            self.post(account, amount);
+        }
+    }
+    /// The debits posted to every account
+    pub fn debits(&self) -> Amount
+    {
+        self.accounts.values().fold(Amount::ZERO, |sum, p| sum.checked_add(p.debits).unwrap_or(Amount::MAX))
+    }
+    /// The credits posted to every account
+    pub fn credits(&self) -> Amount
+    {
+        self.accounts.values().fold(Amount::ZERO, |sum, p| sum.checked_add(p.credits).unwrap_or(Amount::MAX))
+    }
+    /// Whether the debits and credits come to the same, so the books sum to zero
+    pub fn is_balanced(&self) -> bool
+    {
+        self.debits() == self.credits()
+    }
+}
+
+///
+/// A single row of the trial balance report
+///
+#[derive(Serialize)]
+struct TrialBalanceRow<'a>
+{
+    account: &'a str,
+    debit: Amount,
+    credit: Amount,
+    /// The debits less the credits
+    balance: Option<Amount>,
+}
+
+/// Writes the trial balance as csv, an account a row, ending with a total row
+///
+/// # Arguments
+///
+/// * 'books' - The trial balance to write
+/// * 'out' - Where to write it to
+pub fn write_trial_balance<W: io::Write>(books: &TrialBalance, out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for (account, posted) in &books.accounts
+    {
+        wrtr.serialize(TrialBalanceRow { account: account.as_str(), debit: posted.debits, credit: posted.credits, balance: posted.debits.checked_sub(posted.credits) })?;
+    }
+    let (debit, credit) = (books.debits(), books.credits());
+    wrtr.serialize(TrialBalanceRow { account: "total", debit, credit, balance: debit.checked_sub(credit) })?;
+    wrtr.flush()?;
+    Ok(())
+}
+
+impl Engine
+{
+    /// Starts mirroring every change to the balances as postings to the books
+    ///
+    /// The balances the accounts and reserve already have are posted against the opening
+    /// balances account, so the books are whole for an engine restored from a snapshot
+    pub fn enable_trial_balance(&mut self)
+    {
+        if self.books.is_some() {return}
+        let mut books = TrialBalance::default();
+        for c in self.clients.values()
+        {
+            books.post_change(&BalanceChange { client: c.acc.client, tx: 0, cause: "opening".to_string(),
+                available: c.acc.available, held: c.acc.held, total: c.acc.total, pending: c.acc.pending });
+        }
+        if let Some(reserve) = self.reserve.as_ref()
+        {
+            books.post(BookAccount::ChargebackReserve, reserve.balance);
+            books.post(BookAccount::OpeningBalances, Amount::ZERO.checked_sub(reserve.balance).unwrap_or(Amount::MAX));
+        }
+        self.books = Some(books);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn books_sum_to_zero()
+    {
+        let mut engine = Engine::new(EnginePolicy { reserve: Some(Amount::from_minor(100000)), ..EnginePolicy::default() });
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.enable_trial_balance();
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 2, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 3, Some(Amount::from_minor(10000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 2, None));
+        let savepoint = engine.savepoint();
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 2, None));
+        engine.rollback_to(&savepoint);
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 2, None));
+        engine.release(&savepoint);
+
+        let books = engine.books.as_ref().unwrap();
+        assert!(books.is_balanced());
+        assert_eq!(books.accounts[&BookAccount::Cash],Posted { debits: Amount::from_minor(20000), credits: Amount::from_minor(30000) });
+        assert_eq!(books.accounts[&BookAccount::HeldFunds],Posted { debits: Amount::from_minor(20000), credits: Amount::from_minor(20000) });
+        assert_eq!(books.accounts[&BookAccount::ChargebackExpense].debits,Amount::from_minor(20000));
+        assert_eq!(books.accounts[&BookAccount::OpeningBalances],Posted { debits: Amount::from_minor(50000), credits: Amount::from_minor(100000) });
+        let mut out = Vec::new();
+        write_trial_balance(books, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("account,debit,credit,balance\ncash,2.0,3.0,-1.0\ncustomer_funds,3.0,7.0,-4.0\n"));
+        assert!(out.ends_with("total,24.0,24.0,0.0\n"));
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.enable_trial_balance();
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(100000))));
+        engine.accrue(Amount::from_minor(100));
+        let books = engine.books.as_ref().unwrap();
+        assert!(books.is_balanced());
+        assert_eq!(books.accounts[&BookAccount::InterestExpense].debits,Amount::from_minor(1000));
+    }
+}
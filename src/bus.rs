@@ -0,0 +1,135 @@
+use crate::{Engine, RejectReason, TxRecord, TypeTx};
+
+///
+/// What came of a message taken off a message bus
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery
+{
+    /// The transaction was applied
+    Applied,
+    /// The transaction was added to the rejection report, for the reason given
+    Rejected(RejectReason),
+    /// A deposit or withdrawal whose ID was already applied, taken as the bus delivering it again
+    Redelivered,
+    /// The message isn't a json transaction, and nothing was applied
+    Malformed(String),
+}
+
+///
+/// Counts of the messages taken off a bus
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusStats
+{
+    pub received: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    pub redelivered: u64,
+    pub malformed: u64,
+}
+impl BusStats
+{
+    /// Counts a message by what came of it
+    ///
+    /// # Arguments
+    ///
+    /// * 'delivery' - What came of the message
+    pub fn count(&mut self, delivery: &Delivery)
+    {
+        self.received += 1;
+        match delivery
+        {
+            Delivery::Applied => self.applied += 1,
+            Delivery::Rejected(_) => self.rejected += 1,
+            Delivery::Redelivered => self.redelivered += 1,
+            Delivery::Malformed(_) => self.malformed += 1,
+        }
+    }
+}
+
+impl Engine
+{
+    /// Applies a json transaction taken off a message bus, see TxRecord::from_json
+    ///
+    /// Buses that deliver at least once send a message again when it wasn't acknowledged,
+    /// E.G. as the consumer crashed between applying and acknowledging it. With global dedup
+    /// on, a deposit or withdrawal whose ID was already applied is taken as such a
+    /// redelivery and left alone, rather than rejected as duplicate_tx. Disputes, resolves
+    /// and chargebacks repeated straight after themselves change nothing, as the
+    /// transaction is already in the state they lead to
+    ///
+    /// # Arguments
+    ///
+    /// * 'payload' - The body of the message
+    pub fn apply_message(&mut self, payload: &[u8]) -> Delivery
+    {
+        match self.accept_message(payload)
+        {
+            Ok(record) => {
+                let rejections = self.rejections.len();
+                self.apply_record(record);
+                self.delivered(rejections)
+            },
+            Err(delivery) => delivery
+        }
+    }
+    /// Reads a message as apply_message does, returning the record to apply, or what
+    /// came of the message if there is nothing to apply
+    ///
+    /// # Arguments
+    ///
+    /// * 'payload' - The body of the message
+    pub(crate) fn accept_message(&mut self, payload: &[u8]) -> Result<TxRecord, Delivery>
+    {
+        let record = TxRecord::from_json(payload).map_err(|e| Delivery::Malformed(e.to_string()))?;
+        let moves_funds = matches!(record.r#type, TypeTx::Deposit | TypeTx::Withdrawal);
+        if moves_funds && self.dedup.as_mut().is_some_and(|d| d.seen(record.tx))
+        {
+            return Err(Delivery::Redelivered);
+        }
+        Ok(record)
+    }
+    /// What came of a record once applied, by whether it added to the rejections
+    ///
+    /// # Arguments
+    ///
+    /// * 'rejections' - How many rejections there were before it was applied
+    pub(crate) fn delivered(&self, rejections: usize) -> Delivery
+    {
+        match self.rejections.get(rejections)
+        {
+            Some(rejection) => Delivery::Rejected(rejection.reason),
+            None => Delivery::Applied
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EnginePolicy, GlobalDedup};
+
+    #[test]
+    fn redeliveries_are_applied_once()
+    {
+        let policy = EnginePolicy{global_dedup:Some(GlobalDedup::default()), ..EnginePolicy::default()};
+        let mut engine = Engine::new(policy);
+        let mut stats = BusStats::default();
+        for payload in [
+            r#"{"type":"deposit","client":1,"tx":1,"amount":10}"#,
+            r#"{"type":"withdrawal","client":1,"tx":2,"amount":"2.5"}"#,
+            r#"{"type":"withdrawal","client":1,"tx":2,"amount":"2.5"}"#,
+            r#"{"type":"deposit","client":1,"tx":1,"amount":10}"#,
+            r#"{"type":"withdrawal","client":1}"#,
+            r#"{"type":"withdrawal","client":1,"tx":3,"amount":"x"}"#,
+        ]
+        {
+            let delivery = engine.apply_message(payload.as_bytes());
+            stats.count(&delivery);
+        }
+        assert_eq!(stats,BusStats { received: 6, applied: 2, rejected: 1, redelivered: 2, malformed: 1 });
+        assert_eq!(engine.clients[&1].acc.available,Amount::from_minor(75000));
+        assert_eq!(engine.rejections.iter().map(|r| r.tx).collect::<Vec<_>>(),vec![3]);
+    }
+}
@@ -0,0 +1,147 @@
+use std::{fmt, io};
+use sha2::{Digest, Sha256};
+use crate::{ledger_entries, ClientStore};
+
+/// The hash the first entry of a chain is linked to
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Hashes an entry together with the hash of the entry before it, as lowercase hex
+///
+/// # Arguments
+///
+/// * 'prev' - The hash of the previous entry, GENESIS_HASH for the first one
+/// * 'fields' - The fields of the entry, as they are written out
+pub fn chain_hash(prev: &str, fields: &[&str]) -> String
+{
+    let mut hasher = Sha256::new();
+    hasher.update(prev.as_bytes());
+    for field in fields
+    {
+        //a separator that can't appear in the fields, so they can't be shifted into each other
+        hasher.update([0x1f]);
+        hasher.update(field.as_bytes());
+    }
+    hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The hash of the last entry of the ledger export, which every other entry leads up to
+///
+/// # Arguments
+///
+/// * 'clients' - The clients that have been processed
+pub fn ledger_head(clients: &ClientStore) -> String
+{
+    ledger_entries(clients).fold(GENESIS_HASH.to_string(), |prev, (client, tx, entry)| {
+        chain_hash(&prev, &entry_fields(client, tx, entry).iter().map(|f| f.as_str()).collect::<Vec<_>>())
+    })
+}
+
+/// The fields of a ledger entry that are hashed, as they are written in the csv
+pub(crate) fn entry_fields(client: u16, tx: u32, entry: &crate::ClientTransaction) -> [String; 6]
+{
+    [client.to_string(), tx.to_string(), entry.amount.to_string(), entry.in_dispute.to_string(), entry.dispute_chain_text(), entry.memo.clone().unwrap_or_default()]
+}
+
+///
+/// Why a ledger export doesn't check out
+///
+#[derive(Debug)]
+pub enum ChainError
+{
+    /// The file couldn't be read as csv
+    Csv(csv::Error),
+    /// The file has no hash column
+    MissingHash,
+    /// The row, counted from 1 after the header, doesn't hash to what it says;
+    /// it or a row before it was changed, added or taken out
+    Tampered { row: usize },
+    /// The chain is intact, but doesn't end where it should, so rows were cut off or added at the end
+    Head { expected: String, found: String },
+}
+impl fmt::Display for ChainError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            ChainError::Csv(e) => write!(f, "{}", e),
+            ChainError::MissingHash => write!(f, "the ledger has no hash column"),
+            ChainError::Tampered { row } => write!(f, "row {} doesn't match its hash, the ledger was changed at or before it", row),
+            ChainError::Head { expected, found } => write!(f, "the ledger ends at {} rather than {}, it was cut off or added to", found, expected),
+        }
+    }
+}
+impl From<csv::Error> for ChainError
+{
+    fn from(e: csv::Error) -> Self {
+        ChainError::Csv(e)
+    }
+}
+impl From<ChainError> for io::Error
+{
+    fn from(e: ChainError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+}
+
+/// Checks that every row of a ledger export hashes to its hash column, and that
+/// the chain ends at the expected head
+///
+/// Returns how many rows there are and the hash of the last one
+///
+/// # Arguments
+///
+/// * 'input' - The ledger as written by write_ledger
+/// * 'head' - The hash the ledger should end at, E.G. from the report; not checked if None
+pub fn verify_ledger<R: io::Read>(input: R, head: Option<&str>) -> Result<(usize, String), ChainError>
+{
+    let mut rdr = csv::Reader::from_reader(input);
+    let hash_column = rdr.headers()?.iter().position(|h| h == "hash").ok_or(ChainError::MissingHash)?;
+    let mut prev = GENESIS_HASH.to_string();
+    let mut rows = 0;
+    for record in rdr.records()
+    {
+        let record = record?;
+        rows += 1;
+        let fields: Vec<&str> = record.iter().enumerate().filter(|(i, _)| *i != hash_column).map(|(_, f)| f).collect();
+        let hash = chain_hash(&prev, &fields);
+        if record.get(hash_column) != Some(hash.as_str())
+        {
+            return Err(ChainError::Tampered { row: rows });
+        }
+        prev = hash;
+    }
+    match head
+    {
+        Some(expected) if expected != prev => Err(ChainError::Head { expected: expected.to_string(), found: prev }),
+        _ => Ok((rows, prev))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{write_ledger, Amount, Client, Tx, TypeTx};
+
+    #[test]
+    fn tamper_evident_ledger()
+    {
+        let mut client = Client::new(1);
+        for tx in 1..=3
+        {
+            client.process_transaction(&Tx::new(TypeTx::Deposit, 1, tx, Some(Amount::from_minor(10000 * i64::from(tx))))).unwrap();
+        }
+        let clients: ClientStore = vec![(1, client)].into_iter().collect();
+        let mut out = Vec::new();
+        write_ledger(&clients, &mut out).unwrap();
+        let ledger = String::from_utf8(out).unwrap();
+        let head = ledger_head(&clients);
+        assert_eq!(verify_ledger(ledger.as_bytes(), Some(&head)).unwrap(),(3, head.clone()));
+
+        let changed = ledger.replacen("1,2,2.0", "1,2,20.0", 1);
+        assert!(matches!(verify_ledger(changed.as_bytes(), None), Err(ChainError::Tampered { row: 2 })));
+        let cut: String = ledger.lines().take(3).map(|l| format!("{}\n", l)).collect();
+        assert!(verify_ledger(cut.as_bytes(), None).is_ok());
+        assert!(matches!(verify_ledger(cut.as_bytes(), Some(&head)), Err(ChainError::Head { .. })));
+        assert!(matches!(verify_ledger("client,tx\n1,1\n".as_bytes(), None), Err(ChainError::MissingHash)));
+    }
+}
@@ -0,0 +1,176 @@
+use std::io;
+use crate::generate::Rng;
+use crate::{Engine, TxRecord};
+
+///
+/// How often each kind of fault is injected, as a share of deliveries
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChaosConfig
+{
+    /// Where the random numbers start, the same seed always injects the same faults
+    pub seed: u64,
+    /// Deliveries that fail with a storage error, about half of them after the record was applied
+    pub storage_error_rate: f64,
+    /// Deliveries held back and applied after some later ones
+    pub delay_rate: f64,
+    /// How many later deliveries a delayed record waits for at most
+    pub max_delay: u64,
+    /// Deliveries applied twice
+    pub duplicate_rate: f64,
+}
+impl Default for ChaosConfig
+{
+    fn default() -> Self {
+        ChaosConfig { seed: 1, storage_error_rate: 0.05, delay_rate: 0.05, max_delay: 8, duplicate_rate: 0.05 }
+    }
+}
+
+///
+/// A fault injected into a delivery
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault
+{
+    /// The delivery failed, and the record was applied anyway if 'applied'
+    StorageError { client: u16, tx: u32, applied: bool },
+    /// The record was applied after the given number of later deliveries
+    Delayed { client: u16, tx: u32, by: u64 },
+    /// The record was applied twice
+    Duplicated { client: u16, tx: u32 },
+}
+
+///
+/// Wraps an engine and injects recoverable faults into the records delivered to it,
+/// for checking that retries and idempotency hold up
+///
+/// Every fault is drawn from the seed, so a run can be repeated exactly, and the
+/// faults injected are kept in order to compare runs by
+///
+pub struct ChaosEngine
+{
+    engine: Engine,
+    config: ChaosConfig,
+    rng: Rng,
+    /// The records held back, with the delivery count they are due at
+    delayed: Vec<(u64, TxRecord)>,
+    /// How many deliveries have succeeded
+    delivered: u64,
+    /// Every fault injected so far, in order
+    pub faults: Vec<Fault>,
+}
+impl ChaosEngine
+{
+    /// Wraps the engine
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine the records end up in
+    /// * 'config' - Which faults to inject, and how often
+    pub fn new(engine: Engine, config: ChaosConfig) -> ChaosEngine
+    {
+        ChaosEngine { engine, rng: Rng(config.seed), config, delayed: Vec::new(), delivered: 0, faults: Vec::new() }
+    }
+    /// Delivers a record to the engine, unless a fault gets in the way
+    ///
+    /// # Arguments
+    ///
+    /// * 'record' - The transaction as read from the input
+    ///
+    /// # Errors
+    ///
+    /// Fails with ErrorKind::Interrupted on an injected storage error, which may
+    /// come after the record was applied. Delivering it again may succeed
+    pub fn deliver(&mut self, record: TxRecord) -> io::Result<()>
+    {
+        let (client, tx) = (record.client, record.tx);
+        let roll = self.rng.chance();
+        if roll < self.config.storage_error_rate
+        {
+            let applied = self.rng.chance() < 0.5;
+            if applied {self.engine.apply_record(record);}
+            self.faults.push(Fault::StorageError { client, tx, applied });
+            return Err(io::Error::new(io::ErrorKind::Interrupted, format!("injected storage error delivering tx {}", tx)));
+        }
+        self.delivered += 1;
+        if roll < self.config.storage_error_rate + self.config.delay_rate
+        {
+            let by = 1 + self.rng.below(self.config.max_delay);
+            self.delayed.push((self.delivered + by, record));
+            self.faults.push(Fault::Delayed { client, tx, by });
+        }
+        else if roll < self.config.storage_error_rate + self.config.delay_rate + self.config.duplicate_rate
+        {
+            self.engine.apply_record(record.clone());
+            self.engine.apply_record(record);
+            self.faults.push(Fault::Duplicated { client, tx });
+        }
+        else
+        {
+            self.engine.apply_record(record);
+        }
+        while let Some(i) = self.delayed.iter().position(|(due, _)| *due <= self.delivered)
+        {
+            let (_, record) = self.delayed.remove(i);
+            self.engine.apply_record(record);
+        }
+        Ok(())
+    }
+    /// The engine as it is now, without the records still held back
+    pub fn engine(&self) -> &Engine
+    {
+        &self.engine
+    }
+    /// Applies the records still held back and hands the engine back
+    pub fn finish(mut self) -> Engine
+    {
+        for (_, record) in self.delayed.drain(..)
+        {
+            self.engine.apply_record(record);
+        }
+        self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, TypeTx};
+
+    fn run(config: ChaosConfig, records: &[TxRecord]) -> (Engine, Vec<Fault>)
+    {
+        let mut chaos = ChaosEngine::new(Engine::new(EnginePolicy::default()), config);
+        for record in records
+        {
+            //retries up to ten times, as a caller with a retry policy would
+            let delivered = (0..10).any(|_| chaos.deliver(record.clone()).is_ok());
+            assert!(delivered);
+        }
+        let faults = chaos.faults.clone();
+        (chaos.finish(), faults)
+    }
+
+    #[test]
+    fn chaos_mode()
+    {
+        let records: Vec<TxRecord> = (1..=300).map(|tx| TxRecord::new(TypeTx::Deposit, (tx % 5) as u16 + 1, tx, Some("1.5".to_string()))).collect();
+        let config = ChaosConfig { seed: 3, storage_error_rate: 0.2, delay_rate: 0.1, max_delay: 5, duplicate_rate: 0.1 };
+        let (engine, faults) = run(config, &records);
+        let (_, again) = run(config, &records);
+        let (_, other) = run(ChaosConfig { seed: 4, ..config }, &records);
+        assert_eq!(faults,again);
+        assert_ne!(faults,other);
+        assert!(faults.iter().any(|f| matches!(f, Fault::StorageError { applied: true, .. })));
+        assert!(faults.iter().any(|f| matches!(f, Fault::StorageError { applied: false, .. })));
+        assert!(faults.iter().any(|f| matches!(f, Fault::Delayed { .. })) && faults.iter().any(|f| matches!(f, Fault::Duplicated { .. })));
+
+        //deposits are kept by ID, so retries and duplicates leave the same accounts as a clean run
+        let mut clean = Engine::new(EnginePolicy::default());
+        records.iter().cloned().for_each(|r| clean.apply_record(r));
+        for client in 1..=5
+        {
+            assert_eq!(engine.clients[&client].acc,clean.clients[&client].acc);
+        }
+        assert!(engine.rejections.is_empty());
+    }
+}
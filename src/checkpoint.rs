@@ -0,0 +1,355 @@
+use std::{fmt, fs, io, path::{Path, PathBuf}, sync::{Arc, Condvar, Mutex}, thread};
+use serde::{Deserialize, Serialize};
+use crate::snapshot::SnapshotState;
+use crate::{Engine, EnginePolicy, SnapshotError, SnapshotKey};
+
+/// The file in the checkpoint directory saying which snapshot goes with which offset
+const CHECKPOINT_FILE: &str = "checkpoint.json";
+
+///
+/// How far a batch run had got when its state was last persisted
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint
+{
+    /// The input file being processed
+    pub input: String,
+    /// The byte offset of the input just past the last row applied
+    pub offset: u64,
+    /// How many rows had been read from the input
+    pub rows: u64,
+}
+impl Checkpoint
+{
+    /// The name of the snapshot taken with this checkpoint
+    fn snapshot_file(&self) -> String
+    {
+        format!("state-{}.snapshot", self.offset)
+    }
+    /// Reads the latest checkpoint committed to the directory, None if there is none
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - The checkpoint directory
+    pub fn read(dir: &Path) -> Result<Option<Checkpoint>, CheckpointError>
+    {
+        match fs::read(dir.join(CHECKPOINT_FILE))
+        {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes).map_err(SnapshotError::from)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(SnapshotError::from(e).into())
+        }
+    }
+}
+
+///
+/// Something went wrong persisting or resuming from a checkpoint
+///
+#[derive(Debug)]
+pub enum CheckpointError
+{
+    Snapshot(SnapshotError),
+    /// The checkpoint was taken while processing another input
+    OtherInput { expected: String, found: String },
+}
+impl fmt::Display for CheckpointError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            CheckpointError::Snapshot(e) => write!(f, "{}", e),
+            CheckpointError::OtherInput { expected, found } => write!(f, "the checkpoint is of {}, not {}", expected, found),
+        }
+    }
+}
+impl From<SnapshotError> for CheckpointError
+{
+    fn from(e: SnapshotError) -> Self {
+        CheckpointError::Snapshot(e)
+    }
+}
+impl From<io::Error> for CheckpointError
+{
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Snapshot(SnapshotError::Io(e))
+    }
+}
+impl From<CheckpointError> for io::Error
+{
+    fn from(e: CheckpointError) -> Self {
+        match e
+        {
+            CheckpointError::Snapshot(e) => e.into(),
+            e => io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+        }
+    }
+}
+
+/// Writes a file under a temporary name and renames it into place, so it is
+/// either all there or not there at all
+pub(crate) fn write_atomic(path: &Path, write: impl FnOnce(&mut io::BufWriter<fs::File>) -> Result<(), SnapshotError>) -> Result<(), SnapshotError>
+{
+    let temp = path.with_extension("tmp");
+    let mut out = io::BufWriter::new(fs::File::create(&temp)?);
+    write(&mut out)?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+/// Writes the snapshot of the state, then commits the checkpoint and removes the
+/// snapshot of the one before
+fn commit(dir: &Path, state: &SnapshotState, checkpoint: &Checkpoint, key: Option<&SnapshotKey>) -> Result<(), CheckpointError>
+{
+    fs::create_dir_all(dir)?;
+    let previous = Checkpoint::read(dir).ok().flatten();
+    write_atomic(&dir.join(checkpoint.snapshot_file()), |out| state.write_to(out, key))?;
+    write_atomic(&dir.join(CHECKPOINT_FILE), |out| Ok(serde_json::to_writer(out, checkpoint)?))?;
+    if let Some(previous) = previous.filter(|p| p.offset != checkpoint.offset)
+    {
+        let _ = fs::remove_file(dir.join(previous.snapshot_file()));
+    }
+    Ok(())
+}
+
+///
+/// How the checkpoints handed to a background writer fared
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BackgroundStats
+{
+    /// The checkpoints committed
+    pub written: u64,
+    /// The checkpoints dropped for a newer one before the writer got to them
+    pub superseded: u64,
+    /// The checkpoints that failed to be written
+    pub failed: u64,
+}
+
+/// What the ingesting thread and the writer share
+#[derive(Default)]
+struct Shared
+{
+    /// The latest checkpoint waiting to be written
+    pending: Option<(SnapshotState, Checkpoint)>,
+    /// Set once no more checkpoints are coming
+    closed: bool,
+    /// The checkpoints that failed since last asked
+    errors: Vec<(Checkpoint, CheckpointError)>,
+    stats: BackgroundStats,
+}
+
+///
+/// Writes checkpoints on a thread of its own, so ingestion goes on while they are
+/// serialized, encrypted and synced to disk
+///
+/// Double buffered: the engine state is copied when a checkpoint is submitted, which
+/// is all the ingesting thread waits for, and one checkpoint is written while the next
+/// waits. A checkpoint submitted while one is still waiting replaces it, as only the
+/// latest is worth resuming from, so a slow disk means fewer checkpoints rather than
+/// a stalled run. Checkpoints are still committed in order, each only once its
+/// snapshot is complete
+///
+pub struct BackgroundCheckpoints
+{
+    shared: Arc<(Mutex<Shared>, Condvar)>,
+    writer: Option<thread::JoinHandle<()>>,
+}
+impl BackgroundCheckpoints
+{
+    /// Starts the writer thread
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - The checkpoint directory, created if it doesn't exist
+    /// * 'key' - The key snapshots are encrypted with, if any
+    pub fn start(dir: PathBuf, key: Option<SnapshotKey>) -> BackgroundCheckpoints
+    {
+        let shared = Arc::new((Mutex::new(Shared::default()), Condvar::new()));
+        let theirs = Arc::clone(&shared);
+        let writer = thread::spawn(move || {
+            let (lock, wake) = &*theirs;
+            loop
+            {
+                let (state, checkpoint) = {
+                    let mut shared = lock.lock().unwrap_or_else(|e| e.into_inner());
+                    loop
+                    {
+                        if let Some(next) = shared.pending.take() {break next}
+                        if shared.closed {return}
+                        shared = wake.wait(shared).unwrap_or_else(|e| e.into_inner());
+                    }
+                };
+                let result = commit(&dir, &state, &checkpoint, key.as_ref());
+                let mut shared = lock.lock().unwrap_or_else(|e| e.into_inner());
+                match result
+                {
+                    Ok(()) => shared.stats.written += 1,
+                    Err(e) => {
+                        shared.stats.failed += 1;
+                        shared.errors.push((checkpoint, e));
+                    }
+                }
+            }
+        });
+        BackgroundCheckpoints { shared, writer: Some(writer) }
+    }
+    /// Copies the state of the engine and hands it to the writer with the checkpoint
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine to persist
+    /// * 'checkpoint' - How far into the input the engine got
+    pub fn submit(&self, engine: &Engine, checkpoint: Checkpoint)
+    {
+        let state = engine.capture();
+        let (lock, wake) = &*self.shared;
+        let mut shared = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if shared.pending.replace((state, checkpoint)).is_some()
+        {
+            shared.stats.superseded += 1;
+        }
+        wake.notify_all();
+    }
+    /// Takes the checkpoints that failed to be written since last asked, with why
+    pub fn take_errors(&self) -> Vec<(Checkpoint, CheckpointError)>
+    {
+        let mut shared = self.shared.0.lock().unwrap_or_else(|e| e.into_inner());
+        std::mem::take(&mut shared.errors)
+    }
+    /// Tells the writer no more checkpoints are coming and waits for it to write the
+    /// last one
+    fn close(&mut self)
+    {
+        let (lock, wake) = &*self.shared;
+        lock.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        wake.notify_all();
+        if let Some(writer) = self.writer.take()
+        {
+            let _ = writer.join();
+        }
+    }
+    /// Waits for every checkpoint submitted to be written, then stops the writer
+    ///
+    /// Returns the stats, and the checkpoints that failed since last asked
+    pub fn finish(mut self) -> (BackgroundStats, Vec<(Checkpoint, CheckpointError)>)
+    {
+        self.close();
+        let mut shared = self.shared.0.lock().unwrap_or_else(|e| e.into_inner());
+        (shared.stats, std::mem::take(&mut shared.errors))
+    }
+}
+impl Drop for BackgroundCheckpoints
+{
+    /// Lets the writer finish what it was given, so a checkpoint isn't left half done
+    fn drop(&mut self)
+    {
+        self.close();
+    }
+}
+
+impl Engine
+{
+    /// Persists the state of the engine along with how far into the input it got
+    ///
+    /// The snapshot is written first under a name of its own, and the checkpoint is
+    /// only committed once it is complete, so a run stopped at any point resumes from
+    /// a snapshot and offset that belong together. Like snapshots, rejections and the
+    /// audit trail aren't kept
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - The checkpoint directory, created if it doesn't exist
+    /// * 'checkpoint' - How far into the input the engine got
+    /// * 'key' - The key the snapshot is encrypted with, if any
+    pub fn checkpoint(&self, dir: &Path, checkpoint: &Checkpoint, key: Option<&SnapshotKey>) -> Result<(), CheckpointError>
+    {
+        commit(dir, &self.capture(), checkpoint, key)
+    }
+    /// Returns the engine as of the latest checkpoint in the directory, and the
+    /// checkpoint itself, None if nothing has been committed yet
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - The checkpoint directory
+    /// * 'input' - The input file being processed, which has to be the one checkpointed
+    /// * 'policy' - The rules the engine follows from here on
+    /// * 'key' - The key the snapshot was encrypted with, if it was
+    pub fn resume(dir: &Path, input: &str, policy: EnginePolicy, key: Option<&SnapshotKey>) -> Result<Option<(Engine, Checkpoint)>, CheckpointError>
+    {
+        let checkpoint = match Checkpoint::read(dir)?
+        {
+            Some(checkpoint) => checkpoint,
+            None => return Ok(None)
+        };
+        if checkpoint.input != input
+        {
+            return Err(CheckpointError::OtherInput { expected: checkpoint.input, found: input.to_string() });
+        }
+        let engine = Engine::restore_from(fs::File::open(dir.join(checkpoint.snapshot_file()))?, policy, key)?;
+        Ok(Some((engine, checkpoint)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use crate::{Amount, Dialect, SchemaMode};
+
+    #[test]
+    fn resume_from_checkpoint()
+    {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\nwithdrawal,1,3,0.5\ndeposit,2,4,4.0\n";
+        let dir = std::env::temp_dir().join(format!("checkpoint_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert!(Engine::resume(&dir, "input.csv", EnginePolicy::default(), None).unwrap().is_none());
+
+        //stopped after two rows
+        let dialect = Dialect::default();
+        let mut engine = Engine::new(EnginePolicy::default());
+        for (rows, (offset, record)) in dialect.read_records_from(Cursor::new(input), 0, SchemaMode::Strict).unwrap().take(2).enumerate()
+        {
+            engine.apply_record(record);
+            engine.checkpoint(&dir, &Checkpoint { input: "input.csv".to_string(), offset, rows: rows as u64 + 1 }, None).unwrap();
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(),2);
+
+        let (mut engine, checkpoint) = Engine::resume(&dir, "input.csv", EnginePolicy::default(), None).unwrap().unwrap();
+        assert_eq!(checkpoint.rows,2);
+        for (_, record) in dialect.read_records_from(Cursor::new(input), checkpoint.offset, SchemaMode::Strict).unwrap()
+        {
+            engine.apply_record(record);
+        }
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(25000));
+        assert_eq!(engine.clients[&2].acc.total,Amount::from_minor(40000));
+        assert!(matches!(Engine::resume(&dir, "other.csv", EnginePolicy::default(), None), Err(CheckpointError::OtherInput { .. })));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn background_checkpoints_commit_the_latest()
+    {
+        let dir = std::env::temp_dir().join(format!("background_checkpoint_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let writer = BackgroundCheckpoints::start(dir.clone(), None);
+        let mut engine = Engine::new(EnginePolicy::default());
+        for (rows, (offset, record)) in Dialect::default().read_records_from(Cursor::new("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n"), 0, SchemaMode::Strict).unwrap().enumerate()
+        {
+            engine.apply_record(record);
+            writer.submit(&engine, Checkpoint { input: "input.csv".to_string(), offset, rows: rows as u64 + 1 });
+        }
+        //rows applied after a submit don't end up in its snapshot
+        engine.apply_record(crate::TxRecord::new(crate::TypeTx::Deposit, 1, 3, Some("4.0".to_string())));
+        let (stats, errors) = writer.finish();
+        assert!(errors.is_empty());
+        assert_eq!(stats.written + stats.superseded,2);
+        assert!(stats.written >= 1);
+
+        let (engine, checkpoint) = Engine::resume(&dir, "input.csv", EnginePolicy::default(), None).unwrap().unwrap();
+        assert_eq!(checkpoint.rows,2);
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(30000));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(),2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
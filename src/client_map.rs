@@ -0,0 +1,280 @@
+use std::{collections::{HashMap, HashSet}, hash::{BuildHasherDefault, Hasher}, iter::FromIterator, ops::Index};
+use crate::Client;
+
+/// Spreads the bits of a client ID over the whole hash, the golden ratio in 64 bits
+const SPREAD: u64 = 0x9E37_79B9_7F4A_7C15;
+
+///
+/// Hashes client IDs with a single multiplication rather than SipHash
+///
+/// Client IDs come from the input rather than an attacker able to pick colliding
+/// ones, and as they are u16 there are only so many, so hashing them doesn't need
+/// to resist flooding. Every row looks its client up several times, and this makes
+/// each lookup about as cheap as indexing
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClientHasher
+{
+    hash: u64,
+}
+impl Hasher for ClientHasher
+{
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes
+        {
+            self.hash = (self.hash.rotate_left(8) ^ *b as u64).wrapping_mul(SPREAD);
+        }
+    }
+    fn write_u16(&mut self, id: u16) {
+        self.hash = (self.hash ^ id as u64).wrapping_mul(SPREAD);
+    }
+}
+
+/// A map keyed by client ID, hashed with ClientHasher
+pub type ClientMap<V> = HashMap<u16, V, BuildHasherDefault<ClientHasher>>;
+/// A set of client IDs, hashed with ClientHasher
+pub type ClientSet = HashSet<u16, BuildHasherDefault<ClientHasher>>;
+
+/// How many client IDs there are, and so slots in a dense store
+const DENSE_SLOTS: usize = u16::MAX as usize + 1;
+
+///
+/// Where the engine keeps its clients, keyed by client ID
+///
+/// Hashed by default, which takes memory for the clients seen only. A dense store
+/// holds a slot for every possible client ID up front, so finding a client is an
+/// index into it without hashing, at the cost of the whole table even for a few
+/// clients. Dense stores go over their clients in client ID order
+///
+#[derive(Debug, Clone)]
+pub enum ClientStore
+{
+    Hashed(ClientMap<Client>),
+    Dense {
+        /// One slot for every client ID, indexed by it
+        slots: Vec<Option<Client>>,
+        /// How many of the slots hold a client
+        len: usize,
+    },
+}
+impl Default for ClientStore
+{
+    fn default() -> Self {
+        ClientStore::Hashed(ClientMap::default())
+    }
+}
+impl ClientStore
+{
+    /// Returns an empty dense store, with the slot table allocated
+    pub fn dense() -> ClientStore
+    {
+        ClientStore::Dense { slots: (0..DENSE_SLOTS).map(|_| None).collect(), len: 0 }
+    }
+    /// Moves the clients into a dense store, if they aren't in one already
+    pub fn make_dense(&mut self)
+    {
+        if let ClientStore::Hashed(map) = self
+        {
+            let mut dense = ClientStore::dense();
+            for (id, c) in map.drain()
+            {
+                dense.insert(id, c);
+            }
+            *self = dense;
+        }
+    }
+    /// Whether every client ID has a slot of its own
+    pub fn is_dense(&self) -> bool
+    {
+        matches!(self, ClientStore::Dense { .. })
+    }
+    pub fn len(&self) -> usize
+    {
+        match self
+        {
+            ClientStore::Hashed(map) => map.len(),
+            ClientStore::Dense { len, .. } => *len
+        }
+    }
+    pub fn is_empty(&self) -> bool
+    {
+        self.len() == 0
+    }
+    pub fn get(&self, id: &u16) -> Option<&Client>
+    {
+        match self
+        {
+            ClientStore::Hashed(map) => map.get(id),
+            ClientStore::Dense { slots, .. } => slots[*id as usize].as_ref()
+        }
+    }
+    pub fn get_mut(&mut self, id: &u16) -> Option<&mut Client>
+    {
+        match self
+        {
+            ClientStore::Hashed(map) => map.get_mut(id),
+            ClientStore::Dense { slots, .. } => slots[*id as usize].as_mut()
+        }
+    }
+    pub fn contains_key(&self, id: &u16) -> bool
+    {
+        self.get(id).is_some()
+    }
+    /// Puts the client under the ID, returning the client it replaced
+    pub fn insert(&mut self, id: u16, client: Client) -> Option<Client>
+    {
+        match self
+        {
+            ClientStore::Hashed(map) => map.insert(id, client),
+            ClientStore::Dense { slots, len } => {
+                let replaced = slots[id as usize].replace(client);
+                if replaced.is_none() {*len += 1}
+                replaced
+            }
+        }
+    }
+    pub fn remove(&mut self, id: &u16) -> Option<Client>
+    {
+        match self
+        {
+            ClientStore::Hashed(map) => map.remove(id),
+            ClientStore::Dense { slots, len } => {
+                let removed = slots[*id as usize].take();
+                if removed.is_some() {*len -= 1}
+                removed
+            }
+        }
+    }
+    /// Returns the client under the ID, putting the one made by the function there
+    /// first if there is none
+    ///
+    /// # Arguments
+    ///
+    /// * 'id' - The client ID
+    /// * 'new' - Makes the client if it's new
+    pub fn get_or_insert_with<F: FnOnce() -> Client>(&mut self, id: u16, new: F) -> &mut Client
+    {
+        match self
+        {
+            ClientStore::Hashed(map) => map.entry(id).or_insert_with(new),
+            ClientStore::Dense { slots, len } => {
+                let slot = &mut slots[id as usize];
+                if slot.is_none() {*len += 1}
+                slot.get_or_insert_with(new)
+            }
+        }
+    }
+    /// The client IDs, in no particular order unless the store is dense
+    pub fn keys(&self) -> impl Iterator<Item = u16> + '_
+    {
+        self.iter().map(|(id, _)| id)
+    }
+    pub fn values(&self) -> impl Iterator<Item = &Client>
+    {
+        self.iter().map(|(_, c)| c)
+    }
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut Client>
+    {
+        let (hashed, dense) = match self
+        {
+            ClientStore::Hashed(map) => (Some(map.values_mut()), None),
+            ClientStore::Dense { slots, .. } => (None, Some(slots.iter_mut().flatten()))
+        };
+        hashed.into_iter().flatten().chain(dense.into_iter().flatten())
+    }
+    /// The clients with their IDs, in no particular order unless the store is dense
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Client)>
+    {
+        let (hashed, dense) = match self
+        {
+            ClientStore::Hashed(map) => (Some(map.iter().map(|(id, c)| (*id, c))), None),
+            ClientStore::Dense { slots, .. } => (None, Some(slots.iter().enumerate().filter_map(|(id, c)| c.as_ref().map(|c| (id as u16, c)))))
+        };
+        hashed.into_iter().flatten().chain(dense.into_iter().flatten())
+    }
+    /// The clients with their IDs in client ID order, from the first after the cursor on
+    ///
+    /// A dense store is walked from the cursor, a hashed one sorts the IDs after it,
+    /// neither copies any client
+    ///
+    /// # Arguments
+    ///
+    /// * 'after' - The ID the previous page ended at, None to start at the first client
+    pub fn iter_ordered(&self, after: Option<u16>) -> impl Iterator<Item = (u16, &Client)>
+    {
+        let start = after.map_or(0, |id| id as usize + 1);
+        let (hashed, dense) = match self
+        {
+            ClientStore::Hashed(map) => {
+                let mut ids: Vec<u16> = map.keys().copied().filter(|id| *id as usize >= start).collect();
+                ids.sort_unstable();
+                (Some(ids.into_iter().map(move |id| (id, &map[&id]))), None)
+            },
+            ClientStore::Dense { slots, .. } => (None, Some(slots.iter().enumerate().skip(start).filter_map(|(id, c)| c.as_ref().map(|c| (id as u16, c)))))
+        };
+        hashed.into_iter().flatten().chain(dense.into_iter().flatten())
+    }
+}
+impl Index<&u16> for ClientStore
+{
+    type Output = Client;
+    /// Returns the client under the ID, panicking if there is none
+    fn index(&self, id: &u16) -> &Client {
+        self.get(id).expect("no client under the ID")
+    }
+}
+impl FromIterator<(u16, Client)> for ClientStore
+{
+    fn from_iter<I: IntoIterator<Item = (u16, Client)>>(iter: I) -> Self {
+        ClientStore::Hashed(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::hash::BuildHasher;
+
+    #[test]
+    fn client_hashes()
+    {
+        let build = BuildHasherDefault::<ClientHasher>::default();
+        let hash = |id: u16| build.hash_one(id);
+        //neighbouring IDs land far apart, in the top bits hashbrown tags buckets with as well
+        let tags: HashSet<u64> = (0..128).map(|id| hash(id) >> 57).collect();
+        assert!(tags.len() > 64);
+        let hashes: HashSet<u64> = (0..=u16::MAX).map(hash).collect();
+        assert_eq!(hashes.len(),65536);
+
+        let mut clients: ClientMap<&str> = ClientMap::default();
+        clients.insert(7, "seven");
+        assert_eq!(clients.get(&7),Some(&"seven"));
+    }
+    #[test]
+    fn dense_store()
+    {
+        let mut hashed: ClientStore = [9, 2, 65535].iter().map(|id| (*id, Client::new(*id))).collect();
+        let mut dense = hashed.clone();
+        dense.make_dense();
+        assert!(dense.is_dense() && !hashed.is_dense());
+        assert_eq!(dense.keys().collect::<Vec<_>>(),vec![2, 9, 65535]);
+        for store in [&mut hashed, &mut dense]
+        {
+            assert_eq!(store.len(),3);
+            store.get_or_insert_with(4, || Client::new(4)).frozen = true;
+            store.get_or_insert_with(4, || Client::new(5));
+            assert!(store.get(&4).is_some_and(|c| c.frozen && c.acc.client == 4));
+            assert_eq!(store.remove(&9).map(|c| c.acc.client),Some(9));
+            assert!(store.remove(&9).is_none() && !store.contains_key(&9));
+            assert!(store.insert(2, Client::new(2)).is_some());
+            let mut ids: Vec<u16> = store.iter().map(|(id, c)| {assert_eq!(id,c.acc.client); id}).collect();
+            ids.sort();
+            assert_eq!((ids, store.len()),(vec![2, 4, 65535], 3));
+            assert_eq!(store.iter_ordered(Some(2)).map(|(id, _)| id).collect::<Vec<_>>(),vec![4, 65535]);
+            assert!(store.iter_ordered(Some(65535)).next().is_none());
+        }
+    }
+}
@@ -0,0 +1,547 @@
+use std::{convert::TryFrom, fmt, io, sync::Arc};
+#[cfg(feature = "parquet")]
+use std::fs::File;
+use arrow_array::{Array, ArrayRef, BooleanArray, Decimal128Array, RecordBatch, StringArray, UInt16Array, UInt32Array};
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef};
+#[cfg(feature = "parquet")]
+use parquet::{arrow::{ArrowWriter, arrow_reader::ParquetRecordBatchReaderBuilder}, errors::ParquetError};
+use crate::{Account, Amount, Dialect, Engine, EnginePolicy, HeaderError, SchemaMode, TxRecord, TypeTx, AMOUNT_PRECISION};
+#[cfg(feature = "parquet")]
+use crate::{AccountSink, ClientStore};
+
+/// The type, client, tx and amount columns of transactions, as Engine::apply_columns takes them
+pub type TxColumns = (Vec<TypeTx>, Vec<u16>, Vec<u32>, Vec<Option<Amount>>);
+
+/// How many rows go into each record batch, both when reading and writing
+pub const BATCH_SIZE: usize = 8192;
+/// Enough digits for any amount, as an amount is an i64 of minor units
+const AMOUNT_DIGITS: u8 = 19;
+
+///
+/// Something went wrong reading or writing columnar data
+///
+#[derive(Debug)]
+pub enum ColumnarError
+{
+    Arrow(ArrowError),
+    #[cfg(feature = "parquet")]
+    Parquet(ParquetError),
+    Header(HeaderError),
+}
+impl fmt::Display for ColumnarError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            ColumnarError::Arrow(e) => write!(f, "{}", e),
+            #[cfg(feature = "parquet")]
+            ColumnarError::Parquet(e) => write!(f, "{}", e),
+            ColumnarError::Header(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl From<ArrowError> for ColumnarError
+{
+    fn from(e: ArrowError) -> Self {
+        ColumnarError::Arrow(e)
+    }
+}
+#[cfg(feature = "parquet")]
+impl From<ParquetError> for ColumnarError
+{
+    fn from(e: ParquetError) -> Self {
+        ColumnarError::Parquet(e)
+    }
+}
+impl From<HeaderError> for ColumnarError
+{
+    fn from(e: HeaderError) -> Self {
+        ColumnarError::Header(e)
+    }
+}
+impl From<ColumnarError> for io::Error
+{
+    fn from(e: ColumnarError) -> Self {
+        io::Error::other(e.to_string())
+    }
+}
+
+/// The arrow type amounts are stored as, a decimal with four places
+pub fn amount_type() -> DataType
+{
+    DataType::Decimal128(AMOUNT_DIGITS, AMOUNT_PRECISION as i8)
+}
+/// Builds a decimal column from amounts
+fn amount_array(amounts: impl Iterator<Item = Amount>) -> Result<ArrayRef, ArrowError>
+{
+    let array = Decimal128Array::from_iter_values(amounts.map(|a| a.minor() as i128))
+        .with_precision_and_scale(AMOUNT_DIGITS, AMOUNT_PRECISION as i8)?;
+    Ok(Arc::new(array))
+}
+
+/// The schema of the account report
+pub fn account_schema() -> SchemaRef
+{
+    Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("available", amount_type(), false),
+        Field::new("held", amount_type(), false),
+        Field::new("total", amount_type(), false),
+        Field::new("locked", DataType::Boolean, false),
+        Field::new("pending", amount_type(), false),
+    ]))
+}
+/// Puts accounts into a record batch following the account schema
+///
+/// # Arguments
+///
+/// * 'accounts' - The accounts, one row each
+pub fn accounts_to_batch(accounts: &[&Account]) -> Result<RecordBatch, ArrowError>
+{
+    RecordBatch::try_new(account_schema(), vec![
+        Arc::new(UInt16Array::from_iter_values(accounts.iter().map(|a| a.client))),
+        amount_array(accounts.iter().map(|a| a.available))?,
+        amount_array(accounts.iter().map(|a| a.held))?,
+        amount_array(accounts.iter().map(|a| a.total))?,
+        Arc::new(BooleanArray::from(accounts.iter().map(|a| a.locked).collect::<Vec<_>>())),
+        amount_array(accounts.iter().map(|a| a.pending))?,
+    ])
+}
+
+/// The schema of the ledger export
+pub fn ledger_schema() -> SchemaRef
+{
+    Arc::new(Schema::new(vec![
+        Field::new("client", DataType::UInt16, false),
+        Field::new("tx", DataType::UInt32, false),
+        Field::new("amount", amount_type(), false),
+        Field::new("in_dispute", DataType::Boolean, false),
+        Field::new("dispute_chain", DataType::Utf8, false),
+        Field::new("memo", DataType::Utf8, true),
+    ]))
+}
+
+/// Casts a column to the given type, or returns None if it isn't there
+fn column(batch: &RecordBatch, names: &csv::StringRecord, name: &str, to: &DataType) -> Result<Option<ArrayRef>, ArrowError>
+{
+    match names.iter().position(|n| n == name)
+    {
+        Some(i) => Ok(Some(arrow_cast::cast(batch.column(i), to)?)),
+        None => Ok(None)
+    }
+}
+/// Reads the text of a row from an optional string column
+fn text(array: &Option<ArrayRef>, row: usize) -> Option<String>
+{
+    let strings = array.as_ref()?.as_any().downcast_ref::<StringArray>()?;
+    if strings.is_null(row) {None} else {Some(strings.value(row).to_string())}
+}
+
+/// Turns a record batch into transaction records, going column by column
+///
+/// The columns are cast to what we expect, so E.G. an Int64 client column or a
+/// Float64 amount column is fine. Rows with a client, tx or type that can't be
+/// read are skipped, the same as a malformed csv row
+///
+/// # Arguments
+///
+/// * 'batch' - The transactions, one row each
+/// * 'names' - The column names of the batch, after renaming
+pub fn batch_to_records(batch: &RecordBatch, names: &csv::StringRecord) -> Result<Vec<TxRecord>, ArrowError>
+{
+    let types = column(batch, names, "type", &DataType::Utf8)?;
+    let clients = column(batch, names, "client", &DataType::UInt16)?;
+    let txs = column(batch, names, "tx", &DataType::UInt32)?;
+    let amounts = column(batch, names, "amount", &DataType::Utf8)?;
+    let timestamps = column(batch, names, "timestamp", &DataType::Utf8)?;
+    let currencies = column(batch, names, "currency", &DataType::Utf8)?;
+    let memos = column(batch, names, "memo", &DataType::Utf8)?;
+    let counterparties = column(batch, names, "counterparty", &DataType::Utf8)?;
+    let tenants = column(batch, names, "tenant", &DataType::Utf8)?;
+    let signatures = column(batch, names, "signature", &DataType::Utf8)?;
+    let clients = clients.as_ref().and_then(|c| c.as_any().downcast_ref::<UInt16Array>());
+    let txs = txs.as_ref().and_then(|t| t.as_any().downcast_ref::<UInt32Array>());
+    let (clients, txs) = match (clients, txs)
+    {
+        (Some(c), Some(t)) => (c, t),
+        _ => return Ok(Vec::new())
+    };
+
+    let mut records = Vec::with_capacity(batch.num_rows());
+    for row in 0..batch.num_rows()
+    {
+        let r#type = match text(&types, row).and_then(|t| t.parse().ok())
+        {
+            Some(t) => t,
+            None => continue
+        };
+        if clients.is_null(row) || txs.is_null(row) {continue}
+        let mut record = TxRecord::new(r#type, clients.value(row), txs.value(row), text(&amounts, row));
+        record.timestamp = text(&timestamps, row);
+        record.currency = text(&currencies, row);
+        record.memo = text(&memos, row);
+        record.counterparty = text(&counterparties, row);
+        record.tenant = text(&tenants, row);
+        record.signature = text(&signatures, row);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Turns a record batch holding only the type, client, tx and amount columns into
+/// one column each, for Engine::apply_columns
+///
+/// Returns None if there are other columns, or the amounts aren't decimals that
+/// convert to amounts exactly, as those rows need the record path. Rows with a client,
+/// tx or type that can't be read are skipped, the same as batch_to_records does
+///
+/// # Arguments
+///
+/// * 'batch' - The transactions, one row each
+/// * 'names' - The column names of the batch, after renaming
+pub fn batch_to_columns(batch: &RecordBatch, names: &csv::StringRecord) -> Result<Option<TxColumns>, ArrowError>
+{
+    if names.iter().any(|n| !matches!(n, "type" | "client" | "tx" | "amount")) {return Ok(None)}
+    let decimals = match names.iter().position(|n| n == "amount")
+    {
+        Some(i) => match batch.column(i).data_type()
+        {
+            DataType::Decimal128(_, scale) if (0..=AMOUNT_PRECISION as i8).contains(scale) => {
+                let shift = 10i128.pow((AMOUNT_PRECISION as i8 - scale) as u32);
+                batch.column(i).as_any().downcast_ref::<Decimal128Array>().map(|d| (d, shift))
+            },
+            _ => return Ok(None)
+        },
+        None => None
+    };
+    let types = column(batch, names, "type", &DataType::Utf8)?;
+    let clients = column(batch, names, "client", &DataType::UInt16)?;
+    let txs = column(batch, names, "tx", &DataType::UInt32)?;
+    let clients = clients.as_ref().and_then(|c| c.as_any().downcast_ref::<UInt16Array>());
+    let txs = txs.as_ref().and_then(|t| t.as_any().downcast_ref::<UInt32Array>());
+    let (clients, txs) = match (clients, txs)
+    {
+        (Some(c), Some(t)) => (c, t),
+        _ => return Ok(Some(Default::default()))
+    };
+
+    let mut columns: TxColumns = Default::default();
+    for row in 0..batch.num_rows()
+    {
+        let r#type = match text(&types, row).and_then(|t| t.parse().ok())
+        {
+            Some(t) => t,
+            None => continue
+        };
+        if clients.is_null(row) || txs.is_null(row) {continue}
+        let amount = match decimals
+        {
+            Some((d, shift)) if !d.is_null(row) => match d.value(row).checked_mul(shift).and_then(|m| i64::try_from(m).ok())
+            {
+                Some(minor) => Some(Amount::from_minor(minor)),
+                None => return Ok(None)
+            },
+            _ => None
+        };
+        columns.0.push(r#type);
+        columns.1.push(clients.value(row));
+        columns.2.push(txs.value(row));
+        columns.3.push(amount);
+    }
+    Ok(Some(columns))
+}
+
+/// Turns a record batch into transaction records, checking its column names
+/// the same way as csv headers
+///
+/// # Arguments
+///
+/// * 'batch' - The transactions, one row each
+/// * 'dialect' - Holds the column renames
+/// * 'schema' - Whether columns we don't know about are an error
+pub fn read_batch_records(batch: &RecordBatch, dialect: &Dialect, schema: SchemaMode) -> Result<Vec<TxRecord>, ColumnarError>
+{
+    let headers: csv::StringRecord = batch.schema().fields().iter().map(|f| f.name().as_str()).collect();
+    let names = dialect.map_headers(&headers, schema)?;
+    Ok(batch_to_records(batch, &names)?)
+}
+
+impl Engine
+{
+    /// Returns every account as a record batch following the account schema,
+    /// ordered by client ID
+    pub fn accounts_to_record_batch(&self) -> Result<RecordBatch, ArrowError>
+    {
+        let mut accounts: Vec<&Account> = self.clients.values().map(|c| &c.acc).collect();
+        accounts.sort_by_key(|a| a.client);
+        accounts_to_batch(&accounts)
+    }
+    /// Returns a new engine with every transaction in the record batch applied,
+    /// in row order
+    ///
+    /// # Arguments
+    ///
+    /// * 'policy' - The rules used when validating transactions
+    /// * 'batch' - The transactions, with the same columns as the csv input
+    pub fn from_record_batch(policy: EnginePolicy, batch: &RecordBatch) -> Result<Engine, ColumnarError>
+    {
+        let mut engine = Engine::new(policy);
+        engine.apply_record_batch(batch)?;
+        Ok(engine)
+    }
+    /// Applies every transaction in the record batch, in row order
+    ///
+    /// Rows are validated the same way as csv rows, and a batch whose columns
+    /// don't match the input columns is refused as a whole
+    ///
+    /// # Arguments
+    ///
+    /// * 'batch' - The transactions, with the same columns as the csv input
+    pub fn apply_record_batch(&mut self, batch: &RecordBatch) -> Result<(), ColumnarError>
+    {
+        let headers: csv::StringRecord = batch.schema().fields().iter().map(|f| f.name().as_str()).collect();
+        let names = Dialect::default().map_headers(&headers, self.policy.schema)?;
+        if let Some((types, clients, txs, amounts)) = batch_to_columns(batch, &names)?
+        {
+            //the columns are checked to be the same length as they are built
+            let _ = self.apply_columns(&types, &clients, &txs, &amounts);
+            return Ok(());
+        }
+        for record in batch_to_records(batch, &names)?
+        {
+            self.apply_record(record);
+        }
+        Ok(())
+    }
+}
+
+/// Reads every transaction record from a parquet file, a batch at a time
+///
+/// Columns are renamed and checked the same way as csv headers
+///
+/// # Arguments
+///
+/// * 'file' - The parquet file
+/// * 'dialect' - Holds the column renames
+/// * 'schema' - Whether columns we don't know about are an error
+#[cfg(feature = "parquet")]
+pub fn read_parquet_records(file: File, dialect: &Dialect, schema: SchemaMode) -> Result<impl Iterator<Item = TxRecord>, ColumnarError>
+{
+    let builder = ParquetRecordBatchReaderBuilder::try_new(file)?.with_batch_size(BATCH_SIZE);
+    let headers: csv::StringRecord = builder.schema().fields().iter().map(|f| f.name().as_str()).collect();
+    let names = dialect.map_headers(&headers, schema)?;
+    let reader = builder.build()?;
+    Ok(reader
+        .filter_map(Result::ok)
+        .flat_map(move |batch| batch_to_records(&batch, &names).unwrap_or_default()))
+}
+
+///
+/// Writes accounts to a parquet file, a batch at a time
+///
+#[cfg(feature = "parquet")]
+pub struct ParquetSink<W: io::Write + Send>
+{
+    writer: Option<ArrowWriter<W>>,
+    pending: Vec<Account>,
+}
+#[cfg(feature = "parquet")]
+impl<W: io::Write + Send> ParquetSink<W>
+{
+    pub fn new(out: W) -> Result<ParquetSink<W>, ColumnarError>
+    {
+        Ok(ParquetSink { writer: Some(ArrowWriter::try_new(out, account_schema(), None)?), pending: Vec::new() })
+    }
+    /// Writes the buffered accounts out as one batch
+    fn flush_batch(&mut self) -> Result<(), ColumnarError>
+    {
+        if self.pending.is_empty() {return Ok(())}
+        let batch = accounts_to_batch(&self.pending.iter().collect::<Vec<_>>())?;
+        if let Some(writer) = self.writer.as_mut()
+        {
+            writer.write(&batch)?;
+        }
+        self.pending.clear();
+        Ok(())
+    }
+}
+#[cfg(feature = "parquet")]
+impl<W: io::Write + Send> AccountSink for ParquetSink<W>
+{
+    fn write_account(&mut self, acc: &Account) -> io::Result<()>
+    {
+        self.pending.push(acc.clone());
+        if self.pending.len() >= BATCH_SIZE
+        {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> io::Result<()>
+    {
+        self.flush_batch()?;
+        if let Some(writer) = self.writer.take()
+        {
+            writer.close().map_err(ColumnarError::from)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the transaction history of every client as parquet, ordered by
+/// client and then transaction ID
+///
+/// # Arguments
+///
+/// * 'clients' - The clients that have been processed
+/// * 'out' - Where to write the ledger to
+#[cfg(feature = "parquet")]
+pub fn write_ledger_parquet<W: io::Write + Send>(clients: &ClientStore, out: W) -> Result<(), ColumnarError>
+{
+    let mut rows = Vec::new();
+    for (id, client) in clients.iter()
+    {
+        for (tx, entry) in &client.history
+        {
+            rows.push((id, *tx, entry));
+        }
+    }
+    rows.sort_by_key(|(id, tx, _)| (*id, *tx));
+
+    let mut writer = ArrowWriter::try_new(out, ledger_schema(), None)?;
+    for chunk in rows.chunks(BATCH_SIZE)
+    {
+        let batch = RecordBatch::try_new(ledger_schema(), vec![
+            Arc::new(UInt16Array::from_iter_values(chunk.iter().map(|r| r.0))),
+            Arc::new(UInt32Array::from_iter_values(chunk.iter().map(|r| r.1))),
+            amount_array(chunk.iter().map(|r| r.2.amount))?,
+            Arc::new(BooleanArray::from(chunk.iter().map(|r| r.2.in_dispute).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(chunk.iter().map(|r| r.2.dispute_chain_text()).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(chunk.iter().map(|r| r.2.memo.as_deref()).collect::<Vec<_>>())),
+        ])?;
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Float64Array, Int64Array};
+    use crate::TypeTx;
+
+    #[test]
+    fn batch_records()
+    {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::Int64, false),
+            Field::new("tx", DataType::Int64, false),
+            Field::new("amount", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(schema, vec![
+            Arc::new(StringArray::from(vec!["deposit", "bogus", "dispute", "deposit"])),
+            Arc::new(Int64Array::from(vec![1, 1, 1, 70000])),
+            Arc::new(Int64Array::from(vec![1, 2, 1, 3])),
+            Arc::new(Float64Array::from(vec![Some(1.5), Some(1.0), None, Some(1.0)])),
+        ]).unwrap();
+        let names: csv::StringRecord = vec!["type", "client", "tx", "amount"].into_iter().collect();
+        let records = batch_to_records(&batch, &names).unwrap();
+        assert_eq!(records.len(),2);
+        assert_eq!(records[0].amount.as_deref(),Some("1.5"));
+        assert_eq!(records[1].r#type,TypeTx::Dispute);
+        assert!(records[1].amount.is_none());
+        //a float amount may not convert exactly, so only decimals go as columns
+        assert!(batch_to_columns(&batch, &names).unwrap().is_none());
+
+        let amounts = Decimal128Array::from(vec![Some(15), Some(10), None, Some(10)]).with_precision_and_scale(10, 1).unwrap();
+        let batch = RecordBatch::try_new(Arc::new(Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::Int64, false),
+            Field::new("tx", DataType::Int64, false),
+            Field::new("amount", DataType::Decimal128(10, 1), true),
+        ])), vec![batch.column(0).clone(), batch.column(1).clone(), batch.column(2).clone(), Arc::new(amounts)]).unwrap();
+        let (types, clients, txs, amounts) = batch_to_columns(&batch, &names).unwrap().unwrap();
+        assert_eq!((types, clients, txs),(vec![TypeTx::Deposit, TypeTx::Dispute], vec![1, 1], vec![1, 1]));
+        assert_eq!(amounts,vec![Some(Amount::from_minor(15000)), None]);
+    }
+    #[test]
+    fn engine_record_batches()
+    {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::UInt16, false),
+            Field::new("tx", DataType::UInt32, false),
+            Field::new("amount", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(schema, vec![
+            Arc::new(StringArray::from(vec!["deposit", "deposit", "withdrawal", "deposit"])),
+            Arc::new(UInt16Array::from(vec![2, 1, 1, 1])),
+            Arc::new(UInt32Array::from(vec![1, 2, 3, 4])),
+            Arc::new(StringArray::from(vec![Some("1.5"), Some("2.0"), Some("0.5"), Some("0.00001")])),
+        ]).unwrap();
+        let engine = Engine::from_record_batch(EnginePolicy::default(), &batch).unwrap();
+        assert_eq!(engine.rejections.len(),1);
+        let accounts = engine.accounts_to_record_batch().unwrap();
+        assert_eq!(accounts.schema(),account_schema());
+        let clients = accounts.column(0).as_any().downcast_ref::<UInt16Array>().unwrap();
+        assert_eq!(clients.values().to_vec(),vec![1, 2]);
+        let available = accounts.column(1).as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(available.value_as_string(0),"1.5000");
+    }
+    #[test]
+    fn record_batch_bad_columns()
+    {
+        let schema = Arc::new(Schema::new(vec![Field::new("type", DataType::Utf8, false)]));
+        let batch = RecordBatch::try_new(schema, vec![Arc::new(StringArray::from(vec!["deposit"]))]).unwrap();
+        assert!(matches!(Engine::from_record_batch(EnginePolicy::default(), &batch), Err(ColumnarError::Header(_))));
+    }
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn parquet_round_trip()
+    {
+        let mut acc = Account::new(4);
+        acc.available = Amount::from_minor(12345);
+        acc.total = Amount::from_minor(12345);
+        let path = std::env::temp_dir().join("csv_transactions_parquet_round_trip.parquet");
+        let mut sink = ParquetSink::new(File::create(&path).unwrap()).unwrap();
+        sink.write_account(&acc).unwrap();
+        sink.finish().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap().build().unwrap();
+        let batches: Vec<RecordBatch> = reader.map(Result::unwrap).collect();
+        let available = batches[0].column(1).as_any().downcast_ref::<Decimal128Array>().unwrap();
+        assert_eq!(available.value_as_string(0),"1.2345");
+        std::fs::remove_file(path).unwrap();
+    }
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn read_parquet_transactions()
+    {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::UInt16, false),
+            Field::new("transaction_id", DataType::UInt32, false),
+            Field::new("amount", amount_type(), true),
+        ]));
+        let batch = RecordBatch::try_new(schema.clone(), vec![
+            Arc::new(StringArray::from(vec!["deposit"])),
+            Arc::new(UInt16Array::from(vec![2])),
+            Arc::new(UInt32Array::from(vec![8])),
+            amount_array(std::iter::once(Amount::from_minor(25000))).unwrap(),
+        ]).unwrap();
+        let path = std::env::temp_dir().join("csv_transactions_read_parquet_transactions.parquet");
+        let mut writer = ArrowWriter::try_new(File::create(&path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let mut dialect = Dialect::default();
+        assert!(read_parquet_records(File::open(&path).unwrap(), &dialect, SchemaMode::Strict).is_err());
+        dialect.renames.insert("transaction_id".to_string(), "tx".to_string());
+        let records: Vec<TxRecord> = read_parquet_records(File::open(&path).unwrap(), &dialect, SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records[0].tx,8);
+        assert_eq!(records[0].amount.as_deref(),Some("2.5000"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
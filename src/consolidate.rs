@@ -0,0 +1,293 @@
+use std::{collections::BTreeMap, convert::TryFrom, fmt, io, str::FromStr};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use crate::{Amount, Engine};
+
+/// The number of decimal places a rate carries, more than amounts as rates between
+/// currencies of very different worth need them, E.G. 0.006689 yen to the euro
+pub const RATE_PRECISION: usize = 10;
+/// How many units of the last decimal go into a whole rate
+const RATE_SCALE: i64 = 10_000_000_000;
+
+///
+/// A rate between two currencies, stored as a whole number of the units of its tenth decimal
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate(i64);
+impl Rate
+{
+    /// The rate of a currency to itself
+    pub const ONE: Rate = Rate(RATE_SCALE);
+
+    /// Converts an amount at the rate, dropping anything past four decimals, None if the
+    /// result can't be represented
+    ///
+    /// # Arguments
+    ///
+    /// * 'amount' - The amount in the currency converted from
+    pub fn convert(&self, amount: Amount) -> Option<Amount>
+    {
+        let minor = i128::from(amount.minor()) * i128::from(self.0) / i128::from(RATE_SCALE);
+        i64::try_from(minor).ok().map(Amount::from_minor)
+    }
+}
+impl FromStr for Rate
+{
+    type Err = String;
+    /// Reads a plain decimal that isn't negative, E.G. "0.006689", dropping anything past
+    /// ten decimals
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid rate '{}'", s);
+        let (int_part, frac) = s.split_once('.').unwrap_or((s, ""));
+        if int_part.is_empty() && frac.is_empty() {return Err(invalid())}
+        if !int_part.bytes().chain(frac.bytes()).all(|b| b.is_ascii_digit()) {return Err(invalid())}
+        let frac = format!("{:0<width$}", frac.get(..RATE_PRECISION.min(frac.len())).unwrap_or(""), width = RATE_PRECISION);
+        let int_part: i64 = if int_part.is_empty() {0} else {int_part.parse().map_err(|_| invalid())?};
+        let frac: i64 = frac.parse().map_err(|_| invalid())?;
+        int_part.checked_mul(RATE_SCALE).and_then(|r| r.checked_add(frac)).map(Rate).ok_or_else(invalid)
+    }
+}
+impl fmt::Display for Rate
+{
+    /// Writes the rate as a decimal, with trailing zeros dropped but always at least one
+    /// decimal, E.G. "0.8", "1.0", "0.006689"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frac = format!("{:0width$}", self.0 % RATE_SCALE, width = RATE_PRECISION);
+        let frac = frac.trim_end_matches('0');
+        write!(f, "{}.{}", self.0 / RATE_SCALE, if frac.is_empty() {"0"} else {frac})
+    }
+}
+impl Serialize for Rate
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+impl<'de> Deserialize<'de> for Rate
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(de::Error::custom)
+    }
+}
+
+///
+/// Gives the rate a currency converts to another at, as of a time
+///
+pub trait RateProvider
+{
+    /// How much of the currency converted to one of the currency converted from is worth,
+    /// as of the time, or None if there is no rate
+    ///
+    /// # Arguments
+    ///
+    /// * 'from' - The currency converted from, E.G. "USD"
+    /// * 'to' - The currency converted to
+    /// * 'as_of' - The time the rate is wanted for, in seconds since the unix epoch
+    fn rate(&self, from: &str, to: &str, as_of: i64) -> Option<Rate>;
+}
+
+///
+/// A single row of a rate table
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+struct RateRow
+{
+    from: String,
+    to: String,
+    rate: Rate,
+    /// When the rate took effect, in seconds since the unix epoch
+    timestamp: i64,
+}
+
+///
+/// Rates read from a csv, each in effect from its timestamp until the next one for the
+/// same currencies
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateTable
+{
+    /// The rates of each pair of currencies, from and to, keyed by when they took effect
+    rates: BTreeMap<(String, String), BTreeMap<i64, Rate>>,
+}
+impl RateTable
+{
+    /// Reads the table, a csv with from, to, rate and timestamp columns
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The rates as csv, with a header row
+    pub fn read<R: io::Read>(input: R) -> csv::Result<RateTable>
+    {
+        let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+        let mut table = RateTable::default();
+        for row in rdr.deserialize()
+        {
+            let row: RateRow = row?;
+            table.rates.entry((row.from, row.to)).or_default().insert(row.timestamp, row.rate);
+        }
+        Ok(table)
+    }
+}
+impl RateProvider for RateTable
+{
+    fn rate(&self, from: &str, to: &str, as_of: i64) -> Option<Rate> {
+        if from == to {return Some(Rate::ONE)}
+        let rates = self.rates.get(&(from.to_string(), to.to_string()))?;
+        rates.range(..=as_of).next_back().map(|(_, rate)| *rate)
+    }
+}
+
+///
+/// The balances of every client in a single currency, and what they come to in the base
+/// currency
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CurrencyTotal
+{
+    pub currency: String,
+    pub clients: usize,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    /// The rate to the base currency, None if there is none as of the time
+    pub rate: Option<Rate>,
+    /// The balances in the base currency, None without a rate
+    pub base_available: Option<Amount>,
+    pub base_held: Option<Amount>,
+    pub base_total: Option<Amount>,
+}
+
+///
+/// The balances of every client converted to a single base currency, as of a time
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Consolidation
+{
+    pub base: String,
+    /// The time the rates were taken at, in seconds since the unix epoch
+    pub as_of: i64,
+    /// The balances in each currency, ordered by currency
+    pub currencies: Vec<CurrencyTotal>,
+    /// The balances in the base currency, of the currencies with a rate
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+impl Consolidation
+{
+    /// The currencies left out of the consolidated balances for want of a rate
+    pub fn unconverted(&self) -> Vec<&str>
+    {
+        self.currencies.iter().filter(|c| c.rate.is_none()).map(|c| c.currency.as_str()).collect()
+    }
+}
+
+impl Engine
+{
+    /// Converts the balances of every client to the base currency at the rates as of the time
+    ///
+    /// A client is taken to hold its balances in the base currency of its metadata, or in
+    /// the one consolidated to if it has none
+    ///
+    /// # Arguments
+    ///
+    /// * 'base' - The currency to convert to, E.G. "EUR"
+    /// * 'rates' - Where the rates come from
+    /// * 'as_of' - The time to take the rates at, in seconds since the unix epoch
+    pub fn consolidate(&self, base: &str, rates: &dyn RateProvider, as_of: i64) -> Consolidation
+    {
+        let add = |sum: Amount, amount: Amount| sum.checked_add(amount).unwrap_or(Amount::MAX);
+        let mut by_currency: BTreeMap<&str, (usize, Amount, Amount, Amount)> = BTreeMap::new();
+        for c in self.clients.values()
+        {
+            let currency = self.metadata.get(&c.acc.client).and_then(|m| m.base_currency.as_deref()).unwrap_or(base);
+            let sums = by_currency.entry(currency).or_default();
+            *sums = (sums.0 + 1, add(sums.1, c.acc.available), add(sums.2, c.acc.held), add(sums.3, c.acc.total));
+        }
+        let mut consolidation = Consolidation { base: base.to_string(), as_of, currencies: Vec::new(), available: Amount::ZERO, held: Amount::ZERO, total: Amount::ZERO };
+        for (currency, (clients, available, held, total)) in by_currency
+        {
+            let rate = rates.rate(currency, base, as_of);
+            let convert = |amount: Amount| rate.and_then(|r| r.convert(amount));
+            let (base_available, base_held, base_total) = (convert(available), convert(held), convert(total));
+            consolidation.available = add(consolidation.available, base_available.unwrap_or(Amount::ZERO));
+            consolidation.held = add(consolidation.held, base_held.unwrap_or(Amount::ZERO));
+            consolidation.total = add(consolidation.total, base_total.unwrap_or(Amount::ZERO));
+            consolidation.currencies.push(CurrencyTotal { currency: currency.to_string(), clients, available, held, total, rate, base_available, base_held, base_total });
+        }
+        consolidation
+    }
+}
+
+/// Writes the consolidation as csv, a currency a row, ending with a total row in the base
+/// currency, which only counts the clients of the currencies with a rate, as only their
+/// balances are in it
+///
+/// # Arguments
+///
+/// * 'consolidation' - The balances to write
+/// * 'out' - Where to write them to
+pub fn write_consolidation<W: io::Write>(consolidation: &Consolidation, out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for currency in &consolidation.currencies
+    {
+        wrtr.serialize(currency)?;
+    }
+    let clients = consolidation.currencies.iter().filter(|c| c.rate.is_some()).map(|c| c.clients).sum();
+    wrtr.serialize(CurrencyTotal { currency: "total".to_string(), clients, available: consolidation.available, held: consolidation.held,
+        total: consolidation.total, rate: None, base_available: Some(consolidation.available), base_held: Some(consolidation.held), base_total: Some(consolidation.total) })?;
+    wrtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{read_metadata, EnginePolicy, Tx, TypeTx};
+
+    fn engine() -> Engine
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.metadata = read_metadata("client,base_currency\n1,USD\n2,USD\n3,GBP\n".as_bytes()).unwrap();
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(100000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 2, 2, None));
+        engine.apply(Tx::new(TypeTx::Deposit, 3, 3, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 4, 4, Some(Amount::from_minor(30000))));
+        engine
+    }
+    fn rates() -> RateTable
+    {
+        RateTable::read("from,to,rate,timestamp\nUSD,EUR,0.9,100\nUSD,EUR,0.8,200\nGBP,EUR,1.2,300\n".as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn balances_consolidate_to_the_base_currency()
+    {
+        let consolidation = engine().consolidate("EUR", &rates(), 250);
+        assert_eq!(consolidation.unconverted(),vec!["GBP"]);
+        assert_eq!(consolidation.total,Amount::from_minor(150000));
+        let later = engine().consolidate("EUR", &rates(), 300);
+        assert!(later.unconverted().is_empty());
+        assert_eq!(later.total,Amount::from_minor(174000));
+    }
+    #[test]
+    fn total_row_counts_only_converted_clients()
+    {
+        let mut out = Vec::new();
+        write_consolidation(&engine().consolidate("EUR", &rates(), 250), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),"currency,clients,available,held,total,rate,base_available,base_held,base_total\n\
+            EUR,1,3.0,0.0,3.0,1.0,3.0,0.0,3.0\nGBP,1,2.0,0.0,2.0,,,,\nUSD,2,10.0,5.0,15.0,0.8,8.0,4.0,12.0\ntotal,3,11.0,4.0,15.0,,11.0,4.0,15.0\n");
+    }
+    #[test]
+    fn rates_carry_ten_decimals()
+    {
+        let rates = RateTable::read("from,to,rate,timestamp\nJPY,EUR,0.006689,0\nIDR,EUR,0.000056789012345,0\n".as_bytes()).unwrap();
+        let jpy = rates.rate("JPY", "EUR", 0).unwrap();
+        assert_eq!(jpy.to_string(),"0.006689");
+        assert_eq!(jpy.convert(Amount::from_minor(1_000_000_000)),Some(Amount::from_minor(6_689_000)));
+        assert_eq!(rates.rate("IDR", "EUR", 0).map(|r| r.to_string()).as_deref(),Some("0.000056789"));
+        assert!(RateTable::read("from,to,rate,timestamp\nUSD,EUR,-1,0\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,402 @@
+use std::{collections::BTreeMap, fmt, io};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use crate::{Amount, AmountParser, RoundingMode, SymbolPrefixed, TypeTx, OPTIONAL_COLUMNS, REQUIRED_COLUMNS};
+
+/// Header names of other layouts taken for each of our columns when the mapping doesn't
+/// name one, compared without case and with spaces as underscores. Dates aren't taken
+/// for the timestamp, as it is in seconds since the unix epoch
+const ALIASES: [(&str, &[&str]); 9] = [
+    ("type", &["type", "kind", "transaction_type", "tx_type"]),
+    ("client", &["client", "client_id", "customer", "customer_id", "account_id"]),
+    ("tx", &["tx", "tx_id", "transaction", "transaction_id", "reference", "ref", "reference_number"]),
+    ("amount", &["amount", "value", "sum"]),
+    ("timestamp", &["timestamp", "unix_time"]),
+    ("currency", &["currency", "ccy"]),
+    ("memo", &["memo", "description", "text", "narrative"]),
+    ("counterparty", &["counterparty", "payee", "payer", "merchant"]),
+    ("account", &["account", "sub_account"]),
+];
+
+///
+/// What the sign of an amount says about the transaction
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AmountSign
+{
+    /// Amounts are never negative, the type column says what they are
+    #[default]
+    #[serde(rename = "type")]
+    Type,
+    /// Money in is positive and money out negative, so a positive amount is a deposit
+    /// and a negative one a withdrawal
+    #[serde(rename = "signed")]
+    Signed,
+    /// Money in is negative, as in exports made from the side of the bank
+    #[serde(rename = "inverted")]
+    Inverted,
+}
+
+///
+/// How a bank export is laid out, to convert it to our columns, read from a json config, E.G.
+/// {"delimiter": ";", "decimal_comma": true, "columns": {"client": "Customer", "tx": "Ref"},
+/// "types": {"CR": "deposit", "DR": "withdrawal"}, "amount_sign": "signed"}
+///
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConvertMapping
+{
+    /// The field separator of the export, a comma if left out
+    pub delimiter: Option<char>,
+    /// Whether amounts use a comma for the decimal point and dots between thousands
+    #[serde(default)]
+    pub decimal_comma: bool,
+    /// The header of the export each of our columns is read from, keyed by ours; the
+    /// ones left out are looked for under their usual names
+    #[serde(default)]
+    pub columns: BTreeMap<String, String>,
+    /// Our type for each value of the type column of the export, values that are our
+    /// types already needn't be given
+    #[serde(default, deserialize_with = "parse_types")]
+    pub types: BTreeMap<String, TypeTx>,
+    #[serde(default)]
+    pub amount_sign: AmountSign,
+}
+
+/// Reads the types of the mapping, failing on a name that isn't one of our types
+fn parse_types<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<String, TypeTx>, D::Error>
+{
+    let names = BTreeMap::<String, String>::deserialize(deserializer)?;
+    names.into_iter().map(|(given, ours)| ours.parse().map(|ours| (given, ours)).map_err(de::Error::custom)).collect()
+}
+
+///
+/// Why the export couldn't be converted
+///
+#[derive(Debug)]
+pub enum ConvertError
+{
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    /// The mapping maps a column we don't have
+    UnknownColumn(String),
+    /// The export has no column for one we need, neither mapped nor under a usual name
+    MissingColumn(String),
+    /// The delimiter isn't a single ascii character
+    InvalidDelimiter(char),
+}
+impl fmt::Display for ConvertError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            ConvertError::Json(e) => write!(f, "{}", e),
+            ConvertError::Csv(e) => write!(f, "{}", e),
+            ConvertError::UnknownColumn(column) => write!(f, "the mapping names a column '{}' that isn't one of ours", column),
+            ConvertError::MissingColumn(column) => write!(f, "no column of the export is mapped to '{}'", column),
+            ConvertError::InvalidDelimiter(d) => write!(f, "the delimiter '{}' isn't an ascii character", d),
+        }
+    }
+}
+impl std::error::Error for ConvertError {}
+impl From<serde_json::Error> for ConvertError
+{
+    fn from(e: serde_json::Error) -> Self {
+        ConvertError::Json(e)
+    }
+}
+impl From<csv::Error> for ConvertError
+{
+    fn from(e: csv::Error) -> Self {
+        ConvertError::Csv(e)
+    }
+}
+impl From<io::Error> for ConvertError
+{
+    fn from(e: io::Error) -> Self {
+        ConvertError::Csv(csv::Error::from(e))
+    }
+}
+
+///
+/// Where one of our columns was read from
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ColumnSource
+{
+    pub column: String,
+    /// The header in the export
+    pub source: String,
+    /// Whether it was found by its name rather than given in the mapping
+    pub inferred: bool,
+}
+
+///
+/// How the conversion went, to check the mapping against
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConversionReport
+{
+    /// How many rows the export has
+    pub rows: u64,
+    /// How many of them were written out
+    pub converted: u64,
+    /// Where each of our columns was read from, in the order they are written
+    pub columns: Vec<ColumnSource>,
+    /// How many rows went to each of our types
+    pub types: BTreeMap<String, u64>,
+    /// Values of the type column with no type of ours, and how many rows had them
+    pub unmapped_types: BTreeMap<String, u64>,
+    /// Why rows weren't written out, and how many weren't for each reason
+    pub skipped: BTreeMap<String, u64>,
+}
+impl fmt::Display for ConversionReport
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        writeln!(f, "rows: {}, converted: {}", self.rows, self.converted)?;
+        for c in &self.columns
+        {
+            writeln!(f, "  {} <- {}{}", c.column, c.source, if c.inferred {" (inferred)"} else {""})?;
+        }
+        writeln!(f, "types: {}", self.types.iter().map(|(t, n)| format!("{}: {}", t, n)).collect::<Vec<_>>().join(", "))?;
+        for (value, n) in &self.unmapped_types
+        {
+            writeln!(f, "unmapped type '{}': {} rows", value, n)?;
+        }
+        for (reason, n) in &self.skipped
+        {
+            writeln!(f, "skipped, {}: {} rows", reason, n)?;
+        }
+        Ok(())
+    }
+}
+
+impl ConvertMapping
+{
+    /// Reads the mapping from its json config
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The config as json
+    pub fn read<R: io::Read>(input: R) -> Result<ConvertMapping, ConvertError>
+    {
+        let mapping: ConvertMapping = serde_json::from_reader(input)?;
+        let ours = |c: &str| REQUIRED_COLUMNS.contains(&c) || OPTIONAL_COLUMNS.contains(&c);
+        if let Some(column) = mapping.columns.keys().find(|c| !ours(c))
+        {
+            return Err(ConvertError::UnknownColumn(column.clone()));
+        }
+        match mapping.delimiter.filter(|d| !d.is_ascii())
+        {
+            Some(d) => Err(ConvertError::InvalidDelimiter(d)),
+            None => Ok(mapping)
+        }
+    }
+    /// Returns a mapping with the columns of the export found under their usual names,
+    /// for a starting point to edit
+    ///
+    /// # Arguments
+    ///
+    /// * 'headers' - The header row of the export
+    pub fn infer(headers: &csv::StringRecord) -> ConvertMapping
+    {
+        let mut mapping = ConvertMapping::default();
+        for source in infer_columns(headers, &mapping)
+        {
+            mapping.columns.insert(source.column, source.source);
+        }
+        mapping
+    }
+    /// Converts the export to a transactions file in our columns, returning how it went
+    ///
+    /// Rows without a type of ours, a client or transaction ID that can't be read, or an
+    /// amount that isn't a number are left out and counted in the report
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The export as csv, with a header row
+    /// * 'out' - Where to write the transactions to
+    ///
+    /// # Errors
+    ///
+    /// Fails if a column we need can't be found, or the export can't be read
+    pub fn convert<R: io::Read, W: io::Write>(&self, input: R, out: W) -> Result<ConversionReport, ConvertError>
+    {
+        let mut rdr = csv::ReaderBuilder::new().delimiter(self.delimiter.map_or(b',', |d| d as u8)).trim(csv::Trim::All).flexible(true).from_reader(input);
+        let headers = rdr.headers()?.clone();
+        let columns = infer_columns(&headers, self);
+        let needed = match self.amount_sign
+        {
+            AmountSign::Type => &REQUIRED_COLUMNS[..],
+            _ => &REQUIRED_COLUMNS[1..]
+        };
+        if let Some(missing) = needed.iter().find(|c| !columns.iter().any(|s| s.column == **c))
+        {
+            return Err(ConvertError::MissingColumn(missing.to_string()));
+        }
+        let index = |column: &str| columns.iter().find(|s| s.column == column).and_then(|s| headers.iter().position(|h| h == s.source));
+        let extra: Vec<(&str, usize)> = OPTIONAL_COLUMNS.iter().filter_map(|c| index(c).map(|i| (*c, i))).collect();
+        let (type_col, client_col, tx_col, amount_col) = (index("type"), index("client"), index("tx"), index("amount"));
+
+        let mut report = ConversionReport { columns, ..ConversionReport::default() };
+        let mut wrtr = csv::Writer::from_writer(out);
+        wrtr.write_record(REQUIRED_COLUMNS.iter().chain(extra.iter().map(|(c, _)| c)))?;
+        for row in rdr.records()
+        {
+            let row = row?;
+            report.rows += 1;
+            let field = |i: Option<usize>| i.and_then(|i| row.get(i)).unwrap_or("");
+            let given = field(type_col);
+            let r#type = self.types.get(given).copied().or_else(|| given.parse::<TypeTx>().ok());
+            let amount = match self.amount(field(amount_col))
+            {
+                Ok(amount) => amount,
+                Err(()) => {
+                    *report.skipped.entry("amount isn't a number".to_string()).or_insert(0) += 1;
+                    continue;
+                }
+            };
+            let (r#type, amount) = match (self.amount_sign, r#type, amount)
+            {
+                (AmountSign::Type, Some(r#type), amount) => (r#type, amount.map(|(_, a)| a)),
+                //the sign only decides between a deposit and a withdrawal
+                (_, Some(r#type), amount) if !r#type.carries_amount() => (r#type, amount.map(|(_, a)| a)),
+                (sign, _, Some((negative, amount))) => {
+                    let deposit = negative == (sign == AmountSign::Inverted);
+                    (if deposit {TypeTx::Deposit} else {TypeTx::Withdrawal}, Some(amount))
+                },
+                (_, Some(r#type), None) => (r#type, None),
+                (_, _, None) if type_col.is_none() => {
+                    *report.skipped.entry("no amount to take the type from".to_string()).or_insert(0) += 1;
+                    continue;
+                },
+                (_, None, _) => {
+                    *report.unmapped_types.entry(given.to_string()).or_insert(0) += 1;
+                    *report.skipped.entry("unmapped type".to_string()).or_insert(0) += 1;
+                    continue;
+                }
+            };
+            let (client, tx) = (field(client_col), field(tx_col));
+            if client.parse::<u16>().is_err() || tx.parse::<u32>().is_err()
+            {
+                *report.skipped.entry("client or tx isn't a number".to_string()).or_insert(0) += 1;
+                continue;
+            }
+            let amount = amount.unwrap_or_default();
+            let fixed = [r#type.as_str(), client, tx, amount.as_str()];
+            wrtr.write_record(fixed.iter().copied().chain(extra.iter().map(|(_, i)| row.get(*i).unwrap_or(""))))?;
+            *report.types.entry(r#type.as_str().to_string()).or_insert(0) += 1;
+            report.converted += 1;
+        }
+        wrtr.flush()?;
+        Ok(report)
+    }
+    /// Reads an amount of the export as the symbol prefixed amount parser does, returning
+    /// whether it was negative and the amount without its sign, written our way, None if
+    /// there is none and Err if it isn't a number
+    ///
+    /// # Arguments
+    ///
+    /// * 'text' - The amount as given
+    fn amount(&self, text: &str) -> Result<Option<(bool, String)>, ()>
+    {
+        //spaces and apostrophes can also separate the thousands
+        let text: String = text.chars().filter(|c| !c.is_whitespace() && *c != '\'').collect();
+        if text.is_empty() {return Ok(None)}
+        let text = SymbolPrefixed.normalize(&text, self.decimal_comma).ok_or(())?;
+        let (negative, digits) = match text.strip_prefix('-')
+        {
+            Some(digits) => (true, digits),
+            None => (false, text.as_str())
+        };
+        match Amount::parse(digits, RoundingMode::HalfEven)
+        {
+            Ok(amount) if !amount.is_negative() => Ok(Some((negative, digits.to_string()))),
+            _ => Err(())
+        }
+    }
+}
+
+/// Where each of our columns is read from, the mapped ones first and the rest found
+/// under their usual names, in the order they are written
+///
+/// # Arguments
+///
+/// * 'headers' - The header row of the export
+/// * 'mapping' - The columns given in the mapping
+fn infer_columns(headers: &csv::StringRecord, mapping: &ConvertMapping) -> Vec<ColumnSource>
+{
+    let mut sources = Vec::new();
+    for column in REQUIRED_COLUMNS.iter().chain(OPTIONAL_COLUMNS.iter())
+    {
+        if let Some(source) = mapping.columns.get(*column)
+        {
+            if headers.iter().any(|h| h == source)
+            {
+                sources.push(ColumnSource { column: column.to_string(), source: source.clone(), inferred: false });
+            }
+            continue;
+        }
+        let aliases = ALIASES.iter().find(|(c, _)| c == column).map_or(&[][..], |(_, a)| a);
+        let taken = |h: &str| mapping.columns.values().any(|s| s == h) || sources.iter().any(|s: &ColumnSource| s.source == h);
+        let found = aliases.iter().find_map(|alias| headers.iter().find(|h| h.replace([' ', '-'], "_").eq_ignore_ascii_case(alias) && !taken(h)));
+        if let Some(source) = found
+        {
+            sources.push(ColumnSource { column: column.to_string(), source: source.to_string(), inferred: true });
+        }
+    }
+    sources
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(export: &str) -> (String, ConversionReport)
+    {
+        let mapping = ConvertMapping::read(r#"{"delimiter": ";", "decimal_comma": true, "columns": {"tx": "Ref"},
+            "types": {"REV": "dispute"}, "amount_sign": "signed"}"#.as_bytes()).unwrap();
+        let mut out = Vec::new();
+        let report = mapping.convert(format!("Customer;Ref;Kind;Amount;Booking Date;Text\n{}", export).as_bytes(), &mut out).unwrap();
+        (String::from_utf8(out).unwrap(), report)
+    }
+
+    #[test]
+    fn bank_export_converted()
+    {
+        let (out, report) = convert("1;10;CR;1.234,50;2024-01-02;salary\n1;11;DR;-20,00;2024-01-03;rent\n1;10;REV;;2024-01-04;\n");
+        assert_eq!(out,"type,client,tx,amount,memo\ndeposit,1,10,1234.50,salary\nwithdrawal,1,11,20.00,rent\ndispute,1,10,,\n");
+        assert_eq!((report.rows, report.converted),(3, 3));
+        assert_eq!(report.columns.iter().map(|c| (c.column.as_str(), c.source.as_str(), c.inferred)).collect::<Vec<_>>(),vec![
+            ("type", "Kind", true), ("client", "Customer", true), ("tx", "Ref", false), ("amount", "Amount", true), ("memo", "Text", true)]);
+    }
+    #[test]
+    fn currency_symbols_dropped()
+    {
+        let (out, _) = convert("2;15;CR;€ 7,5;2024-01-06;\n2;16;DR;-EUR 1'000,00;2024-01-06;\n");
+        assert_eq!(out,"type,client,tx,amount,memo\ndeposit,2,15,7.5,\nwithdrawal,2,16,1000.00,\n");
+    }
+    #[test]
+    fn unreadable_rows_skipped()
+    {
+        let (out, report) = convert("2;12;XX;;2024-01-05;\nx;13;CR;1,0;2024-01-05;\n2;14;CR;abc;2024-01-05;\n");
+        assert_eq!(out,"type,client,tx,amount,memo\n");
+        assert_eq!(report.unmapped_types.get("XX"),Some(&1));
+        assert_eq!(report.skipped.values().sum::<u64>(),3);
+    }
+    #[test]
+    fn bad_mappings_rejected()
+    {
+        assert!(matches!(ConvertMapping::read(r#"{"columns": {"iban": "IBAN"}}"#.as_bytes()),Err(ConvertError::UnknownColumn(_))));
+        assert!(matches!(ConvertMapping::read(r#"{"types": {"CR": "credit"}}"#.as_bytes()),Err(ConvertError::Json(_))));
+        let missing = ConvertMapping::default().convert("Customer,Amount\n1,2\n".as_bytes(), Vec::new());
+        assert!(matches!(missing,Err(ConvertError::MissingColumn(c)) if c == "type"));
+    }
+    #[test]
+    fn mapping_inferred()
+    {
+        let inferred = ConvertMapping::infer(&csv::StringRecord::from(vec!["Transaction ID", "Client", "Value", "Date"]));
+        assert_eq!(inferred.columns.into_iter().collect::<Vec<_>>(),vec![
+            ("amount".to_string(), "Value".to_string()), ("client".to_string(), "Client".to_string()), ("tx".to_string(), "Transaction ID".to_string())]);
+    }
+}
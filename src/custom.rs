@@ -0,0 +1,78 @@
+use crate::{Client, Engine, RejectReason, Tx, TypeTx};
+
+///
+/// Applies a type of transaction the engine doesn't know itself, for
+/// domain-specific operations
+///
+pub trait CustomTxHandler: Send
+{
+    /// Applies the transaction to its client, or says why it can't be
+    ///
+    /// The client is created first if it is new, and the change is kept in the
+    /// history, so it can be undone and rolled back like any other
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction, with its amount parsed and checked against the policy bounds
+    /// * 'client' - The client it is for
+    fn apply(&mut self, tx: &Tx, client: &mut Client) -> Result<(), RejectReason>;
+}
+
+impl Engine
+{
+    /// Routes transactions of the given type to a handler, replacing any handler
+    /// registered under the name before
+    ///
+    /// Returns false, and registers nothing, if the name is one of the types the engine
+    /// knows, as those never reach a handler
+    ///
+    /// # Arguments
+    ///
+    /// * 'name' - The type as it appears in the type column, E.G. "fee"
+    /// * 'handler' - What applies them
+    pub fn register_custom_type(&mut self, name: &str, handler: Box<dyn CustomTxHandler>) -> bool
+    {
+        if name.parse::<TypeTx>().is_ok() || name == TypeTx::Custom.as_str() {return false}
+        self.custom_handlers.insert(name.to_string(), handler);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, Dialect, EnginePolicy, SchemaMode, TxRecord};
+
+    /// Takes a fee out of the available funds
+    struct Fee;
+    impl CustomTxHandler for Fee
+    {
+        fn apply(&mut self, tx: &Tx, client: &mut Client) -> Result<(), RejectReason>
+        {
+            let fee = tx.amount.ok_or(RejectReason::MissingAmount)?;
+            client.acc.available = client.acc.available.checked_sub(fee).ok_or(RejectReason::Overflow)?;
+            client.acc.total = client.acc.total.checked_sub(fee).ok_or(RejectReason::Overflow)?;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn custom_types()
+    {
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\nfee,1,2,0.5\nrebate,1,3,1.0\n";
+        let records: Vec<TxRecord> = Dialect::default().read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!((records[1].r#type, records[1].custom.as_deref()),(TypeTx::Custom, Some("fee")));
+        assert_eq!(records[0].custom,None);
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        assert!(!engine.register_custom_type("deposit", Box::new(Fee)));
+        assert!(engine.register_custom_type("fee", Box::new(Fee)));
+        records.into_iter().for_each(|r| engine.apply_record(r));
+        engine.apply_record(TxRecord::custom("fee", 1, 4, None));
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(45000));
+        let rejected: Vec<(u32, RejectReason)> = engine.rejections.iter().map(|r| (r.tx, r.reason)).collect();
+        assert_eq!(rejected,vec![(3, RejectReason::UnknownType), (4, RejectReason::MissingAmount)]);
+        assert!(engine.undo(1, 2));
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(50000));
+    }
+}
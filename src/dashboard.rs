@@ -0,0 +1,229 @@
+use std::{collections::{HashMap, VecDeque}, io, time::{Duration, Instant}};
+use ratatui::{
+    Frame, Terminal,
+    backend::{Backend, CrosstermBackend},
+    crossterm::{event::{self, Event, KeyCode}, execute, terminal},
+    layout::{Constraint, Layout},
+    widgets::{Block, List, Paragraph, Row, Table},
+};
+use crate::{Amount, Engine, RejectReason};
+
+/// How many recently locked accounts are kept on screen
+const RECENTLY_LOCKED: usize = 10;
+/// How many accounts are listed by held funds
+const TOP_HELD: usize = 10;
+/// How often the dashboard is redrawn while rows come in
+const REFRESH: Duration = Duration::from_millis(100);
+
+/// Returns the clients holding the most funds, largest first
+///
+/// # Arguments
+///
+/// * 'engine' - The engine holding the clients
+/// * 'n' - How many clients to return at most
+pub fn top_held(engine: &Engine, n: usize) -> Vec<(u16, Amount)>
+{
+    let mut held: Vec<(u16, Amount)> = engine.clients.values()
+        .filter(|c| c.acc.held > Amount::ZERO)
+        .map(|c| (c.acc.client, c.acc.held))
+        .collect();
+    held.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    held.truncate(n);
+    held
+}
+
+///
+/// What the dashboard keeps track of while rows are processed
+///
+#[derive(Debug)]
+pub struct DashboardStats
+{
+    /// How many rows have been processed
+    pub rows: u64,
+    started: Instant,
+    /// The clients most recently locked, newest first
+    pub recently_locked: VecDeque<u16>,
+    /// How many transactions were refused, by reason
+    pub rejections: HashMap<RejectReason, usize>,
+    /// How many rejections have been counted so far
+    counted: usize,
+}
+impl Default for DashboardStats
+{
+    fn default() -> Self {
+        DashboardStats { rows: 0, started: Instant::now(), recently_locked: VecDeque::new(), rejections: HashMap::new(), counted: 0 }
+    }
+}
+impl DashboardStats
+{
+    /// Takes note of a processed row
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine, after the row was applied
+    /// * 'client' - The client of the row
+    /// * 'was_locked' - Whether the client was locked before the row
+    pub fn observe(&mut self, engine: &Engine, client: u16, was_locked: bool)
+    {
+        self.rows += 1;
+        if !was_locked && engine.clients.get(&client).is_some_and(|c| c.acc.locked)
+        {
+            self.recently_locked.push_front(client);
+            self.recently_locked.truncate(RECENTLY_LOCKED);
+        }
+        for rejection in &engine.rejections[self.counted..]
+        {
+            *self.rejections.entry(rejection.reason).or_insert(0) += 1;
+        }
+        self.counted = engine.rejections.len();
+    }
+    /// Rows processed per second since the start
+    pub fn throughput(&self) -> f64
+    {
+        let secs = self.started.elapsed().as_secs_f64();
+        if secs > 0.0 {self.rows as f64 / secs} else {0.0}
+    }
+}
+
+/// Draws the dashboard
+///
+/// # Arguments
+///
+/// * 'frame' - The frame to draw into
+/// * 'stats' - What has been tracked so far
+/// * 'engine' - The engine holding the clients
+/// * 'done' - Whether every row has been processed
+pub fn render(frame: &mut Frame, stats: &DashboardStats, engine: &Engine, done: bool)
+{
+    let [header, body] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(frame.area());
+    let [held, locked, rejected] = Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(25), Constraint::Percentage(35)]).areas(body);
+
+    let status = if done {"done, press q to quit"} else {"processing"};
+    frame.render_widget(Paragraph::new(format!("{} rows, {:.0} rows/s, {} clients - {}",
+        stats.rows, stats.throughput(), engine.clients.len(), status))
+        .block(Block::bordered().title("transactions")), header);
+
+    let rows = top_held(engine, TOP_HELD).into_iter().map(|(client, amount)| Row::new(vec![client.to_string(), amount.to_string()]));
+    frame.render_widget(Table::new(rows, [Constraint::Length(8), Constraint::Min(10)])
+        .header(Row::new(vec!["client", "held"]))
+        .block(Block::bordered().title("top held")), held);
+
+    frame.render_widget(List::new(stats.recently_locked.iter().map(|c| c.to_string()))
+        .block(Block::bordered().title("recently locked")), locked);
+
+    let mut reasons: Vec<(&RejectReason, &usize)> = stats.rejections.iter().collect();
+    reasons.sort_by(|a, b| b.1.cmp(a.1));
+    let rows = reasons.into_iter().map(|(reason, count)| Row::new(vec![reason.to_string(), count.to_string()]));
+    frame.render_widget(Table::new(rows, [Constraint::Min(16), Constraint::Length(8)])
+        .header(Row::new(vec!["reason", "count"]))
+        .block(Block::bordered().title("rejections")), rejected);
+}
+
+///
+/// A dashboard drawn on stderr while the input is processed, so the report
+/// can still be redirected from stdout
+///
+pub struct Dashboard<B: Backend>
+{
+    terminal: Terminal<B>,
+    pub stats: DashboardStats,
+    last_draw: Option<Instant>,
+}
+impl Dashboard<CrosstermBackend<io::Stderr>>
+{
+    /// Takes over the terminal until finish is called
+    pub fn start() -> io::Result<Self>
+    {
+        terminal::enable_raw_mode()?;
+        execute!(io::stderr(), terminal::EnterAlternateScreen)?;
+        Dashboard::with_backend(CrosstermBackend::new(io::stderr()))
+    }
+    /// Draws the final state and waits for q, then gives the terminal back
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine once every row is processed
+    pub fn finish(mut self, engine: &Engine) -> io::Result<()>
+    {
+        let waited = self.draw(engine, true).and_then(|_| loop
+        {
+            if let Event::Key(key) = event::read()?
+            {
+                if matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {break Ok(())}
+            }
+        });
+        terminal::disable_raw_mode()?;
+        execute!(io::stderr(), terminal::LeaveAlternateScreen)?;
+        waited
+    }
+}
+impl<B: Backend> Dashboard<B>
+{
+    /// Returns a dashboard drawing to any backend
+    pub fn with_backend(backend: B) -> io::Result<Self>
+    {
+        Ok(Dashboard { terminal: Terminal::new(backend)?, stats: DashboardStats::default(), last_draw: None })
+    }
+    /// Takes note of a processed row, redrawing if it has been a while
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine, after the row was applied
+    /// * 'client' - The client of the row
+    /// * 'was_locked' - Whether the client was locked before the row
+    pub fn observe(&mut self, engine: &Engine, client: u16, was_locked: bool) -> io::Result<()>
+    {
+        self.stats.observe(engine, client, was_locked);
+        if self.last_draw.is_none_or(|t| t.elapsed() >= REFRESH)
+        {
+            self.draw(engine, false)?;
+        }
+        Ok(())
+    }
+    /// Draws the dashboard now
+    pub fn draw(&mut self, engine: &Engine, done: bool) -> io::Result<()>
+    {
+        let stats = &self.stats;
+        self.terminal.draw(|frame| render(frame, stats, engine, done))?;
+        self.last_draw = Some(Instant::now());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn tracks_rows()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        let mut dashboard = Dashboard::with_backend(TestBackend::new(100, 20)).unwrap();
+        let txs = vec![
+            Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(10000))),
+            Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(30000))),
+            Tx::new(TypeTx::Dispute, 1, 1, None),
+            Tx::new(TypeTx::Dispute, 2, 2, None),
+            Tx::new(TypeTx::Chargeback, 1, 1, None),
+            Tx::new(TypeTx::Deposit, 3, 3, None),
+        ];
+        for tx in txs
+        {
+            let client = tx.client;
+            let was_locked = engine.clients.get(&client).is_some_and(|c| c.acc.locked);
+            engine.apply(tx);
+            dashboard.observe(&engine, client, was_locked).unwrap();
+        }
+        assert_eq!(dashboard.stats.rows,6);
+        assert_eq!(dashboard.stats.recently_locked,vec![1]);
+        assert_eq!(dashboard.stats.rejections[&RejectReason::MissingAmount],1);
+        assert_eq!(top_held(&engine, 10),vec![(2, Amount::from_minor(30000))]);
+
+        dashboard.draw(&engine, true).unwrap();
+        let screen: String = dashboard.terminal.backend().buffer().content().iter().map(|c| c.symbol()).collect();
+        assert!(screen.contains("6 rows"));
+        assert!(screen.contains("MissingAmount"));
+    }
+}
@@ -0,0 +1,188 @@
+use roaring::RoaringBitmap;
+use crate::GlobalDedup;
+
+/// The most hash functions a bloom filter uses, past which lookups cost more than they save
+const MAX_HASHES: u32 = 16;
+
+///
+/// A fixed size bit set that says for sure when a transaction ID was never added,
+/// and only probably when it was
+///
+#[derive(Debug, Clone)]
+pub struct BloomFilter
+{
+    bits: Vec<u64>,
+    /// How many bits each ID sets
+    hashes: u32,
+}
+impl BloomFilter
+{
+    /// Returns an empty filter sized to stay near the false positive rate until it
+    /// holds the expected number of IDs
+    ///
+    /// # Arguments
+    ///
+    /// * 'expected' - How many IDs it is sized for, more can be added at a higher rate
+    /// * 'false_positive_rate' - How often an ID never added may be taken as added, E.G. 0.01
+    pub fn new(expected: u64, false_positive_rate: f64) -> BloomFilter
+    {
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let bits = (-(expected.max(1) as f64) * rate.ln() / (ln2 * ln2)).ceil().max(64.0) as u64;
+        let hashes = ((bits as f64 / expected.max(1) as f64) * ln2).round().clamp(1.0, MAX_HASHES as f64) as u32;
+        BloomFilter { bits: vec![0; bits.div_ceil(64) as usize], hashes }
+    }
+    pub fn insert(&mut self, id: u32)
+    {
+        for bit in positions(id, self.bits.len() as u64 * 64, self.hashes)
+        {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+    /// False if the ID was never added, true if it may have been
+    pub fn may_contain(&self, id: u32) -> bool
+    {
+        positions(id, self.bits.len() as u64 * 64, self.hashes).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+/// The bits an ID sets in a bloom filter of so many bits, by double hashing a single mix of it
+fn positions(id: u32, len: u64, hashes: u32) -> impl Iterator<Item = usize>
+{
+    //splitmix64, so neighbouring IDs set unrelated bits
+    let mut h = (id as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^= h >> 31;
+    let (first, step) = (h & 0xFFFF_FFFF, (h >> 32) | 1);
+    (0..hashes as u64).map(move |i| (first.wrapping_add(i.wrapping_mul(step)) % len) as usize)
+}
+
+///
+/// How the transaction IDs checked for reuse fared
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DedupStats
+{
+    /// The IDs checked
+    pub checked: u64,
+    /// The IDs the bloom filter cleared as fresh without looking at the exact set
+    pub bloom_cleared: u64,
+    /// The IDs found already used
+    pub duplicates: u64,
+}
+
+///
+/// Every transaction ID used by a deposit or withdrawal of any client, so reusing
+/// one is caught even across clients
+///
+/// The exact set is a roaring bitmap, which takes a little over a bit an ID where
+/// they are dense. An optional bloom filter sits in front of it, so the common case of
+/// a fresh ID is settled by a few bit lookups rather than a probe of a set that can
+/// grow past the caches on huge runs
+///
+#[derive(Debug, Clone)]
+pub struct TxDedup
+{
+    bloom: Option<BloomFilter>,
+    seen: RoaringBitmap,
+    pub stats: DedupStats,
+}
+impl TxDedup
+{
+    /// Returns a dedup that has seen no IDs
+    ///
+    /// # Arguments
+    ///
+    /// * 'config' - Whether to put a bloom filter in front, and how large
+    pub fn new(config: &GlobalDedup) -> TxDedup
+    {
+        let bloom = config.bloom_rate.map(|rate| BloomFilter::new(config.expected, rate));
+        TxDedup { bloom, seen: RoaringBitmap::new(), stats: DedupStats::default() }
+    }
+    /// Whether the ID was used already, counting the check
+    pub fn seen(&mut self, id: u32) -> bool
+    {
+        self.stats.checked += 1;
+        if self.bloom.as_ref().is_some_and(|b| !b.may_contain(id))
+        {
+            self.stats.bloom_cleared += 1;
+            return false;
+        }
+        let seen = self.seen.contains(id);
+        if seen {self.stats.duplicates += 1}
+        seen
+    }
+    /// Marks the ID as used
+    pub fn insert(&mut self, id: u32)
+    {
+        if let Some(bloom) = self.bloom.as_mut()
+        {
+            bloom.insert(id);
+        }
+        self.seen.insert(id);
+    }
+    /// Takes the IDs back out of the exact set, E.G. when rolling back
+    ///
+    /// They stay set in the bloom filter, which only costs an exact lookup should they
+    /// come again
+    pub fn forget(&mut self, ids: &[u32])
+    {
+        for id in ids
+        {
+            self.seen.remove(*id);
+        }
+    }
+    /// How many IDs were used
+    pub fn len(&self) -> usize
+    {
+        self.seen.len() as usize
+    }
+    pub fn is_empty(&self) -> bool
+    {
+        self.seen.is_empty()
+    }
+    /// About how many bytes the exact set of IDs takes, as its serialized size
+    pub fn heap_bytes(&self) -> usize
+    {
+        self.seen.serialized_size()
+    }
+    /// Every ID used, in ascending order
+    pub fn ids(&self) -> Vec<u32>
+    {
+        self.seen.iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_front_end()
+    {
+        let mut bloom = BloomFilter::new(10_000, 0.01);
+        (0..10_000).for_each(|id| bloom.insert(id * 2));
+        assert!((0..10_000).all(|id| bloom.may_contain(id * 2)));
+        let false_positives = (0..10_000).filter(|id| bloom.may_contain(id * 2 + 1)).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        let mut dedup = TxDedup::new(&GlobalDedup { bloom_rate: Some(0.01), expected: 1000 });
+        assert!(!dedup.seen(5));
+        dedup.insert(5);
+        assert!(dedup.seen(5) && !dedup.seen(6));
+        dedup.forget(&[5]);
+        assert!(!dedup.seen(5));
+        assert_eq!(dedup.stats,DedupStats { checked: 4, bloom_cleared: 2, duplicates: 1 });
+    }
+    #[test]
+    fn dense_ids_kept_small()
+    {
+        let mut dedup = TxDedup::new(&GlobalDedup { bloom_rate: None, expected: 0 });
+        (1..=20_000).chain([70_000, u32::MAX]).for_each(|id| dedup.insert(id));
+        assert_eq!(dedup.len(),20_002);
+        //a hash set would take at least 4 bytes an id
+        assert!(dedup.heap_bytes() < 20_000, "{} bytes", dedup.heap_bytes());
+        dedup.forget(&(1..=16_000).collect::<Vec<_>>());
+        assert_eq!(dedup.ids(),(16_001..=20_000).chain([70_000, u32::MAX]).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,127 @@
+use std::{collections::HashMap, io};
+use serde::Serialize;
+use crate::{Account, Engine};
+
+///
+/// An account whose balances changed, and the batch of changes it was emitted in
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AccountChange
+{
+    /// Goes up by one with every batch that has any changes, and is kept in snapshots
+    pub sequence: u64,
+    #[serde(flatten)]
+    pub account: Account,
+}
+
+///
+/// Emits only the accounts that changed since it last looked, so downstream
+/// consumers don't have to diff full reports
+///
+#[derive(Debug, Clone, Default)]
+pub struct ChangeFeed
+{
+    /// Each account as it was last emitted
+    last: HashMap<u16, Account>,
+}
+impl ChangeFeed
+{
+    /// Returns a feed that takes the accounts the engine has now as already emitted,
+    /// E.G. those restored from a snapshot
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine the changes are taken from
+    pub fn from_engine(engine: &mut Engine) -> ChangeFeed
+    {
+        engine.take_changed();
+        ChangeFeed { last: engine.clients.iter().map(|(id, c)| (id, c.acc.clone())).collect() }
+    }
+    /// Returns the accounts that changed since the last call, ordered by client
+    ///
+    /// A client touched by a transaction that left its balances alone isn't a change
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine the changes are taken from
+    pub fn next(&mut self, engine: &mut Engine) -> Vec<AccountChange>
+    {
+        let sequence = engine.change_sequence + 1;
+        let mut changes = Vec::new();
+        for id in engine.take_changed()
+        {
+            let account = match engine.clients.get(&id)
+            {
+                Some(c) => &c.acc,
+                None => {
+                    self.last.remove(&id);
+                    continue;
+                }
+            };
+            if self.last.get(&id) != Some(account)
+            {
+                self.last.insert(id, account.clone());
+                changes.push(AccountChange { sequence, account: account.clone() });
+            }
+        }
+        if !changes.is_empty()
+        {
+            engine.change_sequence = sequence;
+        }
+        changes
+    }
+}
+
+/// Writes the changes as JSON lines, one account to a line
+///
+/// # Arguments
+///
+/// * 'changes' - The changes as returned by ChangeFeed::next
+/// * 'out' - Where to write them
+pub fn write_changes<W: io::Write>(changes: &[AccountChange], mut out: W) -> io::Result<()>
+{
+    for change in changes
+    {
+        serde_json::to_writer(&mut out, change)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EnginePolicy, TxRecord, TypeTx};
+
+    #[test]
+    fn differential_output()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_record(TxRecord::new(TypeTx::Deposit, 1, 1, Some("2.0".to_string())));
+        engine.apply_record(TxRecord::new(TypeTx::Deposit, 2, 2, Some("3.0".to_string())));
+        let mut feed = ChangeFeed::default();
+        let changes = feed.next(&mut engine);
+        assert_eq!(changes.iter().map(|c| (c.sequence, c.account.client)).collect::<Vec<_>>(),vec![(1, 1), (1, 2)]);
+
+        engine.apply_record(TxRecord::new(TypeTx::Withdrawal, 2, 3, Some("1.0".to_string())));
+        engine.apply_record(TxRecord::new(TypeTx::Withdrawal, 1, 4, Some("9.0".to_string())));
+        let changes = feed.next(&mut engine);
+        assert_eq!(changes.len(),1);
+        assert_eq!((changes[0].sequence, changes[0].account.client, changes[0].account.total),(2, 2, Amount::from_minor(20000)));
+        assert!(feed.next(&mut engine).is_empty());
+        assert_eq!(engine.change_sequence,2);
+
+        let mut out = Vec::new();
+        write_changes(&changes, &mut out).unwrap();
+        let line = String::from_utf8(out).unwrap();
+        assert!(line.starts_with("{\"sequence\":2,\"client\":2,") && line.ends_with("}\n"));
+
+        let mut snapshot = Vec::new();
+        engine.snapshot_to(&mut snapshot, None).unwrap();
+        let mut restored = Engine::restore_from(snapshot.as_slice(), EnginePolicy::default(), None).unwrap();
+        let mut feed = ChangeFeed::from_engine(&mut restored);
+        restored.apply_record(TxRecord::new(TypeTx::Deposit, 1, 5, Some("1.0".to_string())));
+        let changes = feed.next(&mut restored);
+        assert_eq!(changes.iter().map(|c| (c.sequence, c.account.client)).collect::<Vec<_>>(),vec![(3, 1)]);
+    }
+}
@@ -0,0 +1,100 @@
+use std::{collections::{BTreeMap, HashSet}, io};
+use serde::Serialize;
+use crate::{Account, Dialect, Engine, HeaderError, ReferenceModel};
+
+///
+/// A client whose account came out differently in the engine and the reference model
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Divergence
+{
+    pub client: u16,
+    /// The row, counting from 1 after the header, and the transaction after which the
+    /// accounts first differed, None if they only came to differ outside the input, E.G.
+    /// through interest
+    pub first: Option<(u64, u32)>,
+    /// The account in the engine at the end, all zero if the client isn't there
+    pub engine: Account,
+    /// The account in the reference model at the end, all zero if the client isn't there
+    pub reference: Account,
+}
+
+/// Applies the input to the engine and to a reference model, comparing the client of
+/// every row after it, and the accounts of every client at the end
+///
+/// Returns the clients whose accounts differed at any point, ordered by client ID. The
+/// input is read with the default dialect and the schema mode of the engine policy,
+/// and the reference model parses amounts the way the policy rounds them
+///
+/// # Arguments
+///
+/// * 'input' - The transactions as csv
+/// * 'engine' - The engine to check, set up with whatever policy, hooks and rules are under test
+///
+/// # Errors
+///
+/// Fails if the header of the input can't be read
+pub fn compare<R: io::Read>(input: R, engine: &mut Engine) -> Result<Vec<Divergence>, HeaderError>
+{
+    let dialect = Dialect::default();
+    let records = dialect.read_records(input, engine.policy.schema)?;
+    let mut reference = ReferenceModel::new();
+    let mut first = BTreeMap::new();
+    for (row, record) in (1..).zip(records)
+    {
+        let (client, tx) = (record.client, record.tx);
+        if let Ok(parsed) = record.to_tx(engine.policy.rounding)
+        {
+            reference.apply(&parsed);
+        }
+        engine.apply_record(record);
+        if !first.contains_key(&client) && account(engine, client) != reference_account(&reference, client)
+        {
+            first.insert(client, Some((row, tx)));
+        }
+    }
+    let clients: HashSet<u16> = engine.clients.keys().chain(reference.accounts().iter().map(|a| a.client)).collect();
+    for client in clients
+    {
+        if account(engine, client) != reference_account(&reference, client)
+        {
+            first.entry(client).or_insert(None);
+        }
+    }
+    Ok(first.into_iter().map(|(client, first)| Divergence { client, first, engine: account(engine, client), reference: reference_account(&reference, client) }).collect())
+}
+
+fn account(engine: &Engine, client: u16) -> Account
+{
+    engine.clients.get(&client).map_or_else(|| Account::new(client), |c| c.acc.clone())
+}
+
+fn reference_account(reference: &ReferenceModel, client: u16) -> Account
+{
+    reference.account(client).cloned().unwrap_or_else(|| Account::new(client))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EnginePolicy, LockedAccount};
+
+    #[test]
+    fn differential_comparison()
+    {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,10.0\nwithdrawal,1,2,4.0\nwithdrawal,1,3,6.0\ndeposit,1,1,10.0\n\
+            deposit,2,4,5.0\ndispute,2,4,\nresolve,2,4,\ndispute,2,4,\nchargeback,2,4,\ndeposit,2,5,3.0\n\
+            deposit,3,6,1.0\ndispute,3,9,\nwithdrawal,3,7,-1.0\nresolve,3,6,\nchargeback,3,6,\n";
+        let mut engine = Engine::new(EnginePolicy::default());
+        assert_eq!(compare(input.as_bytes(), &mut engine).unwrap(),vec![]);
+        assert_eq!(engine.clients[&1].acc.available,Amount::from_minor(60000));
+
+        //a policy taking deposits into locked accounts parts ways at the deposit after the chargeback
+        let mut engine = Engine::new(EnginePolicy { locked_deposit: LockedAccount::AcceptNormally, ..EnginePolicy::default() });
+        let divergences = compare(input.as_bytes(), &mut engine).unwrap();
+        assert_eq!(divergences.len(),1);
+        assert_eq!((divergences[0].client, divergences[0].first),(2, Some((10, 5))));
+        assert_eq!((divergences[0].engine.total, divergences[0].reference.total),(Amount::from_minor(30000), Amount::ZERO));
+    }
+}
@@ -0,0 +1,327 @@
+use std::{collections::HashMap, io};
+use serde::{Deserialize, Serialize};
+use crate::{Amount, Engine};
+
+///
+/// Where a dispute stands
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisputeStatus
+{
+    #[serde(rename = "open")]
+    Open,
+    #[serde(rename = "resolved")]
+    Resolved,
+    #[serde(rename = "charged_back")]
+    ChargedBack,
+    /// The chargeback was contested, and waits for a second resolve or chargeback
+    #[serde(rename = "represented")]
+    Represented,
+}
+
+///
+/// A dispute being opened or closed, as the engine logs it
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisputeEvent
+{
+    pub client: u16,
+    pub tx: u32,
+    /// The amount of the disputed deposit
+    pub amount: Amount,
+    /// Open when the dispute was opened, Represented when its chargeback was contested, otherwise how it was closed
+    pub status: DisputeStatus,
+    /// The row it happened at, counting from 1
+    pub row: u64,
+    /// The timestamp of the row, if it had one
+    pub timestamp: Option<i64>,
+}
+
+///
+/// A dispute from when it was opened to when it was closed, if it has been
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisputeLifecycle
+{
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Amount,
+    pub status: DisputeStatus,
+    pub opened_row: u64,
+    pub closed_row: Option<u64>,
+    /// The row the chargeback was contested at, if it was
+    pub represented_row: Option<u64>,
+    /// How many rows it was open for, up to the latest row if it still is
+    pub rows_open: u64,
+    pub opened_at: Option<i64>,
+    pub closed_at: Option<i64>,
+    /// How long it was open for in timestamp units, if both ends had a timestamp
+    pub time_open: Option<i64>,
+}
+
+impl Engine
+{
+    /// Every dispute ever opened, in the order they were opened, with how and when each was closed
+    ///
+    /// A deposit disputed again after being resolved shows up once for each dispute. A
+    /// contested chargeback reopens its dispute as represented, until a second resolve
+    /// or chargeback closes it again
+    pub fn dispute_lifecycles(&self) -> Vec<DisputeLifecycle>
+    {
+        let mut lifecycles: Vec<DisputeLifecycle> = Vec::new();
+        let mut open: HashMap<(u16, u32), usize> = HashMap::new();
+        let mut charged_back: HashMap<(u16, u32), usize> = HashMap::new();
+        for event in &self.dispute_events
+        {
+            match event.status
+            {
+                DisputeStatus::Open => {
+                    open.insert((event.client, event.tx), lifecycles.len());
+                    lifecycles.push(DisputeLifecycle { client: event.client, tx: event.tx, amount: event.amount, status: DisputeStatus::Open, opened_row: event.row,
+                        closed_row: None, represented_row: None, rows_open: self.rows.saturating_sub(event.row), opened_at: event.timestamp, closed_at: None, time_open: None });
+                },
+                DisputeStatus::Represented => if let Some(i) = charged_back.remove(&(event.client, event.tx))
+                {
+                    open.insert((event.client, event.tx), i);
+                    if let Some(lifecycle) = lifecycles.get_mut(i)
+                    {
+                        lifecycle.status = DisputeStatus::Represented;
+                        lifecycle.closed_row = None;
+                        lifecycle.represented_row = Some(event.row);
+                        lifecycle.rows_open = self.rows.saturating_sub(lifecycle.opened_row);
+                        lifecycle.closed_at = None;
+                        lifecycle.time_open = None;
+                    }
+                },
+                status => if let Some(i) = open.remove(&(event.client, event.tx))
+                {
+                    if status == DisputeStatus::ChargedBack
+                    {
+                        charged_back.insert((event.client, event.tx), i);
+                    }
+                    let lifecycle = &mut lifecycles[i];
+                    lifecycle.status = status;
+                    lifecycle.closed_row = Some(event.row);
+                    lifecycle.rows_open = event.row.saturating_sub(lifecycle.opened_row);
+                    lifecycle.closed_at = event.timestamp;
+                    lifecycle.time_open = lifecycle.opened_at.zip(event.timestamp).map(|(opened, closed)| closed.saturating_sub(opened));
+                }
+            }
+        }
+        lifecycles
+    }
+}
+
+/// How many seconds there are in a day, the unit disputes are aged in
+const DAY: i64 = 86_400;
+
+///
+/// How long funds have been held for an open dispute
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum AgeBucket
+{
+    #[serde(rename = "0-7d")]
+    UpTo7Days,
+    #[serde(rename = "7-30d")]
+    UpTo30Days,
+    #[serde(rename = "30d+")]
+    Over30Days,
+    /// The dispute was opened by a row without a timestamp
+    #[serde(rename = "undated")]
+    Undated,
+}
+impl AgeBucket
+{
+    /// The bucket funds held for this long fall in
+    ///
+    /// # Arguments
+    ///
+    /// * 'days' - How many whole days they have been held, None if it isn't known
+    pub fn of(days: Option<i64>) -> AgeBucket
+    {
+        match days
+        {
+            None => AgeBucket::Undated,
+            Some(d) if d < 7 => AgeBucket::UpTo7Days,
+            Some(d) if d < 30 => AgeBucket::UpTo30Days,
+            Some(_) => AgeBucket::Over30Days,
+        }
+    }
+}
+
+///
+/// The funds held for a single open dispute
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HeldDispute
+{
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Amount,
+    pub opened_at: Option<i64>,
+    /// Whole days from when it was opened to the ageing date
+    pub age_days: Option<i64>,
+    pub bucket: AgeBucket,
+}
+
+///
+/// The funds held in one age bucket
+///
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct BucketTotal
+{
+    pub bucket: AgeBucket,
+    pub disputes: u64,
+    pub held: Amount,
+}
+
+///
+/// How long the funds of every open dispute have been held for, as of a date
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HeldAgeing
+{
+    /// The date the disputes were aged to, in seconds since the unix epoch
+    pub as_of: i64,
+    /// The open disputes, oldest first and undated ones last
+    pub disputes: Vec<HeldDispute>,
+    /// The funds held in each bucket, youngest first, every bucket included
+    pub buckets: Vec<BucketTotal>,
+}
+
+impl Engine
+{
+    /// Ages the funds held for every open or represented dispute from when it was opened
+    ///
+    /// # Arguments
+    ///
+    /// * 'as_of' - The date to age them to, in seconds since the unix epoch
+    pub fn held_ageing(&self, as_of: i64) -> HeldAgeing
+    {
+        let mut disputes: Vec<HeldDispute> = self.dispute_lifecycles().into_iter()
+            .filter(|d| matches!(d.status, DisputeStatus::Open | DisputeStatus::Represented))
+            .map(|d| {
+                //a dispute dated after the ageing date counts as opened that day
+                let age_days = d.opened_at.map(|opened| as_of.saturating_sub(opened).max(0) / DAY);
+                HeldDispute { client: d.client, tx: d.tx, amount: d.amount, opened_at: d.opened_at, age_days, bucket: AgeBucket::of(age_days) }
+            })
+            .collect();
+        disputes.sort_by_key(|d| (d.opened_at.is_none(), d.opened_at, d.client, d.tx));
+        let buckets = [AgeBucket::UpTo7Days, AgeBucket::UpTo30Days, AgeBucket::Over30Days, AgeBucket::Undated].iter().map(|&bucket| {
+            let held = disputes.iter().filter(|d| d.bucket == bucket);
+            BucketTotal { bucket, disputes: held.clone().count() as u64, held: held.fold(Amount::ZERO, |sum, d| sum.checked_add(d.amount).unwrap_or(Amount::MAX)) }
+        }).collect();
+        HeldAgeing { as_of, disputes, buckets }
+    }
+}
+
+/// Writes the funds held for each open dispute as csv, with the bucket of each
+///
+/// # Arguments
+///
+/// * 'disputes' - The open disputes, E.G. from Engine::held_ageing
+/// * 'out' - Where to write the report to
+pub fn write_held_ageing<W: io::Write>(disputes: &[HeldDispute], out: W) -> csv::Result<()>
+{
+    let mut wtr = csv::Writer::from_writer(out);
+    for dispute in disputes
+    {
+        wtr.serialize(dispute)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// Writes the dispute lifecycles as csv
+///
+/// # Arguments
+///
+/// * 'lifecycles' - The disputes, E.G. from Engine::dispute_lifecycles
+/// * 'out' - Where to write the report to
+pub fn write_dispute_lifecycles<W: io::Write>(lifecycles: &[DisputeLifecycle], out: W) -> csv::Result<()>
+{
+    let mut wtr = csv::Writer::from_writer(out);
+    for lifecycle in lifecycles
+    {
+        wtr.serialize(lifecycle)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    fn at(mut tx: Tx, timestamp: i64) -> Tx
+    {
+        tx.timestamp = Some(timestamp);
+        tx
+    }
+
+    #[test]
+    fn dispute_lifecycle_report()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(at(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))), 100));
+        engine.apply(at(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(20000))), 110));
+        engine.apply(at(Tx::new(TypeTx::Dispute, 1, 1, None), 200));
+        engine.apply(at(Tx::new(TypeTx::Dispute, 2, 2, None), 210));
+        engine.apply(at(Tx::new(TypeTx::Dispute, 2, 2, None), 220));
+        engine.apply(at(Tx::new(TypeTx::Resolve, 1, 1, None), 500));
+        engine.apply(at(Tx::new(TypeTx::Dispute, 1, 1, None), 600));
+        engine.apply(Tx::new(TypeTx::Chargeback, 2, 2, None));
+        engine.apply(Tx::new(TypeTx::Deposit, 3, 3, Some(Amount::from_minor(10000))));
+
+        let lifecycles = engine.dispute_lifecycles();
+        let summary: Vec<_> = lifecycles.iter()
+            .map(|d| (d.client, d.status, d.opened_row, d.closed_row, d.rows_open, d.time_open)).collect();
+        assert_eq!(summary,vec![
+            (1, DisputeStatus::Resolved, 3, Some(6), 3, Some(300)),
+            (2, DisputeStatus::ChargedBack, 4, Some(8), 4, None),
+            (1, DisputeStatus::Open, 7, None, 2, None),
+        ]);
+
+        let mut out = Vec::new();
+        write_dispute_lifecycles(&lifecycles[..1], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),"client,tx,amount,status,opened_row,closed_row,represented_row,rows_open,opened_at,closed_at,time_open\n1,1,5.0,resolved,3,6,,3,200,500,300\n");
+
+        //rolling back takes the disputes opened since with it
+        let savepoint = engine.savepoint();
+        engine.apply(Tx::new(TypeTx::Dispute, 3, 3, None));
+        assert_eq!(engine.dispute_lifecycles().len(),4);
+        engine.rollback_to(&savepoint);
+        assert_eq!(engine.dispute_lifecycles().len(),3);
+    }
+
+    #[test]
+    fn held_ageing_report()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        for (client, opened) in [(1, 0), (2, 10 * DAY), (3, 35 * DAY), (4, 36 * DAY)]
+        {
+            engine.apply(at(Tx::new(TypeTx::Deposit, client, client.into(), Some(Amount::from_minor(10000 * i64::from(client)))), opened));
+            engine.apply(at(Tx::new(TypeTx::Dispute, client, client.into(), None), opened));
+        }
+        engine.apply(Tx::new(TypeTx::Deposit, 5, 5, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 5, 5, None));
+        engine.apply(Tx::new(TypeTx::Resolve, 4, 4, None));
+
+        let ageing = engine.held_ageing(40 * DAY);
+        let disputes: Vec<(u16, Option<i64>, AgeBucket)> = ageing.disputes.iter().map(|d| (d.client, d.age_days, d.bucket)).collect();
+        assert_eq!(disputes,vec![(1, Some(40), AgeBucket::Over30Days), (2, Some(30), AgeBucket::Over30Days), (3, Some(5), AgeBucket::UpTo7Days), (5, None, AgeBucket::Undated)]);
+        let totals: Vec<(AgeBucket, u64, Amount)> = ageing.buckets.iter().map(|b| (b.bucket, b.disputes, b.held)).collect();
+        assert_eq!(totals,vec![
+            (AgeBucket::UpTo7Days, 1, Amount::from_minor(30000)),
+            (AgeBucket::UpTo30Days, 0, Amount::ZERO),
+            (AgeBucket::Over30Days, 2, Amount::from_minor(30000)),
+            (AgeBucket::Undated, 1, Amount::from_minor(50000)),
+        ]);
+
+        let mut out = Vec::new();
+        write_held_ageing(&ageing.disputes[2..3], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),"client,tx,amount,opened_at,age_days,bucket\n3,3,3.0,3024000,5,0-7d\n");
+    }
+}
@@ -0,0 +1,282 @@
+use std::{fmt, io, str::FromStr, sync::{Arc, Mutex}, time::Duration};
+use lettre::{message::{header::ContentType, Mailbox}, transport::smtp::{authentication::Credentials, client::{Tls, TlsParameters}, Error as SmtpError}, Message, SmtpTransport, Transport};
+use crate::{Notification, Notifier};
+
+///
+/// Why the mail couldn't be set up
+///
+#[derive(Debug)]
+pub enum EmailError
+{
+    /// There is no address to mail to
+    NoRecipient,
+    /// The address isn't a valid email address
+    Address(String),
+    /// The relay isn't given as host or host:port
+    Server(String),
+    /// The TLS connection to the relay couldn't be set up
+    Tls(SmtpError),
+}
+impl fmt::Display for EmailError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            EmailError::NoRecipient => write!(f, "no address to mail to"),
+            EmailError::Address(address) => write!(f, "invalid email address '{}'", address),
+            EmailError::Server(server) => write!(f, "invalid smtp server '{}', expected host or host:port", server),
+            EmailError::Tls(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for EmailError {}
+impl From<EmailError> for io::Error
+{
+    fn from(e: EmailError) -> Self {
+        io::Error::other(e)
+    }
+}
+
+///
+/// How the connection to the relay is secured
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SmtpTls
+{
+    /// Plain SMTP, for a relay on the local network
+    None,
+    /// Upgraded with STARTTLS when the relay offers it, plain otherwise
+    #[default]
+    Opportunistic,
+    /// Upgraded with STARTTLS, failing if the relay doesn't offer it
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+}
+impl FromStr for SmtpTls
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "none" => Ok(SmtpTls::None),
+            "opportunistic" => Ok(SmtpTls::Opportunistic),
+            "starttls" => Ok(SmtpTls::StartTls),
+            "tls" => Ok(SmtpTls::Tls),
+            _ => Err(format!("unknown smtp tls mode '{}', expected none, opportunistic, starttls or tls", s))
+        }
+    }
+}
+
+///
+/// The SMTP relay and addresses the end-of-run summary is mailed with
+///
+#[derive(Debug, Clone)]
+pub struct EmailConfig
+{
+    /// The relay, as host:port, E.G. "localhost:25", the port defaulting to 465 for
+    /// SmtpTls::Tls and 25 otherwise
+    pub server: String,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: String,
+    /// How the connection to the relay is secured
+    pub tls: SmtpTls,
+    /// The user name and password the relay is logged in to with, if it wants them
+    pub credentials: Option<(String, String)>,
+    /// How long the relay may take to answer a single command
+    pub timeout: Duration,
+}
+impl EmailConfig
+{
+    /// Returns the default config mailing the recipients through the relay, upgraded
+    /// to TLS when it offers it and without logging in
+    ///
+    /// # Arguments
+    ///
+    /// * 'server' - The relay, as host:port
+    /// * 'from' - The address the mail is sent from
+    /// * 'to' - The addresses the mail is sent to
+    pub fn new(server: String, from: String, to: Vec<String>) -> EmailConfig
+    {
+        EmailConfig { server, from, to, subject: "Transaction run completed".to_string(), tls: SmtpTls::default(), credentials: None, timeout: Duration::from_secs(10) }
+    }
+}
+
+///
+/// How the mailing went, shared with whoever set the notifier up, as the engine keeps
+/// the notifier itself
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmailStats
+{
+    /// How many summaries the relay refused or couldn't be reached for
+    pub failed: usize,
+    /// Why the last of them wasn't sent
+    pub last_error: Option<String>,
+}
+
+///
+/// Mails the end-of-run summary through an SMTP relay, every other notification is left out
+///
+/// The mail is sent as the summary comes in, as it is only sent once, at the end of the
+/// run; a relay that refuses it or can't be reached is counted in stats, along with why
+///
+pub struct EmailNotifier
+{
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    subject: String,
+    /// How the mailing went, clone it before handing the notifier to the engine
+    pub stats: Arc<Mutex<EmailStats>>,
+}
+impl EmailNotifier
+{
+    /// Checks the addresses and the relay
+    ///
+    /// # Arguments
+    ///
+    /// * 'config' - Where and who to mail
+    pub fn new(config: EmailConfig) -> Result<EmailNotifier, EmailError>
+    {
+        if config.to.is_empty() {return Err(EmailError::NoRecipient)}
+        let mailbox = |address: &String| address.parse::<Mailbox>().map_err(|_| EmailError::Address(address.clone()));
+        let from = mailbox(&config.from)?;
+        let to = config.to.iter().map(mailbox).collect::<Result<Vec<Mailbox>, EmailError>>()?;
+        let (host, port) = match config.server.rsplit_once(':')
+        {
+            Some((host, port)) => (host, port.parse().map_err(|_| EmailError::Server(config.server.clone()))?),
+            None => (config.server.as_str(), if config.tls == SmtpTls::Tls {465} else {25})
+        };
+        if host.is_empty() {return Err(EmailError::Server(config.server.clone()))}
+        let parameters = || TlsParameters::new(host.to_string()).map_err(EmailError::Tls);
+        let tls = match config.tls
+        {
+            SmtpTls::None => Tls::None,
+            SmtpTls::Opportunistic => Tls::Opportunistic(parameters()?),
+            SmtpTls::StartTls => Tls::Required(parameters()?),
+            SmtpTls::Tls => Tls::Wrapper(parameters()?),
+        };
+        let mut transport = SmtpTransport::builder_dangerous(host).port(port).tls(tls).timeout(Some(config.timeout));
+        if let Some((user, password)) = config.credentials
+        {
+            transport = transport.credentials(Credentials::new(user, password));
+        }
+        Ok(EmailNotifier { transport: transport.build(), from, to, subject: config.subject, stats: Arc::default() })
+    }
+    /// Mails the text to every recipient in a single message
+    ///
+    /// # Arguments
+    ///
+    /// * 'text' - The body of the mail
+    fn send(&self, text: &str) -> Result<(), String>
+    {
+        let message = self.to.iter().cloned().fold(Message::builder().from(self.from.clone()), |message, to| message.to(to))
+            .subject(self.subject.as_str())
+            .message_id(None)
+            .header(ContentType::TEXT_PLAIN)
+            .body(text.to_string())
+            .map_err(|e| e.to_string())?;
+        self.transport.send(&message).map(|_| ()).map_err(|e| e.to_string())
+    }
+}
+impl Notifier for EmailNotifier
+{
+    fn notify(&mut self, notification: &Notification) {
+        if let Notification::RunCompleted { .. } = notification
+        {
+            if let (Err(e), Ok(mut stats)) = (self.send(&notification.to_string()), self.stats.lock())
+            {
+                stats.failed += 1;
+                stats.last_error = Some(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::{BufRead, BufReader, Write}, net::TcpListener, thread};
+    use crate::{Amount, Engine, EnginePolicy, Tx, TypeTx};
+
+    /// Starts a relay on a free port that answers every command with 250, or with the
+    /// reply given for a command starting with it, and returns its address and the lines
+    /// it received
+    fn relay(refuse: Option<(&'static str, &'static str)>) -> (String, thread::JoinHandle<Vec<String>>)
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap().to_string();
+        let relay = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            write!(stream, "220-relay\r\n220 ready\r\n").unwrap();
+            let mut received = Vec::new();
+            let mut data = false;
+            loop
+            {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 {break}
+                let line = line.trim_end().to_string();
+                let reply = match line.as_str()
+                {
+                    _ if data && line == "." => {data = false; "250 queued"},
+                    _ if data => "",
+                    _ if refuse.is_some_and(|(command, _)| line.starts_with(command)) => refuse.unwrap().1,
+                    "DATA" => {data = true; "354 go on"},
+                    "QUIT" => "221 bye",
+                    _ => "250 ok"
+                };
+                received.push(line);
+                if !reply.is_empty() && write!(stream, "{}\r\n", reply).is_err() {break}
+                if reply.starts_with("221") {break}
+            }
+            received
+        });
+        (server, relay)
+    }
+    fn config(server: String) -> EmailConfig
+    {
+        EmailConfig { tls: SmtpTls::None, ..EmailConfig::new(server, "engine@example.com".to_string(), vec!["ops@example.com".to_string()]) }
+    }
+
+    #[test]
+    fn run_summary_mailed()
+    {
+        let (server, relay) = relay(None);
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.add_notifier(Box::new(EmailNotifier::new(config(server)).unwrap()));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(10000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 2, None));
+        engine.finish_run();
+
+        let received = relay.join().unwrap();
+        assert!(received[0].starts_with("EHLO "));
+        assert_eq!(received[1..4],["MAIL FROM:<engine@example.com>", "RCPT TO:<ops@example.com>", "DATA"]);
+        assert!(received.contains(&"Subject: Transaction run completed".to_string()));
+        assert!(received.iter().any(|l| l.starts_with("Date: ")) && received.iter().any(|l| l.starts_with("Message-ID: <")));
+        assert!(received.contains(&"run completed: 2 rows processed, 1 rejected, 0 locked accounts".to_string()));
+        assert_eq!(received[received.len() - 2..],[".", "QUIT"]);
+    }
+    #[test]
+    fn refused_mail_counted()
+    {
+        let (server, relay) = relay(Some(("RCPT", "550 no such user")));
+        let mut notifier = EmailNotifier::new(config(server)).unwrap();
+        notifier.notify(&Notification::RunCompleted { rows: 1, rejected: 0, locked: Vec::new() });
+        relay.join().unwrap();
+        let stats = notifier.stats.lock().unwrap();
+        assert_eq!(stats.failed,1);
+        assert!(stats.last_error.as_deref().is_some_and(|e| e.contains("no such user")), "{:?}", stats.last_error);
+    }
+    #[test]
+    fn invalid_config_rejected()
+    {
+        let invalid = |server: &str, to: &str| EmailNotifier::new(EmailConfig::new(server.to_string(), "engine@example.com".to_string(), vec![to.to_string()])).err();
+        assert!(matches!(invalid("localhost:25", "ops\r\n@x"),Some(EmailError::Address(_))));
+        assert!(matches!(invalid("localhost:smtp", "ops@example.com"),Some(EmailError::Server(_))));
+        assert!(matches!(EmailNotifier::new(EmailConfig::new("localhost".to_string(), "engine@example.com".to_string(), Vec::new())).err(),Some(EmailError::NoRecipient)));
+        assert_eq!(("starttls".parse(), "tls".parse()),(Ok(SmtpTls::StartTls), Ok(SmtpTls::Tls)));
+    }
+}
@@ -0,0 +1,2347 @@
+use std::{collections::{BTreeMap, HashMap, VecDeque}, fmt, io, time::{Duration, Instant}};
+use serde::{Deserialize, Serialize};
+use crate::schedule::ScheduleState;
+use crate::script::FEE_MEMO;
+use crate::undo_log::UndoLog;
+use crate::{Account, Amount, AmountError, BookAccount, DedupStats, Client, ClientMap, ClientMetadata, ClientSet, ClientStore, CustomTxHandler, DisputeEvent, DisputeStatus, EnginePolicy, IngestedFile, InterestPolicy, NoScreening, Notification, Notifier, BalanceChange, PolicyHook, Posted, RejectedTotal, RejectionListener, RejectionStats, RuleFlag, RuleSet, Schedule, Screening, ScreeningProvider, SignatureVerifier, SlowCause, SlowRow, TimestampError, Tx, TxDedup, TrialBalance, Journal, Period, TypeTotal, TxError, TxRecord, TypeTx, UnexpectedAmount, WalMark};
+
+/// The memo of adjustments made without one
+pub const ADJUSTMENT_MEMO: &str = "adjustment";
+
+/// The lowest of the transaction IDs kept for the transactions the engine makes itself,
+/// adjustments, fees, interest and recurring transactions, which count down from u32::MAX;
+/// deposits and withdrawals of the input under these IDs are rejected as reserved_tx
+pub const SYNTHETIC_TX_MIN: u32 = 0xF000_0000;
+
+/// The most interest periods credited at once when a timestamp jumps ahead, the periods
+/// before them are skipped
+pub const MAX_INTEREST_PERIODS: i64 = 1000;
+
+///
+/// Why a transaction was refused before it reached the client
+///
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Hash)]
+pub enum RejectReason
+{
+    /// The amount couldn't be read as a number
+    #[serde(rename = "invalid_amount")]
+    InvalidAmount,
+    /// More than four decimals, and the policy is set to reject them
+    #[serde(rename = "precision")]
+    Precision,
+    /// The amount was NaN or infinite
+    #[serde(rename = "non_finite")]
+    NonFinite,
+    /// The amount was below the policy minimum
+    #[serde(rename = "below_minimum")]
+    BelowMinimum,
+    /// The amount was above the policy maximum
+    #[serde(rename = "above_maximum")]
+    AboveMaximum,
+    /// The amount, or the balances it would lead to, can't be represented
+    #[serde(rename = "overflow")]
+    Overflow,
+    /// A deposit or withdrawal without an amount
+    #[serde(rename = "missing_amount")]
+    MissingAmount,
+    /// A dispute, resolve or chargeback with an amount, and the policy is set to reject them
+    #[serde(rename = "unexpected_amount")]
+    UnexpectedAmount,
+    /// The timestamp couldn't be read
+    #[serde(rename = "invalid_timestamp")]
+    InvalidTimestamp,
+    /// A withdrawal from an account frozen for its chargebacks
+    #[serde(rename = "account_frozen")]
+    AccountFrozen,
+    /// The currency isn't the base currency of the client
+    #[serde(rename = "currency_mismatch")]
+    CurrencyMismatch,
+    /// A deposit or withdrawal larger than the tier of the client allows
+    #[serde(rename = "above_tier_limit")]
+    AboveTierLimit,
+    /// A dispute while the tier of the client allows no more open ones
+    #[serde(rename = "too_many_disputes")]
+    TooManyDisputes,
+    /// A dispute of a transaction older than the dispute window of the client allows
+    #[serde(rename = "dispute_window_expired")]
+    DisputeWindowExpired,
+    /// A withdrawal from an account held for compliance review
+    #[serde(rename = "under_review")]
+    UnderReview,
+    /// The engine checks signatures, and the transaction has none or one that doesn't verify
+    #[serde(rename = "invalid_signature")]
+    InvalidSignature,
+    /// The ingestion queue was full, and set to shed what didn't fit
+    #[serde(rename = "queue_full")]
+    QueueFull,
+    /// A type the engine doesn't know, and no custom handler is registered for
+    #[serde(rename = "unknown_type")]
+    UnknownType,
+    /// The policy hook refused it
+    #[serde(rename = "refused_by_policy")]
+    RefusedByPolicy,
+    /// A declarative rule refused it, named in the rule column
+    #[serde(rename = "rule_rejected")]
+    RuleRejected,
+    /// A deposit or withdrawal reusing the transaction ID of any client, with global dedup on
+    #[serde(rename = "duplicate_tx")]
+    DuplicateTx,
+    /// A transaction for a client merged into another
+    #[serde(rename = "account_closed")]
+    AccountClosed,
+    /// A deposit or withdrawal under a transaction ID kept for the engine's own, see SYNTHETIC_TX_MIN
+    #[serde(rename = "reserved_tx")]
+    ReservedTx,
+    /// A withdrawal from a locked account, with locked deposits taken to held
+    #[serde(rename = "account_locked")]
+    AccountLocked,
+}
+impl From<AmountError> for RejectReason
+{
+    fn from(e: AmountError) -> Self {
+        match e
+        {
+            AmountError::Invalid => RejectReason::InvalidAmount,
+            AmountError::NonFinite => RejectReason::NonFinite,
+            AmountError::Precision => RejectReason::Precision,
+            AmountError::Overflow => RejectReason::Overflow,
+        }
+    }
+}
+impl From<TxError> for RejectReason
+{
+    fn from(e: TxError) -> Self {
+        match e
+        {
+            TxError::Overflow => RejectReason::Overflow,
+            TxError::MissingAmount => RejectReason::MissingAmount,
+            TxError::AboveTierLimit => RejectReason::AboveTierLimit,
+            TxError::TooManyDisputes => RejectReason::TooManyDisputes,
+            TxError::DisputeWindowExpired => RejectReason::DisputeWindowExpired,
+            TxError::AccountLocked => RejectReason::AccountLocked,
+        }
+    }
+}
+impl fmt::Display for RejectReason
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+///
+/// A single entry in the rejection report
+///
+#[derive(Debug, Serialize)]
+pub struct Rejection
+{
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: TypeTx,
+    /// The amount as it was given in the input
+    pub amount: Option<String>,
+    pub reason: RejectReason,
+    /// The ID of the rule that refused it, for rule_rejected
+    pub rule: Option<String>,
+}
+
+///
+/// An account held for compliance review
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReviewEntry
+{
+    pub client: u16,
+    /// The transaction ID of the hold
+    pub tx: u32,
+    /// Why the account was held, from the memo of the hold
+    pub reason: Option<String>,
+}
+
+///
+/// What happened to a single transaction of a batch
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchOutcome
+{
+    Applied,
+    Rejected(RejectReason),
+}
+
+///
+/// The outcome of every transaction of a batch, in the order they were given
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchReport
+{
+    pub outcomes: Vec<BatchOutcome>,
+}
+impl BatchReport
+{
+    /// The rejected transactions, as their position in the batch and the reason
+    pub fn rejected(&self) -> impl Iterator<Item = (usize, RejectReason)> + '_
+    {
+        self.outcomes.iter().enumerate().filter_map(|(row, outcome)| match outcome
+        {
+            BatchOutcome::Rejected(reason) => Some((row, *reason)),
+            BatchOutcome::Applied => None
+        })
+    }
+}
+
+///
+/// A batch that was rolled back, as at least one of its transactions was rejected
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchError
+{
+    /// What would have happened to each transaction
+    pub report: BatchReport,
+}
+impl fmt::Display for BatchError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rejected: Vec<String> = self.report.rejected().map(|(row, reason)| format!("row {}: {}", row, reason)).collect();
+        write!(f, "batch rolled back, {} of {} rejected ({})", rejected.len(), self.report.outcomes.len(), rejected.join(", "))
+    }
+}
+impl std::error::Error for BatchError {}
+
+///
+/// Columns handed to Engine::apply_columns that aren't all the same length
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColumnLengths
+{
+    pub types: usize,
+    pub clients: usize,
+    pub txs: usize,
+    pub amounts: usize,
+}
+impl fmt::Display for ColumnLengths
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "columns of different lengths: {} types, {} clients, {} txs, {} amounts", self.types, self.clients, self.txs, self.amounts)
+    }
+}
+impl std::error::Error for ColumnLengths {}
+
+///
+/// A point the engine can be rolled back to, from Engine::savepoint
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Savepoint
+{
+    /// Where it is in the stack of open savepoints
+    depth: usize,
+    /// Tells it apart from a later savepoint at the same depth
+    id: u64,
+}
+
+/// What the engine looked like at a savepoint
+///
+/// Nothing is copied when the savepoint is made: the clients and the entries of the
+/// engine's maps are copied on write, the first time each is changed after it, and
+/// the lists that only grow are cut back to their length
+pub(crate) struct SavedState
+{
+    id: u64,
+    /// The clients changed since the savepoint as they were, None if they didn't exist
+    clients: ClientMap<Option<Client>>,
+    rejections: usize,
+    rejected_reasons: UndoLog<RejectReason, u64>,
+    rejected_types: UndoLog<String, RejectedTotal>,
+    skipped: usize,
+    audit: usize,
+    flags: usize,
+    dispute_events: usize,
+    /// How many balance changes the outbox held
+    pub(crate) outbox: usize,
+    pub(crate) books: UndoLog<BookAccount, Posted>,
+    /// How many postings the journal had
+    journal: usize,
+    pub(crate) period_totals: UndoLog<String, TypeTotal>,
+    rule_hits: UndoLog<String, u64>,
+    /// The reserve balance and the length of its ledger
+    reserve: Option<(Amount, usize)>,
+    counterparties: UndoLog<String, CounterpartyStats>,
+    pub(crate) review_queue: UndoLog<u16, ReviewEntry>,
+    pub(crate) deposit_windows: UndoLog<u16, VecDeque<(u32, bool)>>,
+    /// The clients screened for the first time since the savepoint
+    screened: Vec<u16>,
+    interest_due: Option<i64>,
+    interest_periods_skipped: i64,
+    next_synthetic_tx: u32,
+    /// How many recurring transactions there were, and those that advanced as they were
+    schedules: (usize, UndoLog<usize, ScheduleState>),
+    pub(crate) settlements: UndoLog<i64, Vec<(u16, u32)>>,
+    /// The transaction IDs marked used since the savepoint
+    used_txs: Vec<u32>,
+    /// How many rows had been applied, as the dispute windows count rows
+    rows: u64,
+    slow_rows: usize,
+    timestamp_errors: usize,
+    /// The clients marked changed since the savepoint that weren't already
+    changed: Vec<u16>,
+    /// The clients marked changed at the savepoint, once the change feed took them
+    taken: Option<ClientSet>,
+    change_sequence: u64,
+}
+
+///
+/// A single chargeback debited from the reserve
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ReserveEntry
+{
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Amount,
+    /// The balance of the reserve once the chargeback is debited
+    pub balance: Amount,
+}
+
+///
+/// The account chargebacks are paid out of, for those covering the liability themselves
+///
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Reserve
+{
+    /// What is left in the reserve, which may go negative
+    pub balance: Amount,
+    /// Every chargeback debited, and representment credited, in the order they came in
+    pub ledger: Vec<ReserveEntry>,
+}
+impl Reserve
+{
+    /// Debits a chargeback, keeping the balance at the lowest amount if it would go past it
+    fn debit(&mut self, client: u16, tx: u32, amount: Amount)
+    {
+        self.balance = self.balance.checked_sub(amount).unwrap_or(Amount::MIN);
+        self.ledger.push(ReserveEntry { client, tx, amount, balance: self.balance });
+    }
+    /// Credits back a chargeback being contested, kept in the ledger as a negative debit
+    fn credit(&mut self, client: u16, tx: u32, amount: Amount)
+    {
+        self.balance = self.balance.checked_add(amount).unwrap_or(Amount::MAX);
+        self.ledger.push(ReserveEntry { client, tx, amount: Amount::ZERO.checked_sub(amount).unwrap_or(Amount::MIN), balance: self.balance });
+    }
+}
+
+///
+/// The deposits, withdrawals and chargebacks put down to a single counterparty
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CounterpartyStats
+{
+    pub deposits: usize,
+    pub withdrawals: usize,
+    /// What the deposits and withdrawals add up to
+    pub volume: Amount,
+    pub chargebacks: usize,
+    /// What the chargebacks add up to
+    pub charged_back: Amount,
+}
+impl CounterpartyStats
+{
+    /// Adds an applied transaction, keeping the sums at the largest amount if they would go past it
+    fn record(&mut self, r#type: TypeTx, amount: Amount)
+    {
+        match r#type
+        {
+            TypeTx::Deposit => self.deposits += 1,
+            TypeTx::Withdrawal => self.withdrawals += 1,
+            TypeTx::Chargeback => {
+                self.chargebacks += 1;
+                self.charged_back = self.charged_back.checked_add(amount).unwrap_or(Amount::MAX);
+                return;
+            },
+            _ => return
+        }
+        self.volume = self.volume.checked_add(amount).unwrap_or(Amount::MAX);
+    }
+    /// The share of deposits that were charged back, 0 if there were none
+    pub fn chargeback_rate(&self) -> f64
+    {
+        if self.deposits == 0 {0.0} else {self.chargebacks as f64 / self.deposits as f64}
+    }
+}
+
+///
+/// Holds every client seen so far and applies transactions to them in order
+///
+pub struct Engine
+{
+    /// The clients that have been processed, keyed by client ID
+    pub clients: ClientStore,
+    /// The rules used when validating transactions
+    pub policy: EnginePolicy,
+    /// Transactions that were refused, in the order they came in
+    pub rejections: Vec<Rejection>,
+    /// The refused transactions counted by reason and by type
+    pub rejection_stats: RejectionStats,
+    /// Transactions left alone as their client is outside the client filter, or
+    /// their type is excluded
+    pub skipped: usize,
+    /// Which clients are processed, all of them if None
+    client_filter: Option<Box<dyn Fn(u16) -> bool + Send>>,
+    /// The types of transaction left alone, for what-if runs
+    excluded_types: Vec<TypeTx>,
+    /// Told about chargebacks and locked accounts as they happen
+    notifiers: Vec<Box<dyn Notifier>>,
+    /// Told about every rejection as it happens
+    rejection_listeners: Vec<Box<dyn RejectionListener>>,
+    /// The chargeback reserve, if the policy keeps one
+    pub reserve: Option<Reserve>,
+    /// What was done with each counterparty, keyed by its name
+    pub counterparties: HashMap<String, CounterpartyStats>,
+    /// Every notification sent so far, in order, for the audit trail
+    pub audit: Vec<Notification>,
+    /// What is known about each client from the metadata registry
+    pub metadata: HashMap<u16, ClientMetadata>,
+    /// The accounts awaiting manual review, ordered by client
+    pub review_queue: BTreeMap<u16, ReviewEntry>,
+    /// The latest deposits of each client and whether they were charged back,
+    /// kept only if the policy freezes accounts
+    pub(crate) deposit_windows: ClientMap<VecDeque<(u32, bool)>>,
+    /// Screens clients against sanctions and KYC checks
+    screening: Box<dyn ScreeningProvider>,
+    /// The clients screened on their first deposit
+    screened: ClientSet,
+    /// Checks the signature of every transaction, if they need to be signed
+    verifier: Option<Box<dyn SignatureVerifier>>,
+    /// When interest is next credited, once a timestamp has been seen
+    interest_due: Option<i64>,
+    /// How many interest periods went uncredited, as more than MAX_INTEREST_PERIODS
+    /// passed between two transactions
+    pub interest_periods_skipped: i64,
+    /// The transaction ID the next interest credit or recurring transaction is made
+    /// under, counting down from u32::MAX
+    next_synthetic_tx: u32,
+    /// The recurring transactions, and how far each has got
+    schedules: Vec<ScheduleState>,
+    /// The deposits waiting to settle, as client and transaction ID, keyed by when they settle
+    pub(crate) settlements: BTreeMap<i64, Vec<(u16, u32)>>,
+    /// The open savepoints, oldest first
+    pub(crate) savepoints: Vec<SavedState>,
+    /// The ID the next savepoint gets
+    next_savepoint: u64,
+    /// The clients touched since the change feed last looked
+    changed: ClientSet,
+    /// The sequence number of the latest batch of changes emitted
+    pub change_sequence: u64,
+    /// The input files processed in full, so none is processed twice
+    pub ingested: Vec<IngestedFile>,
+    /// What applies the custom transaction types, keyed by their name
+    pub(crate) custom_handlers: HashMap<String, Box<dyn CustomTxHandler>>,
+    /// Decides the policy points a deployment scripts itself
+    policy_hook: Option<Box<dyn PolicyHook>>,
+    /// Set while a fee the policy hook asked for is charged, so it isn't put to the hook again
+    charging_fee: bool,
+    /// The declarative rules every transaction is checked against
+    pub(crate) rules: Option<RuleSet>,
+    /// How many transactions each rule matched, keyed by rule ID
+    pub rule_hits: BTreeMap<String, u64>,
+    /// The transactions rules flagged, in the order they came in
+    pub flags: Vec<RuleFlag>,
+    /// How long a row may take to apply before it is kept in slow_rows, if rows are timed
+    pub(crate) latency_budget: Option<Duration>,
+    /// How long each timed part of the row being applied took
+    pub(crate) row_timings: Vec<(SlowCause, Duration)>,
+    /// The rows that took longer than the latency budget, in the order they came in
+    pub slow_rows: Vec<SlowRow>,
+    /// The rows whose timestamp couldn't be read, in the order they came in
+    pub timestamp_errors: Vec<TimestampError>,
+    /// How many rows have been applied, rejected ones included
+    pub rows: u64,
+    /// Every dispute opened and closed, in order
+    pub dispute_events: Vec<DisputeEvent>,
+    /// The transaction IDs used by any client, if the policy dedups them globally
+    pub(crate) dedup: Option<TxDedup>,
+    /// How far into its write-ahead log the engine got, if it applied logged records
+    pub(crate) wal: Option<WalMark>,
+    /// The balance changes not yet handed to the outbox, if it is on
+    pub(crate) outbox: Option<Vec<BalanceChange>>,
+    /// Every change to the balances posted to the system accounts, if the trial balance is on
+    pub books: Option<TrialBalance>,
+    /// Every posting, if the engine keeps a journal
+    pub journal: Option<Journal>,
+    /// The period the engine is in, since the last close
+    pub period: Period,
+}
+impl Engine
+{
+    ///
+    /// Returns a new engine with no clients, following the given policy
+    ///
+    /// # Arguments
+    ///
+    /// * 'policy' - The rules used when validating transactions
+    pub fn new(policy: EnginePolicy) -> Engine
+    {
+        let reserve = policy.reserve.map(|balance| Reserve { balance, ledger: Vec::new() });
+        let dedup = policy.global_dedup.as_ref().map(TxDedup::new);
+        Engine { clients: ClientStore::default(), policy, rejections: Vec::new(), rejection_stats: RejectionStats::default(), skipped: 0, client_filter: None, excluded_types: Vec::new(), notifiers: Vec::new(), rejection_listeners: Vec::new(), reserve, counterparties: HashMap::new(),
+            audit: Vec::new(), metadata: HashMap::new(), review_queue: BTreeMap::new(), deposit_windows: ClientMap::default(),
+            screening: Box::new(NoScreening), screened: ClientSet::default(), verifier: None, interest_due: None, interest_periods_skipped: 0, next_synthetic_tx: u32::MAX,
+            settlements: BTreeMap::new(), schedules: Vec::new(), savepoints: Vec::new(), next_savepoint: 0,
+            changed: ClientSet::default(), change_sequence: 0, ingested: Vec::new(), custom_handlers: HashMap::new(),
+            policy_hook: None, charging_fee: false, rules: None, rule_hits: BTreeMap::new(), flags: Vec::new(),
+            latency_budget: None, row_timings: Vec::new(), slow_rows: Vec::new(), timestamp_errors: Vec::new(), rows: 0, dispute_events: Vec::new(), dedup, wal: None, outbox: None, books: None, journal: None, period: Period::default() }
+    }
+    /// Checks that the transaction has an amount only if its type needs one,
+    /// and that the amount is within the policy bounds
+    ///
+    /// A dispute-style transaction with an amount has it dropped if the policy ignores them
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction to check
+    fn validate(&self, tx: &mut Tx) -> Result<(), RejectReason>
+    {
+        let amount = match (tx.amount, tx.r#type.carries_amount())
+        {
+            (Some(amount), true) => amount,
+            (None, true) => return Err(RejectReason::MissingAmount),
+            (None, false) => return Ok(()),
+            //a custom type may or may not take an amount, the handler decides
+            (Some(amount), false) if tx.r#type == TypeTx::Custom => amount,
+            (Some(_), false) => return match self.policy.unexpected_amount
+            {
+                UnexpectedAmount::Reject => Err(RejectReason::UnexpectedAmount),
+                UnexpectedAmount::Ignore => {
+                    tx.amount = None;
+                    Ok(())
+                }
+            }
+        };
+        if self.policy.min_amount.is_some_and(|min| amount < min) {return Err(RejectReason::BelowMinimum)}
+        if self.policy.max_amount.is_some_and(|max| amount > max) {return Err(RejectReason::AboveMaximum)}
+        Ok(())
+    }
+    /// Takes on the client metadata registry, applying the credit limits and tiers to
+    /// clients already seen as well as those still to come
+    ///
+    /// # Arguments
+    ///
+    /// * 'metadata' - The registry, keyed by client ID
+    pub fn load_metadata(&mut self, metadata: HashMap<u16, ClientMetadata>)
+    {
+        self.metadata = metadata;
+        for c in self.clients.values_mut()
+        {
+            configure(c, &self.metadata, &self.policy);
+        }
+    }
+    /// How the transaction IDs checked for reuse across clients fared, None unless the
+    /// policy dedups them globally
+    pub fn dedup_stats(&self) -> Option<DedupStats>
+    {
+        self.dedup.as_ref().map(|d| d.stats)
+    }
+    /// Marks the current state, so that whatever is applied after can be undone with rollback_to
+    ///
+    /// Savepoints nest: rolling back to one also undoes every savepoint made after it.
+    /// Notifications already sent can't be taken back, and direct changes to the
+    /// public fields (or loading metadata) aren't undone
+    pub fn savepoint(&mut self) -> Savepoint
+    {
+        let id = self.next_savepoint;
+        self.next_savepoint += 1;
+        self.savepoints.push(SavedState {
+            id,
+            clients: ClientMap::default(),
+            rejections: self.rejections.len(),
+            rejected_reasons: UndoLog::default(),
+            rejected_types: UndoLog::default(),
+            skipped: self.skipped,
+            audit: self.audit.len(),
+            flags: self.flags.len(),
+            dispute_events: self.dispute_events.len(),
+            outbox: self.outbox.as_ref().map_or(0, Vec::len),
+            books: UndoLog::default(),
+            journal: self.journal.as_ref().map_or(0, |j| j.postings.len()),
+            period_totals: UndoLog::default(),
+            rule_hits: UndoLog::default(),
+            reserve: self.reserve.as_ref().map(|r| (r.balance, r.ledger.len())),
+            counterparties: UndoLog::default(),
+            review_queue: UndoLog::default(),
+            deposit_windows: UndoLog::default(),
+            screened: Vec::new(),
+            interest_due: self.interest_due,
+            interest_periods_skipped: self.interest_periods_skipped,
+            next_synthetic_tx: self.next_synthetic_tx,
+            schedules: (self.schedules.len(), UndoLog::default()),
+            settlements: UndoLog::default(),
+            used_txs: Vec::new(),
+            rows: self.rows,
+            slow_rows: self.slow_rows.len(),
+            timestamp_errors: self.timestamp_errors.len(),
+            changed: Vec::new(),
+            taken: None,
+            change_sequence: self.change_sequence,
+        });
+        Savepoint { depth: self.savepoints.len() - 1, id }
+    }
+    /// Whether the savepoint is still open, as it wasn't released or rolled back past
+    fn is_open(&self, savepoint: &Savepoint) -> bool
+    {
+        self.savepoints.get(savepoint.depth).is_some_and(|s| s.id == savepoint.id)
+    }
+    /// Puts the engine back the way it was at the savepoint, which stays open
+    ///
+    /// Returns false, changing nothing, if the savepoint was released or rolled back past
+    ///
+    /// # Arguments
+    ///
+    /// * 'savepoint' - The savepoint to go back to
+    pub fn rollback_to(&mut self, savepoint: &Savepoint) -> bool
+    {
+        if !self.is_open(savepoint) {return false}
+        while self.savepoints.len() > savepoint.depth
+        {
+            let saved = match self.savepoints.pop()
+            {
+                Some(saved) => saved,
+                None => break
+            };
+            for (id, client) in saved.clients
+            {
+                match client
+                {
+                    Some(c) => {self.clients.insert(id, c);},
+                    None => {self.clients.remove(&id);}
+                }
+            }
+            self.rejections.truncate(saved.rejections);
+            saved.rejected_reasons.restore(&mut self.rejection_stats.by_reason);
+            saved.rejected_types.restore(&mut self.rejection_stats.by_type);
+            self.skipped = saved.skipped;
+            self.audit.truncate(saved.audit);
+            self.flags.truncate(saved.flags);
+            self.dispute_events.truncate(saved.dispute_events);
+            if let Some(outbox) = self.outbox.as_mut()
+            {
+                outbox.truncate(saved.outbox);
+            }
+            if let Some(books) = self.books.as_mut()
+            {
+                saved.books.restore(&mut books.accounts);
+            }
+            if let Some(journal) = self.journal.as_mut()
+            {
+                journal.postings.truncate(saved.journal);
+            }
+            saved.period_totals.restore(&mut self.period.totals);
+            saved.rule_hits.restore(&mut self.rule_hits);
+            if let (Some(reserve), Some((balance, entries))) = (self.reserve.as_mut(), saved.reserve)
+            {
+                reserve.balance = balance;
+                reserve.ledger.truncate(entries);
+            }
+            saved.counterparties.restore(&mut self.counterparties);
+            saved.review_queue.restore(&mut self.review_queue);
+            saved.deposit_windows.restore(&mut self.deposit_windows);
+            for client in saved.screened
+            {
+                self.screened.remove(&client);
+            }
+            self.interest_due = saved.interest_due;
+            self.interest_periods_skipped = saved.interest_periods_skipped;
+            self.next_synthetic_tx = saved.next_synthetic_tx;
+            //the recurring transactions added since are dropped, so only those before advanced
+            let (schedules, advanced) = saved.schedules;
+            self.schedules.truncate(schedules);
+            for (i, state) in advanced.entries
+            {
+                if let (Some(slot), Some(state)) = (self.schedules.get_mut(i), state)
+                {
+                    *slot = state;
+                }
+            }
+            saved.settlements.restore(&mut self.settlements);
+            if let Some(dedup) = self.dedup.as_mut()
+            {
+                dedup.forget(&saved.used_txs);
+            }
+            self.rows = saved.rows;
+            self.slow_rows.truncate(saved.slow_rows);
+            self.timestamp_errors.truncate(saved.timestamp_errors);
+            match saved.taken
+            {
+                Some(taken) => self.changed = taken,
+                None => saved.changed.iter().for_each(|client| {self.changed.remove(client);})
+            }
+            self.change_sequence = saved.change_sequence;
+        }
+        //the savepoint stays open, now marking the restored state
+        self.savepoint();
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            saved.id = savepoint.id;
+        }
+        true
+    }
+    /// Keeps everything applied since the savepoint, closing it and every savepoint made after it
+    ///
+    /// Returns false if the savepoint was already released or rolled back past
+    ///
+    /// # Arguments
+    ///
+    /// * 'savepoint' - The savepoint to close
+    pub fn release(&mut self, savepoint: &Savepoint) -> bool
+    {
+        if !self.is_open(savepoint) {return false}
+        let released: Vec<SavedState> = self.savepoints.drain(savepoint.depth..).collect();
+        if let Some(outer) = self.savepoints.last_mut()
+        {
+            //the outer savepoint still needs the clients as they were before any of these
+            for saved in released
+            {
+                for (id, client) in saved.clients
+                {
+                    outer.clients.entry(id).or_insert(client);
+                }
+                saved.rejected_reasons.merge_into(&mut outer.rejected_reasons);
+                saved.rejected_types.merge_into(&mut outer.rejected_types);
+                saved.books.merge_into(&mut outer.books);
+                saved.period_totals.merge_into(&mut outer.period_totals);
+                saved.rule_hits.merge_into(&mut outer.rule_hits);
+                saved.counterparties.merge_into(&mut outer.counterparties);
+                saved.review_queue.merge_into(&mut outer.review_queue);
+                saved.deposit_windows.merge_into(&mut outer.deposit_windows);
+                outer.screened.extend(saved.screened);
+                saved.schedules.1.merge_into(&mut outer.schedules.1);
+                saved.settlements.merge_into(&mut outer.settlements);
+                outer.used_txs.extend(saved.used_txs);
+                match (&outer.taken, saved.taken)
+                {
+                    (Some(_), _) => (),
+                    //the clients marked between the two savepoints weren't marked at the outer one
+                    (None, Some(mut taken)) => {
+                        outer.changed.iter().for_each(|client| {taken.remove(client);});
+                        outer.taken = Some(taken);
+                    },
+                    (None, None) => outer.changed.extend(saved.changed)
+                }
+            }
+        }
+        true
+    }
+    /// Reverses the balance effects of a client's most recent transaction, for
+    /// correcting operator errors
+    ///
+    /// Only the balances, lock and history entry of the client are put back; the
+    /// counterparty figures, reserve and audit trail keep the transaction
+    ///
+    /// Returns false, changing nothing, if that transaction isn't the last one
+    /// applied to the client or was already undone
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client ID
+    /// * 'tx' - The transaction ID, which must be the client's most recent
+    pub fn undo(&mut self, client: u16, tx: u32) -> bool
+    {
+        let last = self.clients.get(&client).and_then(|c| c.last_change.as_ref()).map(|change| change.tx);
+        if last != Some(tx) {return false}
+        self.touch(client);
+        let before = self.clients.get(&client).map(|c| c.acc.clone());
+        let undone = self.clients.get_mut(&client).and_then(|c| c.undo_last()).is_some();
+        if let (true, Some(before)) = (undone, before)
+        {
+            self.record_balance_change(client, tx, "undo", &before);
+        }
+        undone
+    }
+    /// Unlocks an account locked by a chargeback, releasing the deposits taken to held
+    /// while it was locked to available
+    ///
+    /// Returns false if the client isn't known or its account isn't locked
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client ID
+    pub fn unlock(&mut self, client: u16) -> bool
+    {
+        if !self.clients.get(&client).is_some_and(|c| c.acc.locked) {return false}
+        self.touch(client);
+        let Some(c) = self.clients.get_mut(&client) else {return true};
+        c.acc.locked = false;
+        let mut held: Vec<u32> = c.history.iter().filter(|(_, h)| h.held_while_locked).map(|(id, _)| *id).collect();
+        held.sort_unstable();
+        for tx in held
+        {
+            let Some(c) = self.clients.get_mut(&client) else {break};
+            let before = c.acc.clone();
+            //can't overflow, as held and available are both part of the total
+            let _ = c.release_locked_deposit(&tx);
+            self.record_balance_change(client, tx, "release", &before);
+        }
+        true
+    }
+    /// Posts a manual adjustment to a client, as a deposit if the amount is positive
+    /// and a withdrawal of it otherwise
+    ///
+    /// The adjustment gets a transaction ID counting down from u32::MAX, shared with
+    /// interest credits and recurring transactions, and is rejected like any other
+    /// transaction if it can't be applied
+    ///
+    /// Returns the transaction ID it was made under, None if the synthetic IDs are used up
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client ID
+    /// * 'amount' - How much to credit, negative to debit
+    /// * 'memo' - Why it was made, "adjustment" if None
+    pub fn adjust(&mut self, client: u16, amount: Amount, memo: Option<String>) -> Option<u32>
+    {
+        let (r#type, amount) = match amount.is_negative()
+        {
+            true => (TypeTx::Withdrawal, Amount::ZERO.checked_sub(amount).unwrap_or(Amount::MAX)),
+            false => (TypeTx::Deposit, amount)
+        };
+        let id = self.synthetic_tx()?;
+        let mut tx = Tx::new(r#type, client, id, Some(amount));
+        tx.memo = Some(memo.unwrap_or_else(|| ADJUSTMENT_MEMO.to_string()));
+        self.apply_now(tx);
+        Some(id)
+    }
+    /// Hands out the next synthetic transaction ID, counting down from u32::MAX to
+    /// SYNTHETIC_TX_MIN, None once they are used up
+    pub(crate) fn synthetic_tx(&mut self) -> Option<u32>
+    {
+        let id = self.next_synthetic_tx;
+        if id < SYNTHETIC_TX_MIN {return None}
+        self.next_synthetic_tx = id - 1;
+        Some(id)
+    }
+    /// Whether every synthetic transaction ID has been handed out, so no more
+    /// adjustments, fees, interest or recurring transactions are made
+    pub fn synthetic_txs_used_up(&self) -> bool
+    {
+        self.next_synthetic_tx < SYNTHETIC_TX_MIN
+    }
+    /// Applies every transaction of the batch, or none of them if any is rejected
+    ///
+    /// A rolled back batch leaves nothing behind, not even its rejections
+    ///
+    /// # Arguments
+    ///
+    /// * 'batch' - The transactions, applied in order
+    ///
+    /// # Errors
+    ///
+    /// Returns BatchError with the outcome of every transaction if any was rejected
+    pub fn apply_batch(&mut self, batch: &[Tx]) -> Result<BatchReport, BatchError>
+    {
+        self.run_batch(batch, |engine, tx| {engine.apply(tx.clone()); (tx.client, tx.tx)})
+    }
+    /// Applies every record of the batch, or none of them if any is rejected,
+    /// records whose amount can't be parsed included
+    ///
+    /// # Arguments
+    ///
+    /// * 'batch' - The transactions as read from the input, applied in order
+    ///
+    /// # Errors
+    ///
+    /// Returns BatchError with the outcome of every record if any was rejected
+    pub fn apply_batch_records(&mut self, batch: &[TxRecord]) -> Result<BatchReport, BatchError>
+    {
+        self.run_batch(batch, |engine, record| {engine.apply_record(record.clone()); (record.client, record.tx)})
+    }
+    /// Applies transactions given as columns, one row of each column per transaction,
+    /// with the same outcome as applying the rows in order with apply
+    ///
+    /// The IDs and amounts are checked against the types and the policy bounds in a single
+    /// pass over the columns first, a tight loop over plain slices that the compiler can
+    /// vectorize, so rows that fail it are rejected without building a transaction or
+    /// going through dispatch. The rest are applied one at a time without being checked
+    /// again. If the engine checks signatures every row goes through apply, as the
+    /// columns carry none
+    ///
+    /// # Arguments
+    ///
+    /// * 'types' - The type of each transaction
+    /// * 'clients' - The client ID of each transaction
+    /// * 'txs' - The transaction ID of each transaction, u32 as in Tx rather than u64, so no ID needs narrowing
+    /// * 'amounts' - The amount of each transaction, None where it has none
+    ///
+    /// # Errors
+    ///
+    /// Returns ColumnLengths, applying nothing, if the columns aren't all the same length
+    pub fn apply_columns(&mut self, types: &[TypeTx], clients: &[u16], txs: &[u32], amounts: &[Option<Amount>]) -> Result<(), ColumnLengths>
+    {
+        if clients.len() != types.len() || txs.len() != types.len() || amounts.len() != types.len()
+        {
+            return Err(ColumnLengths { types: types.len(), clients: clients.len(), txs: txs.len(), amounts: amounts.len() });
+        }
+        let checked = self.check_columns(types, txs, amounts);
+        let signed = self.verifier.is_some();
+        for (i, checked) in checked.into_iter().enumerate()
+        {
+            let (r#type, client, id) = (types[i], clients[i], txs[i]);
+            let start = self.start_timing();
+            self.rows += 1;
+            match checked
+            {
+                _ if signed => self.apply_untimed(Tx::new(r#type, client, id, amounts[i])),
+                Ok(amount) => if !self.skip(client, r#type)
+                {
+                    self.apply_validated(Tx::new(r#type, client, id, amount));
+                },
+                Err(reason) => if !self.skip(client, r#type)
+                {
+                    self.reject(&Tx::new(r#type, client, id, amounts[i]), reason);
+                }
+            }
+            self.finish_timing(start, client, id, r#type);
+        }
+        Ok(())
+    }
+    /// Validates columns of IDs and amounts the way apply does a single transaction,
+    /// returning the amount each row is applied with or why it is rejected
+    ///
+    /// # Arguments
+    ///
+    /// * 'types' - The type of each transaction
+    /// * 'txs' - The transaction ID of each transaction
+    /// * 'amounts' - The amount of each transaction
+    fn check_columns(&self, types: &[TypeTx], txs: &[u32], amounts: &[Option<Amount>]) -> Vec<Result<Option<Amount>, RejectReason>>
+    {
+        let min = self.policy.min_amount.unwrap_or(Amount::MIN);
+        let max = self.policy.max_amount.unwrap_or(Amount::MAX);
+        let ignore = self.policy.unexpected_amount == UnexpectedAmount::Ignore;
+        types.iter().zip(txs).zip(amounts).map(|((r#type, id), amount)| match (*amount, r#type.carries_amount() || *r#type == TypeTx::Custom)
+        {
+            _ if r#type.carries_amount() && *id >= SYNTHETIC_TX_MIN => Err(RejectReason::ReservedTx),
+            (Some(amount), true) if amount < min => Err(RejectReason::BelowMinimum),
+            (Some(amount), true) if amount > max => Err(RejectReason::AboveMaximum),
+            (Some(amount), true) => Ok(Some(amount)),
+            (None, true) if *r#type == TypeTx::Custom => Ok(None),
+            (None, true) => Err(RejectReason::MissingAmount),
+            (None, false) => Ok(None),
+            (Some(_), false) if ignore => Ok(None),
+            (Some(_), false) => Err(RejectReason::UnexpectedAmount),
+        }).collect()
+    }
+    /// Applies a batch within a savepoint, rolling it back if any item was rejected
+    ///
+    /// # Arguments
+    ///
+    /// * 'batch' - The items to apply
+    /// * 'apply' - Applies one item, returning its client and transaction ID
+    fn run_batch<T, F: FnMut(&mut Engine, &T) -> (u16, u32)>(&mut self, batch: &[T], mut apply: F) -> Result<BatchReport, BatchError>
+    {
+        let savepoint = self.savepoint();
+        let mut report = BatchReport::default();
+        for item in batch
+        {
+            let before = self.rejections.len();
+            let (client, tx) = apply(self, item);
+            //recurring transactions made on the way may have been rejected as well
+            let outcome = match self.rejections[before..].iter().find(|r| r.client == client && r.tx == tx)
+            {
+                Some(rejection) => BatchOutcome::Rejected(rejection.reason),
+                None => BatchOutcome::Applied
+            };
+            report.outcomes.push(outcome);
+        }
+        if report.rejected().next().is_some()
+        {
+            self.rollback_to(&savepoint);
+            self.release(&savepoint);
+            return Err(BatchError { report });
+        }
+        self.release(&savepoint);
+        Ok(report)
+    }
+    /// Copies a client into the latest savepoint before it is first changed, and
+    /// marks it for the change feed
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client about to be changed
+    pub(crate) fn touch(&mut self, client: u16)
+    {
+        let marked = self.changed.insert(client);
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            let clients = &self.clients;
+            saved.clients.entry(client).or_insert_with(|| clients.get(&client).cloned());
+            if marked {saved.changed.push(client);}
+        }
+    }
+    /// Keeps the review queue entry of a client in the open savepoint, if there is one,
+    /// before it is changed
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client whose entry is about to be added or removed
+    pub(crate) fn keep_review_entry(&mut self, client: u16)
+    {
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            saved.review_queue.keep(&client, self.review_queue.get(&client));
+        }
+    }
+    /// Puts accepting transactions, freezing accounts and charging fees to a hook,
+    /// E.G. a policy script, replacing any hook set before
+    ///
+    /// # Arguments
+    ///
+    /// * 'hook' - What decides the policy points
+    pub fn set_policy_hook(&mut self, hook: Box<dyn PolicyHook>)
+    {
+        self.policy_hook = Some(hook);
+    }
+    /// Freezes the account and charges a fee if the policy hook says so, once a
+    /// transaction has been applied
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction just applied
+    fn after_policy_hook(&mut self, tx: &Tx)
+    {
+        if self.charging_fee {return}
+        let (hook, c) = match (self.policy_hook.as_mut(), self.clients.get_mut(&tx.client))
+        {
+            (Some(hook), Some(c)) => (hook, c),
+            _ => return
+        };
+        let frozen = !c.frozen && hook.freeze(tx, &c.acc);
+        let fee = hook.fee(tx, &c.acc);
+        if frozen
+        {
+            c.frozen = true;
+            self.notify(Notification::PolicyFrozen { client: tx.client, tx: tx.tx });
+        }
+        match fee
+        {
+            Ok(Some(fee)) => {
+                let Some(id) = self.synthetic_tx() else {return};
+                let mut charge = Tx::new(TypeTx::Withdrawal, tx.client, id, Some(fee));
+                charge.memo = Some(FEE_MEMO.to_string());
+                self.charging_fee = true;
+                self.apply_now(charge);
+                self.charging_fee = false;
+            },
+            Ok(None) => {},
+            //the fee is rejected under the ID it would have been charged with
+            Err(e) => {
+                let Some(id) = self.synthetic_tx() else {return};
+                self.push_rejection(Rejection{client:tx.client, tx:id, r#type:TypeTx::Withdrawal, amount:None, reason:e.into(), rule:None});
+            }
+        }
+    }
+    /// Hands over the clients touched since the last call, ordered by client
+    pub(crate) fn take_changed(&mut self) -> Vec<u16>
+    {
+        if let Some(saved) = self.savepoints.last_mut().filter(|s| s.taken.is_none())
+        {
+            let mut taken = self.changed.clone();
+            saved.changed.iter().for_each(|client| {taken.remove(client);});
+            saved.taken = Some(taken);
+        }
+        let mut changed: Vec<u16> = self.changed.drain().collect();
+        changed.sort_unstable();
+        changed
+    }
+    /// Adds a notifier, which is told about every chargeback and locked account from now on
+    ///
+    /// # Arguments
+    ///
+    /// * 'notifier' - Where to send the notifications
+    pub fn add_notifier(&mut self, notifier: Box<dyn Notifier>)
+    {
+        self.notifiers.push(notifier);
+    }
+    /// Tells every notifier the run is over, with how many rows were processed and
+    /// rejected and which accounts are locked
+    ///
+    /// Called once the whole input is applied, E.G. so a chat channel or mailbox hears
+    /// how the run went
+    pub fn finish_run(&mut self)
+    {
+        let mut locked: Vec<u16> = self.clients.values().filter(|c| c.acc.locked).map(|c| c.acc.client).collect();
+        locked.sort();
+        self.notify(Notification::RunCompleted { rows: self.rows, rejected: self.rejections.len(), locked });
+    }
+    /// Adds a rejection listener, which is told about every transaction rejected from now on
+    ///
+    /// # Arguments
+    ///
+    /// * 'listener' - Where to send the rejections
+    pub fn add_rejection_listener(&mut self, listener: Box<dyn RejectionListener>)
+    {
+        self.rejection_listeners.push(listener);
+    }
+    /// Sets how clients are screened, replacing the default which clears everyone
+    ///
+    /// # Arguments
+    ///
+    /// * 'screening' - The sanctions or KYC provider to use
+    pub fn set_screening(&mut self, screening: Box<dyn ScreeningProvider>)
+    {
+        self.screening = screening;
+    }
+    /// Processes only the clients the predicate accepts; the transactions of the
+    /// others are counted in skipped, rather than rejected
+    ///
+    /// # Arguments
+    ///
+    /// * 'filter' - Whether to process a client, E.G. a ClientFilter's contains
+    pub fn set_client_filter<F: Fn(u16) -> bool + Send + 'static>(&mut self, filter: F)
+    {
+        self.client_filter = Some(Box::new(filter));
+    }
+    /// Leaves every transaction of the given types alone, counting them in skipped,
+    /// E.G. to see what balances would be had there been no chargebacks
+    ///
+    /// # Arguments
+    ///
+    /// * 'types' - The types to exclude, replacing any excluded before
+    pub fn set_excluded_types(&mut self, types: Vec<TypeTx>)
+    {
+        self.excluded_types = types;
+    }
+    /// Whether the client is outside the client filter or the type is excluded,
+    /// counting the transaction as skipped if so
+    fn skip(&mut self, client: u16, r#type: TypeTx) -> bool
+    {
+        let skip = self.excluded_types.contains(&r#type) || self.client_filter.as_ref().is_some_and(|wanted| !wanted(client));
+        if skip
+        {
+            self.skipped += 1;
+        }
+        skip
+    }
+    /// Requires every transaction to be signed, rejecting those the verifier refuses
+    ///
+    /// Interest credits and recurring transactions are made by the engine itself, and
+    /// aren't checked
+    ///
+    /// # Arguments
+    ///
+    /// * 'verifier' - Checks the signature of each transaction
+    pub fn set_verifier(&mut self, verifier: Box<dyn SignatureVerifier>)
+    {
+        self.verifier = Some(verifier);
+    }
+    /// Screens the client on its first deposit and on transactions at or above the
+    /// policy's threshold, freezing the account if it fails
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction, already validated
+    fn screen(&mut self, tx: &Tx)
+    {
+        let amount = match (tx.r#type, tx.amount)
+        {
+            (TypeTx::Deposit | TypeTx::Withdrawal, Some(amount)) => amount,
+            _ => return
+        };
+        let first = tx.r#type == TypeTx::Deposit && self.screened.insert(tx.client);
+        if let (true, Some(saved)) = (first, self.savepoints.last_mut())
+        {
+            saved.screened.push(tx.client);
+        }
+        let large = self.policy.screen_above.is_some_and(|above| amount >= above);
+        if !(first || large) || self.clients.get(&tx.client).is_some_and(|c| c.frozen) {return}
+        let reason = match self.screening.screen(tx, self.metadata.get(&tx.client))
+        {
+            Screening::Clear => return,
+            Screening::Reject(reason) => reason
+        };
+        self.touch(tx.client);
+        let (metadata, policy) = (&self.metadata, &self.policy);
+        self.clients.get_or_insert_with(tx.client, || {
+            let mut c = Client::new(tx.client);
+            configure(&mut c, metadata, policy);
+            c
+        }).frozen = true;
+        self.notify(Notification::ScreeningFailed { client: tx.client, tx: tx.tx, reason });
+    }
+    /// Adds a recurring transaction, made whenever the timestamps of the input pass
+    /// one of its times
+    ///
+    /// Each transaction it makes gets a transaction ID counting down from u32::MAX,
+    /// shared with interest credits
+    ///
+    /// # Arguments
+    ///
+    /// * 'schedule' - The recurring deposit or withdrawal
+    pub fn add_schedule(&mut self, schedule: Schedule)
+    {
+        let next = schedule.start;
+        self.schedules.push(ScheduleState { schedule, next, made: 0 });
+    }
+    /// Makes every recurring transaction due at or before the given time, earliest first
+    ///
+    /// # Arguments
+    ///
+    /// * 'until' - The time to make transactions up to
+    fn run_schedules(&mut self, until: i64)
+    {
+        loop
+        {
+            let next = self.schedules.iter().enumerate()
+                .filter_map(|(i, s)| s.due().map(|due| (due, i)))
+                .filter(|(due, _)| *due <= until)
+                .min();
+            let (due, i) = match next
+            {
+                Some(next) => next,
+                None => return
+            };
+            if let Some(saved) = self.savepoints.last_mut()
+            {
+                saved.schedules.1.keep(&i, self.schedules.get(i));
+            }
+            self.schedules[i].advance();
+            let tx = match self.synthetic_tx()
+            {
+                Some(id) => self.schedules[i].schedule.transaction(id, due),
+                None => continue
+            };
+            self.catch_up(due);
+            self.apply_now(tx);
+        }
+    }
+    /// Settles deposits and credits interest up to the given time
+    ///
+    /// # Arguments
+    ///
+    /// * 'timestamp' - When the transaction about to be applied happened
+    fn catch_up(&mut self, timestamp: i64)
+    {
+        self.settle(timestamp);
+        self.accrue_until(timestamp);
+    }
+    /// Settles every pending deposit due at or before the given time, making it available
+    ///
+    /// # Arguments
+    ///
+    /// * 'until' - The time to settle up to, in seconds since the unix epoch; i64::MAX settles everything
+    pub fn settle(&mut self, until: i64)
+    {
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            for (due, pending) in self.settlements.range(..=until)
+            {
+                saved.settlements.keep(due, Some(pending));
+            }
+        }
+        let later = match until.checked_add(1)
+        {
+            Some(next) => self.settlements.split_off(&next),
+            None => BTreeMap::new()
+        };
+        let due = std::mem::replace(&mut self.settlements, later);
+        for (client, tx) in due.into_values().flatten()
+        {
+            self.touch(client);
+            if let Some(c) = self.clients.get_mut(&client)
+            {
+                let before = c.acc.clone();
+                //can't overflow, as pending and available are both part of the total
+                let _ = c.settle_transaction(&tx);
+                let account = c.history.get(&tx).and_then(|h| h.account.clone());
+                c.post_sub_account(account.as_deref(), &before);
+                self.record_balance_change(client, tx, "settlement", &before);
+            }
+        }
+    }
+    /// Credits interest to the available balance of every client, returning the total credited
+    ///
+    /// Each credit is kept in the client's history under a transaction ID counting down
+    /// from u32::MAX, so it can be told apart from the input. Locked accounts, accounts
+    /// with nothing available and accounts whose balance would overflow get nothing, and
+    /// are left out of the changed clients
+    ///
+    /// # Arguments
+    ///
+    /// * 'rate' - The share of the available balance to credit, E.G. 0.001
+    pub fn accrue(&mut self, rate: Amount) -> Amount
+    {
+        let mut ids: Vec<u16> = self.clients.keys().collect();
+        ids.sort();
+        let mut credited = Amount::ZERO;
+        for id in ids
+        {
+            match self.clients.get(&id).map(|c| c.interest(rate))
+            {
+                Some(Ok(interest)) if interest > Amount::ZERO => {},
+                _ => continue
+            }
+            let Some(tx) = self.synthetic_tx() else {break};
+            self.touch(id);
+            let c = match self.clients.get_mut(&id)
+            {
+                Some(c) => c,
+                None => continue
+            };
+            let before = c.acc.clone();
+            if let Ok(interest) = c.accrue_interest(tx, rate)
+            {
+                self.record_balance_change(id, tx, "interest", &before);
+                credited = credited.checked_add(interest).unwrap_or(Amount::MAX);
+            }
+        }
+        credited
+    }
+    /// Credits the interest of every period that ended at or before the timestamp,
+    /// if the policy credits interest periodically
+    ///
+    /// At most MAX_INTEREST_PERIODS are credited, a timestamp further ahead skips the
+    /// periods before them
+    ///
+    /// # Arguments
+    ///
+    /// * 'timestamp' - When the transaction being applied happened
+    fn accrue_until(&mut self, timestamp: i64)
+    {
+        let (rate, period) = match self.policy.interest
+        {
+            Some(InterestPolicy { rate, period: Some(period) }) if period > 0 => (rate, period),
+            _ => return
+        };
+        let mut due = *self.interest_due.get_or_insert(timestamp - timestamp.rem_euclid(period) + period);
+        if due <= timestamp
+        {
+            let periods = timestamp.saturating_sub(due) / period + 1;
+            if periods > MAX_INTEREST_PERIODS
+            {
+                self.interest_periods_skipped += periods - MAX_INTEREST_PERIODS;
+                due += (periods - MAX_INTEREST_PERIODS) * period;
+            }
+        }
+        while due <= timestamp
+        {
+            self.accrue(rate);
+            due = match due.checked_add(period)
+            {
+                Some(next) => next,
+                None => break
+            };
+        }
+        self.interest_due = Some(due);
+    }
+    /// Passes a notification on to every notifier
+    fn notify(&mut self, notification: Notification)
+    {
+        let start = self.start_timing();
+        for notifier in self.notifiers.iter_mut()
+        {
+            notifier.notify(&notification);
+        }
+        self.time_phase(SlowCause::Notify, start);
+        self.audit.push(notification);
+    }
+    /// Keeps track of the latest deposits of a client, freezing the account
+    /// once too many of them are charged back
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client the transaction was for
+    /// * 'tx' - The transaction ID
+    /// * 'deposited' - Whether the transaction was a deposit that went through
+    /// * 'charged_back' - Whether the transaction charged back a deposit
+    fn watch_chargebacks(&mut self, client: u16, tx: u32, deposited: bool, charged_back: bool)
+    {
+        let policy = match &self.policy.freeze
+        {
+            Some(policy) => policy,
+            None => return
+        };
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            saved.deposit_windows.keep(&client, self.deposit_windows.get(&client));
+        }
+        let window = self.deposit_windows.entry(client).or_default();
+        if deposited
+        {
+            window.push_back((tx, false));
+            if window.len() > policy.window {window.pop_front();}
+        }
+        if !charged_back {return}
+        if let Some(entry) = window.iter_mut().find(|(id, _)| *id == tx)
+        {
+            entry.1 = true;
+        }
+        let chargebacks = window.iter().filter(|(_, charged_back)| *charged_back).count();
+        let deposits = window.len();
+        if !policy.exceeded(chargebacks, deposits) {return}
+        match self.clients.get_mut(&client)
+        {
+            Some(c) if !c.frozen => c.frozen = true,
+            _ => return
+        }
+        self.notify(Notification::AccountFrozen { client, chargebacks, deposits });
+    }
+    /// Adds a transaction to the rejection report
+    fn reject(&mut self, tx: &Tx, reason: RejectReason)
+    {
+        self.push_rejection(Rejection{client:tx.client, tx:tx.tx, r#type:tx.r#type, amount:tx.amount.map(|a| a.to_string()), reason, rule:None});
+    }
+    /// Adds a rejection to the report, passing it on to every rejection listener first
+    pub(crate) fn push_rejection(&mut self, rejection: Rejection)
+    {
+        for listener in self.rejection_listeners.iter_mut()
+        {
+            listener.rejected(&rejection);
+        }
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            let stats = &self.rejection_stats;
+            saved.rejected_reasons.keep(&rejection.reason, stats.by_reason.get(&rejection.reason));
+            saved.rejected_types.keep(rejection.r#type.as_str(), stats.by_type.get(rejection.r#type.as_str()));
+        }
+        self.rejection_stats.add(&rejection);
+        self.rejections.push(rejection);
+    }
+    /// Checks a transaction against the declarative rules, counting the hits and keeping
+    /// the flags, and returns false if a rule refused it
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction about to be applied
+    fn check_rules(&mut self, tx: &Tx) -> bool
+    {
+        let verdict = match &self.rules
+        {
+            Some(rules) => rules.evaluate(tx, self.clients.get(&tx.client)),
+            None => return true
+        };
+        for id in verdict.hits
+        {
+            if let Some(saved) = self.savepoints.last_mut()
+            {
+                saved.rule_hits.keep(id, self.rule_hits.get(id));
+            }
+            *self.rule_hits.entry(id.to_string()).or_default() += 1;
+        }
+        match verdict.rejected_by
+        {
+            Some(id) => {
+                let rule = Some(id.to_string());
+                self.push_rejection(Rejection{client:tx.client, tx:tx.tx, r#type:tx.r#type, amount:tx.amount.map(|a| a.to_string()), reason:RejectReason::RuleRejected, rule});
+                false
+            },
+            None => {
+                self.flags.extend(verdict.flagged_by.into_iter().map(|id| RuleFlag { client: tx.client, tx: tx.tx, rule: id.to_string() }));
+                true
+            }
+        }
+    }
+    /// Parses the amount of a record as the policy says, then applies it
+    ///
+    /// A record whose amount can't be parsed is added to the rejection report
+    ///
+    /// # Arguments
+    ///
+    /// 'record' - The transaction as read from the input
+    pub fn apply_record(&mut self, record: TxRecord)
+    {
+        let start = self.start_timing();
+        self.apply_record_since(record, start);
+    }
+    /// Applies a record as apply_record does, timing it from the given start
+    ///
+    /// # Arguments
+    ///
+    /// 'record' - The transaction as read from the input
+    /// 'start' - When the row started, from start_timing
+    pub(crate) fn apply_record_since(&mut self, mut record: TxRecord, start: Option<Instant>)
+    {
+        let (client, tx, r#type) = (record.client, record.tx, record.r#type);
+        self.rows += 1;
+        if !self.skip(client, r#type)
+        {
+            self.check_timestamp(&mut record);
+            match record.to_tx(self.policy.rounding)
+            {
+                Ok(tx) => self.apply_untimed(tx),
+                Err(reason) => self.push_rejection(Rejection{client:record.client, tx:record.tx, r#type:record.r#type, amount:record.amount, reason, rule:None})
+            }
+        }
+        self.finish_timing(start, client, tx, r#type);
+    }
+    /// Applies a transaction to its client, creating the client if it's new
+    ///
+    /// If the transaction fails validation it is added to the rejection report
+    /// instead, and the client is left as it was
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction to apply
+    pub fn apply(&mut self, tx: Tx)
+    {
+        let start = self.start_timing();
+        let (client, id, r#type) = (tx.client, tx.tx, tx.r#type);
+        self.rows += 1;
+        self.apply_untimed(tx);
+        self.finish_timing(start, client, id, r#type);
+    }
+    /// Applies a transaction as apply does, without timing it as a row of its own
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction to apply
+    fn apply_untimed(&mut self, tx: Tx)
+    {
+        if self.skip(tx.client, tx.r#type) {return}
+        if tx.r#type.carries_amount() && tx.tx >= SYNTHETIC_TX_MIN
+        {
+            self.reject(&tx, RejectReason::ReservedTx);
+            return;
+        }
+        if let Some(timestamp) = tx.timestamp
+        {
+            let start = self.start_timing();
+            self.run_schedules(timestamp);
+            self.catch_up(timestamp);
+            self.time_phase(SlowCause::CatchUp, start);
+        }
+        let start = self.start_timing();
+        let verified = self.verifier.as_ref().is_none_or(|v| v.verify(&tx));
+        self.time_phase(SlowCause::Signature, start);
+        if !verified
+        {
+            self.reject(&tx, RejectReason::InvalidSignature);
+            return;
+        }
+        self.apply_now(tx);
+    }
+    /// Applies a transaction once everything due before it has been taken care of
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction to apply
+    fn apply_now(&mut self, mut tx: Tx)
+    {
+        if let Err(reason) = self.validate(&mut tx)
+        {
+            self.reject(&tx, reason);
+            return;
+        }
+        self.apply_validated(tx);
+    }
+    /// Applies a transaction whose amount has already been validated
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction to apply
+    fn apply_validated(&mut self, tx: Tx)
+    {
+        if tx.r#type == TypeTx::Custom && !tx.custom.as_ref().is_some_and(|name| self.custom_handlers.contains_key(name))
+        {
+            self.reject(&tx, RejectReason::UnknownType);
+            return;
+        }
+        if self.clients.get(&tx.client).is_some_and(|c| c.closed)
+        {
+            self.reject(&tx, RejectReason::AccountClosed);
+            return;
+        }
+        match tx.r#type
+        {
+            TypeTx::ComplianceHold => {
+                self.keep_review_entry(tx.client);
+                self.review_queue.entry(tx.client).or_insert(ReviewEntry { client: tx.client, tx: tx.tx, reason: tx.memo.clone() });
+                return;
+            },
+            TypeTx::ComplianceRelease => {
+                self.keep_review_entry(tx.client);
+                self.review_queue.remove(&tx.client);
+                return;
+            },
+            _ => {
+                let start = self.start_timing();
+                self.screen(&tx);
+                self.time_phase(SlowCause::Screening, start);
+            }
+        }
+        match tx.r#type
+        {
+            TypeTx::Withdrawal if self.clients.get(&tx.client).is_some_and(|c| c.frozen) => {
+                self.reject(&tx, RejectReason::AccountFrozen);
+                return;
+            },
+            TypeTx::Withdrawal if self.review_queue.contains_key(&tx.client) => {
+                self.reject(&tx, RejectReason::UnderReview);
+                return;
+            },
+            _ => ()
+        }
+        let base_currency = self.metadata.get(&tx.client).and_then(|m| m.base_currency.as_deref());
+        if matches!((base_currency, tx.currency.as_deref()), (Some(base), Some(currency)) if base != currency)
+        {
+            self.reject(&tx, RejectReason::CurrencyMismatch);
+            return;
+        }
+        let start = self.start_timing();
+        let passed = self.charging_fee || self.check_rules(&tx);
+        let accepted = match (self.policy_hook.as_mut(), self.charging_fee || !passed)
+        {
+            (Some(hook), false) => {
+                let fresh = Account::new(tx.client);
+                let account = match self.clients.get(&tx.client)
+                {
+                    Some(c) => &c.acc,
+                    None => &fresh
+                };
+                hook.accept(&tx, account)
+            },
+            _ => true
+        };
+        self.time_phase(SlowCause::Policy, start);
+        if !passed {return}
+        if !accepted
+        {
+            self.reject(&tx, RejectReason::RefusedByPolicy);
+            return;
+        }
+        let moves_funds = matches!(tx.r#type, TypeTx::Deposit | TypeTx::Withdrawal);
+        if moves_funds && self.dedup.as_mut().is_some_and(|d| d.seen(tx.tx))
+        {
+            self.reject(&tx, RejectReason::DuplicateTx);
+            return;
+        }
+        self.touch(tx.client);
+        let (metadata, policy) = (&self.metadata, &self.policy);
+        let c = self.clients.get_or_insert_with(tx.client, || {
+            let mut c = Client::new(tx.client);
+            configure(&mut c, metadata, policy);
+            c
+        });
+        let was_locked = c.acc.locked;
+        let (before_acc, before_entry) = (c.acc.clone(), c.history.get(&tx.tx).cloned());
+        let before = (c.acc.total, c.acc.held, before_entry.is_some());
+        let transaction_id = tx.tx;
+        let applied = match tx.r#type
+        {
+            TypeTx::Deposit | TypeTx::Withdrawal if was_locked => c.process_locked_transaction(&tx, self.policy.locked_account(tx.r#type)).map_err(RejectReason::from),
+            TypeTx::Deposit | TypeTx::Withdrawal => c.process_transaction(&tx).map_err(RejectReason::from),
+            TypeTx::Dispute => c.dispute_within_window(&transaction_id, self.rows, tx.timestamp).map_err(RejectReason::from),
+            TypeTx::Resolve => c.resolve_transaction(&transaction_id).map_err(RejectReason::from),
+            TypeTx::Chargeback => c.chargeback_transaction(&transaction_id).map_err(RejectReason::from),
+            TypeTx::Representment => c.represent_transaction(&transaction_id).map_err(RejectReason::from),
+            TypeTx::ComplianceHold | TypeTx::ComplianceRelease => Ok(()),
+            TypeTx::Custom => {
+                let handlers = &mut self.custom_handlers;
+                match tx.custom.as_ref().and_then(|name| handlers.get_mut(name))
+                {
+                    Some(handler) => handler.apply(&tx, c),
+                    None => Err(RejectReason::UnknownType)
+                }
+            }
+        };
+        if let Err(reason) = applied
+        {
+            self.reject(&tx, reason);
+            return;
+        }
+        let moved = before != (c.acc.total, c.acc.held, c.history.contains_key(&tx.tx));
+        //a deposit or withdrawal left alone, E.G. for want of funds, can be retried under its ID
+        if let (true, true, Some(dedup)) = (moves_funds, moved, self.dedup.as_mut())
+        {
+            dedup.insert(transaction_id);
+            if let Some(saved) = self.savepoints.last_mut()
+            {
+                saved.used_txs.push(transaction_id);
+            }
+        }
+        if let (false, Some(entry)) = (before.2, c.history.get_mut(&transaction_id))
+        {
+            entry.row = self.rows;
+            entry.timestamp = tx.timestamp;
+        }
+        if let (TypeTx::Deposit, true, Some(delay), Some(timestamp)) = (tx.r#type, moved, self.policy.settlement_delay, tx.timestamp)
+        {
+            if c.defer_settlement(&transaction_id).is_ok() && c.history.get(&transaction_id).is_some_and(|h| h.pending)
+            {
+                let due = timestamp.saturating_add(delay);
+                if let Some(saved) = self.savepoints.last_mut()
+                {
+                    saved.settlements.keep(&due, self.settlements.get(&due));
+                }
+                self.settlements.entry(due).or_default().push((tx.client, transaction_id));
+            }
+        }
+        let was_disputed = before_entry.as_ref().map(|h| h.in_dispute);
+        //a chargeback or representment that went through adds itself to the dispute chain
+        let chained = before_entry.as_ref().map(|h| h.dispute_chain.len()) < c.history.get(&transaction_id).map(|h| h.dispute_chain.len());
+        let dispute = match (tx.r#type, was_disputed, c.history.get(&transaction_id))
+        {
+            (TypeTx::Dispute, Some(false), Some(h)) if h.in_dispute => Some((DisputeStatus::Open, h.amount)),
+            (TypeTx::Resolve, Some(true), Some(h)) if !h.in_dispute => Some((DisputeStatus::Resolved, h.amount)),
+            (TypeTx::Chargeback, Some(true), Some(h)) if chained => Some((DisputeStatus::ChargedBack, h.amount)),
+            (TypeTx::Representment, Some(true), Some(h)) if chained => Some((DisputeStatus::Represented, h.amount)),
+            _ => None
+        };
+        let charged_back = tx.r#type == TypeTx::Chargeback && chained;
+        c.record_change(&tx, &before_acc, before_entry);
+        let account = c.sub_account_of(&tx);
+        c.post_sub_account(account.as_deref(), &before_acc);
+        if let (Some(counterparty), Some(amount), true) = (&tx.counterparty, tx.amount, moved)
+        {
+            if let Some(saved) = self.savepoints.last_mut()
+            {
+                saved.counterparties.keep(counterparty.as_str(), self.counterparties.get(counterparty));
+            }
+            self.counterparties.entry(counterparty.clone()).or_default().record(tx.r#type, amount);
+        }
+        let now_locked = c.acc.locked;
+        let contested = match tx.r#type
+        {
+            TypeTx::Chargeback | TypeTx::Representment if chained => c.history.get(&transaction_id).map(|h| (h.amount, h.counterparty.clone())),
+            _ => None
+        };
+        let cause = match self.charging_fee
+        {
+            true => FEE_MEMO,
+            false => tx.custom.as_deref().unwrap_or(tx.r#type.as_str())
+        };
+        self.record_balance_change(tx.client, transaction_id, cause, &before_acc);
+        match (tx.r#type, contested)
+        {
+            (TypeTx::Chargeback, Some((amount, counterparty))) => {
+                if let Some(reserve) = self.reserve.as_mut()
+                {
+                    reserve.debit(tx.client, transaction_id, amount);
+                    self.post_reserve(tx.client, transaction_id, amount);
+                }
+                if let Some(counterparty) = counterparty
+                {
+                    if let Some(saved) = self.savepoints.last_mut()
+                    {
+                        saved.counterparties.keep(counterparty.as_str(), self.counterparties.get(&counterparty));
+                    }
+                    self.counterparties.entry(counterparty).or_default().record(TypeTx::Chargeback, amount);
+                }
+                self.notify(Notification::Chargeback { client: tx.client, tx: transaction_id, amount });
+            },
+            //the reserve gets the funds back while the chargeback is contested
+            (TypeTx::Representment, Some((amount, _))) => if let Some(reserve) = self.reserve.as_mut()
+            {
+                reserve.credit(tx.client, transaction_id, amount);
+                self.post_reserve(tx.client, transaction_id, Amount::ZERO.checked_sub(amount).unwrap_or(Amount::MIN));
+            },
+            _ => ()
+        }
+        if !was_locked && now_locked
+        {
+            self.notify(Notification::AccountLocked { client: tx.client });
+        }
+        if let Some((status, amount)) = dispute
+        {
+            self.dispute_events.push(DisputeEvent { client: tx.client, tx: transaction_id, amount, status, row: self.rows, timestamp: tx.timestamp });
+        }
+        let deposited = moved && tx.r#type == TypeTx::Deposit;
+        self.watch_chargebacks(tx.client, transaction_id, deposited, charged_back);
+        let start = self.start_timing();
+        self.after_policy_hook(&tx);
+        self.time_phase(SlowCause::Policy, start);
+    }
+}
+
+/// Gives a client the credit limit and tier limits the metadata registry and policy set for it
+pub(crate) fn configure(c: &mut Client, metadata: &HashMap<u16, ClientMetadata>, policy: &EnginePolicy)
+{
+    let meta = metadata.get(&c.acc.client);
+    c.credit_limit = meta.and_then(|m| m.credit_limit).unwrap_or(Amount::ZERO);
+    c.limits = meta.and_then(|m| m.tier.as_deref()).and_then(|t| policy.tier_limits(t)).cloned().unwrap_or_default();
+    c.limits.dispute_window = c.limits.dispute_window.or(policy.dispute_window);
+}
+
+/// Writes the rejection report as csv
+///
+/// # Arguments
+///
+/// * 'rejections' - The rejected transactions
+/// * 'out' - Where to write the report to
+pub fn write_rejections<W: io::Write>(rejections: &[Rejection], out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for r in rejections
+    {
+        wrtr.serialize(r)?;
+    }
+    wrtr.flush()?;
+    Ok(())
+}
+
+/// Writes the accounts awaiting compliance review as csv
+///
+/// # Arguments
+///
+/// * 'queue' - The review queue of the engine
+/// * 'out' - Where to write the queue to
+pub fn write_review_queue<W: io::Write>(queue: &BTreeMap<u16, ReviewEntry>, out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for entry in queue.values()
+    {
+        wrtr.serialize(entry)?;
+    }
+    wrtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DisputeWindow, FreezePolicy, GlobalDedup, LockedAccount};
+
+    fn amt(text: &str) -> Amount
+    {
+        text.parse().unwrap()
+    }
+    fn deposit(tx: u32, amount: &str) -> Tx
+    {
+        Tx::new(TypeTx::Deposit,1,tx,Some(amt(amount)))
+    }
+    fn record(tx: u32, amount: &str) -> TxRecord
+    {
+        TxRecord::new(TypeTx::Deposit,1,tx,Some(amount.to_string()))
+    }
+
+    #[test]
+    fn non_finite_amounts()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_record(record(1,"NaN"));
+        engine.apply_record(record(2,"inf"));
+        engine.apply_record(record(3,"-Infinity"));
+        assert!(engine.clients.is_empty());
+        assert_eq!(engine.rejections.len(),3);
+        assert!(engine.rejections.iter().all(|r| r.reason == RejectReason::NonFinite));
+    }
+    #[test]
+    fn locked_account_policy()
+    {
+        let locked = |policy: EnginePolicy| {
+            let mut engine = Engine::new(policy);
+            engine.apply(deposit(1,"5.0"));
+            engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+            engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+            engine.apply(deposit(2,"3.0"));
+            engine.apply(Tx::new(TypeTx::Withdrawal,1,3,Some(amt("1.0"))));
+            engine.clients.remove(&1).unwrap().acc
+        };
+        let acc = locked(EnginePolicy::default());
+        assert_eq!((acc.available, acc.held, acc.total),(amt("0"), amt("0"), amt("0")));
+        let acc = locked(EnginePolicy{locked_deposit:LockedAccount::AcceptToHeld, locked_withdrawal:LockedAccount::AcceptToHeld, ..EnginePolicy::default()});
+        assert_eq!((acc.available, acc.held, acc.total),(amt("0"), amt("3"), amt("3")));
+        let acc = locked(EnginePolicy{locked_deposit:LockedAccount::AcceptNormally, locked_withdrawal:LockedAccount::AcceptNormally, ..EnginePolicy::default()});
+        assert_eq!((acc.available, acc.held, acc.total),(amt("2"), amt("0"), amt("2")));
+        assert!(acc.locked);
+    }
+    #[test]
+    fn locked_deposits_held_until_unlock()
+    {
+        let mut engine = Engine::new(EnginePolicy{locked_deposit:LockedAccount::AcceptToHeld, locked_withdrawal:LockedAccount::AcceptToHeld, ..EnginePolicy::default()});
+        engine.apply(deposit(1,"5.0"));
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        engine.apply(deposit(2,"3.0"));
+        engine.apply(Tx::new(TypeTx::Withdrawal,1,3,Some(amt("1.0"))));
+        assert_eq!((engine.rejections.len(), engine.rejections[0].tx, engine.rejections[0].reason),(1, 3, RejectReason::AccountLocked));
+        assert!(!engine.clients[&1].history[&2].in_dispute && engine.clients[&1].history[&2].held_while_locked);
+
+        //it isn't disputed, so neither a dispute nor a resolve moves it
+        engine.apply(Tx::new(TypeTx::Dispute,1,2,None));
+        engine.apply(Tx::new(TypeTx::Resolve,1,2,None));
+        assert_eq!(engine.clients[&1].acc.held,amt("3"));
+        assert!(engine.unlock(1));
+        let acc = &engine.clients[&1].acc;
+        assert_eq!((acc.available, acc.held, acc.total),(amt("3"), amt("0"), amt("3")));
+        assert!(!engine.clients[&1].history[&2].held_while_locked);
+    }
+    #[test]
+    fn chargeback_reserve()
+    {
+        let mut engine = Engine::new(EnginePolicy{reserve:Some(amt("10")), ..EnginePolicy::default()});
+        engine.apply(deposit(1,"4.0"));
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        let reserve = engine.reserve.unwrap();
+        assert_eq!(reserve.balance,amt("6"));
+        assert_eq!(reserve.ledger,vec![ReserveEntry{client:1, tx:1, amount:amt("4"), balance:amt("6")}]);
+        assert!(Engine::new(EnginePolicy::default()).reserve.is_none());
+    }
+    #[test]
+    fn chargeback_representment()
+    {
+        let mut engine = Engine::new(EnginePolicy{reserve:Some(amt("10")), ..EnginePolicy::default()});
+        engine.apply(deposit(1,"4.0"));
+        engine.apply(deposit(2,"3.0"));
+        engine.apply(Tx::new(TypeTx::Representment,1,1,None));
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        engine.apply(Tx::new(TypeTx::Representment,1,1,None));
+        let c = &engine.clients[&1];
+        assert!(c.acc.locked && c.history[&1].represented());
+        assert_eq!((c.acc.available,c.acc.held,c.acc.total),(amt("3"),amt("4"),amt("7")));
+        assert_eq!(engine.reserve.as_ref().unwrap().balance,amt("10"));
+
+        //the second decision goes through on the locked account, and is final
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        engine.apply(Tx::new(TypeTx::Representment,1,1,None));
+        let c = &engine.clients[&1];
+        assert_eq!((c.acc.available,c.acc.held,c.acc.total),(amt("3"),amt("0"),amt("3")));
+        assert_eq!(c.history[&1].dispute_chain_text(),"dispute>chargeback>representment>chargeback");
+        let reserve = engine.reserve.as_ref().unwrap();
+        assert_eq!(reserve.ledger.iter().map(|e| e.amount).collect::<Vec<_>>(),vec![amt("4"),amt("-4"),amt("4")]);
+        let statuses: Vec<_> = engine.dispute_events.iter().map(|e| e.status).collect();
+        assert_eq!(statuses,vec![DisputeStatus::Open,DisputeStatus::ChargedBack,DisputeStatus::Represented,DisputeStatus::ChargedBack]);
+        let lifecycles = engine.dispute_lifecycles();
+        assert_eq!((lifecycles.len(),lifecycles[0].status,lifecycles[0].represented_row,lifecycles[0].closed_row),(1,DisputeStatus::ChargedBack,Some(6),Some(7)));
+
+        let mut out = Vec::new();
+        crate::write_ledger(&engine.clients, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().lines().nth(1).unwrap().starts_with("1,1,4.0,true,dispute>chargeback>representment>chargeback,"));
+    }
+    #[test]
+    fn counterparty_stats()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        let with = |mut tx: Tx, counterparty: &str| {tx.counterparty = Some(counterparty.to_string()); tx};
+        engine.apply(with(deposit(1,"4.0"), "shop"));
+        engine.apply(with(deposit(2,"6.0"), "shop"));
+        engine.apply(with(deposit(2,"9.0"), "shop"));
+        engine.apply(with(Tx::new(TypeTx::Withdrawal,1,3,Some(amt("1.0"))), "cafe"));
+        engine.apply(with(Tx::new(TypeTx::Withdrawal,1,4,Some(amt("100.0"))), "cafe"));
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        let shop = &engine.counterparties["shop"];
+        assert_eq!((shop.deposits, shop.volume, shop.chargebacks, shop.charged_back),(2, amt("10"), 1, amt("4")));
+        assert_eq!(shop.chargeback_rate(),0.5);
+        let cafe = &engine.counterparties["cafe"];
+        assert_eq!((cafe.withdrawals, cafe.volume, cafe.chargebacks),(1, amt("1"), 0));
+    }
+    #[test]
+    fn chargeback_freeze()
+    {
+        let policy = EnginePolicy{freeze:Some(FreezePolicy { window: 3, max_chargebacks: None, max_rate: Some(0.3) }), ..EnginePolicy::default()};
+        //the charged back deposit has already left the window
+        let mut engine = Engine::new(policy.clone());
+        for tx in 1..=4
+        {
+            engine.apply(deposit(tx,"1.0"));
+        }
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        assert!(!engine.clients[&1].frozen);
+
+        let mut engine = Engine::new(policy);
+        engine.apply(deposit(1,"1.0"));
+        engine.apply(deposit(2,"1.0"));
+        engine.apply(Tx::new(TypeTx::Dispute,1,2,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,2,None));
+        assert!(engine.clients[&1].frozen);
+        assert_eq!(engine.audit.last(),Some(&Notification::AccountFrozen { client: 1, chargebacks: 1, deposits: 2 }));
+        engine.apply(Tx::new(TypeTx::Withdrawal,1,5,Some(amt("0.5"))));
+        assert_eq!(engine.rejections[0].reason,RejectReason::AccountFrozen);
+    }
+    #[test]
+    fn tier_limits()
+    {
+        let mut policy = EnginePolicy::default();
+        policy.tiers.insert("basic".to_string(), "max_deposit=10,max_withdrawal=2,max_open_disputes=1".parse().unwrap());
+        let mut engine = Engine::new(policy);
+        engine.apply(deposit(1,"10.0"));
+        engine.load_metadata(HashMap::from([(1, ClientMetadata { client: 1, tier: Some("basic".to_string()), ..ClientMetadata::default() })]));
+        engine.apply(deposit(2,"10.5"));
+        engine.apply(deposit(3,"4.0"));
+        engine.apply(Tx::new(TypeTx::Withdrawal,1,4,Some(amt("3.0"))));
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Dispute,1,3,None));
+        let reasons: Vec<RejectReason> = engine.rejections.iter().map(|r| r.reason).collect();
+        assert_eq!(reasons,vec![RejectReason::AboveTierLimit, RejectReason::AboveTierLimit, RejectReason::TooManyDisputes]);
+        assert_eq!(engine.clients[&1].acc.held,amt("10"));
+        assert_eq!(engine.clients[&1].acc.total,amt("14"));
+    }
+    #[test]
+    fn dispute_window()
+    {
+        let at = |mut tx: Tx, timestamp: i64| {tx.timestamp = Some(timestamp); tx};
+        let mut policy = EnginePolicy{dispute_window:DisputeWindow{days:None, rows:Some(4)}, ..EnginePolicy::default()};
+        policy.tiers.insert("card".to_string(), "dispute_window_days=120".parse().unwrap());
+        let mut engine = Engine::new(policy);
+        engine.load_metadata(HashMap::from([(2, ClientMetadata { client: 2, tier: Some("card".to_string()), ..ClientMetadata::default() })]));
+        engine.apply(deposit(1,"1.0"));
+        engine.apply(deposit(2,"1.0"));
+        engine.apply(at(Tx::new(TypeTx::Deposit,2,3,Some(amt("1.0"))), 0));
+        engine.apply(at(Tx::new(TypeTx::Deposit,2,4,Some(amt("1.0"))), 86_400));
+        engine.apply(Tx::new(TypeTx::Dispute,1,2,None));
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(at(Tx::new(TypeTx::Dispute,2,3,None), 121 * 86_400));
+        engine.apply(at(Tx::new(TypeTx::Dispute,2,4,None), 121 * 86_400));
+        let rejected: Vec<(u32, RejectReason)> = engine.rejections.iter().map(|r| (r.tx, r.reason)).collect();
+        assert_eq!(rejected,vec![(1, RejectReason::DisputeWindowExpired), (3, RejectReason::DisputeWindowExpired)]);
+        assert_eq!((engine.clients[&1].acc.held, engine.clients[&2].acc.held),(amt("1"), amt("1")));
+        assert_eq!((engine.clients[&2].history[&4].row, engine.clients[&2].history[&4].timestamp),(4, Some(86_400)));
+    }
+    #[test]
+    fn compliance_review()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(deposit(1,"5.0"));
+        let mut hold = Tx::new(TypeTx::ComplianceHold,1,2,None);
+        hold.memo = Some("large cash deposits".to_string());
+        engine.apply(hold);
+        engine.apply(Tx::new(TypeTx::ComplianceHold,3,3,None));
+        engine.apply(Tx::new(TypeTx::Withdrawal,1,4,Some(amt("1.0"))));
+        engine.apply(deposit(5,"1.0"));
+        assert_eq!(engine.rejections[0].reason,RejectReason::UnderReview);
+        assert!(!engine.clients.contains_key(&3));
+
+        let mut out = Vec::new();
+        write_review_queue(&engine.review_queue, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),"client,tx,reason\n1,2,large cash deposits\n3,3,\n");
+
+        engine.apply(Tx::new(TypeTx::ComplianceRelease,1,6,None));
+        engine.apply(Tx::new(TypeTx::Withdrawal,1,7,Some(amt("1.0"))));
+        assert_eq!(engine.clients[&1].acc.total,amt("5"));
+        assert_eq!(engine.review_queue.len(),1);
+    }
+    #[test]
+    fn interest_accrual()
+    {
+        let interest = InterestPolicy { rate: amt("0.01"), period: Some(100) };
+        let mut engine = Engine::new(EnginePolicy { interest: Some(interest), ..EnginePolicy::default() });
+        let at = |client: u16, tx: u32, amount: &str, timestamp: i64| {
+            let mut tx = Tx::new(TypeTx::Deposit,client,tx,Some(amt(amount)));
+            tx.timestamp = Some(timestamp);
+            tx
+        };
+        engine.apply(at(1,1,"100.0",150));
+        engine.apply(at(2,2,"50.0",160));
+        engine.apply(Tx::new(TypeTx::Dispute,2,2,None));
+        engine.apply(at(3,3,"1.0",420));
+        assert_eq!(engine.clients[&1].acc.total,amt("103.0301"));
+        assert_eq!(engine.clients[&2].acc.total,amt("50"));
+        assert_eq!(engine.clients[&3].acc.total,amt("1"));
+        assert_eq!(engine.clients[&1].history[&u32::MAX].memo.as_deref(),Some(crate::INTEREST_MEMO));
+        assert_eq!(engine.clients[&1].history[&(u32::MAX - 1)].amount,amt("1.01"));
+
+        assert_eq!(engine.accrue(amt("0.5")),amt("52.0150"));
+        assert_eq!(engine.clients[&3].history[&(u32::MAX - 4)].amount,amt("0.5"));
+    }
+    #[test]
+    fn interest_catch_up_capped()
+    {
+        let interest = InterestPolicy { rate: amt("0.0001"), period: Some(1) };
+        let mut engine = Engine::new(EnginePolicy { interest: Some(interest), ..EnginePolicy::default() });
+        let mut deposit = Tx::new(TypeTx::Deposit,1,1,Some(amt("100.0")));
+        deposit.timestamp = Some(0);
+        engine.apply(deposit);
+        engine.apply(Tx::new(TypeTx::Deposit,2,2,Some(amt("0"))));
+        engine.take_changed();
+        let mut late = Tx::new(TypeTx::Deposit,3,3,Some(amt("1.0")));
+        late.timestamp = Some(1_000_000_000_000);
+        engine.apply(late);
+        assert_eq!(engine.clients[&1].history.len() as i64,MAX_INTEREST_PERIODS + 1);
+        assert_eq!(engine.take_changed(),vec![1, 3]);
+        assert_eq!(engine.interest_periods_skipped,1_000_000_000_000 - MAX_INTEREST_PERIODS);
+        assert!(crate::anomalies(&engine).contains(&crate::Warning::InterestPeriodsSkipped { periods: engine.interest_periods_skipped }));
+    }
+    #[test]
+    fn reserved_tx_ids_rejected()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(Tx::new(TypeTx::Deposit,1,u32::MAX,Some(amt("5.0"))));
+        assert_eq!(engine.rejections[0].reason,RejectReason::ReservedTx);
+        assert_eq!(engine.adjust(1, amt("5.0"), None),Some(u32::MAX));
+        engine.apply(Tx::new(TypeTx::Dispute,1,u32::MAX,None));
+        assert_eq!(engine.clients[&1].acc.held,amt("5"));
+
+        engine.next_synthetic_tx = SYNTHETIC_TX_MIN;
+        assert_eq!(engine.adjust(1, amt("1.0"), None),Some(SYNTHETIC_TX_MIN));
+        assert!(engine.synthetic_txs_used_up());
+        assert_eq!(engine.adjust(1, amt("1.0"), None),None);
+        assert_eq!(engine.clients[&1].acc.total,amt("6"));
+    }
+    #[test]
+    fn settlement_delay()
+    {
+        let mut engine = Engine::new(EnginePolicy { settlement_delay: Some(100), ..EnginePolicy::default() });
+        let at = |r#type: TypeTx, tx: u32, amount: Option<&str>, timestamp: Option<i64>| {
+            let mut tx = Tx::new(r#type,1,tx,amount.map(amt));
+            tx.timestamp = timestamp;
+            tx
+        };
+        engine.apply(at(TypeTx::Deposit,1,Some("10.0"),Some(0)));
+        engine.apply(at(TypeTx::Deposit,2,Some("5.0"),Some(50)));
+        engine.apply(at(TypeTx::Deposit,3,Some("1.0"),None));
+        engine.apply(at(TypeTx::Withdrawal,4,Some("2.0"),Some(60)));
+        let acc = &engine.clients[&1].acc;
+        assert_eq!((acc.available,acc.pending,acc.total),(amt("1"),amt("15"),amt("16")));
+        assert!(acc.to_string().ends_with("locked:false, pending: 15.0"));
+        assert_eq!(engine.rejections.len(),0);
+
+        engine.apply(Tx::new(TypeTx::Dispute,1,2,None));
+        engine.apply(at(TypeTx::Withdrawal,5,Some("2.0"),Some(100)));
+        let acc = &engine.clients[&1].acc;
+        assert_eq!((acc.available,acc.held,acc.pending,acc.total),(amt("9"),amt("5"),amt("0"),amt("14")));
+        engine.apply(Tx::new(TypeTx::Resolve,1,2,None));
+        engine.settle(i64::MAX);
+        assert_eq!(engine.clients[&1].acc.available,amt("14"));
+        assert_eq!(engine.clients[&1].acc.to_string()," available: 14.0, held: 0.0, total: 14.0, locked:false");
+    }
+    #[test]
+    fn savepoint_rollback()
+    {
+        let mut engine = Engine::new(EnginePolicy { reserve: Some(amt("10")), ..EnginePolicy::default() });
+        engine.apply(deposit(1,"5.0"));
+        let outer = engine.savepoint();
+        engine.apply(deposit(2,"1.0"));
+        let inner = engine.savepoint();
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        engine.apply(Tx::new(TypeTx::Deposit,2,3,Some(amt("4.0"))));
+        engine.apply(Tx::new(TypeTx::Resolve,2,3,Some(amt("4.0"))));
+        assert!(engine.clients[&1].acc.locked);
+        assert_eq!(engine.rejections.len(),1);
+
+        assert!(engine.rollback_to(&inner));
+        assert!(!engine.clients[&1].acc.locked);
+        assert_eq!(engine.clients[&1].acc.total,amt("6"));
+        assert!(!engine.clients.contains_key(&2));
+        assert_eq!(engine.reserve.as_ref().unwrap().balance,amt("10"));
+        assert!(engine.rejections.is_empty() && engine.audit.is_empty());
+
+        engine.apply(deposit(5,"1.0"));
+        assert!(engine.release(&inner));
+        assert!(!engine.rollback_to(&inner));
+        assert!(engine.rollback_to(&outer));
+        assert_eq!(engine.clients[&1].acc.total,amt("5"));
+        assert_eq!(engine.clients[&1].history.len(),1);
+        assert!(engine.release(&outer));
+        assert!(!engine.release(&outer));
+    }
+    #[test]
+    fn rollback_restores_touched_entries()
+    {
+        let mut engine = Engine::new(EnginePolicy { settlement_delay: Some(100), ..EnginePolicy::default() });
+        let with = |mut tx: Tx, counterparty: &str| {tx.counterparty = Some(counterparty.to_string()); tx.timestamp = Some(0); tx};
+        engine.apply(with(deposit(1,"5.0"), "shop"));
+        engine.apply(Tx::new(TypeTx::ComplianceHold,1,2,None));
+        let before = (engine.counterparties.clone(), engine.review_queue.clone(), engine.settlements.clone(), engine.period.totals.clone());
+        let outer = engine.savepoint();
+        engine.apply(with(deposit(2,"2.0"), "shop"));
+        let inner = engine.savepoint();
+        engine.apply(with(deposit(3,"1.0"), "cafe"));
+        engine.apply(Tx::new(TypeTx::ComplianceRelease,1,4,None));
+        engine.apply(Tx::new(TypeTx::ComplianceHold,3,5,None));
+        engine.apply(Tx::new(TypeTx::Dispute,1,6,Some(amt("1.0"))));
+        engine.settle(i64::MAX);
+        assert!(engine.release(&inner));
+        assert!(engine.settlements.is_empty() && engine.review_queue.contains_key(&3));
+
+        assert!(engine.rollback_to(&outer));
+        assert_eq!((engine.counterparties.clone(), engine.review_queue.clone(), engine.settlements.clone(), engine.period.totals.clone()),before);
+        assert_eq!(engine.rejection_stats,RejectionStats::default());
+        assert_eq!(engine.take_changed(),vec![1]);
+    }
+    #[test]
+    fn atomic_batch()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        let report = engine.apply_batch(&[deposit(1,"5.0"), Tx::new(TypeTx::Dispute,1,1,None)]).unwrap();
+        assert_eq!(report.outcomes,vec![BatchOutcome::Applied, BatchOutcome::Applied]);
+
+        let batch = [Tx::new(TypeTx::Resolve,1,1,None), deposit(2,"1.0"), Tx::new(TypeTx::Dispute,1,2,Some(amt("1.0")))];
+        let e = engine.apply_batch(&batch).unwrap_err();
+        assert_eq!(e.report.rejected().collect::<Vec<_>>(),vec![(2, RejectReason::UnexpectedAmount)]);
+        assert_eq!(engine.clients[&1].acc.held,amt("5"));
+        assert!(engine.rejections.is_empty() && engine.clients[&1].history.len() == 1);
+
+        let e = engine.apply_batch_records(&[record(3,"1.0"), record(4,"0.00001")]).unwrap_err();
+        assert_eq!(e.to_string(),"batch rolled back, 1 of 2 rejected (row 1: Precision)");
+        assert!(engine.savepoints.is_empty());
+    }
+    #[test]
+    fn rolled_back_batch_leaves_dispute_window_alone()
+    {
+        let mut engine = Engine::new(EnginePolicy { dispute_window: DisputeWindow { days: None, rows: Some(2) }, ..EnginePolicy::default() });
+        engine.apply(deposit(1,"5.0"));
+        let batch = [deposit(2,"1.0"), deposit(3,"1.0"), Tx::new(TypeTx::Withdrawal,1,4,None)];
+        assert!(engine.apply_batch(&batch).is_err());
+        assert_eq!(engine.rows,1);
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        assert!(engine.rejections.is_empty());
+        assert_eq!(engine.clients[&1].acc.held,amt("5"));
+    }
+    #[test]
+    fn undo_last()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(deposit(1,"5.0"));
+        engine.apply(deposit(2,"3.0"));
+        assert!(!engine.undo(1,1));
+        assert!(engine.undo(1,2));
+        assert!(!engine.undo(1,2));
+        assert_eq!(engine.clients[&1].acc.total,amt("5"));
+        assert!(!engine.clients[&1].history.contains_key(&2));
+
+        engine.apply(Tx::new(TypeTx::Dispute,1,1,None));
+        engine.apply(Tx::new(TypeTx::Chargeback,1,1,None));
+        //a dispute of an unknown transaction changes nothing, so the chargeback is still the last change
+        engine.apply(Tx::new(TypeTx::Dispute,1,9,None));
+        assert!(engine.undo(1,1));
+        let c = &engine.clients[&1];
+        assert!(!c.acc.locked && c.history[&1].in_dispute);
+        assert_eq!((c.acc.held,c.acc.total),(amt("5"),amt("5")));
+    }
+    #[test]
+    fn amount_bounds()
+    {
+        let policy = EnginePolicy{min_amount:Some(amt("0.01")), max_amount:Some(amt("1000")), ..EnginePolicy::default()};
+        let mut engine = Engine::new(policy);
+        engine.apply(deposit(1,"1000.0001"));
+        engine.apply(deposit(2,"0.001"));
+        engine.apply(deposit(3,"1000.0"));
+        assert_eq!(engine.rejections[0].reason,RejectReason::AboveMaximum);
+        assert_eq!(engine.rejections[1].reason,RejectReason::BelowMinimum);
+        assert_eq!(engine.rejections.len(),2);
+        assert_eq!(engine.clients[&1].acc.total,amt("1000"));
+    }
+    #[test]
+    fn precision_rejection()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_record(record(1,"0.00001"));
+        assert_eq!(engine.rejections[0].reason,RejectReason::Precision);
+    }
+    #[test]
+    fn precision_rounding()
+    {
+        let policy = EnginePolicy{rounding:crate::RoundingMode::HalfEven, ..EnginePolicy::default()};
+        let mut engine = Engine::new(policy);
+        engine.apply_record(record(1,"0.12345"));
+        assert!(engine.rejections.is_empty());
+        assert_eq!(engine.clients[&1].acc.total,amt("0.1234"));
+    }
+    #[test]
+    fn overflow_rejection()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_record(record(1,"1e308"));
+        engine.apply(Tx{amount:Some(Amount::MAX), ..deposit(2,"0")});
+        engine.apply(deposit(3,"0.0001"));
+        assert_eq!(engine.rejections[0].reason,RejectReason::Overflow);
+        assert_eq!(engine.rejections[1].reason,RejectReason::Overflow);
+        assert_eq!(engine.rejections[1].tx,3);
+        assert_eq!(engine.clients[&1].acc.total,Amount::MAX);
+        assert_eq!(engine.clients[&1].history.len(),1);
+    }
+    #[test]
+    fn amount_presence()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(Tx{amount:None, ..deposit(1,"0")});
+        engine.apply(Tx{r#type:TypeTx::Withdrawal, amount:None, ..deposit(2,"0")});
+        engine.apply(deposit(1,"2.0"));
+        engine.apply(Tx{r#type:TypeTx::Dispute, ..deposit(1,"2.0")});
+        assert_eq!(engine.rejections[0].reason,RejectReason::MissingAmount);
+        assert_eq!(engine.rejections[1].reason,RejectReason::MissingAmount);
+        assert_eq!(engine.rejections[2].reason,RejectReason::UnexpectedAmount);
+        assert_eq!(engine.rejections.len(),3);
+        assert_eq!(engine.clients[&1].acc.available,amt("2.0"));
+    }
+    #[test]
+    fn unexpected_amount_ignored()
+    {
+        let policy = EnginePolicy{unexpected_amount:UnexpectedAmount::Ignore, ..EnginePolicy::default()};
+        let mut engine = Engine::new(policy);
+        engine.apply(deposit(1,"2.0"));
+        engine.apply(Tx{r#type:TypeTx::Dispute, ..deposit(1,"5.0")});
+        assert!(engine.rejections.is_empty());
+        assert_eq!(engine.clients[&1].acc.held,amt("2.0"));
+    }
+    #[test]
+    fn optional_columns()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        let mut with_timestamp = record(1,"1.0");
+        with_timestamp.timestamp = Some("1700000000".to_string());
+        let mut bad_timestamp = record(2,"1.0");
+        bad_timestamp.timestamp = Some("yesterday".to_string());
+        let tx = with_timestamp.to_tx(engine.policy.rounding).unwrap();
+        assert_eq!(tx.timestamp,Some(1700000000));
+        engine.apply_record(bad_timestamp);
+        assert_eq!(engine.rejections[0].reason,RejectReason::InvalidTimestamp);
+    }
+    #[test]
+    fn columns_match_rows()
+    {
+        let policy = EnginePolicy{max_amount:Some(amt("100.0")), ..EnginePolicy::default()};
+        let types = [TypeTx::Deposit, TypeTx::Deposit, TypeTx::Withdrawal, TypeTx::Dispute, TypeTx::Deposit, TypeTx::Resolve, TypeTx::Deposit];
+        let clients = [1, 2, 1, 1, 2, 1, 2];
+        let txs = [1, 2, 3, 1, 4, 1, SYNTHETIC_TX_MIN];
+        let amounts = [Some(amt("5.0")), None, Some(amt("1.0")), Some(amt("1.0")), Some(amt("500.0")), None, Some(amt("1.0"))];
+        let mut columnar = Engine::new(policy.clone());
+        columnar.apply_columns(&types, &clients, &txs, &amounts).unwrap();
+        let mut rows = Engine::new(policy);
+        for i in 0..types.len()
+        {
+            rows.apply(Tx::new(types[i], clients[i], txs[i], amounts[i]));
+        }
+        assert_eq!(columnar.rows,7);
+        assert_eq!(columnar.rejections.iter().map(|r| (r.tx, r.reason)).collect::<Vec<_>>(),vec![(2, RejectReason::MissingAmount), (1, RejectReason::UnexpectedAmount), (4, RejectReason::AboveMaximum), (SYNTHETIC_TX_MIN, RejectReason::ReservedTx)]);
+        assert_eq!(columnar.rejections.iter().map(|r| (r.tx, r.reason)).collect::<Vec<_>>(),rows.rejections.iter().map(|r| (r.tx, r.reason)).collect::<Vec<_>>());
+        assert_eq!(columnar.clients[&1].acc,rows.clients[&1].acc);
+        assert_eq!(columnar.clients[&1].acc.available,amt("4.0"));
+
+        let err = columnar.apply_columns(&types, &clients[..2], &txs, &amounts).unwrap_err();
+        assert_eq!(err,ColumnLengths { types: 7, clients: 2, txs: 7, amounts: 7 });
+        assert_eq!(columnar.rows,7);
+    }
+    #[test]
+    fn global_dedup()
+    {
+        let policy = EnginePolicy{global_dedup:Some(GlobalDedup{bloom_rate:Some(0.01), expected:100}), ..EnginePolicy::default()};
+        let mut engine = Engine::new(policy.clone());
+        engine.apply(deposit(1,"5.0"));
+        engine.apply(Tx{client:2, ..deposit(1,"3.0")});
+        engine.apply(Tx{r#type:TypeTx::Withdrawal, tx:2, ..deposit(1,"1.0")});
+        engine.apply(Tx{client:2, ..deposit(2,"1.0")});
+        //a rejected deposit doesn't use up its ID
+        engine.apply(Tx{client:3, amount:None, ..deposit(3,"1.0")});
+        engine.apply(Tx{client:3, ..deposit(3,"1.0")});
+        //nor does a withdrawal left alone for want of funds
+        engine.apply(Tx{r#type:TypeTx::Withdrawal, client:3, tx:6, ..deposit(6,"9.0")});
+        engine.apply(Tx{r#type:TypeTx::Withdrawal, client:3, tx:6, ..deposit(6,"0.5")});
+        assert_eq!(engine.clients[&3].acc.available,amt("0.5"));
+        assert_eq!(engine.rejections.iter().map(|r| (r.client, r.tx, r.reason)).collect::<Vec<_>>(),vec![(2, 1, RejectReason::DuplicateTx), (2, 2, RejectReason::DuplicateTx), (3, 3, RejectReason::MissingAmount)]);
+        assert_eq!(engine.clients[&1].acc.available,amt("4.0"));
+        assert!(!engine.clients.contains_key(&2));
+        assert_eq!(engine.dedup_stats().map(|s| (s.checked, s.duplicates)),Some((7, 2)));
+
+        let savepoint = engine.savepoint();
+        engine.apply(Tx{client:4, ..deposit(4,"1.0")});
+        engine.rollback_to(&savepoint);
+        engine.apply(Tx{client:5, ..deposit(4,"1.0")});
+        assert_eq!(engine.clients[&5].acc.available,amt("1.0"));
+
+        let mut snapshot = Vec::new();
+        engine.snapshot_to(&mut snapshot, None).unwrap();
+        let mut restored = Engine::restore_from(snapshot.as_slice(), policy, None).unwrap();
+        restored.apply(Tx{client:6, ..deposit(4,"1.0")});
+        assert_eq!(restored.rejections.last().map(|r| r.reason),Some(RejectReason::DuplicateTx));
+    }
+    #[test]
+    fn rejection_report()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_record(record(7,"NaN"));
+        let mut out = Vec::new();
+        write_rejections(&engine.rejections, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "client,tx,type,amount,reason,rule\n1,7,deposit,NaN,non_finite,\n");
+    }
+}
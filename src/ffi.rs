@@ -0,0 +1,168 @@
+use std::{ffi::CStr, fs::File, os::raw::c_char};
+use crate::{Engine, EnginePolicy, OPTIONAL_COLUMNS, REQUIRED_COLUMNS, TxRecord, TypeTx, OutputFormat, write_output};
+
+/// The call succeeded
+pub const ENGINE_OK: i32 = 0;
+/// The client asked for hasn't been seen
+pub const ENGINE_NOT_FOUND: i32 = 1;
+/// A null pointer, or a row that isn't a transaction
+pub const ENGINE_INVALID: i32 = -1;
+/// The report couldn't be written
+pub const ENGINE_IO_ERROR: i32 = -2;
+/// Amounts are handed out as whole numbers of this fraction of a unit
+pub const ENGINE_AMOUNT_SCALE: i64 = 10_000;
+
+///
+/// The balances of a single client, with amounts in 1/ENGINE_AMOUNT_SCALE units
+///
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EngineAccount
+{
+    pub client: u16,
+    pub available: i64,
+    pub held: i64,
+    pub total: i64,
+    pub locked: bool,
+    pub pending: i64,
+}
+
+/// Reads a single csv row, in the column order of the input file
+fn parse_row(row: &str) -> Option<TxRecord>
+{
+    let mut rdr = csv::ReaderBuilder::new().has_headers(false).from_reader(row.as_bytes());
+    let fields = rdr.records().next()?.ok()?;
+    let headers: csv::StringRecord = REQUIRED_COLUMNS.iter().chain(OPTIONAL_COLUMNS.iter()).take(fields.len()).collect();
+    //no custom types can be registered through the C API, so they can't be read
+    fields.deserialize(Some(&headers)).ok().filter(|r: &TxRecord| r.r#type != TypeTx::Custom)
+}
+
+/// Returns a new engine following the default policy, to be freed with engine_free
+#[no_mangle]
+pub extern "C" fn engine_new() -> *mut Engine
+{
+    Box::into_raw(Box::new(Engine::new(EnginePolicy::default())))
+}
+
+/// Frees an engine returned by engine_new
+///
+/// # Safety
+///
+/// 'engine' has to come from engine_new and not be used again afterwards
+#[no_mangle]
+pub unsafe extern "C" fn engine_free(engine: *mut Engine)
+{
+    if !engine.is_null()
+    {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Applies a single csv row, E.G. "deposit,1,1,1.5", with the columns in the
+/// order of the input file and no header
+///
+/// Returns ENGINE_OK once the row is applied, even if the engine refused the
+/// transaction, and ENGINE_INVALID if the row can't be read at all
+///
+/// # Safety
+///
+/// 'engine' has to come from engine_new, and 'row' has to be a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn engine_apply_csv_row(engine: *mut Engine, row: *const c_char) -> i32
+{
+    if engine.is_null() || row.is_null() {return ENGINE_INVALID}
+    let record = match CStr::from_ptr(row).to_str().ok().and_then(parse_row)
+    {
+        Some(record) => record,
+        None => return ENGINE_INVALID
+    };
+    (*engine).apply_record(record);
+    ENGINE_OK
+}
+
+/// Copies the balances of a client into 'out'
+///
+/// Returns ENGINE_NOT_FOUND, and leaves 'out' alone, if the client hasn't been seen
+///
+/// # Safety
+///
+/// 'engine' has to come from engine_new, and 'out' has to point to an EngineAccount
+#[no_mangle]
+pub unsafe extern "C" fn engine_account(engine: *const Engine, client: u16, out: *mut EngineAccount) -> i32
+{
+    if engine.is_null() || out.is_null() {return ENGINE_INVALID}
+    match (*engine).clients.get(&client)
+    {
+        Some(c) => {
+            *out = EngineAccount {
+                client,
+                available: c.acc.available.minor(),
+                held: c.acc.held.minor(),
+                total: c.acc.total.minor(),
+                locked: c.acc.locked,
+                pending: c.acc.pending.minor()
+            };
+            ENGINE_OK
+        },
+        None => ENGINE_NOT_FOUND
+    }
+}
+
+/// Writes the account report as csv to the file at 'path', replacing it
+///
+/// # Safety
+///
+/// 'engine' has to come from engine_new, and 'path' has to be a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn engine_write_report(engine: *const Engine, path: *const c_char) -> i32
+{
+    if engine.is_null() || path.is_null() {return ENGINE_INVALID}
+    let path = match CStr::from_ptr(path).to_str()
+    {
+        Ok(path) => path,
+        Err(_) => return ENGINE_INVALID
+    };
+    let written = File::create(path)
+        .and_then(|f| OutputFormat::Csv.sink(f))
+        .and_then(|mut sink| write_output(&(*engine).clients, sink.as_mut()));
+    match written
+    {
+        Ok(()) => ENGINE_OK,
+        Err(_) => ENGINE_IO_ERROR
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn engine_through_ffi()
+    {
+        let engine = engine_new();
+        let path = std::env::temp_dir().join("csv_transactions_engine_through_ffi.csv");
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            for row in ["deposit,1,1,1.5", "withdrawal,1,2,0.25", "dispute,1,1,", "deposit,2,3,1.0,1700000000,EUR,memo"]
+            {
+                assert_eq!(engine_apply_csv_row(engine, CString::new(row).unwrap().as_ptr()),ENGINE_OK);
+            }
+            assert_eq!(engine_apply_csv_row(engine, CString::new("refund,1,4,1.0").unwrap().as_ptr()),ENGINE_INVALID);
+            assert_eq!(engine_apply_csv_row(engine, std::ptr::null()),ENGINE_INVALID);
+
+            let mut acc = EngineAccount::default();
+            assert_eq!(engine_account(engine, 1, &mut acc),ENGINE_OK);
+            assert_eq!(acc.held,15000);
+            assert_eq!(acc.available,-2500);
+            assert_eq!(engine_account(engine, 9, &mut acc),ENGINE_NOT_FOUND);
+
+            assert_eq!(engine_write_report(engine, c_path.as_ptr()),ENGINE_OK);
+            engine_free(engine);
+        }
+        let report = std::fs::read_to_string(&path).unwrap();
+        assert!(report.starts_with("client,available,held,total,locked,pending\n"));
+        assert_eq!(report.lines().count(),3);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+use std::{convert::TryFrom, fmt, ops::RangeInclusive, str::FromStr};
+use serde::Deserialize;
+use crate::{TxRecord, TypeTx};
+
+///
+/// A set of client IDs, given as single IDs and inclusive ranges, E.G. "100-200,5000"
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ClientFilter
+{
+    pub ranges: Vec<RangeInclusive<u16>>,
+}
+impl ClientFilter
+{
+    /// Whether the client is in the set
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client ID
+    pub fn contains(&self, client: u16) -> bool
+    {
+        self.ranges.iter().any(|r| r.contains(&client))
+    }
+}
+impl FromStr for ClientFilter
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut ranges = Vec::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty())
+        {
+            let id = |text: &str| text.trim().parse::<u16>().map_err(|_| format!("'{}' isn't a client ID", text.trim()));
+            let range = match part.split_once('-')
+            {
+                Some((start, end)) => id(start)?..=id(end)?,
+                None => id(part)?..=id(part)?
+            };
+            if range.is_empty()
+            {
+                return Err(format!("the range '{}' is empty", part));
+            }
+            ranges.push(range);
+        }
+        if ranges.is_empty()
+        {
+            return Err("no clients given".to_string());
+        }
+        Ok(ClientFilter { ranges })
+    }
+}
+impl TryFrom<String> for ClientFilter
+{
+    type Error = String;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+impl fmt::Display for ClientFilter
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let parts: Vec<String> = self.ranges.iter().map(|r| match r.start() == r.end()
+        {
+            true => r.start().to_string(),
+            false => format!("{}-{}", r.start(), r.end())
+        }).collect();
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+/// Parses a list of transaction types, E.G. "chargeback,dispute"
+///
+/// # Arguments
+///
+/// * 'text' - The types, separated by commas
+pub fn parse_types(text: &str) -> Result<Vec<TypeTx>, String>
+{
+    text.split(',').map(str::trim).filter(|t| !t.is_empty()).map(str::parse).collect()
+}
+
+///
+/// Where a what-if run stops reading the input
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StopAt
+{
+    /// How many rows are processed, not counting those that couldn't be read
+    pub row: Option<usize>,
+    /// Stops at the first row with a later timestamp; rows without one don't stop it
+    pub timestamp: Option<i64>,
+}
+impl StopAt
+{
+    /// Returns the records up to where the run stops
+    ///
+    /// # Arguments
+    ///
+    /// * 'records' - The records in the order of the input
+    pub fn apply<I: Iterator<Item = TxRecord>>(self, records: I) -> impl Iterator<Item = TxRecord>
+    {
+        records.take(self.row.unwrap_or(usize::MAX)).take_while(move |r| {
+            let timestamp = r.timestamp.as_deref().and_then(|t| t.parse::<i64>().ok());
+            !matches!((self.timestamp, timestamp), (Some(stop), Some(t)) if t > stop)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, Engine, EnginePolicy};
+
+    #[test]
+    fn client_subset()
+    {
+        let filter: ClientFilter = "100-200, 5000".parse().unwrap();
+        assert!(filter.contains(100) && filter.contains(200) && filter.contains(5000));
+        assert!(!filter.contains(99) && !filter.contains(4999));
+        assert_eq!(filter.to_string(),"100-200,5000");
+        assert!("200-100".parse::<ClientFilter>().is_err());
+        assert!("abc".parse::<ClientFilter>().is_err());
+        assert!("".parse::<ClientFilter>().is_err());
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_client_filter(move |client| filter.contains(client));
+        engine.apply_record(TxRecord::new(TypeTx::Deposit, 150, 1, Some("2.0".to_string())));
+        engine.apply_record(TxRecord::new(TypeTx::Deposit, 1, 2, Some("2.0".to_string())));
+        engine.apply_record(TxRecord::new(TypeTx::Deposit, 2, 3, Some("not a number".to_string())));
+        assert_eq!(engine.clients.len(),1);
+        assert_eq!(engine.clients[&150].acc.total,Amount::from_minor(20000));
+        assert_eq!(engine.skipped,2);
+        assert!(engine.rejections.is_empty());
+    }
+
+    #[test]
+    fn what_if()
+    {
+        let mut records = vec![
+            TxRecord::new(TypeTx::Deposit, 1, 1, Some("5.0".to_string())),
+            TxRecord::new(TypeTx::Dispute, 1, 1, None),
+            TxRecord::new(TypeTx::Chargeback, 1, 1, None),
+            TxRecord::new(TypeTx::Deposit, 1, 2, Some("1.0".to_string())),
+        ];
+        for (i, r) in records.iter_mut().enumerate() {r.timestamp = Some((i as i64 * 10).to_string());}
+        assert_eq!(parse_types("chargeback, dispute"),Ok(vec![TypeTx::Chargeback, TypeTx::Dispute]));
+        assert!(parse_types("refund").is_err());
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_excluded_types(parse_types("chargeback").unwrap());
+        records.iter().cloned().for_each(|r| engine.apply_record(r));
+        assert_eq!((engine.clients[&1].acc.held, engine.clients[&1].acc.locked),(Amount::from_minor(50000), false));
+        assert_eq!(engine.skipped,1);
+
+        let rows = |stop: StopAt| stop.apply(records.clone().into_iter()).count();
+        assert_eq!(rows(StopAt::default()),4);
+        assert_eq!(rows(StopAt { row: Some(2), timestamp: None }),2);
+        assert_eq!(rows(StopAt { row: None, timestamp: Some(20) }),3);
+        assert_eq!(rows(StopAt { row: Some(1), timestamp: Some(20) }),1);
+    }
+}
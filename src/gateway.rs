@@ -0,0 +1,445 @@
+use std::{collections::HashMap, fmt, io, time::Instant};
+use serde::{Deserialize, Serialize};
+use crate::{Account, AccountPage, Amount, Engine, MergeError, PageRequest, RejectionBroadcast, RejectionSubscriber, SnapshotKey, TxRecord, TypeTx};
+
+///
+/// What the holder of an API token may do
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum Role
+{
+    /// May submit deposits, withdrawals, disputes, resolves and chargebacks
+    #[serde(rename = "submitter")]
+    Submitter,
+    /// May also unlock accounts, post adjustments, place compliance holds and take snapshots
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+///
+/// The API tokens a server accepts, and the role of each
+///
+#[derive(Debug, Clone, Default)]
+pub struct ApiTokens
+{
+    tokens: HashMap<String, Role>,
+}
+impl ApiTokens
+{
+    /// Reads the tokens, a csv with the columns token and role, the role being
+    /// submitter or admin
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The tokens as csv, with a header row
+    pub fn read<R: io::Read>(input: R) -> csv::Result<ApiTokens>
+    {
+        #[derive(Deserialize)]
+        struct TokenRow
+        {
+            token: String,
+            role: Role,
+        }
+        let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+        let mut tokens = ApiTokens::default();
+        for row in rdr.deserialize()
+        {
+            let row: TokenRow = row?;
+            tokens.insert(row.token, row.role);
+        }
+        Ok(tokens)
+    }
+    /// Accepts a token, replacing its role if it was already known
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The token as the client sends it
+    /// * 'role' - What it may do
+    pub fn insert(&mut self, token: String, role: Role)
+    {
+        self.tokens.insert(token, role);
+    }
+    /// The role of a token, None if it isn't accepted
+    pub fn role(&self, token: &str) -> Option<Role>
+    {
+        self.tokens.get(token).copied()
+    }
+}
+
+///
+/// Why a request was refused before it reached the engine
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthError
+{
+    /// The token isn't one of the accepted ones
+    UnknownToken,
+    /// The token is accepted, but its role doesn't allow the operation
+    Forbidden(&'static str),
+    /// Too many requests, for the client if given and otherwise across all of them
+    RateLimited(Option<u16>),
+}
+impl AuthError
+{
+    /// The HTTP status a server answers with
+    pub fn status(&self) -> u16
+    {
+        match self
+        {
+            AuthError::UnknownToken => 401,
+            AuthError::Forbidden(_) => 403,
+            AuthError::RateLimited(_) => 429,
+        }
+    }
+}
+impl fmt::Display for AuthError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            AuthError::UnknownToken => write!(f, "unknown API token"),
+            AuthError::Forbidden(operation) => write!(f, "{} needs an admin token", operation),
+            AuthError::RateLimited(Some(client)) => write!(f, "too many requests for client {}", client),
+            AuthError::RateLimited(None) => write!(f, "too many requests"),
+        }
+    }
+}
+impl From<AuthError> for io::Error
+{
+    fn from(e: AuthError) -> Self {
+        let kind = match e
+        {
+            AuthError::RateLimited(_) => io::ErrorKind::WouldBlock,
+            _ => io::ErrorKind::PermissionDenied
+        };
+        io::Error::new(kind, e.to_string())
+    }
+}
+
+///
+/// How many requests are let through, refilling steadily up to a burst
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit
+{
+    /// Requests let through per second once the burst is used up
+    pub per_second: f64,
+    /// Requests let through at once after a quiet spell
+    pub burst: u32,
+}
+
+///
+/// The request rate limits of a gateway, none by default
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateLimits
+{
+    /// Across every request
+    pub global: Option<RateLimit>,
+    /// For each client, so a single integration can't starve the others
+    pub per_client: Option<RateLimit>,
+}
+
+/// A token bucket, refilled at the rate of its limit
+#[derive(Debug, Clone)]
+struct TokenBucket
+{
+    limit: RateLimit,
+    tokens: f64,
+    last: Instant,
+}
+impl TokenBucket
+{
+    fn new(limit: RateLimit, now: Instant) -> TokenBucket
+    {
+        TokenBucket { limit, tokens: f64::from(limit.burst), last: now }
+    }
+    /// Adds the tokens earned since the last refill, and whether there's one to take
+    fn refill(&mut self, now: Instant) -> bool
+    {
+        let earned = now.saturating_duration_since(self.last).as_secs_f64() * self.limit.per_second;
+        self.tokens = (self.tokens + earned).min(f64::from(self.limit.burst));
+        self.last = now;
+        self.tokens >= 1.0
+    }
+}
+
+///
+/// What the gateway let through and refused, by why
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct GatewayStats
+{
+    /// Requests passed on to the engine
+    pub accepted: u64,
+    /// Requests with an unknown token
+    pub unauthorized: u64,
+    /// Requests above the role of their token
+    pub forbidden: u64,
+    /// Requests refused by a rate limit
+    pub rate_limited: u64,
+}
+
+///
+/// The engine as a server exposes it, every operation gated on the role of the
+/// caller's API token
+///
+pub struct Gateway
+{
+    pub engine: Engine,
+    tokens: ApiTokens,
+    limits: RateLimits,
+    global_bucket: Option<TokenBucket>,
+    client_buckets: HashMap<u16, TokenBucket>,
+    /// Streams rejections to subscribers, added to the engine on the first subscription
+    rejection_stream: Option<RejectionBroadcast>,
+    /// Counts of the requests let through and refused
+    pub stats: GatewayStats,
+}
+impl Gateway
+{
+    /// Returns a gateway to the engine accepting the given tokens
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine requests are applied to
+    /// * 'tokens' - The accepted API tokens and their roles
+    pub fn new(engine: Engine, tokens: ApiTokens) -> Gateway
+    {
+        Gateway { engine, tokens, limits: RateLimits::default(), global_bucket: None, client_buckets: HashMap::new(), rejection_stream: None, stats: GatewayStats::default() }
+    }
+    /// Limits how fast requests are let through, starting every bucket full
+    ///
+    /// # Arguments
+    ///
+    /// * 'limits' - The global and per client limits
+    pub fn set_rate_limits(&mut self, limits: RateLimits)
+    {
+        self.limits = limits;
+        self.global_bucket = limits.global.map(|limit| TokenBucket::new(limit, Instant::now()));
+        self.client_buckets.clear();
+    }
+    /// Checks the token may carry out the operation and that no rate limit is hit,
+    /// counting the outcome
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'operation' - What is asked for, named in the error
+    /// * 'admin' - Whether the operation needs an admin token
+    /// * 'client' - The client the operation is for, if any
+    fn admit(&mut self, token: &str, operation: &'static str, admin: bool, client: Option<u16>) -> Result<(), AuthError>
+    {
+        let admitted = self.authorize(token, operation, admin).and_then(|_| self.take(client, Instant::now()));
+        match admitted
+        {
+            Ok(()) => self.stats.accepted += 1,
+            Err(AuthError::UnknownToken) => self.stats.unauthorized += 1,
+            Err(AuthError::Forbidden(_)) => self.stats.forbidden += 1,
+            Err(AuthError::RateLimited(_)) => self.stats.rate_limited += 1,
+        }
+        admitted
+    }
+    /// Takes a token from the client's bucket and the global one, or from neither
+    /// if either is empty
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client the request is for, if any
+    /// * 'now' - The time the buckets are refilled up to
+    fn take(&mut self, client: Option<u16>, now: Instant) -> Result<(), AuthError>
+    {
+        let client_bucket = match (client, self.limits.per_client)
+        {
+            (Some(client), Some(limit)) => Some(self.client_buckets.entry(client).or_insert_with(|| TokenBucket::new(limit, now))),
+            _ => None
+        };
+        if !client_bucket.is_none_or(|b| b.refill(now)) {return Err(AuthError::RateLimited(client))}
+        if !self.global_bucket.as_mut().is_none_or(|b| b.refill(now)) {return Err(AuthError::RateLimited(None))}
+        let client_bucket = match client
+        {
+            Some(c) => self.client_buckets.get_mut(&c),
+            None => None
+        };
+        for bucket in client_bucket.into_iter().chain(self.global_bucket.as_mut())
+        {
+            bucket.tokens -= 1.0;
+        }
+        Ok(())
+    }
+    /// Checks that the token may carry out the operation
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'operation' - What is asked for, named in the error
+    /// * 'admin' - Whether the operation needs an admin token
+    fn authorize(&self, token: &str, operation: &'static str, admin: bool) -> Result<(), AuthError>
+    {
+        match self.tokens.role(token)
+        {
+            None => Err(AuthError::UnknownToken),
+            Some(Role::Submitter) if admin => Err(AuthError::Forbidden(operation)),
+            Some(_) => Ok(())
+        }
+    }
+    /// Applies a transaction row, compliance holds and releases needing an admin token
+    ///
+    /// A row that is let through but fails validation ends up in the engine's
+    /// rejections, the same as when read from a file
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'record' - The transaction as submitted
+    pub fn submit(&mut self, token: &str, record: TxRecord) -> Result<(), AuthError>
+    {
+        let admin = matches!(record.r#type, TypeTx::ComplianceHold | TypeTx::ComplianceRelease);
+        self.admit(token, record.r#type.as_str(), admin, Some(record.client))?;
+        self.engine.apply_record(record);
+        Ok(())
+    }
+    /// Unlocks an account, see Engine::unlock; needs an admin token
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'client' - The client ID
+    pub fn unlock(&mut self, token: &str, client: u16) -> Result<bool, AuthError>
+    {
+        self.admit(token, "unlock", true, Some(client))?;
+        Ok(self.engine.unlock(client))
+    }
+    /// Posts a manual adjustment, see Engine::adjust; needs an admin token
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'client' - The client ID
+    /// * 'amount' - How much to credit, negative to debit
+    /// * 'memo' - Why it was made
+    pub fn adjust(&mut self, token: &str, client: u16, amount: Amount, memo: Option<String>) -> Result<Option<u32>, AuthError>
+    {
+        self.admit(token, "adjustment", true, Some(client))?;
+        Ok(self.engine.adjust(client, amount, memo))
+    }
+    /// Merges a client into another, see Engine::merge_clients; needs an admin token
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'from' - The client merged, and closed
+    /// * 'into' - The client merged into
+    pub fn merge_clients(&mut self, token: &str, from: u16, into: u16) -> Result<Result<Account, MergeError>, AuthError>
+    {
+        self.admit(token, "merge", true, Some(into))?;
+        Ok(self.engine.merge_clients(from, into))
+    }
+    /// Lists a page of accounts, ordered by client, for GET /accounts; any token may
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'page' - The cursor and limit, E.G. parsed from the query string
+    pub fn accounts(&mut self, token: &str, page: PageRequest) -> Result<AccountPage, AuthError>
+    {
+        self.admit(token, "accounts", false, None)?;
+        Ok(self.engine.accounts_page(page))
+    }
+    /// Subscribes to every rejection from now on, for GET /rejections/stream served as
+    /// server-sent events or over a websocket; needs an admin token
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    pub fn subscribe_rejections(&mut self, token: &str) -> Result<RejectionSubscriber, AuthError>
+    {
+        self.admit(token, "rejection stream", true, None)?;
+        let engine = &mut self.engine;
+        Ok(self.rejection_stream.get_or_insert_with(|| {
+            let broadcast = RejectionBroadcast::default();
+            engine.add_rejection_listener(Box::new(broadcast.clone()));
+            broadcast
+        }).subscribe())
+    }
+    /// Writes a snapshot of the engine, see Engine::snapshot_to; needs an admin token
+    ///
+    /// # Arguments
+    ///
+    /// * 'token' - The caller's API token
+    /// * 'out' - Where to write the snapshot to
+    /// * 'key' - The key to encrypt it with, if any
+    pub fn snapshot<W: io::Write>(&mut self, token: &str, out: W, key: Option<&SnapshotKey>) -> io::Result<()>
+    {
+        self.admit(token, "snapshot", true, None)?;
+        Ok(self.engine.snapshot_to(out, key)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, ADJUSTMENT_MEMO};
+
+    #[test]
+    fn roles_gate_admin_operations()
+    {
+        let tokens = ApiTokens::read("token,role\nteller,submitter\nroot,admin\n".as_bytes()).unwrap();
+        let mut gateway = Gateway::new(Engine::new(EnginePolicy::default()), tokens);
+        let record = |r#type, tx, amount: Option<&str>| TxRecord::new(r#type, 1, tx, amount.map(String::from));
+
+        assert_eq!(gateway.submit("nobody", record(TypeTx::Deposit, 1, Some("5.0"))),Err(AuthError::UnknownToken));
+        gateway.submit("teller", record(TypeTx::Deposit, 1, Some("5.0"))).unwrap();
+        gateway.submit("teller", record(TypeTx::Dispute, 1, None)).unwrap();
+        gateway.submit("teller", record(TypeTx::Chargeback, 1, None)).unwrap();
+        assert!(gateway.engine.clients[&1].acc.locked);
+        assert_eq!(gateway.submit("teller", record(TypeTx::ComplianceHold, 2, None)),Err(AuthError::Forbidden("compliance_hold")));
+        assert_eq!(gateway.unlock("teller", 1),Err(AuthError::Forbidden("unlock")));
+        assert_eq!(gateway.adjust("teller", 1, Amount::from_minor(10000), None),Err(AuthError::Forbidden("adjustment")));
+        assert_eq!(gateway.snapshot("teller", Vec::new(), None).unwrap_err().kind(),io::ErrorKind::PermissionDenied);
+
+        assert_eq!(gateway.unlock("root", 1),Ok(true));
+        assert_eq!(gateway.unlock("root", 1),Ok(false));
+        let tx = gateway.adjust("root", 1, Amount::from_minor(30000), None).unwrap().unwrap();
+        gateway.adjust("root", 1, Amount::from_minor(-10000), Some("fee".to_string())).unwrap();
+        assert_eq!(gateway.engine.clients[&1].acc.total,Amount::from_minor(20000));
+        assert_eq!(gateway.engine.clients[&1].history[&tx].memo.as_deref(),Some(ADJUSTMENT_MEMO));
+        assert!(gateway.snapshot("root", Vec::new(), None).is_ok());
+        let page = gateway.accounts("teller", "limit=1".parse().unwrap()).unwrap();
+        assert_eq!((page.accounts.len(), page.next),(1, None));
+        assert_eq!(gateway.accounts("nobody", PageRequest::default()).unwrap_err().status(),401);
+        assert!(matches!(gateway.subscribe_rejections("teller"), Err(AuthError::Forbidden("rejection stream"))));
+        let rejections = gateway.subscribe_rejections("root").unwrap();
+        gateway.submit("teller", record(TypeTx::Deposit, 9, Some("x"))).unwrap();
+        assert_eq!(rejections.next(std::time::Duration::from_secs(1)).map(|e| (e.tx, e.reason)),Some((9, crate::RejectReason::InvalidAmount)));
+    }
+
+    #[test]
+    fn rate_limits()
+    {
+        let mut tokens = ApiTokens::default();
+        tokens.insert("teller".to_string(), Role::Submitter);
+        let mut gateway = Gateway::new(Engine::new(EnginePolicy::default()), tokens);
+        gateway.set_rate_limits(RateLimits {
+            global: Some(RateLimit { per_second: 0.0, burst: 3 }),
+            per_client: Some(RateLimit { per_second: 0.0, burst: 2 }),
+        });
+        let deposit = |client, tx| TxRecord::new(TypeTx::Deposit, client, tx, Some("1.0".to_string()));
+        gateway.submit("teller", deposit(1, 1)).unwrap();
+        gateway.submit("teller", deposit(1, 2)).unwrap();
+        let limited = gateway.submit("teller", deposit(1, 3)).unwrap_err();
+        assert_eq!((limited.clone(), limited.status()),(AuthError::RateLimited(Some(1)), 429));
+        gateway.submit("teller", deposit(2, 4)).unwrap();
+        assert_eq!(gateway.submit("teller", deposit(3, 5)),Err(AuthError::RateLimited(None)));
+        assert_eq!(gateway.submit("nobody", deposit(3, 6)).unwrap_err().status(),401);
+        assert_eq!(gateway.stats,GatewayStats { accepted: 3, unauthorized: 1, forbidden: 0, rate_limited: 2 });
+        assert!(!gateway.engine.clients[&1].history.contains_key(&3));
+
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(RateLimit { per_second: 2.0, burst: 1 }, start);
+        bucket.tokens = 0.0;
+        assert!(!bucket.refill(start + std::time::Duration::from_millis(400)));
+        assert!(bucket.refill(start + std::time::Duration::from_millis(500)));
+        assert!(bucket.refill(start + std::time::Duration::from_secs(60)) && bucket.tokens == 1.0);
+    }
+}
@@ -0,0 +1,216 @@
+use std::io::{self, Write};
+use serde::Serialize;
+use crate::AMOUNT_PRECISION;
+
+///
+/// What the generated input looks like
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeneratorConfig
+{
+    /// How many clients the rows are spread over, IDs 1 and up
+    pub clients: u16,
+    /// How many rows are written, not counting the header
+    pub rows: u64,
+    /// The share of rows that dispute an earlier deposit
+    pub dispute_rate: f64,
+    /// The share of rows that repeat an earlier deposit or withdrawal, transaction ID and all
+    pub duplicate_rate: f64,
+    /// The share of rows that can't be read as a transaction
+    pub malformed_rate: f64,
+    /// Where the random numbers start, the same seed always gives the same rows
+    pub seed: u64,
+}
+impl Default for GeneratorConfig
+{
+    fn default() -> Self {
+        GeneratorConfig { clients: 1000, rows: 100_000, dispute_rate: 0.01, duplicate_rate: 0.0, malformed_rate: 0.0, seed: 1 }
+    }
+}
+
+///
+/// How many rows of each kind were generated
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct GeneratorStats
+{
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+    pub duplicates: u64,
+    pub malformed: u64,
+}
+
+/// Parses a count given on the command line, with an optional k or M suffix, E.G. "10k"
+///
+/// # Arguments
+///
+/// * 'text' - The count as given
+pub fn parse_count(text: &str) -> Option<u64>
+{
+    let (digits, scale) = match text.trim()
+    {
+        t if t.ends_with('k') || t.ends_with('K') => (&t[..t.len() - 1], 1_000),
+        t if t.ends_with('M') || t.ends_with('m') => (&t[..t.len() - 1], 1_000_000),
+        t => (t, 1)
+    };
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// A small splitmix64 generator, so whatever is drawn can be reproduced from the
+/// seed without pulling in a dependency
+pub(crate) struct Rng(pub(crate) u64);
+impl Rng
+{
+    pub(crate) fn next(&mut self) -> u64
+    {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    /// A number in [0, 1)
+    pub(crate) fn chance(&mut self) -> f64
+    {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    /// A number in [0, n)
+    pub(crate) fn below(&mut self, n: u64) -> u64
+    {
+        self.next() % n.max(1)
+    }
+}
+
+/// How many of the latest deposits and withdrawals are kept to dispute or repeat
+const RECENT: usize = 1024;
+
+/// Writes random transactions as csv with a header row, for benchmarking and fuzzing
+///
+/// Disputes only name deposits made earlier by the same client, and every resolve and
+/// chargeback follows a dispute, so the chains are valid. Withdrawals can still overdraw,
+/// as they would in real input. Amounts have up to four decimals
+///
+/// # Arguments
+///
+/// * 'config' - How many rows and clients, and how often the unusual rows come up
+/// * 'out' - Where to write the csv
+pub fn generate<W: Write>(config: &GeneratorConfig, out: W) -> io::Result<GeneratorStats>
+{
+    let mut out = io::BufWriter::new(out);
+    let mut rng = Rng(config.seed);
+    let mut stats = GeneratorStats::default();
+    //the latest deposits and withdrawals as written, and the deposits that can still be disputed
+    let mut recent: Vec<String> = Vec::with_capacity(RECENT);
+    let mut deposits: Vec<(u16, u32)> = Vec::with_capacity(RECENT);
+    let mut disputed: Vec<(u16, u32)> = Vec::new();
+    let mut next_tx: u32 = 1;
+    writeln!(out, "type,client,tx,amount")?;
+    for _ in 0..config.rows
+    {
+        let roll = rng.chance();
+        if roll < config.malformed_rate
+        {
+            let row = match rng.below(4)
+            {
+                0 => format!("deposit,{},{},not_a_number", 1 + rng.below(config.clients.into()), rng.below(u32::MAX.into())),
+                1 => "withdrawal,1".to_string(),
+                2 => format!("refund,{},{},1.0", 1 + rng.below(config.clients.into()), rng.below(u32::MAX.into())),
+                _ => format!("deposit,{},{},1.0", 70000 + rng.below(1000), rng.below(u32::MAX.into()))
+            };
+            writeln!(out, "{}", row)?;
+            stats.malformed += 1;
+            continue;
+        }
+        if roll < config.malformed_rate + config.duplicate_rate && !recent.is_empty()
+        {
+            writeln!(out, "{}", recent[rng.below(recent.len() as u64) as usize])?;
+            stats.duplicates += 1;
+            continue;
+        }
+        if roll < config.malformed_rate + config.duplicate_rate + config.dispute_rate && !deposits.is_empty()
+        {
+            let (client, tx) = deposits.swap_remove(rng.below(deposits.len() as u64) as usize);
+            writeln!(out, "dispute,{},{},", client, tx)?;
+            disputed.push((client, tx));
+            stats.disputes += 1;
+            continue;
+        }
+        //open disputes are settled about as often as they are raised
+        if roll < config.malformed_rate + config.duplicate_rate + config.dispute_rate * 2.0 && !disputed.is_empty()
+        {
+            let (client, tx) = disputed.swap_remove(rng.below(disputed.len() as u64) as usize);
+            match rng.chance() < 0.3
+            {
+                true => {writeln!(out, "chargeback,{},{},", client, tx)?; stats.chargebacks += 1;},
+                false => {writeln!(out, "resolve,{},{},", client, tx)?; stats.resolves += 1;}
+            }
+            continue;
+        }
+        let client = 1 + rng.below(config.clients.into()) as u16;
+        let tx = next_tx;
+        next_tx = next_tx.wrapping_add(1);
+        //mostly small amounts, now and then a large one
+        let scale = if rng.chance() < 0.05 {100_000_000} else {1_000_000};
+        let minor = 1 + rng.below(scale);
+        let amount = format!("{}.{:0width$}", minor / 10_000, minor % 10_000, width = AMOUNT_PRECISION);
+        let row = match rng.chance() < 0.65
+        {
+            true => {
+                if deposits.len() == RECENT {deposits.swap_remove(rng.below(RECENT as u64) as usize);}
+                deposits.push((client, tx));
+                stats.deposits += 1;
+                format!("deposit,{},{},{}", client, tx, amount)
+            },
+            false => {
+                stats.withdrawals += 1;
+                format!("withdrawal,{},{},{}", client, tx, amount)
+            }
+        };
+        writeln!(out, "{}", row)?;
+        if recent.len() == RECENT {recent.swap_remove(rng.below(RECENT as u64) as usize);}
+        recent.push(row);
+    }
+    out.flush()?;
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dialect, Engine, EnginePolicy, SchemaMode, TxRecord, TypeTx};
+    use std::collections::HashSet;
+
+    #[test]
+    fn generated_input()
+    {
+        assert_eq!((parse_count("10k"), parse_count("10M"), parse_count("250"), parse_count("x")),(Some(10_000), Some(10_000_000), Some(250), None));
+        let config = GeneratorConfig { clients: 50, rows: 5000, dispute_rate: 0.05, duplicate_rate: 0.01, malformed_rate: 0.01, seed: 7 };
+        let mut first = Vec::new();
+        let stats = generate(&config, &mut first).unwrap();
+        let mut second = Vec::new();
+        generate(&config, &mut second).unwrap();
+        assert_eq!(first,second);
+        assert_eq!(String::from_utf8_lossy(&first).lines().count(),5001);
+        assert!(stats.disputes > 100 && stats.resolves + stats.chargebacks > 50 && stats.duplicates > 10 && stats.malformed > 10);
+
+        let records: Vec<TxRecord> = Dialect::default().read_records(first.as_slice(), SchemaMode::Strict).unwrap().collect();
+        let mut deposited = HashSet::new();
+        let mut open = HashSet::new();
+        for r in records.iter().filter(|r| r.client <= 50)
+        {
+            match r.r#type
+            {
+                TypeTx::Deposit => {deposited.insert((r.client, r.tx));},
+                TypeTx::Dispute => assert!(deposited.contains(&(r.client, r.tx)) && open.insert((r.client, r.tx))),
+                TypeTx::Resolve | TypeTx::Chargeback => assert!(open.remove(&(r.client, r.tx))),
+                _ => ()
+            }
+        }
+        let mut engine = Engine::new(EnginePolicy::default());
+        records.into_iter().for_each(|r| engine.apply_record(r));
+        assert!(engine.clients.len() <= 50 && !engine.rejections.is_empty());
+    }
+}
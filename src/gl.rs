@@ -0,0 +1,159 @@
+use std::{fmt, io};
+use serde::{Deserialize, Serialize};
+use crate::{Amount, BookAccount, Direction, Posting};
+
+///
+/// The general ledger account code a posting goes to, by what caused it and the
+/// system account it is posted to
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct GlRule
+{
+    /// The type of transaction, fee, settlement, interest, undo, chargeback or opening, or * for any
+    pub cause: String,
+    pub account: BookAccount,
+    /// The account code in the general ledger, E.G. 1010
+    pub code: String,
+}
+
+///
+/// Which general ledger account each posting of the journal goes to
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GlMapping
+{
+    pub rules: Vec<GlRule>,
+}
+impl GlMapping
+{
+    /// Reads the mapping, a csv with cause, account and code columns
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The mapping as csv, with a header row
+    pub fn read<R: io::Read>(input: R) -> csv::Result<GlMapping>
+    {
+        let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+        let rules = rdr.deserialize().collect::<csv::Result<Vec<GlRule>>>()?;
+        Ok(GlMapping { rules })
+    }
+    /// The code the posting goes to, from the rule for its cause, or else the rule for
+    /// any cause, of its account
+    ///
+    /// # Arguments
+    ///
+    /// * 'posting' - The posting to find the code of
+    pub fn code(&self, posting: &Posting) -> Option<&str>
+    {
+        let rules = || self.rules.iter().filter(|r| r.account == posting.account);
+        rules().find(|r| r.cause == posting.cause).or_else(|| rules().find(|r| r.cause == "*")).map(|r| r.code.as_str())
+    }
+}
+
+///
+/// Something went wrong writing the general ledger journal
+///
+#[derive(Debug)]
+pub enum GlError
+{
+    Csv(csv::Error),
+    /// No rule maps postings of the cause to the account, so nothing was written
+    Unmapped { cause: String, account: BookAccount },
+}
+impl fmt::Display for GlError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            GlError::Csv(e) => write!(f, "{}", e),
+            GlError::Unmapped { cause, account } => write!(f, "no general ledger code for {} postings to {}", cause, account),
+        }
+    }
+}
+impl From<csv::Error> for GlError
+{
+    fn from(e: csv::Error) -> Self {
+        GlError::Csv(e)
+    }
+}
+
+///
+/// A single row of the general ledger journal
+///
+#[derive(Serialize)]
+struct GlRow<'a>
+{
+    entry: u64,
+    code: &'a str,
+    debit: Option<Amount>,
+    credit: Option<Amount>,
+    client: u16,
+    tx: u32,
+    /// The cause and system account, E.G. "deposit: cash", for the description in the ledger
+    description: String,
+}
+
+/// Writes the journal as csv for the general ledger, a posting a row with its account code
+/// and either a debit or a credit
+///
+/// Every posting is checked first, and nothing is written if any can't be mapped
+///
+/// # Arguments
+///
+/// * 'postings' - The postings of the journal
+/// * 'mapping' - The code each posting goes to
+/// * 'out' - Where to write the journal to
+pub fn write_gl_journal<W: io::Write>(postings: &[Posting], mapping: &GlMapping, out: W) -> Result<(), GlError>
+{
+    let mut codes = Vec::with_capacity(postings.len());
+    for posting in postings
+    {
+        match mapping.code(posting)
+        {
+            Some(code) => codes.push(code),
+            None => return Err(GlError::Unmapped { cause: posting.cause.clone(), account: posting.account })
+        }
+    }
+    let mut wrtr = csv::Writer::from_writer(out);
+    for (posting, code) in postings.iter().zip(codes)
+    {
+        let (debit, credit) = match posting.direction
+        {
+            Direction::Debit => (Some(posting.amount), None),
+            Direction::Credit => (None, Some(posting.amount))
+        };
+        wrtr.serialize(GlRow { entry: posting.entry, code, debit, credit, client: posting.client, tx: posting.tx,
+            description: format!("{}: {}", posting.cause, posting.account) })?;
+    }
+    wrtr.flush().map_err(csv::Error::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn postings_map_to_codes()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.enable_journal();
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 2, Some(Amount::from_minor(10000))));
+        let postings = &engine.journal.as_ref().unwrap().postings;
+
+        let mapping = GlMapping::read("cause,account,code\n*,cash,1010\n*,customer_funds,2100\nwithdrawal,cash,1020\n".as_bytes()).unwrap();
+        let mut out = Vec::new();
+        write_gl_journal(postings, &mapping, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),"entry,code,debit,credit,client,tx,description\n\
+            1,2100,,5.0,1,1,deposit: customer_funds\n1,1010,5.0,,1,1,deposit: cash\n\
+            2,2100,1.0,,1,2,withdrawal: customer_funds\n2,1020,,1.0,1,2,withdrawal: cash\n");
+
+        let partial = GlMapping::read("cause,account,code\ndeposit,cash,1010\n*,customer_funds,2100\n".as_bytes()).unwrap();
+        let mut out = Vec::new();
+        assert!(matches!(write_gl_journal(postings, &partial, &mut out), Err(GlError::Unmapped { account: BookAccount::Cash, .. })));
+        assert!(out.is_empty());
+        assert!(GlMapping::read("cause,account,code\n*,bank,1010\n".as_bytes()).is_err());
+    }
+}
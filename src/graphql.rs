@@ -0,0 +1,222 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Result, Schema, SimpleObject};
+use crate::{Amount, Client, ClientTransaction, Engine};
+
+/// How many items a list returns when the query doesn't say
+const DEFAULT_PAGE: usize = 100;
+/// The most items a list returns, whatever the query asks for
+const MAX_PAGE: usize = 1000;
+
+/// The schema a server answers GraphQL queries with
+pub type EngineSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Returns the schema over the engine, which the server keeps on applying transactions to
+///
+/// # Arguments
+///
+/// * 'engine' - The engine, shared with whatever applies transactions
+pub fn schema(engine: Arc<Mutex<Engine>>) -> EngineSchema
+{
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription).data(engine).finish()
+}
+
+/// Runs a GraphQL request on the calling thread, returning the response
+///
+/// # Arguments
+///
+/// * 'schema' - The schema over the engine
+/// * 'request' - The request body, json with a query and optionally variables
+pub fn execute_blocking(schema: &EngineSchema, request: &str) -> String
+{
+    let response = match serde_json::from_str::<async_graphql::Request>(request)
+    {
+        Ok(request) => futures::executor::block_on(schema.execute(request)),
+        Err(e) => async_graphql::Response::from_errors(vec![async_graphql::ServerError::new(format!("invalid request: {}", e), None)])
+    };
+    serde_json::to_string(&response).unwrap_or_default()
+}
+
+/// Locks the engine for the resolver, a panic elsewhere doesn't stop it being read
+fn engine<'a>(ctx: &Context<'a>) -> Result<MutexGuard<'a, Engine>>
+{
+    Ok(ctx.data::<Arc<Mutex<Engine>>>()?.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// How many items to return, the default if not given and never more than the maximum
+fn page(first: Option<usize>) -> usize
+{
+    first.unwrap_or(DEFAULT_PAGE).min(MAX_PAGE)
+}
+
+///
+/// The account of a client
+///
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct AccountObject
+{
+    client: u16,
+    available: String,
+    held: String,
+    total: String,
+    pending: String,
+    locked: bool,
+    frozen: bool,
+}
+impl From<&Client> for AccountObject
+{
+    fn from(c: &Client) -> Self {
+        AccountObject { client: c.acc.client, available: c.acc.available.to_string(), held: c.acc.held.to_string(), total: c.acc.total.to_string(),
+            pending: c.acc.pending.to_string(), locked: c.acc.locked, frozen: c.frozen }
+    }
+}
+#[ComplexObject]
+impl AccountObject
+{
+    /// The deposits and withdrawals of the client, ordered by transaction ID
+    async fn history(&self, ctx: &Context<'_>, after: Option<u32>, first: Option<usize>) -> Result<Vec<HistoryObject>>
+    {
+        let engine = engine(ctx)?;
+        let Some(client) = engine.clients.get(&self.client) else {return Ok(Vec::new())};
+        let mut txs: Vec<u32> = client.history.keys().copied().filter(|tx| after.is_none_or(|after| *tx > after)).collect();
+        txs.sort();
+        Ok(txs.into_iter().take(page(first)).map(|tx| HistoryObject::new(tx, &client.history[&tx])).collect())
+    }
+}
+
+///
+/// A deposit or withdrawal in the history of a client
+///
+#[derive(SimpleObject)]
+pub struct HistoryObject
+{
+    tx: u32,
+    amount: String,
+    in_dispute: bool,
+    /// The disputes, resolves, chargebacks and representments applied to it, in order
+    dispute_chain: Vec<String>,
+    memo: Option<String>,
+    counterparty: Option<String>,
+    /// The row it came in at, 0 if it isn't known
+    row: u64,
+    timestamp: Option<i64>,
+}
+impl HistoryObject
+{
+    fn new(tx: u32, entry: &ClientTransaction) -> HistoryObject
+    {
+        HistoryObject { tx, amount: entry.amount.to_string(), in_dispute: entry.in_dispute, dispute_chain: entry.dispute_chain.iter().map(|t| t.as_str().to_string()).collect(),
+            memo: entry.memo.clone(), counterparty: entry.counterparty.clone(), row: entry.row, timestamp: entry.timestamp }
+    }
+}
+
+///
+/// A dispute still open, its funds held
+///
+#[derive(SimpleObject)]
+pub struct DisputeObject
+{
+    client: u16,
+    tx: u32,
+    amount: String,
+    row: u64,
+    timestamp: Option<i64>,
+}
+
+///
+/// Figures across every account
+///
+#[derive(SimpleObject)]
+pub struct StatsObject
+{
+    clients: usize,
+    locked: usize,
+    frozen: usize,
+    /// Rows applied, rejected ones included
+    rows: u64,
+    rejections: usize,
+    open_disputes: usize,
+    held: String,
+    total: String,
+}
+
+///
+/// The queries a back-office UI can make
+///
+pub struct QueryRoot;
+#[Object]
+impl QueryRoot
+{
+    /// The account of the client, null if it has none
+    async fn account(&self, ctx: &Context<'_>, client: u16) -> Result<Option<AccountObject>>
+    {
+        Ok(engine(ctx)?.clients.get(&client).map(AccountObject::from))
+    }
+    /// The accounts ordered by client, those of clients after the one given if any,
+    /// only locked or unlocked ones if asked and only those with a balance if non_zero is set
+    async fn accounts(&self, ctx: &Context<'_>, after: Option<u16>, first: Option<usize>, locked: Option<bool>, non_zero: Option<bool>) -> Result<Vec<AccountObject>>
+    {
+        let engine = engine(ctx)?;
+        Ok(engine.clients.iter_ordered(after).map(|(_, c)| c)
+            .filter(|c| locked.is_none_or(|locked| c.acc.locked == locked))
+            .filter(|c| !non_zero.unwrap_or(false) || c.acc.total != Amount::ZERO || c.acc.held != Amount::ZERO)
+            .take(page(first)).map(AccountObject::from).collect())
+    }
+    /// The open disputes ordered by client and transaction, of one client if given
+    async fn open_disputes(&self, ctx: &Context<'_>, client: Option<u16>, first: Option<usize>) -> Result<Vec<DisputeObject>>
+    {
+        let engine = engine(ctx)?;
+        let mut disputes: Vec<DisputeObject> = engine.clients.values().filter(|c| client.is_none_or(|id| c.acc.client == id))
+            .flat_map(|c| c.history.iter().filter(|(_, e)| e.in_dispute).map(move |(tx, e)| DisputeObject {
+                client: c.acc.client, tx: *tx, amount: e.amount.to_string(), row: e.row, timestamp: e.timestamp
+            })).collect();
+        disputes.sort_by_key(|d| (d.client, d.tx));
+        disputes.truncate(page(first));
+        Ok(disputes)
+    }
+    async fn stats(&self, ctx: &Context<'_>) -> Result<StatsObject>
+    {
+        let engine = engine(ctx)?;
+        let sum = |balance: fn(&Client) -> Amount| engine.clients.values().fold(Amount::ZERO, |sum, c| sum.checked_add(balance(c)).unwrap_or(Amount::MAX));
+        Ok(StatsObject {
+            clients: engine.clients.len(),
+            locked: engine.clients.values().filter(|c| c.acc.locked).count(),
+            frozen: engine.clients.values().filter(|c| c.frozen).count(),
+            rows: engine.rows,
+            rejections: engine.rejections.len(),
+            open_disputes: engine.clients.values().map(|c| c.history.values().filter(|e| e.in_dispute).count()).sum(),
+            held: sum(|c| c.acc.held).to_string(),
+            total: sum(|c| c.acc.total).to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn account_and_dispute_queries()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        for client in 1..=3
+        {
+            engine.apply(Tx::new(TypeTx::Deposit, client, client as u32, Some(Amount::from_minor(10000))));
+        }
+        engine.apply(Tx::new(TypeTx::Dispute, 2, 2, None));
+        let schema = schema(Arc::new(Mutex::new(engine)));
+
+        let request = json!({"query": "{ accounts(after: 1, first: 1) { client held history { tx inDispute disputeChain } } openDisputes { client tx amount } stats { clients openDisputes held total } }"});
+        let response: Value = serde_json::from_str(&execute_blocking(&schema, &request.to_string())).unwrap();
+        assert_eq!(response["data"]["accounts"],json!([{"client": 2, "held": "1.0", "history": [{"tx": 2, "inDispute": true, "disputeChain": ["dispute"]}]}]));
+        assert_eq!(response["data"]["openDisputes"],json!([{"client": 2, "tx": 2, "amount": "1.0"}]));
+        assert_eq!(response["data"]["stats"],json!({"clients": 3, "openDisputes": 1, "held": "1.0", "total": "3.0"}));
+
+        let request = json!({"query": "query($c: Int!) { account(client: $c) { total } }", "variables": {"c": 9}});
+        let response: Value = serde_json::from_str(&execute_blocking(&schema, &request.to_string())).unwrap();
+        assert_eq!(response["data"]["account"],Value::Null);
+        assert!(execute_blocking(&schema, "not json").contains("invalid request"));
+    }
+}
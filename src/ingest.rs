@@ -0,0 +1,81 @@
+use std::io;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::Engine;
+
+///
+/// An input file the engine has processed in full
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestedFile
+{
+    /// The path it was read from
+    pub path: String,
+    /// The sha256 of its content, as lowercase hex
+    pub hash: String,
+}
+
+/// Hashes the content of an input file, as lowercase hex
+///
+/// # Arguments
+///
+/// * 'input' - The file, read to the end
+pub fn content_hash<R: io::Read>(mut input: R) -> io::Result<String>
+{
+    let mut hasher = Sha256::new();
+    io::copy(&mut input, &mut hasher)?;
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+impl Engine
+{
+    /// Returns the file already processed with this content, if any, so it isn't
+    /// processed twice
+    ///
+    /// # Arguments
+    ///
+    /// * 'hash' - The content hash of the file about to be processed
+    pub fn already_ingested(&self, hash: &str) -> Option<&IngestedFile>
+    {
+        self.ingested.iter().find(|f| f.hash == hash)
+    }
+    /// Records a file as processed in full, kept in snapshots and checkpoints
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - The path it was read from
+    /// * 'hash' - The content hash of the file
+    pub fn mark_ingested(&mut self, path: &str, hash: &str)
+    {
+        if self.already_ingested(hash).is_none()
+        {
+            self.ingested.push(IngestedFile { path: path.to_string(), hash: hash.to_string() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EnginePolicy;
+
+    #[test]
+    fn ingestion_registry()
+    {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        let hash = content_hash(input.as_bytes()).unwrap();
+        assert_eq!(hash.len(),64);
+        assert_ne!(hash,content_hash("type,client,tx,amount\n".as_bytes()).unwrap());
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        assert!(engine.already_ingested(&hash).is_none());
+        engine.mark_ingested("day1.csv", &hash);
+        engine.mark_ingested("copy_of_day1.csv", &hash);
+        assert_eq!(engine.ingested.len(),1);
+
+        let mut snapshot = Vec::new();
+        engine.snapshot_to(&mut snapshot, None).unwrap();
+        let restored = Engine::restore_from(snapshot.as_slice(), EnginePolicy::default(), None).unwrap();
+        assert_eq!(restored.already_ingested(&hash).map(|f| f.path.as_str()),Some("day1.csv"));
+    }
+}
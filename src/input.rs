@@ -0,0 +1,324 @@
+use std::{collections::HashMap, fmt, io, sync::Arc};
+use crate::{AmountParser, PlainDecimal, SchemaMode, TimestampConfig, TxRecord, TypeTx};
+
+/// The columns every input file needs to have
+pub const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+/// The columns that are picked up if the input has them
+pub const OPTIONAL_COLUMNS: [&str; 7] = ["timestamp", "currency", "memo", "counterparty", "tenant", "signature", "account"];
+
+///
+/// The headers of the input don't match what we expect
+///
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct HeaderError
+{
+    /// Expected columns that aren't in the input
+    pub missing: Vec<String>,
+    /// Columns in the input we don't know what to do with
+    pub unknown: Vec<String>,
+}
+impl fmt::Display for HeaderError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid headers")?;
+        if !self.missing.is_empty() {write!(f, ", missing columns: {}", self.missing.join(", "))?;}
+        if !self.unknown.is_empty() {write!(f, ", unknown columns: {}", self.unknown.join(", "))?;}
+        Ok(())
+    }
+}
+impl From<HeaderError> for io::Error
+{
+    fn from(e: HeaderError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+}
+
+///
+/// How the input csv is laid out
+///
+#[derive(Debug, Clone)]
+pub struct Dialect
+{
+    /// The byte separating fields, a comma by default
+    pub delimiter: u8,
+    /// Whether whitespace around headers and fields is stripped
+    pub trim: bool,
+    /// Whether amounts use a comma for the decimal point, E.G. "1,5"
+    pub decimal_comma: bool,
+    /// Columns to rename before reading, from the name in the input to ours,
+    /// E.G. "transaction_id" to "tx"
+    pub renames: HashMap<String, String>,
+    /// How the amounts are written, plain decimals by default
+    pub amount_parser: Arc<dyn AmountParser>,
+    /// How the timestamps are written, unix timestamps by default
+    pub timestamps: TimestampConfig,
+}
+impl Default for Dialect
+{
+    fn default() -> Self {
+        Dialect { delimiter: b',', trim: false, decimal_comma: false, renames: HashMap::new(), amount_parser: Arc::new(PlainDecimal), timestamps: TimestampConfig::default() }
+    }
+}
+impl Dialect
+{
+    /// Parses a delimiter given on the command line
+    ///
+    /// Accepts a single ascii character, or "tab"/"\t" for tabs
+    ///
+    /// # Arguments
+    ///
+    /// 'text' - The delimiter as given
+    pub fn parse_delimiter(text: &str) -> Option<u8>
+    {
+        match text
+        {
+            "tab" | "\\t" | "\t" => Some(b'\t'),
+            _ if text.len() == 1 && text.is_ascii() => Some(text.as_bytes()[0]),
+            _ => None
+        }
+    }
+    /// Returns a csv reader set up for this dialect
+    ///
+    /// # Arguments
+    ///
+    /// 'input' - Where to read the csv from
+    pub fn reader<R: io::Read>(&self, input: R) -> csv::Reader<R>
+    {
+        csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .trim(if self.trim {csv::Trim::All} else {csv::Trim::None})
+            .from_reader(input)
+    }
+    /// Parses a column rename given on the command line, as "from=to"
+    ///
+    /// # Arguments
+    ///
+    /// 'text' - The rename as given
+    pub fn parse_rename(text: &str) -> Option<(String, String)>
+    {
+        let (from, to) = text.split_once('=')?;
+        if from.is_empty() || to.is_empty() {return None}
+        Some((from.to_string(), to.to_string()))
+    }
+    /// Renames the headers as configured, and checks that we end up with
+    /// all the columns we need
+    ///
+    /// # Arguments
+    ///
+    /// 'headers' - The headers as read from the input
+    /// 'schema' - Whether columns we don't know about are an error
+    pub fn map_headers(&self, headers: &csv::StringRecord, schema: SchemaMode) -> Result<csv::StringRecord, HeaderError>
+    {
+        let mapped: csv::StringRecord = headers.iter()
+            .map(|h| self.renames.get(h).map(String::as_str).unwrap_or(h))
+            .collect();
+        let error = HeaderError {
+            missing: REQUIRED_COLUMNS.iter()
+                .filter(|c| !mapped.iter().any(|h| h == **c))
+                .map(|c| c.to_string())
+                .collect(),
+            unknown: match schema
+            {
+                SchemaMode::Lenient => Vec::new(),
+                SchemaMode::Strict => mapped.iter()
+                    .filter(|h| !REQUIRED_COLUMNS.contains(h) && !OPTIONAL_COLUMNS.contains(h))
+                    .map(String::from)
+                    .collect()
+            }
+        };
+        if error.missing.is_empty() && error.unknown.is_empty() {Ok(mapped)} else {Err(error)}
+    }
+    /// Reads every transaction record from the input
+    ///
+    /// Rows that can't be read as a record at all are skipped
+    ///
+    /// # Arguments
+    ///
+    /// 'input' - Where to read the csv from
+    /// 'schema' - Whether columns we don't know about are an error
+    ///
+    /// # Errors
+    ///
+    /// Returns a HeaderError if the headers don't match the expected columns
+    /// after renaming, as then no row could be read anyway
+    pub fn read_records<'a, R: io::Read + 'a>(&'a self, input: R, schema: SchemaMode) -> Result<impl Iterator<Item = TxRecord> + 'a, HeaderError>
+    {
+        let mut rdr = self.reader(input);
+        let headers = rdr.headers().cloned().unwrap_or_default();
+        let headers = self.map_headers(&headers, schema)?;
+        Ok(rdr.into_records()
+            .filter_map(Result::ok)
+            .filter_map(move |row| self.record(&row, &headers)))
+    }
+    /// Reads a row as a transaction record, None if it can't be read as one
+    ///
+    /// The type column of a row of a type we don't know is kept as the custom type name
+    ///
+    /// # Arguments
+    ///
+    /// 'row' - The row as read from the input
+    /// 'headers' - The headers after renaming
+    pub(crate) fn record(&self, row: &csv::StringRecord, headers: &csv::StringRecord) -> Option<TxRecord>
+    {
+        let mut record: TxRecord = row.deserialize(Some(headers)).ok()?;
+        if record.r#type == TypeTx::Custom
+        {
+            record.custom = headers.iter().position(|h| h == "type").and_then(|i| row.get(i)).map(String::from);
+        }
+        Some(self.normalize(record))
+    }
+    /// Reads the transaction records from a byte offset on, each with the offset just past it,
+    /// so a run can be resumed where it stopped
+    ///
+    /// Rows that can't be read as a record at all are skipped
+    ///
+    /// # Arguments
+    ///
+    /// 'input' - Where to read the csv from, its headers are always read from the start
+    /// 'offset' - Where to start reading the rows, as returned with an earlier record, or 0
+    /// 'schema' - Whether columns we don't know about are an error
+    ///
+    /// # Errors
+    ///
+    /// Fails if the headers don't match the expected columns, or the offset can't be reached
+    pub fn read_records_from<'a, R: io::Read + io::Seek + 'a>(&'a self, input: R, offset: u64, schema: SchemaMode) -> io::Result<impl Iterator<Item = (u64, TxRecord)> + 'a>
+    {
+        let mut rdr = self.reader(input);
+        let headers = rdr.headers().cloned().unwrap_or_default();
+        let headers = self.map_headers(&headers, schema)?;
+        if offset > 0
+        {
+            let mut position = csv::Position::new();
+            position.set_byte(offset);
+            rdr.seek(position)?;
+        }
+        let mut row = csv::StringRecord::new();
+        Ok(std::iter::from_fn(move || loop
+        {
+            match rdr.read_record(&mut row)
+            {
+                Ok(true) => if let Some(record) = self.record(&row, &headers)
+                {
+                    return Some((rdr.position().byte(), record));
+                },
+                Ok(false) => return None,
+                Err(e) if e.is_io_error() => return None,
+                Err(_) => {}
+            }
+        }))
+    }
+    /// Rewrites the amount and timestamp of a record into the standard notation, so they
+    /// can be parsed the same way no matter the dialect; an amount or timestamp that can't
+    /// be read is kept as given, the amount marked invalid
+    ///
+    /// # Arguments
+    ///
+    /// 'record' - The record as read from the input
+    pub fn normalize(&self, mut record: TxRecord) -> TxRecord
+    {
+        if let Some(amount) = record.amount.take()
+        {
+            match self.amount_parser.normalize(&amount, self.decimal_comma)
+            {
+                Some(normalized) => record.amount = Some(normalized),
+                None => {
+                    record.amount = Some(amount);
+                    record.invalid_amount = true;
+                }
+            }
+        }
+        record.timestamp = record.timestamp.map(|t| self.timestamps.normalize(&t).map_or(t, |secs| secs.to_string()));
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TypeTx;
+
+    #[test]
+    fn default_dialect()
+    {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.5\ndispute,1,1,\n";
+        let records: Vec<TxRecord> = Dialect::default().read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records.len(),2);
+        assert_eq!(records[0].amount.as_deref(),Some("1.5"));
+        assert_eq!(records[1].r#type,TypeTx::Dispute);
+        assert!(records[1].amount.is_none());
+    }
+    #[test]
+    fn whitespace_without_trim()
+    {
+        let input = "type,client,tx,amount\ndeposit, 1, 1, 1.5\n";
+        assert_eq!(Dialect::default().read_records(input.as_bytes(), SchemaMode::Strict).unwrap().count(),0);
+    }
+    #[test]
+    fn semicolon_trim_decimal_comma()
+    {
+        let dialect = Dialect { delimiter: b';', trim: true, decimal_comma: true, ..Dialect::default() };
+        let input = "type ; client ; tx ; amount\n deposit ; 1 ; 1 ; 1,5 \nwithdrawal;1;2;0,25\n";
+        let records: Vec<TxRecord> = dialect.read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records.len(),2);
+        assert_eq!(records[0].amount.as_deref(),Some("1.5"));
+        assert_eq!(records[1].amount.as_deref(),Some("0.25"));
+    }
+    #[test]
+    fn tab_delimiter()
+    {
+        let dialect = Dialect { delimiter: Dialect::parse_delimiter("tab").unwrap(), ..Dialect::default() };
+        let input = "type\tclient\ttx\tamount\ndeposit\t3\t9\t2.0\n";
+        let records: Vec<TxRecord> = dialect.read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records[0].client,3);
+        assert_eq!(Dialect::parse_delimiter(";"),Some(b';'));
+        assert_eq!(Dialect::parse_delimiter(";;"),None);
+    }
+    #[test]
+    fn reordered_headers()
+    {
+        let input = "amount,tx,client,type\n1.5,1,2,deposit\n";
+        let records: Vec<TxRecord> = Dialect::default().read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records[0].client,2);
+        assert_eq!(records[0].amount.as_deref(),Some("1.5"));
+    }
+    #[test]
+    fn invalid_headers()
+    {
+        let input = "type,client,transaction_id,amount,note\ndeposit,1,1,1.5,hi\n";
+        let error = Dialect::default().read_records(input.as_bytes(), SchemaMode::Strict).err().unwrap();
+        assert_eq!(error.missing,vec!["tx"]);
+        assert_eq!(error.unknown,vec!["transaction_id","note"]);
+        assert_eq!(error.to_string(),"invalid headers, missing columns: tx, unknown columns: transaction_id, note");
+    }
+    #[test]
+    fn renamed_headers()
+    {
+        let mut dialect = Dialect::default();
+        let (from, to) = Dialect::parse_rename("transaction_id=tx").unwrap();
+        dialect.renames.insert(from, to);
+        let input = "type,client,transaction_id,amount\ndeposit,1,7,1.5\n";
+        let records: Vec<TxRecord> = dialect.read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records[0].tx,7);
+        assert_eq!(Dialect::parse_rename("tx"),None);
+    }
+    #[test]
+    fn optional_columns()
+    {
+        let input = "type,client,tx,amount,timestamp,currency,memo\ndeposit,1,7,1.5,1700000000,EUR,invoice 12\n";
+        let records: Vec<TxRecord> = Dialect::default().read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records[0].timestamp.as_deref(),Some("1700000000"));
+        assert_eq!(records[0].currency.as_deref(),Some("EUR"));
+        assert_eq!(records[0].memo.as_deref(),Some("invoice 12"));
+    }
+    #[test]
+    fn lenient_schema()
+    {
+        let input = "type,client,tx,amount,note\ndeposit,1,7,1.5,hi\n";
+        assert!(Dialect::default().read_records(input.as_bytes(), SchemaMode::Strict).is_err());
+        let records: Vec<TxRecord> = Dialect::default().read_records(input.as_bytes(), SchemaMode::Lenient).unwrap().collect();
+        assert_eq!(records[0].tx,7);
+        assert!(records[0].memo.is_none());
+        let input = "type,client,amount\ndeposit,1,1.5\n";
+        assert_eq!(Dialect::default().read_records(input.as_bytes(), SchemaMode::Lenient).err().unwrap().missing,vec!["tx"]);
+    }
+}
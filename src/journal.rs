@@ -0,0 +1,251 @@
+use std::{collections::BTreeMap, io};
+use serde::{Deserialize, Serialize};
+use crate::{books::{change_postings, reserve_postings}, Account, Amount, BalanceChange, BookAccount, Engine};
+
+///
+/// Which side of a book account a posting goes to
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction
+{
+    Debit,
+    Credit,
+}
+
+///
+/// A single line of the journal
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Posting
+{
+    /// Counts up from 1, shared by the postings of a single change, which balance each other
+    pub entry: u64,
+    pub account: BookAccount,
+    pub direction: Direction,
+    /// Never negative, the direction says which way it goes
+    pub amount: Amount,
+    /// The client whose transaction made the posting
+    pub client: u16,
+    /// The transaction that made the posting
+    pub tx: u32,
+    /// What the transaction was, as in BalanceChange
+    pub cause: String,
+}
+
+///
+/// Every change to the balances as a journal of debit and credit postings against the
+/// system accounts, in the order they were made
+///
+/// Unlike the trial balance, which only keeps totals, the journal keeps each posting
+/// with the client and transaction behind it, so the balances of every client can be
+/// derived from it alone, and checked against the accounts
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Journal
+{
+    pub postings: Vec<Posting>,
+}
+impl Journal
+{
+    /// Adds the postings of a single change as the next entry, leaving out those of nothing
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client whose transaction made the change
+    /// * 'tx' - The transaction
+    /// * 'cause' - What the transaction was
+    /// * 'postings' - The amounts to debit each account by, negative for a credit
+    fn record(&mut self, client: u16, tx: u32, cause: &str, postings: &[(BookAccount, Amount)])
+    {
+        let entry = self.postings.last().map_or(1, |p| p.entry + 1);
+        for (account, amount) in postings.iter().filter(|(_, amount)| *amount != Amount::ZERO)
+        {
+            let (direction, amount) = match amount.is_negative()
+            {
+                true => (Direction::Credit, Amount::ZERO.checked_sub(*amount).unwrap_or(Amount::MAX)),
+                false => (Direction::Debit, *amount)
+            };
+            self.postings.push(Posting { entry, account: *account, direction, amount, client, tx, cause: cause.to_string() });
+        }
+    }
+    /// Adds the postings of a change to the balances of a client
+    ///
+    /// # Arguments
+    ///
+    /// * 'change' - How the balances of the client changed
+    pub(crate) fn record_change(&mut self, change: &BalanceChange)
+    {
+        self.record(change.client, change.tx, &change.cause, &change_postings(change));
+    }
+    /// Adds the postings of a chargeback debited from the reserve
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client charged back
+    /// * 'tx' - The chargeback, or the representment crediting it back
+    /// * 'amount' - How much the reserve went down by, negative if it was credited back
+    pub(crate) fn record_reserve(&mut self, client: u16, tx: u32, amount: Amount)
+    {
+        let cause = if amount.is_negative() {"representment"} else {"chargeback"};
+        self.record(client, tx, cause, &reserve_postings(amount));
+    }
+    /// Derives the balances of every client from their postings to customer, held and
+    /// pending funds, which are owed to the client, so a credit adds to the balance
+    ///
+    /// The accounts derived are never locked, as locking isn't posted
+    pub fn accounts(&self) -> BTreeMap<u16, Account>
+    {
+        let mut accounts: BTreeMap<u16, Account> = BTreeMap::new();
+        for posting in &self.postings
+        {
+            if !matches!(posting.account, BookAccount::CustomerFunds | BookAccount::HeldFunds | BookAccount::PendingFunds) {continue}
+            let account = accounts.entry(posting.client).or_insert_with(|| Account::new(posting.client));
+            let balance = match posting.account
+            {
+                BookAccount::HeldFunds => &mut account.held,
+                BookAccount::PendingFunds => &mut account.pending,
+                _ => &mut account.available
+            };
+            *balance = match posting.direction
+            {
+                Direction::Credit => balance.checked_add(posting.amount),
+                Direction::Debit => balance.checked_sub(posting.amount)
+            }.unwrap_or(*balance);
+        }
+        for account in accounts.values_mut()
+        {
+            account.total = [account.available, account.held, account.pending].iter()
+                .fold(Amount::ZERO, |sum, b| sum.checked_add(*b).unwrap_or(Amount::MAX));
+        }
+        accounts
+    }
+}
+
+/// Writes the journal as csv, a posting a row in the order they were made
+///
+/// # Arguments
+///
+/// * 'postings' - The postings of the journal
+/// * 'out' - Where to write them to
+pub fn write_journal<W: io::Write>(postings: &[Posting], out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for posting in postings
+    {
+        wrtr.serialize(posting)?;
+    }
+    wrtr.flush()?;
+    Ok(())
+}
+
+impl Engine
+{
+    /// Starts keeping a journal of every posting, see Journal
+    ///
+    /// The balances the accounts and reserve already have are posted against the opening
+    /// balances account as the first entries, so the journal derives every account
+    pub fn enable_journal(&mut self)
+    {
+        if self.journal.is_some() {return}
+        let mut journal = Journal::default();
+        let mut ids: Vec<u16> = self.clients.keys().collect();
+        ids.sort();
+        for c in ids.iter().filter_map(|id| self.clients.get(id))
+        {
+            journal.record_change(&BalanceChange { client: c.acc.client, tx: 0, cause: "opening".to_string(),
+                available: c.acc.available, held: c.acc.held, total: c.acc.total, pending: c.acc.pending });
+        }
+        if let Some(reserve) = self.reserve.as_ref()
+        {
+            journal.record(0, 0, "opening", &[(BookAccount::ChargebackReserve, reserve.balance),
+                (BookAccount::OpeningBalances, Amount::ZERO.checked_sub(reserve.balance).unwrap_or(Amount::MAX))]);
+        }
+        self.journal = Some(journal);
+    }
+    /// The accounts as derived from the journal, locked as the engine has them, if it is on
+    pub fn journal_accounts(&self) -> Option<BTreeMap<u16, Account>>
+    {
+        let mut accounts = self.journal.as_ref()?.accounts();
+        for account in accounts.values_mut()
+        {
+            account.locked = self.clients.get(&account.client).is_some_and(|c| c.acc.locked);
+        }
+        Some(accounts)
+    }
+    /// The clients whose account doesn't have the balances the journal derives, ordered by client
+    pub fn journal_mismatches(&self) -> Vec<u16>
+    {
+        let Some(derived) = self.journal_accounts() else {return Vec::new()};
+        let mut ids: Vec<u16> = self.clients.keys().chain(derived.keys().copied()).collect();
+        ids.sort();
+        ids.dedup();
+        ids.into_iter().filter(|id| {
+            let kept = self.clients.get(id).map(|c| (c.acc.available, c.acc.held, c.acc.pending, c.acc.total));
+            let derived = derived.get(id).map(|a| (a.available, a.held, a.pending, a.total));
+            //an account the journal has nothing for is one whose balances never moved
+            kept.unwrap_or_default() != derived.unwrap_or_default()
+        }).collect()
+    }
+    /// Posts a chargeback debited from the reserve, or credited back to it, to the books
+    /// and journal that are on
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client charged back
+    /// * 'tx' - The chargeback or representment
+    /// * 'amount' - How much the reserve went down by, negative if it was credited back
+    pub(crate) fn post_reserve(&mut self, client: u16, tx: u32, amount: Amount)
+    {
+        if let Some(books) = self.books.as_mut()
+        {
+            if let Some(saved) = self.savepoints.last_mut()
+            {
+                reserve_postings(amount).iter().for_each(|(account, _)| saved.books.keep(account, books.accounts.get(account)));
+            }
+            books.post_reserve(amount);
+        }
+        if let Some(journal) = self.journal.as_mut()
+        {
+            journal.record_reserve(client, tx, amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn accounts_derive_from_the_journal()
+    {
+        let mut engine = Engine::new(EnginePolicy { reserve: Some(Amount::from_minor(100000)), ..EnginePolicy::default() });
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.enable_journal();
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 3, Some(Amount::from_minor(10000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 2, 2, None));
+        let savepoint = engine.savepoint();
+        engine.apply(Tx::new(TypeTx::Resolve, 2, 2, None));
+        engine.rollback_to(&savepoint);
+        engine.release(&savepoint);
+        engine.apply(Tx::new(TypeTx::Chargeback, 2, 2, None));
+
+        let journal = engine.journal.as_ref().unwrap();
+        assert_eq!(journal.postings.last().map(|p| p.entry),Some(7));
+        assert!(journal.postings.iter().all(|p| !p.amount.is_negative()));
+        assert_eq!(journal.postings.iter().filter(|p| p.tx == 3).map(|p| (p.account, p.direction, p.amount)).collect::<Vec<_>>(),vec![
+            (BookAccount::CustomerFunds, Direction::Debit, Amount::from_minor(10000)),
+            (BookAccount::Cash, Direction::Credit, Amount::from_minor(10000)),
+        ]);
+        let derived = engine.journal_accounts().unwrap();
+        assert_eq!(derived[&1],engine.clients[&1].acc);
+        assert_eq!(derived[&2],engine.clients[&2].acc);
+        assert!(engine.journal_mismatches().is_empty());
+
+        let mut out = Vec::new();
+        write_journal(&journal.postings, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("entry,account,direction,amount,client,tx,cause\n1,customer_funds,credit,5.0,1,0,opening\n1,opening_balances,debit,5.0,1,0,opening\n"));
+    }
+}
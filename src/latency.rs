@@ -0,0 +1,168 @@
+use std::{convert::TryFrom, fmt, time::{Duration, Instant}};
+use serde::Serialize;
+use crate::{Engine, TypeTx};
+
+///
+/// The part of applying a row that took the most time
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SlowCause
+{
+    /// Reading the row back from the ingestion queue's spill file
+    #[serde(rename = "disk_spill")]
+    DiskSpill,
+    /// Waiting for the ingestion queue's lock while producers held it
+    #[serde(rename = "lock_contention")]
+    LockContention,
+    /// Catching up on recurring transactions, interest and settlements due before the row
+    #[serde(rename = "catch_up")]
+    CatchUp,
+    /// Checking the signature
+    #[serde(rename = "signature")]
+    Signature,
+    /// Screening the client
+    #[serde(rename = "screening")]
+    Screening,
+    /// The declarative rules and the policy hook
+    #[serde(rename = "policy")]
+    Policy,
+    /// Passing notifications on to the notifiers
+    #[serde(rename = "notify")]
+    Notify,
+    /// Applying the row to its client
+    #[serde(rename = "apply")]
+    Apply,
+}
+impl fmt::Display for SlowCause
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self
+        {
+            SlowCause::DiskSpill => "disk_spill",
+            SlowCause::LockContention => "lock_contention",
+            SlowCause::CatchUp => "catch_up",
+            SlowCause::Signature => "signature",
+            SlowCause::Screening => "screening",
+            SlowCause::Policy => "policy",
+            SlowCause::Notify => "notify",
+            SlowCause::Apply => "apply",
+        };
+        f.write_str(name)
+    }
+}
+
+///
+/// A row that took longer to apply than the latency budget
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SlowRow
+{
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: TypeTx,
+    /// How long it took, in microseconds
+    pub micros: u64,
+    /// What most of the time went to
+    pub cause: SlowCause,
+}
+
+impl Engine
+{
+    /// Times every row applied from now on, keeping those that take longer than the
+    /// budget in slow_rows; None stops timing
+    ///
+    /// # Arguments
+    ///
+    /// * 'budget' - How long a row may take
+    pub fn set_latency_budget(&mut self, budget: Option<Duration>)
+    {
+        self.latency_budget = budget;
+        self.row_timings.clear();
+    }
+    /// Starts timing a row or a part of it, None if there is no budget
+    pub(crate) fn start_timing(&self) -> Option<Instant>
+    {
+        self.latency_budget.map(|_| Instant::now())
+    }
+    /// Notes how long a part of the row being applied took
+    ///
+    /// # Arguments
+    ///
+    /// * 'cause' - The part
+    /// * 'start' - When it started, from start_timing
+    pub(crate) fn time_phase(&mut self, cause: SlowCause, start: Option<Instant>)
+    {
+        if let Some(start) = start
+        {
+            self.row_timings.push((cause, start.elapsed()));
+        }
+    }
+    /// Keeps the row in slow_rows if it went over the budget, blaming the part it
+    /// spent the most time in
+    ///
+    /// # Arguments
+    ///
+    /// * 'start' - When the row started, from start_timing
+    /// * 'client' - The client of the row
+    /// * 'tx' - The transaction ID of the row
+    /// * 'r#type' - The type of the row
+    pub(crate) fn finish_timing(&mut self, start: Option<Instant>, client: u16, tx: u32, r#type: TypeTx)
+    {
+        let mut timings = std::mem::take(&mut self.row_timings);
+        let (elapsed, budget) = match (start, self.latency_budget)
+        {
+            (Some(start), Some(budget)) => (start.elapsed(), budget),
+            _ => return
+        };
+        if elapsed <= budget {return}
+        //whatever isn't accounted for by the timed parts went to applying the row
+        let accounted: Duration = timings.iter().map(|(_, d)| *d).sum();
+        timings.push((SlowCause::Apply, elapsed.saturating_sub(accounted)));
+        let cause = timings.iter().max_by_key(|(_, d)| *d).map_or(SlowCause::Apply, |(cause, _)| *cause);
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.slow_rows.push(SlowRow { client, tx, r#type, micros, cause });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EnginePolicy, IngestQueue, Notification, Notifier, Overflow, Tx, TxRecord};
+
+    /// A notifier as slow as a webhook over a bad connection
+    struct Slow;
+    impl Notifier for Slow
+    {
+        fn notify(&mut self, _notification: &Notification)
+        {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn latency_budget()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.add_notifier(Box::new(Slow));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(10000))));
+        assert!(engine.slow_rows.is_empty());
+
+        engine.set_latency_budget(Some(Duration::from_millis(5)));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 2, Some(Amount::from_minor(10000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 2, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 2, None));
+        assert_eq!(engine.slow_rows.len(),1);
+        let slow = &engine.slow_rows[0];
+        assert_eq!((slow.client, slow.tx, slow.r#type, slow.cause),(1, 2, TypeTx::Chargeback, SlowCause::Notify));
+        assert!(slow.micros >= 20_000);
+
+        let path = std::env::temp_dir().join(format!("latency_spill_{}.csv", std::process::id()));
+        let queue = IngestQueue::new(1, Overflow::Spill(path.to_string_lossy().into_owned()));
+        for tx in 10..14 {queue.push(TxRecord::new(TypeTx::Deposit, 2, tx, Some("1.0".to_string()))).unwrap();}
+        queue.close();
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_latency_budget(Some(Duration::ZERO));
+        engine.apply_queue(&queue).unwrap();
+        assert_eq!(engine.slow_rows.iter().map(|r| r.tx).collect::<Vec<_>>(),vec![10, 11, 12, 13]);
+    }
+}
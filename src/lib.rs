@@ -1,7 +1,155 @@
-use std::{collections::{HashMap}, fmt::{self}, io};
+use std::{collections::{BTreeMap, HashMap}, fmt::{self}, io, str::FromStr};
 use serde::{Serialize,Deserialize};
 
-#[derive(Debug,Serialize,Deserialize,PartialEq)]
+mod amount;
+mod policy;
+mod engine;
+mod input;
+mod output;
+mod repl;
+mod notify;
+mod report;
+mod metadata;
+mod screening;
+mod signature;
+mod schedule;
+mod chain;
+mod snapshot;
+mod gateway;
+mod queue;
+mod filter;
+mod diff;
+mod checkpoint;
+mod ingest;
+mod custom;
+mod script;
+mod rules;
+mod generate;
+mod latency;
+mod disputes;
+mod reference;
+mod stream;
+mod client_map;
+mod dedup;
+mod wal;
+mod query;
+mod page;
+mod sse;
+mod bus;
+mod outbox;
+mod reconcile;
+mod books;
+mod journal;
+mod gl;
+mod period;
+mod opening;
+mod merge;
+mod subaccount;
+mod consolidate;
+mod profile;
+mod summary;
+mod rejection_stats;
+mod quality;
+mod convert;
+mod amount_parser;
+mod timestamp;
+mod ordering;
+mod undo_log;
+pub mod differential;
+pub mod bench;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+#[cfg(feature = "s3")]
+pub mod object_source;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis_state;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+#[cfg(feature = "webhook")]
+pub mod webhook;
+#[cfg(feature = "email")]
+pub mod email;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "amqp")]
+pub mod amqp;
+pub use amount::{Amount, AmountError, AMOUNT_PRECISION};
+pub use policy::{DisputeWindow, EnginePolicy, FreezePolicy, GlobalDedup, InterestPolicy, LockedAccount, RoundingMode, SchemaMode, TierLimits, UnexpectedAmount};
+pub use engine::{ADJUSTMENT_MEMO, BatchError, MAX_INTEREST_PERIODS, SYNTHETIC_TX_MIN, BatchOutcome, BatchReport, ColumnLengths, CounterpartyStats, Engine, RejectReason, Rejection, Reserve, ReserveEntry, ReviewEntry, Savepoint, write_rejections, write_review_queue};
+pub use input::{Dialect, HeaderError, OPTIONAL_COLUMNS, REQUIRED_COLUMNS};
+pub use stream::{AccountStream, StreamSummary};
+pub use client_map::{ClientHasher, ClientMap, ClientSet, ClientStore};
+pub use dedup::{BloomFilter, DedupStats, TxDedup};
+pub use output::{check_unique_clients, AccountSink, CsvSink, DuplicateClients, JsonLinesSink, JsonSink, OutputFilter, OutputFormat, SortKey};
+pub use repl::Repl;
+pub use notify::{CollectNotifier, Notification, Notifier, RejectionListener};
+pub use outbox::{BalanceChange, Outbox, OutboxEvent, OUTBOX_BATCH};
+pub use bus::{BusStats, Delivery};
+pub use books::{write_trial_balance, BookAccount, Posted, TrialBalance};
+pub use journal::{write_journal, Direction, Journal, Posting};
+pub use gl::{write_gl_journal, GlError, GlMapping, GlRule};
+pub use period::{ClientPeriod, Period, PeriodBalance, PeriodClose, TypeTotal};
+pub use opening::{read_opening_balances, OpeningBalance, OpeningBalanceError, OPENING_HELD_MEMO};
+pub use merge::MergeError;
+pub use subaccount::{write_sub_accounts, SubAccount, MAIN_ACCOUNT};
+pub use consolidate::{write_consolidation, Consolidation, CurrencyTotal, Rate, RateProvider, RateTable, RATE_PRECISION};
+pub use profile::{write_ledger_with_profile, DateFormat, ExportProfile};
+pub use summary::{write_summary, ReportFormat};
+pub use rejection_stats::{RejectedTotal, RejectionStats};
+pub use quality::DataProfile;
+pub use amount_parser::{parse_amount_parser, AmountParser, MinorUnits, PlainDecimal, SymbolPrefixed};
+pub use timestamp::{parse_utc_offset, TimestampConfig, TimestampError};
+pub use ordering::{order_by_timestamp, TieBreak, TimestampMerge};
+pub use convert::{AmountSign, ColumnSource, ConversionReport, ConvertError, ConvertMapping};
+pub use reconcile::{read_expected, reconcile, write_discrepancies, Discrepancy, ExpectedBalance, Reconciliation, Tolerance};
+pub use sse::{RejectionBroadcast, RejectionEvent, RejectionSubscriber, SUBSCRIBER_BACKLOG};
+pub use metadata::{read_metadata, ClientMetadata};
+pub use snapshot::{SnapshotError, SnapshotKey, SNAPSHOT_VERSION};
+pub use page::{AccountPage, PageRequest, DEFAULT_PAGE_LIMIT, MAX_PAGE_LIMIT};
+pub use query::{write_history, HistoryRow, ReadOnlyEngine};
+pub use wal::{Durability, Wal, WalMark, WalStats};
+pub use checkpoint::{BackgroundCheckpoints, BackgroundStats, Checkpoint, CheckpointError};
+pub use custom::CustomTxHandler;
+pub use script::{PolicyHook, FEE_MEMO};
+pub use rules::{Condition, Rule, RuleAction, RuleError, RuleFlag, RuleSet, Verdict};
+pub use reference::ReferenceModel;
+pub use latency::{SlowCause, SlowRow};
+pub use disputes::{write_dispute_lifecycles, write_held_ageing, AgeBucket, BucketTotal, DisputeEvent, DisputeLifecycle, DisputeStatus, HeldAgeing, HeldDispute};
+pub use generate::{generate, parse_count, GeneratorConfig, GeneratorStats};
+#[cfg(feature = "scripting")]
+pub use script::{RhaiPolicy, ScriptError, MAX_CALL_LEVELS, MAX_OPERATIONS};
+pub use ingest::{content_hash, IngestedFile};
+pub use diff::{write_changes, AccountChange, ChangeFeed};
+pub use filter::{parse_types, ClientFilter, StopAt};
+pub use queue::{IngestQueue, Overflow, QueueStats};
+pub use gateway::{ApiTokens, AuthError, Gateway, GatewayStats, RateLimit, RateLimits, Role};
+pub use chain::{chain_hash, ledger_head, verify_ledger, ChainError, GENESIS_HASH};
+pub use schedule::{read_schedules, Schedule, RECURRING_MEMO};
+pub use screening::{ListScreening, NoScreening, Screening, ScreeningProvider};
+pub use signature::{signed_message, SignatureVerifier};
+#[cfg(feature = "ed25519")]
+pub use signature::Ed25519Verifier;
+pub use report::{anomalies, ClientSummary, CounterpartySummary, DisputeSummary, Report, Warning};
+
+#[derive(Debug,Clone,Copy,Serialize,Deserialize,PartialEq)]
 pub enum TypeTx 
 {
     #[serde(rename = "deposit")]
@@ -13,7 +161,19 @@ pub enum TypeTx
     #[serde(rename = "resolve")]
     Resolve,
     #[serde(rename = "chargeback")]
-    Chargeback
+    Chargeback,
+    /// Contests a chargeback, putting its funds back in held until a second resolve or chargeback decides it
+    #[serde(rename = "representment")]
+    Representment,
+    /// Puts the account up for manual review, withdrawals are refused until it is released
+    #[serde(rename = "compliance_hold")]
+    ComplianceHold,
+    /// Takes the account out of review
+    #[serde(rename = "compliance_release")]
+    ComplianceRelease,
+    /// A type the engine doesn't know, handled by the CustomTxHandler registered under its name
+    #[serde(rename = "custom", other)]
+    Custom
 }
 impl fmt::Display for TypeTx
 {
@@ -21,13 +181,87 @@ impl fmt::Display for TypeTx
         write!(f, "{:?}", self)
     }
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Tx 
 {
     pub r#type: TypeTx,
     pub client: u16,
     pub tx: u32,
-    pub amount: Option<f64>
+    pub amount: Option<Amount>,
+    /// When the transaction happened, in seconds since the unix epoch
+    pub timestamp: Option<i64>,
+    /// The currency code of the amount, E.G. "EUR"
+    pub currency: Option<String>,
+    /// Free text from the upstream system
+    pub memo: Option<String>,
+    /// The merchant or other party on the other side, E.G. "acme-shop"
+    pub counterparty: Option<String>,
+    /// Hex encoded signature over signed_message, checked if the engine has a verifier
+    pub signature: Option<String>,
+    /// The name of the type, for custom transactions
+    #[serde(default)]
+    pub custom: Option<String>,
+    /// The sub-account of the client the funds go in or out of, the main one if None
+    #[serde(default)]
+    pub account: Option<String>
+}
+impl Tx
+{
+    ///
+    /// Returns a new transaction with none of the optional columns set
+    /// 
+    /// # Arguments
+    /// 
+    /// * 'r#type' - What kind of transaction it is
+    /// * 'client' - The client ID
+    /// * 'tx' - The transaction ID
+    /// * 'amount' - The amount, for deposits and withdrawals
+    pub fn new(r#type: TypeTx, client: u16, tx: u32, amount: Option<Amount>) -> Tx
+    {
+        Tx { r#type, client, tx, amount, timestamp: None, currency: None, memo: None, counterparty: None, signature: None, custom: None, account: None }
+    }
+}
+impl FromStr for TypeTx
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "deposit" => Ok(TypeTx::Deposit),
+            "withdrawal" => Ok(TypeTx::Withdrawal),
+            "dispute" => Ok(TypeTx::Dispute),
+            "resolve" => Ok(TypeTx::Resolve),
+            "chargeback" => Ok(TypeTx::Chargeback),
+            "representment" => Ok(TypeTx::Representment),
+            "compliance_hold" => Ok(TypeTx::ComplianceHold),
+            "compliance_release" => Ok(TypeTx::ComplianceRelease),
+            _ => Err(format!("unknown transaction type '{}'", s))
+        }
+    }
+}
+impl TypeTx
+{
+    /// Whether this type of transaction moves money, and so needs an amount
+    pub fn carries_amount(&self) -> bool
+    {
+        matches!(self, TypeTx::Deposit | TypeTx::Withdrawal)
+    }
+    /// The name of the type as it appears in the input, E.G. "deposit"
+    pub fn as_str(&self) -> &'static str
+    {
+        match self
+        {
+            TypeTx::Deposit => "deposit",
+            TypeTx::Withdrawal => "withdrawal",
+            TypeTx::Dispute => "dispute",
+            TypeTx::Resolve => "resolve",
+            TypeTx::Chargeback => "chargeback",
+            TypeTx::Representment => "representment",
+            TypeTx::ComplianceHold => "compliance_hold",
+            TypeTx::ComplianceRelease => "compliance_release",
+            TypeTx::Custom => "custom"
+        }
+    }
 }
 impl fmt::Display for Tx
 {
@@ -35,26 +269,261 @@ impl fmt::Display for Tx
     {
         f.write_str(
             format!("Id: {}, Tx: {}, Type: {}, Amount: {}", 
-            self.client, self.tx, self.r#type, self.amount.unwrap_or(0.0)).as_str()
+            self.client, self.tx, self.r#type, self.amount.unwrap_or(Amount::ZERO)).as_str()
         )   
     }
 }
 
+///
+/// A transaction as read from the input, with the amount and timestamp still as text
+/// 
+/// The amount is only parsed once we know how it should be rounded
+/// 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TxRecord
+{
+    pub r#type: TypeTx,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<String>,
+    /// Whether the amount couldn't be read in the notation of its input, E.G. a decimal
+    /// in a feed of cents, so the row is rejected as an invalid amount
+    #[serde(default)]
+    pub invalid_amount: bool,
+    #[serde(default)]
+    pub timestamp: Option<String>,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default)]
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    /// The book the transaction belongs to, a run only processes the rows of one
+    #[serde(default)]
+    pub tenant: Option<String>,
+    #[serde(default)]
+    pub signature: Option<String>,
+    /// The name of the type, for custom transactions, taken from the type column
+    #[serde(default)]
+    pub custom: Option<String>,
+    /// The sub-account of the client, E.G. "savings", the main one if left out
+    #[serde(default)]
+    pub account: Option<String>
+}
+impl TxRecord
+{
+    /// Returns a new record with none of the optional columns set
+    pub fn new(r#type: TypeTx, client: u16, tx: u32, amount: Option<String>) -> TxRecord
+    {
+        TxRecord { r#type, client, tx, amount, invalid_amount: false, timestamp: None, currency: None, memo: None, counterparty: None, tenant: None, signature: None, custom: None, account: None }
+    }
+    /// Returns a new record of a custom type, handled by the CustomTxHandler registered under its name
+    ///
+    /// # Arguments
+    ///
+    /// * 'name' - The name of the type, as it appears in the type column
+    /// * 'client' - The client ID
+    /// * 'tx' - The transaction ID
+    /// * 'amount' - The amount, if the type takes one
+    pub fn custom(name: &str, client: u16, tx: u32, amount: Option<String>) -> TxRecord
+    {
+        TxRecord { custom: Some(name.to_string()), ..TxRecord::new(TypeTx::Custom, client, tx, amount) }
+    }
+    /// Reads a transaction from json, E.G. {"type":"deposit","client":1,"tx":1,"amount":"1.5"}
+    ///
+    /// The amount may also be a json number, which is read from its text so no
+    /// precision is lost on the way
+    ///
+    /// # Arguments
+    ///
+    /// * 'json' - The transaction as a json object
+    pub fn from_json(json: &[u8]) -> serde_json::Result<TxRecord>
+    {
+        let mut value: serde_json::Value = serde_json::from_slice(json)?;
+        if let Some(amount) = value.get_mut("amount")
+        {
+            if amount.is_number()
+            {
+                *amount = serde_json::Value::String(amount.to_string());
+            }
+        }
+        serde_json::from_value(value)
+    }
+    /// Parses the amount and timestamp and returns the transaction
+    /// 
+    /// # Arguments
+    /// 
+    /// 'rounding' - What to do with amounts that have more than four decimals
+    pub fn to_tx(&self, rounding: RoundingMode) -> Result<Tx, RejectReason>
+    {
+        let amount = match &self.amount
+        {
+            Some(_) if self.invalid_amount => return Err(RejectReason::InvalidAmount),
+            Some(text) => Some(Amount::parse(text, rounding)?),
+            None => None
+        };
+        let timestamp = match &self.timestamp
+        {
+            Some(text) => Some(text.parse().map_err(|_| RejectReason::InvalidTimestamp)?),
+            None => None
+        };
+        Ok(Tx {
+            r#type: self.r#type,
+            client: self.client,
+            tx: self.tx,
+            amount,
+            timestamp,
+            currency: self.currency.clone(),
+            memo: self.memo.clone(),
+            counterparty: self.counterparty.clone(),
+            signature: self.signature.clone(),
+            custom: self.custom.clone(),
+            account: self.account.clone().filter(|a| a != MAIN_ACCOUNT)
+        })
+    }
+}
+
+///
+/// Why a transaction couldn't be applied to an account
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxError
+{
+    /// The balance update would go past what an amount can hold
+    Overflow,
+    /// A deposit or withdrawal came without an amount
+    MissingAmount,
+    /// A deposit or withdrawal larger than the tier of the account allows
+    AboveTierLimit,
+    /// A dispute while the tier of the account allows no more open ones
+    TooManyDisputes,
+    /// A dispute of a transaction older than the dispute window of the account
+    DisputeWindowExpired,
+    /// A withdrawal from a locked account, under a policy that takes deposits to held
+    AccountLocked,
+}
+impl fmt::Display for TxError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// The tenant of records without a tenant column, unless another one is given
+pub const DEFAULT_TENANT: &str = "default";
+
+/// The memo of the history entries interest is credited as
+pub const INTEREST_MEMO: &str = "interest";
+
+/// The longest memo kept in the history, in characters
+pub const MAX_MEMO_LEN: usize = 256;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ClientTransaction
 {
-    pub amount: f64,
+    pub amount: Amount,
     pub in_dispute: bool,
+    /// The memo of the transaction, cut down to MAX_MEMO_LEN characters
+    pub memo: Option<String>,
+    /// Who the transaction was with, so chargebacks can be put down to them
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    /// Deposited but not settled yet, so the amount is in pending rather than available
+    #[serde(default)]
+    pub pending: bool,
+    /// The disputes, resolves, chargebacks and representments applied to it, in order
+    #[serde(default)]
+    pub dispute_chain: Vec<TypeTx>,
+    /// The row it came in at, counting from 1, or 0 if it isn't known
+    #[serde(default)]
+    pub row: u64,
+    /// The timestamp of the transaction, if it had one
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+    /// The sub-account its funds went into, the main one if None
+    #[serde(default)]
+    pub account: Option<String>,
+    /// Deposited to held while the account was locked, rather than disputed, so the
+    /// amount stays in held until the account is unlocked
+    #[serde(default)]
+    pub held_while_locked: bool,
+}
+impl ClientTransaction
+{
+    /// Returns a new, undisputed history entry
+    /// 
+    /// # Arguments
+    /// 
+    /// 'amount' - The amount of the transaction
+    /// 'memo' - The memo of the transaction, which is cut down if too long
+    pub fn new(amount: Amount, memo: Option<&str>) -> ClientTransaction
+    {
+        let memo = memo.map(|m| m.chars().take(MAX_MEMO_LEN).collect());
+        ClientTransaction { amount, in_dispute: false, memo, counterparty: None, pending: false, dispute_chain: Vec::new(), row: 0, timestamp: None, account: None, held_while_locked: false }
+    }
+    /// Whether its chargeback has been contested and is waiting for a second resolve or chargeback
+    pub fn represented(&self) -> bool
+    {
+        self.dispute_chain.last() == Some(&TypeTx::Representment)
+    }
+    /// The dispute chain as it is written in the ledger export, E.G. "dispute>chargeback>representment"
+    pub fn dispute_chain_text(&self) -> String
+    {
+        self.dispute_chain.iter().map(|t| t.as_str()).collect::<Vec<_>>().join(">")
+    }
+}
+
+///
+/// How the last transaction applied to a client changed it, so the change can be undone
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LastChange
+{
+    pub tx: u32,
+    pub r#type: TypeTx,
+    /// How much each balance went up by, negative if it went down
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub pending: Amount,
+    /// Whether the account was locked before
+    pub was_locked: bool,
+    /// The history entry of the transaction as it was, None if the transaction added it
+    pub entry: Option<ClientTransaction>,
+    /// The sub-account the change was made to, the main one if None
+    #[serde(default)]
+    pub account: Option<String>,
 }
 
 ///
 /// This represents a clients account and their transaction history
 /// 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Client
 {
     /// Account of the client, with the client ID
     pub acc: Account,
     /// History of client transactions (deposits and withdrawals)
     pub history: HashMap<u32,ClientTransaction>,
+    /// Frozen for too many chargebacks, so withdrawals are refused
+    #[serde(default)]
+    pub frozen: bool,
+    /// How far available may go below zero through withdrawals
+    #[serde(default)]
+    pub credit_limit: Amount,
+    /// The limits of the tier of the account
+    #[serde(default)]
+    pub limits: TierLimits,
+    /// The last change a transaction made, until it is undone
+    #[serde(default)]
+    pub last_change: Option<LastChange>,
+    /// Merged into another client, so every transaction for it is rejected
+    #[serde(default)]
+    pub closed: bool,
+    /// The balances of every named sub-account, keyed by name, which are part of those
+    /// of the account; what is left over is the main sub-account
+    #[serde(default)]
+    pub sub_accounts: BTreeMap<String, SubAccount>,
 }
 impl Client
 {
@@ -65,7 +534,7 @@ impl Client
     /// 
     /// * 'name' - The Client ID, as a u32 
     pub fn new(id: u16) -> Client{
-        Client { acc: Account::new(id), history:HashMap::new() }
+        Client { acc: Account::new(id), history:HashMap::new(), frozen: false, credit_limit: Amount::ZERO, limits: TierLimits::default(), last_change: None, closed: false, sub_accounts: BTreeMap::new() }
     }
     /// Gets a transaction based on ID, if the client has it
     /// 
@@ -77,77 +546,300 @@ impl Client
     /// tests later I decided to keep it like this
     pub fn get_transaction(&self, id: &u32) -> Option<&ClientTransaction>
     {
-        let out= match self.history.get(id)
-        {
-            Some(tx) => Some(tx),
-            _ => None
-        };
-        out
+        self.history.get(id)
     }
     /// Sets a transaction to disputed state, if the client has it
     /// 
     /// # Arguments
     /// 
     /// 'id' - The transaction ID, as u32
-    pub fn dispute_transaction(&mut self, id: &u32)
+    /// 
+    /// # Errors
+    /// 
+    /// Returns TxError::TooManyDisputes if the tier of the account allows no more open disputes
+    pub fn dispute_transaction(&mut self, id: &u32) -> Result<(), TxError>
     {
+        //the open disputes are only counted when the tier limits them, as it takes a pass over the history
+        let disputable = self.history.get(id).is_some_and(|tx| !tx.in_dispute && !tx.held_while_locked);
+        if disputable && self.limits.max_open_disputes.is_some_and(|max| self.history.values().filter(|h| h.in_dispute).count() >= max)
+        {
+            return Err(TxError::TooManyDisputes)
+        }
         let try_tx = self.history.get_mut(id);
         match try_tx
         {
             Some(tx) 
-            if tx.in_dispute == false => {
-                self.acc.held += tx.amount;
-                self.acc.available -= tx.amount;
+            if !tx.in_dispute && !tx.held_while_locked => {
+                let held = checked_add(self.acc.held, tx.amount)?;
+                //a deposit that hasn't settled yet is held out of pending, and counts as settled once resolved
+                let (available, pending) = match tx.pending
+                {
+                    true => (self.acc.available, checked_sub(self.acc.pending, tx.amount)?),
+                    false => (checked_sub(self.acc.available, tx.amount)?, self.acc.pending)
+                };
+                self.acc.held = held;
+                self.acc.available = available;
+                self.acc.pending = pending;
                 tx.in_dispute = true;
+                tx.pending = false;
+                tx.dispute_chain.push(TypeTx::Dispute);
             },
             _ => ()
         }
+        Ok(())
+    }
+    /// Sets a transaction to disputed state like dispute_transaction, unless it is
+    /// older than the dispute window of the account
+    ///
+    /// # Arguments
+    ///
+    /// 'id' - The transaction ID, as u32
+    /// 'row' - The row of the dispute
+    /// 'timestamp' - The timestamp of the dispute, if it has one
+    ///
+    /// # Errors
+    ///
+    /// Returns TxError::DisputeWindowExpired if the transaction is too old, or whatever dispute_transaction does
+    pub fn dispute_within_window(&mut self, id: &u32, row: u64, timestamp: Option<i64>) -> Result<(), TxError>
+    {
+        let window = self.limits.dispute_window;
+        if self.history.get(id).is_some_and(|tx| !tx.in_dispute && window.expired((tx.row, tx.timestamp), (row, timestamp)))
+        {
+            return Err(TxError::DisputeWindowExpired);
+        }
+        self.dispute_transaction(id)
     }
     /// Resolves a transaction in a disputed state, if the client has it
     /// 
     /// # Constraint
-    /// This can only run if account is not locked
+    /// This can only run if account is not locked, unless the transaction is represented
     /// 
     /// # Arguments
     /// 
     /// 'id' - The transaction ID, as u32
-    pub fn resolve_transaction(&mut self, id: &u32)
+    pub fn resolve_transaction(&mut self, id: &u32) -> Result<(), TxError>
     {
-        if self.acc.locked == true{return;}
+        if self.acc.locked && !self.history.get(id).is_some_and(|tx| tx.represented()) {return Ok(());}
         let try_tx = self.history.get_mut(id);
         match try_tx
         {
-            Some(tx) if tx.in_dispute == true => {
-                self.acc.held -= tx.amount;
-                self.acc.available += tx.amount;
+            Some(tx) if tx.in_dispute => {
+                let held = checked_sub(self.acc.held, tx.amount)?;
+                let available = checked_add(self.acc.available, tx.amount)?;
+                self.acc.held = held;
+                self.acc.available = available;
                 tx.in_dispute = false;
+                tx.dispute_chain.push(TypeTx::Resolve);
             },
             _ => ()
         }
+        Ok(())
     }
     /// Chargebacks a transaction in a disputed state, if the client has it
     /// This also locks the account
     /// 
     /// # Constraint
-    /// This can only run if account is not locked
+    /// This can only run if account is not locked, unless the transaction is represented
     /// 
     /// # Arguments
     /// 
     /// 'id' - The transaction ID, as u32
-    pub fn chargeback_transaction(&mut self, id: &u32)
+    pub fn chargeback_transaction(&mut self, id: &u32) -> Result<(), TxError>
     {
-        if self.acc.locked == true{return;}
+        if self.acc.locked && !self.history.get(id).is_some_and(|tx| tx.represented()) {return Ok(());}
         let try_tx = self.history.get_mut(id);
         match try_tx
         {
             Some(tx) 
-            if tx.in_dispute == true => {
-                self.acc.held -= tx.amount;
-                self.acc.total -= tx.amount;
+            if tx.in_dispute => {
+                let held = checked_sub(self.acc.held, tx.amount)?;
+                let total = checked_sub(self.acc.total, tx.amount)?;
+                self.acc.held = held;
+                self.acc.total = total;
                 self.acc.locked = true;
+                tx.dispute_chain.push(TypeTx::Chargeback);
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+    /// Contests the chargeback of a transaction, if the client has it charged back
+    /// and it hasn't been contested before
+    ///
+    /// The funds go back to held until a second resolve or chargeback decides it, which
+    /// is final. The account stays locked, but the decision still goes through
+    ///
+    /// # Arguments
+    ///
+    /// 'id' - The transaction ID, as u32
+    pub fn represent_transaction(&mut self, id: &u32) -> Result<(), TxError>
+    {
+        match self.history.get_mut(id)
+        {
+            Some(tx)
+            if tx.dispute_chain.last() == Some(&TypeTx::Chargeback) && !tx.dispute_chain.contains(&TypeTx::Representment) => {
+                let held = checked_add(self.acc.held, tx.amount)?;
+                let total = checked_add(self.acc.total, tx.amount)?;
+                self.acc.held = held;
+                self.acc.total = total;
+                tx.dispute_chain.push(TypeTx::Representment);
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+    /// Remembers how a transaction changed the client, so undo_last can reverse it
+    ///
+    /// Nothing is remembered if the transaction changed nothing, so the previous change can still be undone
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction that was just applied
+    /// 'before' - The account as it was before the transaction
+    /// 'entry' - The history entry of the transaction as it was before, if it had one
+    pub fn record_change(&mut self, tx: &Tx, before: &Account, entry: Option<ClientTransaction>)
+    {
+        let now = self.history.get(&tx.tx);
+        let entry_changed = match (&entry, now)
+        {
+            (Some(old), Some(new)) => old.in_dispute != new.in_dispute || old.pending != new.pending || old.dispute_chain != new.dispute_chain || old.held_while_locked != new.held_while_locked,
+            (None, None) => false,
+            _ => true
+        };
+        let deltas = (
+            self.acc.available.checked_sub(before.available),
+            self.acc.held.checked_sub(before.held),
+            self.acc.total.checked_sub(before.total),
+            self.acc.pending.checked_sub(before.pending),
+        );
+        let (available, held, total, pending) = match deltas
+        {
+            (Some(available), Some(held), Some(total), Some(pending)) => (available, held, total, pending),
+            //a change that can't be put into deltas can't be undone either
+            _ => {self.last_change = None; return}
+        };
+        let unchanged = [available, held, total, pending].iter().all(|d| *d == Amount::ZERO);
+        if unchanged && !entry_changed && before.locked == self.acc.locked {return}
+        let account = self.sub_account_of(tx);
+        self.last_change = Some(LastChange { tx: tx.tx, r#type: tx.r#type, available, held, total, pending, was_locked: before.locked, entry, account });
+    }
+    /// Reverses the balance effects of the last transaction applied, putting its
+    /// history entry and the lock back as they were
+    ///
+    /// Only the last change is kept, so only one transaction can be undone. Anything
+    /// that changed the account since without a transaction, like interest, stays
+    ///
+    /// Returns the ID of the transaction undone, or None if there is nothing to undo
+    /// or undoing it would overflow a balance
+    pub fn undo_last(&mut self) -> Option<u32>
+    {
+        let change = self.last_change.take()?;
+        let reversed = (
+            self.acc.available.checked_sub(change.available),
+            self.acc.held.checked_sub(change.held),
+            self.acc.total.checked_sub(change.total),
+            self.acc.pending.checked_sub(change.pending),
+        );
+        match reversed
+        {
+            (Some(available), Some(held), Some(total), Some(pending)) => {
+                self.acc.available = available;
+                self.acc.held = held;
+                self.acc.total = total;
+                self.acc.pending = pending;
+            },
+            _ => {self.last_change = Some(change); return None}
+        }
+        if let Some(sub) = change.account.as_ref().and_then(|name| self.sub_accounts.get_mut(name))
+        {
+            let reversed = SubAccount { available: change.available, held: change.held, total: change.total, pending: change.pending };
+            sub.take(&reversed);
+        }
+        self.acc.locked = change.was_locked;
+        match change.entry
+        {
+            Some(entry) => {self.history.insert(change.tx, entry);},
+            None => {self.history.remove(&change.tx);}
+        }
+        Some(change.tx)
+    }
+    /// Moves a deposit out of available into pending, until it is settled
+    ///
+    /// Nothing happens if the client doesn't have it, or it is disputed or already pending
+    ///
+    /// # Arguments
+    ///
+    /// 'id' - The transaction ID of the deposit
+    pub fn defer_settlement(&mut self, id: &u32) -> Result<(), TxError>
+    {
+        match self.history.get_mut(id)
+        {
+            Some(tx) if !tx.in_dispute && !tx.pending => {
+                let available = checked_sub(self.acc.available, tx.amount)?;
+                let pending = checked_add(self.acc.pending, tx.amount)?;
+                self.acc.available = available;
+                self.acc.pending = pending;
+                tx.pending = true;
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+    /// Settles a pending deposit, moving it from pending into available
+    ///
+    /// Nothing happens if the client doesn't have it or it isn't pending
+    ///
+    /// # Arguments
+    ///
+    /// 'id' - The transaction ID of the deposit
+    pub fn settle_transaction(&mut self, id: &u32) -> Result<(), TxError>
+    {
+        match self.history.get_mut(id)
+        {
+            Some(tx) if tx.pending => {
+                let pending = checked_sub(self.acc.pending, tx.amount)?;
+                let available = checked_add(self.acc.available, tx.amount)?;
+                self.acc.pending = pending;
+                self.acc.available = available;
+                tx.pending = false;
             },
             _ => ()
         }
+        Ok(())
+    }
+    /// Credits interest on the available balance, kept in the history like a
+    /// deposit with INTEREST_MEMO as its memo
+    ///
+    /// # Constraint
+    /// Nothing is credited to a locked account, or if nothing is available
+    ///
+    /// # Arguments
+    ///
+    /// 'id' - The transaction ID the interest is recorded under
+    /// 'rate' - The share of the available balance to credit, E.G. 0.001
+    ///
+    /// Returns the interest credited
+    pub fn accrue_interest(&mut self, id: u32, rate: Amount) -> Result<Amount, TxError>
+    {
+        let interest = self.interest(rate)?;
+        if interest <= Amount::ZERO {return Ok(Amount::ZERO)}
+        let total = checked_add(self.acc.total, interest)?;
+        let available = checked_add(self.acc.available, interest)?;
+        self.acc.total = total;
+        self.acc.available = available;
+        self.history.insert(id, ClientTransaction::new(interest, Some(INTEREST_MEMO)));
+        Ok(interest)
+    }
+    /// The interest accrue_interest would credit, without crediting it
+    ///
+    /// # Arguments
+    ///
+    /// 'rate' - The share of the available balance to credit, E.G. 0.001
+    pub fn interest(&self, rate: Amount) -> Result<Amount, TxError>
+    {
+        if self.acc.locked || self.acc.available <= Amount::ZERO {return Ok(Amount::ZERO)}
+        let interest = self.acc.available.checked_mul_rate(rate).ok_or(TxError::Overflow)?;
+        Ok(interest.max(Amount::ZERO))
     }
     /// Processes a Deposit/Withdrawal style transaction, increasing/decreasing the total/available
     /// and adds it to the history
@@ -161,40 +853,148 @@ impl Client
     /// # Arguments
     /// 
     /// 'tx' - A reference to the transaction
-    pub fn process_transaction(&mut self, tx: &Tx)
+    /// 
+    /// # Errors
+    /// 
+    /// Returns TxError::Overflow if the balances can't hold the result, in
+    /// which case the account is left as it was
+    /// 
+    /// Returns TxError::MissingAmount if the transaction has no amount, so it
+    /// doesn't end up in the history and block a later transaction with the same ID
+    /// 
+    /// Returns TxError::AboveTierLimit if the amount is larger than the tier of the account allows
+    pub fn process_transaction(&mut self, tx: &Tx) -> Result<(), TxError>
+    {
+        if self.acc.locked {return Ok(())}
+        self.move_funds(tx)
+    }
+    /// Processes a Deposit/Withdrawal style transaction for a locked account,
+    /// following what the policy says should happen to it
+    ///
+    /// A deposit accepted to held is added to the history as held while locked, not
+    /// disputed, so it can't be disputed, resolved or charged back, and its funds are
+    /// released once the account is unlocked. A withdrawal can't be taken from held,
+    /// so under AcceptToHeld it is refused
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - A reference to the transaction
+    /// 'action' - What the policy says to do with it
+    ///
+    /// # Errors
+    ///
+    /// The same as process_transaction, and TxError::AccountLocked for a withdrawal under AcceptToHeld
+    pub fn process_locked_transaction(&mut self, tx: &Tx, action: LockedAccount) -> Result<(), TxError>
+    {
+        match (action, tx.r#type)
+        {
+            (LockedAccount::AcceptNormally, _) => self.move_funds(tx),
+            (LockedAccount::AcceptToHeld, TypeTx::Deposit) => {
+                if self.history.contains_key(&tx.tx) {return Ok(())}
+                let amount = tx.amount.ok_or(TxError::MissingAmount)?;
+                if amount.is_negative() {return Ok(())}
+                let total = checked_add(self.acc.total, amount)?;
+                let held = checked_add(self.acc.held, amount)?;
+                self.acc.total = total;
+                self.acc.held = held;
+                let mut entry = ClientTransaction::new(amount, tx.memo.as_deref());
+                entry.held_while_locked = true;
+                entry.counterparty = tx.counterparty.clone();
+                entry.account = tx.account.clone();
+                self.history.insert(tx.tx, entry);
+                Ok(())
+            },
+            (LockedAccount::AcceptToHeld, TypeTx::Withdrawal) => Err(TxError::AccountLocked),
+            _ => Ok(())
+        }
+    }
+    /// Moves a deposit held while the account was locked to available, if the client has it
+    ///
+    /// # Arguments
+    ///
+    /// 'id' - The transaction ID, as u32
+    pub fn release_locked_deposit(&mut self, id: &u32) -> Result<(), TxError>
     {
-        if self.acc.locked || self.history.contains_key(&tx.tx) {return}
-        let amount = tx.amount.unwrap_or(0f64); //if something went wrong just set it to 0 and move on
-        if amount < 0.0 {return}
+        match self.history.get_mut(id)
+        {
+            Some(tx) if tx.held_while_locked => {
+                let held = checked_sub(self.acc.held, tx.amount)?;
+                let available = checked_add(self.acc.available, tx.amount)?;
+                self.acc.held = held;
+                self.acc.available = available;
+                tx.held_while_locked = false;
+            },
+            _ => ()
+        }
+        Ok(())
+    }
+    /// Moves the funds of a Deposit/Withdrawal style transaction, whether the account is locked or not
+    fn move_funds(&mut self, tx: &Tx) -> Result<(), TxError>
+    {
+        if self.history.contains_key(&tx.tx) {return Ok(())}
+        let amount = match tx.amount
+        {
+            Some(amount) => amount,
+            None => return Err(TxError::MissingAmount)
+        };
+        if amount.is_negative() {return Ok(())}
+        let limit = match tx.r#type
+        {
+            TypeTx::Deposit => self.limits.max_deposit,
+            _ => self.limits.max_withdrawal
+        };
+        if limit.is_some_and(|max| amount > max) {return Err(TxError::AboveTierLimit)}
         match tx.r#type
         {
             TypeTx::Deposit => {
-                self.acc.total+=amount;
-                self.acc.available+=amount;
-                self.history.insert(tx.tx, ClientTransaction{amount, in_dispute:false});
+                let total = checked_add(self.acc.total, amount)?;
+                let available = checked_add(self.acc.available, amount)?;
+                self.acc.total = total;
+                self.acc.available = available;
+                let mut entry = ClientTransaction::new(amount, tx.memo.as_deref());
+                entry.counterparty = tx.counterparty.clone();
+                entry.account = tx.account.clone();
+                self.history.insert(tx.tx, entry);
             },
-            TypeTx::Withdrawal if self.acc.available > amount => {
-                self.acc.total-=amount;
-                self.acc.available-=amount;
+            TypeTx::Withdrawal if self.can_withdraw(tx.account.as_deref(), amount)? => {
+                let total = checked_sub(self.acc.total, amount)?;
+                let available = checked_sub(self.acc.available, amount)?;
+                self.acc.total = total;
+                self.acc.available = available;
             },
             _ => ()
         }
+        Ok(())
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Adds two balances, failing instead of wrapping around
+fn checked_add(a: Amount, b: Amount) -> Result<Amount, TxError>
+{
+    a.checked_add(b).ok_or(TxError::Overflow)
+}
+/// Subtracts two balances, failing instead of wrapping around
+fn checked_sub(a: Amount, b: Amount) -> Result<Amount, TxError>
+{
+    a.checked_sub(b).ok_or(TxError::Overflow)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Account 
 {
     pub client: u16,
-    pub available: f64,
-    pub held: f64,
-    pub total: f64,
-    pub locked: bool
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    /// Deposited but not settled yet, so part of the total but not available
+    #[serde(default)]
+    pub pending: Amount
 }
 impl Account
 {
     pub fn new(id: u16) -> Account{
-        Account { client: id, available: 0.0, held: 0.0, total: 0.0, locked: false }
+        Account { client: id, available: Amount::ZERO, held: Amount::ZERO, total: Amount::ZERO, locked: false, pending: Amount::ZERO }
     }
 }
 impl fmt::Display for Account
@@ -204,61 +1004,169 @@ impl fmt::Display for Account
         f.write_str(
             format!(" available: {}, held: {}, total: {}, locked:{}", 
             self.available, self.held, self.total, self.locked).as_str()
-        )   
+        )?;
+        if self.pending != Amount::ZERO
+        {
+            write!(f, ", pending: {}", self.pending)?;
+        }
+        Ok(())
     }
 }
 
-/// Writes the resulting accounts to stdout
+/// Writes the resulting accounts to a sink
 /// 
 /// # Arguments
 /// 
 /// * 'clients' - The list of clients that have been processed, as a HashMap<u32,Client>
-pub fn write_output(clients: HashMap<u16, Client>)
+/// * 'sink' - Where to write the accounts, E.G. `OutputFormat::Csv.sink(io::stdout())`
+pub fn write_output(clients: &ClientStore, sink: &mut dyn AccountSink) -> io::Result<()>
+{
+    write_filtered_output(clients, &OutputFilter::default(), sink)
+}
+
+/// Writes the accounts that pass the filter to a sink
+/// 
+/// Without a top-N the accounts are written as they are found, so no more than
+/// one is held at a time; with one they are ranked first
+/// 
+/// # Arguments
+/// 
+/// * 'clients' - The clients that have been processed
+/// * 'filter' - Which accounts to write
+/// * 'sink' - Where to write the accounts
+///
+/// # Errors
+///
+/// Fails with ErrorKind::InvalidData before anything is written if two accounts carry
+/// the same client ID, see check_unique_clients, or if the sink fails
+pub fn write_filtered_output(clients: &ClientStore, filter: &OutputFilter, sink: &mut dyn AccountSink) -> io::Result<()>
 {
-    let mut wrtr = csv::Writer::from_writer(io::stdout());
-    for c in clients
+    check_unique_clients(clients)?;
+    let accounts = clients.values().map(|c| &c.acc).filter(|acc| filter.keeps(acc));
+    let accounts: Box<dyn Iterator<Item = &Account>> = match filter.top
     {
-        if wrtr.serialize(c.1.acc).is_err()
+        Some((key, n)) => {
+            let mut ranked: Vec<&Account> = accounts.collect();
+            ranked.sort_by(|a, b| key.of(b).cmp(&key.of(a)).then(a.client.cmp(&b.client)));
+            ranked.truncate(n);
+            Box::new(ranked.into_iter())
+        },
+        None => Box::new(accounts)
+    };
+    for acc in accounts
+    {
+        if sink.write_account(acc).is_err()
         {
             continue;
         }
     }
+    sink.finish()
+}
+
+/// A single row of the ledger export
+#[derive(Serialize)]
+struct LedgerEntry<'a>
+{
+    client: u16,
+    tx: u32,
+    amount: Amount,
+    in_dispute: bool,
+    /// The disputes, resolves, chargebacks and representments applied to it, E.G. "dispute>chargeback"
+    dispute_chain: String,
+    memo: Option<&'a str>,
+    /// Chains the entry to the one before, see chain_hash
+    hash: &'a str,
+}
+
+/// Every history entry of every client, ordered by client and then transaction ID
+///
+/// # Arguments
+///
+/// * 'clients' - The clients that have been processed
+pub(crate) fn ledger_entries(clients: &ClientStore) -> impl Iterator<Item = (u16, u32, &ClientTransaction)>
+{
+    let mut ids: Vec<u16> = clients.keys().collect();
+    ids.sort();
+    ids.into_iter().flat_map(move |id| {
+        let history = &clients[&id].history;
+        let mut txs: Vec<&u32> = history.keys().collect();
+        txs.sort();
+        txs.into_iter().map(move |tx| (id, *tx, &history[tx]))
+    })
+}
+
+/// Writes the transaction history of every client as csv, ordered by client
+/// and then transaction ID
+/// 
+/// Each row ends with a hash of its fields and the hash of the row before, so
+/// verify_ledger can tell if the file was changed later
+/// 
+/// # Arguments
+/// 
+/// * 'clients' - The clients that have been processed
+/// * 'out' - Where to write the ledger to
+pub fn write_ledger<W: io::Write>(clients: &ClientStore, out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    let mut prev = GENESIS_HASH.to_string();
+    for (client, tx, entry) in ledger_entries(clients)
+    {
+        let fields = chain::entry_fields(client, tx, entry);
+        prev = chain_hash(&prev, &fields.iter().map(|f| f.as_str()).collect::<Vec<_>>());
+        wrtr.serialize(LedgerEntry {
+            client,
+            tx,
+            amount: entry.amount,
+            in_dispute: entry.in_dispute,
+            dispute_chain: entry.dispute_chain_text(),
+            memo: entry.memo.as_deref(),
+            hash: &prev,
+        })?;
+    }
+    wrtr.flush()?;
+    Ok(())
 }
 
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use super::*;
+
+    fn amt(text: &str) -> Amount
+    {
+        text.parse().unwrap()
+    }
     
     #[test]
     fn deposit()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.1)};
-        client.process_transaction(&tx_deposit);
-        assert_eq!(client.acc.total,0.1);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.1);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.1")));
+        client.process_transaction(&tx_deposit).unwrap();
+        assert_eq!(client.acc.total,amt("0.1"));
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.1"));
     }
     #[test]
     fn deposit_lessthan_zero()
     {
         let mut client = Client::new(1);
-        let tx_deposit_negative = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(-0.1)};
-        client.process_transaction(&tx_deposit_negative);
-        assert_eq!(client.acc.total,0.0);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.0);
+        let tx_deposit_negative = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("-0.1")));
+        client.process_transaction(&tx_deposit_negative).unwrap();
+        assert_eq!(client.acc.total,amt("0.0"));
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.0"));
     }
     #[test]
     fn deposit_history()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.1)};
-        let tx_deposit_dupl_id = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(1.0)};
-        let tx_deposit_negative = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(-0.1)};
-        client.process_transaction(&tx_deposit);
-        client.process_transaction(&tx_deposit_negative);
-        client.process_transaction(&tx_deposit_dupl_id);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.1")));
+        let tx_deposit_dupl_id = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("1.0")));
+        let tx_deposit_negative = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("-0.1")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.process_transaction(&tx_deposit_negative).unwrap();
+        client.process_transaction(&tx_deposit_dupl_id).unwrap();
         assert_eq!(client.history.len(),1);
         assert_eq!(client.history.contains_key(&tx_deposit.tx),true);
         assert_ne!(client.history.contains_key(&tx_deposit_negative.tx),false);
@@ -268,198 +1176,257 @@ mod tests {
     fn withdrawal()
     {
         let mut client = Client::new(1);
-        client.acc.total = 1.0;
-        client.acc.available = 1.0;
-        let tx_withdrawal = Tx{r#type:TypeTx::Withdrawal,client:client.acc.client,tx:1,amount:Some(0.5)};
-        client.process_transaction(&tx_withdrawal);
-        assert_eq!(client.acc.total,0.5);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.5);
+        client.acc.total = amt("1.0");
+        client.acc.available = amt("1.0");
+        let tx_withdrawal = Tx::new(TypeTx::Withdrawal,client.acc.client,1,Some(amt("0.5")));
+        client.process_transaction(&tx_withdrawal).unwrap();
+        assert_eq!(client.acc.total,amt("0.5"));
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.5"));
     }
     #[test]
     fn withdrawal_precision()
     {
         let mut client = Client::new(1);
-        client.acc.total = 1.0;
-        client.acc.available = 1.0;
-        let tx_withdrawal = Tx{r#type:TypeTx::Withdrawal,client:client.acc.client,tx:1,amount:Some(0.0001)};
-        client.process_transaction(&tx_withdrawal);
-        assert_eq!(client.acc.total,0.9999);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.9999);
+        client.acc.total = amt("1.0");
+        client.acc.available = amt("1.0");
+        let tx_withdrawal = Tx::new(TypeTx::Withdrawal,client.acc.client,1,Some(amt("0.0001")));
+        client.process_transaction(&tx_withdrawal).unwrap();
+        assert_eq!(client.acc.total,amt("0.9999"));
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.9999"));
     }
     #[test]
     fn withdrawal_lessthan_zero()
     {
         let mut client = Client::new(1);
-        client.acc.total = 1.0;
-        client.acc.available = 1.0;
-        let tx_withdrawal = Tx{r#type:TypeTx::Withdrawal,client:client.acc.client,tx:1,amount:Some(-0.5)};
-        client.process_transaction(&tx_withdrawal);
-        assert_eq!(client.acc.total,1.0);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,1.0);
+        client.acc.total = amt("1.0");
+        client.acc.available = amt("1.0");
+        let tx_withdrawal = Tx::new(TypeTx::Withdrawal,client.acc.client,1,Some(amt("-0.5")));
+        client.process_transaction(&tx_withdrawal).unwrap();
+        assert_eq!(client.acc.total,amt("1.0"));
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("1.0"));
     }
     #[test]
     fn withdrawal_whentotal_zero()
     {
         let mut client = Client::new(1);
-        let tx_withdrawal = Tx{r#type:TypeTx::Withdrawal,client:client.acc.client,tx:1,amount:Some(0.5)};
-        client.process_transaction(&tx_withdrawal);
-        assert_eq!(client.acc.total,0.0);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.0);
+        let tx_withdrawal = Tx::new(TypeTx::Withdrawal,client.acc.client,1,Some(amt("0.5")));
+        client.process_transaction(&tx_withdrawal).unwrap();
+        assert_eq!(client.acc.total,amt("0.0"));
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.0"));
     }
     #[test]
     fn dispute_transactions()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        client.process_transaction(&tx_deposit);
-        client.dispute_transaction(&tx_deposit.tx);
-        let tx_withdrawal = Tx{r#type:TypeTx::Withdrawal,client:client.acc.client,tx:2,amount:Some(0.1)};
-        client.process_transaction(&tx_deposit);
-        client.dispute_transaction(&tx_withdrawal.tx);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        let tx_withdrawal = Tx::new(TypeTx::Withdrawal,client.acc.client,2,Some(amt("0.1")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.dispute_transaction(&tx_withdrawal.tx).unwrap();
         assert_eq!(client.get_transaction(&tx_deposit.tx).unwrap().in_dispute,true);
         assert_eq!(client.get_transaction(&tx_withdrawal.tx).is_none(),true);
-        assert_eq!(client.acc.held,0.5);
-        assert_eq!(client.acc.available,0.0);
-        assert_eq!(client.acc.total,0.5);
+        assert_eq!(client.acc.held,amt("0.5"));
+        assert_eq!(client.acc.available,amt("0.0"));
+        assert_eq!(client.acc.total,amt("0.5"));
     }
     #[test]
     fn dispute_multiple_transactions()
     {
         let mut client = Client::new(1);
-        let tx_deposit_a = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        let tx_deposit_b = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:2,amount:Some(0.5)};
-        let tx_deposit_c = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:3,amount:Some(0.5)};
-        client.process_transaction(&tx_deposit_a);
-        client.process_transaction(&tx_deposit_b);
-        client.process_transaction(&tx_deposit_c);
+        let tx_deposit_a = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        let tx_deposit_b = Tx::new(TypeTx::Deposit,client.acc.client,2,Some(amt("0.5")));
+        let tx_deposit_c = Tx::new(TypeTx::Deposit,client.acc.client,3,Some(amt("0.5")));
+        client.process_transaction(&tx_deposit_a).unwrap();
+        client.process_transaction(&tx_deposit_b).unwrap();
+        client.process_transaction(&tx_deposit_c).unwrap();
         
-        client.dispute_transaction(&tx_deposit_b.tx);
-        client.dispute_transaction(&tx_deposit_c.tx);
+        client.dispute_transaction(&tx_deposit_b.tx).unwrap();
+        client.dispute_transaction(&tx_deposit_c.tx).unwrap();
 
         assert_eq!(client.get_transaction(&tx_deposit_a.tx).unwrap().in_dispute,false);
         assert_eq!(client.get_transaction(&tx_deposit_b.tx).unwrap().in_dispute,true);
         assert_eq!(client.get_transaction(&tx_deposit_c.tx).unwrap().in_dispute,true);
-        assert_eq!(client.acc.held,1.0);
-        assert_eq!(client.acc.available,0.5);
-        assert_eq!(client.acc.total,1.5);
+        assert_eq!(client.acc.held,amt("1.0"));
+        assert_eq!(client.acc.available,amt("0.5"));
+        assert_eq!(client.acc.total,amt("1.5"));
     }
     #[test]
     fn resolve_transactions()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        client.process_transaction(&tx_deposit);
-        client.dispute_transaction(&tx_deposit.tx);
-        client.resolve_transaction(&tx_deposit.tx);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.resolve_transaction(&tx_deposit.tx).unwrap();
         assert_eq!(client.get_transaction(&tx_deposit.tx).unwrap().in_dispute,false);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.5);
-        assert_eq!(client.acc.total,0.5);
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.5"));
+        assert_eq!(client.acc.total,amt("0.5"));
     }
     #[test]
     fn chargeback_transactions()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        client.process_transaction(&tx_deposit);
-        client.dispute_transaction(&tx_deposit.tx);
-        client.chargeback_transaction(&tx_deposit.tx);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit.tx).unwrap();
         assert_eq!(client.get_transaction(&tx_deposit.tx).unwrap().in_dispute,true);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.0);
-        assert_eq!(client.acc.total,0.0);
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.0"));
+        assert_eq!(client.acc.total,amt("0.0"));
     }
     #[test]
     fn chargeback_transaction_twice()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        client.process_transaction(&tx_deposit);
-        client.dispute_transaction(&tx_deposit.tx);
-        client.chargeback_transaction(&tx_deposit.tx);
-        client.dispute_transaction(&tx_deposit.tx);
-        client.chargeback_transaction(&tx_deposit.tx);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.0);
-        assert_eq!(client.acc.total,0.0);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit.tx).unwrap();
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit.tx).unwrap();
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.0"));
+        assert_eq!(client.acc.total,amt("0.0"));
     }
     #[test]
     fn chargeback_with_disputes()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        let tx_deposit_1 = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:2,amount:Some(1.0)};
-        let tx_deposit_2 = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:3,amount:Some(1.0)};
-        let tx_deposit_3 = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:4,amount:Some(1.0)};
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        let tx_deposit_1 = Tx::new(TypeTx::Deposit,client.acc.client,2,Some(amt("1.0")));
+        let tx_deposit_2 = Tx::new(TypeTx::Deposit,client.acc.client,3,Some(amt("1.0")));
+        let tx_deposit_3 = Tx::new(TypeTx::Deposit,client.acc.client,4,Some(amt("1.0")));
 
-        client.process_transaction(&tx_deposit);
-        client.process_transaction(&tx_deposit_1);
-        client.process_transaction(&tx_deposit_2);
-        client.process_transaction(&tx_deposit_3);
-        client.dispute_transaction(&tx_deposit.tx);
-        client.chargeback_transaction(&tx_deposit.tx);
-        client.dispute_transaction(&tx_deposit_1.tx);
-        client.dispute_transaction(&tx_deposit_2.tx);
-        client.dispute_transaction(&tx_deposit_3.tx);
+        client.process_transaction(&tx_deposit).unwrap();
+        client.process_transaction(&tx_deposit_1).unwrap();
+        client.process_transaction(&tx_deposit_2).unwrap();
+        client.process_transaction(&tx_deposit_3).unwrap();
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit.tx).unwrap();
+        client.dispute_transaction(&tx_deposit_1.tx).unwrap();
+        client.dispute_transaction(&tx_deposit_2.tx).unwrap();
+        client.dispute_transaction(&tx_deposit_3.tx).unwrap();
 
         assert_eq!(client.get_transaction(&tx_deposit_1.tx).unwrap().in_dispute,true);
         assert_eq!(client.get_transaction(&tx_deposit_2.tx).unwrap().in_dispute,true);
         assert_eq!(client.get_transaction(&tx_deposit_3.tx).unwrap().in_dispute,true);
-        assert_eq!(client.acc.held,3.0);
-        assert_eq!(client.acc.available,0.0);
-        assert_eq!(client.acc.total,3.0);
+        assert_eq!(client.acc.held,amt("3.0"));
+        assert_eq!(client.acc.available,amt("0.0"));
+        assert_eq!(client.acc.total,amt("3.0"));
     }
     #[test]
     fn missing_transactions()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        client.dispute_transaction(&tx_deposit.tx);
-        client.resolve_transaction(&tx_deposit.tx);
-        client.chargeback_transaction(&tx_deposit.tx);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.resolve_transaction(&tx_deposit.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit.tx).unwrap();
         assert_eq!(client.history.contains_key(&tx_deposit.tx),false);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.0);
-        assert_eq!(client.acc.total,0.0);
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.0"));
+        assert_eq!(client.acc.total,amt("0.0"));
     }
     #[test]
     fn locked_account()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        let tx_deposit_locked = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:2,amount:Some(0.5)};
-        let tx_withdrawal_locked = Tx{r#type:TypeTx::Withdrawal,client:client.acc.client,tx:2,amount:Some(0.5)};
-        client.process_transaction(&tx_deposit);
-        client.dispute_transaction(&tx_deposit.tx);
-        client.chargeback_transaction(&tx_deposit.tx);
-        client.process_transaction(&tx_deposit_locked);
-        client.process_transaction(&tx_withdrawal_locked);
-        assert_eq!(client.acc.held,0.0);
-        assert_eq!(client.acc.available,0.0);
-        assert_eq!(client.acc.total,0.0);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        let tx_deposit_locked = Tx::new(TypeTx::Deposit,client.acc.client,2,Some(amt("0.5")));
+        let tx_withdrawal_locked = Tx::new(TypeTx::Withdrawal,client.acc.client,2,Some(amt("0.5")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit.tx).unwrap();
+        client.process_transaction(&tx_deposit_locked).unwrap();
+        client.process_transaction(&tx_withdrawal_locked).unwrap();
+        assert_eq!(client.acc.held,amt("0.0"));
+        assert_eq!(client.acc.available,amt("0.0"));
+        assert_eq!(client.acc.total,amt("0.0"));
     }
     
     #[test]
     fn locked_account_chargeback()
     {
         let mut client = Client::new(1);
-        let tx_deposit = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:1,amount:Some(0.5)};
-        let tx_deposit_chargeback = Tx{r#type:TypeTx::Deposit,client:client.acc.client,tx:2,amount:Some(0.5)};
-        client.process_transaction(&tx_deposit);
-        client.process_transaction(&tx_deposit_chargeback);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("0.5")));
+        let tx_deposit_chargeback = Tx::new(TypeTx::Deposit,client.acc.client,2,Some(amt("0.5")));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.process_transaction(&tx_deposit_chargeback).unwrap();
 
-        client.dispute_transaction(&tx_deposit.tx);
-        client.chargeback_transaction(&tx_deposit.tx);
+        client.dispute_transaction(&tx_deposit.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit.tx).unwrap();
         
-        client.dispute_transaction(&tx_deposit_chargeback.tx);
-        client.chargeback_transaction(&tx_deposit_chargeback.tx);
+        client.dispute_transaction(&tx_deposit_chargeback.tx).unwrap();
+        client.chargeback_transaction(&tx_deposit_chargeback.tx).unwrap();
         
-        assert_eq!(client.acc.held,0.5);
-        assert_eq!(client.acc.available,0.0);
-        assert_eq!(client.acc.total,0.5);
+        assert_eq!(client.acc.held,amt("0.5"));
+        assert_eq!(client.acc.available,amt("0.0"));
+        assert_eq!(client.acc.total,amt("0.5"));
+    }
+    #[test]
+    fn overflow_leaves_account_untouched()
+    {
+        let mut client = Client::new(1);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(Amount::MAX));
+        let tx_deposit_over = Tx::new(TypeTx::Deposit,client.acc.client,2,Some(amt("0.0001")));
+        client.process_transaction(&tx_deposit).unwrap();
+        assert_eq!(client.process_transaction(&tx_deposit_over),Err(TxError::Overflow));
+        assert!(!client.history.contains_key(&tx_deposit_over.tx));
+        assert_eq!(client.acc.total,Amount::MAX);
+        assert_eq!(client.acc.available,Amount::MAX);
+
+        client.acc.held = Amount::MAX;
+        assert_eq!(client.dispute_transaction(&tx_deposit.tx),Err(TxError::Overflow));
+        assert!(!client.get_transaction(&tx_deposit.tx).unwrap().in_dispute);
+        assert_eq!(client.acc.available,Amount::MAX);
+    }
+    #[test]
+    fn deposit_missing_amount()
+    {
+        let mut client = Client::new(1);
+        let tx_deposit_missing = Tx::new(TypeTx::Deposit,client.acc.client,1,None);
+        let tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("1.0")));
+        assert_eq!(client.process_transaction(&tx_deposit_missing),Err(TxError::MissingAmount));
+        assert!(client.history.is_empty());
+        client.process_transaction(&tx_deposit).unwrap();
+        assert_eq!(client.acc.total,amt("1.0"));
+    }
+    #[test]
+    fn memo_history()
+    {
+        let mut client = Client::new(1);
+        let mut tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,1,Some(amt("1.0")));
+        tx_deposit.memo = Some("invoice 12".to_string());
+        let mut tx_deposit_long = Tx::new(TypeTx::Deposit,client.acc.client,2,Some(amt("1.0")));
+        tx_deposit_long.memo = Some("x".repeat(MAX_MEMO_LEN + 10));
+        client.process_transaction(&tx_deposit).unwrap();
+        client.process_transaction(&tx_deposit_long).unwrap();
+        assert_eq!(client.get_transaction(&1).unwrap().memo.as_deref(),Some("invoice 12"));
+        assert_eq!(client.get_transaction(&2).unwrap().memo.as_ref().unwrap().len(),MAX_MEMO_LEN);
+    }
+    #[test]
+    fn ledger_export()
+    {
+        let mut clients = ClientStore::default();
+        let mut client = Client::new(2);
+        let mut tx_deposit = Tx::new(TypeTx::Deposit,client.acc.client,5,Some(amt("1.5")));
+        tx_deposit.memo = Some("ref, 7".to_string());
+        client.process_transaction(&Tx::new(TypeTx::Deposit,client.acc.client,9,Some(amt("2")))).unwrap();
+        client.process_transaction(&tx_deposit).unwrap();
+        client.dispute_transaction(&5).unwrap();
+        clients.insert(2, client);
+        let mut out = Vec::new();
+        write_ledger(&clients, &mut out).unwrap();
+        let ledger = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = ledger.lines().map(|l| l.rsplit_once(',').unwrap().0).collect();
+        assert_eq!(rows, vec!["client,tx,amount,in_dispute,dispute_chain,memo", "2,5,1.5,true,dispute,\"ref, 7\"", "2,9,2.0,false,,"]);
+        assert!(ledger.ends_with(&format!(",{}\n", ledger_head(&clients))));
     }
 }
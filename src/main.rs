@@ -1,72 +1,1760 @@
-use std::{collections::HashMap, fs::File};
-use csv_transactions::{Client, Tx, TypeTx, write_output};
-fn main() 
+use std::{cell::Cell, collections::BTreeSet, convert::TryFrom, fs::File, io::{self, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use csv_transactions::{anomalies, read_expected, read_opening_balances, reconcile, write_discrepancies, Tolerance, read_metadata, read_schedules, verify_ledger, parse_types, parse_amount_parser, parse_utc_offset, order_by_timestamp, TieBreak, ClientFilter, StopAt, TypeTx, ListScreening, RuleSet, SnapshotError, SnapshotKey, DEFAULT_TENANT, ClientStore, GlobalDedup, Dialect, Engine, EnginePolicy, FreezePolicy, InterestPolicy, OutputFormat, Repl, Report, SchemaMode, TxRecord, write_ledger, write_trial_balance, write_journal, write_sub_accounts, write_consolidation, RateTable, write_ledger_with_profile, write_summary, ReportFormat, ConvertMapping, AccountSink, CsvSink, ExportProfile, write_gl_journal, GlError, GlMapping, write_filtered_output, write_changes, ChangeFeed, Durability, Outbox, Wal, OUTBOX_BATCH, BackgroundCheckpoints, Checkpoint, content_hash, generate, parse_count, AccountStream, GeneratorConfig, OutputFilter, SortKey, write_rejections, write_dispute_lifecycles, write_held_ageing, write_history, write_review_queue};
+
+/// Options given on the command line
+struct Args
+{
+    path: String,
+    policy: EnginePolicy,
+    dialect: Dialect,
+    rejections: Option<String>,
+    /// Path the dispute lifecycle report is written to
+    disputes: Option<String>,
+    /// Path the held-funds ageing report is written to
+    held_ageing: Option<String>,
+    /// The date open disputes are aged to, in seconds since the unix epoch, now if not set
+    as_of: Option<i64>,
+    /// Path the compliance review queue is written to
+    review_queue: Option<String>,
+    ledger: Option<String>,
+    /// Path the trial balance of the system accounts is written to
+    trial_balance: Option<String>,
+    /// Path the journal of every posting is written to
+    journal: Option<String>,
+    /// Path the general ledger account codes of each posting are read from
+    gl_mapping: Option<String>,
+    /// Path the journal is written to for the general ledger, with account codes
+    gl_journal: Option<String>,
+    /// Directory the figures of the period are archived to when it is closed at the end of the run
+    close_period: Option<String>,
+    /// Path the balances clients start with are read from
+    opening_balances: Option<String>,
+    /// Path the balances of every sub-account of every client are written to
+    sub_accounts: Option<String>,
+    /// Path the balances converted to the base currency are written to
+    consolidate: Option<String>,
+    /// The currency the balances are converted to
+    base_currency: Option<String>,
+    /// Path the exchange rates are read from
+    rates: Option<String>,
+    format: OutputFormat,
+    /// The delimiter, decimal point and dates of the csv account report and ledger export
+    export_profile: Option<ExportProfile>,
+    /// Connection url of the database to export to
+    postgres: Option<String>,
+    postgres_accounts_table: Option<String>,
+    postgres_ledger_table: Option<String>,
+    /// Url of the redis server holding the shared clients
+    redis: Option<String>,
+    redis_prefix: String,
+    /// Whether to show the live dashboard while processing
+    dashboard: bool,
+    /// Whether the whole input is applied as a single batch, all or nothing
+    atomic: bool,
+    /// Urls chargebacks and locked accounts are POSTed to
+    webhooks: Vec<String>,
+    /// Slack incoming webhooks the end-of-run summary is posted to
+    slack_webhooks: Vec<String>,
+    /// Addresses the end-of-run summary is mailed to
+    emails: Vec<String>,
+    /// The SMTP relay the summary is mailed through, as host:port
+    smtp_server: String,
+    email_from: String,
+    /// How the connection to the relay is secured, none, opportunistic, starttls or tls
+    smtp_tls: String,
+    /// Path of the file with the user name and password the relay is logged in to with
+    smtp_credentials: Option<String>,
+    /// Whether to print the management report rather than the accounts
+    report: bool,
+    /// The markup of the short summary printed in place of the report
+    report_format: Option<ReportFormat>,
+    /// How many clients and disputes the report lists
+    top: usize,
+    /// The book rows without a tenant column belong to, and the only one processed
+    tenant: Option<String>,
+    /// Path of the client metadata registry
+    client_metadata: Option<String>,
+    /// Path of the sanctions list clients are screened against
+    screening_list: Option<String>,
+    /// Path of the recurring transactions
+    schedules: Option<String>,
+    /// Path of the public keys transactions must be signed with
+    signing_keys: Option<String>,
+    /// Path of the rhai script making policy decisions
+    policy_script: Option<String>,
+    /// Path of the declarative rules every transaction is checked against
+    rules: Option<String>,
+    /// Path of the snapshot the engine starts from
+    restore: Option<String>,
+    /// Path the engine is snapshotted to once the input is processed
+    snapshot: Option<String>,
+    /// Path of the key snapshots are encrypted with
+    snapshot_key: Option<String>,
+    /// The only clients processed
+    only_clients: Option<ClientFilter>,
+    /// The transaction types left alone
+    exclude_types: Vec<TypeTx>,
+    /// More inputs merged with the first by timestamp
+    merge_inputs: Vec<String>,
+    tie_break: TieBreak,
+    /// Where to stop reading the input
+    stop_at: StopAt,
+    /// Which accounts are written
+    output_filter: OutputFilter,
+    /// Writes only the accounts changed since the restored snapshot
+    changes_only: bool,
+    /// The directory the run is checkpointed to, and resumed from
+    checkpoint: Option<String>,
+    /// How many rows are applied between checkpoints
+    checkpoint_every: u64,
+    /// Processes the input even if it was already ingested
+    force: bool,
+    /// How long a row may take before it is logged as slow
+    latency_budget: Option<Duration>,
+    /// Fails the run if the report has warnings
+    fail_on_warn: bool,
+    /// How many bytes of input without a row for a client before its account is emitted
+    stream_after: Option<u64>,
+    /// Keeps the clients in a table with a slot for every client ID
+    dense_clients: bool,
+    /// Directory of the write-ahead log every row is appended to before it is applied
+    wal: Option<String>,
+    /// How often the write-ahead log is synced to disk
+    wal_sync: Durability,
+    /// How big a segment of the write-ahead log grows before a new one is started
+    wal_segment_bytes: u64,
+    /// File every balance change is appended to, for downstream systems to poll
+    outbox: Option<String>,
+}
+
+/// Takes the value following a flag, panicking if there is none
+fn flag_value(flag: &str, args: &mut impl Iterator<Item = String>) -> String
+{
+    match args.next()
+    {
+        Some(v) => v,
+        None => panic!("ERR: {} needs a value", flag)
+    }
+}
+
+/// Parses the value following a flag, panicking if it isn't valid
+fn parse_flag<T: std::str::FromStr>(flag: &str, args: &mut impl Iterator<Item = String>) -> T
 {
-    let input_argument = std::env::args().nth(1);
+    let value = flag_value(flag, args);
+    match value.parse()
+    {
+        Ok(v) => v,
+        Err(_) => panic!("ERR: Invalid value '{}' for {}", value, flag)
+    }
+}
 
-    if input_argument.is_none()
+///
+/// Reads the input path and any options from the command line
+///
+/// Usage: csv_transactions [options] <path>, or csv_transactions repl for an interactive session,
+/// or csv_transactions report [options] <path> for management metrics rather than the accounts,
+/// or csv_transactions verify-ledger <path> [--head <hash>] to check a ledger export wasn't changed,
+/// or csv_transactions generate [--clients <n>] [--rows <n>] [--dispute-rate <rate>] [--duplicate-rate <rate>]
+/// [--malformed-rate <rate>] [--seed <n>] [--output <path>] for random input to benchmark and fuzz with,
+/// or csv_transactions query --snapshot <path> [--client <id>]... [--history] [--output-format <format>]
+/// [--snapshot-key <path>] to look at the accounts, or the history, of a snapshot without changing it,
+/// or csv_transactions reconcile --expected <path> (--snapshot <path> [--snapshot-key <path>] | <path>)
+/// [--tolerance <amount>] [--tolerance-rate <rate>] [--output <path>] to compare the final balances to
+/// an externally produced balance file, exiting with 1 if they don't match,
+/// or csv_transactions profile [--sample <n>] [--delimiter <char>] [--output-format json] <path> for
+/// quality metrics of the first rows of the input, without applying them,
+/// or csv_transactions convert (--mapping <path> | --infer) [--delimiter <char>] [--output <path>]
+/// [--report <path>] <path> to convert a bank export to our columns, or print the mapping inferred
+/// from its headers
+///
+/// * --rounding half-even|truncate|reject
+/// * --min-amount <amount>
+/// * --max-amount <amount>
+/// * --unexpected-amount reject|ignore - for disputes, resolves, chargebacks and representments with an amount
+/// * --locked-deposit reject|accept-to-held|accept - what happens to deposits for a locked account
+/// * --locked-withdrawal reject|accept - what happens to withdrawals for a locked account
+/// * --reserve <amount> - keeps a chargeback reserve with this opening balance, shown by the report
+/// * --freeze-chargebacks <n> - freezes accounts with more than n of their latest deposits charged back
+/// * --freeze-rate <rate> - freezes accounts with more than this share of their latest deposits charged back
+/// * --freeze-window <n> - how many of the latest deposits are looked at, 100 by default
+/// * --interest-rate <rate> - the share of available balances credited as interest each period, E.G. 0.001
+/// * --interest-period <seconds> - the length of an interest period; interest is credited whenever
+///   the timestamp column crosses into a new one
+/// * --settlement-delay <seconds> - deposits only become available this long after their timestamp,
+///   until then they are pending
+/// * --dispute-window-days <days> - rejects disputes more than this many days after the timestamp
+///   of the transaction, for clients whose tier doesn't set dispute_window_days
+/// * --dispute-window-rows <rows> - rejects disputes more than this many rows after the
+///   transaction, for clients whose tier doesn't set dispute_window_rows
+/// * --global-dedup - rejects deposits and withdrawals reusing the transaction ID of any client,
+///   not only one in the history of their own
+/// * --dedup-bloom <rate> - puts a bloom filter with this false positive rate, E.G. 0.01, in front
+///   of the IDs --global-dedup keeps, so fresh IDs rarely need them looked up; implies --global-dedup
+/// * --dedup-expected <count> - how many IDs the bloom filter is sized for, E.G. 500M, 10M by default
+/// * --schedules <path> - reads recurring deposits and withdrawals (type, client, amount, start,
+///   interval, count, memo, counterparty), made as the timestamp column reaches them
+/// * --rejections <path> - writes the rejection report as csv
+/// * --disputes <path> - writes every dispute opened, with how and after how long it was closed, as csv
+/// * --held-ageing <path> - writes every open dispute with how long its funds have been held,
+///   bucketed into 0-7d, 7-30d and 30d+, as csv
+/// * --as-of <seconds> - the unix timestamp --held-ageing ages disputes to, and --consolidate takes
+///   rates at, now by default
+/// * --review-queue <path> - writes the accounts held for compliance review as csv
+/// * --ledger <path> - writes the transaction history of every client, as parquet if the path ends in .parquet
+/// * --trial-balance <path> - mirrors every change to the balances as postings to system accounts, and
+///   writes their debits and credits as csv, warning if they don't come to the same
+/// * --journal <path> - keeps every posting to the system accounts, with the client and transaction behind
+///   it, and writes them as csv, warning for any account whose balances aren't those its postings come to
+/// * --gl-mapping <path> - a csv of cause, account and code columns, giving the general ledger account code
+///   postings of each cause, or * for any, to each system account go to
+/// * --gl-journal <path> - writes the journal as csv for import into the general ledger, with the account code
+///   of every posting, needs --gl-mapping
+/// * --close-period <dir> - closes the period at the end of the run, archiving the opening and closing
+///   balances of every client and the totals by transaction type to the directory, and starts the next
+///   period, which --snapshot keeps so the next run carries on from it
+/// * --opening-balances <path> - a csv of client, available, and optionally held and locked columns, opening
+///   an account with those balances for each client before the input is processed, ignored when resuming
+/// * --sub-accounts <path> - writes the balances of every sub-account of every client as csv, with an
+///   account column, the main sub-account first
+/// * --consolidate <path> - writes the balances of the clients in each currency, converted to the
+///   currency given with --base-currency at the rates in the csv given with --rates, and their total
+/// * --base-currency <code> - the currency --consolidate converts to, E.G. EUR, which clients without
+///   a base currency in the metadata are taken to hold theirs in
+/// * --rates <path> - a csv of from, to, rate and timestamp columns, each rate in effect from its
+///   timestamp until the next one for the same currencies
+/// * --output-format csv|json|jsonl|parquet|avro|xlsx - the format of the account report, parquet, avro and
+///   xlsx need the feature of the same name
+/// * --export-profile default|eu|us|iso - lays the csv account report and ledger export out for the
+///   spreadsheets of a region, with the delimiter, decimal point and date format of the profile; the
+///   ledger gains a date column, and can't be verified unless written with the default profile
+/// * --delimiter <char> - the field separator of the input, "tab" for tabs
+/// * --trim - strips whitespace around input fields
+/// * --decimal-comma - amounts in the input use a comma as the decimal point
+/// * --amount-format decimal|minor[:<decimals>]|symbol - how the input writes amounts, as plain
+///   decimals, whole minor units (cents unless the decimals are given) or with a currency symbol in front
+/// * --merge-input <path> - another input merged with the first by timestamp, can be repeated; sources
+///   given earlier go first among rows with the same timestamp, unless --tie-break says otherwise
+/// * --tie-break source|seed:<n> - how rows of different inputs with the same timestamp are ordered, by
+///   the order the inputs were given, or in an order drawn from the seed; replays with the same inputs
+///   and rule are applied in the same order
+/// * --timestamp-format unix|iso|dmy|mdy - a format the input timestamps may be written in, can be
+///   repeated to try several in order; unix timestamps only if not given
+/// * --timezone UTC|<offset> - the offset from UTC, E.G. +02:00, of input timestamps that don't carry one
+/// * --rename-column <from>=<to> - reads the input column 'from' as 'to', can be repeated
+/// * --schema strict|lenient - whether unknown input columns stop the run or are skipped, and
+///   whether rows with a timestamp that can't be read are rejected or applied without it
+/// * --export-postgres <url> - upserts the accounts and ledger into postgres, needs the postgres feature
+/// * --postgres-accounts-table <name> - the table accounts are upserted into, "accounts" by default
+/// * --postgres-ledger-table <name> - the table the ledger is upserted into, "ledger" by default
+/// * --redis <url> - keeps the clients in redis, shared with other engines, needs the redis feature
+/// * --redis-prefix <prefix> - prepended to every redis key, "transactions" by default
+/// * --atomic - applies the whole input or none of it; if any row is rejected the run stops
+///   without writing any output. Can't be used with --redis or --dashboard
+/// * --dashboard - shows throughput, held funds, locks and rejections on stderr while processing,
+///   needs the tui feature
+/// * --webhook <url> - POSTs every chargeback, locked account and the end-of-run summary to the url as json, can be repeated,
+///   needs the webhook feature
+/// * --notify-slack <url> - posts the end-of-run summary, rows processed, rejected and locked
+///   accounts, to the Slack incoming webhook, can be repeated, needs the webhook feature
+/// * --notify-email <address> - mails the end-of-run summary to the address, can be repeated,
+///   needs the email feature
+/// * --smtp-server <host:port> - the relay --notify-email mails through, localhost:25 by default
+/// * --smtp-tls none|opportunistic|starttls|tls - how the connection to the relay is secured,
+///   opportunistic, upgrading with STARTTLS when the relay offers it, by default
+/// * --smtp-credentials <path> - logs in to the relay with the user name on the first line of
+///   the file and the password on the second
+/// * --email-from <address> - the address --notify-email mails from, csv_transactions@localhost
+///   by default
+/// * --top <n> - how many clients and disputes the report lists, 10 by default
+/// * --report-format html|md - prints a short summary of the report, its totals, locked accounts,
+///   largest disputes and warnings, as html or markdown for a run email or ticket; implies report
+/// * --only-locked - writes only the locked accounts
+/// * --non-zero - writes only the accounts with a balance other than zero
+/// * --top-n-by available|held|total - writes only the --top accounts with the largest of this balance
+/// * --client-metadata <path> - reads the client metadata registry (client, name, tier, credit_limit,
+///   base_currency) before processing
+/// * --tier <name>:<limits> - the limits of a tier given in the registry, E.G.
+///   basic:max_deposit=1000,max_withdrawal=500,max_open_disputes=1,dispute_window_days=120, can be repeated
+/// * --screening-list <path> - freezes clients found on this list (client, name, counterparty,
+///   reason), screened on their first deposit
+/// * --screen-above <amount> - also screens deposits and withdrawals of at least this amount
+/// * --signing-keys <path> - only accepts transactions whose signature column verifies against
+///   the client's ed25519 public key (client, public_key as hex), needs the ed25519 feature
+/// * --policy-script <path> - asks the rhai script at this path whether to accept each transaction,
+///   freeze the account and charge a fee, through its accept, freeze and fee functions; needs the
+///   scripting feature
+/// * --rules <path> - checks every transaction against the declarative rules in this json file, which
+///   accept, reject or flag transactions by their type, amount, client and account state
+/// * --restore <path> - starts from the snapshot at this path rather than an empty engine
+/// * --snapshot <path> - writes a snapshot of the engine here once the input is processed
+/// * --checkpoint <dir> - persists the state and how far into the input the run got to this directory,
+///   and on a restart with the same input resumes from there instead of starting over; local csv input only
+/// * --checkpoint-every <rows> - how many rows are applied between checkpoints, 10000 by default;
+///   checkpoints are written on a thread of their own, and one not yet written when the next is
+///   due is dropped for it
+/// * --wal <dir> - appends every row to the write-ahead log in this directory before applying it,
+///   and first replays the rows logged after the snapshot given with --restore, or after the
+///   snapshot the log was compacted into if none is; with --snapshot-key the rows are encrypted
+/// * --wal-segment-bytes <bytes> - starts a new segment of the write-ahead log once one grows past
+///   this size, E.G. 16M, 64M by default; the closed segments are then compacted into a snapshot
+/// * --wal-sync record|records:<n>|ms:<n>|os - syncs the write-ahead log to disk after every row,
+///   every n rows, by the first row n milliseconds after the last sync, or leaves it to the OS;
+///   every row by default
+/// * --outbox <path> - appends every change to the balances of a client to this file as a json
+///   line, with the deltas and the transaction that caused it, for downstream systems to poll.
+///   Can't be used with --redis
+/// * --latency-budget <ms> - times every row and logs those that take longer than this many
+///   milliseconds, E.G. 0.5, with what most of the time went to
+/// * --force - processes the input even if the restored snapshot or checkpoint shows a file with the
+///   same content was already ingested
+/// * --stream-after <bytes> - emits the account of a client once this much of the input went by
+///   without a row for it, E.G. 64M, rather than once the whole file is processed; a client that
+///   comes back is emitted again, and every account not emitted as it ends up is emitted at the
+///   end, so the last row for a client is the one to go by. Needs a local csv file
+/// * --fail-on-warn - fails the run if any account needs looking at, E.G. more than one account
+///   carries a client ID; the report is still written, the account output isn't
+/// * --dense-clients - keeps the clients in a table with a slot for every client ID rather than a
+///   hash map, which finds them faster but takes the memory of all 65536 up front
+/// * --changes-only - writes only the accounts whose balances changed since the snapshot given with
+///   --restore, as JSON lines with a change sequence number kept across snapshots
+/// * --snapshot-key <path> - encrypts and decrypts snapshots with the key in this file, 32 bytes
+///   or 64 hex digits; taken from CSV_TRANSACTIONS_SNAPSHOT_KEY if not given, needs the
+///   encryption feature
+/// * --clients <ids> - processes only these clients, E.G. 100-200,5000, skipping the rows of others
+/// * --exclude-types <types> - leaves every transaction of these types alone, E.G. chargeback,dispute,
+///   to see what balances would have been without them
+/// * --stop-at-row <n> - stops after the nth row of the input that could be read
+/// * --stop-at-timestamp <seconds> - stops at the first row with a later timestamp
+/// * --tenant <name> - processes and reports only this tenant's book, which rows without a tenant
+///   column belong to, "default" by default
+///
+/// An input path ending in .parquet or .avro is read as parquet or avro, which needs the feature of the same name.
+/// An s3://bucket/key path is streamed from s3, which needs the s3 feature
+fn parse_args() -> Args
+{
+    let mut path = None;
+    let mut policy = EnginePolicy::default();
+    let mut dialect = Dialect::default();
+    let mut rejections = None;
+    let mut disputes = None;
+    let mut held_ageing = None;
+    let mut as_of = None;
+    let mut review_queue = None;
+    let mut ledger = None;
+    let mut trial_balance = None;
+    let mut journal = None;
+    let mut gl_mapping = None;
+    let mut gl_journal = None;
+    let mut close_period = None;
+    let mut opening_balances = None;
+    let mut sub_accounts = None;
+    let mut consolidate = None;
+    let mut base_currency = None;
+    let mut rates = None;
+    let mut format = OutputFormat::Csv;
+    let mut export_profile = None;
+    let mut postgres = None;
+    let mut postgres_accounts_table = None;
+    let mut postgres_ledger_table = None;
+    let mut redis = None;
+    let mut redis_prefix = "transactions".to_string();
+    let mut atomic = false;
+    let mut dashboard = false;
+    let mut webhooks = Vec::new();
+    let mut slack_webhooks = Vec::new();
+    let mut emails = Vec::new();
+    let mut smtp_server = "localhost:25".to_string();
+    let mut email_from = "csv_transactions@localhost".to_string();
+    let mut smtp_tls = "opportunistic".to_string();
+    let mut smtp_credentials = None;
+    let mut top = 10;
+    let mut report_format = None;
+    let mut tenant = None;
+    let mut client_metadata = None;
+    let mut screening_list = None;
+    let mut signing_keys = None;
+    let mut policy_script = None;
+    let mut rules = None;
+    let mut restore = None;
+    let mut snapshot = None;
+    let mut changes_only = false;
+    let mut checkpoint = None;
+    let mut checkpoint_every = 10000;
+    let mut force = false;
+    let mut fail_on_warn = false;
+    let mut stream_after = None;
+    let mut dense_clients = false;
+    let mut wal = None;
+    let mut wal_sync = Durability::default();
+    let mut wal_segment_bytes = Wal::DEFAULT_SEGMENT_BYTES;
+    let mut outbox = None;
+    let mut dedup_expected = None;
+    let mut latency_budget = None;
+    let mut snapshot_key = None;
+    let mut only_clients = None;
+    let mut exclude_types = Vec::new();
+    let mut timestamp_formats = Vec::new();
+    let mut merge_inputs = Vec::new();
+    let mut tie_break = TieBreak::Source;
+    let mut stop_at = StopAt::default();
+    let mut output_filter = OutputFilter::default();
+    let mut top_n_by = None;
+    let mut schedules = None;
+    let mut args = std::env::args().skip(1).peekable();
+    let report = args.next_if(|arg| arg == "report").is_some();
+    while let Some(arg) = args.next()
     {
+        match arg.as_str()
+        {
+            "--rounding" => policy.rounding = parse_flag(&arg, &mut args),
+            "--min-amount" => policy.min_amount = Some(parse_flag(&arg, &mut args)),
+            "--max-amount" => policy.max_amount = Some(parse_flag(&arg, &mut args)),
+            "--unexpected-amount" => policy.unexpected_amount = parse_flag(&arg, &mut args),
+            "--locked-deposit" => policy.locked_deposit = parse_flag(&arg, &mut args),
+            "--locked-withdrawal" => policy.locked_withdrawal = parse_flag(&arg, &mut args),
+            "--reserve" => policy.reserve = Some(parse_flag(&arg, &mut args)),
+            "--freeze-chargebacks" => policy.freeze.get_or_insert_with(FreezePolicy::default).max_chargebacks = Some(parse_flag(&arg, &mut args)),
+            "--freeze-rate" => policy.freeze.get_or_insert_with(FreezePolicy::default).max_rate = Some(parse_flag(&arg, &mut args)),
+            "--freeze-window" => policy.freeze.get_or_insert_with(FreezePolicy::default).window = parse_flag(&arg, &mut args),
+            "--interest-rate" => policy.interest.get_or_insert_with(InterestPolicy::default).rate = parse_flag(&arg, &mut args),
+            "--interest-period" => policy.interest.get_or_insert_with(InterestPolicy::default).period = Some(parse_flag(&arg, &mut args)),
+            "--settlement-delay" => policy.settlement_delay = Some(parse_flag(&arg, &mut args)),
+            "--dispute-window-days" => policy.dispute_window.days = Some(parse_flag(&arg, &mut args)),
+            "--dispute-window-rows" => policy.dispute_window.rows = Some(parse_flag(&arg, &mut args)),
+            "--global-dedup" => {policy.global_dedup.get_or_insert_with(GlobalDedup::default);},
+            "--dedup-bloom" => {
+                let rate: f64 = parse_flag(&arg, &mut args);
+                if !(rate > 0.0 && rate < 1.0) {panic!("ERR: Invalid value '{}' for {}, it must be between 0 and 1", rate, arg)}
+                policy.global_dedup.get_or_insert_with(GlobalDedup::default).bloom_rate = Some(rate);
+            },
+            "--dedup-expected" => {
+                let count = flag_value(&arg, &mut args);
+                dedup_expected = Some(parse_count(&count).unwrap_or_else(|| panic!("ERR: Invalid value '{}' for {}", count, arg)));
+            },
+            "--rejections" => rejections = Some(flag_value(&arg, &mut args)),
+            "--disputes" => disputes = Some(flag_value(&arg, &mut args)),
+            "--held-ageing" => held_ageing = Some(flag_value(&arg, &mut args)),
+            "--as-of" => as_of = Some(parse_flag(&arg, &mut args)),
+            "--review-queue" => review_queue = Some(flag_value(&arg, &mut args)),
+            "--ledger" => ledger = Some(flag_value(&arg, &mut args)),
+            "--trial-balance" => trial_balance = Some(flag_value(&arg, &mut args)),
+            "--journal" => journal = Some(flag_value(&arg, &mut args)),
+            "--gl-mapping" => gl_mapping = Some(flag_value(&arg, &mut args)),
+            "--gl-journal" => gl_journal = Some(flag_value(&arg, &mut args)),
+            "--close-period" => close_period = Some(flag_value(&arg, &mut args)),
+            "--opening-balances" => opening_balances = Some(flag_value(&arg, &mut args)),
+            "--sub-accounts" => sub_accounts = Some(flag_value(&arg, &mut args)),
+            "--consolidate" => consolidate = Some(flag_value(&arg, &mut args)),
+            "--base-currency" => base_currency = Some(flag_value(&arg, &mut args)),
+            "--rates" => rates = Some(flag_value(&arg, &mut args)),
+            "--output-format" => format = parse_flag(&arg, &mut args),
+            "--export-profile" => export_profile = Some(parse_flag(&arg, &mut args)),
+            "--delimiter" => {
+                let value = flag_value(&arg, &mut args);
+                dialect.delimiter = match Dialect::parse_delimiter(&value) {
+                    Some(d) => d,
+                    None => panic!("ERR: Invalid value '{}' for {}", value, arg)
+                };
+            },
+            "--schema" => policy.schema = parse_flag(&arg, &mut args),
+            "--export-postgres" => postgres = Some(flag_value(&arg, &mut args)),
+            "--postgres-accounts-table" => postgres_accounts_table = Some(flag_value(&arg, &mut args)),
+            "--postgres-ledger-table" => postgres_ledger_table = Some(flag_value(&arg, &mut args)),
+            "--redis" => redis = Some(flag_value(&arg, &mut args)),
+            "--redis-prefix" => redis_prefix = flag_value(&arg, &mut args),
+            "--dashboard" => dashboard = true,
+            "--atomic" => atomic = true,
+            "--webhook" => webhooks.push(flag_value(&arg, &mut args)),
+            "--notify-slack" => slack_webhooks.push(flag_value(&arg, &mut args)),
+            "--notify-email" => emails.push(flag_value(&arg, &mut args)),
+            "--smtp-server" => smtp_server = flag_value(&arg, &mut args),
+            "--email-from" => email_from = flag_value(&arg, &mut args),
+            "--smtp-tls" => smtp_tls = flag_value(&arg, &mut args),
+            "--smtp-credentials" => smtp_credentials = Some(flag_value(&arg, &mut args)),
+            "--top" => top = parse_flag(&arg, &mut args),
+            "--report-format" => report_format = Some(parse_flag(&arg, &mut args)),
+            "--tenant" => tenant = Some(flag_value(&arg, &mut args)),
+            "--client-metadata" => client_metadata = Some(flag_value(&arg, &mut args)),
+            "--schedules" => schedules = Some(flag_value(&arg, &mut args)),
+            "--screening-list" => screening_list = Some(flag_value(&arg, &mut args)),
+            "--signing-keys" => signing_keys = Some(flag_value(&arg, &mut args)),
+            "--policy-script" => policy_script = Some(flag_value(&arg, &mut args)),
+            "--rules" => rules = Some(flag_value(&arg, &mut args)),
+            "--restore" => restore = Some(flag_value(&arg, &mut args)),
+            "--snapshot" => snapshot = Some(flag_value(&arg, &mut args)),
+            "--changes-only" => changes_only = true,
+            "--force" => force = true,
+            "--fail-on-warn" => fail_on_warn = true,
+            "--dense-clients" => dense_clients = true,
+            "--stream-after" => {
+                let bytes = flag_value(&arg, &mut args);
+                stream_after = Some(parse_count(&bytes).unwrap_or_else(|| panic!("ERR: Invalid value '{}' for {}", bytes, arg)));
+            },
+            "--latency-budget" => {
+                let millis: f64 = parse_flag(&arg, &mut args);
+                latency_budget = Some(Duration::try_from_secs_f64(millis / 1000.0).unwrap_or_else(|_| panic!("ERR: Invalid value '{}' for {}", millis, arg)));
+            },
+            "--checkpoint" => checkpoint = Some(flag_value(&arg, &mut args)),
+            "--checkpoint-every" => checkpoint_every = parse_flag::<u64>(&arg, &mut args).max(1),
+            "--wal" => wal = Some(flag_value(&arg, &mut args)),
+            "--wal-sync" => wal_sync = parse_flag(&arg, &mut args),
+            "--wal-segment-bytes" => {
+                let bytes = flag_value(&arg, &mut args);
+                wal_segment_bytes = parse_count(&bytes).unwrap_or_else(|| panic!("ERR: Invalid value '{}' for {}", bytes, arg));
+            },
+            "--outbox" => outbox = Some(flag_value(&arg, &mut args)),
+            "--snapshot-key" => snapshot_key = Some(flag_value(&arg, &mut args)),
+            "--clients" => only_clients = Some(parse_flag(&arg, &mut args)),
+            "--exclude-types" => {
+                let value = flag_value(&arg, &mut args);
+                exclude_types = parse_types(&value).unwrap_or_else(|e| panic!("ERR: Invalid value '{}' for {}: {}", value, arg, e));
+            },
+            "--only-locked" => output_filter.only_locked = true,
+            "--non-zero" => output_filter.non_zero = true,
+            "--top-n-by" => top_n_by = Some(parse_flag::<SortKey>(&arg, &mut args)),
+            "--stop-at-row" => stop_at.row = Some(parse_flag(&arg, &mut args)),
+            "--stop-at-timestamp" => stop_at.timestamp = Some(parse_flag(&arg, &mut args)),
+            "--screen-above" => policy.screen_above = Some(parse_flag(&arg, &mut args)),
+            "--tier" => {
+                let value = flag_value(&arg, &mut args);
+                match value.split_once(':').map(|(name, limits)| (name, limits.parse()))
+                {
+                    Some((name, Ok(limits))) => policy.tiers.insert(name.to_string(), limits),
+                    _ => panic!("ERR: Invalid value '{}' for {}", value, arg)
+                };
+            },
+            "--trim" => dialect.trim = true,
+            "--decimal-comma" => dialect.decimal_comma = true,
+            "--merge-input" => merge_inputs.push(flag_value(&arg, &mut args)),
+            "--tie-break" => tie_break = parse_flag(&arg, &mut args),
+            "--timestamp-format" => timestamp_formats.push(parse_flag(&arg, &mut args)),
+            "--timezone" => {
+                let value = flag_value(&arg, &mut args);
+                dialect.timestamps.utc_offset = parse_utc_offset(&value).unwrap_or_else(|e| panic!("ERR: Invalid value '{}' for {}: {}", value, arg, e));
+            },
+            "--amount-format" => {
+                let value = flag_value(&arg, &mut args);
+                dialect.amount_parser = parse_amount_parser(&value).unwrap_or_else(|e| panic!("ERR: Invalid value '{}' for {}: {}", value, arg, e));
+            },
+            "--rename-column" => {
+                let value = flag_value(&arg, &mut args);
+                match Dialect::parse_rename(&value) {
+                    Some((from, to)) => dialect.renames.insert(from, to),
+                    None => panic!("ERR: Invalid value '{}' for {}", value, arg)
+                };
+            },
+            other if other.starts_with("--") => panic!("ERR: Unknown option '{}'", other),
+            _ => path = Some(arg)
+        }
+    }
+    output_filter.top = top_n_by.map(|key| (key, top));
+    if !timestamp_formats.is_empty()
+    {
+        dialect.timestamps.formats = timestamp_formats;
+    }
+    if let (Some(expected), Some(dedup)) = (dedup_expected, policy.global_dedup.as_mut())
+    {
+        dedup.expected = expected;
+    }
+    match path
+    {
+        Some(path) => Args { path, policy, dialect, rejections, disputes, held_ageing, as_of, review_queue, ledger, trial_balance, journal, gl_mapping, gl_journal, close_period, opening_balances, sub_accounts, consolidate, base_currency, rates, format, export_profile, postgres, postgres_accounts_table, postgres_ledger_table, redis, redis_prefix, dashboard, atomic, webhooks, slack_webhooks, emails, smtp_server, email_from, smtp_tls, smtp_credentials, report: report || report_format.is_some(), report_format, top, tenant, client_metadata, screening_list, schedules, signing_keys, policy_script, rules, restore, snapshot, snapshot_key, only_clients, exclude_types, merge_inputs, tie_break, stop_at, output_filter, changes_only, checkpoint, checkpoint_every, force, latency_budget, fail_on_warn, stream_after, dense_clients, wal, wal_sync, wal_segment_bytes, outbox },
         //we panic here as we can't really continue without input anyway
-        panic!("ERR: No path argument given");        
+        None => panic!("ERR: No path argument given")
     }
-    let path = input_argument.unwrap();
-    let file = match File::open(&path)
+}
+
+/// Whether a path points to a parquet file, going by its extension
+fn is_parquet(path: &str) -> bool
+{
+    path.ends_with(".parquet")
+}
+
+/// Whether a path points to an avro container file, going by its extension
+fn is_avro(path: &str) -> bool
+{
+    path.ends_with(".avro")
+}
+
+/// Opens a local input file
+fn open_file(path: &str) -> File
+{
+    match File::open(path)
     {
         Ok(f) => f,
         Err(_) => {
             //we panic here as we can't really continue without input anyway
-            panic!("ERR: Couldn't open file specified");  
+            panic!("ERR: Couldn't open file specified");
         }
+    }
+}
+
+/// Opens the input, streaming it from object storage if the path is an s3:// url
+fn open_input(path: &str) -> Box<dyn io::Read>
+{
+    if path.starts_with("s3://")
+    {
+        #[cfg(feature = "s3")]
+        return match csv_transactions::object_source::ObjectStoreSource::from_url(path).and_then(|s| s.open())
+        {
+            Ok(reader) => Box::new(reader),
+            //we panic here as we can't really continue without input anyway
+            Err(e) => panic!("ERR: Couldn't open {}: {}", path, e)
+        };
+        #[cfg(not(feature = "s3"))]
+        panic!("ERR: Built without s3 support");
+    }
+    Box::new(open_file(path))
+}
+
+/// Reads the transactions from the input, as parquet or avro if the path
+/// ends in .parquet or .avro and as csv otherwise
+fn read_input<'a>(path: &str, dialect: &'a Dialect, schema: SchemaMode) -> Box<dyn Iterator<Item = TxRecord> + 'a>
+{
+    if is_parquet(path)
+    {
+        if path.starts_with("s3://") {panic!("ERR: Parquet input has to be a local file")}
+        #[cfg(feature = "parquet")]
+        return match csv_transactions::columnar::read_parquet_records(open_file(path), dialect, schema)
+        {
+            Ok(records) => Box::new(records),
+            //we panic here as no row in the file could be read anyway
+            Err(e) => panic!("ERR: {}", e)
+        };
+        #[cfg(not(feature = "parquet"))]
+        panic!("ERR: Built without parquet support");
+    }
+    let input = open_input(path);
+    if is_avro(path)
+    {
+        #[cfg(feature = "avro")]
+        return match csv_transactions::avro::read_avro_records(input, dialect, schema)
+        {
+            Ok(records) => Box::new(records),
+            //we panic here as no row in the file could be read anyway
+            Err(e) => panic!("ERR: {}", e)
+        };
+        #[cfg(not(feature = "avro"))]
+        panic!("ERR: Built without avro support");
+    }
+    match dialect.read_records(input, schema)
+    {
+        Ok(records) => Box::new(records),
+        //we panic here as no row in the file could be read anyway
+        Err(e) => panic!("ERR: {}", e)
+    }
+}
+
+/// Reads a local csv input from a byte offset on, keeping the offset just past
+/// the latest record read, for checkpoints
+fn read_input_from<'a>(path: &str, dialect: &'a Dialect, schema: SchemaMode, offset: &'a Cell<u64>) -> Box<dyn Iterator<Item = TxRecord> + 'a>
+{
+    if is_parquet(path) || is_avro(path) || path.starts_with("s3://")
+    {
+        panic!("ERR: --checkpoint and --stream-after need the input to be a local csv file");
+    }
+    match dialect.read_records_from(open_file(path), offset.get(), schema)
+    {
+        Ok(records) => Box::new(records.map(move |(end, record)| {
+            offset.set(end);
+            record
+        })),
+        //we panic here as no row in the file could be read anyway
+        Err(e) => panic!("ERR: {}", e)
+    }
+}
+
+/// Hands the engine and how far into the input it got to the checkpoint writer, if
+/// checkpoints are on, logging the checkpoints that failed to be written since last time
+fn write_checkpoint(engine: &Engine, writer: Option<&BackgroundCheckpoints>, input: &str, offset: u64, rows: u64)
+{
+    if let Some(writer) = writer
+    {
+        writer.submit(engine, Checkpoint { input: input.to_string(), offset, rows });
+        for (checkpoint, e) in writer.take_errors()
+        {
+            eprintln!("ERR: Couldn't write the checkpoint after row {}: {}", checkpoint.rows, e);
+        }
+    }
+}
+
+/// Writes the ledger export, as parquet if the path ends in .parquet and as csv otherwise,
+/// laid out as the export profile has it if one is given
+fn write_ledger_file(path: &str, clients: &ClientStore, profile: Option<&ExportProfile>) -> io::Result<()>
+{
+    let file = File::create(path)?;
+    if is_parquet(path)
+    {
+        #[cfg(feature = "parquet")]
+        return Ok(csv_transactions::columnar::write_ledger_parquet(clients, file)?);
+        #[cfg(not(feature = "parquet"))]
+        return Err(io::Error::other("built without parquet support"));
+    }
+    match profile
+    {
+        Some(profile) => Ok(write_ledger_with_profile(clients, profile, file)?),
+        None => Ok(write_ledger(clients, file)?)
+    }
+}
+
+/// Returns the sink the account report is written to stdout with, a csv one laid out as
+/// the export profile has it if one is given
+///
+/// # Arguments
+///
+/// * 'format' - The format of the report
+/// * 'profile' - The export profile, only used for csv
+fn account_sink(format: OutputFormat, profile: Option<ExportProfile>) -> io::Result<Box<dyn AccountSink>>
+{
+    match (format, profile)
+    {
+        (OutputFormat::Csv, Some(profile)) => Ok(Box::new(CsvSink::with_profile(io::stdout(), profile))),
+        _ => format.sink(io::stdout())
+    }
+}
+
+/// Upserts the accounts and ledger into postgres, into the tables given on the command line
+#[cfg(feature = "postgres")]
+fn export_postgres(url: &str, accounts_table: Option<String>, ledger_table: Option<String>, clients: &ClientStore) -> io::Result<()>
+{
+    let mut export = csv_transactions::postgres::PgExport::default();
+    if let Some(table) = accounts_table {export.accounts_table = table;}
+    if let Some(table) = ledger_table {export.ledger_table = table;}
+    export.export_blocking(url, clients).map_err(|e| io::Error::other(e.to_string()))
+}
+#[cfg(not(feature = "postgres"))]
+fn export_postgres(_url: &str, _accounts_table: Option<String>, _ledger_table: Option<String>, _clients: &ClientStore) -> io::Result<()>
+{
+    Err(io::Error::other("built without postgres support"))
+}
+
+/// Applies the records to the clients kept in redis, then loads every client
+/// into the engine so the reports cover what all engines have processed
+#[cfg(feature = "redis")]
+fn apply_shared(url: &str, prefix: &str, engine: &mut Engine, records: impl Iterator<Item = TxRecord>) -> Result<(), csv_transactions::redis_state::StateError>
+{
+    let mut state = csv_transactions::redis_state::RedisState::connect(url, prefix, engine.policy.clone())?;
+    for record in records
+    {
+        state.apply_record(record)?;
+    }
+    engine.clients = state.clients()?;
+    engine.rejections = state.rejections;
+    engine.rejection_stats = engine.rejections.iter().collect();
+    Ok(())
+}
+#[cfg(not(feature = "redis"))]
+fn apply_shared(_url: &str, _prefix: &str, _engine: &mut Engine, _records: impl Iterator<Item = TxRecord>) -> Result<(), &'static str>
+{
+    Err("built without redis support")
+}
+
+/// The key snapshots are encrypted with, from the key file if one was given and
+/// otherwise from the environment
+fn snapshot_key(path: Option<&str>) -> Option<SnapshotKey>
+{
+    let key = match path
+    {
+        Some(path) => Some(SnapshotKey::read(path)),
+        None => SnapshotKey::from_env()
     };
-    let mut clients = HashMap::new();
-    let mut rdr = csv::Reader::from_reader(file);
-    for line in rdr.deserialize()
-    {  
-        let tx: Tx = match line {
-            Ok(tx) => tx,
-            Err(_)=> {
-                continue;
+    //we panic here rather than write customer balances unencrypted
+    key.map(|k| k.unwrap_or_else(|e| panic!("ERR: Couldn't read the snapshot key: {}", e)))
+}
+
+/// Has the engine check every transaction's signature against the keys in the file
+#[cfg(feature = "ed25519")]
+fn add_verifier(engine: &mut Engine, path: &str) -> io::Result<()>
+{
+    engine.set_verifier(Box::new(csv_transactions::Ed25519Verifier::read(File::open(path)?)?));
+    Ok(())
+}
+#[cfg(not(feature = "ed25519"))]
+fn add_verifier(_engine: &mut Engine, _path: &str) -> io::Result<()>
+{
+    Err(io::Error::other("built without ed25519 support"))
+}
+
+/// Has the engine put its policy decisions to the rhai script in the file
+#[cfg(feature = "scripting")]
+fn add_policy_script(engine: &mut Engine, path: &str) -> io::Result<()>
+{
+    let rounding = engine.policy.rounding;
+    engine.set_policy_hook(Box::new(csv_transactions::RhaiPolicy::read(path, rounding)?));
+    Ok(())
+}
+#[cfg(not(feature = "scripting"))]
+fn add_policy_script(_engine: &mut Engine, _path: &str) -> io::Result<()>
+{
+    Err(io::Error::other("built without scripting support"))
+}
+
+/// Has the engine POST notifications to the webhooks, and the end-of-run summary to
+/// the Slack ones, delivering whatever is still queued when the engine is dropped
+#[cfg(feature = "webhook")]
+fn add_webhooks(engine: &mut Engine, urls: Vec<String>, slack: Vec<String>) -> io::Result<()>
+{
+    use csv_transactions::webhook::{WebhookConfig, WebhookFormat, WebhookNotifier};
+    if !urls.is_empty()
+    {
+        engine.add_notifier(Box::new(WebhookNotifier::new(WebhookConfig::new(urls))?));
+    }
+    if !slack.is_empty()
+    {
+        let config = WebhookConfig { format: WebhookFormat::Slack, ..WebhookConfig::new(slack) };
+        engine.add_notifier(Box::new(WebhookNotifier::new(config)?));
+    }
+    Ok(())
+}
+#[cfg(not(feature = "webhook"))]
+fn add_webhooks(_engine: &mut Engine, _urls: Vec<String>, _slack: Vec<String>) -> io::Result<()>
+{
+    Err(io::Error::other("built without webhook support"))
+}
+
+/// Has the engine mail the end-of-run summary to the addresses through the relay, the
+/// connection secured as given by --smtp-tls and logged in to with the credentials file,
+/// returning what to ask once the run is done for why the mail wasn't sent, if it wasn't
+#[cfg(feature = "email")]
+fn add_email(engine: &mut Engine, to: Vec<String>, server: String, from: String, tls: &str, credentials: Option<&str>) -> io::Result<Box<dyn Fn() -> Option<String>>>
+{
+    use csv_transactions::email::{EmailConfig, EmailNotifier};
+    let tls = tls.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let credentials = match credentials
+    {
+        Some(path) => {
+            let text = std::fs::read_to_string(path)?;
+            let mut lines = text.lines();
+            match (lines.next(), lines.next())
+            {
+                (Some(user), Some(password)) => Some((user.to_string(), password.to_string())),
+                _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} needs a user name and a password on two lines", path)))
             }
-        };
-        let c = clients.entry(tx.client).or_insert(Client::new(tx.client));
-        let transaction_id = tx.tx;
-        match tx.r#type
+        },
+        None => None
+    };
+    let config = EmailConfig { tls, credentials, ..EmailConfig::new(server, from, to) };
+    let notifier = EmailNotifier::new(config)?;
+    let stats = notifier.stats.clone();
+    engine.add_notifier(Box::new(notifier));
+    Ok(Box::new(move || stats.lock().ok().and_then(|s| s.last_error.clone())))
+}
+#[cfg(not(feature = "email"))]
+fn add_email(_engine: &mut Engine, _to: Vec<String>, _server: String, _from: String, _tls: &str, _credentials: Option<&str>) -> io::Result<Box<dyn Fn() -> Option<String>>>
+{
+    Err(io::Error::other("built without email support"))
+}
+
+/// Applies the records while drawing the dashboard, which stays up until q is pressed
+#[cfg(feature = "tui")]
+fn apply_with_dashboard(engine: &mut Engine, records: impl Iterator<Item = TxRecord>) -> io::Result<()>
+{
+    let mut dashboard = csv_transactions::dashboard::Dashboard::start()?;
+    for record in records
+    {
+        let client = record.client;
+        let was_locked = engine.clients.get(&client).is_some_and(|c| c.acc.locked);
+        engine.apply_record(record);
+        dashboard.observe(engine, client, was_locked)?;
+    }
+    dashboard.finish(engine)
+}
+#[cfg(not(feature = "tui"))]
+fn apply_with_dashboard(_engine: &mut Engine, _records: impl Iterator<Item = TxRecord>) -> io::Result<()>
+{
+    Err(io::Error::other("built without tui support"))
+}
+
+/// Checks a csv ledger export against its hash chain, and the head from the report if given
+fn run_verify_ledger()
+{
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let mut path = None;
+    let mut head = None;
+    let mut i = 0;
+    while i < args.len()
+    {
+        match args[i].as_str()
+        {
+            "--head" => {head = Some(args.get(i + 1).cloned().unwrap_or_else(|| panic!("ERR: --head needs a hash"))); i += 1;},
+            other => path = Some(other.to_string())
+        }
+        i += 1;
+    }
+    let path = path.unwrap_or_else(|| panic!("ERR: Usage: csv_transactions verify-ledger <path> [--head <hash>]"));
+    match verify_ledger(open_file(&path), head.as_deref())
+    {
+        Ok((rows, head)) => println!("OK: {} entries, head {}", rows, head),
+        Err(e) => panic!("ERR: Ledger {} failed verification: {}", path, e)
+    }
+}
+
+/// Writes random transactions to the output path, or stdout, and how many of each kind to stderr
+fn run_generate()
+{
+    let mut args = std::env::args().skip(2);
+    let mut config = GeneratorConfig::default();
+    let mut output = None;
+    let count = |flag: &str, args: &mut std::iter::Skip<std::env::Args>| {
+        let value = flag_value(flag, args);
+        parse_count(&value).unwrap_or_else(|| panic!("ERR: Invalid value '{}' for {}", value, flag))
+    };
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--clients" => config.clients = u16::try_from(count("--clients", &mut args)).unwrap_or_else(|_| panic!("ERR: --clients can be at most {}", u16::MAX)),
+            "--rows" => config.rows = count("--rows", &mut args),
+            "--dispute-rate" => config.dispute_rate = parse_flag("--dispute-rate", &mut args),
+            "--duplicate-rate" => config.duplicate_rate = parse_flag("--duplicate-rate", &mut args),
+            "--malformed-rate" => config.malformed_rate = parse_flag("--malformed-rate", &mut args),
+            "--seed" => config.seed = parse_flag("--seed", &mut args),
+            "--output" => output = Some(flag_value("--output", &mut args)),
+            other => panic!("ERR: Unknown option '{}' for generate", other)
+        }
+    }
+    let rates = [config.dispute_rate, config.duplicate_rate, config.malformed_rate];
+    if config.clients == 0 || rates.iter().any(|r| !(0.0..=1.0).contains(r)) || rates.iter().sum::<f64>() > 1.0
+    {
+        panic!("ERR: generate needs at least one client, and rates between 0 and 1 that add up to at most 1");
+    }
+    let stats = match &output
+    {
+        Some(path) => File::create(path).and_then(|file| generate(&config, file)),
+        None => generate(&config, io::stdout().lock())
+    };
+    match stats
+    {
+        Ok(stats) => eprintln!("{} deposits, {} withdrawals, {} disputes, {} resolves, {} chargebacks, {} duplicates, {} malformed",
+            stats.deposits, stats.withdrawals, stats.disputes, stats.resolves, stats.chargebacks, stats.duplicates, stats.malformed),
+        Err(e) => panic!("ERR: Couldn't write generated input: {}", e)
+    }
+}
+
+/// Prints accounts, or the history of clients, from a snapshot opened read only
+fn run_query()
+{
+    let mut args = std::env::args().skip(2);
+    let mut snapshot = None;
+    let mut clients = Vec::new();
+    let mut history = false;
+    let mut format = OutputFormat::Csv;
+    let mut key_path = None;
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--snapshot" => snapshot = Some(flag_value("--snapshot", &mut args)),
+            "--client" => clients.push(parse_flag::<u16>("--client", &mut args)),
+            "--history" => history = true,
+            "--output-format" => format = parse_flag("--output-format", &mut args),
+            "--snapshot-key" => key_path = Some(flag_value("--snapshot-key", &mut args)),
+            other => panic!("ERR: Unknown option '{}' for query", other)
+        }
+    }
+    let path = snapshot.unwrap_or_else(|| panic!("ERR: Usage: csv_transactions query --snapshot <path> [--client <id>] [--history]"));
+    let engine = match Engine::open_read_only(Path::new(&path), snapshot_key(key_path.as_deref()).as_ref())
+    {
+        Ok(engine) => engine,
+        Err(e) => panic!("ERR: Couldn't open the snapshot {}: {}", path, e)
+    };
+    if clients.is_empty()
+    {
+        clients = engine.clients.keys().collect();
+        clients.sort();
+    }
+    for client in clients.iter().filter(|c| engine.client(**c).is_none())
+    {
+        eprintln!("WARN: The snapshot has no account for client {}", client);
+    }
+    let written = match history
+    {
+        true => {
+            let rows: Vec<_> = clients.iter().flat_map(|c| engine.history(*c)).collect();
+            write_history(&rows, io::stdout()).map_err(io::Error::from)
+        },
+        false => format.sink(io::stdout()).and_then(|mut sink| {
+            for client in clients.iter().filter_map(|c| engine.client(*c))
+            {
+                sink.write_account(&client.acc)?;
+            }
+            sink.finish()
+        })
+    };
+    if let Err(e) = written
+    {
+        eprintln!("ERR: Couldn't write the query result: {}", e);
+    }
+}
+
+/// Compares the final balances, of a snapshot or of running the input, to an expected balance
+/// file, writing any discrepancies as csv and exiting with 1 if there are any
+fn run_reconcile()
+{
+    let mut args = std::env::args().skip(2);
+    let mut expected = None;
+    let mut snapshot = None;
+    let mut key_path = None;
+    let mut input = None;
+    let mut tolerance = Tolerance::default();
+    let mut output = None;
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--expected" => expected = Some(flag_value("--expected", &mut args)),
+            "--snapshot" => snapshot = Some(flag_value("--snapshot", &mut args)),
+            "--snapshot-key" => key_path = Some(flag_value("--snapshot-key", &mut args)),
+            "--tolerance" => tolerance.absolute = parse_flag("--tolerance", &mut args),
+            "--tolerance-rate" => tolerance.relative = parse_flag("--tolerance-rate", &mut args),
+            "--output" => output = Some(flag_value("--output", &mut args)),
+            other if !other.starts_with("--") => input = Some(other.to_string()),
+            other => panic!("ERR: Unknown option '{}' for reconcile", other)
+        }
+    }
+    let usage = "ERR: Usage: csv_transactions reconcile --expected <path> (--snapshot <path> | <path>) [--tolerance <amount>] [--tolerance-rate <rate>] [--output <path>]";
+    let expected_path = expected.unwrap_or_else(|| panic!("{}", usage));
+    if tolerance.absolute.is_negative() || tolerance.relative.is_negative()
+    {
+        panic!("ERR: The tolerances can't be negative");
+    }
+    let expected = match read_expected(open_file(&expected_path))
+    {
+        Ok(expected) => expected,
+        Err(e) => panic!("ERR: Couldn't read the expected balances {}: {}", expected_path, e)
+    };
+    let reconciliation = match (snapshot, input)
+    {
+        (Some(path), None) => match Engine::open_read_only(Path::new(&path), snapshot_key(key_path.as_deref()).as_ref())
+        {
+            Ok(engine) => reconcile(&engine.clients, &expected, tolerance),
+            Err(e) => panic!("ERR: Couldn't open the snapshot {}: {}", path, e)
+        },
+        (None, Some(path)) => {
+            let policy = EnginePolicy::default();
+            let mut engine = Engine::new(policy.clone());
+            match Dialect::default().read_records(open_file(&path), policy.schema)
+            {
+                Ok(records) => records.for_each(|record| engine.apply_record(record)),
+                Err(e) => panic!("ERR: Couldn't read the header of {}: {}", path, e)
+            }
+            reconcile(&engine.clients, &expected, tolerance)
+        },
+        _ => panic!("{}", usage)
+    };
+    let written = match &output
+    {
+        Some(path) => File::create(path).map_err(csv::Error::from).and_then(|file| write_discrepancies(&reconciliation.discrepancies, file)),
+        None => write_discrepancies(&reconciliation.discrepancies, io::stdout())
+    };
+    if let Err(e) = written
+    {
+        panic!("ERR: Couldn't write the discrepancies: {}", e);
+    }
+    eprintln!("{} clients, {} with discrepancies", reconciliation.clients, reconciliation.mismatched);
+    if !reconciliation.is_clean()
+    {
+        std::process::exit(1);
+    }
+}
+
+/// Prints quality metrics of a sample of the input, the first 10000 rows unless
+/// --sample says otherwise, 0 for every row
+fn run_profile()
+{
+    let mut args = std::env::args().skip(2);
+    let mut dialect = Dialect::default();
+    let mut sample = Some(10_000);
+    let mut format = OutputFormat::Csv;
+    let mut path = None;
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
         {
-            TypeTx::Deposit | TypeTx::Withdrawal => {
-                c.process_transaction(&tx);
+            "--sample" => {
+                let value = flag_value("--sample", &mut args);
+                sample = parse_count(&value).map(|n| Some(n).filter(|n| *n > 0)).unwrap_or_else(|| panic!("ERR: Invalid value '{}' for --sample", value));
             },
-            TypeTx::Dispute => {
-                match c.get_transaction(&transaction_id) {
-                    Some(_) => {
-                        c.dispute_transaction(&transaction_id);
-                    },
-                    None => ()
-                };
+            "--delimiter" => {
+                let value = flag_value("--delimiter", &mut args);
+                dialect.delimiter = Dialect::parse_delimiter(&value).unwrap_or_else(|| panic!("ERR: Invalid value '{}' for --delimiter", value));
+            },
+            "--output-format" => format = parse_flag("--output-format", &mut args),
+            other if !other.starts_with("--") => path = Some(other.to_string()),
+            other => panic!("ERR: Unknown option '{}' for profile", other)
+        }
+    }
+    let path = path.unwrap_or_else(|| panic!("ERR: Usage: csv_transactions profile [--sample <n>] [--delimiter <char>] [--output-format json] <path>"));
+    let profile = match dialect.profile(open_file(&path), sample, SchemaMode::Lenient)
+    {
+        Ok(profile) => profile,
+        Err(e) => panic!("ERR: {}", e)
+    };
+    let written = match format
+    {
+        OutputFormat::Json => serde_json::to_writer_pretty(io::stdout(), &profile).map_err(io::Error::from).and_then(|_| writeln!(io::stdout())),
+        _ => write!(io::stdout(), "{}", profile)
+    };
+    if written.is_err()
+    {
+        eprintln!("ERR: Couldn't write the profile");
+    }
+}
+
+/// Converts a bank export to a transactions file in our columns, written to the output path
+/// or stdout, with how it went written as json to the report path or to stderr; with --infer
+/// it prints the mapping found from the headers instead, as json to start a config from
+fn run_convert()
+{
+    let mut args = std::env::args().skip(2);
+    let mut mapping = None;
+    let mut infer = false;
+    let mut delimiter = None;
+    let mut output = None;
+    let mut report_path = None;
+    let mut path = None;
+    while let Some(arg) = args.next()
+    {
+        match arg.as_str()
+        {
+            "--mapping" => mapping = Some(flag_value("--mapping", &mut args)),
+            "--infer" => infer = true,
+            "--delimiter" => {
+                let value = flag_value("--delimiter", &mut args);
+                delimiter = Some(Dialect::parse_delimiter(&value).unwrap_or_else(|| panic!("ERR: Invalid value '{}' for --delimiter", value)) as char);
             },
-            TypeTx::Resolve => {
-                match c.get_transaction(&transaction_id) {
-                    Some(transaction) => {
-                        if transaction.in_dispute
+            "--output" => output = Some(flag_value("--output", &mut args)),
+            "--report" => report_path = Some(flag_value("--report", &mut args)),
+            other if !other.starts_with("--") => path = Some(other.to_string()),
+            other => panic!("ERR: Unknown option '{}' for convert", other)
+        }
+    }
+    let usage = "ERR: Usage: csv_transactions convert (--mapping <path> | --infer) [--delimiter <char>] [--output <path>] [--report <path>] <path>";
+    let path = path.unwrap_or_else(|| panic!("{}", usage));
+    let mut mapping = match (mapping, infer)
+    {
+        (Some(mapping_path), false) => ConvertMapping::read(open_file(&mapping_path))
+            .unwrap_or_else(|e| panic!("ERR: Couldn't read the mapping {}: {}", mapping_path, e)),
+        (None, true) => ConvertMapping::default(),
+        _ => panic!("{}", usage)
+    };
+    mapping.delimiter = delimiter.or(mapping.delimiter);
+    if infer
+    {
+        let headers = csv::ReaderBuilder::new().delimiter(mapping.delimiter.map_or(b',', |d| d as u8)).from_reader(open_file(&path)).headers().cloned()
+            .unwrap_or_else(|e| panic!("ERR: Couldn't read the headers of {}: {}", path, e));
+        let inferred = ConvertMapping { delimiter: mapping.delimiter, ..ConvertMapping::infer(&headers) };
+        if serde_json::to_writer_pretty(io::stdout(), &inferred).map_err(io::Error::from).and_then(|_| writeln!(io::stdout())).is_err()
+        {
+            eprintln!("ERR: Couldn't write the mapping");
+        }
+        return;
+    }
+    let converted = match &output
+    {
+        Some(out) => File::create(out).map_err(From::from).and_then(|f| mapping.convert(open_file(&path), io::BufWriter::new(f))),
+        None => mapping.convert(open_file(&path), io::stdout().lock())
+    };
+    let report = converted.unwrap_or_else(|e| panic!("ERR: Couldn't convert {}: {}", path, e));
+    match report_path
+    {
+        Some(report_path) => {
+            let written = File::create(&report_path).map_err(serde_json::Error::io)
+                .and_then(|f| serde_json::to_writer_pretty(f, &report));
+            if written.is_err()
+            {
+                eprintln!("ERR: Couldn't write the mapping report to {}", report_path);
+            }
+        },
+        None => eprint!("{}", report)
+    }
+    if !report.unmapped_types.is_empty()
+    {
+        eprintln!("WARN: {} rows had a type the mapping doesn't map", report.unmapped_types.values().sum::<u64>());
+    }
+}
+
+/// Reads commands from stdin until it ends or the operator types quit
+fn run_repl()
+{
+    let mut repl = Repl::new(Engine::new(EnginePolicy::default()));
+    let mut stdout = io::stdout();
+    let mut line = String::new();
+    loop
+    {
+        print!("> ");
+        if stdout.flush().is_err() {return}
+        line.clear();
+        match io::stdin().read_line(&mut line)
+        {
+            Ok(0) | Err(_) => return,
+            Ok(_) => ()
+        }
+        match line.trim()
+        {
+            "quit" | "exit" => return,
+            command => {
+                let output = repl.execute(command);
+                if !output.is_empty() {println!("{}", output);}
+            }
+        }
+    }
+}
+
+fn main()
+{
+    if std::env::args().nth(1).as_deref() == Some("repl")
+    {
+        return run_repl();
+    }
+    if std::env::args().nth(1).as_deref() == Some("verify-ledger")
+    {
+        return run_verify_ledger();
+    }
+    if std::env::args().nth(1).as_deref() == Some("generate")
+    {
+        return run_generate();
+    }
+    if std::env::args().nth(1).as_deref() == Some("query")
+    {
+        return run_query();
+    }
+    if std::env::args().nth(1).as_deref() == Some("reconcile")
+    {
+        return run_reconcile();
+    }
+    if std::env::args().nth(1).as_deref() == Some("profile")
+    {
+        return run_profile();
+    }
+    if std::env::args().nth(1).as_deref() == Some("convert")
+    {
+        return run_convert();
+    }
+    let args = parse_args();
+    let key = snapshot_key(args.snapshot_key.as_deref());
+    let resumed = match &args.checkpoint
+    {
+        Some(dir) => match Engine::resume(Path::new(dir), &args.path, args.policy.clone(), key.as_ref())
+        {
+            Ok(resumed) => resumed,
+            //we panic here as starting over would apply the rows before the checkpoint twice
+            Err(e) => panic!("ERR: Couldn't resume from the checkpoint in {}: {}", dir, e)
+        },
+        None => None
+    };
+    let mut rows = resumed.as_ref().map_or(0, |(_, c)| c.rows);
+    let start = resumed.as_ref().map_or(0, |(_, c)| c.offset);
+    let mut engine = match (resumed, &args.restore)
+    {
+        (Some((engine, checkpoint)), _) => {
+            eprintln!("WARN: Resuming {} after row {}, from the checkpoint", args.path, checkpoint.rows);
+            engine
+        },
+        (None, Some(path)) => match Engine::restore_from(open_file(path), args.policy, key.as_ref())
+        {
+            Ok(engine) => engine,
+            Err(e) => panic!("ERR: Couldn't restore the snapshot {}: {}", path, e)
+        },
+        (None, None) => match args.wal.as_ref().map(|dir| Wal::snapshot_path(Path::new(dir))).filter(|path| path.exists())
+        {
+            Some(path) => match Engine::restore_from(open_file(&path.to_string_lossy()), args.policy, key.as_ref())
+            {
+                Ok(engine) => engine,
+                Err(e) => panic!("ERR: Couldn't restore the snapshot {}: {}", path.display(), e)
+            },
+            None => Engine::new(args.policy)
+        }
+    };
+    if let Some(path) = &args.client_metadata
+    {
+        match read_metadata(open_file(path))
+        {
+            Ok(metadata) => engine.load_metadata(metadata),
+            //we panic here as the limits and policies depend on it
+            Err(e) => panic!("ERR: Couldn't read client metadata from {}: {}", path, e)
+        }
+    }
+    //a restored engine already carries them, from the run that started with them
+    match (&args.opening_balances, engine.rows == 0 && engine.clients.is_empty())
+    {
+        (Some(path), true) => {
+            let loaded = read_opening_balances(open_file(path)).map_err(|e| e.to_string())
+                .and_then(|balances| engine.load_opening_balances(&balances).map_err(|e| e.to_string()));
+            //we panic here as every balance after would be off
+            if let Err(e) = loaded
+            {
+                panic!("ERR: Couldn't load opening balances from {}: {}", path, e);
+            }
+        },
+        (Some(path), false) => eprintln!("WARN: Not loading the opening balances {}, as the engine carries on from earlier state", path),
+        (None, _) => ()
+    }
+    if let Some(path) = &args.screening_list
+    {
+        match ListScreening::read(open_file(path))
+        {
+            Ok(list) => engine.set_screening(Box::new(list)),
+            Err(e) => panic!("ERR: Couldn't read screening list from {}: {}", path, e)
+        }
+    }
+    if let Some(path) = &args.signing_keys
+    {
+        if let Err(e) = add_verifier(&mut engine, path)
+        {
+            panic!("ERR: Couldn't read signing keys from {}: {}", path, e);
+        }
+    }
+    if let Some(path) = &args.rules
+    {
+        match RuleSet::read(open_file(path))
+        {
+            Ok(rules) => engine.set_rules(rules),
+            Err(e) => panic!("ERR: Couldn't read rules from {}: {}", path, e)
+        }
+    }
+    if let Some(path) = &args.policy_script
+    {
+        if let Err(e) = add_policy_script(&mut engine, path)
+        {
+            panic!("ERR: Couldn't load the policy script {}: {}", path, e);
+        }
+    }
+    if let Some(path) = &args.schedules
+    {
+        match read_schedules(open_file(path))
+        {
+            Ok(schedules) => schedules.into_iter().for_each(|s| engine.add_schedule(s)),
+            Err(e) => panic!("ERR: Couldn't read schedules from {}: {}", path, e)
+        }
+    }
+    if !args.webhooks.is_empty() || !args.slack_webhooks.is_empty()
+    {
+        if let Err(e) = add_webhooks(&mut engine, args.webhooks, args.slack_webhooks)
+        {
+            panic!("ERR: Couldn't set up webhooks: {}", e);
+        }
+    }
+    let email_error = match args.emails.is_empty()
+    {
+        true => None,
+        false => match add_email(&mut engine, args.emails, args.smtp_server, args.email_from, &args.smtp_tls, args.smtp_credentials.as_deref())
+        {
+            Ok(email_error) => Some(email_error),
+            Err(e) => panic!("ERR: Couldn't set up email notifications: {}", e)
+        }
+    };
+    if let Some(filter) = args.only_clients.clone()
+    {
+        engine.set_client_filter(move |client| filter.contains(client));
+    }
+    engine.set_excluded_types(args.exclude_types.clone());
+    engine.set_latency_budget(args.latency_budget);
+    if args.dense_clients
+    {
+        engine.clients.make_dense();
+    }
+    let (wal_sync, wal_segment_bytes) = (args.wal_sync, args.wal_segment_bytes);
+    let mut wal = args.wal.as_ref().map(|path| {
+        match engine.replay_wal(Path::new(path), key.as_ref())
+        {
+            Ok(0) => (),
+            Ok(replayed) => eprintln!("WARN: Replayed {} rows from the write-ahead log {}", replayed, path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            //we panic here as the engine would miss the rows logged after its snapshot
+            Err(e) => panic!("ERR: Couldn't replay the write-ahead log {}: {}", path, e)
+        }
+        Wal::open(Path::new(path), wal_sync, wal_segment_bytes, key.clone()).unwrap_or_else(|e| panic!("ERR: Couldn't open the write-ahead log {}: {}", path, e))
+    });
+    //rows replayed from the log went to the outbox in the run that logged them
+    let mut outbox = args.outbox.as_ref().map(|path| {
+        engine.enable_outbox();
+        Outbox::open(Path::new(path)).unwrap_or_else(|e| panic!("ERR: Couldn't open the outbox {}: {}", path, e))
+    });
+    if args.trial_balance.is_some()
+    {
+        engine.enable_trial_balance();
+    }
+    //read before the run, so a mapping that can't be read doesn't waste it
+    let gl_mapping = match (&args.gl_journal, &args.gl_mapping)
+    {
+        (Some(_), Some(path)) => Some(GlMapping::read(open_file(path)).unwrap_or_else(|e| panic!("ERR: Couldn't read the general ledger mapping {}: {}", path, e))),
+        (Some(_), None) => panic!("ERR: --gl-journal needs --gl-mapping"),
+        (None, _) => None
+    };
+    if args.journal.is_some() || args.gl_journal.is_some()
+    {
+        engine.enable_journal();
+    }
+    let rates = match (&args.consolidate, &args.base_currency, &args.rates)
+    {
+        (Some(_), Some(_), Some(path)) => Some(RateTable::read(open_file(path)).unwrap_or_else(|e| panic!("ERR: Couldn't read the rates {}: {}", path, e))),
+        (Some(_), _, _) => panic!("ERR: --consolidate needs --base-currency and --rates"),
+        (None, _, _) => None
+    };
+    let mut feed = args.changes_only.then(|| ChangeFeed::from_engine(&mut engine));
+    let tenant = args.tenant.as_deref().unwrap_or(DEFAULT_TENANT);
+    let mut other_tenants = BTreeSet::new();
+    //files read from s3 aren't registered, as they would have to be downloaded twice
+    let hash = match args.path.starts_with("s3://")
+    {
+        true => None,
+        false => match content_hash(open_file(&args.path))
+        {
+            Ok(hash) => Some(hash),
+            Err(e) => panic!("ERR: Couldn't read {}: {}", args.path, e)
+        }
+    };
+    if let Some(file) = hash.as_deref().and_then(|h| engine.already_ingested(h))
+    {
+        match args.force
+        {
+            true => eprintln!("WARN: {} was already ingested as {}, processing it again as --force was given", args.path, file.path),
+            //we panic here as processing it again would apply every row twice
+            false => panic!("ERR: {} was already ingested as {}, pass --force to process it again", args.path, file.path)
+        }
+    }
+    if !args.merge_inputs.is_empty() && (args.checkpoint.is_some() || args.stream_after.is_some())
+    {
+        panic!("ERR: --merge-input can't be used with --checkpoint or --stream-after");
+    }
+    let offset = Cell::new(start);
+    let input = match (args.merge_inputs.is_empty(), args.checkpoint.is_some() || args.stream_after.is_some())
+    {
+        (false, _) => {
+            let (dialect, schema) = (&args.dialect, engine.policy.schema);
+            let sources = std::iter::once(&args.path).chain(&args.merge_inputs)
+                .map(|path| read_input(path, dialect, schema))
+                .collect();
+            Box::new(order_by_timestamp(sources, args.tie_break))
+        },
+        (true, true) => read_input_from(&args.path, &args.dialect, engine.policy.schema, &offset),
+        (true, false) => read_input(&args.path, &args.dialect, engine.policy.schema)
+    };
+    let records = args.stop_at.apply(input).filter(|r| match &r.tenant
+    {
+        Some(t) if t != tenant => {other_tenants.insert(t.clone()); false},
+        _ => true
+    });
+    let redis_prefix = match &args.tenant
+    {
+        Some(tenant) => format!("{}:{}", args.redis_prefix, tenant),
+        None => args.redis_prefix.clone()
+    };
+    if args.atomic && (args.redis.is_some() || args.dashboard)
+    {
+        panic!("ERR: --atomic can't be used with --redis or --dashboard");
+    }
+    if args.checkpoint.is_some() && (args.atomic || args.redis.is_some() || args.dashboard)
+    {
+        panic!("ERR: --checkpoint can't be used with --atomic, --redis or --dashboard");
+    }
+    if args.wal.is_some() && (args.checkpoint.is_some() || args.atomic || args.redis.is_some() || args.dashboard)
+    {
+        panic!("ERR: --wal can't be used with --checkpoint, --atomic, --redis or --dashboard");
+    }
+    if args.outbox.is_some() && args.redis.is_some()
+    {
+        panic!("ERR: --outbox can't be used with --redis");
+    }
+    if args.stream_after.is_some() && (args.atomic || args.redis.is_some() || args.dashboard || args.report || args.changes_only || args.output_filter != OutputFilter::default())
+    {
+        panic!("ERR: --stream-after can't be used with --atomic, --redis, --dashboard, --report, --changes-only or output filters");
+    }
+    //checkpoints are written on a thread of their own so the rows go on while they are
+    let checkpoints = args.checkpoint.as_ref().map(|dir| BackgroundCheckpoints::start(PathBuf::from(dir), key.clone()));
+    let (format, export_profile) = (args.format, args.export_profile);
+    let mut stream = args.stream_after.map(|quiet| {
+        let sink = account_sink(format, export_profile).unwrap_or_else(|e| panic!("ERR: Couldn't write the account report: {}", e));
+        (AccountStream::new(quiet), sink)
+    });
+    match &args.redis
+    {
+        Some(url) => if let Err(e) = apply_shared(url, &redis_prefix, &mut engine, records)
+        {
+            //we panic here as the shared state can't be trusted to be complete
+            panic!("ERR: Couldn't apply transactions in redis: {}", e);
+        },
+        None if args.dashboard => if let Err(e) = apply_with_dashboard(&mut engine, records)
+        {
+            panic!("ERR: Couldn't show the dashboard: {}", e);
+        },
+        None if args.atomic => {
+            let records: Vec<TxRecord> = records.collect();
+            if let Err(e) = engine.apply_batch_records(&records)
+            {
+                for (row, reason) in e.report.rejected()
+                {
+                    eprintln!("ERR: Row {} (client {}, tx {}) was rejected: {}", row + 1, records[row].client, records[row].tx, reason);
+                }
+                //we panic here as nothing was applied, so there is nothing to report
+                panic!("ERR: Applied none of the input, as {} rows were rejected", e.report.rejected().count());
+            }
+        },
+        None => {
+            for record in records
+            {
+                let client = record.client;
+                match wal.as_mut()
+                {
+                    Some(wal) => {
+                        if let Err(e) = engine.apply_logged(wal, record)
                         {
-                            c.resolve_transaction(&transaction_id);
+                            //we panic here as a row that isn't logged can't be applied
+                            panic!("ERR: Couldn't append to the write-ahead log: {}", e);
                         }
-                            
-                    } ,
-                    None => ()
-                };
-            },
-            TypeTx::Chargeback => {
-                match c.get_transaction(&transaction_id) {
-                    Some(transaction) => {
-                        if transaction.in_dispute
+                        //a segment was closed, fold it into the snapshot
+                        if wal.stats().segments > 1
                         {
-                            c.chargeback_transaction(&transaction_id);
+                            if let Err(e) = wal.compact(&engine, key.as_ref())
+                            {
+                                eprintln!("ERR: Couldn't compact the write-ahead log: {}", e);
+                            }
                         }
                     },
-                    None => ()
-                };
+                    None => engine.apply_record(record)
+                }
+                if let (Some(outbox), true) = (outbox.as_mut(), engine.pending_outbox() >= OUTBOX_BATCH)
+                {
+                    if let Err(e) = engine.flush_outbox(outbox)
+                    {
+                        eprintln!("ERR: Couldn't append to the outbox: {}", e);
+                    }
+                }
+                if let Some((stream, sink)) = stream.as_mut()
+                {
+                    if let Err(e) = stream.observe(&engine, client, offset.get(), sink.as_mut())
+                    {
+                        //we panic here as the rows streamed so far can't be followed up on
+                        panic!("ERR: Couldn't write the account report: {}", e);
+                    }
+                }
+                rows += 1;
+                if rows % args.checkpoint_every == 0
+                {
+                    write_checkpoint(&engine, checkpoints.as_ref(), &args.path, offset.get(), rows);
+                }
             }
         }
     }
-    write_output(clients);
-}
\ No newline at end of file
+    if let Some(outbox) = outbox.as_mut()
+    {
+        if let Err(e) = engine.flush_outbox(outbox)
+        {
+            eprintln!("ERR: Couldn't append to the outbox: {}", e);
+        }
+    }
+    //a what-if run stopped early hasn't processed the whole file
+    match hash
+    {
+        Some(hash) if args.stop_at == StopAt::default() => engine.mark_ingested(&args.path, &hash),
+        _ => {}
+    }
+    write_checkpoint(&engine, checkpoints.as_ref(), &args.path, offset.get(), rows);
+    if let Some(checkpoints) = checkpoints
+    {
+        for (checkpoint, e) in checkpoints.finish().1
+        {
+            eprintln!("ERR: Couldn't write the checkpoint after row {}: {}", checkpoint.rows, e);
+        }
+    }
+    engine.finish_run();
+    if let Some(e) = email_error.and_then(|email_error| email_error())
+    {
+        eprintln!("WARN: Couldn't mail the run summary: {}", e);
+    }
+    if !other_tenants.is_empty()
+    {
+        eprintln!("WARN: Skipped the rows of other tenants ({}), pick one with --tenant",
+            other_tenants.into_iter().collect::<Vec<_>>().join(", "));
+    }
+    for error in &engine.timestamp_errors
+    {
+        eprintln!("WARN: {}", error);
+    }
+    for slow in &engine.slow_rows
+    {
+        eprintln!("WARN: Row for client {}, tx {} ({}) took {}us, mostly {}", slow.client, slow.tx, slow.r#type.as_str(), slow.micros, slow.cause);
+    }
+    if args.only_clients.is_some() || !args.exclude_types.is_empty()
+    {
+        eprintln!("WARN: Skipped {} rows of other clients or excluded types", engine.skipped);
+    }
+    if let Some(path) = args.rejections
+    {
+        let written = File::create(&path).map_err(csv::Error::from)
+            .and_then(|f| write_rejections(&engine.rejections, f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write rejection report to {}", path);
+        }
+    }
+    if let Some(path) = args.disputes
+    {
+        let written = File::create(&path).map_err(csv::Error::from)
+            .and_then(|f| write_dispute_lifecycles(&engine.dispute_lifecycles(), f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write dispute report to {}", path);
+        }
+    }
+    if let Some(path) = args.held_ageing
+    {
+        let as_of = args.as_of.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX)));
+        let written = File::create(&path).map_err(csv::Error::from)
+            .and_then(|f| write_held_ageing(&engine.held_ageing(as_of).disputes, f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write held-funds ageing report to {}", path);
+        }
+    }
+    if let (Some(path), Some(base), Some(rates)) = (&args.consolidate, &args.base_currency, &rates)
+    {
+        let as_of = args.as_of.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| i64::try_from(d.as_secs()).unwrap_or(i64::MAX)));
+        let consolidation = engine.consolidate(base, rates, as_of);
+        for currency in consolidation.unconverted()
+        {
+            eprintln!("WARN: No rate from {} to {}, left out of the consolidated balances", currency, base);
+        }
+        let written = File::create(path).map_err(csv::Error::from).and_then(|f| write_consolidation(&consolidation, f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write consolidation to {}", path);
+        }
+    }
+    if let Some(path) = args.review_queue
+    {
+        let written = File::create(&path).map_err(csv::Error::from)
+            .and_then(|f| write_review_queue(&engine.review_queue, f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write review queue to {}", path);
+        }
+    }
+    if let Some(path) = args.ledger
+    {
+        if let Err(e) = write_ledger_file(&path, &engine.clients, args.export_profile.as_ref())
+        {
+            eprintln!("ERR: Couldn't write ledger to {}: {}", path, e);
+        }
+    }
+    if let Some(path) = &args.sub_accounts
+    {
+        let written = File::create(path).map_err(csv::Error::from).and_then(|f| write_sub_accounts(&engine.clients, f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write sub-accounts to {}", path);
+        }
+    }
+    if let (Some(path), Some(books)) = (&args.trial_balance, &engine.books)
+    {
+        let written = File::create(path).map_err(csv::Error::from).and_then(|f| write_trial_balance(books, f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write trial balance to {}", path);
+        }
+    }
+    if let (Some(path), Some(journal)) = (&args.journal, &engine.journal)
+    {
+        let written = File::create(path).map_err(csv::Error::from).and_then(|f| write_journal(&journal.postings, f));
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write journal to {}", path);
+        }
+    }
+    if let (Some(path), Some(mapping), Some(journal)) = (&args.gl_journal, &gl_mapping, &engine.journal)
+    {
+        let written = File::create(path).map_err(|e| GlError::Csv(e.into())).and_then(|f| write_gl_journal(&journal.postings, mapping, f));
+        if let Err(e) = written
+        {
+            eprintln!("ERR: Couldn't write general ledger journal to {}: {}", path, e);
+        }
+    }
+    //closed before the snapshot, so it starts the next run in the new period
+    if let Some(dir) = &args.close_period
+    {
+        match engine.close_period().map(|closed| closed.archive(Path::new(dir)))
+        {
+            Some(Ok(path)) => eprintln!("Closed the period, archived to {}", path.display()),
+            Some(Err(e)) => eprintln!("ERR: Couldn't archive the period to {}: {}", dir, e),
+            None => eprintln!("ERR: Couldn't close the period while a savepoint is open")
+        }
+    }
+    //taken before the snapshot, so it keeps the new sequence number
+    let changes = feed.as_mut().map(|feed| feed.next(&mut engine));
+    if let Some(path) = &args.snapshot
+    {
+        let written = File::create(path).map_err(SnapshotError::from)
+            .and_then(|f| engine.snapshot_to(io::BufWriter::new(f), key.as_ref()));
+        if let Err(e) = written
+        {
+            eprintln!("ERR: Couldn't write snapshot to {}: {}", path, e);
+        }
+    }
+    if let Some(url) = args.postgres
+    {
+        if let Err(e) = export_postgres(&url, args.postgres_accounts_table, args.postgres_ledger_table, &engine.clients)
+        {
+            eprintln!("ERR: Couldn't export to postgres: {}", e);
+        }
+    }
+    if args.report
+    {
+        let report = Report::new(&engine, args.top);
+        let written = match (args.report_format, args.format)
+        {
+            (Some(format), _) => write_summary(&report, format, io::stdout()),
+            (None, OutputFormat::Json) => serde_json::to_writer_pretty(io::stdout(), &report).map_err(io::Error::from).and_then(|_| writeln!(io::stdout())),
+            (None, _) => write!(io::stdout(), "{}", report)
+        };
+        if written.is_err()
+        {
+            eprintln!("ERR: Couldn't write the report");
+        }
+        fail_on_warnings(args.fail_on_warn, report.warnings.len());
+        return;
+    }
+    let warnings = anomalies(&engine);
+    for warning in &warnings
+    {
+        eprintln!("WARN: {}", warning);
+    }
+    fail_on_warnings(args.fail_on_warn, warnings.len());
+    if let Some(changes) = changes
+    {
+        if write_changes(&changes, io::stdout()).is_err()
+        {
+            eprintln!("ERR: Couldn't write the changed accounts");
+        }
+        return;
+    }
+    if let Some((stream, mut sink)) = stream
+    {
+        if let Err(e) = stream.finish(&engine, sink.as_mut())
+        {
+            eprintln!("ERR: Couldn't write the account report: {}", e);
+        }
+        return;
+    }
+    let output_filter = args.output_filter;
+    let written = account_sink(args.format, args.export_profile)
+        .and_then(|mut sink| write_filtered_output(&engine.clients, &output_filter, sink.as_mut()));
+    if let Err(e) = written
+    {
+        eprintln!("ERR: Couldn't write the account report: {}", e);
+    }
+}
+
+/// Fails the run if there were warnings and --fail-on-warn is set
+///
+/// # Arguments
+///
+/// * 'fail_on_warn' - Whether --fail-on-warn is set
+/// * 'warnings' - How many warnings the report has
+fn fail_on_warnings(fail_on_warn: bool, warnings: usize)
+{
+    if fail_on_warn && warnings > 0
+    {
+        panic!("ERR: {} warnings, failing as --fail-on-warn is set", warnings);
+    }
+}
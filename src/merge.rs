@@ -0,0 +1,186 @@
+use std::fmt;
+use crate::{checked_add, Account, Amount, Engine};
+
+///
+/// Why two clients couldn't be merged
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError
+{
+    /// A client can't be merged into itself
+    SameClient,
+    /// The client has no account
+    Unknown(u16),
+    /// The client was already merged into another
+    Closed(u16),
+    /// Transaction IDs both clients have in their history, ordered
+    Collision(Vec<u32>),
+    /// The balances add up to more than an account can hold
+    Overflow,
+}
+impl fmt::Display for MergeError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            MergeError::SameClient => write!(f, "a client can't be merged into itself"),
+            MergeError::Unknown(client) => write!(f, "client {} has no account", client),
+            MergeError::Closed(client) => write!(f, "client {} is closed", client),
+            MergeError::Collision(ids) => write!(f, "both clients have transactions {:?}", ids),
+            MergeError::Overflow => write!(f, "the merged balances are too large"),
+        }
+    }
+}
+
+impl Engine
+{
+    /// Merges a client into another, E.G. when duplicate customer records are consolidated
+    /// upstream, returning the account it was merged into
+    ///
+    /// The balances are summed, sub-account by sub-account, and the history moved over,
+    /// so its deposits can still be disputed under the client merged into. Nothing is changed if both have a transaction
+    /// under the same ID. The account merged into is locked or frozen if either was, and
+    /// neither keeps its last change, so neither can be undone. The client merged from is
+    /// left closed with nothing in it, and every later transaction for it is rejected
+    ///
+    /// # Arguments
+    ///
+    /// * 'from' - The client merged, and closed
+    /// * 'into' - The client merged into
+    pub fn merge_clients(&mut self, from: u16, into: u16) -> Result<Account, MergeError>
+    {
+        if from == into {return Err(MergeError::SameClient)}
+        let (a, b) = match (self.clients.get(&from), self.clients.get(&into))
+        {
+            (None, _) => return Err(MergeError::Unknown(from)),
+            (_, None) => return Err(MergeError::Unknown(into)),
+            (Some(a), _) if a.closed => return Err(MergeError::Closed(from)),
+            (_, Some(b)) if b.closed => return Err(MergeError::Closed(into)),
+            (Some(a), Some(b)) => (a, b)
+        };
+        let mut shared: Vec<u32> = a.history.keys().filter(|id| b.history.contains_key(id)).copied().collect();
+        if !shared.is_empty()
+        {
+            shared.sort();
+            return Err(MergeError::Collision(shared));
+        }
+        let sum = |x: Amount, y: Amount| checked_add(x, y).map_err(|_| MergeError::Overflow);
+        let merged = Account {
+            client: into,
+            available: sum(a.acc.available, b.acc.available)?,
+            held: sum(a.acc.held, b.acc.held)?,
+            total: sum(a.acc.total, b.acc.total)?,
+            pending: sum(a.acc.pending, b.acc.pending)?,
+            locked: a.acc.locked || b.acc.locked,
+        };
+        let (before_from, before_into) = (a.acc.clone(), b.acc.clone());
+        self.touch(from);
+        self.touch(into);
+        let (history, sub_accounts, frozen) = match self.clients.get_mut(&from)
+        {
+            Some(c) => {
+                c.acc = Account::new(from);
+                c.last_change = None;
+                c.closed = true;
+                (std::mem::take(&mut c.history), std::mem::take(&mut c.sub_accounts), c.frozen)
+            },
+            None => return Err(MergeError::Unknown(from))
+        };
+        if let Some(c) = self.clients.get_mut(&into)
+        {
+            c.acc = merged.clone();
+            c.history.extend(history);
+            for (name, sub) in sub_accounts
+            {
+                c.sub_accounts.entry(name).or_default().add(&sub);
+            }
+            c.frozen = c.frozen || frozen;
+            c.last_change = None;
+        }
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            for (due, pending) in self.settlements.iter().filter(|(_, pending)| pending.iter().any(|(client, _)| *client == from))
+            {
+                saved.settlements.keep(due, Some(pending));
+            }
+            for client in [from, into]
+            {
+                saved.review_queue.keep(&client, self.review_queue.get(&client));
+                saved.deposit_windows.keep(&client, self.deposit_windows.get(&client));
+            }
+        }
+        for pending in self.settlements.values_mut().flatten().filter(|(client, _)| *client == from)
+        {
+            pending.0 = into;
+        }
+        if let Some(mut entry) = self.review_queue.remove(&from)
+        {
+            entry.client = into;
+            self.review_queue.entry(into).or_insert(entry);
+        }
+        if let Some(window) = self.deposit_windows.remove(&from)
+        {
+            self.deposit_windows.entry(into).or_default().extend(window);
+        }
+        self.record_balance_change(from, 0, "merge", &before_from);
+        self.record_balance_change(into, 0, "merge", &before_into);
+        Ok(merged)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, RejectReason, Tx, TypeTx};
+
+    /// Client 1 with 50 available, client 2 with 20 held in dispute under tx 2, and
+    /// client 3 with a deposit under tx 1, same as client 1
+    fn engine() -> Engine
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.enable_journal();
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 2, 2, None));
+        engine.apply(Tx::new(TypeTx::Deposit, 3, 1, Some(Amount::from_minor(10000))));
+        engine
+    }
+
+    #[test]
+    fn clients_merge_into_one()
+    {
+        let mut engine = engine();
+        let merged = engine.merge_clients(2, 1).unwrap();
+        assert_eq!((merged.available, merged.held, merged.total),(Amount::from_minor(50000), Amount::from_minor(20000), Amount::from_minor(70000)));
+        assert_eq!(engine.clients[&1].acc,merged);
+        assert!(engine.journal_mismatches().is_empty());
+    }
+    #[test]
+    fn merged_client_closed()
+    {
+        let mut engine = engine();
+        engine.merge_clients(2, 1).unwrap();
+        assert!(engine.clients[&2].closed && engine.clients[&2].history.is_empty());
+        assert_eq!(engine.clients[&2].acc,Account::new(2));
+        assert_eq!(engine.merge_clients(2, 3),Err(MergeError::Closed(2)));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 4, Some(Amount::from_minor(10000))));
+        assert_eq!(engine.rejections.last().map(|r| r.reason),Some(RejectReason::AccountClosed));
+    }
+    #[test]
+    fn disputes_carried_over()
+    {
+        let mut engine = engine();
+        engine.merge_clients(2, 1).unwrap();
+        engine.apply(Tx::new(TypeTx::Resolve, 1, 2, None));
+        assert_eq!(engine.clients[&1].acc.available,Amount::from_minor(70000));
+    }
+    #[test]
+    fn bad_merges_refused()
+    {
+        let mut engine = engine();
+        assert_eq!(engine.merge_clients(3, 1),Err(MergeError::Collision(vec![1])));
+        assert_eq!(engine.merge_clients(1, 1),Err(MergeError::SameClient));
+        assert_eq!(engine.merge_clients(4, 1),Err(MergeError::Unknown(4)));
+        assert_eq!(engine.clients[&3].acc.total,Amount::from_minor(10000));
+    }
+}
@@ -0,0 +1,74 @@
+use std::{collections::HashMap, io};
+use serde::{Deserialize, Serialize};
+use crate::Amount;
+
+///
+/// What is known about a client from outside the transaction stream
+///
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ClientMetadata
+{
+    pub client: u16,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The account tier, E.G. "verified"
+    #[serde(default)]
+    pub tier: Option<String>,
+    /// How far available may go below zero through withdrawals
+    #[serde(default)]
+    pub credit_limit: Option<Amount>,
+    /// The only currency the client transacts in, E.G. "EUR"
+    #[serde(default)]
+    pub base_currency: Option<String>,
+}
+
+/// Reads the client metadata registry, a csv with a client column and any of
+/// name, tier, credit_limit and base_currency
+///
+/// A client listed twice keeps its last row
+///
+/// # Arguments
+///
+/// * 'input' - The registry as csv, with a header row
+pub fn read_metadata<R: io::Read>(input: R) -> csv::Result<HashMap<u16, ClientMetadata>>
+{
+    let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+    let mut metadata = HashMap::new();
+    for row in rdr.deserialize()
+    {
+        let row: ClientMetadata = row?;
+        metadata.insert(row.client, row);
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EnginePolicy, RejectReason, Tx, TypeTx};
+
+    #[test]
+    fn registry_limits()
+    {
+        let registry = "client,name,tier,credit_limit,base_currency\n1,Ada,premium,10.0,EUR\n2,Bob,,,\n";
+        let metadata = read_metadata(registry.as_bytes()).unwrap();
+        assert_eq!(metadata[&1].name.as_deref(),Some("Ada"));
+        assert_eq!(metadata[&2].credit_limit,None);
+        assert!(read_metadata("client,name\nx,Ada\n".as_bytes()).is_err());
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.load_metadata(metadata);
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 2, Some(Amount::from_minor(120000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 3, Some(Amount::from_minor(200000))));
+        assert_eq!(engine.clients[&1].acc.available,Amount::from_minor(-70000));
+
+        let mut usd = Tx::new(TypeTx::Deposit, 1, 4, Some(Amount::from_minor(10000)));
+        usd.currency = Some("USD".to_string());
+        engine.apply(usd);
+        assert_eq!(engine.rejections[0].reason,RejectReason::CurrencyMismatch);
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 5, Some(Amount::from_minor(10000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 2, 6, Some(Amount::from_minor(20000))));
+        assert_eq!(engine.clients[&2].acc.available,Amount::from_minor(10000));
+    }
+}
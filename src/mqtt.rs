@@ -0,0 +1,128 @@
+use std::{fmt, time::Duration};
+use rumqttc::{Client, ClientError, Connection, ConnectionError, Event, MqttOptions, OptionError, Packet, QoS, RecvTimeoutError};
+use crate::{BusStats, Delivery, Engine};
+
+/// How many requests to the broker can be queued before the client blocks
+const REQUEST_CAPACITY: usize = 64;
+
+///
+/// Something went wrong connecting to the broker or taking messages off it
+///
+#[derive(Debug)]
+pub enum MqttError
+{
+    /// The engine doesn't dedup transaction IDs globally, so redelivered withdrawals would be taken twice
+    NoDedup,
+    /// The broker url couldn't be read
+    Url(OptionError),
+    Client(ClientError),
+    Connection(Box<ConnectionError>),
+    /// The connection to the broker was closed for good
+    Closed,
+}
+impl fmt::Display for MqttError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            MqttError::NoDedup => write!(f, "the engine needs global dedup to apply redelivered messages once"),
+            MqttError::Url(e) => write!(f, "invalid broker url: {}", e),
+            MqttError::Client(e) => write!(f, "{}", e),
+            MqttError::Connection(e) => write!(f, "{}", e),
+            MqttError::Closed => write!(f, "the connection to the broker is closed"),
+        }
+    }
+}
+impl From<ClientError> for MqttError
+{
+    fn from(e: ClientError) -> Self {
+        MqttError::Client(e)
+    }
+}
+impl From<ConnectionError> for MqttError
+{
+    fn from(e: ConnectionError) -> Self {
+        MqttError::Connection(Box::new(e))
+    }
+}
+
+///
+/// Applies the json transactions published to an MQTT topic, each acknowledged only
+/// once it is applied
+///
+/// The topic is subscribed at least once, in a session the broker keeps, so messages
+/// not acknowledged before a crash or disconnect are delivered again. Those are left
+/// alone by Engine::apply_message, which is why the engine must dedup globally
+///
+pub struct MqttIngest
+{
+    pub engine: Engine,
+    client: Client,
+    connection: Connection,
+    /// Counts of the messages taken off the topic
+    pub stats: BusStats,
+}
+impl MqttIngest
+{
+    /// Returns an ingest subscribed to the topic, which connects once first polled
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine messages are applied to, with global dedup on
+    /// * 'url' - The broker, E.G. mqtt://localhost:1883?client_id=engine-1
+    /// * 'topic' - The topic, or filter of topics, transactions are published to
+    pub fn connect(engine: Engine, url: &str, topic: &str) -> Result<MqttIngest, MqttError>
+    {
+        if engine.dedup_stats().is_none() {return Err(MqttError::NoDedup)}
+        let mut options = MqttOptions::parse_url(url).map_err(MqttError::Url)?;
+        options.set_manual_acks(true).set_clean_session(false);
+        let (client, connection) = Client::new(options, REQUEST_CAPACITY);
+        client.subscribe(topic, QoS::AtLeastOnce)?;
+        Ok(MqttIngest { engine, client, connection, stats: BusStats::default() })
+    }
+    /// Waits for the next message and applies it, then acknowledges it
+    ///
+    /// Returns None if no message came in time. A connection error is returned as is,
+    /// and polling again reconnects
+    ///
+    /// # Arguments
+    ///
+    /// * 'timeout' - How long to wait for a message
+    pub fn poll(&mut self, timeout: Duration) -> Result<Option<Delivery>, MqttError>
+    {
+        let event = match self.connection.recv_timeout(timeout)
+        {
+            Ok(event) => event?,
+            Err(RecvTimeoutError::Timeout) => return Ok(None),
+            Err(RecvTimeoutError::Disconnected) => return Err(MqttError::Closed)
+        };
+        let Event::Incoming(Packet::Publish(publish)) = event else {return Ok(None)};
+        let delivery = self.engine.apply_message(&publish.payload);
+        self.stats.count(&delivery);
+        self.client.ack(&publish)?;
+        Ok(Some(delivery))
+    }
+    /// Disconnects from the broker and hands the engine back
+    pub fn finish(self) -> Engine
+    {
+        let _ = self.client.disconnect();
+        self.engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, GlobalDedup};
+
+    #[test]
+    fn needs_global_dedup()
+    {
+        let url = "mqtt://localhost:1883?client_id=engine-1";
+        assert!(matches!(MqttIngest::connect(Engine::new(EnginePolicy::default()), url, "transactions"), Err(MqttError::NoDedup)));
+        let policy = EnginePolicy{global_dedup:Some(GlobalDedup::default()), ..EnginePolicy::default()};
+        assert!(matches!(MqttIngest::connect(Engine::new(policy.clone()), "http://localhost", "transactions"), Err(MqttError::Url(_))));
+        let ingest = MqttIngest::connect(Engine::new(policy), url, "transactions").unwrap();
+        assert_eq!(ingest.stats,BusStats::default());
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+use serde::Serialize;
+use crate::{Amount, Rejection};
+
+///
+/// Something that happened to an account which someone outside the engine may want to hear about
+///
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "event")]
+pub enum Notification
+{
+    /// The account was locked and takes no more transactions
+    #[serde(rename = "account_locked")]
+    AccountLocked { client: u16 },
+    /// A disputed transaction was charged back
+    #[serde(rename = "chargeback")]
+    Chargeback { client: u16, tx: u32, amount: Amount },
+    /// The account was frozen, as too many of its latest deposits were charged back
+    #[serde(rename = "account_frozen")]
+    AccountFrozen { client: u16, chargebacks: usize, deposits: usize },
+    /// The account was frozen, as the client failed sanctions or KYC screening
+    #[serde(rename = "screening_failed")]
+    ScreeningFailed { client: u16, tx: u32, reason: String },
+    /// The account was frozen, as the policy hook said so after the transaction
+    #[serde(rename = "policy_frozen")]
+    PolicyFrozen { client: u16, tx: u32 },
+    /// The whole input was processed, sent once at the end of the run
+    #[serde(rename = "run_completed")]
+    RunCompleted { rows: u64, rejected: usize, locked: Vec<u16> },
+}
+impl fmt::Display for Notification
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Notification::AccountLocked { client } => write!(f, "client {}: account locked", client),
+            Notification::Chargeback { client, tx, amount } => write!(f, "client {}: tx {} charged back ({})", client, tx, amount),
+            Notification::AccountFrozen { client, chargebacks, deposits } => write!(f, "client {}: account frozen, {} of the latest {} deposits charged back", client, chargebacks, deposits),
+            Notification::ScreeningFailed { client, tx, reason } => write!(f, "client {}: account frozen by screening at tx {}: {}", client, tx, reason),
+            Notification::PolicyFrozen { client, tx } => write!(f, "client {}: account frozen by the policy at tx {}", client, tx),
+            Notification::RunCompleted { rows, rejected, locked } => {
+                write!(f, "run completed: {} rows processed, {} rejected, {} locked accounts", rows, rejected, locked.len())?;
+                match locked.is_empty()
+                {
+                    true => Ok(()),
+                    false => write!(f, " ({})", locked.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "))
+                }
+            }
+        }
+    }
+}
+
+///
+/// Told about every notification as the engine applies transactions
+///
+/// Implementations should return quickly, as they are called in the middle of processing
+///
+pub trait Notifier: Send
+{
+    /// Passes on a single notification
+    ///
+    /// # Arguments
+    ///
+    /// * 'notification' - What happened
+    fn notify(&mut self, notification: &Notification);
+}
+
+///
+/// Keeps every notification in memory, E.G. for tests or to hand them on later
+///
+#[derive(Debug, Default, Clone)]
+pub struct CollectNotifier
+{
+    pub notifications: std::sync::Arc<std::sync::Mutex<Vec<Notification>>>,
+}
+impl Notifier for CollectNotifier
+{
+    fn notify(&mut self, notification: &Notification) {
+        if let Ok(mut notifications) = self.notifications.lock()
+        {
+            notifications.push(notification.clone());
+        }
+    }
+}
+
+///
+/// Told about every transaction the engine rejects, as it rejects it
+///
+/// Kept apart from the notifiers, as rejections can be as frequent as the rows themselves
+/// and aren't kept in the audit trail. Implementations should return quickly
+///
+pub trait RejectionListener: Send
+{
+    /// Passes on a single rejection
+    ///
+    /// # Arguments
+    ///
+    /// * 'rejection' - The rejection, as it is added to the report
+    fn rejected(&mut self, rejection: &Rejection);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn chargeback_notifies()
+    {
+        let collect = CollectNotifier::default();
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.add_notifier(Box::new(collect.clone()));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(15000))));
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 1, None));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 1, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 1, None));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 1, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 1, None));
+        let notifications = collect.notifications.lock().unwrap();
+        assert_eq!(*notifications,vec![
+            Notification::Chargeback { client: 1, tx: 1, amount: Amount::from_minor(15000) },
+            Notification::AccountLocked { client: 1 },
+        ]);
+        assert_eq!(serde_json::to_string(&notifications[0]).unwrap(),
+            r#"{"event":"chargeback","client":1,"tx":1,"amount":"1.5"}"#);
+    }
+}
@@ -0,0 +1,159 @@
+use std::{fmt, io, sync::Arc};
+use bytes::{Buf, Bytes};
+use futures::{StreamExt, stream::BoxStream};
+use object_store::{ObjectStore, aws::AmazonS3Builder, path::Path};
+use tokio::runtime::Runtime;
+
+///
+/// The location of an object couldn't be used
+///
+#[derive(Debug)]
+pub enum SourceError
+{
+    /// The url isn't of the form s3://bucket/key
+    Url(String),
+    Store(object_store::Error),
+    Io(io::Error),
+}
+impl fmt::Display for SourceError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            SourceError::Url(url) => write!(f, "invalid object url '{}', expected s3://bucket/key", url),
+            SourceError::Store(e) => write!(f, "{}", e),
+            SourceError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl From<object_store::Error> for SourceError
+{
+    fn from(e: object_store::Error) -> Self {
+        SourceError::Store(e)
+    }
+}
+impl From<io::Error> for SourceError
+{
+    fn from(e: io::Error) -> Self {
+        SourceError::Io(e)
+    }
+}
+
+/// Whether a path given on the command line points to object storage
+pub fn is_object_url(path: &str) -> bool
+{
+    path.starts_with("s3://")
+}
+
+///
+/// A single object in object storage, that can be read as it is downloaded
+///
+pub struct ObjectStoreSource
+{
+    store: Arc<dyn ObjectStore>,
+    path: Path,
+    runtime: Runtime,
+}
+impl ObjectStoreSource
+{
+    /// Returns a source for an object in any store
+    ///
+    /// # Arguments
+    ///
+    /// * 'store' - The store holding the object
+    /// * 'path' - Where the object is in the store
+    pub fn new(store: Arc<dyn ObjectStore>, path: Path) -> Result<ObjectStoreSource, SourceError>
+    {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(ObjectStoreSource { store, path, runtime })
+    }
+    /// Returns a source for an s3 url, E.G. "s3://bucket/input/transactions.csv"
+    ///
+    /// Credentials and the region are taken from the usual AWS_* environment variables
+    ///
+    /// # Arguments
+    ///
+    /// * 'url' - The url of the object
+    pub fn from_url(url: &str) -> Result<ObjectStoreSource, SourceError>
+    {
+        let (bucket, key) = match url.strip_prefix("s3://").and_then(|rest| rest.split_once('/'))
+        {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => (bucket, key),
+            _ => return Err(SourceError::Url(url.to_string()))
+        };
+        let store = AmazonS3Builder::from_env().with_bucket_name(bucket).build()?;
+        ObjectStoreSource::new(Arc::new(store), Path::from(key))
+    }
+    /// Starts downloading the object, returning a reader that yields it a
+    /// chunk at a time without keeping the whole object around
+    pub fn open(self) -> Result<ObjectReader, SourceError>
+    {
+        let result = self.runtime.block_on(self.store.get(&self.path))?;
+        Ok(ObjectReader { runtime: self.runtime, stream: result.into_stream(), chunk: Bytes::new() })
+    }
+}
+
+///
+/// Reads an object as it is downloaded
+///
+pub struct ObjectReader
+{
+    runtime: Runtime,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    chunk: Bytes,
+}
+impl io::Read for ObjectReader
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>
+    {
+        while self.chunk.is_empty()
+        {
+            match self.runtime.block_on(self.stream.next())
+            {
+                Some(Ok(bytes)) => self.chunk = bytes,
+                Some(Err(e)) => return Err(io::Error::other(e)),
+                None => return Ok(0)
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk[..n]);
+        self.chunk.advance(n);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use crate::{Dialect, SchemaMode};
+
+    #[test]
+    fn read_from_store()
+    {
+        let store = Arc::new(InMemory::new());
+        let path = Path::from("input/transactions.csv");
+        let source = ObjectStoreSource::new(store.clone(), path.clone()).unwrap();
+        let csv = "type,client,tx,amount\ndeposit,1,1,1.5\nwithdrawal,1,2,0.5\n";
+        source.runtime.block_on(store.put(&path, Bytes::from(csv).into())).unwrap();
+
+        let dialect = Dialect::default();
+        let records: Vec<_> = dialect.read_records(source.open().unwrap(), SchemaMode::Strict).unwrap().collect();
+        assert_eq!(records.len(),2);
+        assert_eq!(records[1].amount.as_deref(),Some("0.5"));
+    }
+    #[test]
+    fn missing_object()
+    {
+        let source = ObjectStoreSource::new(Arc::new(InMemory::new()), Path::from("nope.csv")).unwrap();
+        assert!(source.open().is_err());
+    }
+    #[test]
+    fn object_urls()
+    {
+        assert!(is_object_url("s3://bucket/key.csv"));
+        assert!(!is_object_url("transactions.csv"));
+        assert!(matches!(ObjectStoreSource::from_url("s3://bucket"), Err(SourceError::Url(_))));
+        assert!(matches!(ObjectStoreSource::from_url("s3:///key.csv"), Err(SourceError::Url(_))));
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::HashSet, fmt, io};
+use serde::Deserialize;
+use crate::{engine::configure, Amount, Client, ClientTransaction, Engine, PeriodBalance, TypeTx, SYNTHETIC_TX_MIN};
+
+/// The memo of the disputed deposits opening held balances are imported as
+pub const OPENING_HELD_MEMO: &str = "opening balance held";
+
+///
+/// The balances a client ended with in the system the engine takes over from
+///
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct OpeningBalance
+{
+    pub client: u16,
+    pub available: Amount,
+    #[serde(default)]
+    pub held: Option<Amount>,
+    #[serde(default)]
+    pub locked: Option<bool>,
+    /// The transaction the funds are held for in the prior system, so a resolve or
+    /// chargeback of it releases them; a synthetic ID is given to them if None
+    #[serde(default)]
+    pub held_tx: Option<u32>,
+}
+
+/// Reads the opening balances, a csv with client and available columns, and optionally
+/// held, locked and held_tx
+///
+/// # Arguments
+///
+/// * 'input' - The balances as csv, with a header row
+pub fn read_opening_balances<R: io::Read>(input: R) -> csv::Result<Vec<OpeningBalance>>
+{
+    let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+    rdr.deserialize().collect()
+}
+
+///
+/// Why the opening balances couldn't be loaded
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpeningBalanceError
+{
+    /// The client already has an account, or is listed twice
+    Duplicate(u16),
+    /// The held balance is negative
+    NegativeHeld(u16),
+    /// The available and held balances add up to more than the total can hold
+    Overflow(u16),
+    /// The held funds are given a transaction ID kept for the engine's own, see SYNTHETIC_TX_MIN
+    ReservedTx(u16),
+}
+impl fmt::Display for OpeningBalanceError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            OpeningBalanceError::Duplicate(client) => write!(f, "client {} already has an account", client),
+            OpeningBalanceError::NegativeHeld(client) => write!(f, "client {} has a negative held balance", client),
+            OpeningBalanceError::Overflow(client) => write!(f, "the balances of client {} are too large", client),
+            OpeningBalanceError::ReservedTx(client) => write!(f, "the held funds of client {} are given a reserved transaction ID", client),
+        }
+    }
+}
+
+impl Engine
+{
+    /// Opens an account for every client with the balances it ended with in a prior
+    /// system, returning how many, so processing carries on from there rather than zero
+    ///
+    /// Every row is checked before any account is opened. The funds held are taken into
+    /// the history as a disputed deposit under held_tx, or a synthetic ID if it isn't
+    /// given, with OPENING_HELD_MEMO as its memo, so a resolve or chargeback of it
+    /// releases them, and the balances count as the opening balances of the period
+    ///
+    /// # Arguments
+    ///
+    /// * 'balances' - The balances, as read by read_opening_balances
+    pub fn load_opening_balances(&mut self, balances: &[OpeningBalance]) -> Result<usize, OpeningBalanceError>
+    {
+        let mut seen = HashSet::new();
+        for row in balances
+        {
+            if self.clients.contains_key(&row.client) || !seen.insert(row.client)
+            {
+                return Err(OpeningBalanceError::Duplicate(row.client));
+            }
+            let held = row.held.unwrap_or(Amount::ZERO);
+            if held.is_negative() {return Err(OpeningBalanceError::NegativeHeld(row.client))}
+            if row.available.checked_add(held).is_none() {return Err(OpeningBalanceError::Overflow(row.client))}
+            if row.held_tx.is_some_and(|tx| tx >= SYNTHETIC_TX_MIN) {return Err(OpeningBalanceError::ReservedTx(row.client))}
+        }
+        for row in balances
+        {
+            self.touch(row.client);
+            let mut c = Client::new(row.client);
+            configure(&mut c, &self.metadata, &self.policy);
+            c.acc.available = row.available;
+            c.acc.held = row.held.unwrap_or(Amount::ZERO);
+            c.acc.total = c.acc.available.checked_add(c.acc.held).unwrap_or(Amount::MAX);
+            c.acc.locked = row.locked.unwrap_or(false);
+            let held_tx = match c.acc.held > Amount::ZERO
+            {
+                true => row.held_tx.or_else(|| self.synthetic_tx()),
+                false => None
+            };
+            if let Some(tx) = held_tx
+            {
+                let mut entry = ClientTransaction::new(c.acc.held, Some(OPENING_HELD_MEMO));
+                entry.in_dispute = true;
+                entry.dispute_chain.push(TypeTx::Dispute);
+                c.history.insert(tx, entry);
+                if let Some(dedup) = self.dedup.as_mut()
+                {
+                    dedup.insert(tx);
+                }
+            }
+            self.period.opening.insert(row.client, PeriodBalance::from(&c.acc));
+            self.clients.insert(row.client, c);
+        }
+        Ok(balances.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn carries_on_from_opening_balances()
+    {
+        let balances = read_opening_balances("client,available,held,locked\n1,10.0,2.5,false\n2,-1.0,,true\n3,4.0,,\n".as_bytes()).unwrap();
+        let mut engine = Engine::new(EnginePolicy::default());
+        assert_eq!(engine.load_opening_balances(&balances),Ok(3));
+        assert_eq!((engine.clients[&1].acc.total, engine.clients[&2].acc.locked, engine.clients[&3].acc.held),(Amount::from_minor(125000), true, Amount::ZERO));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 1, Some(Amount::from_minor(40000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(10000))));
+        assert_eq!(engine.clients[&1].acc.available,Amount::from_minor(60000));
+        assert_eq!(engine.clients[&2].acc.available,Amount::from_minor(-10000));
+        assert_eq!(engine.period.opening[&1].total,Amount::from_minor(125000));
+
+        assert_eq!(engine.load_opening_balances(&balances[2..]),Err(OpeningBalanceError::Duplicate(3)));
+        let negative = read_opening_balances("client,available,held\n4,1.0,-1.0\n5,1.0,\n".as_bytes()).unwrap();
+        assert_eq!(engine.load_opening_balances(&negative),Err(OpeningBalanceError::NegativeHeld(4)));
+        assert!(!engine.clients.contains_key(&5));
+        assert!(read_opening_balances("client,held\n1,1.0\n".as_bytes()).is_err());
+    }
+    #[test]
+    fn held_balances_released()
+    {
+        let balances = read_opening_balances("client,available,held,held_tx\n1,10.0,2.5,\n2,1.0,1.0,7\n3,1.0,,\n".as_bytes()).unwrap();
+        let mut engine = Engine::new(EnginePolicy::default());
+        assert_eq!(engine.load_opening_balances(&balances),Ok(3));
+        assert_eq!(engine.clients[&1].history[&u32::MAX].memo.as_deref(),Some(OPENING_HELD_MEMO));
+        assert!(engine.clients[&3].history.is_empty());
+
+        engine.apply(Tx::new(TypeTx::Resolve, 1, u32::MAX, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 2, 7, None));
+        assert_eq!((engine.clients[&1].acc.available, engine.clients[&1].acc.held),(Amount::from_minor(125000), Amount::ZERO));
+        assert_eq!((engine.clients[&2].acc.total, engine.clients[&2].acc.held, engine.clients[&2].acc.locked),(Amount::from_minor(10000), Amount::ZERO, true));
+
+        let reserved = read_opening_balances(format!("client,available,held,held_tx\n4,1.0,1.0,{}\n", u32::MAX).as_bytes()).unwrap();
+        assert_eq!(engine.load_opening_balances(&reserved),Err(OpeningBalanceError::ReservedTx(4)));
+    }
+}
@@ -0,0 +1,169 @@
+use std::str::FromStr;
+use crate::{generate::Rng, TxRecord};
+
+///
+/// How rows of different sources with the same timestamp are ordered when the sources
+/// are merged by timestamp
+///
+/// Rows of the same source always keep their order, so the rule only picks which
+/// source goes first, and the same rule always gives the same order, so a replay of
+/// the same sources is applied the same way
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TieBreak
+{
+    /// The source given first goes first
+    #[default]
+    Source,
+    /// The sources go in an order drawn from the seed and the timestamp, so no source
+    /// always goes first, and then in the order given
+    Seeded(u64),
+}
+impl FromStr for TieBreak
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':')
+        {
+            None if s == "source" => Ok(TieBreak::Source),
+            Some(("seed", seed)) => seed.parse().map(TieBreak::Seeded).map_err(|_| format!("invalid seed '{}'", seed)),
+            _ => Err(format!("unknown tie-break '{}', expected source or seed:<n>", s))
+        }
+    }
+}
+impl TieBreak
+{
+    /// Where the source goes among those with a row at the timestamp, lowest first;
+    /// sources that draw the same go in the order given
+    ///
+    /// # Arguments
+    ///
+    /// * 'timestamp' - The timestamp the rows share
+    /// * 'source' - The position of the source in the order given
+    fn rank(&self, timestamp: i64, source: usize) -> u64
+    {
+        match self
+        {
+            TieBreak::Source => 0,
+            TieBreak::Seeded(seed) => Rng(seed ^ (timestamp as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ (source as u64).rotate_left(32)).next()
+        }
+    }
+}
+
+/// Merges the rows of several sources into a single run ordered by timestamp, ties
+/// broken by the rule
+///
+/// The sources are read side by side as the run goes, a row at a time, and are expected
+/// to be ordered by timestamp each; rows of the same source always keep their order, so
+/// a row with an earlier timestamp than the one before it still goes after it. A row
+/// without a timestamp, or with one that can't be read, takes the timestamp of the row
+/// before it in its source; those at the start of a source go before every timestamped row
+///
+/// # Arguments
+///
+/// * 'sources' - The rows of each source, in the order given, which is their priority
+/// * 'tie_break' - How rows of different sources with the same timestamp are ordered
+pub fn order_by_timestamp<I: Iterator<Item = TxRecord>>(sources: Vec<I>, tie_break: TieBreak) -> TimestampMerge<I>
+{
+    let sources = sources.into_iter().map(|rows| MergeSource { rows, next: None, timestamp: i64::MIN }).collect();
+    TimestampMerge { sources, tie_break }
+}
+
+///
+/// The merged run of order_by_timestamp, reading its sources as it goes
+///
+pub struct TimestampMerge<I: Iterator<Item = TxRecord>>
+{
+    sources: Vec<MergeSource<I>>,
+    tie_break: TieBreak,
+}
+///
+/// A source of the merge, along with the row read ahead from it
+///
+struct MergeSource<I>
+{
+    rows: I,
+    /// The row read ahead, None once the source is used up or before it is read
+    next: Option<TxRecord>,
+    /// The timestamp of the row read ahead, or of the last one that had one
+    timestamp: i64,
+}
+impl<I: Iterator<Item = TxRecord>> Iterator for TimestampMerge<I>
+{
+    type Item = TxRecord;
+    fn next(&mut self) -> Option<TxRecord> {
+        for source in self.sources.iter_mut().filter(|s| s.next.is_none())
+        {
+            source.next = source.rows.next();
+            if let Some(t) = source.next.as_ref().and_then(|r| r.timestamp.as_deref()).and_then(|t| t.parse().ok())
+            {
+                source.timestamp = t;
+            }
+        }
+        let tie_break = self.tie_break;
+        //the keys all differ by source, so the first source wins among equal ones
+        let (_, source) = self.sources.iter().enumerate()
+            .filter(|(_, s)| s.next.is_some())
+            .map(|(i, s)| ((s.timestamp, tie_break.rank(s.timestamp, i), i), i))
+            .min()?;
+        self.sources[source].next.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TypeTx;
+
+    fn row(client: u16, tx: u32, timestamp: Option<&str>) -> TxRecord
+    {
+        let mut record = TxRecord::new(TypeTx::Deposit, client, tx, Some("1.0".to_string()));
+        record.timestamp = timestamp.map(String::from);
+        record
+    }
+    fn sources() -> Vec<std::vec::IntoIter<TxRecord>>
+    {
+        vec![
+            vec![row(1, 1, Some("20")), row(1, 2, None), row(1, 3, Some("30"))].into_iter(),
+            vec![row(2, 4, None), row(2, 5, Some("20")), row(2, 6, Some("20")), row(2, 7, Some("10"))].into_iter(),
+        ]
+    }
+    fn ids(records: impl Iterator<Item = TxRecord>) -> Vec<u32>
+    {
+        records.map(|r| r.tx).collect()
+    }
+
+    #[test]
+    fn ties_go_to_the_source_given_first()
+    {
+        assert_eq!(ids(order_by_timestamp(sources(), TieBreak::Source)),vec![4, 1, 2, 5, 6, 7, 3]);
+    }
+    #[test]
+    fn rows_keep_their_order_within_a_source()
+    {
+        //7 is earlier than 6, but comes after it in its source
+        let merged = ids(order_by_timestamp(sources(), TieBreak::Source));
+        let position = |tx| merged.iter().position(|t| *t == tx);
+        assert!(position(6) < position(7) && position(1) < position(2));
+    }
+    #[test]
+    fn seeded_ties_broken_the_same_way_every_time()
+    {
+        let seeded: Vec<Vec<u32>> = (0..8).map(|seed| ids(order_by_timestamp(sources(), TieBreak::Seeded(seed)))).collect();
+        assert!(seeded.contains(&vec![4, 5, 6, 7, 1, 2, 3]) && seeded.contains(&vec![4, 1, 2, 5, 6, 7, 3]));
+        assert_eq!(ids(order_by_timestamp(sources(), TieBreak::Seeded(3))),seeded[3]);
+    }
+    #[test]
+    fn sources_read_as_the_run_goes()
+    {
+        let endless = |client: u16, timestamp: &'static str| (0..).map(move |tx| row(client, tx, Some(timestamp)));
+        let endless = vec![Box::new(endless(1, "5")) as Box<dyn Iterator<Item = TxRecord>>, Box::new(endless(2, "7"))];
+        assert_eq!(order_by_timestamp(endless, TieBreak::Source).take(3).map(|r| r.client).collect::<Vec<_>>(),vec![1, 1, 1]);
+    }
+    #[test]
+    fn tie_break_parsed()
+    {
+        assert_eq!(("seed:42".parse(), "source".parse()),(Ok(TieBreak::Seeded(42)), Ok(TieBreak::Source)));
+        assert!("random".parse::<TieBreak>().is_err());
+    }
+}
@@ -0,0 +1,253 @@
+use std::{fs::{File, OpenOptions}, io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write}, path::Path};
+use serde::{Deserialize, Serialize};
+use crate::{books::change_postings, Account, Amount, Engine};
+
+/// How many changes the run holds before appending them to the outbox
+pub const OUTBOX_BATCH: usize = 1024;
+
+///
+/// How the balances of a client changed, and what changed them
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BalanceChange
+{
+    pub client: u16,
+    /// The transaction that caused the change, or the ID its interest credit was made under
+    pub tx: u32,
+    /// The type of the transaction, or fee, settlement, interest or undo
+    pub cause: String,
+    /// How much each balance went up by, negative if it went down
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub pending: Amount,
+}
+
+///
+/// A balance change as it is kept in the outbox
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutboxEvent
+{
+    /// Counts up from 1 across the whole outbox, so a poller can carry on after the last one it saw
+    pub id: u64,
+    #[serde(flatten)]
+    pub change: BalanceChange,
+}
+
+///
+/// An append-only file of balance changes, one json object a line, that downstream
+/// systems poll rather than reading the engine's own reports or snapshots
+///
+/// Every append is synced to disk before it returns. A line torn by a crash is cut off
+/// when the outbox is opened again, and the IDs go on from the last whole line
+///
+pub struct Outbox
+{
+    file: File,
+    /// The ID of the last event appended
+    last: u64,
+}
+impl Outbox
+{
+    /// Opens the outbox at the path, creating it if it doesn't exist
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - The outbox file
+    pub fn open(path: &Path) -> io::Result<Outbox>
+    {
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        let whole = contents.iter().rposition(|b| *b == b'\n').map_or(0, |end| end + 1);
+        if whole < contents.len()
+        {
+            file.set_len(whole as u64)?;
+            file.seek(SeekFrom::End(0))?;
+        }
+        let last = contents[..whole].split(|b| *b == b'\n').rev().find_map(|line| serde_json::from_slice::<OutboxEvent>(line).ok()).map_or(0, |e| e.id);
+        Ok(Outbox { file, last })
+    }
+    /// The ID of the last event appended, 0 if there are none
+    pub fn last_id(&self) -> u64
+    {
+        self.last
+    }
+    /// Appends the changes as events and syncs them to disk, returning the ID of the last
+    ///
+    /// # Arguments
+    ///
+    /// * 'changes' - The changes, in the order they were made
+    pub fn append(&mut self, changes: &[BalanceChange]) -> io::Result<u64>
+    {
+        let mut lines = Vec::new();
+        for (id, change) in (self.last + 1..).zip(changes)
+        {
+            serde_json::to_writer(&mut lines, &OutboxEvent { id, change: change.clone() })?;
+            lines.push(b'\n');
+        }
+        self.file.write_all(&lines)?;
+        self.file.sync_data()?;
+        self.last += changes.len() as u64;
+        Ok(self.last)
+    }
+    /// Reads the events after the given ID, at most limit of them, for polling
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - The outbox file
+    /// * 'after' - The ID of the last event already seen, 0 for all of them
+    /// * 'limit' - The most events to return
+    pub fn read(path: &Path, after: u64, limit: usize) -> io::Result<Vec<OutboxEvent>>
+    {
+        let mut events = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines()
+        {
+            if events.len() >= limit {break}
+            //a line still being written is left for the next poll
+            let Ok(event) = serde_json::from_str::<OutboxEvent>(&line?) else {break};
+            if event.id > after
+            {
+                events.push(event);
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl Engine
+{
+    /// Starts keeping a balance change for every change to the balances of a client
+    pub fn enable_outbox(&mut self)
+    {
+        self.outbox.get_or_insert_with(Vec::new);
+    }
+    /// How many balance changes are kept, waiting to be handed to the outbox
+    pub fn pending_outbox(&self) -> usize
+    {
+        self.outbox.as_ref().map_or(0, Vec::len)
+    }
+    /// Hands over the balance changes kept since the last call, in the order they were made
+    ///
+    /// Changes made since a savepoint that is still open are held back, as they may yet
+    /// be rolled back
+    pub fn take_outbox(&mut self) -> Vec<BalanceChange>
+    {
+        let held = self.savepoints.first().map(|s| s.outbox);
+        let Some(outbox) = self.outbox.as_mut() else {return Vec::new()};
+        let settled = held.unwrap_or(outbox.len());
+        for saved in self.savepoints.iter_mut()
+        {
+            saved.outbox -= settled;
+        }
+        outbox.drain(..settled).collect()
+    }
+    /// Appends the balance changes kept since the last call to the outbox, returning how many
+    ///
+    /// The changes are kept if they couldn't be appended, so they go with the next call
+    ///
+    /// # Arguments
+    ///
+    /// * 'outbox' - The outbox to append them to
+    pub fn flush_outbox(&mut self, outbox: &mut Outbox) -> io::Result<usize>
+    {
+        let changes = self.take_outbox();
+        if let Err(e) = outbox.append(&changes)
+        {
+            for saved in self.savepoints.iter_mut()
+            {
+                saved.outbox += changes.len();
+            }
+            if let Some(kept) = self.outbox.as_mut()
+            {
+                kept.splice(..0, changes);
+            }
+            return Err(e);
+        }
+        Ok(changes.len())
+    }
+    /// Counts a change to the balances of the client in the period, keeps it for the
+    /// outbox if it is on, and posts it to the books and journal that are on
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client ID
+    /// * 'tx' - The transaction that caused the change
+    /// * 'cause' - What the transaction was
+    /// * 'before' - The account of the client before the change
+    pub(crate) fn record_balance_change(&mut self, client: u16, tx: u32, cause: &str, before: &Account)
+    {
+        let Some(c) = self.clients.get(&client) else {return};
+        let delta = |now: Amount, then: Amount| now.checked_sub(then).unwrap_or(Amount::ZERO);
+        let (available, held, total, pending) = (delta(c.acc.available, before.available), delta(c.acc.held, before.held),
+            delta(c.acc.total, before.total), delta(c.acc.pending, before.pending));
+        if [available, held, total, pending].iter().all(|d| *d == Amount::ZERO) {return}
+        if let Some(saved) = self.savepoints.last_mut()
+        {
+            saved.period_totals.keep(cause, self.period.totals.get(cause));
+        }
+        self.period.count(cause, available, held, total);
+        if self.outbox.is_none() && self.books.is_none() && self.journal.is_none() {return}
+        let change = BalanceChange { client, tx, cause: cause.to_string(), available, held, total, pending };
+        if let Some(books) = self.books.as_mut()
+        {
+            if let Some(saved) = self.savepoints.last_mut()
+            {
+                change_postings(&change).iter().for_each(|(account, _)| saved.books.keep(account, books.accounts.get(account)));
+            }
+            books.post_change(&change);
+        }
+        if let Some(journal) = self.journal.as_mut()
+        {
+            journal.record_change(&change);
+        }
+        if let Some(outbox) = self.outbox.as_mut()
+        {
+            outbox.push(change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn balance_changes_reach_the_outbox()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.enable_outbox();
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 2, Some(Amount::from_minor(90000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 1, None));
+        let savepoint = engine.savepoint();
+        engine.apply(Tx::new(TypeTx::Resolve, 1, 1, None));
+        let changes = engine.take_outbox();
+        assert_eq!(changes.iter().map(|c| (c.tx, c.cause.as_str(), c.available, c.held)).collect::<Vec<_>>(),vec![
+            (1, "deposit", Amount::from_minor(20000), Amount::ZERO),
+            (1, "dispute", Amount::from_minor(-20000), Amount::from_minor(20000)),
+        ]);
+        engine.rollback_to(&savepoint);
+        engine.release(&savepoint);
+        assert!(engine.take_outbox().is_empty());
+
+        let path = std::env::temp_dir().join(format!("outbox_{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut outbox = Outbox::open(&path).unwrap();
+        assert_eq!(outbox.append(&changes).unwrap(),2);
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 1, None));
+        assert_eq!(engine.flush_outbox(&mut outbox).unwrap(),1);
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"id\":4,\"cli").unwrap();
+
+        let mut reopened = Outbox::open(&path).unwrap();
+        assert_eq!(reopened.last_id(),3);
+        let events = Outbox::read(&path, 1, 10).unwrap();
+        assert_eq!(events.iter().map(|e| (e.id, e.change.cause.as_str(), e.change.total)).collect::<Vec<_>>(),vec![(2, "dispute", Amount::ZERO), (3, "chargeback", Amount::from_minor(-20000))]);
+        assert_eq!(reopened.append(&changes[..1]).unwrap(),4);
+        assert!(fs::read_to_string(&path).unwrap().lines().nth(1).unwrap().starts_with("{\"id\":2,\"client\":1,\"tx\":1,\"cause\":\"dispute\",\"available\":\"-2.0\",\"held\":\"2.0\""));
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,387 @@
+use std::{collections::BTreeSet, fmt, io, str::FromStr};
+use crate::{profile::ProfiledAccount, Account, Amount, ClientStore, ExportProfile};
+
+///
+/// Client IDs that more than one account in the output would carry, so the output
+/// couldn't be told apart by client
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateClients(pub Vec<u16>);
+impl fmt::Display for DuplicateClients
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let clients: Vec<String> = self.0.iter().map(|c| c.to_string()).collect();
+        write!(f, "more than one account for client {}", clients.join(", "))
+    }
+}
+impl From<DuplicateClients> for io::Error
+{
+    fn from(e: DuplicateClients) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+}
+
+/// Checks that no two accounts carry the same client ID, which can happen if an
+/// account ends up under a key other than its own client ID
+///
+/// # Arguments
+///
+/// * 'clients' - The clients about to be written
+///
+/// # Errors
+///
+/// Returns the client IDs carried more than once, in order
+pub fn check_unique_clients(clients: &ClientStore) -> Result<(), DuplicateClients>
+{
+    let mut seen = BTreeSet::new();
+    let duplicates: BTreeSet<u16> = clients.values().map(|c| c.acc.client).filter(|id| !seen.insert(*id)).collect();
+    match duplicates.is_empty()
+    {
+        true => Ok(()),
+        false => Err(DuplicateClients(duplicates.into_iter().collect()))
+    }
+}
+
+///
+/// Somewhere the final accounts can be written to, one at a time
+///
+pub trait AccountSink
+{
+    /// Writes a single account
+    fn write_account(&mut self, acc: &Account) -> io::Result<()>;
+    /// Writes anything left over once every account has been written
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+///
+/// The format the account report is written in
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat
+{
+    /// Comma separated, with a header row
+    Csv,
+    /// A single json array of account objects
+    Json,
+    /// One json account object per line
+    JsonLines,
+    /// A parquet file, written a batch at a time
+    #[cfg(feature = "parquet")]
+    Parquet,
+    /// An avro container file, with the account schema embedded
+    #[cfg(feature = "avro")]
+    Avro,
+    /// An Excel workbook, with the accounts on one sheet and their sums on another
+    #[cfg(feature = "xlsx")]
+    Xlsx
+}
+impl OutputFormat
+{
+    /// Returns a sink writing this format to the given output
+    ///
+    /// # Arguments
+    ///
+    /// 'out' - Where to write the accounts to
+    pub fn sink<'a, W: io::Write + Send + 'a>(&self, out: W) -> io::Result<Box<dyn AccountSink + 'a>>
+    {
+        Ok(match self
+        {
+            OutputFormat::Csv => Box::new(CsvSink::new(out)),
+            OutputFormat::Json => Box::new(JsonSink::new(out)),
+            OutputFormat::JsonLines => Box::new(JsonLinesSink::new(out)),
+            #[cfg(feature = "parquet")]
+            OutputFormat::Parquet => Box::new(crate::columnar::ParquetSink::new(out)?),
+            #[cfg(feature = "avro")]
+            OutputFormat::Avro => Box::new(crate::avro::AvroSink::new(out)),
+            #[cfg(feature = "xlsx")]
+            OutputFormat::Xlsx => Box::new(crate::xlsx::XlsxSink::new(out)?),
+        })
+    }
+}
+impl FromStr for OutputFormat
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" => Ok(OutputFormat::JsonLines),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            #[cfg(feature = "avro")]
+            "avro" => Ok(OutputFormat::Avro),
+            #[cfg(feature = "xlsx")]
+            "xlsx" => Ok(OutputFormat::Xlsx),
+            _ => Err(format!("unknown output format '{}'", s))
+        }
+    }
+}
+impl fmt::Display for OutputFormat
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+///
+/// The balance accounts are ranked by for a top-N report
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey
+{
+    Available,
+    Held,
+    Total,
+}
+impl SortKey
+{
+    /// The balance of the account this key ranks by
+    pub fn of(&self, acc: &Account) -> Amount
+    {
+        match self
+        {
+            SortKey::Available => acc.available,
+            SortKey::Held => acc.held,
+            SortKey::Total => acc.total,
+        }
+    }
+}
+impl FromStr for SortKey
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "available" => Ok(SortKey::Available),
+            "held" => Ok(SortKey::Held),
+            "total" => Ok(SortKey::Total),
+            _ => Err(format!("unknown balance '{}'", s))
+        }
+    }
+}
+
+///
+/// Which accounts are written, for when only the interesting few of millions are wanted
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OutputFilter
+{
+    /// Only accounts locked by a chargeback
+    pub only_locked: bool,
+    /// Only accounts with a balance other than zero, pending included
+    pub non_zero: bool,
+    /// Only the given number of accounts with the largest of the balance, largest first
+    pub top: Option<(SortKey, usize)>,
+}
+impl OutputFilter
+{
+    /// Whether the account passes the locked and non-zero filters
+    ///
+    /// # Arguments
+    ///
+    /// * 'acc' - The account to check
+    pub fn keeps(&self, acc: &Account) -> bool
+    {
+        let zero = [acc.available, acc.held, acc.total, acc.pending].iter().all(|a| *a == Amount::ZERO);
+        (!self.only_locked || acc.locked) && (!self.non_zero || !zero)
+    }
+}
+
+/// Writes accounts as csv
+pub struct CsvSink<W: io::Write>
+{
+    wrtr: csv::Writer<W>,
+    /// The delimiter and decimal point the accounts are written with
+    profile: ExportProfile,
+}
+impl<W: io::Write> CsvSink<W>
+{
+    pub fn new(out: W) -> CsvSink<W>
+    {
+        CsvSink::with_profile(out, ExportProfile::default())
+    }
+    /// Returns a sink laying the csv out as the export profile has it
+    ///
+    /// # Arguments
+    ///
+    /// * 'out' - Where to write the accounts to
+    /// * 'profile' - The delimiter and decimal point to write them with
+    pub fn with_profile(out: W, profile: ExportProfile) -> CsvSink<W>
+    {
+        CsvSink { wrtr: profile.writer(out), profile }
+    }
+}
+impl<W: io::Write> AccountSink for CsvSink<W>
+{
+    fn write_account(&mut self, acc: &Account) -> io::Result<()>
+    {
+        match self.profile.decimal_comma
+        {
+            true => Ok(self.wrtr.serialize(ProfiledAccount::new(acc, &self.profile))?),
+            false => Ok(self.wrtr.serialize(acc)?)
+        }
+    }
+    fn finish(&mut self) -> io::Result<()>
+    {
+        self.wrtr.flush()
+    }
+}
+
+/// Writes accounts as a single json array
+pub struct JsonSink<W: io::Write>
+{
+    out: W,
+    written: usize,
+}
+impl<W: io::Write> JsonSink<W>
+{
+    pub fn new(out: W) -> JsonSink<W>
+    {
+        JsonSink { out, written: 0 }
+    }
+}
+impl<W: io::Write> AccountSink for JsonSink<W>
+{
+    fn write_account(&mut self, acc: &Account) -> io::Result<()>
+    {
+        // serialize first, so a failed account doesn't leave half an object behind
+        let json = serde_json::to_string(acc)?;
+        self.out.write_all(if self.written == 0 {b"["} else {b","})?;
+        self.out.write_all(json.as_bytes())?;
+        self.written += 1;
+        Ok(())
+    }
+    fn finish(&mut self) -> io::Result<()>
+    {
+        if self.written == 0 {self.out.write_all(b"[")?;}
+        self.out.write_all(b"]\n")?;
+        self.out.flush()
+    }
+}
+
+/// Writes accounts as one json object per line
+pub struct JsonLinesSink<W: io::Write>
+{
+    out: W,
+}
+impl<W: io::Write> JsonLinesSink<W>
+{
+    pub fn new(out: W) -> JsonLinesSink<W>
+    {
+        JsonLinesSink { out }
+    }
+}
+impl<W: io::Write> AccountSink for JsonLinesSink<W>
+{
+    fn write_account(&mut self, acc: &Account) -> io::Result<()>
+    {
+        let json = serde_json::to_string(acc)?;
+        writeln!(self.out, "{}", json)
+    }
+    fn finish(&mut self) -> io::Result<()>
+    {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accounts() -> Vec<Account>
+    {
+        let mut a = Account::new(1);
+        a.available = Amount::from_minor(15000);
+        a.total = Amount::from_minor(15000);
+        let mut b = Account::new(2);
+        b.locked = true;
+        vec![a, b]
+    }
+    fn write(format: OutputFormat, accounts: &[Account]) -> String
+    {
+        let mut out = Vec::new();
+        {
+            let mut sink = format.sink(&mut out).unwrap();
+            for acc in accounts
+            {
+                sink.write_account(acc).unwrap();
+            }
+            sink.finish().unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_output()
+    {
+        assert_eq!(write(OutputFormat::Csv, &accounts()),
+            "client,available,held,total,locked,pending\n1,1.5,0.0,1.5,false,0.0\n2,0.0,0.0,0.0,true,0.0\n");
+    }
+    #[test]
+    fn json_output()
+    {
+        assert_eq!(write(OutputFormat::Json, &accounts()),
+            "[{\"client\":1,\"available\":\"1.5\",\"held\":\"0.0\",\"total\":\"1.5\",\"locked\":false,\"pending\":\"0.0\"},\
+            {\"client\":2,\"available\":\"0.0\",\"held\":\"0.0\",\"total\":\"0.0\",\"locked\":true,\"pending\":\"0.0\"}]\n");
+        assert_eq!(write(OutputFormat::Json, &[]),"[]\n");
+    }
+    #[test]
+    fn json_lines_output()
+    {
+        let out = write(OutputFormat::JsonLines, &accounts());
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(),2);
+        assert_eq!(lines[1],"{\"client\":2,\"available\":\"0.0\",\"held\":\"0.0\",\"total\":\"0.0\",\"locked\":true,\"pending\":\"0.0\"}");
+    }
+    #[test]
+    fn output_format_from_str()
+    {
+        assert_eq!("csv".parse(),Ok(OutputFormat::Csv));
+        assert_eq!("json".parse(),Ok(OutputFormat::Json));
+        assert_eq!("jsonl".parse(),Ok(OutputFormat::JsonLines));
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+
+    #[test]
+    fn filtered_output()
+    {
+        let mut clients = ClientStore::default();
+        for (id, minor, locked) in [(1, 30000, false), (2, 0, true), (3, 50000, true), (4, 10000, false)]
+        {
+            let mut c = crate::Client::new(id);
+            c.acc.available = Amount::from_minor(minor);
+            c.acc.total = Amount::from_minor(minor);
+            c.acc.locked = locked;
+            clients.insert(id, c);
+        }
+        let write = |filter: OutputFilter| {
+            let mut out = Vec::new();
+            crate::write_filtered_output(&clients, &filter, &mut CsvSink::new(&mut out)).unwrap();
+            let mut ids: Vec<String> = String::from_utf8(out).unwrap().lines().skip(1).map(|l| l.split(',').next().unwrap().to_string()).collect();
+            if filter.top.is_none() {ids.sort();}
+            ids.join(",")
+        };
+        assert_eq!(write(OutputFilter::default()),"1,2,3,4");
+        assert_eq!(write(OutputFilter { only_locked: true, ..OutputFilter::default() }),"2,3");
+        assert_eq!(write(OutputFilter { non_zero: true, ..OutputFilter::default() }),"1,3,4");
+        assert_eq!(write(OutputFilter { top: Some((SortKey::Total, 2)), ..OutputFilter::default() }),"3,1");
+        assert_eq!(write(OutputFilter { only_locked: true, non_zero: true, top: Some(("available".parse().unwrap(), 5)) }),"3");
+    }
+    #[test]
+    fn duplicate_clients()
+    {
+        let mut clients: ClientStore = (1..=3).map(|id| (id, crate::Client::new(id))).collect();
+        assert_eq!(check_unique_clients(&clients),Ok(()));
+        //an account filed under the wrong key would come out as a second row for client 1
+        clients.insert(7, crate::Client::new(1));
+        assert_eq!(check_unique_clients(&clients),Err(DuplicateClients(vec![1])));
+        let mut out = Vec::new();
+        let e = crate::write_filtered_output(&clients, &OutputFilter::default(), &mut CsvSink::new(&mut out)).unwrap_err();
+        assert_eq!((e.kind(), e.to_string()),(io::ErrorKind::InvalidData, "more than one account for client 1".to_string()));
+        assert!(out.is_empty());
+        let mut engine = crate::Engine::new(crate::EnginePolicy::default());
+        engine.clients = clients;
+        assert_eq!(crate::anomalies(&engine),vec![crate::Warning::DuplicateClient { client: 1 }]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::str::FromStr;
+use serde::Serialize;
+use crate::{Account, Engine};
+
+/// How many accounts a page holds when the request doesn't say
+pub const DEFAULT_PAGE_LIMIT: usize = 100;
+/// The most accounts a page holds, whatever the request asks for
+pub const MAX_PAGE_LIMIT: usize = 1000;
+
+///
+/// Which page of accounts is asked for, by the client ID the previous one ended at
+///
+/// Accounts are always ordered by client ID, so a cursor stays valid while
+/// transactions are applied between requests: new clients before it are skipped,
+/// those after it show up on a later page
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageRequest
+{
+    /// The last client of the previous page, None for the first page
+    pub after: Option<u16>,
+    /// How many accounts to return, at most MAX_PAGE_LIMIT
+    pub limit: usize,
+}
+impl Default for PageRequest
+{
+    fn default() -> Self {
+        PageRequest { after: None, limit: DEFAULT_PAGE_LIMIT }
+    }
+}
+impl FromStr for PageRequest
+{
+    type Err = String;
+    /// Reads the after and limit parameters of a query string, E.G. after=42&limit=50,
+    /// leaving any other parameter alone
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut page = PageRequest::default();
+        for (key, value) in s.trim_start_matches('?').split('&').filter_map(|p| p.split_once('='))
+        {
+            match key
+            {
+                "after" => page.after = Some(value.parse().map_err(|_| format!("invalid cursor '{}'", value))?),
+                "limit" => page.limit = value.parse().ok().filter(|l| *l > 0).ok_or_else(|| format!("invalid limit '{}'", value))?,
+                _ => {}
+            }
+        }
+        Ok(page)
+    }
+}
+
+///
+/// A page of accounts, ordered by client ID
+///
+#[derive(Debug, Clone, Serialize)]
+pub struct AccountPage
+{
+    pub accounts: Vec<Account>,
+    /// The cursor to ask for the next page with, None if this is the last
+    pub next: Option<u16>,
+}
+
+impl Engine
+{
+    /// The accounts in client ID order, from the first client after the cursor on,
+    /// without copying them
+    ///
+    /// # Arguments
+    ///
+    /// * 'after' - The client the previous page ended at, None to start at the first
+    pub fn accounts_iter(&self, after: Option<u16>) -> impl Iterator<Item = &Account>
+    {
+        self.clients.iter_ordered(after).map(|(_, c)| &c.acc)
+    }
+    /// Returns a page of accounts, with the cursor for the next one if there are more
+    ///
+    /// # Arguments
+    ///
+    /// * 'page' - Where the page starts and how many accounts it holds
+    pub fn accounts_page(&self, page: PageRequest) -> AccountPage
+    {
+        let limit = page.limit.clamp(1, MAX_PAGE_LIMIT);
+        let mut accounts: Vec<Account> = self.accounts_iter(page.after).take(limit + 1).cloned().collect();
+        let next = match accounts.len() > limit
+        {
+            true => {
+                accounts.truncate(limit);
+                accounts.last().map(|a| a.client)
+            },
+            false => None
+        };
+        AccountPage { accounts, next }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn cursor_pagination()
+    {
+        assert_eq!("after=7&limit=2&sort=asc".parse(),Ok(PageRequest { after: Some(7), limit: 2 }));
+        assert_eq!("?limit=5".parse(),Ok(PageRequest { after: None, limit: 5 }));
+        assert!("limit=0".parse::<PageRequest>().is_err() && "after=-1".parse::<PageRequest>().is_err());
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        for client in [5, 1, 9, 3, 7]
+        {
+            engine.apply(Tx::new(TypeTx::Deposit, client, client as u32, Some(Amount::from_minor(10000))));
+        }
+        let mut pages = Vec::new();
+        let mut page = PageRequest { after: None, limit: 2 };
+        loop
+        {
+            let result = engine.accounts_page(page);
+            pages.push(result.accounts.iter().map(|a| a.client).collect::<Vec<_>>());
+            match result.next
+            {
+                Some(next) => page.after = Some(next),
+                None => break
+            }
+        }
+        assert_eq!(pages,vec![vec![1, 3], vec![5, 7], vec![9]]);
+        assert!(engine.accounts_page(PageRequest { after: Some(9), limit: 2 }).accounts.is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+use std::{collections::BTreeMap, fs, io, path::{Path, PathBuf}};
+use serde::{Deserialize, Serialize};
+use crate::{Account, Amount, Engine};
+
+///
+/// The balances of a client at the start or end of a period
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeriodBalance
+{
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+impl From<&Account> for PeriodBalance
+{
+    fn from(acc: &Account) -> Self {
+        PeriodBalance { available: acc.available, held: acc.held, total: acc.total }
+    }
+}
+
+///
+/// What the changes of a single type of transaction came to over a period
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeTotal
+{
+    /// How many of them changed a balance
+    pub count: u64,
+    /// How much they moved each balance by, net
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+}
+
+///
+/// The period the engine is in, from the last close, kept in snapshots so a period
+/// can go on across runs
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Period
+{
+    /// How many periods were closed before this one
+    pub closed: u64,
+    /// How many rows had been applied when it started
+    pub opened_at: u64,
+    /// The balances of every client with any at the start, keyed by client
+    pub opening: BTreeMap<u16, PeriodBalance>,
+    /// What the changes of each type of transaction came to so far, keyed by the cause of
+    /// the change, as in BalanceChange
+    pub totals: BTreeMap<String, TypeTotal>,
+}
+impl Period
+{
+    /// Adds a change to the totals of its cause
+    ///
+    /// # Arguments
+    ///
+    /// * 'cause' - What made the change
+    /// * 'available' - How much the available balance moved by
+    /// * 'held' - How much the held balance moved by
+    /// * 'total' - How much the total balance moved by
+    pub(crate) fn count(&mut self, cause: &str, available: Amount, held: Amount, total: Amount)
+    {
+        if !self.totals.contains_key(cause)
+        {
+            self.totals.insert(cause.to_string(), TypeTotal::default());
+        }
+        if let Some(t) = self.totals.get_mut(cause)
+        {
+            t.count += 1;
+            t.available = t.available.checked_add(available).unwrap_or(t.available);
+            t.held = t.held.checked_add(held).unwrap_or(t.held);
+            t.total = t.total.checked_add(total).unwrap_or(t.total);
+        }
+    }
+}
+
+///
+/// A client in a closed period
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClientPeriod
+{
+    pub client: u16,
+    pub opening: PeriodBalance,
+    pub closing: PeriodBalance,
+}
+
+///
+/// The figures of a period, frozen when it was closed
+///
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeriodClose
+{
+    /// Counts up from 1
+    pub period: u64,
+    /// How many rows were applied in the period, rejected ones included
+    pub rows: u64,
+    /// Every client with a balance at the start or the end, ordered by client
+    pub clients: Vec<ClientPeriod>,
+    /// What the changes of each type of transaction came to, keyed by cause
+    pub totals: BTreeMap<String, TypeTotal>,
+}
+impl PeriodClose
+{
+    /// The name of the archive file of the period, E.G. period-000042.json
+    pub fn file_name(&self) -> String
+    {
+        format!("period-{:06}.json", self.period)
+    }
+    /// Writes the figures as json into the archive directory, creating it if need be,
+    /// and returns the path of the file
+    ///
+    /// The file is written under a temporary name and renamed, so a crash never leaves
+    /// half an archive. A period already archived is never overwritten
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - The archive directory
+    pub fn archive(&self, dir: &Path) -> io::Result<PathBuf>
+    {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(self.file_name());
+        if path.exists()
+        {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("period {} is already archived", self.period)));
+        }
+        let partial = dir.join(format!("{}.partial", self.file_name()));
+        fs::write(&partial, serde_json::to_vec_pretty(self)?)?;
+        fs::rename(&partial, &path)?;
+        Ok(path)
+    }
+    /// Reads an archived period back
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - The archive file
+    pub fn read(path: &Path) -> io::Result<PeriodClose>
+    {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+}
+
+impl Engine
+{
+    /// Closes the period, freezing the balances of every client at its start and end and
+    /// what each type of transaction came to, and starts the next one from the closing
+    /// balances
+    ///
+    /// Returns None, closing nothing, while a savepoint is open, as what it would close
+    /// could still be rolled back
+    pub fn close_period(&mut self) -> Option<PeriodClose>
+    {
+        if !self.savepoints.is_empty() {return None}
+        let closing: BTreeMap<u16, PeriodBalance> = self.clients.values()
+            .map(|c| (c.acc.client, PeriodBalance::from(&c.acc)))
+            .filter(|(_, b)| *b != PeriodBalance::default())
+            .collect();
+        let mut ids: Vec<u16> = self.period.opening.keys().chain(closing.keys()).copied().collect();
+        ids.sort();
+        ids.dedup();
+        let clients = ids.into_iter().map(|client| ClientPeriod {
+            client,
+            opening: self.period.opening.get(&client).copied().unwrap_or_default(),
+            closing: closing.get(&client).copied().unwrap_or_default(),
+        }).collect();
+        let next = Period { closed: self.period.closed + 1, opened_at: self.rows, opening: closing, totals: BTreeMap::new() };
+        let period = std::mem::replace(&mut self.period, next);
+        Some(PeriodClose { period: period.closed + 1, rows: self.rows - period.opened_at, clients, totals: period.totals })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    /// An engine whose first period has two deposits and a withdrawal that can't be made,
+    /// and whose second has a withdrawal and a dispute
+    fn engine() -> Engine
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 2, 3, Some(Amount::from_minor(90000))));
+        engine
+    }
+    fn second_period(engine: &mut Engine)
+    {
+        engine.apply(Tx::new(TypeTx::Withdrawal, 2, 4, Some(Amount::from_minor(15000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 1, None));
+    }
+
+    #[test]
+    fn period_totals_by_type()
+    {
+        let first = engine().close_period().unwrap();
+        assert_eq!((first.period, first.rows),(1, 3));
+        assert_eq!(first.totals["deposit"],TypeTotal { count: 2, available: Amount::from_minor(70000), held: Amount::ZERO, total: Amount::from_minor(70000) });
+        assert!(!first.totals.contains_key("withdrawal"));
+    }
+    #[test]
+    fn next_period_opens_at_the_closing_balances()
+    {
+        let mut engine = engine();
+        engine.close_period().unwrap();
+        second_period(&mut engine);
+        let second = engine.close_period().unwrap();
+        assert_eq!(second.period,2);
+        assert_eq!(second.clients,vec![
+            ClientPeriod { client: 1, opening: PeriodBalance { available: Amount::from_minor(50000), held: Amount::ZERO, total: Amount::from_minor(50000) },
+                closing: PeriodBalance { available: Amount::ZERO, held: Amount::from_minor(50000), total: Amount::from_minor(50000) } },
+            ClientPeriod { client: 2, opening: PeriodBalance { available: Amount::from_minor(20000), held: Amount::ZERO, total: Amount::from_minor(20000) },
+                closing: PeriodBalance { available: Amount::from_minor(5000), held: Amount::ZERO, total: Amount::from_minor(5000) } },
+        ]);
+        assert_eq!(second.totals.keys().collect::<Vec<_>>(),vec!["dispute", "withdrawal"]);
+    }
+    #[test]
+    fn no_close_under_a_savepoint()
+    {
+        let mut engine = engine();
+        let savepoint = engine.savepoint();
+        assert!(engine.close_period().is_none());
+        engine.release(&savepoint);
+        assert_eq!(engine.close_period().map(|p| p.period),Some(1));
+    }
+    #[test]
+    fn closed_period_archived_once()
+    {
+        let mut engine = engine();
+        second_period(&mut engine);
+        let closed = engine.close_period().unwrap();
+        let dir = std::env::temp_dir().join(format!("periods_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = closed.archive(&dir).unwrap();
+        assert!(path.ends_with("period-000001.json"));
+        assert_eq!(PeriodClose::read(&path).unwrap(),closed);
+        assert!(closed.archive(&dir).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
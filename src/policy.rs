@@ -0,0 +1,410 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+use serde::{Deserialize, Serialize};
+use crate::{Amount, TypeTx};
+
+///
+/// How amounts with more than four decimal places are handled on ingest
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoundingMode
+{
+    /// Round to nearest, ties going to the even digit (banker's rounding)
+    HalfEven,
+    /// Drop any digits past the fourth decimal
+    Truncate,
+    /// Refuse the transaction altogether
+    Reject
+}
+impl FromStr for RoundingMode
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "half-even" => Ok(RoundingMode::HalfEven),
+            "truncate" => Ok(RoundingMode::Truncate),
+            "reject" => Ok(RoundingMode::Reject),
+            _ => Err(format!("unknown rounding mode '{}'", s))
+        }
+    }
+}
+impl fmt::Display for RoundingMode
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+///
+/// What to do with a dispute, resolve or chargeback that carries an amount
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnexpectedAmount
+{
+    /// Refuse the transaction
+    Reject,
+    /// Drop the amount and process the transaction as normal
+    Ignore
+}
+impl FromStr for UnexpectedAmount
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "reject" => Ok(UnexpectedAmount::Reject),
+            "ignore" => Ok(UnexpectedAmount::Ignore),
+            _ => Err(format!("unknown unexpected amount policy '{}'", s))
+        }
+    }
+}
+
+///
+/// How strictly the columns and timestamps of the input are checked
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SchemaMode
+{
+    /// Any column we don't know stops the run, and rows with a timestamp that
+    /// can't be read are rejected
+    Strict,
+    /// Columns we don't know are skipped over, and timestamps that can't be read
+    /// are dropped from their row
+    Lenient
+}
+impl FromStr for SchemaMode
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "strict" => Ok(SchemaMode::Strict),
+            "lenient" => Ok(SchemaMode::Lenient),
+            _ => Err(format!("unknown schema mode '{}'", s))
+        }
+    }
+}
+
+///
+/// What happens to a deposit or withdrawal for an account that is locked
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockedAccount
+{
+    /// Leave the account as it is
+    Reject,
+    /// Add the funds to held rather than available, for deposits only;
+    /// a withdrawal is left out as with Reject
+    AcceptToHeld,
+    /// Apply it as if the account weren't locked
+    AcceptNormally
+}
+impl FromStr for LockedAccount
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "reject" => Ok(LockedAccount::Reject),
+            "accept-to-held" => Ok(LockedAccount::AcceptToHeld),
+            "accept" => Ok(LockedAccount::AcceptNormally),
+            _ => Err(format!("unknown locked account policy '{}'", s))
+        }
+    }
+}
+
+///
+/// When an account is frozen for too many chargebacks, which stops withdrawals
+/// while deposits and disputes still go through
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct FreezePolicy
+{
+    /// How many of the latest deposits of a client are looked at
+    pub window: usize,
+    /// Freezes the account once more of those deposits than this are charged back
+    pub max_chargebacks: Option<usize>,
+    /// Freezes the account once a larger share than this of those deposits is charged back
+    pub max_rate: Option<f64>,
+}
+impl Default for FreezePolicy
+{
+    fn default() -> Self {
+        FreezePolicy { window: 100, max_chargebacks: None, max_rate: None }
+    }
+}
+impl FreezePolicy
+{
+    /// Whether the chargebacks among the deposits in the window go past either threshold
+    ///
+    /// # Arguments
+    ///
+    /// * 'chargebacks' - How many of the deposits were charged back
+    /// * 'deposits' - How many deposits are in the window
+    pub fn exceeded(&self, chargebacks: usize, deposits: usize) -> bool
+    {
+        let rate = if deposits == 0 {0.0} else {chargebacks as f64 / deposits as f64};
+        self.max_chargebacks.is_some_and(|max| chargebacks > max) || self.max_rate.is_some_and(|max| rate > max)
+    }
+}
+
+///
+/// How interest is credited to available balances
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InterestPolicy
+{
+    /// The share of the available balance credited each period, E.G. 0.001
+    pub rate: Amount,
+    /// The length of a period in seconds; interest is credited whenever a transaction's
+    /// timestamp crosses into a new period. If None it is only credited when asked for
+    pub period: Option<i64>,
+}
+
+///
+/// How long after a transaction it may still be disputed, going by its timestamp, by
+/// how many rows came in since, or both
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DisputeWindow
+{
+    /// How many days after its timestamp, only checked if it and the dispute both have one
+    pub days: Option<u32>,
+    /// How many rows after it
+    pub rows: Option<u64>,
+}
+impl DisputeWindow
+{
+    /// This window, with each limit it doesn't set taken from another
+    ///
+    /// # Arguments
+    ///
+    /// * 'fallback' - The window to take the missing limits from, E.G. the global one
+    pub fn or(self, fallback: DisputeWindow) -> DisputeWindow
+    {
+        DisputeWindow { days: self.days.or(fallback.days), rows: self.rows.or(fallback.rows) }
+    }
+    /// Whether a transaction can no longer be disputed
+    ///
+    /// # Arguments
+    ///
+    /// * 'at' - The row and timestamp of the transaction; a row of 0 isn't known
+    /// * 'now' - The row and timestamp of the dispute
+    pub fn expired(&self, at: (u64, Option<i64>), now: (u64, Option<i64>)) -> bool
+    {
+        let by_rows = self.rows.is_some_and(|rows| at.0 > 0 && now.0.saturating_sub(at.0) > rows);
+        let by_days = match (self.days, at.1, now.1)
+        {
+            (Some(days), Some(then), Some(now)) => now.saturating_sub(then) > i64::from(days) * 86_400,
+            _ => false
+        };
+        by_rows || by_days
+    }
+}
+
+///
+/// Checks the transaction ID of every deposit and withdrawal against those of all
+/// clients, rather than only the history of its own
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalDedup
+{
+    /// Puts a bloom filter with this false positive rate in front of the exact set of
+    /// IDs, E.G. 0.01, so fresh IDs rarely need the set looked up
+    pub bloom_rate: Option<f64>,
+    /// How many IDs the bloom filter is sized for
+    pub expected: u64,
+}
+impl Default for GlobalDedup
+{
+    fn default() -> Self {
+        GlobalDedup { bloom_rate: None, expected: 10_000_000 }
+    }
+}
+
+///
+/// The limits of an account tier, E.G. "basic" or "premium"
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TierLimits
+{
+    /// The largest single deposit
+    pub max_deposit: Option<Amount>,
+    /// The largest single withdrawal
+    pub max_withdrawal: Option<Amount>,
+    /// How many disputes may be open at once, a further one is refused
+    pub max_open_disputes: Option<usize>,
+    /// How long after a transaction it may be disputed, any limit not set here comes from the policy
+    #[serde(default)]
+    pub dispute_window: DisputeWindow,
+}
+/// Reads the limits as on the command line, E.G. "max_deposit=1000,max_open_disputes=1,dispute_window_days=120"
+impl FromStr for TierLimits
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = TierLimits::default();
+        for pair in s.split(',').filter(|p| !p.is_empty())
+        {
+            let invalid = || format!("invalid tier limit '{}'", pair);
+            let (key, value) = pair.split_once('=').ok_or_else(invalid)?;
+            match key.trim()
+            {
+                "max_deposit" => limits.max_deposit = Some(value.trim().parse().map_err(|_| invalid())?),
+                "max_withdrawal" => limits.max_withdrawal = Some(value.trim().parse().map_err(|_| invalid())?),
+                "max_open_disputes" => limits.max_open_disputes = Some(value.trim().parse().map_err(|_| invalid())?),
+                "dispute_window_days" => limits.dispute_window.days = Some(value.trim().parse().map_err(|_| invalid())?),
+                "dispute_window_rows" => limits.dispute_window.rows = Some(value.trim().parse().map_err(|_| invalid())?),
+                _ => return Err(format!("unknown tier limit '{}'", key))
+            }
+        }
+        Ok(limits)
+    }
+}
+
+///
+/// The rules the engine follows when it's given input that isn't clear cut
+///
+#[derive(Debug, Clone)]
+pub struct EnginePolicy
+{
+    /// What to do with amounts that have more than four decimal places
+    pub rounding: RoundingMode,
+    /// The smallest amount a transaction may carry, if any
+    pub min_amount: Option<Amount>,
+    /// The largest amount a transaction may carry, if any
+    pub max_amount: Option<Amount>,
+    /// What to do when a dispute, resolve or chargeback has an amount
+    pub unexpected_amount: UnexpectedAmount,
+    /// Whether unknown input columns are an error or skipped, and whether rows with a
+    /// timestamp that can't be read are rejected or applied without it
+    pub schema: SchemaMode,
+    /// What happens to deposits for a locked account
+    pub locked_deposit: LockedAccount,
+    /// What happens to withdrawals for a locked account
+    pub locked_withdrawal: LockedAccount,
+    /// The opening balance of the chargeback reserve, which every chargeback
+    /// is debited from as well; no reserve is kept if None
+    pub reserve: Option<Amount>,
+    /// When accounts are frozen for their chargebacks; none are if None
+    pub freeze: Option<FreezePolicy>,
+    /// The limits of each account tier, keyed by tier name; clients get theirs
+    /// through the tier in the metadata registry
+    pub tiers: HashMap<String, TierLimits>,
+    /// Deposits and withdrawals of at least this amount are screened, as well as
+    /// each client's first deposit
+    pub screen_above: Option<Amount>,
+    /// The interest credited to available balances; none is if None
+    pub interest: Option<InterestPolicy>,
+    /// How many seconds after its timestamp a deposit settles; until then it counts
+    /// towards the total but not available. Deposits settle right away if None
+    pub settlement_delay: Option<i64>,
+    /// How long after a transaction it may be disputed, for clients whose tier doesn't say
+    pub dispute_window: DisputeWindow,
+    /// Rejects deposits and withdrawals reusing the transaction ID of any client, if set
+    pub global_dedup: Option<GlobalDedup>,
+}
+impl EnginePolicy
+{
+    /// The limits of a tier, or None if it has none configured
+    ///
+    /// # Arguments
+    ///
+    /// * 'tier' - The name of the tier
+    pub fn tier_limits(&self, tier: &str) -> Option<&TierLimits>
+    {
+        self.tiers.get(tier)
+    }
+    /// What happens to a transaction of the given type for a locked account
+    ///
+    /// # Arguments
+    ///
+    /// * 'r#type' - The type of the transaction
+    pub fn locked_account(&self, r#type: TypeTx) -> LockedAccount
+    {
+        match r#type
+        {
+            TypeTx::Deposit => self.locked_deposit,
+            TypeTx::Withdrawal => self.locked_withdrawal,
+            _ => LockedAccount::Reject
+        }
+    }
+}
+impl Default for EnginePolicy
+{
+    fn default() -> Self {
+        EnginePolicy {
+            rounding: RoundingMode::Reject,
+            min_amount: None,
+            max_amount: None,
+            unexpected_amount: UnexpectedAmount::Reject,
+            schema: SchemaMode::Strict,
+            locked_deposit: LockedAccount::Reject,
+            locked_withdrawal: LockedAccount::Reject,
+            reserve: None,
+            freeze: None,
+            tiers: HashMap::new(),
+            screen_above: None,
+            interest: None,
+            settlement_delay: None,
+            dispute_window: DisputeWindow::default(),
+            global_dedup: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding_mode_from_str()
+    {
+        assert_eq!("half-even".parse(),Ok(RoundingMode::HalfEven));
+        assert_eq!("truncate".parse(),Ok(RoundingMode::Truncate));
+        assert_eq!("reject".parse(),Ok(RoundingMode::Reject));
+        assert!("round".parse::<RoundingMode>().is_err());
+    }
+    #[test]
+    fn unexpected_amount_from_str()
+    {
+        assert_eq!("reject".parse(),Ok(UnexpectedAmount::Reject));
+        assert_eq!("ignore".parse(),Ok(UnexpectedAmount::Ignore));
+        assert!("keep".parse::<UnexpectedAmount>().is_err());
+    }
+    #[test]
+    fn schema_mode_from_str()
+    {
+        assert_eq!("strict".parse(),Ok(SchemaMode::Strict));
+        assert_eq!("lenient".parse(),Ok(SchemaMode::Lenient));
+        assert!("loose".parse::<SchemaMode>().is_err());
+    }
+    #[test]
+    fn locked_account_from_str()
+    {
+        assert_eq!("reject".parse(),Ok(LockedAccount::Reject));
+        assert_eq!("accept-to-held".parse(),Ok(LockedAccount::AcceptToHeld));
+        assert_eq!("accept".parse(),Ok(LockedAccount::AcceptNormally));
+        assert!("held".parse::<LockedAccount>().is_err());
+    }
+    #[test]
+    fn freeze_thresholds()
+    {
+        let policy = FreezePolicy { window: 10, max_chargebacks: Some(2), max_rate: Some(0.25) };
+        assert!(!policy.exceeded(2, 10));
+        assert!(policy.exceeded(3, 20));
+        assert!(policy.exceeded(1, 3));
+        assert!(!FreezePolicy::default().exceeded(5, 5));
+    }
+    #[test]
+    fn tier_limits_from_str()
+    {
+        let limits: TierLimits = "max_deposit=1000, max_open_disputes=1, dispute_window_rows=50".parse().unwrap();
+        assert_eq!(limits.max_deposit,Some("1000".parse().unwrap()));
+        assert_eq!(limits.max_withdrawal,None);
+        assert_eq!(limits.max_open_disputes,Some(1));
+        assert_eq!(limits.dispute_window.or(DisputeWindow { days: Some(120), rows: Some(10) }),DisputeWindow { days: Some(120), rows: Some(50) });
+        assert!("max_deposit".parse::<TierLimits>().is_err());
+        assert!("max_fee=1".parse::<TierLimits>().is_err());
+    }
+}
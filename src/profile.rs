@@ -0,0 +1,379 @@
+use std::{fmt, io, str::FromStr};
+use serde::Serialize;
+use crate::{chain::{self, chain_hash, GENESIS_HASH}, ledger_entries, timestamp::number, parse_utc_offset, Account, Amount, ClientStore};
+
+///
+/// How dates are written in an export
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateFormat
+{
+    /// Seconds since the unix epoch, E.G. 1700000000
+    #[default]
+    Unix,
+    /// E.G. 2023-11-14T22:13:20Z
+    Iso,
+    /// E.G. 14.11.2023 22:13:20
+    DayMonthYear,
+    /// E.G. 11/14/2023 22:13:20
+    MonthDayYear,
+}
+impl FromStr for DateFormat
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "unix" => Ok(DateFormat::Unix),
+            "iso" => Ok(DateFormat::Iso),
+            "dmy" => Ok(DateFormat::DayMonthYear),
+            "mdy" => Ok(DateFormat::MonthDayYear),
+            _ => Err(format!("unknown timestamp format '{}'", s))
+        }
+    }
+}
+impl DateFormat
+{
+    /// Writes the time in this format, in UTC
+    ///
+    /// # Arguments
+    ///
+    /// * 'timestamp' - The time, in seconds since the unix epoch
+    pub fn format(&self, timestamp: i64) -> String
+    {
+        let (days, secs) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+        let (year, month, day) = civil_from_days(days);
+        let time = format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60);
+        match self
+        {
+            DateFormat::Unix => timestamp.to_string(),
+            DateFormat::Iso => format!("{:04}-{:02}-{:02}T{}Z", year, month, day, time),
+            DateFormat::DayMonthYear => format!("{:02}.{:02}.{:04} {}", day, month, year, time),
+            DateFormat::MonthDayYear => format!("{:02}/{:02}/{:04} {}", month, day, year, time),
+        }
+    }
+    /// Reads a time written in this format, the time of day left out meaning midnight,
+    /// and returns it in seconds since the unix epoch in UTC
+    ///
+    /// Iso times may end in Z or an offset, E.G. "2023-11-14T23:13:20+01:00", which
+    /// is used over the one given
+    ///
+    /// # Arguments
+    ///
+    /// * 'text' - The time, trimmed
+    /// * 'utc_offset' - The offset from UTC of times that don't carry one, in seconds
+    pub fn parse(&self, text: &str, utc_offset: i64) -> Option<i64>
+    {
+        //every date format is ten characters long
+        let (date, rest) = match text.is_char_boundary(10)
+        {
+            true => text.split_at(10),
+            false => (text, "")
+        };
+        let (date, rest) = match self
+        {
+            DateFormat::Unix => return text.parse().ok(),
+            DateFormat::Iso => {
+                let mut parts = date.splitn(3, '-');
+                ((number(parts.next()?, 4)?, number(parts.next()?, 2)?, number(parts.next()?, 2)?), rest)
+            },
+            DateFormat::DayMonthYear => {
+                let mut parts = date.splitn(3, '.');
+                let (day, month) = (number(parts.next()?, 2)?, number(parts.next()?, 2)?);
+                ((number(parts.next()?, 4)?, month, day), rest)
+            },
+            DateFormat::MonthDayYear => {
+                let mut parts = date.splitn(3, '/');
+                let (month, day) = (number(parts.next()?, 2)?, number(parts.next()?, 2)?);
+                ((number(parts.next()?, 4)?, month, day), rest)
+            },
+        };
+        let days = days_from_civil(date)?;
+        let (time, offset) = match rest.strip_prefix(['T', ' '])
+        {
+            None if rest.is_empty() => (0, None),
+            None => return None,
+            Some(rest) => {
+                //only iso times carry their own offset
+                let zone = match self
+                {
+                    DateFormat::Iso => rest.find(['Z', '+', '-']).unwrap_or(rest.len()),
+                    _ => rest.len()
+                };
+                let (time, zone) = rest.split_at(zone);
+                (seconds_of_day(time)?, if zone.is_empty() {None} else {Some(parse_utc_offset(zone).ok()?)})
+            }
+        };
+        Some(days * 86400 + time - offset.unwrap_or(utc_offset))
+    }
+}
+
+/// The year, month and day of a day counted from the unix epoch, in the proleptic
+/// Gregorian calendar
+///
+/// # Arguments
+///
+/// * 'days' - Days since 1970-01-01, negative before it
+fn civil_from_days(days: i64) -> (i64, i64, i64)
+{
+    //shifted so years start in March, putting the leap day at the end of the year
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {shifted_month + 3} else {shifted_month - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    (year, month, day)
+}
+
+/// The day counted from the unix epoch of a date in the proleptic Gregorian calendar,
+/// None if there is no such date
+///
+/// # Arguments
+///
+/// * 'date' - The year, month and day
+fn days_from_civil((year, month, day): (i64, i64, i64)) -> Option<i64>
+{
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month
+    {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None
+    };
+    if day < 1 || day > days_in_month {return None}
+    //shifted so years start in March, putting the leap day at the end of the year
+    let year = if month <= 2 {year - 1} else {year};
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 {month - 3} else {month + 9}) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+/// The seconds since midnight of a time of day, "HH:MM" or "HH:MM:SS" with any
+/// fraction of a second dropped
+///
+/// # Arguments
+///
+/// * 'text' - The time of day
+fn seconds_of_day(text: &str) -> Option<i64>
+{
+    let text = text.split_once('.').map_or(text, |(whole, fraction)| if !fraction.is_empty() && fraction.bytes().all(|b| b.is_ascii_digit()) {whole} else {""});
+    let mut parts = text.split(':');
+    let (hours, minutes) = (number(parts.next()?, 2)?, number(parts.next()?, 2)?);
+    let seconds = match parts.next()
+    {
+        Some(seconds) => number(seconds, 2)?,
+        None => 0
+    };
+    if parts.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {return None}
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+///
+/// How the account report and ledger export are laid out, for the spreadsheets of a region
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExportProfile
+{
+    /// The byte separating fields
+    pub delimiter: u8,
+    /// Whether amounts use a comma for the decimal point, E.G. "1,5"
+    pub decimal_comma: bool,
+    pub date_format: DateFormat,
+}
+impl Default for ExportProfile
+{
+    fn default() -> Self {
+        ExportProfile { delimiter: b',', decimal_comma: false, date_format: DateFormat::Unix }
+    }
+}
+impl ExportProfile
+{
+    /// Writes an amount with the decimal point of the profile
+    ///
+    /// # Arguments
+    ///
+    /// * 'amount' - The amount to write
+    pub fn amount(&self, amount: Amount) -> String
+    {
+        match self.decimal_comma
+        {
+            true => amount.to_string().replace('.', ","),
+            false => amount.to_string()
+        }
+    }
+    /// Returns a csv writer separating fields as the profile does
+    ///
+    /// # Arguments
+    ///
+    /// * 'out' - Where to write the csv to
+    pub fn writer<W: io::Write>(&self, out: W) -> csv::Writer<W>
+    {
+        csv::WriterBuilder::new().delimiter(self.delimiter).from_writer(out)
+    }
+}
+impl FromStr for ExportProfile
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "default" => Ok(ExportProfile::default()),
+            "eu" => Ok(ExportProfile { delimiter: b';', decimal_comma: true, date_format: DateFormat::DayMonthYear }),
+            "us" => Ok(ExportProfile { delimiter: b',', decimal_comma: false, date_format: DateFormat::MonthDayYear }),
+            "iso" => Ok(ExportProfile { delimiter: b',', decimal_comma: false, date_format: DateFormat::Iso }),
+            _ => Err(format!("unknown export profile '{}'", s))
+        }
+    }
+}
+impl fmt::Display for ExportProfile
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+///
+/// An account with its amounts written as the profile has them
+///
+#[derive(Serialize)]
+pub(crate) struct ProfiledAccount
+{
+    client: u16,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+    pending: String,
+}
+impl ProfiledAccount
+{
+    /// Returns the account with its amounts written out
+    ///
+    /// # Arguments
+    ///
+    /// * 'acc' - The account
+    /// * 'profile' - How to write the amounts
+    pub(crate) fn new(acc: &Account, profile: &ExportProfile) -> ProfiledAccount
+    {
+        ProfiledAccount { client: acc.client, available: profile.amount(acc.available), held: profile.amount(acc.held),
+            total: profile.amount(acc.total), locked: acc.locked, pending: profile.amount(acc.pending) }
+    }
+}
+
+///
+/// A single row of the ledger export, as the profile has it
+///
+#[derive(Serialize)]
+struct ProfiledLedgerEntry<'a>
+{
+    client: u16,
+    tx: u32,
+    amount: String,
+    in_dispute: bool,
+    dispute_chain: String,
+    memo: Option<&'a str>,
+    /// When the transaction happened, empty if it had no timestamp
+    date: String,
+    hash: &'a str,
+}
+
+/// Writes the ledger export as write_ledger does, laid out as the profile has it, with a
+/// date column for when each transaction happened
+///
+/// The hashes are those of the default layout, so the ledger head in the report still
+/// matches, but a ledger written with another profile can't be verified
+///
+/// # Arguments
+///
+/// * 'clients' - The clients that have been processed
+/// * 'profile' - How to lay the ledger out
+/// * 'out' - Where to write the ledger to
+pub fn write_ledger_with_profile<W: io::Write>(clients: &ClientStore, profile: &ExportProfile, out: W) -> csv::Result<()>
+{
+    let mut wrtr = profile.writer(out);
+    let mut prev = GENESIS_HASH.to_string();
+    for (client, tx, entry) in ledger_entries(clients)
+    {
+        let fields = chain::entry_fields(client, tx, entry);
+        prev = chain_hash(&prev, &fields.iter().map(|f| f.as_str()).collect::<Vec<_>>());
+        wrtr.serialize(ProfiledLedgerEntry {
+            client,
+            tx,
+            amount: profile.amount(entry.amount),
+            in_dispute: entry.in_dispute,
+            dispute_chain: entry.dispute_chain_text(),
+            memo: entry.memo.as_deref(),
+            date: entry.timestamp.map(|t| profile.date_format.format(t)).unwrap_or_default(),
+            hash: &prev,
+        })?;
+    }
+    wrtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AccountSink, CsvSink, Engine, EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn exports_follow_the_profile()
+    {
+        assert_eq!(DateFormat::Iso.format(1700000000),"2023-11-14T22:13:20Z");
+        assert_eq!(DateFormat::DayMonthYear.format(951782400),"29.02.2000 00:00:00");
+        assert_eq!(DateFormat::MonthDayYear.format(-1),"12/31/1969 23:59:59");
+        assert!("jp".parse::<ExportProfile>().is_err());
+
+        let eu: ExportProfile = "eu".parse().unwrap();
+        let mut engine = Engine::new(EnginePolicy::default());
+        let mut deposit = Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(15000)));
+        deposit.timestamp = Some(1700000000);
+        engine.apply(deposit);
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 2, Some(Amount::from_minor(2500))));
+
+        let mut out = Vec::new();
+        let mut sink = CsvSink::with_profile(&mut out, eu);
+        sink.write_account(&engine.clients[&1].acc).unwrap();
+        sink.finish().unwrap();
+        drop(sink);
+        assert_eq!(String::from_utf8(out).unwrap(),"client;available;held;total;locked;pending\n1;1,75;0,0;1,75;false;0,0\n");
+
+        let mut out = Vec::new();
+        write_ledger_with_profile(&engine.clients, &eu, &mut out).unwrap();
+        let mut plain = Vec::new();
+        crate::write_ledger(&engine.clients, &mut plain).unwrap();
+        let (out, plain) = (String::from_utf8(out).unwrap(), String::from_utf8(plain).unwrap());
+        assert!(out.starts_with("client;tx;amount;in_dispute;dispute_chain;memo;date;hash\n1;1;1,5;false;;;14.11.2023 22:13:20;"));
+        assert!(out.contains("\n1;2;0,25;false;;;;"));
+        assert_eq!(out.lines().last().and_then(|l| l.rsplit(';').next()),plain.lines().last().and_then(|l| l.rsplit(',').next()));
+    }
+    #[test]
+    fn dates_parsed_in_each_format()
+    {
+        assert_eq!(DateFormat::Iso.parse("2023-11-14T22:13:20Z", 3600),Some(1700000000));
+        assert_eq!(DateFormat::DayMonthYear.parse("14.11.2023 22:13:20", 0),Some(1700000000));
+        assert_eq!(DateFormat::MonthDayYear.parse("12/31/1969 23:59:59", 0),Some(-1));
+        assert_eq!(DateFormat::Unix.parse("1700000000", 3600),Some(1700000000));
+    }
+    #[test]
+    fn impossible_dates_not_parsed()
+    {
+        assert_eq!(DateFormat::DayMonthYear.parse("29.02.2023", 0),None);
+        assert_eq!(DateFormat::Iso.parse("2023-11-14T25:00:00Z", 0),None);
+        assert_eq!(DateFormat::Iso.parse("2023-11-14 at noon", 0),None);
+    }
+    #[test]
+    fn date_format_names_parsed()
+    {
+        assert_eq!(("iso".parse(), "dmy".parse(), "mdy".parse()),(Ok(DateFormat::Iso), Ok(DateFormat::DayMonthYear), Ok(DateFormat::MonthDayYear)));
+        assert!("ymd".parse::<DateFormat>().is_err());
+    }
+}
@@ -0,0 +1,258 @@
+// Protobuf messages for transactions and accounts, matching proto/transactions.proto.
+// The types are laid out the way prost-build generates them, but are kept in the
+// tree so building doesn't need protoc
+use std::{convert::TryFrom, fmt};
+use crate::{Amount, AmountError, RejectReason, RoundingMode, TxRecord, TypeTx};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum TxType
+{
+    Unspecified = 0,
+    Deposit = 1,
+    Withdrawal = 2,
+    Dispute = 3,
+    Resolve = 4,
+    Chargeback = 5,
+    ComplianceHold = 6,
+    ComplianceRelease = 7,
+    Representment = 8,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Tx
+{
+    #[prost(enumeration = "TxType", tag = "1")]
+    pub r#type: i32,
+    #[prost(uint32, tag = "2")]
+    pub client: u32,
+    #[prost(uint32, tag = "3")]
+    pub tx: u32,
+    #[prost(string, optional, tag = "4")]
+    pub amount: Option<String>,
+    #[prost(int64, optional, tag = "5")]
+    pub timestamp: Option<i64>,
+    #[prost(string, optional, tag = "6")]
+    pub currency: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub memo: Option<String>,
+    #[prost(string, optional, tag = "8")]
+    pub counterparty: Option<String>,
+    #[prost(string, optional, tag = "9")]
+    pub tenant: Option<String>,
+    #[prost(string, optional, tag = "10")]
+    pub signature: Option<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Account
+{
+    #[prost(uint32, tag = "1")]
+    pub client: u32,
+    #[prost(string, tag = "2")]
+    pub available: String,
+    #[prost(string, tag = "3")]
+    pub held: String,
+    #[prost(string, tag = "4")]
+    pub total: String,
+    #[prost(bool, tag = "5")]
+    pub locked: bool,
+    #[prost(string, tag = "6")]
+    pub pending: String,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TxBatch
+{
+    #[prost(message, repeated, tag = "1")]
+    pub transactions: Vec<Tx>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct AccountBatch
+{
+    #[prost(message, repeated, tag = "1")]
+    pub accounts: Vec<Account>,
+}
+
+///
+/// A protobuf message that doesn't make a valid transaction or account
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtoError
+{
+    /// The type is unspecified or not one we know
+    Type(i32),
+    /// The client ID doesn't fit in 16 bits
+    Client(u32),
+    /// The amount couldn't be parsed
+    Amount(RejectReason),
+}
+impl fmt::Display for ProtoError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            ProtoError::Type(t) => write!(f, "invalid transaction type {}", t),
+            ProtoError::Client(c) => write!(f, "client ID {} is out of range", c),
+            ProtoError::Amount(reason) => write!(f, "invalid amount: {}", reason),
+        }
+    }
+}
+impl From<AmountError> for ProtoError
+{
+    fn from(e: AmountError) -> Self {
+        ProtoError::Amount(e.into())
+    }
+}
+
+impl From<TypeTx> for TxType
+{
+    fn from(t: TypeTx) -> Self {
+        match t
+        {
+            TypeTx::Deposit => TxType::Deposit,
+            TypeTx::Withdrawal => TxType::Withdrawal,
+            TypeTx::Dispute => TxType::Dispute,
+            TypeTx::Resolve => TxType::Resolve,
+            TypeTx::Chargeback => TxType::Chargeback,
+            TypeTx::ComplianceHold => TxType::ComplianceHold,
+            TypeTx::ComplianceRelease => TxType::ComplianceRelease,
+            TypeTx::Representment => TxType::Representment,
+            //custom types have no place in the enum, and are refused when read back
+            TypeTx::Custom => TxType::Unspecified
+        }
+    }
+}
+impl TryFrom<TxType> for TypeTx
+{
+    type Error = ProtoError;
+    fn try_from(t: TxType) -> Result<Self, Self::Error> {
+        match t
+        {
+            TxType::Deposit => Ok(TypeTx::Deposit),
+            TxType::Withdrawal => Ok(TypeTx::Withdrawal),
+            TxType::Dispute => Ok(TypeTx::Dispute),
+            TxType::Resolve => Ok(TypeTx::Resolve),
+            TxType::Chargeback => Ok(TypeTx::Chargeback),
+            TxType::ComplianceHold => Ok(TypeTx::ComplianceHold),
+            TxType::ComplianceRelease => Ok(TypeTx::ComplianceRelease),
+            TxType::Representment => Ok(TypeTx::Representment),
+            TxType::Unspecified => Err(ProtoError::Type(t as i32))
+        }
+    }
+}
+
+impl From<&crate::Tx> for Tx
+{
+    fn from(tx: &crate::Tx) -> Self {
+        Tx {
+            r#type: TxType::from(tx.r#type) as i32,
+            client: tx.client.into(),
+            tx: tx.tx,
+            amount: tx.amount.map(|a| a.to_string()),
+            timestamp: tx.timestamp,
+            currency: tx.currency.clone(),
+            memo: tx.memo.clone(),
+            counterparty: tx.counterparty.clone(),
+            tenant: None,
+            signature: tx.signature.clone()
+        }
+    }
+}
+/// Keeps the amount as text, so the engine can parse it following its policy
+impl TryFrom<Tx> for TxRecord
+{
+    type Error = ProtoError;
+    fn try_from(tx: Tx) -> Result<Self, Self::Error> {
+        let r#type = TxType::try_from(tx.r#type).map_err(|_| ProtoError::Type(tx.r#type))?;
+        let client = u16::try_from(tx.client).map_err(|_| ProtoError::Client(tx.client))?;
+        let mut record = TxRecord::new(TypeTx::try_from(r#type)?, client, tx.tx, tx.amount);
+        record.timestamp = tx.timestamp.map(|t| t.to_string());
+        record.currency = tx.currency;
+        record.memo = tx.memo;
+        record.counterparty = tx.counterparty;
+        record.tenant = tx.tenant;
+        record.signature = tx.signature;
+        Ok(record)
+    }
+}
+/// Amounts with more than four decimals are refused rather than rounded
+impl TryFrom<Tx> for crate::Tx
+{
+    type Error = ProtoError;
+    fn try_from(tx: Tx) -> Result<Self, Self::Error> {
+        TxRecord::try_from(tx)?.to_tx(RoundingMode::Reject).map_err(ProtoError::Amount)
+    }
+}
+
+impl From<&crate::Account> for Account
+{
+    fn from(acc: &crate::Account) -> Self {
+        Account {
+            client: acc.client.into(),
+            available: acc.available.to_string(),
+            held: acc.held.to_string(),
+            total: acc.total.to_string(),
+            locked: acc.locked,
+            pending: acc.pending.to_string()
+        }
+    }
+}
+impl TryFrom<Account> for crate::Account
+{
+    type Error = ProtoError;
+    fn try_from(acc: Account) -> Result<Self, Self::Error> {
+        Ok(crate::Account {
+            client: u16::try_from(acc.client).map_err(|_| ProtoError::Client(acc.client))?,
+            available: acc.available.parse::<Amount>()?,
+            held: acc.held.parse::<Amount>()?,
+            total: acc.total.parse::<Amount>()?,
+            locked: acc.locked,
+            //accounts written before pending was added don't have it
+            pending: if acc.pending.is_empty() {Amount::ZERO} else {acc.pending.parse::<Amount>()?}
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+
+    #[test]
+    fn tx_batch_round_trip()
+    {
+        let mut deposit = crate::Tx::new(TypeTx::Deposit, 3, 7, Some(Amount::from_minor(15000)));
+        deposit.timestamp = Some(1700000000);
+        let batch = TxBatch { transactions: vec![Tx::from(&deposit), Tx::from(&crate::Tx::new(TypeTx::Dispute, 3, 7, None))] };
+        let decoded = TxBatch::decode(batch.encode_to_vec().as_slice()).unwrap();
+        let txs: Vec<crate::Tx> = decoded.transactions.into_iter().map(|t| crate::Tx::try_from(t).unwrap()).collect();
+        assert_eq!(txs[0].amount,Some(Amount::from_minor(15000)));
+        assert_eq!(txs[0].timestamp,Some(1700000000));
+        assert_eq!(txs[1].r#type,TypeTx::Dispute);
+        assert!(txs[1].amount.is_none());
+    }
+    #[test]
+    fn invalid_tx()
+    {
+        let tx = Tx { r#type: TxType::Deposit as i32, client: 70000, tx: 1, amount: Some("1.0".to_string()), ..Tx::default() };
+        assert_eq!(TxRecord::try_from(tx.clone()).err(),Some(ProtoError::Client(70000)));
+        assert_eq!(TxRecord::try_from(Tx { client: 1, r#type: 0, ..tx.clone() }).err(),Some(ProtoError::Type(0)));
+        let precise = Tx { client: 1, amount: Some("1.00001".to_string()), ..tx };
+        assert_eq!(crate::Tx::try_from(precise.clone()).err(),Some(ProtoError::Amount(RejectReason::Precision)));
+        assert!(TxRecord::try_from(precise).is_ok());
+    }
+    #[test]
+    fn account_round_trip()
+    {
+        let mut acc = crate::Account::new(9);
+        acc.held = Amount::from_minor(2500);
+        acc.locked = true;
+        let message = Account::from(&acc);
+        assert_eq!(message.held,"0.25");
+        let back = crate::Account::try_from(Account::decode(message.encode_to_vec().as_slice()).unwrap()).unwrap();
+        assert_eq!(back.held,acc.held);
+        assert!(back.locked);
+    }
+}
@@ -0,0 +1,172 @@
+// the code pymethods generates for PyResult returns trips this lint
+#![allow(clippy::useless_conversion)]
+use std::fs::File;
+use pyo3::{exceptions::{PyIOError, PyValueError}, prelude::*};
+use crate::{Amount, Dialect, Engine, EnginePolicy, TxRecord};
+
+/// Turns an amount into a python Decimal, so no precision is lost
+fn decimal(py: Python<'_>, amount: Amount) -> PyResult<PyObject>
+{
+    let decimal = py.import_bound("decimal")?.getattr("Decimal")?;
+    Ok(decimal.call1((amount.to_string(),))?.unbind())
+}
+
+///
+/// A transaction, with the amount as text so the engine rounds it following its policy
+///
+#[pyclass(name = "Tx")]
+#[derive(Clone)]
+pub struct PyTx
+{
+    record: TxRecord,
+}
+#[pymethods]
+impl PyTx
+{
+    #[new]
+    #[pyo3(signature = (r#type, client, tx, amount=None))]
+    fn new(r#type: &str, client: u16, tx: u32, amount: Option<String>) -> PyResult<PyTx>
+    {
+        let r#type = r#type.parse().map_err(PyValueError::new_err)?;
+        Ok(PyTx { record: TxRecord::new(r#type, client, tx, amount) })
+    }
+    #[getter]
+    fn r#type(&self) -> &'static str
+    {
+        self.record.r#type.as_str()
+    }
+    #[getter]
+    fn client(&self) -> u16
+    {
+        self.record.client
+    }
+    #[getter]
+    fn tx(&self) -> u32
+    {
+        self.record.tx
+    }
+    #[getter]
+    fn amount(&self) -> Option<String>
+    {
+        self.record.amount.clone()
+    }
+}
+
+///
+/// The balances of a single client
+///
+#[pyclass(name = "Account", get_all)]
+pub struct PyAccount
+{
+    client: u16,
+    available: PyObject,
+    held: PyObject,
+    total: PyObject,
+    locked: bool,
+    pending: PyObject,
+}
+impl PyAccount
+{
+    fn new(py: Python<'_>, acc: &crate::Account) -> PyResult<PyAccount>
+    {
+        Ok(PyAccount {
+            client: acc.client,
+            available: decimal(py, acc.available)?,
+            held: decimal(py, acc.held)?,
+            total: decimal(py, acc.total)?,
+            locked: acc.locked,
+            pending: decimal(py, acc.pending)?
+        })
+    }
+}
+
+///
+/// The engine, with the same dispute semantics as the binary
+///
+#[pyclass(name = "Engine")]
+pub struct PyEngine
+{
+    engine: Engine,
+}
+#[pymethods]
+impl PyEngine
+{
+    /// Takes the rounding mode as on the command line, "reject" by default
+    #[new]
+    #[pyo3(signature = (rounding=None))]
+    fn new(rounding: Option<&str>) -> PyResult<PyEngine>
+    {
+        let mut policy = EnginePolicy::default();
+        if let Some(rounding) = rounding
+        {
+            policy.rounding = rounding.parse().map_err(|_| PyValueError::new_err(format!("unknown rounding mode '{}'", rounding)))?;
+        }
+        Ok(PyEngine { engine: Engine::new(policy) })
+    }
+    /// Applies every transaction in a csv file, E.G. one exported from pandas
+    fn process_csv(&mut self, path: &str) -> PyResult<()>
+    {
+        let file = File::open(path).map_err(|e| PyIOError::new_err(e.to_string()))?;
+        let dialect = Dialect::default();
+        let records = dialect.read_records(file, self.engine.policy.schema).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        for record in records
+        {
+            self.engine.apply_record(record);
+        }
+        Ok(())
+    }
+    /// Applies a single transaction
+    fn apply(&mut self, tx: &PyTx)
+    {
+        self.engine.apply_record(tx.record.clone());
+    }
+    /// Returns the account of a client, or None if the client hasn't been seen
+    fn account(&self, py: Python<'_>, client: u16) -> PyResult<Option<PyAccount>>
+    {
+        self.engine.clients.get(&client).map(|c| PyAccount::new(py, &c.acc)).transpose()
+    }
+    /// Returns every account, ordered by client
+    fn accounts(&self, py: Python<'_>) -> PyResult<Vec<PyAccount>>
+    {
+        let mut ids: Vec<u16> = self.engine.clients.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| PyAccount::new(py, &self.engine.clients[&id].acc)).collect()
+    }
+    /// How many transactions were refused
+    #[getter]
+    fn rejected(&self) -> usize
+    {
+        self.engine.rejections.len()
+    }
+}
+
+#[pymodule]
+fn csv_transactions(m: &Bound<'_, PyModule>) -> PyResult<()>
+{
+    m.add_class::<PyEngine>()?;
+    m.add_class::<PyTx>()?;
+    m.add_class::<PyAccount>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn engine_from_python()
+    {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut engine = PyEngine::new(Some("truncate")).unwrap();
+            engine.apply(&PyTx::new("deposit", 1, 1, Some("1.23456".to_string())).unwrap());
+            engine.apply(&PyTx::new("withdrawal", 1, 2, None).unwrap());
+            assert_eq!(engine.rejected(),1);
+            let acc = engine.account(py, 1).unwrap().unwrap();
+            assert_eq!(acc.available.bind(py).str().unwrap().to_string(),"1.2345");
+            assert!(engine.account(py, 2).unwrap().is_none());
+            assert!(PyTx::new("refund", 1, 1, None).is_err());
+            assert!(PyEngine::new(Some("up")).is_err());
+        });
+    }
+}
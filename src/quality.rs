@@ -0,0 +1,182 @@
+use std::{collections::{BTreeMap, HashSet}, convert::TryFrom, fmt, io};
+use serde::Serialize;
+use crate::{Amount, Dialect, HeaderError, RoundingMode, SchemaMode, TypeTx};
+
+///
+/// Field-level quality metrics of a sample of the input, to spot problems with a feed
+/// before a full run
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DataProfile
+{
+    /// How many rows were profiled
+    pub rows: u64,
+    /// Rows that couldn't be read as a transaction at all, E.G. a client that isn't a number
+    pub unreadable: u64,
+    /// How many rows of each type there were, custom ones by their name
+    pub types: BTreeMap<String, u64>,
+    /// How many different clients the rows were for
+    pub clients: usize,
+    /// Deposits and withdrawals without an amount
+    pub missing_amounts: u64,
+    /// Disputes, resolves and chargebacks with an amount
+    pub unexpected_amounts: u64,
+    /// Amounts that couldn't be read as a number
+    pub invalid_amounts: u64,
+    /// How many amounts were given with each number of decimals
+    pub precision: BTreeMap<usize, u64>,
+    /// The lowest and highest transaction IDs of the deposits and withdrawals
+    pub min_tx: Option<u32>,
+    pub max_tx: Option<u32>,
+    /// Deposits and withdrawals reusing the ID of one earlier in the sample
+    pub duplicate_txs: u64,
+    /// IDs between the lowest and highest that no deposit or withdrawal has
+    pub missing_txs: u64,
+    /// The longest run of missing IDs
+    pub largest_gap: u64,
+}
+impl DataProfile
+{
+    /// Takes a row into the profile
+    ///
+    /// # Arguments
+    ///
+    /// * 'r#type' - The type of the row
+    /// * 'name' - The name of the type, as given for custom ones
+    /// * 'amount' - The amount as given
+    /// * 'invalid' - Whether the amount parser of the dialect couldn't read the amount
+    fn add(&mut self, r#type: TypeTx, name: &str, amount: Option<&str>, invalid: bool)
+    {
+        *self.types.entry(name.to_string()).or_insert(0) += 1;
+        let amount = amount.map(str::trim).filter(|a| !a.is_empty());
+        match (r#type.carries_amount(), amount)
+        {
+            (true, None) => self.missing_amounts += 1,
+            (false, Some(_)) if r#type != TypeTx::Custom => self.unexpected_amounts += 1,
+            _ => {}
+        }
+        let Some(amount) = amount else {return};
+        if invalid || Amount::parse(amount, RoundingMode::HalfEven).is_err()
+        {
+            self.invalid_amounts += 1;
+            return;
+        }
+        let decimals = amount.split_once('.').map_or(0, |(_, frac)| frac.len());
+        *self.precision.entry(decimals).or_insert(0) += 1;
+    }
+    /// Works out the gaps in the transaction IDs
+    ///
+    /// # Arguments
+    ///
+    /// * 'ids' - The distinct IDs of the deposits and withdrawals
+    fn find_gaps(&mut self, ids: HashSet<u32>)
+    {
+        let mut ids: Vec<u32> = ids.into_iter().collect();
+        ids.sort_unstable();
+        self.min_tx = ids.first().copied();
+        self.max_tx = ids.last().copied();
+        for pair in ids.windows(2)
+        {
+            let gap = (pair[1] - pair[0] - 1) as u64;
+            self.missing_txs += gap;
+            self.largest_gap = self.largest_gap.max(gap);
+        }
+    }
+}
+impl fmt::Display for DataProfile
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        writeln!(f, "rows profiled: {}", self.rows)?;
+        writeln!(f, "unreadable rows: {}", self.unreadable)?;
+        writeln!(f, "clients: {}", self.clients)?;
+        writeln!(f, "types: {}", self.types.iter().map(|(t, n)| format!("{}: {}", t, n)).collect::<Vec<_>>().join(", "))?;
+        writeln!(f, "missing amounts: {}", self.missing_amounts)?;
+        writeln!(f, "unexpected amounts: {}", self.unexpected_amounts)?;
+        writeln!(f, "invalid amounts: {}", self.invalid_amounts)?;
+        writeln!(f, "decimals: {}", self.precision.iter().map(|(d, n)| format!("{}: {}", d, n)).collect::<Vec<_>>().join(", "))?;
+        if let (Some(min), Some(max)) = (self.min_tx, self.max_tx)
+        {
+            writeln!(f, "tx ids: {} to {}", min, max)?;
+        }
+        writeln!(f, "duplicate tx ids: {}", self.duplicate_txs)?;
+        writeln!(f, "missing tx ids: {}, the longest gap {}", self.missing_txs, self.largest_gap)
+    }
+}
+
+impl Dialect
+{
+    /// Profiles the rows at the start of the input, without applying any of them
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - Where to read the csv from
+    /// * 'sample' - How many rows to profile, every row if None
+    /// * 'schema' - Whether columns we don't know about are an error
+    ///
+    /// # Errors
+    ///
+    /// Returns a HeaderError if the headers don't match the expected columns after renaming
+    pub fn profile<R: io::Read>(&self, input: R, sample: Option<u64>, schema: SchemaMode) -> Result<DataProfile, HeaderError>
+    {
+        let mut rdr = self.reader(input);
+        let headers = rdr.headers().cloned().unwrap_or_default();
+        let headers = self.map_headers(&headers, schema)?;
+        let mut profile = DataProfile::default();
+        let mut clients = HashSet::new();
+        let mut ids = HashSet::new();
+        for row in rdr.into_records().take(sample.map_or(usize::MAX, |n| usize::try_from(n).unwrap_or(usize::MAX)))
+        {
+            profile.rows += 1;
+            let record = match row.ok().and_then(|row| self.record(&row, &headers))
+            {
+                Some(record) => record,
+                None => {
+                    profile.unreadable += 1;
+                    continue;
+                }
+            };
+            profile.add(record.r#type, record.custom.as_deref().unwrap_or(record.r#type.as_str()), record.amount.as_deref(), record.invalid_amount);
+            clients.insert(record.client);
+            if record.r#type.carries_amount() && !ids.insert(record.tx)
+            {
+                profile.duplicate_txs += 1;
+            }
+        }
+        profile.clients = clients.len();
+        profile.find_gaps(ids);
+        Ok(profile)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_profiled()
+    {
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,1.5\n\
+            deposit,2,2,\n\
+            withdrawal,1,5,0.1234\n\
+            dispute,1,1,2.0\n\
+            deposit,x,6,1.0\n\
+            deposit,3,5,abc\n\
+            bonus,3,9,1\n\
+            deposit,3,10,3\n";
+        let profile = Dialect::default().profile(input.as_bytes(), None, SchemaMode::Lenient).unwrap();
+        assert_eq!(profile.rows,8);
+        assert_eq!(profile.unreadable,1);
+        assert_eq!(profile.clients,3);
+        assert_eq!(profile.types.iter().map(|(t, n)| (t.as_str(), *n)).collect::<Vec<_>>(),vec![("bonus", 1), ("deposit", 4), ("dispute", 1), ("withdrawal", 1)]);
+        assert_eq!((profile.missing_amounts, profile.unexpected_amounts, profile.invalid_amounts),(1, 1, 1));
+        assert_eq!(profile.precision.into_iter().collect::<Vec<_>>(),vec![(0, 2), (1, 2), (4, 1)]);
+        assert_eq!((profile.min_tx, profile.max_tx, profile.duplicate_txs),(Some(1), Some(10), 1));
+        assert_eq!((profile.missing_txs, profile.largest_gap),(6, 4));
+
+        let head = Dialect::default().profile(input.as_bytes(), Some(2), SchemaMode::Lenient).unwrap();
+        assert_eq!((head.rows, head.clients, head.missing_amounts),(2, 2, 1));
+        assert!(head.to_string().starts_with("rows profiled: 2\nunreadable rows: 0\nclients: 2\ntypes: deposit: 2\n"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::{fs::File, io, ops::Deref, path::Path};
+use serde::Serialize;
+use crate::{Amount, Client, ClientTransaction, Engine, EnginePolicy, SnapshotError, SnapshotKey};
+
+///
+/// An engine restored from a snapshot that can be looked at but not changed, for
+/// inspecting persisted state without any risk of writing to it
+///
+/// It derefs to the engine, but only ever as a shared reference, so nothing can be
+/// applied to it and it can't be snapshotted over the file it came from
+///
+pub struct ReadOnlyEngine
+{
+    engine: Engine,
+}
+impl Deref for ReadOnlyEngine
+{
+    type Target = Engine;
+    fn deref(&self) -> &Engine
+    {
+        &self.engine
+    }
+}
+impl ReadOnlyEngine
+{
+    /// The client, if the snapshot has an account for it
+    pub fn client(&self, client: u16) -> Option<&Client>
+    {
+        self.engine.clients.get(&client)
+    }
+    /// The deposits and withdrawals of the client, in the order they came in, or
+    /// ordered by transaction ID where the rows aren't known
+    pub fn history(&self, client: u16) -> Vec<HistoryRow<'_>>
+    {
+        let mut rows: Vec<HistoryRow> = self.client(client).map_or_else(Vec::new, |c| c.history.iter()
+            .map(|(tx, entry)| HistoryRow::new(client, *tx, entry)).collect());
+        rows.sort_by_key(|r| (r.row, r.tx));
+        rows
+    }
+}
+
+///
+/// A transaction in the history of a client, as the query subcommand writes it
+///
+#[derive(Debug, Serialize)]
+pub struct HistoryRow<'a>
+{
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Amount,
+    pub in_dispute: bool,
+    /// The disputes, resolves, chargebacks and representments applied to it, E.G. "dispute>chargeback"
+    pub dispute_chain: String,
+    pub memo: Option<&'a str>,
+    /// The row it came in at, 0 if it isn't known
+    pub row: u64,
+    pub timestamp: Option<i64>,
+}
+impl<'a> HistoryRow<'a>
+{
+    fn new(client: u16, tx: u32, entry: &'a ClientTransaction) -> HistoryRow<'a>
+    {
+        HistoryRow { client, tx, amount: entry.amount, in_dispute: entry.in_dispute, dispute_chain: entry.dispute_chain_text(),
+            memo: entry.memo.as_deref(), row: entry.row, timestamp: entry.timestamp }
+    }
+}
+
+/// Writes history rows as csv
+///
+/// # Arguments
+///
+/// * 'rows' - The rows, in the order they are written
+/// * 'out' - Where to write them to
+pub fn write_history<W: io::Write>(rows: &[HistoryRow], out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for row in rows
+    {
+        wrtr.serialize(row)?;
+    }
+    wrtr.flush()?;
+    Ok(())
+}
+
+impl Engine
+{
+    /// Restores the snapshot at the path as an engine that can only be looked at
+    ///
+    /// The file is only ever opened for reading
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - The snapshot, as written by snapshot_to
+    /// * 'key' - The key the snapshot was encrypted with, if it was
+    pub fn open_read_only(path: &Path, key: Option<&SnapshotKey>) -> Result<ReadOnlyEngine, SnapshotError>
+    {
+        let engine = Engine::restore_from(io::BufReader::new(File::open(path)?), EnginePolicy::default(), key)?;
+        Ok(ReadOnlyEngine { engine })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::{Tx, TypeTx};
+
+    #[test]
+    fn query_snapshot()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(Tx::new(TypeTx::Deposit, 42, 7, Some(Amount::from_minor(15000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 42, 3, Some(Amount::from_minor(5000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 42, 7, None));
+        let path = std::env::temp_dir().join(format!("query_{}.json", std::process::id()));
+        engine.snapshot_to(File::create(&path).unwrap(), None).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        let snapshot = Engine::open_read_only(&path, None).unwrap();
+        assert_eq!(snapshot.client(42).map(|c| c.acc.held),Some(Amount::from_minor(15000)));
+        assert!(snapshot.client(1).is_none() && snapshot.history(1).is_empty());
+        let history = snapshot.history(42);
+        assert_eq!(history.iter().map(|r| (r.tx, r.in_dispute)).collect::<Vec<_>>(),vec![(7, true), (3, false)]);
+        let mut out = Vec::new();
+        write_history(&history, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("client,tx,amount,in_dispute,dispute_chain,memo,row,timestamp\n42,7,1.5,true,dispute,,1,\n"));
+        assert_eq!(fs::read(&path).unwrap(),before);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,356 @@
+use std::{collections::VecDeque, fs::File, io, sync::{Condvar, Mutex}, time::{Duration, Instant}};
+use serde::Serialize;
+use crate::{Engine, RejectReason, Rejection, SlowCause, TxRecord};
+
+///
+/// What happens to a record pushed onto a full queue
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Overflow
+{
+    /// The producer waits until the engine has taken something off the queue
+    Block,
+    /// The record is dropped, and later reported as rejected with queue_full
+    Shed,
+    /// The record is written to a csv file at this path, and read back once the
+    /// queue has drained, so nothing is lost and the order is kept
+    Spill(String),
+}
+
+///
+/// How deep the queue is and what it had to do about overflowing
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QueueStats
+{
+    /// Records waiting, in memory and spilled
+    pub depth: usize,
+    /// The deepest the queue has been
+    pub high_water: usize,
+    /// Records dropped as the queue was full
+    pub shed: u64,
+    /// Records written to disk as the queue was full
+    pub spilled: u64,
+}
+
+/// The records spilled to disk, read back in the order they were written; the file is
+/// removed once they have all been read back, or the queue is dropped
+struct Spill
+{
+    path: String,
+    writer: csv::Writer<File>,
+    /// Opened once there is something to read, as it takes the header row straight away
+    reader: Option<csv::DeserializeRecordsIntoIter<File, TxRecord>>,
+    /// Written but not yet read back
+    waiting: usize,
+}
+
+impl Spill
+{
+    /// Reads back the oldest record not yet read
+    ///
+    /// # Errors
+    ///
+    /// Fails if the file can't be read, or ends before every record written to it, in
+    /// which case the record is lost
+    fn read(&mut self) -> io::Result<TxRecord>
+    {
+        self.waiting -= 1;
+        if self.reader.is_none()
+        {
+            self.reader = Some(csv::Reader::from_path(&self.path)?.into_deserialize());
+        }
+        match self.reader.as_mut().and_then(Iterator::next)
+        {
+            Some(record) => Ok(record?),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("the spill file {} ended before every record was read back", self.path)))
+        }
+    }
+}
+impl Drop for Spill
+{
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+struct QueueState
+{
+    records: VecDeque<TxRecord>,
+    spill: Option<Spill>,
+    /// The records shed, kept to be reported as rejections
+    shed: Vec<Rejection>,
+    stats: QueueStats,
+    closed: bool,
+}
+impl QueueState
+{
+    fn spilled(&self) -> usize
+    {
+        self.spill.as_ref().map_or(0, |s| s.waiting)
+    }
+    /// Reads back the oldest spilled record, dropping the spill file once it is drained
+    fn read_spill(&mut self) -> io::Result<Option<TxRecord>>
+    {
+        let Some(spill) = self.spill.as_mut().filter(|s| s.waiting > 0) else {return Ok(None)};
+        let record = spill.read();
+        if spill.waiting == 0
+        {
+            self.spill = None;
+        }
+        record.map(Some)
+    }
+}
+
+///
+/// A bounded queue between whatever takes in transactions and the engine, so
+/// a burst of input can't grow memory without end
+///
+/// Producers push from any thread, and a single consumer applies the records
+/// with Engine::apply_queue
+///
+pub struct IngestQueue
+{
+    capacity: usize,
+    overflow: Overflow,
+    state: Mutex<QueueState>,
+    /// Signalled when a record is pushed or the queue is closed
+    not_empty: Condvar,
+    /// Signalled when a record is taken off
+    not_full: Condvar,
+}
+impl IngestQueue
+{
+    /// Returns an empty queue
+    ///
+    /// # Arguments
+    ///
+    /// * 'capacity' - How many records are held in memory, at least one
+    /// * 'overflow' - What to do with records pushed while it is full
+    pub fn new(capacity: usize, overflow: Overflow) -> IngestQueue
+    {
+        let state = QueueState { records: VecDeque::new(), spill: None, shed: Vec::new(), stats: QueueStats::default(), closed: false };
+        IngestQueue { capacity: capacity.max(1), overflow, state: Mutex::new(state), not_empty: Condvar::new(), not_full: Condvar::new() }
+    }
+    /// Adds a record to the back of the queue, following the overflow policy if it is full
+    ///
+    /// Returns false if the record was shed, or the queue was closed
+    ///
+    /// # Arguments
+    ///
+    /// * 'record' - The transaction as taken in
+    ///
+    /// # Errors
+    ///
+    /// Fails if the record had to be spilled and couldn't be written
+    pub fn push(&self, record: TxRecord) -> io::Result<bool>
+    {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Overflow::Block = self.overflow
+        {
+            while state.records.len() >= self.capacity && !state.closed
+            {
+                state = self.not_full.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        }
+        if state.closed {return Ok(false)}
+        //once anything is spilled, everything after it is too, so the order is kept
+        let full = state.records.len() >= self.capacity || state.spilled() > 0;
+        match (&self.overflow, full)
+        {
+            (Overflow::Shed, true) => {
+                state.shed.push(Rejection { client: record.client, tx: record.tx, r#type: record.r#type, amount: record.amount, reason: RejectReason::QueueFull, rule: None });
+                state.stats.shed += 1;
+                return Ok(false);
+            },
+            (Overflow::Spill(path), true) => {
+                if state.spill.is_none()
+                {
+                    state.spill = Some(Spill { path: path.clone(), writer: csv::Writer::from_path(path)?, reader: None, waiting: 0 });
+                }
+                if let Some(spill) = &mut state.spill
+                {
+                    spill.writer.serialize(&record)?;
+                    spill.writer.flush()?;
+                    spill.waiting += 1;
+                }
+                state.stats.spilled += 1;
+            },
+            _ => state.records.push_back(record)
+        }
+        state.stats.depth = state.records.len() + state.spilled();
+        state.stats.high_water = state.stats.high_water.max(state.stats.depth);
+        self.not_empty.notify_one();
+        Ok(true)
+    }
+    /// Takes the record at the front of the queue, waiting for one to be pushed
+    ///
+    /// Returns None once the queue is closed and empty
+    ///
+    /// # Errors
+    ///
+    /// Fails if the record had to be read back from the spill file and couldn't be
+    pub fn pop(&self) -> io::Result<Option<TxRecord>>
+    {
+        self.pop_timed().0
+    }
+    /// Takes the record at the front of the queue as pop does, along with how long
+    /// it waited for the lock and how long reading it back from the spill file took
+    fn pop_timed(&self) -> (io::Result<Option<TxRecord>>, Duration, Duration)
+    {
+        let asked = Instant::now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let contention = asked.elapsed();
+        loop
+        {
+            let read = Instant::now();
+            let (record, spill) = match state.records.pop_front()
+            {
+                Some(record) => (Ok(Some(record)), Duration::ZERO),
+                None => (state.read_spill(), read.elapsed())
+            };
+            if !matches!(record, Ok(None)) || (state.records.is_empty() && state.spilled() == 0 && state.closed)
+            {
+                state.stats.depth = state.records.len() + state.spilled();
+                self.not_full.notify_one();
+                return (record, contention, spill);
+            }
+            if state.records.is_empty() && state.spilled() == 0
+            {
+                state = self.not_empty.wait(state).unwrap_or_else(|e| e.into_inner());
+            }
+        }
+    }
+    /// Stops taking records; those already queued can still be taken
+    pub fn close(&self)
+    {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+    /// How many records are waiting, in memory and spilled
+    pub fn depth(&self) -> usize
+    {
+        self.stats().depth
+    }
+    /// The depth and overflow counts of the queue
+    pub fn stats(&self) -> QueueStats
+    {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).stats
+    }
+    /// Hands over the records shed since the last call, as rejections
+    fn take_shed(&self) -> Vec<Rejection>
+    {
+        std::mem::take(&mut self.state.lock().unwrap_or_else(|e| e.into_inner()).shed)
+    }
+}
+
+impl Engine
+{
+    /// Applies records from the queue as they arrive, until it is closed and empty
+    ///
+    /// Records the queue shed are added to the rejections as queue_full
+    ///
+    /// # Arguments
+    ///
+    /// * 'queue' - The queue the intake pushes onto
+    ///
+    /// # Errors
+    ///
+    /// Fails if a spilled record couldn't be read back, leaving the rest in the queue
+    pub fn apply_queue(&mut self, queue: &IngestQueue) -> io::Result<()>
+    {
+        loop
+        {
+            let (record, contention, spill) = queue.pop_timed();
+            let Some(record) = record? else {break};
+            for rejection in queue.take_shed()
+            {
+                self.push_rejection(rejection);
+            }
+            //the row is timed from when it was asked for, leaving out any wait for one to be pushed
+            let start = self.start_timing().map(|now| now.checked_sub(contention + spill).unwrap_or(now));
+            if start.is_some()
+            {
+                self.row_timings.push((SlowCause::LockContention, contention));
+                self.row_timings.push((SlowCause::DiskSpill, spill));
+            }
+            self.apply_record_since(record, start);
+        }
+        for rejection in queue.take_shed()
+        {
+            self.push_rejection(rejection);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+    use crate::{Amount, EnginePolicy, TypeTx};
+
+    fn deposit(tx: u32) -> TxRecord
+    {
+        TxRecord::new(TypeTx::Deposit, 1, tx, Some("1.0".to_string()))
+    }
+
+    #[test]
+    fn shed_records_rejected()
+    {
+        let shed = IngestQueue::new(2, Overflow::Shed);
+        assert!(shed.push(deposit(1)).unwrap() && shed.push(deposit(2)).unwrap());
+        assert!(!shed.push(deposit(3)).unwrap());
+        shed.close();
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_queue(&shed).unwrap();
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(20000));
+        assert_eq!((engine.rejections[0].tx, engine.rejections[0].reason),(3, RejectReason::QueueFull));
+        assert_eq!(shed.stats(),QueueStats { depth: 0, high_water: 2, shed: 1, spilled: 0 });
+    }
+    #[test]
+    fn spilled_records_read_back_in_order()
+    {
+        let path = std::env::temp_dir().join(format!("spill_{}.csv", std::process::id()));
+        let spill = IngestQueue::new(1, Overflow::Spill(path.to_string_lossy().into_owned()));
+        for tx in 1..=4 {spill.push(deposit(tx)).unwrap();}
+        assert_eq!((spill.depth(), spill.stats().spilled),(4, 3));
+        assert_eq!(spill.pop().unwrap().map(|r| r.tx),Some(1));
+        spill.push(deposit(5)).unwrap();
+        spill.close();
+        let order: Vec<u32> = std::iter::from_fn(|| spill.pop().unwrap()).map(|r| r.tx).collect();
+        assert_eq!(order,vec![2, 3, 4, 5]);
+        //drained, so the file is gone
+        assert!(!path.exists());
+    }
+    #[test]
+    fn unreadable_spill_fails()
+    {
+        let path = std::env::temp_dir().join(format!("spill_lost_{}.csv", std::process::id()));
+        let spill = IngestQueue::new(1, Overflow::Spill(path.to_string_lossy().into_owned()));
+        for tx in 1..=3 {spill.push(deposit(tx)).unwrap();}
+        std::fs::remove_file(&path).unwrap();
+        spill.close();
+        let mut engine = Engine::new(EnginePolicy::default());
+        assert!(engine.apply_queue(&spill).is_err());
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(10000));
+    }
+    #[test]
+    fn blocked_producer_waits()
+    {
+        let block = Arc::new(IngestQueue::new(1, Overflow::Block));
+        let producer = {
+            let block = Arc::clone(&block);
+            thread::spawn(move || {
+                for tx in 1..=50 {block.push(deposit(tx)).unwrap();}
+                block.close();
+            })
+        };
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_queue(&block).unwrap();
+        producer.join().unwrap();
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(500000));
+        assert_eq!(block.stats().high_water,1);
+    }
+}
@@ -0,0 +1,213 @@
+use std::{collections::BTreeMap, io};
+use serde::{Deserialize, Serialize};
+use crate::{Account, Amount, ClientStore};
+
+///
+/// The balances an external system expects a client to have, any of which may be left out
+///
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct ExpectedBalance
+{
+    pub client: u16,
+    #[serde(default)]
+    pub available: Option<Amount>,
+    #[serde(default)]
+    pub held: Option<Amount>,
+    #[serde(default)]
+    pub total: Option<Amount>,
+    #[serde(default)]
+    pub locked: Option<bool>,
+}
+
+/// Reads the expected balances, a csv with a client column and any of available, held,
+/// total and locked
+///
+/// A client listed twice keeps its last row
+///
+/// # Arguments
+///
+/// * 'input' - The balances as csv, with a header row
+pub fn read_expected<R: io::Read>(input: R) -> csv::Result<BTreeMap<u16, ExpectedBalance>>
+{
+    let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+    let mut expected = BTreeMap::new();
+    for row in rdr.deserialize()
+    {
+        let row: ExpectedBalance = row?;
+        expected.insert(row.client, row);
+    }
+    Ok(expected)
+}
+
+///
+/// How far a balance may be from the expected one and still match
+///
+/// A balance matches if it is within the absolute tolerance, or within the relative one
+/// of the expected balance, whichever allows more
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Tolerance
+{
+    pub absolute: Amount,
+    /// A share of the expected balance, E.G. 0.001 for a tenth of a percent
+    pub relative: Amount,
+}
+impl Tolerance
+{
+    /// Whether the actual balance is close enough to the expected one
+    ///
+    /// # Arguments
+    ///
+    /// * 'expected' - The balance the external file has
+    /// * 'actual' - The balance the engine came to
+    pub fn allows(&self, expected: Amount, actual: Amount) -> bool
+    {
+        let abs = |a: Amount| if a.is_negative() {Amount::ZERO.checked_sub(a).unwrap_or(Amount::MAX)} else {a};
+        let difference = match actual.checked_sub(expected)
+        {
+            Some(difference) => abs(difference),
+            None => return false
+        };
+        let relative = abs(expected).checked_mul_rate(self.relative).unwrap_or(Amount::MAX);
+        difference <= self.absolute.max(relative)
+    }
+}
+
+///
+/// A balance the engine and the external file disagree on
+///
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Discrepancy
+{
+    pub client: u16,
+    /// The balance, or account when the client is only on one side
+    pub field: &'static str,
+    /// What the external file has, "missing" when it has no row for the client
+    pub expected: String,
+    /// What the engine came to, "missing" when it has no account for the client
+    pub actual: String,
+    /// The actual balance less the expected one
+    pub difference: Option<Amount>,
+}
+
+///
+/// The outcome of reconciling the accounts against the expected balances
+///
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reconciliation
+{
+    /// The clients on either side
+    pub clients: usize,
+    /// The clients with at least one discrepancy
+    pub mismatched: usize,
+    /// Every discrepancy, ordered by client
+    pub discrepancies: Vec<Discrepancy>,
+}
+impl Reconciliation
+{
+    /// Whether the engine and the external file agree on every client
+    pub fn is_clean(&self) -> bool
+    {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Compares the accounts to the expected balances, client by client
+///
+/// Only the balances the external file gives are compared. A client with an account but
+/// no row, or a row but no account, is a discrepancy of the whole account
+///
+/// # Arguments
+///
+/// * 'clients' - The accounts the engine came to
+/// * 'expected' - The balances an external system expects, as read by read_expected
+/// * 'tolerance' - How far apart balances may be and still match
+pub fn reconcile(clients: &ClientStore, expected: &BTreeMap<u16, ExpectedBalance>, tolerance: Tolerance) -> Reconciliation
+{
+    let mut ids: Vec<u16> = clients.keys().chain(expected.keys().copied()).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let mut reconciliation = Reconciliation { clients: ids.len(), ..Reconciliation::default() };
+    for id in ids
+    {
+        let found = reconciliation.discrepancies.len();
+        match (clients.get(&id).map(|c| &c.acc), expected.get(&id))
+        {
+            (Some(_), None) => reconciliation.discrepancies.push(Discrepancy { client: id, field: "account", expected: "missing".to_string(), actual: "present".to_string(), difference: None }),
+            (None, Some(_)) => reconciliation.discrepancies.push(Discrepancy { client: id, field: "account", expected: "present".to_string(), actual: "missing".to_string(), difference: None }),
+            (Some(account), Some(row)) => compare(account, row, tolerance, &mut reconciliation.discrepancies),
+            (None, None) => ()
+        }
+        if reconciliation.discrepancies.len() > found
+        {
+            reconciliation.mismatched += 1;
+        }
+    }
+    reconciliation
+}
+
+/// Adds a discrepancy for every balance of the account that doesn't match the expected row
+fn compare(account: &Account, row: &ExpectedBalance, tolerance: Tolerance, discrepancies: &mut Vec<Discrepancy>)
+{
+    let balances = [("available", row.available, account.available), ("held", row.held, account.held), ("total", row.total, account.total)];
+    for (field, expected, actual) in balances
+    {
+        match expected
+        {
+            Some(expected) if !tolerance.allows(expected, actual) => discrepancies.push(Discrepancy {
+                client: account.client, field, expected: expected.to_string(), actual: actual.to_string(), difference: actual.checked_sub(expected)
+            }),
+            _ => ()
+        }
+    }
+    if let Some(locked) = row.locked.filter(|locked| *locked != account.locked)
+    {
+        discrepancies.push(Discrepancy { client: account.client, field: "locked", expected: locked.to_string(), actual: account.locked.to_string(), difference: None });
+    }
+}
+
+/// Writes the discrepancies as csv
+///
+/// # Arguments
+///
+/// * 'discrepancies' - The discrepancies, as reconcile found them
+/// * 'out' - Where to write them to
+pub fn write_discrepancies<W: io::Write>(discrepancies: &[Discrepancy], out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for d in discrepancies
+    {
+        wrtr.serialize(d)?;
+    }
+    wrtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn discrepancies_outside_tolerance()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(1_000_000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 3, 3, Some(Amount::from_minor(10000))));
+        let expected = read_expected("client,available,held,total,locked\n1,100.05,0,,false\n2, 2.5 ,,,\n4,1.0,,,\n".as_bytes()).unwrap();
+        let tolerance = Tolerance { absolute: Amount::from_minor(1), relative: Amount::from_minor(10) };
+
+        let reconciliation = reconcile(&engine.clients, &expected, tolerance);
+        assert_eq!((reconciliation.clients, reconciliation.mismatched),(4, 3));
+        assert_eq!(reconciliation.discrepancies.iter().map(|d| (d.client, d.field, d.difference)).collect::<Vec<_>>(),vec![
+            (2, "available", Some(Amount::from_minor(-5000))),
+            (3, "account", None),
+            (4, "account", None),
+        ]);
+        let mut out = Vec::new();
+        write_discrepancies(&reconciliation.discrepancies, &mut out).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("client,field,expected,actual,difference\n2,available,2.5,2.0,-0.5\n3,account,missing,present,\n"));
+        assert!(reconcile(&engine.clients, &expected, Tolerance::default()).discrepancies.iter().any(|d| d.client == 1 && d.field == "available"));
+    }
+}
@@ -0,0 +1,227 @@
+use std::fmt;
+use redis::{Commands, Connection};
+use crate::{Client, ClientStore, Engine, EnginePolicy, Rejection, Tx, TxRecord};
+
+///
+/// Something went wrong reading or writing the shared state
+///
+#[derive(Debug)]
+pub enum StateError
+{
+    Redis(redis::RedisError),
+    /// A stored client that couldn't be read back
+    Decode(serde_json::Error),
+    /// A client that couldn't be written out to be stored
+    Encode(serde_json::Error),
+}
+impl fmt::Display for StateError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            StateError::Redis(e) => write!(f, "{}", e),
+            StateError::Decode(e) => write!(f, "stored client is invalid: {}", e),
+            StateError::Encode(e) => write!(f, "client couldn't be stored: {}", e),
+        }
+    }
+}
+impl From<redis::RedisError> for StateError
+{
+    fn from(e: redis::RedisError) -> Self {
+        StateError::Redis(e)
+    }
+}
+impl From<serde_json::Error> for StateError
+{
+    fn from(e: serde_json::Error) -> Self {
+        StateError::Decode(e)
+    }
+}
+
+/// Applies a transaction to a client the same way the engine does
+///
+/// Returns the client if the transaction got far enough to touch it, and any
+/// rejection it led to
+///
+/// # Arguments
+///
+/// * 'policy' - The rules used when validating transactions
+/// * 'client' - The client as currently stored, if it exists
+/// * 'tx' - The transaction to apply
+fn apply_to_client(policy: &EnginePolicy, client: Option<Client>, tx: Tx) -> (Option<Client>, Vec<Rejection>)
+{
+    let mut engine = Engine::new(policy.clone());
+    if let Some(client) = client
+    {
+        engine.clients.insert(tx.client, client);
+    }
+    let client_id = tx.client;
+    engine.apply(tx);
+    (engine.clients.remove(&client_id), engine.rejections)
+}
+
+///
+/// Clients kept in redis instead of in memory, so several engines processing
+/// partitions of the same stream can share them
+///
+/// Every transaction is applied with optimistic locking: the client and the key
+/// marking its transaction ID as seen are watched, and the update is retried if
+/// another engine changed either of them in the meantime, so engines only hold each
+/// other up over the same client or the same transaction ID
+///
+pub struct RedisState
+{
+    conn: Connection,
+    /// Prepended to every key, so several deployments can share a server
+    prefix: String,
+    /// The rules used when validating transactions
+    pub policy: EnginePolicy,
+    /// Transactions that were refused by this engine, in the order they came in
+    pub rejections: Vec<Rejection>,
+}
+impl RedisState
+{
+    /// Connects to redis
+    ///
+    /// # Arguments
+    ///
+    /// * 'url' - The url of the server, E.G. "redis://127.0.0.1/"
+    /// * 'prefix' - Prepended to every key
+    /// * 'policy' - The rules used when validating transactions
+    pub fn connect(url: &str, prefix: &str, policy: EnginePolicy) -> Result<RedisState, StateError>
+    {
+        let conn = redis::Client::open(url)?.get_connection()?;
+        Ok(RedisState { conn, prefix: prefix.to_string(), policy, rejections: Vec::new() })
+    }
+    /// The key a client is stored under
+    fn client_key(&self, id: u16) -> String
+    {
+        format!("{}:client:{}", self.prefix, id)
+    }
+    /// The key set once a deposit or withdrawal under the ID has been applied
+    fn seen_key(&self, tx: u32) -> String
+    {
+        format!("{}:seen:{}", self.prefix, tx)
+    }
+    /// Parses the amount of a record as the policy says, then applies it
+    ///
+    /// # Arguments
+    ///
+    /// 'record' - The transaction as read from the input
+    pub fn apply_record(&mut self, record: TxRecord) -> Result<(), StateError>
+    {
+        match record.to_tx(self.policy.rounding)
+        {
+            Ok(tx) => self.apply(tx),
+            Err(reason) => {
+                self.rejections.push(Rejection{client:record.client, tx:record.tx, r#type:record.r#type, amount:record.amount, reason, rule:None});
+                Ok(())
+            }
+        }
+    }
+    /// Applies a transaction to its client in redis, creating the client if it's new
+    ///
+    /// A deposit or withdrawal whose ID any engine has already applied is skipped
+    ///
+    /// # Arguments
+    ///
+    /// 'tx' - The transaction to apply
+    pub fn apply(&mut self, tx: Tx) -> Result<(), StateError>
+    {
+        let client_key = self.client_key(tx.client);
+        let seen_key = self.seen_key(tx.tx);
+        let mut rejections = Vec::new();
+        let mut error = None;
+        let policy = &self.policy;
+        let _: () = redis::transaction(&mut self.conn, &[&client_key, &seen_key], |conn, pipe| {
+            rejections.clear();
+            if tx.r#type.carries_amount() && conn.exists(&seen_key)?
+            {
+                return pipe.query(conn);
+            }
+            let stored: Option<String> = conn.get(&client_key)?;
+            let client = match stored.map(|json| serde_json::from_str::<Client>(&json)).transpose()
+            {
+                Ok(client) => client,
+                Err(e) => {
+                    error = Some(StateError::Decode(e));
+                    return pipe.query(conn);
+                }
+            };
+            let (client, refused) = apply_to_client(policy, client, tx.clone());
+            if let Some(client) = client
+            {
+                match serde_json::to_string(&client)
+                {
+                    Ok(json) => {pipe.set(&client_key, json).ignore();},
+                    Err(e) => {
+                        error = Some(StateError::Encode(e));
+                        return pipe.query(conn);
+                    }
+                }
+            }
+            if tx.r#type.carries_amount() && refused.is_empty()
+            {
+                pipe.set(&seen_key, tx.client).ignore();
+            }
+            rejections = refused;
+            pipe.query(conn)
+        })?;
+        if let Some(e) = error {return Err(e)}
+        self.rejections.append(&mut rejections);
+        Ok(())
+    }
+    /// Reads a client back from redis
+    ///
+    /// # Arguments
+    ///
+    /// 'id' - The client ID
+    pub fn client(&mut self, id: u16) -> Result<Option<Client>, StateError>
+    {
+        let stored: Option<String> = self.conn.get(self.client_key(id))?;
+        Ok(stored.map(|json| serde_json::from_str(&json)).transpose()?)
+    }
+    /// Reads every client back from redis, including those other engines created
+    pub fn clients(&mut self) -> Result<ClientStore, StateError>
+    {
+        let keys: Vec<String> = self.conn.scan_match::<_, String>(format!("{}:client:*", self.prefix))?.collect();
+        let mut clients = ClientStore::default();
+        for key in keys
+        {
+            let stored: Option<String> = self.conn.get(&key)?;
+            if let Some(json) = stored
+            {
+                let client: Client = serde_json::from_str(&json)?;
+                clients.insert(client.acc.client, client);
+            }
+        }
+        Ok(clients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, RejectReason, TypeTx};
+
+    #[test]
+    fn apply_stored_client()
+    {
+        let policy = EnginePolicy::default();
+        let (client, rejections) = apply_to_client(&policy, None, Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(20000))));
+        assert!(rejections.is_empty());
+        let json = serde_json::to_string(&client.unwrap()).unwrap();
+        let stored: Client = serde_json::from_str(&json).unwrap();
+        let (client, _) = apply_to_client(&policy, Some(stored), Tx::new(TypeTx::Dispute, 1, 1, None));
+        let client = client.unwrap();
+        assert_eq!(client.acc.held,Amount::from_minor(20000));
+        assert!(client.history[&1].in_dispute);
+    }
+    #[test]
+    fn rejected_before_client()
+    {
+        let (client, rejections) = apply_to_client(&EnginePolicy::default(), None, Tx::new(TypeTx::Deposit, 1, 1, None));
+        assert!(client.is_none());
+        assert_eq!(rejections[0].reason,RejectReason::MissingAmount);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{Account, Amount, Tx, TypeTx};
+
+/// A deposit as the reference model keeps it
+struct Deposit
+{
+    client: u16,
+    tx: u32,
+    amount: Amount,
+    disputed: bool,
+    /// Charged back and not contested since
+    charged_back: bool,
+    /// Its chargeback is contested and waits for a second resolve or chargeback
+    contested: bool,
+    /// Its chargeback was contested at some point, which can only happen once
+    represented: bool,
+}
+
+///
+/// A deliberately simple and slow implementation of the accounting rules, kept
+/// apart from the engine so the two can be checked against each other
+///
+/// It knows deposits, withdrawals, disputes, resolves, chargebacks and representments under the
+/// default policy, and nothing else: no credit limits, tiers, settlement, interest,
+/// hooks or rules. Everything is kept in plain lists and searched from the start
+///
+#[derive(Default)]
+pub struct ReferenceModel
+{
+    accounts: Vec<Account>,
+    deposits: Vec<Deposit>,
+}
+impl ReferenceModel
+{
+    /// Returns a model with no accounts
+    pub fn new() -> ReferenceModel
+    {
+        ReferenceModel::default()
+    }
+    /// The accounts seen so far, in the order their clients first came up
+    pub fn accounts(&self) -> &[Account]
+    {
+        &self.accounts
+    }
+    /// The account of the client, None if it hasn't come up
+    ///
+    /// # Arguments
+    ///
+    /// * 'client' - The client ID
+    pub fn account(&self, client: u16) -> Option<&Account>
+    {
+        self.accounts.iter().find(|a| a.client == client)
+    }
+    /// Applies a transaction the way the engine does under the default policy
+    ///
+    /// A transaction that breaks a rule is left out, and the accounts stay as they were
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction, with its amount parsed
+    pub fn apply(&mut self, tx: &Tx)
+    {
+        let amount = match (tx.r#type, tx.amount)
+        {
+            (TypeTx::Deposit | TypeTx::Withdrawal, Some(amount)) if !amount.is_negative() => Some(amount),
+            (TypeTx::Dispute | TypeTx::Resolve | TypeTx::Chargeback | TypeTx::Representment, None) => None,
+            _ => return
+        };
+        if self.account(tx.client).is_none()
+        {
+            self.accounts.push(Account::new(tx.client));
+        }
+        let seen = self.deposits.iter().any(|d| d.client == tx.client && d.tx == tx.tx);
+        let account = match self.accounts.iter_mut().find(|a| a.client == tx.client)
+        {
+            Some(account) => account,
+            None => return
+        };
+        let deposit = self.deposits.iter_mut().find(|d| d.client == tx.client && d.tx == tx.tx);
+        let mut next = account.clone();
+        match (tx.r#type, amount, deposit)
+        {
+            (TypeTx::Deposit, Some(amount), _) if !account.locked && !seen => {
+                next.available = match account.available.checked_add(amount) {Some(a) => a, None => return};
+                next.total = match account.total.checked_add(amount) {Some(t) => t, None => return};
+                self.deposits.push(Deposit { client: tx.client, tx: tx.tx, amount, disputed: false, charged_back: false, contested: false, represented: false });
+            },
+            //only if more than the amount is available
+            (TypeTx::Withdrawal, Some(amount), _) if !account.locked && !seen && account.available > amount => {
+                next.available = match account.available.checked_sub(amount) {Some(a) => a, None => return};
+                next.total = match account.total.checked_sub(amount) {Some(t) => t, None => return};
+            },
+            (TypeTx::Dispute, _, Some(deposit)) if !deposit.disputed => {
+                next.available = match account.available.checked_sub(deposit.amount) {Some(a) => a, None => return};
+                next.held = match account.held.checked_add(deposit.amount) {Some(h) => h, None => return};
+                deposit.disputed = true;
+            },
+            //a contested chargeback is decided even though the account is locked
+            (TypeTx::Resolve, _, Some(deposit)) if (!account.locked || deposit.contested) && deposit.disputed => {
+                next.held = match account.held.checked_sub(deposit.amount) {Some(h) => h, None => return};
+                next.available = match account.available.checked_add(deposit.amount) {Some(a) => a, None => return};
+                deposit.disputed = false;
+                deposit.contested = false;
+            },
+            //the deposit stays disputed, so it can't be disputed again
+            (TypeTx::Chargeback, _, Some(deposit)) if (!account.locked || deposit.contested) && deposit.disputed => {
+                next.held = match account.held.checked_sub(deposit.amount) {Some(h) => h, None => return};
+                next.total = match account.total.checked_sub(deposit.amount) {Some(t) => t, None => return};
+                next.locked = true;
+                deposit.charged_back = true;
+                deposit.contested = false;
+            },
+            (TypeTx::Representment, _, Some(deposit)) if deposit.charged_back && !deposit.represented => {
+                next.held = match account.held.checked_add(deposit.amount) {Some(h) => h, None => return};
+                next.total = match account.total.checked_add(deposit.amount) {Some(t) => t, None => return};
+                deposit.charged_back = false;
+                deposit.contested = true;
+                deposit.represented = true;
+            },
+            _ => return
+        }
+        *account = next;
+    }
+}
@@ -0,0 +1,131 @@
+use std::{collections::{BTreeMap, HashMap}, iter::FromIterator};
+use serde::Serialize;
+use crate::{Amount, Engine, RejectReason, Rejection};
+
+///
+/// How many transactions of a single type were rejected, and what their amounts came to
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RejectedTotal
+{
+    pub count: u64,
+    /// The sum of the amounts given, those that couldn't be read left out
+    pub amount: Amount,
+}
+
+///
+/// Running counts of the rejected transactions, kept as they are rejected, so an embedder
+/// can alert when rejections spike without going through the whole rejection report
+///
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct RejectionStats
+{
+    /// How many transactions were rejected for each reason
+    pub by_reason: HashMap<RejectReason, u64>,
+    /// The rejected transactions of each type, keyed by type, E.G. "withdrawal"
+    pub by_type: BTreeMap<String, RejectedTotal>,
+}
+impl RejectionStats
+{
+    /// Counts a rejection in
+    ///
+    /// # Arguments
+    ///
+    /// * 'rejection' - The rejection, as it is added to the report
+    pub(crate) fn add(&mut self, rejection: &Rejection)
+    {
+        *self.by_reason.entry(rejection.reason).or_insert(0) += 1;
+        let total = self.by_type.entry(rejection.r#type.as_str().to_string()).or_default();
+        total.count += 1;
+        if let Some(amount) = rejection.amount.as_deref().and_then(|a| a.trim().parse::<Amount>().ok())
+        {
+            total.amount = total.amount.checked_add(amount).unwrap_or(Amount::MAX);
+        }
+    }
+    /// How many transactions were rejected, for any reason
+    pub fn total(&self) -> u64
+    {
+        self.by_reason.values().sum()
+    }
+    /// How many transactions were rejected for the reason
+    ///
+    /// # Arguments
+    ///
+    /// * 'reason' - Why they were rejected
+    pub fn count(&self, reason: RejectReason) -> u64
+    {
+        self.by_reason.get(&reason).copied().unwrap_or(0)
+    }
+    /// The rejections counted since the earlier stats were taken, E.G. over the last
+    /// thousand rows
+    ///
+    /// # Arguments
+    ///
+    /// * 'earlier' - A copy of the stats taken earlier in the same run
+    pub fn since(&self, earlier: &RejectionStats) -> RejectionStats
+    {
+        let by_reason = self.by_reason.iter()
+            .map(|(reason, n)| (*reason, n.saturating_sub(earlier.count(*reason))))
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        let by_type = self.by_type.iter().filter_map(|(r#type, total)| {
+            let before = earlier.by_type.get(r#type).copied().unwrap_or_default();
+            let count = total.count.saturating_sub(before.count);
+            (count > 0).then(|| (r#type.clone(), RejectedTotal { count, amount: total.amount.checked_sub(before.amount).unwrap_or(total.amount) }))
+        }).collect();
+        RejectionStats { by_reason, by_type }
+    }
+}
+impl<'a> FromIterator<&'a Rejection> for RejectionStats
+{
+    fn from_iter<I: IntoIterator<Item = &'a Rejection>>(rejections: I) -> Self {
+        let mut stats = RejectionStats::default();
+        for rejection in rejections
+        {
+            stats.add(rejection);
+        }
+        stats
+    }
+}
+
+impl Engine
+{
+    /// The share of the rows applied so far that were rejected, 0 before any row
+    pub fn rejection_rate(&self) -> f64
+    {
+        match self.rows
+        {
+            0 => 0.0,
+            rows => self.rejection_stats.total() as f64 / rows as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn rejections_counted_by_reason_and_type()
+    {
+        let mut engine = Engine::new(EnginePolicy { max_amount: Some(Amount::from_minor(100000)), ..EnginePolicy::default() });
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 2, Some(Amount::from_minor(200000))));
+        let earlier = engine.rejection_stats.clone();
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 3, Some(Amount::from_minor(150000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 4, None));
+        let savepoint = engine.savepoint();
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 5, None));
+        assert!(engine.rollback_to(&savepoint));
+
+        let stats = &engine.rejection_stats;
+        assert_eq!((stats.count(RejectReason::AboveMaximum), stats.count(RejectReason::MissingAmount), stats.total()),(2, 1, 3));
+        assert_eq!(stats.by_type["withdrawal"],RejectedTotal { count: 2, amount: Amount::from_minor(350000) });
+        assert_eq!(stats.by_type["deposit"],RejectedTotal { count: 1, amount: Amount::ZERO });
+        assert_eq!(engine.rejection_rate(),0.75);
+        assert_eq!(stats.since(&earlier).by_type["withdrawal"],RejectedTotal { count: 1, amount: Amount::from_minor(150000) });
+        assert_eq!(stats.since(&earlier).total(),2);
+        assert_eq!(engine.rejections.iter().collect::<RejectionStats>(),*stats);
+    }
+}
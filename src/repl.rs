@@ -0,0 +1,191 @@
+use crate::{Amount, Engine, Savepoint, TxRecord, TypeTx};
+
+/// Where the engine was before a transaction, so it can be undone
+struct UndoEntry
+{
+    /// The client the transaction was for
+    client: u16,
+    /// The savepoint made right before it
+    savepoint: Savepoint,
+}
+
+///
+/// An interactive session, where transactions are typed in one line at a time
+///
+/// * deposit|withdrawal <client> <tx> <amount>
+/// * dispute|resolve|chargeback|representment|compliance_hold|compliance_release <client> <tx>
+/// * show [client] - prints one account, or all of them
+/// * undo - takes back the last transaction
+/// * undo <client> <tx> - reverses the balance effects of the client's most recent transaction
+/// * accrue [rate] - credits interest to every account, at the policy's rate if none is given
+///
+pub struct Repl
+{
+    pub engine: Engine,
+    undo: Vec<UndoEntry>,
+}
+impl Repl
+{
+    /// Returns a new session on top of the engine
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine transactions are applied to
+    pub fn new(engine: Engine) -> Repl
+    {
+        Repl { engine, undo: Vec::new() }
+    }
+    /// Runs a single line, returning what to print back
+    ///
+    /// # Arguments
+    ///
+    /// * 'line' - The line as typed
+    pub fn execute(&mut self, line: &str) -> String
+    {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice()
+        {
+            [] => String::new(),
+            ["show"] => self.show_all(),
+            ["show", client] => match client.parse()
+            {
+                Ok(client) => self.show(client),
+                Err(_) => format!("invalid client '{}'", client)
+            },
+            ["undo"] => self.undo(),
+            ["undo", client, tx] => match (client.parse(), tx.parse())
+            {
+                (Ok(client), Ok(tx)) if self.engine.undo(client, tx) => format!("undone, {}", self.show(client)),
+                (Ok(client), Ok(tx)) => format!("tx {} isn't the last transaction of client {}", tx, client),
+                _ => format!("invalid client or tx in '{} {}'", client, tx)
+            },
+            ["accrue"] => match self.engine.policy.interest
+            {
+                Some(interest) => self.accrue(interest.rate),
+                None => "no interest rate set, try accrue <rate>".to_string()
+            },
+            ["accrue", rate] => match rate.parse()
+            {
+                Ok(rate) => self.accrue(rate),
+                Err(_) => format!("invalid rate '{}'", rate)
+            },
+            ["help"] => "deposit|withdrawal <client> <tx> <amount>, dispute|resolve|chargeback|representment|compliance_hold|compliance_release <client> <tx>, show [client], undo [client tx], accrue [rate], quit".to_string(),
+            [command, args @ ..] => match command.parse::<TypeTx>()
+            {
+                Ok(r#type) => self.transaction(r#type, args),
+                Err(_) => format!("unknown command '{}', try help", command)
+            }
+        }
+    }
+    /// Applies a typed in transaction, under a savepoint it can be rolled back to
+    fn transaction(&mut self, r#type: TypeTx, args: &[&str]) -> String
+    {
+        let expected = if r#type.carries_amount() {3} else {2};
+        if args.len() != expected
+        {
+            return format!("{} takes {} arguments", r#type.as_str(), expected);
+        }
+        let (client, tx) = match (args[0].parse(), args[1].parse())
+        {
+            (Ok(client), Ok(tx)) => (client, tx),
+            _ => return format!("invalid client or tx in '{} {}'", args[0], args[1])
+        };
+        let rejections = self.engine.rejections.len();
+        self.undo.push(UndoEntry { client, savepoint: self.engine.savepoint() });
+        self.engine.apply_record(TxRecord::new(r#type, client, tx, args.get(2).map(|a| a.to_string())));
+        match self.engine.rejections.get(rejections)
+        {
+            Some(rejection) => format!("rejected: {}", rejection.reason),
+            None => self.show(client)
+        }
+    }
+    /// Credits interest to every account; as that touches every client, it can't be
+    /// undone, and neither can anything before it
+    fn accrue(&mut self, rate: Amount) -> String
+    {
+        if let Some(first) = self.undo.first()
+        {
+            self.engine.release(&first.savepoint);
+        }
+        self.undo.clear();
+        format!("accrued {} of interest", self.engine.accrue(rate))
+    }
+    /// Rolls the engine back to before the last transaction
+    fn undo(&mut self) -> String
+    {
+        let entry = match self.undo.pop()
+        {
+            Some(entry) => entry,
+            None => return "nothing to undo".to_string()
+        };
+        self.engine.rollback_to(&entry.savepoint);
+        self.engine.release(&entry.savepoint);
+        format!("undone, {}", self.show(entry.client))
+    }
+    /// Describes the account of a client
+    fn show(&self, client: u16) -> String
+    {
+        match self.engine.clients.get(&client)
+        {
+            Some(c) => format!("client {}:{}", client, c.acc),
+            None => format!("client {}: no account", client)
+        }
+    }
+    /// Describes every account, ordered by client
+    fn show_all(&self) -> String
+    {
+        let mut ids: Vec<u16> = self.engine.clients.keys().collect();
+        ids.sort();
+        ids.into_iter().map(|id| self.show(id)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, GlobalDedup};
+
+    #[test]
+    fn typed_transactions()
+    {
+        let mut repl = Repl::new(Engine::new(EnginePolicy::default()));
+        assert_eq!(repl.execute("deposit 1 42 10.0"),"client 1: available: 10.0, held: 0.0, total: 10.0, locked:false");
+        assert_eq!(repl.execute("dispute 1 42"),"client 1: available: 0.0, held: 10.0, total: 10.0, locked:false");
+        assert_eq!(repl.execute("deposit 1 43"),"deposit takes 3 arguments");
+        assert_eq!(repl.execute("deposit 1 43 0.00001"),"rejected: Precision");
+        assert_eq!(repl.execute("refund 1 42"),"unknown command 'refund', try help");
+        assert_eq!(repl.execute("show 2"),"client 2: no account");
+    }
+    #[test]
+    fn undo()
+    {
+        let mut repl = Repl::new(Engine::new(EnginePolicy::default()));
+        repl.execute("deposit 1 1 5.0");
+        repl.execute("dispute 1 1");
+        repl.execute("deposit 1 2 0.00001");
+        assert_eq!(repl.engine.rejections.len(),1);
+        repl.execute("undo");
+        assert!(repl.engine.rejections.is_empty());
+        assert_eq!(repl.execute("undo"),"undone, client 1: available: 5.0, held: 0.0, total: 5.0, locked:false");
+        assert!(!repl.engine.clients[&1].history[&1].in_dispute);
+        assert_eq!(repl.execute("undo"),"undone, client 1: no account");
+        assert_eq!(repl.execute("undo"),"nothing to undo");
+        assert_eq!(repl.engine.rows,0);
+        repl.execute("deposit 1 3 10.0");
+        assert_eq!(repl.execute("accrue"),"no interest rate set, try accrue <rate>");
+        assert_eq!(repl.execute("accrue 0.1"),"accrued 1.0 of interest");
+        assert_eq!(repl.execute("undo"),"nothing to undo");
+        assert_eq!(repl.execute("undo 1 2"),"tx 2 isn't the last transaction of client 1");
+        assert_eq!(repl.execute("undo 1 3"),"undone, client 1: available: 1.0, held: 0.0, total: 1.0, locked:false");
+    }
+    #[test]
+    fn undo_rolls_back_the_whole_engine()
+    {
+        let mut repl = Repl::new(Engine::new(EnginePolicy { global_dedup: Some(GlobalDedup::default()), ..EnginePolicy::default() }));
+        repl.execute("deposit 1 7 5.0");
+        repl.execute("undo");
+        //the transaction ID is free again, as the dedup was rolled back with the client
+        assert_eq!(repl.execute("deposit 2 7 5.0"),"client 2: available: 5.0, held: 0.0, total: 5.0, locked:false");
+        assert!(repl.execute("help").contains("representment"));
+    }
+}
@@ -0,0 +1,380 @@
+use std::{collections::BTreeMap, fmt};
+use serde::Serialize;
+use crate::{check_unique_clients, ledger_head, Amount, Engine, TrialBalance, MAX_INTEREST_PERIODS};
+
+///
+/// A client in the report, with how many deposits and withdrawals it made
+///
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ClientSummary
+{
+    pub client: u16,
+    /// The name from the metadata registry
+    pub name: Option<String>,
+    /// The tier from the metadata registry
+    pub tier: Option<String>,
+    pub total: Amount,
+    pub held: Amount,
+    pub transactions: usize,
+    pub locked: bool,
+}
+
+///
+/// A single disputed transaction
+///
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct DisputeSummary
+{
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Amount,
+}
+
+///
+/// What was done with a single counterparty, for risk monitoring
+///
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct CounterpartySummary
+{
+    pub counterparty: String,
+    pub deposits: usize,
+    pub withdrawals: usize,
+    pub volume: Amount,
+    pub chargebacks: usize,
+    /// The share of deposits that were charged back
+    pub chargeback_rate: f64,
+}
+
+///
+/// An account in a state the engine shouldn't be able to get into, or that
+/// needs someone to look at it
+///
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(tag = "warning")]
+pub enum Warning
+{
+    /// Less than nothing available, E.G. a deposit disputed after its funds were withdrawn
+    #[serde(rename = "negative_available")]
+    NegativeAvailable { client: u16, available: Amount },
+    /// More held than the account holds in total
+    #[serde(rename = "held_above_total")]
+    HeldAboveTotal { client: u16, held: Amount, total: Amount },
+    /// Funds held on a locked account, which can no longer be resolved or charged back
+    #[serde(rename = "stranded_held")]
+    StrandedHeld { client: u16, held: Amount },
+    /// More than one account carries the client ID, so the output can't be told apart
+    #[serde(rename = "duplicate_client")]
+    DuplicateClient { client: u16 },
+    /// The debits and credits of the books don't come to the same, so balances moved without their total
+    #[serde(rename = "unbalanced_books")]
+    UnbalancedBooks { debits: Amount, credits: Amount },
+    /// The balances of the account aren't those its postings to the journal come to
+    #[serde(rename = "journal_mismatch")]
+    JournalMismatch { client: u16 },
+    /// Every synthetic transaction ID was handed out, so adjustments, fees, interest and
+    /// recurring transactions stopped being made
+    #[serde(rename = "synthetic_txs_used_up")]
+    SyntheticTxsUsedUp,
+    /// More than MAX_INTEREST_PERIODS passed between two transactions, so the periods
+    /// before the last of them weren't credited
+    #[serde(rename = "interest_periods_skipped")]
+    InterestPeriodsSkipped { periods: i64 },
+}
+impl fmt::Display for Warning
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        match self
+        {
+            Warning::NegativeAvailable { client, available } => write!(f, "client {}: available is negative ({})", client, available),
+            Warning::HeldAboveTotal { client, held, total } => write!(f, "client {}: held ({}) is above total ({})", client, held, total),
+            Warning::StrandedHeld { client, held } => write!(f, "client {}: {} held on a locked account with no way to release it", client, held),
+            Warning::DuplicateClient { client } => write!(f, "client {}: more than one account carries the ID", client),
+            Warning::UnbalancedBooks { debits, credits } => write!(f, "the books don't balance, debits are {} and credits {}", debits, credits),
+            Warning::JournalMismatch { client } => write!(f, "client {}: the balances aren't those the journal comes to", client),
+            Warning::SyntheticTxsUsedUp => write!(f, "the synthetic transaction IDs are used up, no more adjustments, fees, interest or recurring transactions are made"),
+            Warning::InterestPeriodsSkipped { periods } => write!(f, "{} interest periods weren't credited, as more than {} passed at once", periods, MAX_INTEREST_PERIODS),
+        }
+    }
+}
+
+/// Scans every account for anomalies, ordered by client
+///
+/// # Arguments
+///
+/// * 'engine' - The engine once the input is processed
+pub fn anomalies(engine: &Engine) -> Vec<Warning>
+{
+    let mut ids: Vec<u16> = engine.clients.keys().collect();
+    ids.sort();
+    let mut warnings = Vec::new();
+    for id in ids
+    {
+        let acc = &engine.clients[&id].acc;
+        if acc.available.is_negative()
+        {
+            warnings.push(Warning::NegativeAvailable { client: acc.client, available: acc.available });
+        }
+        if acc.held > acc.total
+        {
+            warnings.push(Warning::HeldAboveTotal { client: acc.client, held: acc.held, total: acc.total });
+        }
+        if acc.locked && acc.held > Amount::ZERO
+        {
+            warnings.push(Warning::StrandedHeld { client: acc.client, held: acc.held });
+        }
+    }
+    if let Err(duplicates) = check_unique_clients(&engine.clients)
+    {
+        warnings.extend(duplicates.0.into_iter().map(|client| Warning::DuplicateClient { client }));
+    }
+    if let Some(books) = engine.books.as_ref().filter(|b| !b.is_balanced())
+    {
+        warnings.push(Warning::UnbalancedBooks { debits: books.debits(), credits: books.credits() });
+    }
+    warnings.extend(engine.journal_mismatches().into_iter().map(|client| Warning::JournalMismatch { client }));
+    if engine.synthetic_txs_used_up()
+    {
+        warnings.push(Warning::SyntheticTxsUsedUp);
+    }
+    if engine.interest_periods_skipped > 0
+    {
+        warnings.push(Warning::InterestPeriodsSkipped { periods: engine.interest_periods_skipped });
+    }
+    warnings
+}
+
+///
+/// Management metrics, taken from the state of the engine
+///
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct Report
+{
+    /// How many clients have been seen
+    pub clients: usize,
+    /// How many deposits and withdrawals were applied
+    pub transactions: usize,
+    /// How many transactions were refused
+    pub rejected: usize,
+    /// How many transactions were left alone as their client is outside the client filter
+    pub skipped: usize,
+    /// Funds held across every account while their disputes are open
+    pub held: Amount,
+    /// How many accounts are locked
+    pub locked: usize,
+    /// The locked accounts, ordered by client
+    pub locked_clients: Vec<u16>,
+    /// The clients with the largest total balance, largest first
+    pub top_clients: Vec<ClientSummary>,
+    /// The largest transactions disputed and not resolved, charged back ones included
+    pub largest_disputes: Vec<DisputeSummary>,
+    /// Accounts in a state that needs looking at
+    pub warnings: Vec<Warning>,
+    /// The balance of the chargeback reserve, if the policy keeps one
+    pub reserve: Option<Amount>,
+    /// The debits and credits posted to each system account, if the trial balance is on
+    pub trial_balance: Option<TrialBalance>,
+    /// The counterparties with the largest volume, largest first
+    pub counterparties: Vec<CounterpartySummary>,
+    /// The accounts frozen for their chargebacks or by screening, ordered by client
+    pub frozen: Vec<u16>,
+    /// The hash the ledger export ends at, to check it against with verify-ledger
+    pub ledger_head: String,
+    /// How many transactions each declarative rule matched, keyed by rule ID
+    pub rule_hits: BTreeMap<String, u64>,
+    /// How many transactions rules flagged
+    pub flagged: usize,
+}
+impl Report
+{
+    ///
+    /// Returns the metrics for every client in the engine
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine once the input is processed
+    /// * 'top' - How many clients and disputes to list
+    pub fn new(engine: &Engine, top: usize) -> Report
+    {
+        let mut top_clients: Vec<ClientSummary> = engine.clients.values().map(|c| ClientSummary {
+            client: c.acc.client,
+            name: engine.metadata.get(&c.acc.client).and_then(|m| m.name.clone()),
+            tier: engine.metadata.get(&c.acc.client).and_then(|m| m.tier.clone()),
+            total: c.acc.total,
+            held: c.acc.held,
+            transactions: c.history.len(),
+            locked: c.acc.locked,
+        }).collect();
+        let clients = top_clients.len();
+        let transactions = top_clients.iter().map(|c| c.transactions).sum();
+        let held = top_clients.iter().fold(Amount::ZERO, |sum, c| sum.checked_add(c.held).unwrap_or(Amount::MAX));
+        let locked = top_clients.iter().filter(|c| c.locked).count();
+        top_clients.sort_by(|a, b| b.total.cmp(&a.total).then(a.client.cmp(&b.client)));
+        top_clients.truncate(top);
+
+        let mut largest_disputes: Vec<DisputeSummary> = engine.clients.values()
+            .flat_map(|c| c.history.iter()
+                .filter(|(_, h)| h.in_dispute)
+                .map(move |(tx, h)| DisputeSummary { client: c.acc.client, tx: *tx, amount: h.amount }))
+            .collect();
+        largest_disputes.sort_by(|a, b| b.amount.cmp(&a.amount).then(a.client.cmp(&b.client)).then(a.tx.cmp(&b.tx)));
+        largest_disputes.truncate(top);
+
+        let mut counterparties: Vec<CounterpartySummary> = engine.counterparties.iter().map(|(name, stats)| CounterpartySummary {
+            counterparty: name.clone(),
+            deposits: stats.deposits,
+            withdrawals: stats.withdrawals,
+            volume: stats.volume,
+            chargebacks: stats.chargebacks,
+            chargeback_rate: stats.chargeback_rate(),
+        }).collect();
+        counterparties.sort_by(|a, b| b.volume.cmp(&a.volume).then(a.counterparty.cmp(&b.counterparty)));
+        counterparties.truncate(top);
+
+        let mut frozen: Vec<u16> = engine.clients.values().filter(|c| c.frozen).map(|c| c.acc.client).collect();
+        frozen.sort();
+        let mut locked_clients: Vec<u16> = engine.clients.values().filter(|c| c.acc.locked).map(|c| c.acc.client).collect();
+        locked_clients.sort();
+
+        Report { clients, transactions, rejected: engine.rejections.len(), skipped: engine.skipped, held, locked, locked_clients, top_clients, largest_disputes, warnings: anomalies(engine),
+            reserve: engine.reserve.as_ref().map(|r| r.balance), trial_balance: engine.books.clone(), counterparties, frozen, ledger_head: ledger_head(&engine.clients),
+            rule_hits: engine.rule_hits.clone(), flagged: engine.flags.len() }
+    }
+}
+impl fmt::Display for Report
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        writeln!(f, "clients: {}", self.clients)?;
+        writeln!(f, "transactions: {}", self.transactions)?;
+        writeln!(f, "rejected: {}", self.rejected)?;
+        if self.skipped > 0
+        {
+            writeln!(f, "skipped: {}", self.skipped)?;
+        }
+        writeln!(f, "held in dispute: {}", self.held)?;
+        writeln!(f, "locked accounts: {}", self.locked)?;
+        if !self.frozen.is_empty()
+        {
+            writeln!(f, "frozen accounts: {}", self.frozen.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "))?;
+        }
+        if let Some(reserve) = self.reserve
+        {
+            writeln!(f, "chargeback reserve: {}", reserve)?;
+        }
+        if let Some(books) = &self.trial_balance
+        {
+            writeln!(f, "trial balance: debits {}, credits {}{}", books.debits(), books.credits(), if books.is_balanced() {""} else {", out of balance"})?;
+        }
+        writeln!(f, "ledger head: {}", self.ledger_head)?;
+        if !self.rule_hits.is_empty()
+        {
+            writeln!(f, "flagged by rules: {}", self.flagged)?;
+            writeln!(f, "rule hits: {}", self.rule_hits.iter().map(|(id, n)| format!("{}: {}", id, n)).collect::<Vec<_>>().join(", "))?;
+        }
+        writeln!(f, "\nclients by total balance:")?;
+        for c in &self.top_clients
+        {
+            let name = match (&c.name, &c.tier)
+            {
+                (Some(name), Some(tier)) => format!(" ({}, {})", name, tier),
+                (Some(name), None) | (None, Some(name)) => format!(" ({})", name),
+                (None, None) => String::new()
+            };
+            writeln!(f, "  client {}{}: total: {}, held: {}, transactions: {}{}",
+                c.client, name, c.total, c.held, c.transactions, if c.locked {", locked"} else {""})?;
+        }
+        writeln!(f, "\nlargest disputed amounts:")?;
+        for d in &self.largest_disputes
+        {
+            writeln!(f, "  client {}, tx {}: {}", d.client, d.tx, d.amount)?;
+        }
+        if !self.counterparties.is_empty()
+        {
+            writeln!(f, "\ncounterparties by volume:")?;
+            for c in &self.counterparties
+            {
+                writeln!(f, "  {}: volume: {}, deposits: {}, withdrawals: {}, chargebacks: {} ({:.2}%)",
+                    c.counterparty, c.volume, c.deposits, c.withdrawals, c.chargebacks, c.chargeback_rate * 100.0)?;
+            }
+        }
+        if !self.warnings.is_empty()
+        {
+            writeln!(f, "\nwarnings:")?;
+            for w in &self.warnings
+            {
+                writeln!(f, "  {}", w)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn metrics_from_engine()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        for tx in [
+            Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))),
+            Tx::new(TypeTx::Deposit, 1, 2, Some(Amount::from_minor(20000))),
+            Tx::new(TypeTx::Deposit, 2, 3, Some(Amount::from_minor(90000))),
+            Tx::new(TypeTx::Deposit, 3, 4, Some(Amount::from_minor(10000))),
+            Tx::new(TypeTx::Dispute, 1, 1, None),
+            Tx::new(TypeTx::Dispute, 1, 2, None),
+            Tx::new(TypeTx::Dispute, 3, 4, None),
+            Tx::new(TypeTx::Chargeback, 3, 4, None),
+            Tx::new(TypeTx::Deposit, 2, 5, None),
+        ]
+        {
+            engine.apply(tx);
+        }
+        let mut shop = Tx::new(TypeTx::Deposit, 2, 6, Some(Amount::from_minor(10000)));
+        shop.counterparty = Some("shop".to_string());
+        engine.apply(shop);
+        let report = Report::new(&engine, 2);
+        assert_eq!(report.counterparties,vec![CounterpartySummary {
+            counterparty: "shop".to_string(), deposits: 1, withdrawals: 0, volume: Amount::from_minor(10000), chargebacks: 0, chargeback_rate: 0.0
+        }]);
+        assert_eq!(report.clients,3);
+        assert_eq!(report.transactions,5);
+        assert_eq!(report.rejected,1);
+        assert_eq!(report.held,Amount::from_minor(70000));
+        assert_eq!(report.locked,1);
+        assert_eq!(report.top_clients.iter().map(|c| c.client).collect::<Vec<_>>(),vec![2, 1]);
+        assert_eq!(report.largest_disputes,vec![
+            DisputeSummary { client: 1, tx: 1, amount: Amount::from_minor(50000) },
+            DisputeSummary { client: 1, tx: 2, amount: Amount::from_minor(20000) },
+        ]);
+        assert!(report.warnings.is_empty());
+        assert!(report.to_string().starts_with("clients: 3\ntransactions: 5\nrejected: 1\nheld in dispute: 7.0\nlocked accounts: 1\n"));
+    }
+    #[test]
+    fn anomalies_found()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        for tx in [
+            Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))),
+            Tx::new(TypeTx::Withdrawal, 1, 2, Some(Amount::from_minor(40000))),
+            Tx::new(TypeTx::Dispute, 1, 1, None),
+            Tx::new(TypeTx::Deposit, 2, 3, Some(Amount::from_minor(10000))),
+            Tx::new(TypeTx::Deposit, 2, 4, Some(Amount::from_minor(20000))),
+            Tx::new(TypeTx::Dispute, 2, 3, None),
+            Tx::new(TypeTx::Dispute, 2, 4, None),
+            Tx::new(TypeTx::Chargeback, 2, 3, None),
+        ]
+        {
+            engine.apply(tx);
+        }
+        assert_eq!(anomalies(&engine),vec![
+            Warning::NegativeAvailable { client: 1, available: Amount::from_minor(-40000) },
+            Warning::HeldAboveTotal { client: 1, held: Amount::from_minor(50000), total: Amount::from_minor(10000) },
+            Warning::StrandedHeld { client: 2, held: Amount::from_minor(20000) },
+        ]);
+        assert!(Report::new(&engine, 10).to_string().ends_with("\nwarnings:\n  client 1: available is negative (-4.0)\n  client 1: held (5.0) is above total (1.0)\n  client 2: 2.0 held on a locked account with no way to release it\n"));
+    }
+}
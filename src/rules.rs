@@ -0,0 +1,257 @@
+use std::{collections::HashSet, fmt, io};
+use serde::{Deserialize, Serialize};
+use crate::{Amount, Client, ClientFilter, Engine, Tx, TypeTx};
+
+///
+/// What a rule does with a transaction it matches
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum RuleAction
+{
+    /// Lets it through without looking at the rules after this one
+    #[serde(rename = "accept")]
+    Accept,
+    /// Refuses it, with the rule ID in the rejection report
+    #[serde(rename = "reject")]
+    Reject,
+    /// Lets it through but flags it for looking at, and goes on to the next rule
+    #[serde(rename = "flag")]
+    Flag,
+}
+
+///
+/// What a transaction and its account have to be like for a rule to match, every
+/// condition given has to hold and a rule without any matches everything
+///
+/// Account conditions are on the account as it was before the transaction, and
+/// amount conditions don't match transactions without an amount
+///
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Condition
+{
+    /// The transaction is of one of these types
+    #[serde(default)]
+    pub types: Vec<TypeTx>,
+    /// The client is one of these, E.G. "100-200,5000"
+    pub clients: Option<ClientFilter>,
+    pub amount_above: Option<Amount>,
+    pub amount_below: Option<Amount>,
+    pub available_above: Option<Amount>,
+    pub available_below: Option<Amount>,
+    pub held_above: Option<Amount>,
+    pub locked: Option<bool>,
+    pub frozen: Option<bool>,
+    pub currency: Option<String>,
+    pub counterparty: Option<String>,
+}
+impl Condition
+{
+    /// Whether the transaction and account are as the condition says
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction about to be applied
+    /// * 'client' - Its client, None if it is new
+    pub fn matches(&self, tx: &Tx, client: Option<&Client>) -> bool
+    {
+        let fresh = Client::new(tx.client);
+        let c = client.unwrap_or(&fresh);
+        let above = |bound: Option<Amount>, value: Option<Amount>| bound.is_none_or(|b| value.is_some_and(|v| v > b));
+        let below = |bound: Option<Amount>, value: Option<Amount>| bound.is_none_or(|b| value.is_some_and(|v| v < b));
+        (self.types.is_empty() || self.types.contains(&tx.r#type))
+            && self.clients.as_ref().is_none_or(|f| f.contains(tx.client))
+            && above(self.amount_above, tx.amount) && below(self.amount_below, tx.amount)
+            && above(self.available_above, Some(c.acc.available)) && below(self.available_below, Some(c.acc.available))
+            && above(self.held_above, Some(c.acc.held))
+            && self.locked.is_none_or(|l| l == c.acc.locked)
+            && self.frozen.is_none_or(|f| f == c.frozen)
+            && self.currency.as_ref().is_none_or(|cur| tx.currency.as_ref() == Some(cur))
+            && self.counterparty.as_ref().is_none_or(|cp| tx.counterparty.as_ref() == Some(cp))
+    }
+}
+
+///
+/// A single declarative rule
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule
+{
+    /// Names the rule in rejections, flags and hit counts
+    pub id: String,
+    #[serde(default)]
+    pub when: Condition,
+    pub action: RuleAction,
+}
+
+///
+/// The rules every transaction is checked against, in order, once it has passed
+/// the engine's own checks
+///
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleSet
+{
+    pub rules: Vec<Rule>,
+}
+
+///
+/// What the rules made of a transaction
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Verdict<'a>
+{
+    /// The rule that refused it, if one did
+    pub rejected_by: Option<&'a str>,
+    /// The rules that flagged it
+    pub flagged_by: Vec<&'a str>,
+    /// Every rule that matched it, in order
+    pub hits: Vec<&'a str>,
+}
+
+///
+/// A transaction a rule flagged for looking at
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RuleFlag
+{
+    pub client: u16,
+    pub tx: u32,
+    pub rule: String,
+}
+
+///
+/// The rule config couldn't be read
+///
+#[derive(Debug)]
+pub enum RuleError
+{
+    Json(serde_json::Error),
+    /// Two rules have the same ID
+    DuplicateId(String),
+}
+impl fmt::Display for RuleError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            RuleError::Json(e) => write!(f, "{}", e),
+            RuleError::DuplicateId(id) => write!(f, "there is more than one rule with the ID '{}'", id),
+        }
+    }
+}
+impl From<serde_json::Error> for RuleError
+{
+    fn from(e: serde_json::Error) -> Self {
+        RuleError::Json(e)
+    }
+}
+impl From<RuleError> for io::Error
+{
+    fn from(e: RuleError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    }
+}
+
+impl RuleSet
+{
+    /// Reads the rules from a json config, E.G.
+    /// {"rules": [{"id": "large-withdrawal", "when": {"types": ["withdrawal"], "amount_above": "1000"}, "action": "reject"}]}
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The config as json
+    ///
+    /// # Errors
+    ///
+    /// Fails on json that isn't a rule set, and on rules sharing an ID
+    pub fn read<R: io::Read>(input: R) -> Result<RuleSet, RuleError>
+    {
+        let rules: RuleSet = serde_json::from_reader(input)?;
+        let mut ids = HashSet::new();
+        if let Some(rule) = rules.rules.iter().find(|r| !ids.insert(r.id.as_str()))
+        {
+            return Err(RuleError::DuplicateId(rule.id.clone()));
+        }
+        Ok(rules)
+    }
+    /// Checks a transaction against the rules in order, until one accepts or rejects it
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction about to be applied
+    /// * 'client' - Its client, None if it is new
+    pub fn evaluate(&self, tx: &Tx, client: Option<&Client>) -> Verdict<'_>
+    {
+        let mut verdict = Verdict::default();
+        for rule in self.rules.iter().filter(|r| r.when.matches(tx, client))
+        {
+            verdict.hits.push(&rule.id);
+            match rule.action
+            {
+                RuleAction::Accept => break,
+                RuleAction::Reject => {
+                    verdict.rejected_by = Some(&rule.id);
+                    break;
+                },
+                RuleAction::Flag => verdict.flagged_by.push(&rule.id)
+            }
+        }
+        verdict
+    }
+}
+
+impl Engine
+{
+    /// Checks every transaction against the rules from now on, replacing any set before
+    ///
+    /// # Arguments
+    ///
+    /// * 'rules' - The rules, checked in order
+    pub fn set_rules(&mut self, rules: RuleSet)
+    {
+        self.rules = Some(rules);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EnginePolicy, RejectReason, write_rejections};
+
+    #[test]
+    fn declarative_rules()
+    {
+        let config = r#"{"rules": [
+            {"id": "vip", "when": {"clients": "100-200"}, "action": "accept"},
+            {"id": "watch-acme", "when": {"counterparty": "acme"}, "action": "flag"},
+            {"id": "large-withdrawal", "when": {"types": ["withdrawal"], "amount_above": "100"}, "action": "reject"},
+            {"id": "overdrawn", "when": {"types": ["withdrawal"], "available_below": "10"}, "action": "reject"}
+        ]}"#;
+        let rules = RuleSet::read(config.as_bytes()).unwrap();
+        assert!(matches!(RuleSet::read(r#"{"rules": [{"id": "a", "action": "flag"}, {"id": "a", "action": "flag"}]}"#.as_bytes()), Err(RuleError::DuplicateId(_))));
+        assert!(RuleSet::read(r#"{"rules": [{"id": "a", "when": {"amount_over": "1"}, "action": "flag"}]}"#.as_bytes()).is_err());
+
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_rules(rules);
+        let mut deposit = Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(5000000)));
+        deposit.counterparty = Some("acme".to_string());
+        engine.apply(deposit);
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 2, Some(Amount::from_minor(2000000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 150, 3, Some(Amount::from_minor(5000000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 150, 4, Some(Amount::from_minor(2000000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 2, 5, Some(Amount::from_minor(10000))));
+
+        assert_eq!(engine.clients[&150].acc.total,Amount::from_minor(3000000));
+        let rejected: Vec<(u32, RejectReason, Option<&str>)> = engine.rejections.iter().map(|r| (r.tx, r.reason, r.rule.as_deref())).collect();
+        assert_eq!(rejected,vec![(2, RejectReason::RuleRejected, Some("large-withdrawal")), (5, RejectReason::RuleRejected, Some("overdrawn"))]);
+        assert_eq!(engine.flags,vec![RuleFlag { client: 1, tx: 1, rule: "watch-acme".to_string() }]);
+        let hits: Vec<(&str, u64)> = engine.rule_hits.iter().map(|(id, n)| (id.as_str(), *n)).collect();
+        assert_eq!(hits,vec![("large-withdrawal", 1), ("overdrawn", 1), ("vip", 2), ("watch-acme", 1)]);
+
+        let mut out = Vec::new();
+        write_rejections(&engine.rejections[..1], &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),"client,tx,type,amount,reason,rule\n1,2,withdrawal,200.0,rule_rejected,large-withdrawal\n");
+    }
+}
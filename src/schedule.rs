@@ -0,0 +1,143 @@
+use std::io;
+use serde::Deserialize;
+use crate::{Amount, Tx, TypeTx};
+
+/// The memo of transactions made by a schedule without one of its own
+pub const RECURRING_MEMO: &str = "recurring";
+
+///
+/// A deposit or withdrawal repeated at a fixed interval, E.G. a monthly subscription
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Schedule
+{
+    /// Deposit or withdrawal
+    pub r#type: TypeTx,
+    pub client: u16,
+    pub amount: Amount,
+    /// When the first transaction happens, in seconds since the unix epoch
+    pub start: i64,
+    /// Seconds between two transactions
+    pub interval: i64,
+    /// How many transactions are made, without end if None
+    #[serde(default)]
+    pub count: Option<u32>,
+    #[serde(default)]
+    pub memo: Option<String>,
+    #[serde(default)]
+    pub counterparty: Option<String>,
+}
+impl Schedule
+{
+    /// The transaction made at the given time
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction ID to give it
+    /// * 'timestamp' - When it happens
+    pub fn transaction(&self, tx: u32, timestamp: i64) -> Tx
+    {
+        let mut transaction = Tx::new(self.r#type, self.client, tx, Some(self.amount));
+        transaction.timestamp = Some(timestamp);
+        transaction.memo = Some(self.memo.clone().unwrap_or_else(|| RECURRING_MEMO.to_string()));
+        transaction.counterparty = self.counterparty.clone();
+        transaction
+    }
+}
+
+///
+/// A schedule along with how far the engine has got through it
+///
+#[derive(Debug, Clone)]
+pub(crate) struct ScheduleState
+{
+    pub schedule: Schedule,
+    /// When the next transaction is due
+    pub next: i64,
+    /// How many transactions have been made so far
+    pub made: u32,
+}
+impl ScheduleState
+{
+    /// When the next transaction is due, or None once the schedule has run out
+    pub fn due(&self) -> Option<i64>
+    {
+        match self.schedule.count
+        {
+            Some(count) if self.made >= count => None,
+            _ => Some(self.next)
+        }
+    }
+    /// Moves on past the transaction that was just made
+    pub fn advance(&mut self)
+    {
+        self.made += 1;
+        self.next = match self.next.checked_add(self.schedule.interval)
+        {
+            Some(next) => next,
+            //there is no later time to make it at
+            None => {self.schedule.count = Some(self.made); self.next}
+        };
+    }
+}
+
+/// Reads recurring transactions, a csv with the columns type, client, amount, start
+/// and interval, and optionally count, memo and counterparty
+///
+/// # Arguments
+///
+/// * 'input' - The schedules as csv, with a header row
+///
+/// # Errors
+///
+/// Fails on rows that aren't deposits or withdrawals, or whose interval isn't positive
+pub fn read_schedules<R: io::Read>(input: R) -> csv::Result<Vec<Schedule>>
+{
+    let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+    let mut schedules = Vec::new();
+    for row in rdr.deserialize()
+    {
+        let schedule: Schedule = row?;
+        let invalid = match schedule.r#type
+        {
+            TypeTx::Deposit | TypeTx::Withdrawal if schedule.interval > 0 => None,
+            TypeTx::Deposit | TypeTx::Withdrawal => Some(format!("interval {} isn't positive", schedule.interval)),
+            other => Some(format!("{} can't recur", other.as_str()))
+        };
+        if let Some(e) = invalid
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("schedule for client {}: {}", schedule.client, e)).into());
+        }
+        schedules.push(schedule);
+    }
+    Ok(schedules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EnginePolicy};
+
+    #[test]
+    fn recurring_transactions()
+    {
+        let schedules = "type,client,amount,start,interval,count,memo,counterparty\n\
+            deposit,1,10.0,100,30,,salary,\n\
+            withdrawal,1,4.0,110,30,2,,streaming-co\n";
+        let mut engine = Engine::new(EnginePolicy::default());
+        for schedule in read_schedules(schedules.as_bytes()).unwrap()
+        {
+            engine.add_schedule(schedule);
+        }
+        let mut tx = Tx::new(TypeTx::Deposit, 2, 1, Some(Amount::from_minor(10000)));
+        tx.timestamp = Some(200);
+        engine.apply(tx);
+        //deposits at 100, 130, 160 and 190, withdrawals at 110 and 140
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(320000));
+        assert_eq!(engine.clients[&1].history[&u32::MAX].memo.as_deref(),Some("salary"));
+        assert_eq!(engine.counterparties["streaming-co"].withdrawals,2);
+
+        assert!(read_schedules("type,client,amount,start,interval\ndispute,1,1.0,0,10\n".as_bytes()).is_err());
+        assert!(read_schedules("type,client,amount,start,interval\ndeposit,1,1.0,0,0\n".as_bytes()).is_err());
+    }
+}
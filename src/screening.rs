@@ -0,0 +1,159 @@
+use std::{collections::HashMap, io};
+use serde::Deserialize;
+use crate::{ClientMetadata, Tx};
+
+///
+/// The outcome of screening a client against sanctions or KYC checks
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Screening
+{
+    /// Nothing was found, the client may keep transacting
+    Clear,
+    /// The client failed screening, for the given reason
+    Reject(String),
+}
+
+///
+/// Screens a client when it makes its first deposit, and on any transaction
+/// at or above the policy's screening threshold
+///
+/// A rejected client has its account frozen, and the reason is kept in the audit trail
+///
+pub trait ScreeningProvider: Send
+{
+    /// Screens the client of a transaction
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction that triggered the screening
+    /// * 'metadata' - What the registry knows about the client, if anything
+    fn screen(&mut self, tx: &Tx, metadata: Option<&ClientMetadata>) -> Screening;
+}
+
+///
+/// Clears every client, the default of an engine
+///
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoScreening;
+impl ScreeningProvider for NoScreening
+{
+    fn screen(&mut self, _tx: &Tx, _metadata: Option<&ClientMetadata>) -> Screening {
+        Screening::Clear
+    }
+}
+
+/// A single row of a screening list
+#[derive(Debug, Deserialize)]
+struct ListEntry
+{
+    #[serde(default)]
+    client: Option<u16>,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    counterparty: Option<String>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+///
+/// Rejects clients found on a list, by client ID, by name in the metadata
+/// registry, or by the counterparty of the transaction
+///
+/// Names and counterparties are matched ignoring case
+///
+#[derive(Debug, Default, Clone)]
+pub struct ListScreening
+{
+    /// The reason given for each listed client ID
+    pub clients: HashMap<u16, String>,
+    /// The reason given for each listed name, lowercased
+    pub names: HashMap<String, String>,
+    /// The reason given for each listed counterparty, lowercased
+    pub counterparties: HashMap<String, String>,
+}
+impl ListScreening
+{
+    /// Reads a screening list, a csv with any of the columns client, name and
+    /// counterparty, and an optional reason
+    ///
+    /// A row without a reason is given "listed"
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The list as csv, with a header row
+    pub fn read<R: io::Read>(input: R) -> csv::Result<ListScreening>
+    {
+        let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+        let mut list = ListScreening::default();
+        for row in rdr.deserialize()
+        {
+            let row: ListEntry = row?;
+            let reason = row.reason.filter(|r| !r.is_empty()).unwrap_or_else(|| "listed".to_string());
+            if let Some(client) = row.client {list.clients.insert(client, reason.clone());}
+            if let Some(name) = row.name.filter(|n| !n.is_empty()) {list.names.insert(name.to_lowercase(), reason.clone());}
+            if let Some(counterparty) = row.counterparty.filter(|c| !c.is_empty()) {list.counterparties.insert(counterparty.to_lowercase(), reason);}
+        }
+        Ok(list)
+    }
+}
+impl ScreeningProvider for ListScreening
+{
+    fn screen(&mut self, tx: &Tx, metadata: Option<&ClientMetadata>) -> Screening {
+        let name = metadata.and_then(|m| m.name.as_deref()).map(|n| n.to_lowercase());
+        let counterparty = tx.counterparty.as_deref().map(|c| c.to_lowercase());
+        let found = self.clients.get(&tx.client)
+            .or_else(|| name.and_then(|n| self.names.get(&n)))
+            .or_else(|| counterparty.and_then(|c| self.counterparties.get(&c)));
+        match found
+        {
+            Some(reason) => Screening::Reject(reason.clone()),
+            None => Screening::Clear
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, Engine, EnginePolicy, Notification, RejectReason, TypeTx};
+
+    fn tx(r#type: TypeTx, client: u16, tx: u32, minor: i64) -> Tx
+    {
+        Tx::new(r#type, client, tx, Some(Amount::from_minor(minor)))
+    }
+
+    #[test]
+    fn list_freezes()
+    {
+        let list = "client,name,counterparty,reason\n2,,,sanctioned\n,Mallory,,\n,,Shady Bank,high risk\n";
+        let list = ListScreening::read(list.as_bytes()).unwrap();
+        let mut engine = Engine::new(EnginePolicy { screen_above: Some(Amount::from_minor(1000000)), ..EnginePolicy::default() });
+        engine.load_metadata(crate::read_metadata("client,name\n3,mallory\n".as_bytes()).unwrap());
+        engine.set_screening(Box::new(list));
+
+        engine.apply(tx(TypeTx::Deposit, 1, 1, 50000));
+        engine.apply(tx(TypeTx::Deposit, 2, 2, 50000));
+        engine.apply(tx(TypeTx::Withdrawal, 2, 3, 10000));
+        engine.apply(tx(TypeTx::Deposit, 3, 4, 50000));
+        assert!(!engine.clients[&1].frozen);
+        assert!(engine.clients[&2].frozen && engine.clients[&3].frozen);
+        assert_eq!(engine.clients[&2].acc.total,Amount::from_minor(50000));
+        assert_eq!(engine.rejections[0].reason,RejectReason::AccountFrozen);
+
+        let mut shady = tx(TypeTx::Deposit, 1, 5, 10000);
+        shady.counterparty = Some("shady bank".to_string());
+        engine.apply(shady.clone());
+        assert!(!engine.clients[&1].frozen);
+        shady.tx = 6;
+        shady.amount = Some(Amount::from_minor(1000000));
+        engine.apply(shady);
+        assert!(engine.clients[&1].frozen);
+        assert_eq!(engine.audit,vec![
+            Notification::ScreeningFailed { client: 2, tx: 2, reason: "sanctioned".to_string() },
+            Notification::ScreeningFailed { client: 3, tx: 4, reason: "listed".to_string() },
+            Notification::ScreeningFailed { client: 1, tx: 6, reason: "high risk".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,349 @@
+use crate::{Account, Amount, AmountError, Tx};
+
+/// The memo of the withdrawals made for fees a policy hook charges
+pub const FEE_MEMO: &str = "fee";
+
+///
+/// Decides policy points the engine asks about as it applies transactions, for
+/// deployments that need rules of their own without recompiling
+///
+/// Every method has a default that leaves the engine's behaviour as it is, so a hook
+/// only needs to implement the points it cares about. Fees charged for a hook aren't
+/// put to it again
+///
+pub trait PolicyHook: Send
+{
+    /// Whether the transaction may be applied, asked once it has passed the engine's own checks
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction about to be applied
+    /// * 'account' - The account as it is, all zero if the client is new
+    fn accept(&mut self, _tx: &Tx, _account: &Account) -> bool
+    {
+        true
+    }
+    /// Whether to freeze the account once the transaction has been applied
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction just applied
+    /// * 'account' - The account as it is now
+    fn freeze(&mut self, _tx: &Tx, _account: &Account) -> bool
+    {
+        false
+    }
+    /// The fee to charge for the transaction once it has been applied, withdrawn
+    /// from the account under a transaction ID of its own
+    ///
+    /// A fee that can't be given as an amount, E.G. one with more decimals than the
+    /// rounding policy lets through, is an error, and the fee is rejected
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction just applied
+    /// * 'account' - The account as it is now
+    fn fee(&mut self, _tx: &Tx, _account: &Account) -> Result<Option<Amount>, AmountError>
+    {
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "scripting")]
+pub use rhai_policy::{RhaiPolicy, ScriptError, MAX_CALL_LEVELS, MAX_OPERATIONS};
+
+#[cfg(feature = "scripting")]
+mod rhai_policy
+{
+    use std::{fmt, io};
+    use rhai::{Dynamic, Map, Scope, AST};
+    use super::PolicyHook;
+    use crate::{Account, Amount, AmountError, RoundingMode, Tx, TypeTx};
+
+    /// How many operations a single call of the script may take, so a script that
+    /// runs away fails instead of holding up the run
+    pub const MAX_OPERATIONS: u64 = 1_000_000;
+    /// How deep the functions of the script may call each other
+    pub const MAX_CALL_LEVELS: usize = 64;
+
+    ///
+    /// The policy script couldn't be read or compiled
+    ///
+    #[derive(Debug)]
+    pub enum ScriptError
+    {
+        Io(io::Error),
+        Compile(String),
+    }
+    impl fmt::Display for ScriptError
+    {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            match self
+            {
+                ScriptError::Io(e) => write!(f, "{}", e),
+                ScriptError::Compile(e) => write!(f, "{}", e),
+            }
+        }
+    }
+    impl From<io::Error> for ScriptError
+    {
+        fn from(e: io::Error) -> Self {
+            ScriptError::Io(e)
+        }
+    }
+    impl From<ScriptError> for io::Error
+    {
+        fn from(e: ScriptError) -> Self {
+            match e
+            {
+                ScriptError::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+            }
+        }
+    }
+
+    ///
+    /// A policy hook written as a rhai script, defining any of the functions
+    /// accept(tx, account), freeze(tx, account) and fee(tx, account)
+    ///
+    /// The transaction is a map of type, client, tx, amount, timestamp, currency, memo
+    /// and counterparty, and the account one of client, available, held, total, pending
+    /// and locked, with amounts as floats and missing values as (). Every amount is also
+    /// given exactly, as an integer of ten-thousandths, under its name with _minor, E.G.
+    /// amount_minor. fee returns the fee as a number or a string, or () for none; an
+    /// integer is whole units, minor(n) gives the fee of n ten-thousandths, and a float
+    /// is cut to ten decimals before the rounding policy applies, so float noise such as
+    /// 0.30000000000000004 doesn't get it rejected
+    ///
+    /// A script that fails while running, or takes more than MAX_OPERATIONS, refuses the
+    /// transaction it was asked about, and neither freezes the account nor charges a fee
+    ///
+    pub struct RhaiPolicy
+    {
+        engine: rhai::Engine,
+        ast: AST,
+        /// How a fee with more than four decimals is rounded
+        rounding: RoundingMode,
+    }
+    impl RhaiPolicy
+    {
+        /// Compiles a policy script
+        ///
+        /// # Arguments
+        ///
+        /// * 'script' - The source of the script
+        /// * 'rounding' - How a fee with more than four decimals is rounded
+        pub fn compile(script: &str, rounding: RoundingMode) -> Result<RhaiPolicy, ScriptError>
+        {
+            let mut engine = rhai::Engine::new();
+            engine.set_max_operations(MAX_OPERATIONS);
+            engine.set_max_call_levels(MAX_CALL_LEVELS);
+            engine.register_fn("minor", |minor: i64| Amount::from_minor(minor).to_string());
+            let ast = engine.compile(script).map_err(|e| ScriptError::Compile(e.to_string()))?;
+            Ok(RhaiPolicy { engine, ast, rounding })
+        }
+        /// Reads and compiles the policy script at the path
+        ///
+        /// # Arguments
+        ///
+        /// * 'path' - Where the script is
+        /// * 'rounding' - How a fee with more than four decimals is rounded
+        pub fn read(path: &str, rounding: RoundingMode) -> Result<RhaiPolicy, ScriptError>
+        {
+            RhaiPolicy::compile(&std::fs::read_to_string(path)?, rounding)
+        }
+        /// Calls a function of the script, None if it doesn't define it or it fails
+        fn call(&self, name: &str, tx: &Tx, account: &Account) -> Option<Dynamic>
+        {
+            if !self.defines(name) {return None}
+            self.engine.call_fn::<Dynamic>(&mut Scope::new(), &self.ast, name, (tx_map(tx), account_map(account))).ok()
+        }
+        /// Whether the script defines the function
+        fn defines(&self, name: &str) -> bool
+        {
+            self.ast.iter_functions().any(|f| f.name == name && f.params.len() == 2)
+        }
+    }
+    impl PolicyHook for RhaiPolicy
+    {
+        fn accept(&mut self, tx: &Tx, account: &Account) -> bool
+        {
+            if !self.defines("accept") {return true}
+            self.call("accept", tx, account).and_then(|d| d.as_bool().ok()).unwrap_or(false)
+        }
+        fn freeze(&mut self, tx: &Tx, account: &Account) -> bool
+        {
+            self.call("freeze", tx, account).and_then(|d| d.as_bool().ok()).unwrap_or(false)
+        }
+        fn fee(&mut self, tx: &Tx, account: &Account) -> Result<Option<Amount>, AmountError>
+        {
+            let fee = match self.call("fee", tx, account)
+            {
+                Some(fee) if !fee.is_unit() => fee,
+                _ => return Ok(None)
+            };
+            let text = match (fee.as_int(), fee.as_float())
+            {
+                (Ok(int), _) => int.to_string(),
+                (_, Ok(float)) if float.is_finite() => format!("{:.10}", float),
+                (_, Ok(_)) => return Err(AmountError::NonFinite),
+                _ => fee.into_string().map_err(|_| AmountError::Invalid)?
+            };
+            let fee = Amount::parse(&text, self.rounding)?;
+            Ok((fee > Amount::ZERO).then_some(fee))
+        }
+    }
+
+    fn amount(amount: Amount) -> Dynamic
+    {
+        Dynamic::from_float(amount.minor() as f64 / 10f64.powi(crate::AMOUNT_PRECISION as i32))
+    }
+    /// Puts the amount in the map as a float, and exactly in minor units under its name with _minor
+    fn insert_amount(map: &mut Map, name: &str, value: Option<Amount>)
+    {
+        map.insert(name.into(), optional(value, amount));
+        map.insert(format!("{}_minor", name).into(), optional(value, |a| Dynamic::from_int(a.minor())));
+    }
+    fn optional<T, F: FnOnce(T) -> Dynamic>(value: Option<T>, f: F) -> Dynamic
+    {
+        value.map_or(Dynamic::UNIT, f)
+    }
+
+    /// The transaction as the script sees it
+    fn tx_map(tx: &Tx) -> Map
+    {
+        let r#type = match (tx.r#type, &tx.custom)
+        {
+            (TypeTx::Custom, Some(name)) => name.clone(),
+            (t, _) => t.as_str().to_string()
+        };
+        let mut map = Map::new();
+        map.insert("type".into(), r#type.into());
+        map.insert("client".into(), Dynamic::from_int(tx.client.into()));
+        map.insert("tx".into(), Dynamic::from_int(tx.tx.into()));
+        insert_amount(&mut map, "amount", tx.amount);
+        map.insert("timestamp".into(), optional(tx.timestamp, Dynamic::from_int));
+        map.insert("currency".into(), optional(tx.currency.clone(), Dynamic::from));
+        map.insert("memo".into(), optional(tx.memo.clone(), Dynamic::from));
+        map.insert("counterparty".into(), optional(tx.counterparty.clone(), Dynamic::from));
+        map
+    }
+
+    /// The account as the script sees it
+    fn account_map(account: &Account) -> Map
+    {
+        let mut map = Map::new();
+        map.insert("client".into(), Dynamic::from_int(account.client.into()));
+        insert_amount(&mut map, "available", Some(account.available));
+        insert_amount(&mut map, "held", Some(account.held));
+        insert_amount(&mut map, "total", Some(account.total));
+        insert_amount(&mut map, "pending", Some(account.pending));
+        map.insert("locked".into(), account.locked.into());
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EnginePolicy, Notification, RejectReason, TypeTx};
+
+    /// Refuses withdrawals over 100, freezes accounts that go over 1000 and charges 1% on withdrawals
+    struct Rules;
+    impl PolicyHook for Rules
+    {
+        fn accept(&mut self, tx: &Tx, _account: &Account) -> bool
+        {
+            !(tx.r#type == TypeTx::Withdrawal && tx.amount.is_some_and(|a| a > Amount::from_minor(1000000)))
+        }
+        fn freeze(&mut self, _tx: &Tx, account: &Account) -> bool
+        {
+            account.total > Amount::from_minor(10000000)
+        }
+        fn fee(&mut self, tx: &Tx, _account: &Account) -> Result<Option<Amount>, AmountError>
+        {
+            match tx.r#type
+            {
+                TypeTx::Withdrawal => Ok(tx.amount.and_then(|a| a.checked_mul_rate(Amount::from_minor(100)))),
+                _ => Ok(None)
+            }
+        }
+    }
+
+    fn run(hook: Box<dyn PolicyHook>) -> Engine
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_policy_hook(hook);
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(5000000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 2, Some(Amount::from_minor(2000000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 3, Some(Amount::from_minor(500000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 4, Some(Amount::from_minor(20000000))));
+        engine
+    }
+
+    fn check(engine: &Engine)
+    {
+        assert_eq!(engine.rejections.iter().map(|r| (r.tx, r.reason)).collect::<Vec<_>>(),vec![(2, RejectReason::RefusedByPolicy)]);
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(4495000));
+        assert!(!engine.clients[&1].frozen && engine.clients[&2].frozen);
+        assert_eq!(engine.audit.last(),Some(&Notification::PolicyFrozen { client: 2, tx: 4 }));
+    }
+
+    #[test]
+    fn policy_hooks()
+    {
+        check(&run(Box::new(Rules)));
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn policy_script()
+    {
+        let script = r#"
+            fn accept(tx, account) { !(tx.type == "withdrawal" && tx.amount > 100.0) }
+            fn freeze(tx, account) { account.total > 1000.0 }
+            fn fee(tx, account) { if tx.type == "withdrawal" { tx.amount * 0.01 } else { () } }
+        "#;
+        let policy = RhaiPolicy::compile(script, crate::RoundingMode::HalfEven).unwrap();
+        check(&run(Box::new(policy)));
+        assert!(RhaiPolicy::compile("fn accept(tx, account) {", crate::RoundingMode::HalfEven).is_err());
+
+        //a script that fails refuses rather than lets through
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_policy_hook(Box::new(RhaiPolicy::compile("fn accept(tx, account) { tx.nope.field }", crate::RoundingMode::HalfEven).unwrap()));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(10000))));
+        assert_eq!(engine.rejections[0].reason,RejectReason::RefusedByPolicy);
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn script_fees_exact()
+    {
+        let fees = |script: &str| {
+            let mut engine = Engine::new(EnginePolicy::default());
+            engine.set_policy_hook(Box::new(RhaiPolicy::compile(script, crate::RoundingMode::Reject).unwrap()));
+            engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(30000))));
+            engine
+        };
+        //3.0 * 0.1 is 0.30000000000000004 as a float
+        let engine = fees(r#"fn fee(tx, account) { if tx.type == "deposit" { tx.amount * 0.1 } else { () } }"#);
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(27000));
+
+        let engine = fees(r#"fn fee(tx, account) { if tx.type == "deposit" { minor(tx.amount_minor / 3) } else { () } }"#);
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(20000));
+
+        let engine = fees(r#"fn fee(tx, account) { if tx.type == "deposit" { 0.00001 } else { () } }"#);
+        assert_eq!(engine.rejections.iter().map(|r| (r.r#type, r.reason)).collect::<Vec<_>>(),vec![(TypeTx::Withdrawal, RejectReason::Precision)]);
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(30000));
+    }
+
+    #[cfg(feature = "scripting")]
+    #[test]
+    fn runaway_script_refused()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_policy_hook(Box::new(RhaiPolicy::compile("fn accept(tx, account) { loop {} }", crate::RoundingMode::HalfEven).unwrap()));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(10000))));
+        assert_eq!(engine.rejections[0].reason,RejectReason::RefusedByPolicy);
+    }
+}
@@ -0,0 +1,154 @@
+#[cfg(feature = "ed25519")]
+use std::{collections::HashMap, convert::TryFrom, io};
+use crate::Tx;
+
+/// The text a transaction's signature is made over: its type, client, transaction
+/// ID and amount separated by commas, E.G. "deposit,1,42,10.5"
+///
+/// The amount is written the way the engine writes it, without trailing zeros,
+/// and is left empty for disputes and the like
+///
+/// # Arguments
+///
+/// * 'tx' - The transaction to sign or verify
+pub fn signed_message(tx: &Tx) -> String
+{
+    let amount = tx.amount.map(|a| a.to_string()).unwrap_or_default();
+    format!("{},{},{},{}", tx.r#type.as_str(), tx.client, tx.tx, amount)
+}
+
+///
+/// Checks the signature of every transaction before it is applied, so only
+/// transactions signed by a trusted key are accepted
+///
+/// A transaction that fails is rejected as invalid_signature
+///
+pub trait SignatureVerifier: Send
+{
+    /// Whether the transaction carries a valid signature
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction to check, signature included
+    fn verify(&self, tx: &Tx) -> bool;
+}
+
+///
+/// Verifies ed25519 signatures against the public key of each client
+///
+/// Clients without a key can't have any transaction accepted
+///
+#[cfg(feature = "ed25519")]
+#[derive(Debug, Default, Clone)]
+pub struct Ed25519Verifier
+{
+    /// The public key of each client
+    pub keys: HashMap<u16, ed25519_dalek::VerifyingKey>,
+}
+#[cfg(feature = "ed25519")]
+impl Ed25519Verifier
+{
+    /// Reads the public keys, a csv with the columns client and public_key, the
+    /// key hex encoded
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The keys as csv, with a header row
+    pub fn read<R: io::Read>(input: R) -> csv::Result<Ed25519Verifier>
+    {
+        #[derive(serde::Deserialize)]
+        struct KeyRow
+        {
+            client: u16,
+            public_key: String,
+        }
+        let mut rdr = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(input);
+        let mut verifier = Ed25519Verifier::default();
+        for row in rdr.deserialize()
+        {
+            let row: KeyRow = row?;
+            let key = hex::decode(&row.public_key).ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .and_then(|bytes| ed25519_dalek::VerifyingKey::from_bytes(&bytes).ok());
+            match key
+            {
+                Some(key) => {verifier.keys.insert(row.client, key);},
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("public key of client {} isn't a valid ed25519 key", row.client)).into())
+            }
+        }
+        Ok(verifier)
+    }
+}
+#[cfg(feature = "ed25519")]
+impl SignatureVerifier for Ed25519Verifier
+{
+    fn verify(&self, tx: &Tx) -> bool {
+        let signature = tx.signature.as_deref()
+            .and_then(|s| hex::decode(s).ok())
+            .and_then(|bytes| <[u8; 64]>::try_from(bytes).ok())
+            .map(|bytes| ed25519_dalek::Signature::from_bytes(&bytes));
+        match (self.keys.get(&tx.client), signature)
+        {
+            (Some(key), Some(signature)) => key.verify_strict(signed_message(tx).as_bytes(), &signature).is_ok(),
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, Engine, EnginePolicy, RejectReason, TypeTx};
+
+    /// Accepts the signature if it's the message reversed
+    struct Reversed;
+    impl SignatureVerifier for Reversed
+    {
+        fn verify(&self, tx: &Tx) -> bool {
+            tx.signature == Some(signed_message(tx).chars().rev().collect())
+        }
+    }
+
+    fn signed(mut tx: Tx) -> Tx
+    {
+        tx.signature = Some(signed_message(&tx).chars().rev().collect());
+        tx
+    }
+
+    #[test]
+    fn only_signed_accepted()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.set_verifier(Box::new(Reversed));
+        engine.apply(signed(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(15000)))));
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 2, Some(Amount::from_minor(10000))));
+        let mut forged = signed(Tx::new(TypeTx::Withdrawal, 1, 3, Some(Amount::from_minor(5000))));
+        forged.amount = Some(Amount::from_minor(15000));
+        engine.apply(forged);
+        engine.apply(signed(Tx::new(TypeTx::Dispute, 1, 1, None)));
+        assert_eq!(signed_message(&Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(15000)))),"deposit,1,1,1.5");
+        assert_eq!(engine.clients[&1].acc.held,Amount::from_minor(15000));
+        assert_eq!(engine.clients[&1].acc.total,Amount::from_minor(15000));
+        assert_eq!(engine.rejections.iter().map(|r| (r.tx, r.reason)).collect::<Vec<_>>(),
+            vec![(2, RejectReason::InvalidSignature), (3, RejectReason::InvalidSignature)]);
+    }
+
+    #[cfg(feature = "ed25519")]
+    #[test]
+    fn ed25519_signatures()
+    {
+        use ed25519_dalek::{Signer, SigningKey};
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let keys = format!("client,public_key\n1,{}\n", hex::encode(key.verifying_key().as_bytes()));
+        let verifier = Ed25519Verifier::read(keys.as_bytes()).unwrap();
+        let mut tx = Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(15000)));
+        tx.signature = Some(hex::encode(key.sign(signed_message(&tx).as_bytes()).to_bytes()));
+        assert!(verifier.verify(&tx));
+        let mut other = tx.clone();
+        other.client = 2;
+        assert!(!verifier.verify(&other));
+        tx.tx = 2;
+        assert!(!verifier.verify(&tx));
+        assert!(Ed25519Verifier::read("client,public_key\n1,abcd\n".as_bytes()).is_err());
+    }
+}
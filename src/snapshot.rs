@@ -0,0 +1,442 @@
+use std::{collections::{BTreeMap, HashMap}, convert::TryFrom, fmt, fs, io};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use crate::{Client, CounterpartyStats, DisputeEvent, Engine, EnginePolicy, IngestedFile, Period, Reserve, ReviewEntry, TxDedup, WalMark};
+
+/// The first bytes of an encrypted snapshot, followed by the nonce and the ciphertext
+const ENCRYPTED_MAGIC: &[u8; 8] = b"CTXSNAP\x01";
+/// The layout snapshots are written in, older ones are migrated when restored
+pub const SNAPSHOT_VERSION: u64 = 6;
+/// Upgrades a snapshot by one version, the first from v1 to v2
+const MIGRATIONS: [fn(&mut Map<String, Value>); 5] = [v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6];
+/// How long the AES-GCM nonce is
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+
+///
+/// What a snapshot keeps of an engine
+///
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SnapshotState
+{
+    /// The layout it was written in, v1 snapshots have none
+    version: u64,
+    /// Ordered by client, so the same engine always gives the same snapshot
+    clients: Vec<Client>,
+    counterparties: HashMap<String, CounterpartyStats>,
+    review_queue: BTreeMap<u16, ReviewEntry>,
+    reserve: Option<Reserve>,
+    /// The sequence number of the latest changes emitted
+    change_sequence: u64,
+    /// The input files processed in full
+    ingested: Vec<IngestedFile>,
+    /// How many rows had been applied
+    rows: u64,
+    /// Every dispute opened and closed
+    dispute_events: Vec<DisputeEvent>,
+    /// The transaction IDs used by any client, in ascending order, if they were deduped globally
+    used_txs: Vec<u32>,
+    /// How far into its write-ahead log the engine got, and the durability it was written at
+    wal: Option<WalMark>,
+    /// The period the engine was in
+    period: Period,
+}
+
+/// v2 added the change sequence and the files ingested
+fn v1_to_v2(state: &mut Map<String, Value>)
+{
+    state.entry("change_sequence").or_insert_with(|| Value::from(0));
+    state.entry("ingested").or_insert_with(|| Value::Array(Vec::new()));
+}
+
+/// v3 added the row count and the dispute events
+fn v2_to_v3(state: &mut Map<String, Value>)
+{
+    state.entry("rows").or_insert_with(|| Value::from(0));
+    state.entry("dispute_events").or_insert_with(|| Value::Array(Vec::new()));
+}
+
+/// v4 added the transaction IDs used, for global dedup
+fn v3_to_v4(state: &mut Map<String, Value>)
+{
+    state.entry("used_txs").or_insert_with(|| Value::Array(Vec::new()));
+}
+
+/// v5 added the write-ahead log mark
+fn v4_to_v5(state: &mut Map<String, Value>)
+{
+    state.entry("wal").or_insert(Value::Null);
+}
+
+/// v6 added the period, which for older snapshots started with the engine
+fn v5_to_v6(state: &mut Map<String, Value>)
+{
+    state.entry("period").or_insert_with(|| serde_json::json!({"closed": 0, "opened_at": 0, "opening": {}, "totals": {}}));
+}
+
+/// Reads a snapshot of any version up to the current one, upgrading it step by step
+///
+/// # Arguments
+///
+/// * 'plain' - The snapshot json, decrypted if it was encrypted
+fn migrate(plain: &[u8]) -> Result<SnapshotState, SnapshotError>
+{
+    let mut value: Value = serde_json::from_slice(plain)?;
+    if let Some(state) = value.as_object_mut()
+    {
+        let version = state.get("version").map_or(Some(1), Value::as_u64).unwrap_or(0);
+        if version == 0 || version > SNAPSHOT_VERSION
+        {
+            return Err(SnapshotError::Version(version));
+        }
+        for migration in &MIGRATIONS[version as usize - 1..]
+        {
+            migration(state);
+        }
+        state.insert("version".to_string(), Value::from(SNAPSHOT_VERSION));
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+///
+/// Something went wrong writing or restoring a snapshot
+///
+#[derive(Debug)]
+pub enum SnapshotError
+{
+    Io(io::Error),
+    Json(serde_json::Error),
+    /// The snapshot is encrypted, and no key was given
+    KeyRequired,
+    /// The snapshot couldn't be decrypted, the key is wrong or the file was changed
+    Decrypt,
+    /// A key was given, but this build can't encrypt
+    Unsupported,
+    /// The snapshot is of a version this build doesn't know, E.G. written by a newer one
+    Version(u64),
+}
+impl fmt::Display for SnapshotError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::Json(e) => write!(f, "{}", e),
+            SnapshotError::KeyRequired => write!(f, "the snapshot is encrypted, and no key was given"),
+            SnapshotError::Decrypt => write!(f, "the snapshot couldn't be decrypted, the key is wrong or the file was changed"),
+            SnapshotError::Unsupported => write!(f, "built without encryption support"),
+            SnapshotError::Version(v) => write!(f, "the snapshot is of version {}, this build reads up to {}", v, SNAPSHOT_VERSION),
+        }
+    }
+}
+impl From<io::Error> for SnapshotError
+{
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+impl From<serde_json::Error> for SnapshotError
+{
+    fn from(e: serde_json::Error) -> Self {
+        SnapshotError::Json(e)
+    }
+}
+impl From<SnapshotError> for io::Error
+{
+    fn from(e: SnapshotError) -> Self {
+        match e
+        {
+            SnapshotError::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        }
+    }
+}
+
+///
+/// A 256 bit AES-GCM key snapshots are encrypted with
+///
+#[derive(Clone, PartialEq)]
+pub struct SnapshotKey([u8; 32]);
+impl SnapshotKey
+{
+    /// The environment variable a hex encoded key is taken from
+    pub const ENV: &'static str = "CSV_TRANSACTIONS_SNAPSHOT_KEY";
+
+    /// Returns the key made of the given bytes
+    pub fn new(bytes: [u8; 32]) -> SnapshotKey
+    {
+        SnapshotKey(bytes)
+    }
+    /// Parses a key written as 64 hex digits
+    ///
+    /// # Arguments
+    ///
+    /// * 'text' - The key, surrounding whitespace is ignored
+    pub fn from_hex(text: &str) -> Option<SnapshotKey>
+    {
+        let text = text.trim();
+        if text.len() != 64 || !text.is_ascii() {return None}
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate()
+        {
+            *byte = u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()?;
+        }
+        Some(SnapshotKey(bytes))
+    }
+    /// Reads a key file, holding either the 32 bytes of the key or them as hex
+    ///
+    /// # Arguments
+    ///
+    /// * 'path' - Where the key file is
+    pub fn read(path: &str) -> io::Result<SnapshotKey>
+    {
+        let bytes = fs::read(path)?;
+        if let Ok(raw) = <[u8; 32]>::try_from(bytes.as_slice())
+        {
+            return Ok(SnapshotKey(raw));
+        }
+        std::str::from_utf8(&bytes).ok().and_then(SnapshotKey::from_hex)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the key file holds neither 32 bytes nor 64 hex digits"))
+    }
+    /// Takes the key from the CSV_TRANSACTIONS_SNAPSHOT_KEY environment variable, if it is set
+    pub fn from_env() -> Option<io::Result<SnapshotKey>>
+    {
+        let text = std::env::var(SnapshotKey::ENV).ok()?;
+        Some(SnapshotKey::from_hex(&text).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't 64 hex digits", SnapshotKey::ENV))))
+    }
+}
+/// Keeps the key out of logs
+impl fmt::Debug for SnapshotKey
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SnapshotKey(..)")
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn encrypt(key: &SnapshotKey, plain: &[u8]) -> Result<Vec<u8>, SnapshotError>
+{
+    use aes_gcm::{aead::{Aead, AeadCore, KeyInit, OsRng}, Aes256Gcm};
+    let cipher = Aes256Gcm::new(&key.0.into());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, plain).map_err(|_| SnapshotError::Decrypt)?;
+    Ok(ENCRYPTED_MAGIC.iter().chain(nonce.iter()).chain(sealed.iter()).copied().collect())
+}
+#[cfg(not(feature = "encryption"))]
+fn encrypt(_key: &SnapshotKey, _plain: &[u8]) -> Result<Vec<u8>, SnapshotError>
+{
+    Err(SnapshotError::Unsupported)
+}
+
+/// Decrypts what follows the magic bytes of an encrypted snapshot
+#[cfg(feature = "encryption")]
+fn decrypt(key: &SnapshotKey, sealed: &[u8]) -> Result<Vec<u8>, SnapshotError>
+{
+    use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
+    if sealed.len() < NONCE_LEN {return Err(SnapshotError::Decrypt)}
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    Aes256Gcm::new(&key.0.into()).decrypt(Nonce::from_slice(nonce), ciphertext).map_err(|_| SnapshotError::Decrypt)
+}
+#[cfg(not(feature = "encryption"))]
+fn decrypt(_key: &SnapshotKey, _sealed: &[u8]) -> Result<Vec<u8>, SnapshotError>
+{
+    Err(SnapshotError::Unsupported)
+}
+
+/// Encrypts a line of the write-ahead log as encrypt does, written as hex so it stays
+/// a single line
+///
+/// # Arguments
+///
+/// * 'key' - The key to encrypt with
+/// * 'plain' - The line, without its newline
+pub(crate) fn seal_line(key: &SnapshotKey, plain: &[u8]) -> Result<Vec<u8>, SnapshotError>
+{
+    Ok(encrypt(key, plain)?.iter().flat_map(|b| format!("{:02x}", b).into_bytes()).collect())
+}
+/// Decrypts a line sealed by seal_line
+///
+/// # Arguments
+///
+/// * 'key' - The key it was encrypted with
+/// * 'line' - The hex of the line, without its newline
+pub(crate) fn open_line(key: &SnapshotKey, line: &[u8]) -> Result<Vec<u8>, SnapshotError>
+{
+    if !line.len().is_multiple_of(2) {return Err(SnapshotError::Decrypt)}
+    let bytes = line.chunks(2)
+        .map(|pair| std::str::from_utf8(pair).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok()))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or(SnapshotError::Decrypt)?;
+    match bytes.strip_prefix(ENCRYPTED_MAGIC.as_slice())
+    {
+        Some(sealed) => decrypt(key, sealed),
+        None => Err(SnapshotError::Decrypt)
+    }
+}
+
+impl Engine
+{
+    /// Writes the clients, counterparty figures, review queue, reserve, the files
+    /// ingested, the dispute events and the transaction IDs used as json, encrypted with AES-256-GCM if a key is given
+    ///
+    /// Rejections, the audit trail, schedules and open savepoints aren't kept
+    ///
+    /// # Arguments
+    ///
+    /// * 'out' - Where to write the snapshot to
+    /// * 'key' - The key to encrypt with, the snapshot is written as plain json if None
+    pub fn snapshot_to<W: io::Write>(&self, out: W, key: Option<&SnapshotKey>) -> Result<(), SnapshotError>
+    {
+        self.capture().write_to(out, key)
+    }
+    /// Copies what a snapshot keeps of the engine, so it can be written while the
+    /// engine goes on
+    pub(crate) fn capture(&self) -> SnapshotState
+    {
+        let mut clients: Vec<Client> = self.clients.values().cloned().collect();
+        clients.sort_by_key(|c| c.acc.client);
+        SnapshotState {
+            version: SNAPSHOT_VERSION,
+            clients,
+            counterparties: self.counterparties.clone(),
+            review_queue: self.review_queue.clone(),
+            reserve: self.reserve.clone(),
+            change_sequence: self.change_sequence,
+            ingested: self.ingested.clone(),
+            rows: self.rows,
+            dispute_events: self.dispute_events.clone(),
+            used_txs: self.dedup.as_ref().map(TxDedup::ids).unwrap_or_default(),
+            wal: self.wal,
+            period: self.period.clone(),
+        }
+    }
+}
+
+impl SnapshotState
+{
+    /// Writes the state as json, encrypted if a key is given
+    ///
+    /// # Arguments
+    ///
+    /// * 'out' - Where to write the snapshot to
+    /// * 'key' - The key to encrypt with, the snapshot is written as plain json if None
+    pub(crate) fn write_to<W: io::Write>(&self, mut out: W, key: Option<&SnapshotKey>) -> Result<(), SnapshotError>
+    {
+        let plain = serde_json::to_vec(self)?;
+        match key
+        {
+            Some(key) => out.write_all(&encrypt(key, &plain)?)?,
+            None => out.write_all(&plain)?
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl Engine
+{
+    /// Returns an engine following the given policy, with the state of a snapshot
+    ///
+    /// Encrypted snapshots are recognised and decrypted, plain ones are read as they are,
+    /// and snapshots of an older version are migrated to the current layout
+    ///
+    /// # Arguments
+    ///
+    /// * 'input' - The snapshot as written by snapshot_to
+    /// * 'policy' - The rules the engine follows from here on
+    /// * 'key' - The key the snapshot was encrypted with, if it was
+    pub fn restore_from<R: io::Read>(mut input: R, policy: EnginePolicy, key: Option<&SnapshotKey>) -> Result<Engine, SnapshotError>
+    {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        let plain = match (bytes.strip_prefix(ENCRYPTED_MAGIC.as_slice()), key)
+        {
+            (Some(sealed), Some(key)) => decrypt(key, sealed)?,
+            (Some(_), None) => return Err(SnapshotError::KeyRequired),
+            (None, _) => bytes
+        };
+        let state = migrate(&plain)?;
+        let mut engine = Engine::new(policy);
+        engine.clients = state.clients.into_iter().map(|c| (c.acc.client, c)).collect();
+        engine.counterparties = state.counterparties;
+        engine.review_queue = state.review_queue;
+        engine.change_sequence = state.change_sequence;
+        engine.ingested = state.ingested;
+        engine.rows = state.rows;
+        engine.dispute_events = state.dispute_events;
+        engine.wal = state.wal;
+        engine.period = state.period;
+        if let Some(dedup) = engine.dedup.as_mut()
+        {
+            state.used_txs.into_iter().for_each(|id| dedup.insert(id));
+        }
+        if state.reserve.is_some()
+        {
+            engine.reserve = state.reserve;
+        }
+        Ok(engine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, Tx, TypeTx};
+
+    fn engine() -> Engine
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(15000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 2, 2, None));
+        engine
+    }
+
+    #[test]
+    fn snapshot_round_trip()
+    {
+        let mut out = Vec::new();
+        engine().snapshot_to(&mut out, None).unwrap();
+        let restored = Engine::restore_from(out.as_slice(), EnginePolicy::default(), None).unwrap();
+        assert_eq!(restored.clients[&1].acc.available,Amount::from_minor(15000));
+        assert_eq!(restored.clients[&2].acc.held,Amount::from_minor(20000));
+        assert!(restored.clients[&2].history[&2].in_dispute);
+        assert_eq!((restored.rows, restored.dispute_events.len()),(3, 1));
+
+        let key = SnapshotKey::new([3; 32]);
+        assert_eq!(SnapshotKey::from_hex(&"03".repeat(32)),Some(key.clone()));
+        assert!(SnapshotKey::from_hex("03").is_none());
+        #[cfg(not(feature = "encryption"))]
+        assert!(matches!(engine().snapshot_to(Vec::new(), Some(&key)), Err(SnapshotError::Unsupported)));
+        #[cfg(feature = "encryption")]
+        {
+            let mut sealed = Vec::new();
+            engine().snapshot_to(&mut sealed, Some(&key)).unwrap();
+            assert!(sealed.starts_with(ENCRYPTED_MAGIC) && !sealed.windows(6).any(|w| w == b"client"));
+            let restored = Engine::restore_from(sealed.as_slice(), EnginePolicy::default(), Some(&key)).unwrap();
+            assert_eq!(restored.clients[&2].acc.total,Amount::from_minor(20000));
+            assert!(matches!(Engine::restore_from(sealed.as_slice(), EnginePolicy::default(), None), Err(SnapshotError::KeyRequired)));
+            assert!(matches!(Engine::restore_from(sealed.as_slice(), EnginePolicy::default(), Some(&SnapshotKey::new([4; 32]))), Err(SnapshotError::Decrypt)));
+        }
+    }
+
+    #[test]
+    fn snapshot_migration()
+    {
+        let mut current = Vec::new();
+        engine().snapshot_to(&mut current, None).unwrap();
+        let mut state: Value = serde_json::from_slice(&current).unwrap();
+        assert_eq!(state["version"],Value::from(SNAPSHOT_VERSION));
+
+        //v1 snapshots had no version, change sequence, files ingested, row count, dispute events, used IDs, log mark or period
+        let v1 = state.as_object_mut().unwrap();
+        for field in &["version", "change_sequence", "ingested", "rows", "dispute_events", "used_txs", "wal", "period"] {v1.remove(*field);}
+        let restored = Engine::restore_from(serde_json::to_vec(&state).unwrap().as_slice(), EnginePolicy::default(), None).unwrap();
+        assert_eq!(restored.clients[&2].acc.total,Amount::from_minor(20000));
+        assert_eq!((restored.change_sequence, restored.ingested.len(), restored.rows, restored.dispute_events.len()),(0, 0, 0, 0));
+
+        state["version"] = Value::from(SNAPSHOT_VERSION + 1);
+        let newer = Engine::restore_from(serde_json::to_vec(&state).unwrap().as_slice(), EnginePolicy::default(), None);
+        assert!(matches!(newer, Err(SnapshotError::Version(v)) if v == SNAPSHOT_VERSION + 1));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{io, sync::{mpsc, Arc, Mutex}, time::Duration};
+use serde::Serialize;
+use crate::{RejectReason, Rejection, RejectionListener, TypeTx};
+
+/// How many rejections a subscriber can fall behind by before it misses some
+pub const SUBSCRIBER_BACKLOG: usize = 1024;
+
+///
+/// A rejection as it is streamed to subscribers, numbered in the order the engine made them
+///
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RejectionEvent
+{
+    /// Counts up from 1 for every rejection the broadcast has seen, so a subscriber can tell it missed some
+    pub id: u64,
+    pub client: u16,
+    pub tx: u32,
+    pub r#type: TypeTx,
+    pub reason: RejectReason,
+    /// The ID of the rule that refused it, for rule_rejected
+    pub rule: Option<String>,
+}
+impl RejectionEvent
+{
+    /// The event as json, E.G. for the text frame of a websocket
+    pub fn to_json(&self) -> String
+    {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+    /// The event as a server-sent event, for a text/event-stream response
+    pub fn to_sse(&self) -> String
+    {
+        format!("id: {}\nevent: rejection\ndata: {}\n\n", self.id, self.to_json())
+    }
+}
+
+struct BroadcastState
+{
+    next_id: u64,
+    subscribers: Vec<mpsc::SyncSender<RejectionEvent>>,
+    /// Events not handed to a subscriber as it was too far behind
+    missed: u64,
+}
+
+///
+/// Hands every rejection of the engine on to whoever subscribed, as it happens, so
+/// integrators can alert on malformed or risky traffic without waiting for the report
+///
+/// Added to the engine as a rejection listener, while a clone of it is kept to take new
+/// subscribers, E.G. one for each SSE or websocket connection of a server. The engine
+/// never waits for a subscriber: one that falls too far behind misses events, which it
+/// can tell from the gap in their IDs
+///
+#[derive(Clone)]
+pub struct RejectionBroadcast
+{
+    state: Arc<Mutex<BroadcastState>>,
+}
+impl Default for RejectionBroadcast
+{
+    fn default() -> Self {
+        RejectionBroadcast { state: Arc::new(Mutex::new(BroadcastState { next_id: 1, subscribers: Vec::new(), missed: 0 })) }
+    }
+}
+impl RejectionBroadcast
+{
+    fn lock(&self) -> std::sync::MutexGuard<'_, BroadcastState>
+    {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+    /// Returns a subscriber that gets every rejection from now on
+    pub fn subscribe(&self) -> RejectionSubscriber
+    {
+        let (sender, events) = mpsc::sync_channel(SUBSCRIBER_BACKLOG);
+        self.lock().subscribers.push(sender);
+        RejectionSubscriber { events }
+    }
+    /// How many subscribers are still connected, as of the last rejection
+    pub fn subscribers(&self) -> usize
+    {
+        self.lock().subscribers.len()
+    }
+    /// How many events were dropped across all subscribers, as they were too far behind
+    pub fn missed(&self) -> u64
+    {
+        self.lock().missed
+    }
+}
+impl RejectionListener for RejectionBroadcast
+{
+    fn rejected(&mut self, rejection: &Rejection) {
+        let mut state = self.lock();
+        let event = RejectionEvent { id: state.next_id, client: rejection.client, tx: rejection.tx, r#type: rejection.r#type,
+            reason: rejection.reason, rule: rejection.rule.clone() };
+        state.next_id += 1;
+        let mut missed = 0;
+        state.subscribers.retain(|s| match s.try_send(event.clone())
+        {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                missed += 1;
+                true
+            },
+            Err(mpsc::TrySendError::Disconnected(_)) => false
+        });
+        state.missed += missed;
+    }
+}
+
+///
+/// The rejections streamed to a single subscriber
+///
+pub struct RejectionSubscriber
+{
+    events: mpsc::Receiver<RejectionEvent>,
+}
+impl RejectionSubscriber
+{
+    /// Waits for the next rejection, None if none came in time or the broadcast is gone
+    ///
+    /// # Arguments
+    ///
+    /// * 'timeout' - How long to wait for one
+    pub fn next(&self, timeout: Duration) -> Option<RejectionEvent>
+    {
+        self.events.recv_timeout(timeout).ok()
+    }
+    /// Writes the rejections as server-sent events, until every clone of the broadcast is
+    /// gone or the writer fails, E.G. as the client disconnected
+    ///
+    /// A comment is written whenever no rejection came for a while, so proxies keep the
+    /// connection open and a disconnected client is noticed
+    ///
+    /// # Arguments
+    ///
+    /// * 'out' - The body of the text/event-stream response
+    /// * 'keep_alive' - How long to go without writing anything
+    pub fn serve<W: io::Write>(&self, mut out: W, keep_alive: Duration) -> io::Result<()>
+    {
+        loop
+        {
+            match self.events.recv_timeout(keep_alive)
+            {
+                Ok(event) => out.write_all(event.to_sse().as_bytes())?,
+                Err(mpsc::RecvTimeoutError::Timeout) => out.write_all(b": keep-alive\n\n")?,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return out.flush()
+            }
+            out.flush()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, Engine, EnginePolicy, Tx};
+
+    #[test]
+    fn rejections_are_streamed()
+    {
+        let broadcast = RejectionBroadcast::default();
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.add_rejection_listener(Box::new(broadcast.clone()));
+        let subscriber = broadcast.subscribe();
+        let gone = broadcast.subscribe();
+        drop(gone);
+
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(10000))));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, None));
+        let event = subscriber.next(Duration::from_secs(1)).unwrap();
+        assert_eq!((event.id, event.client, event.tx, event.reason),(1, 2, 2, RejectReason::MissingAmount));
+        assert_eq!(event.to_sse(),"id: 1\nevent: rejection\ndata: {\"id\":1,\"client\":2,\"tx\":2,\"type\":\"deposit\",\"reason\":\"missing_amount\",\"rule\":null}\n\n");
+        assert_eq!(broadcast.subscribers(),1);
+
+        engine.apply(Tx::new(TypeTx::Withdrawal, 3, 3, None));
+        drop(engine);
+        drop(broadcast);
+        let mut out = Vec::new();
+        subscriber.serve(&mut out, Duration::from_secs(1)).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("id: 2\nevent: rejection\n"));
+    }
+}
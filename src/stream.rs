@@ -0,0 +1,152 @@
+use std::{collections::{BTreeSet, HashMap}, io};
+use crate::{Account, AccountSink, Engine};
+
+///
+/// How many accounts a streamed run emitted, and when
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamSummary
+{
+    /// Accounts emitted while the input was still being read, updates included
+    pub early: usize,
+    /// Accounts emitted by the final reconciliation pass, as their clients never went
+    /// quiet or changed after being emitted
+    pub reconciled: usize,
+}
+
+///
+/// Emits the account of a client to a sink as soon as the client goes quiet, rather
+/// than once the whole input is processed
+///
+/// A client is quiet once so many bytes of input have gone by without a row for it.
+/// A client that comes back after that is emitted again once it goes quiet again, so
+/// the last row for a client is the one to go by. Rows for clients that didn't change
+/// since they were last emitted aren't written again
+///
+pub struct AccountStream
+{
+    /// How many bytes of input without a row make a client quiet
+    quiet: u64,
+    /// The clients not yet quiet, by the offset they were last seen at
+    waiting: BTreeSet<(u64, u16)>,
+    /// The offset each waiting client was last seen at
+    last_seen: HashMap<u16, u64>,
+    /// Every account as it was last emitted
+    emitted: HashMap<u16, Account>,
+    summary: StreamSummary,
+}
+impl AccountStream
+{
+    /// Returns a stream that has emitted nothing yet
+    ///
+    /// # Arguments
+    ///
+    /// * 'quiet' - How many bytes of input without a row for a client make it quiet
+    pub fn new(quiet: u64) -> AccountStream
+    {
+        AccountStream { quiet, waiting: BTreeSet::new(), last_seen: HashMap::new(), emitted: HashMap::new(), summary: StreamSummary::default() }
+    }
+    /// Notes a row for a client once it is applied, and emits the accounts of clients
+    /// that have gone quiet by the end of it
+    ///
+    /// Returns how many accounts were emitted
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine the row was applied to
+    /// * 'client' - The client of the row
+    /// * 'offset' - How far into the input the end of the row is, in bytes
+    /// * 'sink' - Where to emit the accounts
+    ///
+    /// # Errors
+    ///
+    /// Fails if the sink does
+    pub fn observe(&mut self, engine: &Engine, client: u16, offset: u64, sink: &mut dyn AccountSink) -> io::Result<usize>
+    {
+        if let Some(previous) = self.last_seen.insert(client, offset)
+        {
+            self.waiting.remove(&(previous, client));
+        }
+        self.waiting.insert((offset, client));
+        let mut written = 0;
+        while let Some(&(seen, quiet)) = self.waiting.iter().next()
+        {
+            if offset.saturating_sub(seen) < self.quiet {break}
+            self.waiting.remove(&(seen, quiet));
+            self.last_seen.remove(&quiet);
+            if self.emit(engine, quiet, sink)?
+            {
+                written += 1;
+            }
+        }
+        self.summary.early += written;
+        Ok(written)
+    }
+    /// Emits every account that wasn't emitted yet, or changed since it was, once the
+    /// input is done, and finishes the sink
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine once the whole input is applied
+    /// * 'sink' - Where to emit the accounts
+    ///
+    /// # Errors
+    ///
+    /// Fails if the sink does
+    pub fn finish(mut self, engine: &Engine, sink: &mut dyn AccountSink) -> io::Result<StreamSummary>
+    {
+        let mut ids: Vec<u16> = engine.clients.keys().collect();
+        ids.sort();
+        for client in ids
+        {
+            if self.emit(engine, client, sink)?
+            {
+                self.summary.reconciled += 1;
+            }
+        }
+        sink.finish()?;
+        Ok(self.summary)
+    }
+    /// Writes the account of the client unless it is already written as it is,
+    /// returning whether it was
+    fn emit(&mut self, engine: &Engine, client: u16, sink: &mut dyn AccountSink) -> io::Result<bool>
+    {
+        let acc = match engine.clients.get(&client)
+        {
+            Some(c) if self.emitted.get(&client) != Some(&c.acc) => &c.acc,
+            _ => return Ok(false)
+        };
+        sink.write_account(acc)?;
+        self.emitted.insert(client, acc.clone());
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, CsvSink, EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn streaming_emission()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        let mut stream = AccountStream::new(100);
+        let mut out = Vec::new();
+        let mut sink = CsvSink::new(&mut out);
+        let rows = [(1, 1, 30), (2, 2, 60), (1, 3, 90), (3, 4, 200), (2, 5, 230), (3, 6, 240)];
+        let mut emitted = Vec::new();
+        for (client, tx, offset) in rows
+        {
+            engine.apply(Tx::new(TypeTx::Deposit, client, tx, Some(Amount::from_minor(10000))));
+            emitted.push(stream.observe(&engine, client, offset, &mut sink).unwrap());
+        }
+        //clients 2 and 1 went quiet at 200, and client 2 came back after
+        assert_eq!(emitted,vec![0, 0, 0, 2, 0, 0]);
+        assert_eq!(stream.finish(&engine, &mut sink).unwrap(),StreamSummary { early: 2, reconciled: 2 });
+        drop(sink);
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<String> = text.lines().map(|l| l.split(',').take(2).collect::<Vec<_>>().join(",")).collect();
+        assert_eq!(lines,vec!["client,available", "2,1.0", "1,2.0", "2,2.0", "3,2.0"]);
+    }
+}
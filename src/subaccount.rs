@@ -0,0 +1,183 @@
+use std::io;
+use serde::{Deserialize, Serialize};
+use crate::{checked_add, Account, Amount, Client, ClientStore, TxError, Tx, TypeTx};
+
+/// The name of the sub-account transactions without an account column go to
+pub const MAIN_ACCOUNT: &str = "main";
+
+///
+/// The balances of a single sub-account of a client, E.G. savings or bonus
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubAccount
+{
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub pending: Amount,
+}
+impl SubAccount
+{
+    /// Moves each balance up by that of the change
+    ///
+    /// # Arguments
+    ///
+    /// * 'change' - How much each balance went up by, negative if it went down
+    pub(crate) fn add(&mut self, change: &SubAccount)
+    {
+        let add = |now: Amount, by: Amount| now.checked_add(by).unwrap_or(now);
+        *self = SubAccount { available: add(self.available, change.available), held: add(self.held, change.held),
+            total: add(self.total, change.total), pending: add(self.pending, change.pending) };
+    }
+    /// Moves each balance down by that of the change, as add does up
+    ///
+    /// # Arguments
+    ///
+    /// * 'change' - How much each balance goes down by
+    pub(crate) fn take(&mut self, change: &SubAccount)
+    {
+        let take = |now: Amount, by: Amount| now.checked_sub(by).unwrap_or(now);
+        *self = SubAccount { available: take(self.available, change.available), held: take(self.held, change.held),
+            total: take(self.total, change.total), pending: take(self.pending, change.pending) };
+    }
+}
+impl From<&Account> for SubAccount
+{
+    fn from(acc: &Account) -> Self {
+        SubAccount { available: acc.available, held: acc.held, total: acc.total, pending: acc.pending }
+    }
+}
+
+impl Client
+{
+    /// The named sub-account a transaction moves the funds of, None for the main one
+    ///
+    /// A dispute, resolve, chargeback or representment stays with the sub-account of the
+    /// transaction it is for, whatever its own account column says
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx' - The transaction
+    pub fn sub_account_of(&self, tx: &Tx) -> Option<String>
+    {
+        let account = match tx.r#type
+        {
+            TypeTx::Dispute | TypeTx::Resolve | TypeTx::Chargeback | TypeTx::Representment => self.history.get(&tx.tx).and_then(|h| h.account.clone()),
+            _ => tx.account.clone()
+        };
+        account.filter(|a| a != MAIN_ACCOUNT)
+    }
+    /// The balances of the main sub-account, what the account has beyond the named ones
+    pub fn main_account(&self) -> SubAccount
+    {
+        let mut main = SubAccount::from(&self.acc);
+        for sub in self.sub_accounts.values()
+        {
+            main.take(sub);
+        }
+        main
+    }
+    /// The balances of every sub-account, the main one first and then the named ones by name
+    pub fn balances_by_sub_account(&self) -> Vec<(&str, SubAccount)>
+    {
+        let named = self.sub_accounts.iter().map(|(name, sub)| (name.as_str(), *sub));
+        std::iter::once((MAIN_ACCOUNT, self.main_account())).chain(named).collect()
+    }
+    /// Whether the sub-account has enough available for a withdrawal of the amount; the
+    /// credit limit only covers the main one
+    ///
+    /// # Arguments
+    ///
+    /// * 'account' - The sub-account, the main one if None
+    /// * 'amount' - How much is withdrawn
+    pub(crate) fn can_withdraw(&self, account: Option<&str>, amount: Amount) -> Result<bool, TxError>
+    {
+        match account.filter(|a| *a != MAIN_ACCOUNT)
+        {
+            Some(name) => Ok(self.sub_accounts.get(name).is_some_and(|sub| sub.available > amount)),
+            None => Ok(checked_add(self.main_account().available, self.credit_limit)? > amount)
+        }
+    }
+    /// Puts how the account changed since before down to a named sub-account, opening it
+    /// if need be; the main one takes whatever the named ones don't
+    ///
+    /// # Arguments
+    ///
+    /// * 'account' - The sub-account, the main one if None
+    /// * 'before' - The account before the change
+    pub(crate) fn post_sub_account(&mut self, account: Option<&str>, before: &Account)
+    {
+        let Some(name) = account.filter(|a| *a != MAIN_ACCOUNT) else {return};
+        let mut change = SubAccount::from(&self.acc);
+        change.take(&SubAccount::from(before));
+        if change == SubAccount::default() {return}
+        self.sub_accounts.entry(name.to_string()).or_default().add(&change);
+    }
+}
+
+///
+/// A single row of the sub-account report
+///
+#[derive(Serialize)]
+struct SubAccountRow<'a>
+{
+    client: u16,
+    account: &'a str,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    pending: Amount,
+    locked: bool,
+}
+
+/// Writes the balances of every sub-account as csv, ordered by client and then with the
+/// main sub-account first
+///
+/// # Arguments
+///
+/// * 'clients' - The clients to write
+/// * 'out' - Where to write them to
+pub fn write_sub_accounts<W: io::Write>(clients: &ClientStore, out: W) -> csv::Result<()>
+{
+    let mut wrtr = csv::Writer::from_writer(out);
+    for (client, c) in clients.iter_ordered(None)
+    {
+        for (account, sub) in c.balances_by_sub_account()
+        {
+            wrtr.serialize(SubAccountRow { client, account, available: sub.available, held: sub.held, total: sub.total, pending: sub.pending, locked: c.acc.locked })?;
+        }
+    }
+    wrtr.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EnginePolicy};
+
+    #[test]
+    fn sub_accounts_keep_their_own_balances()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        let to = |mut tx: Tx, account: &str| {tx.account = Some(account.to_string()); tx};
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.apply(to(Tx::new(TypeTx::Deposit, 1, 2, Some(Amount::from_minor(20000))), "savings"));
+        engine.apply(to(Tx::new(TypeTx::Withdrawal, 1, 3, Some(Amount::from_minor(30000))), "savings"));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 1, 4, Some(Amount::from_minor(60000))));
+        engine.apply(to(Tx::new(TypeTx::Withdrawal, 1, 5, Some(Amount::from_minor(5000))), "savings"));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 2, None));
+        let c = &engine.clients[&1];
+        assert_eq!(c.acc.available,Amount::from_minor(45000));
+        assert_eq!(c.main_account().available,Amount::from_minor(50000));
+        assert_eq!(c.sub_accounts["savings"],SubAccount { available: Amount::from_minor(-5000), held: Amount::from_minor(20000), total: Amount::from_minor(15000), pending: Amount::ZERO });
+
+        assert!(engine.undo(1, 2));
+        assert_eq!(engine.clients[&1].sub_accounts["savings"].available,Amount::from_minor(15000));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 2, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 1, 2, None));
+        let mut out = Vec::new();
+        write_sub_accounts(&engine.clients, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),"client,account,available,held,total,pending,locked\n1,main,5.0,0.0,5.0,0.0,true\n1,savings,-0.5,0.0,-0.5,0.0,true\n");
+    }
+}
@@ -0,0 +1,224 @@
+use std::{fmt, io, str::FromStr};
+use crate::Report;
+
+///
+/// The markup of the human-readable summary of a run, for pasting into an email or ticket
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat
+{
+    Html,
+    Markdown,
+}
+impl FromStr for ReportFormat
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s
+        {
+            "html" => Ok(ReportFormat::Html),
+            "md" => Ok(ReportFormat::Markdown),
+            _ => Err(format!("unknown report format '{}'", s))
+        }
+    }
+}
+impl fmt::Display for ReportFormat
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            ReportFormat::Html => write!(f, "html"),
+            ReportFormat::Markdown => write!(f, "md"),
+        }
+    }
+}
+
+/// The figures of the totals table, in order, those that don't apply to the run left out
+///
+/// # Arguments
+///
+/// * 'report' - The report the figures are taken from
+fn totals(report: &Report) -> Vec<(&'static str, String)>
+{
+    let mut totals = vec![
+        ("clients", report.clients.to_string()),
+        ("transactions", report.transactions.to_string()),
+        ("rejected", report.rejected.to_string()),
+    ];
+    if report.skipped > 0
+    {
+        totals.push(("skipped", report.skipped.to_string()));
+    }
+    totals.push(("held in dispute", report.held.to_string()));
+    totals.push(("locked accounts", report.locked.to_string()));
+    if !report.frozen.is_empty()
+    {
+        totals.push(("frozen accounts", report.frozen.len().to_string()));
+    }
+    if let Some(reserve) = report.reserve
+    {
+        totals.push(("chargeback reserve", reserve.to_string()));
+    }
+    totals.push(("warnings", report.warnings.len().to_string()));
+    totals.push(("ledger head", report.ledger_head.clone()));
+    totals
+}
+
+/// Writes the totals, locked accounts, largest disputes and warnings of the report as a
+/// short summary in the format
+///
+/// # Arguments
+///
+/// * 'report' - The report to summarise
+/// * 'format' - The markup to write it in
+/// * 'out' - Where to write the summary to
+pub fn write_summary<W: io::Write>(report: &Report, format: ReportFormat, out: W) -> io::Result<()>
+{
+    match format
+    {
+        ReportFormat::Html => write_html(report, out),
+        ReportFormat::Markdown => write_markdown(report, out),
+    }
+}
+
+/// Writes the summary as markdown, with the totals and disputes as tables
+///
+/// # Arguments
+///
+/// * 'report' - The report to summarise
+/// * 'out' - Where to write the summary to
+fn write_markdown<W: io::Write>(report: &Report, mut out: W) -> io::Result<()>
+{
+    writeln!(out, "## Transaction run summary\n")?;
+    writeln!(out, "| figure | value |\n| --- | --- |")?;
+    for (name, value) in totals(report)
+    {
+        writeln!(out, "| {} | {} |", name, value)?;
+    }
+    writeln!(out, "\n### Locked accounts\n")?;
+    match report.locked_clients.is_empty()
+    {
+        true => writeln!(out, "none")?,
+        false => writeln!(out, "{}", report.locked_clients.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "))?
+    }
+    writeln!(out, "\n### Largest disputes\n")?;
+    match report.largest_disputes.is_empty()
+    {
+        true => writeln!(out, "none")?,
+        false => {
+            writeln!(out, "| client | tx | amount |\n| --- | --- | --- |")?;
+            for d in &report.largest_disputes
+            {
+                writeln!(out, "| {} | {} | {} |", d.client, d.tx, d.amount)?;
+            }
+        }
+    }
+    writeln!(out, "\n### Warnings\n")?;
+    if report.warnings.is_empty()
+    {
+        writeln!(out, "none")?;
+    }
+    for w in &report.warnings
+    {
+        writeln!(out, "* {}", w)?;
+    }
+    out.flush()
+}
+
+/// Escapes the characters html gives a meaning to
+///
+/// # Arguments
+///
+/// * 'text' - The text to escape
+fn escape_html(text: &str) -> String
+{
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Writes the summary as a html fragment, with the totals and disputes as tables
+///
+/// # Arguments
+///
+/// * 'report' - The report to summarise
+/// * 'out' - Where to write the summary to
+fn write_html<W: io::Write>(report: &Report, mut out: W) -> io::Result<()>
+{
+    writeln!(out, "<h2>Transaction run summary</h2>")?;
+    writeln!(out, "<table>\n<tr><th>figure</th><th>value</th></tr>")?;
+    for (name, value) in totals(report)
+    {
+        writeln!(out, "<tr><td>{}</td><td>{}</td></tr>", name, escape_html(&value))?;
+    }
+    writeln!(out, "</table>\n<h3>Locked accounts</h3>")?;
+    match report.locked_clients.is_empty()
+    {
+        true => writeln!(out, "<p>none</p>")?,
+        false => writeln!(out, "<p>{}</p>", report.locked_clients.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", "))?
+    }
+    writeln!(out, "<h3>Largest disputes</h3>")?;
+    match report.largest_disputes.is_empty()
+    {
+        true => writeln!(out, "<p>none</p>")?,
+        false => {
+            writeln!(out, "<table>\n<tr><th>client</th><th>tx</th><th>amount</th></tr>")?;
+            for d in &report.largest_disputes
+            {
+                writeln!(out, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>", d.client, d.tx, d.amount)?;
+            }
+            writeln!(out, "</table>")?;
+        }
+    }
+    writeln!(out, "<h3>Warnings</h3>")?;
+    match report.warnings.is_empty()
+    {
+        true => writeln!(out, "<p>none</p>")?,
+        false => {
+            writeln!(out, "<ul>")?;
+            for w in &report.warnings
+            {
+                writeln!(out, "<li>{}</li>", escape_html(&w.to_string()))?;
+            }
+            writeln!(out, "</ul>")?;
+        }
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, Engine, EnginePolicy, Tx, TypeTx};
+
+    #[test]
+    fn summary_written_as_markdown_and_html()
+    {
+        assert_eq!("md".parse::<ReportFormat>(),Ok(ReportFormat::Markdown));
+        assert!("pdf".parse::<ReportFormat>().is_err());
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply(Tx::new(TypeTx::Deposit, 1, 1, Some(Amount::from_minor(50000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 1, 1, None));
+        engine.apply(Tx::new(TypeTx::Deposit, 2, 2, Some(Amount::from_minor(10000))));
+        engine.apply(Tx::new(TypeTx::Withdrawal, 2, 3, Some(Amount::from_minor(5000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 2, 2, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 2, 2, None));
+        let report = Report::new(&engine, 10);
+        assert_eq!(report.locked_clients,vec![2]);
+
+        let mut out = Vec::new();
+        write_summary(&report, ReportFormat::Markdown, &mut out).unwrap();
+        let md = String::from_utf8(out).unwrap();
+        assert!(md.starts_with("## Transaction run summary\n\n| figure | value |\n| --- | --- |\n| clients | 2 |\n"));
+        assert!(md.contains("| held in dispute | 5.0 |\n| locked accounts | 1 |\n| warnings | 2 |\n"));
+        assert!(md.contains("### Locked accounts\n\n2\n"));
+        assert!(md.contains("| client | tx | amount |\n| --- | --- | --- |\n| 1 | 1 | 5.0 |\n| 2 | 2 | 1.0 |\n"));
+        assert!(md.ends_with("### Warnings\n\n* client 2: available is negative (-0.5)\n* client 2: held (0.0) is above total (-0.5)\n"));
+
+        let mut out = Vec::new();
+        write_summary(&report, ReportFormat::Html, &mut out).unwrap();
+        let html = String::from_utf8(out).unwrap();
+        assert!(html.contains("<tr><td>locked accounts</td><td>1</td></tr>"));
+        assert!(html.contains("<tr><td>2</td><td>2</td><td>1.0</td></tr>"));
+        assert!(html.ends_with("<li>client 2: held (0.0) is above total (-0.5)</li>\n</ul>\n"));
+        assert_eq!(escape_html("<a & b>"),"&lt;a &amp; b&gt;");
+    }
+}
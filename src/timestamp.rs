@@ -0,0 +1,192 @@
+use std::fmt;
+use serde::Serialize;
+use crate::{DateFormat, Engine, SchemaMode, TxRecord};
+
+///
+/// How the timestamps of an input are written, they are read into seconds since the unix
+/// epoch in UTC
+///
+/// Time zones are fixed offsets from UTC, there is no time zone database, so a feed in
+/// local time that switches to daylight saving needs the offset in its timestamps
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimestampConfig
+{
+    /// The formats tried, in order, the first that reads the timestamp is used
+    pub formats: Vec<DateFormat>,
+    /// The offset from UTC of timestamps that don't carry one, in seconds east of UTC;
+    /// unix timestamps are always UTC
+    pub utc_offset: i64,
+}
+impl Default for TimestampConfig
+{
+    fn default() -> Self {
+        TimestampConfig { formats: vec![DateFormat::Unix], utc_offset: 0 }
+    }
+}
+impl TimestampConfig
+{
+    /// Reads the timestamp in the first format that fits it, None if none does
+    ///
+    /// # Arguments
+    ///
+    /// * 'text' - The timestamp as given in the input
+    pub fn normalize(&self, text: &str) -> Option<i64>
+    {
+        self.formats.iter().find_map(|format| format.parse(text.trim(), self.utc_offset))
+    }
+}
+
+/// Parses an offset from UTC given on the command line or carried by a timestamp,
+/// "UTC", "Z" or E.G. "+02:00", "-0530" and "+01", into seconds east of UTC
+///
+/// # Arguments
+///
+/// * 'text' - The offset as given
+pub fn parse_utc_offset(text: &str) -> Result<i64, String>
+{
+    if text == "UTC" || text == "Z" {return Ok(0)}
+    let invalid = || format!("invalid utc offset '{}', expected UTC or E.G. +02:00", text);
+    let (sign, rest) = match (text.strip_prefix('+'), text.strip_prefix('-'))
+    {
+        (Some(rest), _) => (1, rest),
+        (_, Some(rest)) => (-1, rest),
+        _ => return Err(invalid())
+    };
+    let (hours, minutes) = match rest.len()
+    {
+        2 => (rest, "00"),
+        4 => rest.split_at(2),
+        5 => rest.split_once(':').ok_or_else(invalid)?,
+        _ => return Err(invalid())
+    };
+    match (number(hours, 2), number(minutes, 2))
+    {
+        (Some(hours), Some(minutes)) if hours <= 18 && minutes < 60 => Ok(sign * (hours * 3600 + minutes * 60)),
+        _ => Err(invalid())
+    }
+}
+
+/// Reads a number of exactly the given digits
+///
+/// # Arguments
+///
+/// * 'text' - The digits
+/// * 'digits' - How many there have to be
+pub(crate) fn number(text: &str, digits: usize) -> Option<i64>
+{
+    match text.len() == digits && text.bytes().all(|b| b.is_ascii_digit())
+    {
+        true => text.parse().ok(),
+        false => None
+    }
+}
+
+///
+/// A row whose timestamp couldn't be read in any of the formats of the input
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TimestampError
+{
+    /// Which row it was, counting every row applied
+    pub row: u64,
+    pub client: u16,
+    pub tx: u32,
+    /// The timestamp as given in the input
+    pub timestamp: String,
+    /// Whether the row was applied without its timestamp, in lenient mode, rather
+    /// than rejected
+    pub dropped: bool,
+}
+impl fmt::Display for TimestampError
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+    {
+        write!(f, "row {}: client {}, tx {}: couldn't read the timestamp '{}', {}", self.row, self.client, self.tx, self.timestamp,
+            if self.dropped {"applied without it"} else {"rejected"})
+    }
+}
+
+impl Engine
+{
+    /// Keeps a row whose timestamp wasn't read in timestamp_errors, dropping the
+    /// timestamp in lenient mode; in strict mode it is left to be rejected
+    ///
+    /// # Arguments
+    ///
+    /// * 'record' - The row about to be applied, its timestamp normalized by the dialect
+    pub(crate) fn check_timestamp(&mut self, record: &mut TxRecord)
+    {
+        let Some(timestamp) = record.timestamp.as_deref() else {return};
+        if timestamp.parse::<i64>().is_ok() {return}
+        let dropped = self.policy.schema == SchemaMode::Lenient;
+        self.timestamp_errors.push(TimestampError { row: self.rows, client: record.client, tx: record.tx, timestamp: timestamp.to_string(), dropped });
+        if dropped
+        {
+            record.timestamp = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DateFormat, Dialect, EnginePolicy, RejectReason};
+
+    fn config() -> TimestampConfig
+    {
+        TimestampConfig { formats: vec![DateFormat::Unix, DateFormat::Iso, DateFormat::DayMonthYear], utc_offset: 3600 }
+    }
+    fn records() -> Vec<TxRecord>
+    {
+        let dialect = Dialect { timestamps: config(), ..Dialect::default() };
+        let input = "type,client,tx,amount,timestamp\ndeposit,1,1,5.0,2023-11-14T22:13:20Z\ndeposit,1,2,5.0,yesterday\n";
+        dialect.read_records(input.as_bytes(), SchemaMode::Strict).unwrap().collect()
+    }
+
+    #[test]
+    fn first_fitting_format_used()
+    {
+        let config = config();
+        assert_eq!(config.normalize("1700000000"),Some(1700000000));
+        assert_eq!(config.normalize("2023-11-14T22:13:20Z"),Some(1700000000));
+        assert_eq!(config.normalize("14.11.2023 23:13:20"),Some(1700000000));
+        assert_eq!(config.normalize("29.02.2023"),None);
+    }
+    #[test]
+    fn offset_applied_unless_carried()
+    {
+        let config = config();
+        assert_eq!(config.normalize("2023-11-14T23:13:20"),Some(1700000000));
+        assert_eq!(config.normalize("2023-11-14 23:13:20.5+01:00"),Some(1700000000));
+        assert_eq!(config.normalize("2000-03-01"),Some(951868800 - 3600));
+    }
+    #[test]
+    fn utc_offsets_parsed()
+    {
+        assert_eq!((parse_utc_offset("-0530"), parse_utc_offset("+02"), parse_utc_offset("Z")),(Ok(-19800), Ok(7200), Ok(0)));
+        assert!(parse_utc_offset("CET").is_err() && parse_utc_offset("+19:00").is_err());
+    }
+    #[test]
+    fn timestamps_normalized_on_read()
+    {
+        let records = records();
+        assert_eq!((records[0].timestamp.as_deref(), records[1].timestamp.as_deref()),(Some("1700000000"), Some("yesterday")));
+    }
+    #[test]
+    fn unreadable_timestamp_rejected_when_strict()
+    {
+        let mut strict = Engine::new(EnginePolicy::default());
+        records().into_iter().for_each(|r| strict.apply_record(r));
+        assert_eq!(strict.rejections[0].reason,RejectReason::InvalidTimestamp);
+        assert_eq!(strict.timestamp_errors[0].to_string(),"row 2: client 1, tx 2: couldn't read the timestamp 'yesterday', rejected");
+    }
+    #[test]
+    fn unreadable_timestamp_dropped_when_lenient()
+    {
+        let mut lenient = Engine::new(EnginePolicy { schema: SchemaMode::Lenient, ..EnginePolicy::default() });
+        records().into_iter().for_each(|r| lenient.apply_record(r));
+        assert!(lenient.rejections.is_empty() && lenient.timestamp_errors[0].dropped);
+        assert_eq!(lenient.clients[&1].history[&2].timestamp,None);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{borrow::Borrow, collections::{BTreeMap, HashMap}, hash::{BuildHasher, Hash}};
+
+///
+/// A map an undo log can put its entries back into
+///
+pub(crate) trait Entries<K, V>
+{
+    /// Puts the entry back as it was, removing it if it didn't exist
+    fn put(&mut self, key: K, value: Option<V>);
+}
+impl<K: Hash + Eq, V, S: BuildHasher> Entries<K, V> for HashMap<K, V, S>
+{
+    fn put(&mut self, key: K, value: Option<V>) {
+        match value
+        {
+            Some(v) => {self.insert(key, v);},
+            None => {self.remove(&key);}
+        }
+    }
+}
+impl<K: Ord, V> Entries<K, V> for BTreeMap<K, V>
+{
+    fn put(&mut self, key: K, value: Option<V>) {
+        match value
+        {
+            Some(v) => {self.insert(key, v);},
+            None => {self.remove(&key);}
+        }
+    }
+}
+
+///
+/// The entries of a map changed since a savepoint, as they were before the first change
+///
+/// Only the keys touched are kept, so a savepoint costs nothing up front however big
+/// the map is
+///
+pub(crate) struct UndoLog<K, V>
+{
+    /// The entries as they were, None if they didn't exist
+    pub(crate) entries: HashMap<K, Option<V>>,
+}
+impl<K, V> Default for UndoLog<K, V>
+{
+    fn default() -> Self {
+        UndoLog { entries: HashMap::new() }
+    }
+}
+impl<K: Hash + Eq, V: Clone> UndoLog<K, V>
+{
+    /// Keeps the entry as it is now, unless it was kept already since the savepoint
+    ///
+    /// # Arguments
+    ///
+    /// * 'key' - The key about to be changed
+    /// * 'value' - What the map holds under it now
+    pub(crate) fn keep<Q>(&mut self, key: &Q, value: Option<&V>)
+        where K: Borrow<Q>, Q: Hash + Eq + ToOwned<Owned = K> + ?Sized
+    {
+        if !self.entries.contains_key(key)
+        {
+            self.entries.insert(key.to_owned(), value.cloned());
+        }
+    }
+    /// Puts every kept entry back into the map
+    ///
+    /// # Arguments
+    ///
+    /// * 'map' - The map the entries were kept from
+    pub(crate) fn restore(self, map: &mut impl Entries<K, V>)
+    {
+        for (key, value) in self.entries
+        {
+            map.put(key, value);
+        }
+    }
+    /// Hands the kept entries to the savepoint made before, which keeps those it already had
+    ///
+    /// # Arguments
+    ///
+    /// * 'outer' - The log of the savepoint made before
+    pub(crate) fn merge_into(self, outer: &mut UndoLog<K, V>)
+    {
+        for (key, value) in self.entries
+        {
+            outer.entries.entry(key).or_insert(value);
+        }
+    }
+}
@@ -0,0 +1,554 @@
+use std::{fmt, fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, Write}, path::{Path, PathBuf}, str::FromStr, time::{Duration, Instant}};
+use serde::{Deserialize, Serialize};
+use crate::checkpoint::write_atomic;
+use crate::snapshot::{open_line, seal_line};
+use crate::{Engine, SnapshotError, SnapshotKey, TxRecord};
+
+///
+/// How often the write-ahead log is synced to disk, trading the records a power
+/// loss can take for throughput
+///
+/// Every record is handed to the OS as it is appended whatever the level, so only
+/// a crash of the machine, not of the process, can lose records not yet synced
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Durability
+{
+    /// Synced after every record, nothing appended is lost
+    #[default]
+    EveryRecord,
+    /// Synced once this many records were appended since the last sync
+    EveryRecords(u64),
+    /// Synced by the first record appended this many milliseconds after the last sync
+    EveryMillis(u64),
+    /// Never synced, the OS writes the records out when it sees fit
+    Os,
+}
+impl FromStr for Durability
+{
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let count = |n: &str| n.parse::<u64>().ok().filter(|n| *n > 0);
+        match s.split_once(':')
+        {
+            None if s == "record" => Ok(Durability::EveryRecord),
+            None if s == "os" => Ok(Durability::Os),
+            Some(("records", n)) => count(n).map(Durability::EveryRecords).ok_or_else(|| format!("invalid record count '{}'", n)),
+            Some(("ms", n)) => count(n).map(Durability::EveryMillis).ok_or_else(|| format!("invalid interval '{}'", n)),
+            _ => Err(format!("unknown durability level '{}'", s))
+        }
+    }
+}
+impl fmt::Display for Durability
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            Durability::EveryRecord => f.write_str("record"),
+            Durability::EveryRecords(n) => write!(f, "records:{}", n),
+            Durability::EveryMillis(n) => write!(f, "ms:{}", n),
+            Durability::Os => f.write_str("os"),
+        }
+    }
+}
+
+///
+/// How far into a write-ahead log an engine got, kept in its snapshots so the
+/// records logged after can be replayed
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WalMark
+{
+    /// The level the log was written at
+    pub durability: Durability,
+    /// How many records of the log the engine has applied
+    pub records: u64,
+}
+
+///
+/// What a write-ahead log has done since it was opened
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct WalStats
+{
+    pub durability: Durability,
+    /// The records in the log, those from earlier runs and compacted segments included
+    pub records: u64,
+    /// The records appended since it was opened
+    pub appended: u64,
+    /// How many times it was synced to disk
+    pub syncs: u64,
+    /// The records appended since the last sync
+    pub unsynced: u64,
+    /// The segment files the log is kept in
+    pub segments: usize,
+    /// How many times a full segment was closed for a new one since it was opened
+    pub rotations: u64,
+    /// The segments removed as a snapshot covered them
+    pub compacted: u64,
+}
+
+/// The extension of segment files, named after the number of the first record in them
+const SEGMENT_EXTENSION: &str = "wal";
+/// The snapshot compaction folds the closed segments into
+const SNAPSHOT_FILE: &str = "snapshot.json";
+
+/// The name of the segment starting at the record
+fn segment_name(first: u64) -> String
+{
+    format!("{:020}.{}", first, SEGMENT_EXTENSION)
+}
+
+/// The segments in the directory, as the number of their first record and their path,
+/// oldest first
+fn segments(dir: &Path) -> io::Result<Vec<(u64, PathBuf)>>
+{
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)?
+    {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == SEGMENT_EXTENSION)
+        {
+            if let Some(first) = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse().ok())
+            {
+                segments.push((first, path));
+            }
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+///
+/// A log every row is appended to before it is applied, so an engine restored from
+/// a snapshot can be brought up to date by replaying what was logged after it
+///
+/// The log is a directory of segment files of json lines, each named after the
+/// number of its first record. Once a segment grows past the segment size a new one
+/// is started, and compaction folds the closed segments into a snapshot kept next to
+/// them, so a long running deployment only keeps what the snapshot doesn't cover.
+/// A line cut short by a crash is dropped when the log is opened again
+///
+/// Opened with a key, each line is encrypted with it as snapshots are and written as
+/// hex, so the rows logged are no more readable on disk than the snapshot
+///
+pub struct Wal
+{
+    dir: PathBuf,
+    /// The segment appended to
+    file: File,
+    /// The number of the first record in the segment appended to
+    first: u64,
+    /// How many bytes the segment appended to takes
+    bytes: u64,
+    /// How big a segment may grow before a new one is started
+    segment_bytes: u64,
+    durability: Durability,
+    /// The key the lines appended are encrypted with, if any
+    key: Option<SnapshotKey>,
+    records: u64,
+    appended: u64,
+    syncs: u64,
+    unsynced: u64,
+    segments: usize,
+    rotations: u64,
+    compacted: u64,
+    last_sync: Instant,
+}
+impl Wal
+{
+    /// How big a segment grows by default before a new one is started
+    pub const DEFAULT_SEGMENT_BYTES: u64 = 64 << 20;
+
+    /// Opens the log in the directory for appending, creating it if it doesn't exist
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - Where the segments and the compacted snapshot are kept
+    /// * 'durability' - How often it is synced to disk
+    /// * 'segment_bytes' - How big a segment may grow before a new one is started
+    /// * 'key' - The key the lines appended are encrypted with, written in plain json if None
+    pub fn open(dir: &Path, durability: Durability, segment_bytes: u64, key: Option<SnapshotKey>) -> io::Result<Wal>
+    {
+        fs::create_dir_all(dir)?;
+        let mut existing = segments(dir)?;
+        let (first, path) = existing.pop().unwrap_or_else(|| (0, dir.join(segment_name(0))));
+        let file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let (records, bytes) = count_records(&file)?;
+        //whatever follows the last complete line was cut short, and would run into the next record
+        if bytes < file.metadata()?.len()
+        {
+            file.set_len(bytes)?;
+        }
+        Ok(Wal { dir: dir.to_path_buf(), file, first, bytes, segment_bytes: segment_bytes.max(1), durability, key, records: first + records, appended: 0,
+            syncs: 0, unsynced: 0, segments: existing.len() + 1, rotations: 0, compacted: 0, last_sync: Instant::now() })
+    }
+    /// Appends the record, starting a new segment first if this one is full, and
+    /// syncing the log if the durability level says it is time
+    pub fn append(&mut self, record: &TxRecord) -> io::Result<()>
+    {
+        let mut line = serde_json::to_vec(record)?;
+        if let Some(key) = &self.key
+        {
+            line = seal_line(key, &line)?;
+        }
+        line.push(b'\n');
+        if self.bytes >= self.segment_bytes
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(&line)?;
+        self.bytes += line.len() as u64;
+        self.records += 1;
+        self.appended += 1;
+        self.unsynced += 1;
+        let due = match self.durability
+        {
+            Durability::EveryRecord => true,
+            Durability::EveryRecords(n) => self.unsynced >= n,
+            Durability::EveryMillis(ms) => self.last_sync.elapsed() >= Duration::from_millis(ms),
+            Durability::Os => false,
+        };
+        if due {self.sync()?}
+        Ok(())
+    }
+    /// Closes the segment appended to, synced whatever the durability level so a
+    /// closed segment is always complete on disk, and starts the next
+    fn rotate(&mut self) -> io::Result<()>
+    {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        self.file = OpenOptions::new().append(true).create(true).open(self.dir.join(segment_name(self.records)))?;
+        //the new file has to survive a crash for the records in it to be found
+        File::open(&self.dir)?.sync_all()?;
+        self.first = self.records;
+        self.bytes = 0;
+        self.segments += 1;
+        self.rotations += 1;
+        Ok(())
+    }
+    /// Syncs whatever was appended since the last sync to disk
+    pub fn sync(&mut self) -> io::Result<()>
+    {
+        if self.unsynced > 0
+        {
+            self.file.sync_data()?;
+            self.syncs += 1;
+            self.unsynced = 0;
+        }
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+    pub fn stats(&self) -> WalStats
+    {
+        WalStats { durability: self.durability, records: self.records, appended: self.appended, syncs: self.syncs, unsynced: self.unsynced,
+            segments: self.segments, rotations: self.rotations, compacted: self.compacted }
+    }
+    /// How far into the log an engine that applied every record appended got
+    pub fn mark(&self) -> WalMark
+    {
+        WalMark { durability: self.durability, records: self.records }
+    }
+    /// Folds the closed segments into a snapshot of the engine, then removes those the
+    /// snapshot covers, and returns how many were removed
+    ///
+    /// The snapshot is written next to the segments before any is removed, so the log
+    /// can always be replayed from it. The segment appended to is kept
+    ///
+    /// # Arguments
+    ///
+    /// * 'engine' - The engine the records were applied to, as far as its mark says
+    /// * 'key' - The key the snapshot is encrypted with, if any
+    pub fn compact(&mut self, engine: &Engine, key: Option<&SnapshotKey>) -> Result<u64, SnapshotError>
+    {
+        let covered = engine.wal.map_or(0, |m| m.records);
+        write_atomic(&Wal::snapshot_path(&self.dir), |out| engine.snapshot_to(out, key))?;
+        let existing = segments(&self.dir)?;
+        let mut removed = 0;
+        //a segment is covered once the one after it starts at or before the mark
+        for pair in existing.windows(2)
+        {
+            let ((_, path), (next, _)) = (&pair[0], &pair[1]);
+            if *next <= covered
+            {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        self.segments -= removed as usize;
+        self.compacted += removed;
+        Ok(removed)
+    }
+    /// Where compaction keeps the snapshot of the log in the directory
+    pub fn snapshot_path(dir: &Path) -> PathBuf
+    {
+        dir.join(SNAPSHOT_FILE)
+    }
+    /// Reads the records of the log in the directory from the record numbered from on,
+    /// in the order they were appended
+    ///
+    /// A line cut short by a crash, or a plain line that isn't a record, is skipped. Fails
+    /// if the records from there on were compacted, the snapshot next to the log has them
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - The directory of the log
+    /// * 'from' - The number of the first record read, 0 for the whole log
+    /// * 'key' - The key the log was encrypted with, if it was
+    ///
+    /// # Errors
+    ///
+    /// Each record read fails if its segment can't be read, or it is encrypted and
+    /// there is no key or the wrong one
+    pub fn read<'a>(dir: &Path, from: u64, key: Option<&'a SnapshotKey>) -> io::Result<impl Iterator<Item = io::Result<TxRecord>> + 'a>
+    {
+        let mut segments = segments(dir)?;
+        if segments.first().is_some_and(|(first, _)| *first > from)
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                format!("record {} was compacted, restore from {} first", from, Wal::snapshot_path(dir).display())));
+        }
+        let start = segments.iter().rposition(|(first, _)| *first <= from).unwrap_or(0);
+        let skip = segments.get(start).map_or(0, |(first, _)| from - first);
+        let records = segments.split_off(start).into_iter().flat_map(move |(_, path)| {
+            let lines: Box<dyn Iterator<Item = io::Result<TxRecord>>> = match File::open(path)
+            {
+                Ok(file) => Box::new(read_segment(file, key)),
+                Err(e) => Box::new(std::iter::once(Err(e)))
+            };
+            lines
+        });
+        Ok(records.skip(skip as usize))
+    }
+}
+impl Drop for Wal
+{
+    /// Syncs what the durability level left unsynced, so a clean shutdown loses nothing
+    fn drop(&mut self)
+    {
+        if self.durability != Durability::Os
+        {
+            let _ = self.sync();
+        }
+    }
+}
+
+/// Reads the records of a segment, leaving out a last line cut short by a crash
+///
+/// # Arguments
+///
+/// * 'file' - The segment
+/// * 'key' - The key its lines were encrypted with, if they were
+fn read_segment(file: File, key: Option<&SnapshotKey>) -> impl Iterator<Item = io::Result<TxRecord>> + '_
+{
+    let mut reader = BufReader::new(file);
+    std::iter::from_fn(move || loop
+    {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line)
+        {
+            Err(e) => return Some(Err(e)),
+            Ok(_) if !line.ends_with(b"\n") => return None,
+            Ok(_) => {line.pop();}
+        }
+        //plain lines are json objects, encrypted ones hex
+        let plain = match (line.first(), key)
+        {
+            (Some(b'{'), _) => line,
+            (Some(_), Some(key)) => match open_line(key, &line)
+            {
+                Ok(plain) => plain,
+                Err(e) => return Some(Err(e.into()))
+            },
+            (Some(_), None) => return Some(Err(io::Error::new(io::ErrorKind::InvalidData, "the write-ahead log is encrypted, and no key was given"))),
+            (None, _) => continue
+        };
+        if let Ok(record) = serde_json::from_slice(&plain)
+        {
+            return Some(Ok(record));
+        }
+    })
+}
+
+/// Counts the complete lines of a segment, and how many bytes they take
+fn count_records(file: &File) -> io::Result<(u64, u64)>
+{
+    let mut reader = BufReader::new(file);
+    let (mut records, mut complete) = (0, 0);
+    let mut line = Vec::new();
+    loop
+    {
+        line.clear();
+        match reader.read_until(b'\n', &mut line)?
+        {
+            0 => return Ok((records, complete)),
+            n if line.ends_with(b"\n") => {
+                records += 1;
+                complete += n as u64;
+            },
+            _ => return Ok((records, complete)),
+        }
+    }
+}
+
+impl Engine
+{
+    /// Appends the record to the write-ahead log, then applies it
+    ///
+    /// Nothing is applied if the record couldn't be logged
+    pub fn apply_logged(&mut self, wal: &mut Wal, record: TxRecord) -> io::Result<()>
+    {
+        wal.append(&record)?;
+        self.apply_record(record);
+        self.wal = Some(wal.mark());
+        Ok(())
+    }
+    /// Applies the records of the log the engine hasn't applied yet, those after the
+    /// mark in the snapshot it was restored from, and returns how many there were
+    ///
+    /// # Arguments
+    ///
+    /// * 'dir' - The directory of the log, as written by Wal
+    /// * 'key' - The key the log was encrypted with, if it was
+    pub fn replay_wal(&mut self, dir: &Path, key: Option<&SnapshotKey>) -> io::Result<u64>
+    {
+        let applied = self.wal.map_or(0, |m| m.records);
+        let mut replayed = 0;
+        for record in Wal::read(dir, applied, key)?
+        {
+            self.apply_record(record?);
+            replayed += 1;
+        }
+        if replayed > 0
+        {
+            let durability = self.wal.map_or_else(Durability::default, |m| m.durability);
+            self.wal = Some(WalMark { durability, records: applied + replayed });
+        }
+        Ok(replayed)
+    }
+    /// How far into its write-ahead log the engine got, None if it never applied a
+    /// logged record
+    pub fn wal_mark(&self) -> Option<WalMark>
+    {
+        self.wal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EnginePolicy, TypeTx};
+
+    fn test_dir(name: &str) -> PathBuf
+    {
+        let dir = std::env::temp_dir().join(format!("{}_{}_wal", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn deposit(tx: u32) -> TxRecord
+    {
+        TxRecord::new(TypeTx::Deposit, 1, tx, Some("1.0".to_string()))
+    }
+
+    #[test]
+    fn durability_levels()
+    {
+        assert_eq!("records:100".parse(),Ok(Durability::EveryRecords(100)));
+        assert_eq!("ms:50".parse(),Ok(Durability::EveryMillis(50)));
+        assert!("records:0".parse::<Durability>().is_err() && "always".parse::<Durability>().is_err());
+        for level in [Durability::EveryRecord, Durability::EveryRecords(3), Durability::EveryMillis(10), Durability::Os]
+        {
+            assert_eq!(level.to_string().parse(),Ok(level));
+        }
+
+        let dir = test_dir("durability");
+        let mut wal = Wal::open(&dir, Durability::EveryRecords(2), Wal::DEFAULT_SEGMENT_BYTES, None).unwrap();
+        (1..=5).for_each(|tx| wal.append(&deposit(tx)).unwrap());
+        assert_eq!((wal.stats().syncs, wal.stats().unsynced),(2, 1));
+        drop(wal);
+
+        let wal = Wal::open(&dir, Durability::Os, Wal::DEFAULT_SEGMENT_BYTES, None).unwrap();
+        assert_eq!((wal.stats().records, wal.stats().appended),(5, 0));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn replay_after_snapshot()
+    {
+        let dir = test_dir("replay");
+        let mut engine = Engine::new(EnginePolicy::default());
+        let mut wal = Wal::open(&dir, Durability::EveryRecord, Wal::DEFAULT_SEGMENT_BYTES, None).unwrap();
+        engine.apply_logged(&mut wal, deposit(1)).unwrap();
+        let mut snapshot = Vec::new();
+        engine.snapshot_to(&mut snapshot, None).unwrap();
+        engine.apply_logged(&mut wal, deposit(2)).unwrap();
+        drop(wal);
+        //a crash cutting the last line short
+        let segment = dir.join(segment_name(0));
+        OpenOptions::new().append(true).open(&segment).unwrap().write_all(b"{\"type\":\"dep").unwrap();
+
+        let mut restored = Engine::restore_from(snapshot.as_slice(), EnginePolicy::default(), None).unwrap();
+        assert_eq!(restored.wal_mark(),Some(WalMark { durability: Durability::EveryRecord, records: 1 }));
+        assert_eq!(restored.replay_wal(&dir, None).unwrap(),1);
+        assert_eq!(restored.clients[&1].acc.total,Amount::from_minor(20000));
+        assert_eq!(restored.replay_wal(&dir, None).unwrap(),0);
+
+        let wal = Wal::open(&dir, Durability::EveryRecord, Wal::DEFAULT_SEGMENT_BYTES, None).unwrap();
+        assert_eq!(wal.stats().records,2);
+        assert_eq!(fs::read(&segment).unwrap().last(),Some(&b'\n'));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn rotation_and_compaction()
+    {
+        let dir = test_dir("compaction");
+        let mut engine = Engine::new(EnginePolicy::default());
+        //a record takes over 150 bytes, so every segment takes two
+        let mut wal = Wal::open(&dir, Durability::Os, 300, None).unwrap();
+        (1..=5).for_each(|tx| engine.apply_logged(&mut wal, deposit(tx)).unwrap());
+        assert_eq!((wal.stats().segments, wal.stats().rotations),(3, 2));
+        assert_eq!(segments(&dir).unwrap().iter().map(|(first, _)| *first).collect::<Vec<_>>(),vec![0, 2, 4]);
+
+        assert_eq!(wal.compact(&engine, None).unwrap(),2);
+        assert_eq!((wal.stats().segments, wal.stats().compacted),(1, 2));
+        engine.apply_logged(&mut wal, deposit(6)).unwrap();
+        drop(wal);
+
+        //an engine without the snapshot can't replay what was compacted
+        assert_eq!(Engine::new(EnginePolicy::default()).replay_wal(&dir, None).unwrap_err().kind(),io::ErrorKind::InvalidData);
+        let mut restored = Engine::restore_from(fs::File::open(Wal::snapshot_path(&dir)).unwrap(), EnginePolicy::default(), None).unwrap();
+        assert_eq!(restored.replay_wal(&dir, None).unwrap(),1);
+        assert_eq!(restored.clients[&1].acc.total,Amount::from_minor(60000));
+        assert_eq!(Wal::open(&dir, Durability::Os, 300, None).unwrap().stats().records,6);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn encrypted_log()
+    {
+        let dir = test_dir("encrypted");
+        let key = SnapshotKey::new([5; 32]);
+        let mut wal = Wal::open(&dir, Durability::EveryRecord, Wal::DEFAULT_SEGMENT_BYTES, Some(key.clone())).unwrap();
+        #[cfg(not(feature = "encryption"))]
+        assert!(wal.append(&deposit(1)).is_err());
+        #[cfg(feature = "encryption")]
+        {
+            let mut engine = Engine::new(EnginePolicy::default());
+            (1..=2).for_each(|tx| engine.apply_logged(&mut wal, deposit(tx)).unwrap());
+            drop(wal);
+            let segment = fs::read(dir.join(segment_name(0))).unwrap();
+            assert!(!segment.windows(7).any(|w| w == b"deposit"));
+
+            let mut restored = Engine::new(EnginePolicy::default());
+            assert_eq!(restored.replay_wal(&dir, None).unwrap_err().kind(),io::ErrorKind::InvalidData);
+            assert!(Engine::new(EnginePolicy::default()).replay_wal(&dir, Some(&SnapshotKey::new([6; 32]))).is_err());
+            let mut restored = Engine::new(EnginePolicy::default());
+            assert_eq!(restored.replay_wal(&dir, Some(&key)).unwrap(),2);
+            assert_eq!(restored.clients[&1].acc.total,Amount::from_minor(20000));
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,85 @@
+use wasm_bindgen::prelude::*;
+use crate::{Engine, EnginePolicy, JsonSink, TxRecord, write_output};
+
+/// Reads a transaction from json, see TxRecord::from_json
+///
+/// # Arguments
+///
+/// * 'json' - The transaction as a json object
+fn record_from_json(json: &str) -> Result<TxRecord, String>
+{
+    TxRecord::from_json(json.as_bytes()).map_err(|e| e.to_string())
+}
+/// Writes the accounts as a json array
+fn report_json(engine: &Engine) -> Result<String, String>
+{
+    let mut out = Vec::new();
+    write_output(&engine.clients, &mut JsonSink::new(&mut out)).map_err(|e| e.to_string())?;
+    String::from_utf8(out).map_err(|e| e.to_string())
+}
+
+///
+/// The engine as seen from javascript
+///
+#[wasm_bindgen]
+pub struct WasmEngine
+{
+    engine: Engine,
+}
+#[wasm_bindgen]
+impl WasmEngine
+{
+    /// Returns a new engine with no clients, following the default policy
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmEngine
+    {
+        WasmEngine { engine: Engine::new(EnginePolicy::default()) }
+    }
+    /// Applies a transaction given as json
+    ///
+    /// A transaction the engine refuses ends up in the rejections, only json
+    /// that isn't a transaction at all is an error
+    ///
+    /// # Arguments
+    ///
+    /// * 'tx_json' - The transaction, with the same fields as a csv row
+    pub fn apply(&mut self, tx_json: &str) -> Result<(), JsError>
+    {
+        let record = record_from_json(tx_json).map_err(|e| JsError::new(&e))?;
+        self.engine.apply_record(record);
+        Ok(())
+    }
+    /// Returns every account as a json array
+    pub fn report(&self) -> Result<String, JsError>
+    {
+        report_json(&self.engine).map_err(|e| JsError::new(&e))
+    }
+    /// Returns every refused transaction as a json array
+    pub fn rejections(&self) -> Result<String, JsError>
+    {
+        serde_json::to_string(&self.engine.rejections).map_err(|e| JsError::new(&e.to_string()))
+    }
+}
+impl Default for WasmEngine
+{
+    fn default() -> Self {
+        WasmEngine::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_transactions()
+    {
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.apply_record(record_from_json(r#"{"type":"deposit","client":1,"tx":1,"amount":1.5}"#).unwrap());
+        engine.apply_record(record_from_json(r#"{"type":"dispute","client":1,"tx":1}"#).unwrap());
+        assert_eq!(report_json(&engine).unwrap(),
+            "[{\"client\":1,\"available\":\"0.0\",\"held\":\"1.5\",\"total\":\"1.5\",\"locked\":false,\"pending\":\"0.0\"}]\n");
+        assert!(record_from_json(r#"{"type":"refund","client":1,"tx":1}"#).is_err());
+        assert!(record_from_json("not json").is_err());
+    }
+}
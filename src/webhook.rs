@@ -0,0 +1,304 @@
+use std::{fmt, io, sync::mpsc::{self, Receiver, SyncSender, TrySendError}, thread::{self, JoinHandle}, time::Duration};
+use reqwest::{blocking::Client, header::CONTENT_TYPE, Url};
+use crate::{Notification, Notifier};
+
+///
+/// Why the webhooks couldn't be set up
+///
+#[derive(Debug)]
+pub enum WebhookError
+{
+    /// The url isn't a valid http(s) url
+    Url(String),
+    /// The http client couldn't be built
+    Http(reqwest::Error),
+}
+impl fmt::Display for WebhookError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self
+        {
+            WebhookError::Url(url) => write!(f, "invalid webhook url '{}'", url),
+            WebhookError::Http(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for WebhookError {}
+impl From<reqwest::Error> for WebhookError
+{
+    fn from(e: reqwest::Error) -> Self {
+        WebhookError::Http(e)
+    }
+}
+impl From<WebhookError> for io::Error
+{
+    fn from(e: WebhookError) -> Self {
+        io::Error::other(e)
+    }
+}
+
+///
+/// What is POSTed for a notification
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WebhookFormat
+{
+    /// Every notification, as json
+    #[default]
+    Json,
+    /// Only the end-of-run summary, as the text of a Slack incoming webhook message
+    Slack,
+}
+
+///
+/// Where and how hard to try delivering notifications
+///
+#[derive(Debug, Clone)]
+pub struct WebhookConfig
+{
+    /// Every notification is POSTed to each of these
+    pub urls: Vec<String>,
+    /// How many notifications may wait for delivery before new ones are dropped
+    pub queue_size: usize,
+    /// How many times a POST is tried before giving up on that url
+    pub max_attempts: u32,
+    /// How long to wait after the first failed attempt, doubled after each one
+    pub backoff: Duration,
+    /// How long a single POST may take
+    pub timeout: Duration,
+    pub format: WebhookFormat,
+}
+impl WebhookConfig
+{
+    /// Returns the default config for the urls
+    ///
+    /// # Arguments
+    ///
+    /// * 'urls' - Where to POST the notifications
+    pub fn new(urls: Vec<String>) -> WebhookConfig
+    {
+        WebhookConfig { urls, queue_size: 1024, max_attempts: 5, backoff: Duration::from_millis(200), timeout: Duration::from_secs(10), format: WebhookFormat::Json }
+    }
+}
+
+///
+/// How the deliveries went, once the queue is drained
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WebhookStats
+{
+    /// POSTs a webhook accepted
+    pub delivered: usize,
+    /// POSTs that still failed after every attempt
+    pub failed: usize,
+    /// Notifications dropped because the queue was full
+    pub dropped: usize,
+}
+
+///
+/// POSTs each notification as json to every configured url
+///
+/// Notifications go through a bounded queue to a background thread, so a slow
+/// webhook never holds up the engine; when the queue is full they are dropped
+///
+pub struct WebhookNotifier
+{
+    sender: Option<SyncSender<Notification>>,
+    worker: Option<JoinHandle<WebhookStats>>,
+    dropped: usize,
+}
+impl WebhookNotifier
+{
+    /// Checks the urls and starts the delivery thread
+    ///
+    /// # Arguments
+    ///
+    /// * 'config' - Where and how hard to try delivering
+    pub fn new(config: WebhookConfig) -> Result<WebhookNotifier, WebhookError>
+    {
+        let urls = config.urls.iter()
+            .map(|url| Url::parse(url).ok().filter(|u| matches!(u.scheme(), "http" | "https")).ok_or_else(|| WebhookError::Url(url.clone())))
+            .collect::<Result<Vec<Url>, WebhookError>>()?;
+        let client = Client::builder().timeout(config.timeout).build()?;
+        let (sender, receiver) = mpsc::sync_channel(config.queue_size);
+        let worker = thread::spawn(move || deliver_all(client, urls, &config, receiver));
+        Ok(WebhookNotifier { sender: Some(sender), worker: Some(worker), dropped: 0 })
+    }
+    /// Waits for every queued notification to be delivered or given up on
+    pub fn finish(mut self) -> WebhookStats
+    {
+        self.stop()
+    }
+    /// Closes the queue and waits for the delivery thread
+    fn stop(&mut self) -> WebhookStats
+    {
+        self.sender.take();
+        let mut stats = match self.worker.take()
+        {
+            Some(worker) => worker.join().unwrap_or_default(),
+            None => WebhookStats::default()
+        };
+        stats.dropped = self.dropped;
+        stats
+    }
+}
+impl Notifier for WebhookNotifier
+{
+    fn notify(&mut self, notification: &Notification) {
+        if let Some(sender) = &self.sender
+        {
+            if let Err(TrySendError::Full(_)) = sender.try_send(notification.clone())
+            {
+                self.dropped += 1;
+            }
+        }
+    }
+}
+impl Drop for WebhookNotifier
+{
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Delivers notifications until the queue is closed
+fn deliver_all(client: Client, urls: Vec<Url>, config: &WebhookConfig, receiver: Receiver<Notification>) -> WebhookStats
+{
+    let mut stats = WebhookStats::default();
+    for notification in receiver
+    {
+        let body = match payload(&notification, config.format)
+        {
+            Some(body) => body,
+            None => continue
+        };
+        for url in &urls
+        {
+            if deliver(&client, url, &body, config) {stats.delivered += 1} else {stats.failed += 1}
+        }
+    }
+    stats
+}
+
+/// The body POSTed for the notification, None if the format leaves it out
+///
+/// # Arguments
+///
+/// * 'notification' - What happened
+/// * 'format' - What is POSTed
+fn payload(notification: &Notification, format: WebhookFormat) -> Option<Vec<u8>>
+{
+    match (format, notification)
+    {
+        (WebhookFormat::Json, _) => serde_json::to_vec(notification).ok(),
+        (WebhookFormat::Slack, Notification::RunCompleted { .. }) => serde_json::to_vec(&serde_json::json!({ "text": notification.to_string() })).ok(),
+        (WebhookFormat::Slack, _) => None
+    }
+}
+
+/// POSTs the body to the url, backing off between attempts, and returns whether it got through
+fn deliver(client: &Client, url: &Url, body: &[u8], config: &WebhookConfig) -> bool
+{
+    let mut wait = config.backoff;
+    for attempt in 1..=config.max_attempts
+    {
+        let sent = client.post(url.clone()).header(CONTENT_TYPE, "application/json").body(body.to_vec()).send();
+        if sent.is_ok_and(|r| r.status().is_success()) {return true}
+        if attempt < config.max_attempts
+        {
+            thread::sleep(wait);
+            wait *= 2;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::{BufRead, BufReader, Read, Write}, net::TcpListener};
+    use crate::{Amount, Engine, EnginePolicy, Tx, TypeTx};
+
+    /// Answers each request with the next status, returning the bodies it was sent
+    fn serve(listener: TcpListener, statuses: Vec<u16>) -> JoinHandle<Vec<String>>
+    {
+        thread::spawn(move || statuses.into_iter().map(|status| {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut length = 0;
+            loop
+            {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line == "\r\n" {break}
+                if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:")
+                {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            write!(reader.get_mut(), "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status).unwrap();
+            String::from_utf8(body).unwrap()
+        }).collect())
+    }
+
+    #[test]
+    fn posts_with_retries()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = serve(listener, vec![500, 200, 200]);
+
+        let mut config = WebhookConfig::new(vec![url]);
+        config.backoff = Duration::from_millis(10);
+        let notifier = WebhookNotifier::new(config).unwrap();
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.add_notifier(Box::new(notifier));
+        engine.apply(Tx::new(TypeTx::Deposit, 3, 1, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 3, 1, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 3, 1, None));
+        drop(engine);
+
+        assert_eq!(server.join().unwrap(),vec![
+            r#"{"event":"chargeback","client":3,"tx":1,"amount":"2.0"}"#,
+            r#"{"event":"chargeback","client":3,"tx":1,"amount":"2.0"}"#,
+            r#"{"event":"account_locked","client":3}"#,
+        ]);
+    }
+    #[test]
+    fn bounded_queue()
+    {
+        let mut config = WebhookConfig::new(vec!["http://127.0.0.1:9/hook".to_string()]);
+        config.queue_size = 1;
+        config.max_attempts = 1;
+        let mut notifier = WebhookNotifier::new(config).unwrap();
+        for client in 0..50
+        {
+            notifier.notify(&Notification::AccountLocked { client });
+        }
+        let stats = notifier.finish();
+        assert!(stats.dropped > 0);
+        assert_eq!(stats.failed + stats.dropped,50);
+        assert!(WebhookNotifier::new(WebhookConfig::new(vec!["ftp://x".to_string()])).is_err());
+    }
+    #[test]
+    fn slack_gets_the_run_summary()
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/services/x", listener.local_addr().unwrap());
+        let server = serve(listener, vec![200]);
+
+        let mut config = WebhookConfig::new(vec![url]);
+        config.format = WebhookFormat::Slack;
+        let mut engine = Engine::new(EnginePolicy::default());
+        engine.add_notifier(Box::new(WebhookNotifier::new(config).unwrap()));
+        engine.apply(Tx::new(TypeTx::Deposit, 3, 1, Some(Amount::from_minor(20000))));
+        engine.apply(Tx::new(TypeTx::Dispute, 3, 1, None));
+        engine.apply(Tx::new(TypeTx::Chargeback, 3, 1, None));
+        engine.finish_run();
+        drop(engine);
+
+        assert_eq!(server.join().unwrap(),vec![r#"{"text":"run completed: 3 rows processed, 0 rejected, 1 locked accounts (3)"}"#]);
+    }
+}
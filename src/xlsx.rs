@@ -0,0 +1,123 @@
+use std::io;
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use crate::{Account, AccountSink, Amount};
+
+/// The columns of the accounts sheet, in order
+const ACCOUNT_COLUMNS: [&str; 6] = ["client", "available", "held", "total", "locked", "pending"];
+
+/// Writes accounts as an Excel workbook, the accounts on the first sheet and what they
+/// come to on a second
+///
+/// The workbook is kept in memory and only written out once every account is in
+pub struct XlsxSink<W: io::Write>
+{
+    out: Option<W>,
+    workbook: Workbook,
+    /// The row the next account goes on, below the header
+    row: u32,
+    /// How many accounts were written, and how many of them are locked
+    accounts: u64,
+    locked: u64,
+    /// The sums of the available, held, total and pending balances
+    sums: [Amount; 4],
+}
+impl<W: io::Write> XlsxSink<W>
+{
+    pub fn new(out: W) -> io::Result<XlsxSink<W>>
+    {
+        let mut workbook = Workbook::new();
+        let bold = Format::new().set_bold();
+        let sheet = workbook.add_worksheet().set_name("accounts").map_err(to_io)?;
+        for (col, name) in (0..).zip(ACCOUNT_COLUMNS)
+        {
+            sheet.write_string_with_format(0, col, name, &bold).map_err(to_io)?;
+        }
+        workbook.add_worksheet().set_name("summary").map_err(to_io)?;
+        Ok(XlsxSink { out: Some(out), workbook, row: 1, accounts: 0, locked: 0, sums: [Amount::ZERO; 4] })
+    }
+}
+impl<W: io::Write> AccountSink for XlsxSink<W>
+{
+    fn write_account(&mut self, acc: &Account) -> io::Result<()>
+    {
+        let balances = [acc.available, acc.held, acc.total, acc.pending];
+        let sheet = self.workbook.worksheet_from_index(0).map_err(to_io)?;
+        sheet.write_number(self.row, 0, acc.client).map_err(to_io)?;
+        write_amount(sheet, self.row, 1, acc.available)?;
+        write_amount(sheet, self.row, 2, acc.held)?;
+        write_amount(sheet, self.row, 3, acc.total)?;
+        sheet.write_boolean(self.row, 4, acc.locked).map_err(to_io)?;
+        write_amount(sheet, self.row, 5, acc.pending)?;
+        self.row += 1;
+        self.accounts += 1;
+        if acc.locked {self.locked += 1}
+        for (sum, balance) in self.sums.iter_mut().zip(balances)
+        {
+            *sum = sum.checked_add(balance).unwrap_or(Amount::MAX);
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> io::Result<()>
+    {
+        let Some(mut out) = self.out.take() else {return Ok(())};
+        let sheet = self.workbook.worksheet_from_index(1).map_err(to_io)?;
+        let bold = Format::new().set_bold();
+        sheet.write_string_with_format(0, 0, "figure", &bold).map_err(to_io)?;
+        sheet.write_string_with_format(0, 1, "value", &bold).map_err(to_io)?;
+        sheet.write_string(1, 0, "accounts").map_err(to_io)?;
+        sheet.write_number(1, 1, self.accounts as f64).map_err(to_io)?;
+        sheet.write_string(2, 0, "locked").map_err(to_io)?;
+        sheet.write_number(2, 1, self.locked as f64).map_err(to_io)?;
+        for ((row, name), sum) in (3..).zip(["available", "held", "total", "pending"]).zip(self.sums)
+        {
+            sheet.write_string(row, 0, name).map_err(to_io)?;
+            write_amount(sheet, row, 1, sum)?;
+        }
+        out.write_all(&self.workbook.save_to_buffer().map_err(to_io)?)?;
+        out.flush()
+    }
+}
+
+/// Writes an amount as a number with four decimals
+///
+/// # Arguments
+///
+/// * 'sheet' - The sheet to write to
+/// * 'row' - The row of the cell
+/// * 'col' - The column of the cell
+/// * 'amount' - The amount to write
+fn write_amount(sheet: &mut Worksheet, row: u32, col: u16, amount: Amount) -> io::Result<()>
+{
+    let format = Format::new().set_num_format("0.0000");
+    sheet.write_number_with_format(row, col, amount.minor() as f64 / 10000.0, &format).map_err(to_io)?;
+    Ok(())
+}
+
+/// Turns an error of the workbook into an io error, as the sinks return
+fn to_io(e: XlsxError) -> io::Error
+{
+    io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accounts_written_as_a_workbook()
+    {
+        let mut out = Vec::new();
+        let mut sink = XlsxSink::new(&mut out).unwrap();
+        let mut acc = Account::new(1);
+        acc.available = Amount::from_minor(12345);
+        acc.total = acc.available;
+        sink.write_account(&acc).unwrap();
+        sink.write_account(&Account { locked: true, ..Account::new(2) }).unwrap();
+        sink.finish().unwrap();
+        assert_eq!(sink.sums[0],Amount::from_minor(12345));
+        assert_eq!((sink.accounts, sink.locked),(2, 1));
+        drop(sink);
+        //a workbook is a zip archive
+        assert!(out.starts_with(b"PK"));
+    }
+}